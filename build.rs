@@ -0,0 +1,32 @@
+// Build metadata for GET /api/tbm/version (see src/version.rs): the git commit and build time,
+// as the NVT_GIT_COMMIT and NVT_BUILD_TIMESTAMP environment variables of the compiler.
+// Builds outside a git checkout (e.g. from a source archive) can pass NVT_GIT_COMMIT themselves;
+// SOURCE_DATE_EPOCH gives reproducible builds a fixed timestamp.
+
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=NVT_GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    // HEAD only changes on checkout; commits move the branch it points to
+    if let Some(branch) = fs::read_to_string(".git/HEAD").ok().and_then(|head| head.strip_prefix("ref: ").map(|r| r.trim().to_string())) {
+        println!("cargo:rerun-if-changed=.git/{}", branch);
+    }
+
+    let commit = std::env::var("NVT_GIT_COMMIT").ok()
+        .or_else(|| {
+            let output = Command::new("git").args(["rev-parse", "--short=12", "HEAD"]).output().ok()?;
+            output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+        .filter(|commit| !commit.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=NVT_GIT_COMMIT={}", commit);
+
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH").ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default());
+    println!("cargo:rustc-env=NVT_BUILD_TIMESTAMP={}", timestamp);
+}
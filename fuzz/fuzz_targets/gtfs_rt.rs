@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nvtweb::tbm_api_models::NVTModels;
+
+// The three feeds share the FeedMessage format, so one target covers all of them
+fuzz_target!(|data: &[u8]| {
+    let _ = NVTModels::parse_alerts_feed(data);
+    let _ = NVTModels::parse_vehicle_positions(data);
+    let _ = NVTModels::parse_trip_updates(data);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nvtweb::tbm_api_models::NVTModels;
+
+// Rows of the largest GTFS file, where most of the malformed data shows up
+fuzz_target!(|data: &[u8]| {
+    if let Ok(contents) = std::str::from_utf8(data) {
+        let _ = NVTModels::parse_stop_times_csv(contents);
+    }
+});
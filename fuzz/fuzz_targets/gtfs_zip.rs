@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nvtweb::tbm_api_models::NVTModels;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = NVTModels::parse_tbm_gtfs(data);
+    let _ = NVTModels::parse_naq_gtfs(data);
+    let _ = NVTModels::parse_sncf_gtfs(data);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nvtweb::tbm_api_models::NVTModels;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = NVTModels::parse_lines_discovery(data);
+});
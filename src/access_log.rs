@@ -0,0 +1,194 @@
+// Structured access log
+// One line per request, with the route template it matched so the heavy endpoints can be sized
+// from the log: status, response bytes (before compression), duration and whether the client's
+// copy was still current (a 304 from the ETag check). Lines are plain text or, for log
+// pipelines, one JSON object each, written to stdout or to a file rotated by size
+// (access.log, then access.log.1, access.log.2, ... for the older ones).
+
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::config::{AccessLogConfig, AccessLogFormat};
+use crate::privacy::{self, IpAnonymizer};
+use crate::tbm_api_models::NVTModels;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessEntry {
+    pub timestamp: i64,
+    pub client: String, // As the privacy settings allow, "-" when unknown
+    pub method: String,
+    pub path: String,
+    pub route: String, // Matched route template, e.g. /api/tbm/stop/{id}; the path when none matched
+    pub status: u16,
+    pub bytes: Option<u64>, // None for streamed bodies
+    pub duration_ms: f64,
+    pub cache_hit: bool, // 304 Not Modified
+    pub user_agent: Option<String>,
+}
+
+/// What is known of a request before it is handled
+pub struct PendingEntry {
+    started: Instant,
+    client: String,
+    method: String,
+    path: String,
+    user_agent: Option<String>,
+}
+
+impl PendingEntry {
+    pub fn start(req: &ServiceRequest, anonymizer: &IpAnonymizer) -> Self {
+        PendingEntry {
+            started: Instant::now(),
+            client: privacy::client_ip(req).map(|ip| anonymizer.anonymize(ip)).unwrap_or_else(|| "-".to_string()),
+            method: req.method().to_string(),
+            path: req.path().to_string(),
+            user_agent: req.headers().get(actix_web::http::header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        }
+    }
+
+    pub fn finish<B: MessageBody>(self, res: &ServiceResponse<B>) -> AccessEntry {
+        let route = res.request().match_pattern();
+        self.entry(res.status(), route, res.response().body().size())
+    }
+
+    /// Entry of a request answered by a middleware error (429, 504), whose response actix
+    /// builds further up
+    pub fn failed(self, error: &actix_web::Error) -> AccessEntry {
+        let response = error.error_response();
+        self.entry(response.status(), None, response.body().size())
+    }
+
+    fn entry(self, status: StatusCode, route: Option<String>, size: BodySize) -> AccessEntry {
+        AccessEntry {
+            timestamp: NVTModels::get_current_timestamp(),
+            route: route.unwrap_or_else(|| self.path.clone()),
+            client: self.client,
+            method: self.method,
+            path: self.path,
+            status: status.as_u16(),
+            bytes: match size {
+                BodySize::Sized(bytes) => Some(bytes),
+                BodySize::None => Some(0),
+                BodySize::Stream => None,
+            },
+            duration_ms: self.started.elapsed().as_secs_f64() * 1000.0,
+            cache_hit: status == StatusCode::NOT_MODIFIED,
+            user_agent: self.user_agent,
+        }
+    }
+}
+
+impl AccessEntry {
+    fn text(&self) -> String {
+        format!(
+            r#"{} "{} {}" {} {} {} {:.1}ms{} "{}""#,
+            self.client,
+            self.method,
+            self.path,
+            self.status,
+            self.bytes.map_or("-".to_string(), |bytes| bytes.to_string()),
+            self.route,
+            self.duration_ms,
+            if self.cache_hit { " cache-hit" } else { "" },
+            self.user_agent.as_deref().unwrap_or("-"),
+        )
+    }
+}
+
+pub struct AccessLog {
+    format: AccessLogFormat,
+    file: Option<Mutex<RotatingFile>>, // stdout when None
+}
+
+impl AccessLog {
+    pub fn new(config: &AccessLogConfig) -> Self {
+        AccessLog {
+            format: config.format,
+            file: config.file.as_ref().map(|path| Mutex::new(RotatingFile {
+                path: path.clone(),
+                max_bytes: config.max_file_mb.max(1) * 1024 * 1024,
+                max_files: config.max_files,
+                file: None,
+                written: 0,
+            })),
+        }
+    }
+
+    pub fn record(&self, entry: &AccessEntry) {
+        let line = match self.format {
+            AccessLogFormat::Text => entry.text(),
+            AccessLogFormat::Json => serde_json::to_string(entry).unwrap_or_default(),
+        };
+        match &self.file {
+            Some(file) => {
+                let mut file = match file.lock() {
+                    Ok(file) => file,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                if let Err(e) = file.write_line(&line) {
+                    eprintln!("⚠️  Failed to write access log ({})", e);
+                }
+            }
+            None => println!("{}", line),
+        }
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize, // Rotated files kept besides the current one
+    file: Option<File>,
+    written: u64, // Size of the current file
+}
+
+impl RotatingFile {
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.file.is_some() && self.written + line.len() as u64 + 1 > self.max_bytes {
+            self.file = None;
+            self.rotate()?;
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                    fs::create_dir_all(dir)?;
+                }
+                let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+                self.written = file.metadata()?.len();
+                self.file.insert(file)
+            }
+        };
+        writeln!(file, "{}", line)?;
+        self.written += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Shift access.log.N to access.log.N+1, dropping those past max_files, and the current
+    /// file to access.log.1
+    fn rotate(&self) -> std::io::Result<()> {
+        if self.max_files == 0 {
+            return fs::remove_file(&self.path);
+        }
+        fs::remove_file(rotated_path(&self.path, self.max_files)).ok();
+        for n in (1..self.max_files).rev() {
+            fs::rename(rotated_path(&self.path, n), rotated_path(&self.path, n + 1)).ok();
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))
+    }
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
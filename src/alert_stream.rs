@@ -0,0 +1,131 @@
+// Alert changes as Server-Sent Events (GET /api/tbm/alerts/stream)
+// After each refresh, data_refresh_task diffs the alert set against the previous one and
+// broadcasts one event per change: `new` and `updated` carry the alert, `expired` the last
+// version seen before it left the feeds. Every connection gets its own queue fed from the
+// broadcast channel, plus a keep-alive comment so proxies don't close idle streams.
+
+use actix_web::body::{BodySize, MessageBody};
+use bytes::Bytes;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+
+use crate::tbm_api_models::AlertInfo;
+
+pub const CONTENT_TYPE: &str = "text/event-stream";
+const CHANNEL_CAPACITY: usize = 256; // Events a slow client may fall behind before skipping some
+const CLIENT_QUEUE: usize = 64;
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+const RETRY_MS: u32 = 5000; // Reconnection delay suggested to EventSource
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertChange {
+    New,
+    Updated,
+    Expired,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertEvent {
+    pub change: AlertChange,
+    pub alert: AlertInfo,
+}
+
+impl AlertEvent {
+    /// One SSE message, the change as the event name and the alert as JSON data
+    pub fn to_sse(&self) -> Bytes {
+        let data = serde_json::to_string(&self.alert).unwrap_or_default();
+        let event = serde_json::to_value(self.change).ok()
+            .and_then(|name| name.as_str().map(String::from))
+            .unwrap_or_default();
+        Bytes::from(format!("event: {}\nid: {}\ndata: {}\n\n", event, self.alert.id, data))
+    }
+}
+
+/// Changes from `previous` to `current`, new and updated alerts in feed order, then the expired ones
+pub fn diff(previous: &[AlertInfo], current: &[AlertInfo]) -> Vec<AlertEvent> {
+    let before: HashMap<&str, &AlertInfo> = previous.iter().map(|alert| (alert.id.as_str(), alert)).collect();
+    let after: HashMap<&str, &AlertInfo> = current.iter().map(|alert| (alert.id.as_str(), alert)).collect();
+
+    let changed = current.iter().filter_map(|alert| match before.get(alert.id.as_str()) {
+        None => Some(AlertChange::New),
+        Some(&old) if old != alert => Some(AlertChange::Updated),
+        Some(_) => None,
+    }.map(|change| AlertEvent { change, alert: alert.clone() }));
+    let expired = previous.iter()
+        .filter(|alert| !after.contains_key(alert.id.as_str()))
+        .map(|alert| AlertEvent { change: AlertChange::Expired, alert: alert.clone() });
+    changed.chain(expired).collect()
+}
+
+/// Sending side of the alert events, shared through AppState
+#[derive(Clone)]
+pub struct AlertBroadcaster(broadcast::Sender<Bytes>);
+
+impl Default for AlertBroadcaster {
+    fn default() -> Self {
+        AlertBroadcaster(broadcast::channel(CHANNEL_CAPACITY).0)
+    }
+}
+
+impl AlertBroadcaster {
+    /// Broadcast the changes between two alert sets; returns how many there were
+    pub fn publish(&self, previous: &[AlertInfo], current: &[AlertInfo]) -> usize {
+        let events = diff(previous, current);
+        for event in &events {
+            // Fails only when nobody is listening
+            let _ = self.0.send(event.to_sse());
+        }
+        events.len()
+    }
+
+    /// Body of a new SSE connection
+    pub fn subscribe(&self) -> EventStream {
+        let mut events = self.0.subscribe();
+        let (tx, rx) = mpsc::channel(CLIENT_QUEUE);
+        actix_web::rt::spawn(async move {
+            if tx.send(Bytes::from(format!("retry: {}\n\n", RETRY_MS))).await.is_err() {
+                return;
+            }
+            let mut keep_alive = tokio::time::interval(KEEP_ALIVE);
+            keep_alive.tick().await;
+            loop {
+                let message = tokio::select! {
+                    event = events.recv() => match event {
+                        Ok(message) => message,
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            Bytes::from(format!(": {} events skipped\n\n", skipped))
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    },
+                    _ = keep_alive.tick() => Bytes::from_static(b": keep-alive\n\n"),
+                };
+                // The client went away
+                if tx.send(message).await.is_err() {
+                    return;
+                }
+            }
+        });
+        EventStream(rx)
+    }
+}
+
+/// Streaming response body of one SSE connection
+pub struct EventStream(mpsc::Receiver<Bytes>);
+
+impl MessageBody for EventStream {
+    type Error = Infallible;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        self.get_mut().0.poll_recv(cx).map(|message| message.map(Ok))
+    }
+}
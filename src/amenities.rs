@@ -0,0 +1,249 @@
+// Stop amenities from OpenStreetMap (optional, see `amenities` in the config)
+// A background thread queries Overpass tile by tile around the stops, throttled, and tags each
+// stop with shelter / bench / lighting / tactile paving from the nearest OSM stop node and
+// nearby amenity=shelter|bench nodes. Results are cached in tbm_nvt/amenities.json.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::AmenitiesConfig;
+use crate::tbm_api_models::{CachedNetworkData, NVTError, NVTModels, Result};
+
+const TILE_SIZE_DEGREES: f64 = 0.1;
+const MATCH_RADIUS_METERS: f64 = 30.0;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+const MAX_ATTEMPTS: u32 = 3;
+
+type StopPosition = (String, f64, f64); // stop_id, latitude, longitude
+
+// ============================================================================
+// Data Structures
+// ============================================================================
+
+/// `None` means OpenStreetMap doesn't say, `Some(false)` that it's tagged as absent
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StopAmenities {
+    pub shelter: Option<bool>,
+    pub bench: Option<bool>,
+    pub lit: Option<bool>,
+    pub tactile_paving: Option<bool>,
+    pub osm_node_id: Option<i64>, // Matched OSM stop node, for contributors fixing the data
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct AmenitiesCache {
+    cached_at: i64,
+    stops: HashMap<String, StopAmenities>, // key: stop_id
+}
+
+struct OsmNode {
+    id: i64,
+    latitude: f64,
+    longitude: f64,
+    tags: HashMap<String, String>,
+}
+
+impl OsmNode {
+    fn is_stop(&self) -> bool {
+        self.tag("highway") == Some("bus_stop")
+            || self.tag("public_transport") == Some("platform")
+            || self.tag("railway") == Some("tram_stop")
+    }
+
+    fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.get(key).map(String::as_str)
+    }
+
+    fn flag(&self, key: &str) -> Option<bool> {
+        match self.tag(key)? {
+            "no" => Some(false),
+            _ => Some(true), // "yes" and more specific values like "separate" or "incorrect"
+        }
+    }
+}
+
+fn cache_path() -> PathBuf {
+    let mut path = dirs::cache_dir().unwrap_or_else(|| PathBuf::from("."));
+    path.push("tbm_nvt");
+    fs::create_dir_all(&path).ok();
+    path.push("amenities.json");
+    path
+}
+
+// ============================================================================
+// Background Enrichment
+// ============================================================================
+
+/// Fill `stop_amenities` from the cache file, or from Overpass when it is missing or stale
+pub fn spawn(config: AmenitiesConfig, cache: Arc<Mutex<CachedNetworkData>>) {
+    thread::spawn(move || {
+        let now = NVTModels::get_current_timestamp();
+        let cached = fs::read_to_string(cache_path()).ok()
+            .and_then(|contents| serde_json::from_str::<AmenitiesCache>(&contents).ok())
+            .filter(|c| now - c.cached_at < (config.max_age_days * 86400) as i64);
+
+        let amenities = match cached {
+            Some(cached) => {
+                println!("✓ Loaded OSM amenities of {} stops from cache", cached.stops.len());
+                cached.stops
+            }
+            None => {
+                let positions = match cache.lock() {
+                    Ok(cache) => cache.stop_positions(),
+                    Err(e) => {
+                        eprintln!("❌ Failed to lock cache: {}", e);
+                        return;
+                    }
+                };
+                let stops = fetch_all(&config, &positions);
+                let snapshot = AmenitiesCache { cached_at: now, stops };
+                if let Ok(json) = serde_json::to_string(&snapshot) {
+                    fs::write(cache_path(), json).ok();
+                }
+                snapshot.stops
+            }
+        };
+
+        match cache.lock() {
+            Ok(mut cache) => cache.stop_amenities = amenities,
+            Err(e) => eprintln!("❌ Failed to lock cache: {}", e),
+        }
+    });
+}
+
+fn fetch_all(config: &AmenitiesConfig, positions: &[StopPosition]) -> HashMap<String, StopAmenities> {
+    let mut tiles: HashMap<(i64, i64), Vec<&StopPosition>> = HashMap::new();
+    for position in positions {
+        let key = ((position.1 / TILE_SIZE_DEGREES).floor() as i64, (position.2 / TILE_SIZE_DEGREES).floor() as i64);
+        tiles.entry(key).or_default().push(position);
+    }
+
+    println!("🗺️  Fetching OSM amenities for {} stops ({} tiles)...", positions.len(), tiles.len());
+    let client = match reqwest::blocking::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("❌ Failed to build Overpass HTTP client: {}", e);
+            return HashMap::new();
+        }
+    };
+
+    let mut amenities = HashMap::new();
+    for (done, ((tile_lat, tile_lon), stops)) in tiles.iter().enumerate() {
+        // Small margin so stops on a tile edge still see nodes across it
+        let margin = 0.001;
+        let bbox = (
+            *tile_lat as f64 * TILE_SIZE_DEGREES - margin,
+            *tile_lon as f64 * TILE_SIZE_DEGREES - margin,
+            (*tile_lat + 1) as f64 * TILE_SIZE_DEGREES + margin,
+            (*tile_lon + 1) as f64 * TILE_SIZE_DEGREES + margin,
+        );
+
+        let nodes = (1..=MAX_ATTEMPTS).find_map(|attempt| {
+            match query_overpass(&client, &config.overpass_url, bbox) {
+                Ok(nodes) => Some(nodes),
+                Err(e) => {
+                    eprintln!("⚠️  Overpass query failed (attempt {}/{}): {}", attempt, MAX_ATTEMPTS, e);
+                    thread::sleep(Duration::from_secs(config.request_interval_secs * 4));
+                    None
+                }
+            }
+        });
+
+        if let Some(nodes) = nodes {
+            for (stop_id, lat, lon) in stops {
+                if let Some(found) = match_stop(*lat, *lon, &nodes) {
+                    amenities.insert(stop_id.clone(), found);
+                }
+            }
+        }
+
+        if (done + 1) % 50 == 0 {
+            println!("   … {}/{} tiles, {} stops tagged", done + 1, tiles.len(), amenities.len());
+        }
+        thread::sleep(Duration::from_secs(config.request_interval_secs));
+    }
+
+    println!("✓ OSM amenities found for {} stops", amenities.len());
+    amenities
+}
+
+// ============================================================================
+// Overpass
+// ============================================================================
+
+fn query_overpass(client: &reqwest::blocking::Client, url: &str, bbox: (f64, f64, f64, f64)) -> Result<Vec<OsmNode>> {
+    let (south, west, north, east) = bbox;
+    let area = format!("({:.4},{:.4},{:.4},{:.4})", south, west, north, east);
+    let query = format!(
+        "[out:json][timeout:90];(\
+         node[\"highway\"=\"bus_stop\"]{area};\
+         node[\"public_transport\"=\"platform\"]{area};\
+         node[\"railway\"=\"tram_stop\"]{area};\
+         node[\"amenity\"~\"^(shelter|bench)$\"]{area};\
+         );out body;"
+    );
+
+    let response = client.post(url)
+        .form(&[("data", query)])
+        .send()
+        .map_err(|e| NVTError::NetworkError(format!("Failed to query Overpass: {}", e)))?;
+
+    if !response.status().is_success() {
+        return Err(NVTError::NetworkError(format!("Overpass returned error: {}", response.status())));
+    }
+
+    let json: serde_json::Value = response.json()
+        .map_err(|e| NVTError::ParseError(format!("Invalid Overpass response: {}", e)))?;
+
+    let elements = json["elements"].as_array()
+        .ok_or_else(|| NVTError::ParseError("Missing Overpass elements".to_string()))?;
+
+    Ok(elements.iter()
+        .filter_map(|element| {
+            Some(OsmNode {
+                id: element["id"].as_i64()?,
+                latitude: element["lat"].as_f64()?,
+                longitude: element["lon"].as_f64()?,
+                tags: element["tags"].as_object()
+                    .map(|tags| tags.iter()
+                        .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                        .collect())
+                    .unwrap_or_default(),
+            })
+        })
+        .collect())
+}
+
+fn match_stop(lat: f64, lon: f64, nodes: &[OsmNode]) -> Option<StopAmenities> {
+    let nearby: Vec<(f64, &OsmNode)> = nodes.iter()
+        .map(|node| (NVTModels::distance_meters(lat, lon, node.latitude, node.longitude), node))
+        .filter(|(distance, _)| *distance <= MATCH_RADIUS_METERS)
+        .collect();
+
+    let stop_node = nearby.iter()
+        .filter(|(_, node)| node.is_stop())
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, node)| *node);
+    // Shelters and benches are also mapped as separate amenity nodes next to the stop
+    let presence = |key: &str| {
+        let tagged = stop_node.and_then(|n| n.flag(key));
+        let standalone = nearby.iter().any(|(_, node)| node.tag("amenity") == Some(key));
+        if standalone { Some(true) } else { tagged }
+    };
+
+    let amenities = StopAmenities {
+        shelter: presence("shelter"),
+        bench: presence("bench"),
+        lit: stop_node.and_then(|n| n.flag("lit")),
+        tactile_paving: stop_node.and_then(|n| n.flag("tactile_paving")),
+        osm_node_id: stop_node.map(|n| n.id),
+    };
+
+    let known = amenities.osm_node_id.is_some() || amenities.shelter.is_some() || amenities.bench.is_some();
+    known.then_some(amenities)
+}
@@ -0,0 +1,127 @@
+// Root API index (GET /api)
+// What a developer poking at the server with curl needs to find their way: the API version,
+// the data sources and how much each loaded, the optional features this instance runs, and
+// every endpoint with its parameters. The endpoint list is the route table `api_routes`
+// registers, so it can't drift from what the server answers.
+
+use actix_web::Route;
+use serde::Serialize;
+
+use crate::config;
+use crate::tbm_api_models::CachedNetworkData;
+
+pub const API_VERSION: &str = crate::version::VERSION;
+
+/// One API route, with what the index says of it
+pub struct Endpoint {
+    pub method: &'static str,
+    pub path: &'static str, // Path parameters in braces, e.g. /api/tbm/stop/{id}
+    pub summary: &'static str,
+    pub params: &'static [&'static str], // Query parameters
+    pub route: fn() -> Route,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EndpointInfo {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub href: String,
+    pub templated: bool, // href has path parameters to fill in
+    pub summary: &'static str,
+    pub params: &'static [&'static str],
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourceInfo {
+    pub name: String, // Plain GTFS feeds by their configured name
+    pub enabled: bool, // The last load returned data
+    pub stops: usize,
+    pub lines: usize,
+    pub trips: usize,
+    pub gtfs_url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeatureInfo {
+    pub bot: bool, // Configured and built with the "bot" feature
+    pub digest: bool,
+    pub amenities: bool,
+    pub map_matching: bool,
+    pub derived_alerts: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiIndex {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub schema: String, // JSON Schema of the responses
+    pub sources: Vec<SourceInfo>,
+    pub features: FeatureInfo,
+    pub endpoints: Vec<EndpointInfo>,
+}
+
+pub fn index(endpoints: &[Endpoint], cache: &CachedNetworkData, base_url: &str) -> ApiIndex {
+    let base_url = base_url.trim_end_matches('/');
+    let gtfs_url = |source: &str| cache.upstreams.feed(source).map(|feed| feed.static_url.clone()).unwrap_or_default();
+    let mut sources = vec![
+        SourceInfo {
+            name: "TBM".to_string(),
+            enabled: !cache.tbm_stops_metadata.is_empty(),
+            stops: cache.tbm_stops_metadata.len(),
+            lines: cache.tbm_lines_metadata.len(),
+            trips: cache.tbm_gtfs_cache.trips.len(),
+            gtfs_url: gtfs_url("TBM"),
+        },
+        SourceInfo {
+            name: "New-Aquitaine".to_string(),
+            enabled: !cache.transgironde_stops.is_empty(),
+            stops: cache.transgironde_stops.len(),
+            lines: cache.transgironde_lines.len(),
+            trips: cache.transgironde_gtfs_cache.trips.len(),
+            gtfs_url: gtfs_url("NewAquitaine"),
+        },
+        SourceInfo {
+            name: "SNCF".to_string(),
+            enabled: !cache.sncf_stops.is_empty(),
+            stops: cache.sncf_stops.len(),
+            lines: cache.sncf_lines.len(),
+            trips: cache.sncf_gtfs_cache.trips.len(),
+            gtfs_url: gtfs_url("SNCF"),
+        },
+    ];
+    sources.extend(cache.feeds.iter().map(|(name, feed)| SourceInfo {
+        name: name.clone(),
+        enabled: !feed.stops.is_empty(),
+        stops: feed.stops.len(),
+        lines: feed.lines.len(),
+        trips: feed.gtfs_cache.trips.len(),
+        gtfs_url: gtfs_url(name),
+    }));
+
+    let settings = config::get();
+    let features = FeatureInfo {
+        bot: cfg!(feature = "bot") && settings.bot.is_some(),
+        digest: settings.digest.is_some(),
+        amenities: settings.amenities.is_some(),
+        map_matching: settings.map_matching.is_some(),
+        derived_alerts: settings.derived_alerts.enabled,
+    };
+
+    ApiIndex {
+        name: "NVT Web Edition API",
+        version: API_VERSION,
+        schema: format!("{}/api/schema", base_url),
+        sources,
+        features,
+        endpoints: endpoints.iter()
+            .map(|endpoint| EndpointInfo {
+                method: endpoint.method,
+                path: endpoint.path,
+                href: format!("{}{}", base_url, endpoint.path),
+                templated: endpoint.path.contains('{'),
+                summary: endpoint.summary,
+                params: endpoint.params,
+            })
+            .collect(),
+    }
+}
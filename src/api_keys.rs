@@ -0,0 +1,104 @@
+// API keys of the admin and heavy routes (X-Api-Key header)
+// Forced refreshes re-download every GTFS feed, cache purges drop them and the GTFS export
+// rebuilds a full feed, so once keys are configured these routes (see ApiKeysConfig) answer 401
+// to requests without a known key. A key may carry a limit of requests per minute, counted over
+// fixed one-minute windows; past it the key gets 429 until the window ends. Without any key
+// configured the routes stay open, as they were before keys existed.
+// The sync and simulation tokens join the keys as ones limited to /admin/sync and /admin/simulate,
+// so both routes need their token (or a full key) whether other keys are configured or not. A key
+// is read from X-Api-Key, or from an `Authorization: Bearer` header as standbys send it.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::config::{self, ApiKeyConfig, ApiKeysConfig};
+
+pub const HEADER: &str = "X-Api-Key";
+
+const WINDOW_SECS: u64 = 60;
+
+#[derive(Debug, PartialEq)]
+pub enum Refusal {
+    MissingKey,
+    UnknownKey,
+    RateLimited { key: String, retry_after_secs: u64 },
+}
+
+pub struct ApiKeyGuard {
+    config: ApiKeysConfig,
+    windows: Mutex<HashMap<String, (u64, u32)>>, // key: key name, value: (window start, requests in it)
+}
+
+/// Comparison taking the same time wherever the keys differ
+pub fn same_key(given: &str, key: &str) -> bool {
+    given.len() == key.len()
+        && given.bytes().zip(key.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+impl ApiKeyGuard {
+    pub fn new(config: &ApiKeysConfig) -> Self {
+        ApiKeyGuard { config: config.clone(), windows: Mutex::new(HashMap::new()) }
+    }
+
+    /// Guard of the configured keys, the sync and simulation tokens included
+    pub fn from_config(app_config: &config::AppConfig) -> Self {
+        let token = |name: &str, token: Option<String>, route: &str| token.map(|key| ApiKeyConfig {
+            name: name.to_string(),
+            key,
+            requests_per_minute: None,
+            routes: vec![route.to_string()],
+        });
+        let mut keys = app_config.api_keys.clone();
+        keys.keys.extend(token("sync", app_config.sync.as_ref().and_then(config::SyncConfig::resolve_token), "/admin/sync"));
+        keys.keys.extend(token("simulation", app_config.simulation.as_ref().and_then(config::SimulationConfig::resolve_token), "/admin/simulate"));
+        Self::new(&keys)
+    }
+
+    /// Whether keys valid on every protected route are configured
+    pub fn enabled(&self) -> bool {
+        self.config.keys.iter().any(|key| key.routes.is_empty())
+    }
+
+    pub fn routes(&self) -> &[String] {
+        &self.config.routes
+    }
+
+    /// Name of the key a request to `path` is let through with (None for open routes), or why it
+    /// is refused; `given` is its key (see `given_key`) and `now` a unix time
+    pub fn check(&self, path: &str, given: Option<&str>, now: u64) -> Result<Option<String>, Refusal> {
+        let protected = (self.enabled() && self.config.is_protected(path))
+            || self.config.keys.iter().any(|key| key.covers(path));
+        if !protected {
+            return Ok(None);
+        }
+        let given = given.filter(|given| !given.is_empty()).ok_or(Refusal::MissingKey)?;
+        let key: &ApiKeyConfig = self.config.keys.iter()
+            .filter(|key| key.routes.is_empty() || key.covers(path))
+            .find(|key| same_key(given, &key.key))
+            .ok_or(Refusal::UnknownKey)?;
+
+        if let Some(limit) = key.requests_per_minute {
+            let mut windows = match self.windows.lock() {
+                Ok(windows) => windows,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let window = windows.entry(key.name.clone()).or_insert((now, 0));
+            if now >= window.0 + WINDOW_SECS {
+                *window = (now, 0);
+            }
+            if window.1 >= limit {
+                return Err(Refusal::RateLimited {
+                    key: key.name.clone(),
+                    retry_after_secs: (window.0 + WINDOW_SECS).saturating_sub(now).max(1),
+                });
+            }
+            window.1 += 1;
+        }
+        Ok(Some(key.name.clone()))
+    }
+}
+
+/// Key of a request: its X-Api-Key header, else the token of its `Authorization: Bearer` header
+pub fn given_key<'a>(api_key: Option<&'a str>, authorization: Option<&'a str>) -> Option<&'a str> {
+    api_key.or_else(|| authorization.and_then(|header| header.strip_prefix("Bearer ")))
+}
@@ -0,0 +1,421 @@
+// Load-test harness (`bench` command)
+// `bench --record` snapshots the live network data into a fixture file. `bench` then serves the
+// fixture on a local port with the real API routes and measures throughput and latency of key
+// endpoints, the cost of a refresh cycle, and the endpoints again while refreshes run in the
+// background. With the same fixture, runs are comparable across code changes.
+
+use actix_web::{web, App, HttpServer};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::alert_stream::AlertBroadcaster;
+use crate::elevation::ElevationStore;
+use crate::history::HistoryStore;
+use crate::journey_share::JourneyShareStore;
+use crate::refresh_jobs::RefreshJobs;
+use crate::snapshot::{DynamicSnapshot, StaticSnapshot};
+use crate::tbm_api_models::{CachedNetworkData, NVTModels, Upstreams};
+use crate::vector_tiles::TileCache;
+use crate::{api_routes, AppState};
+
+const CONTENDED_REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+// ============================================================================
+// Fixture
+// ============================================================================
+
+/// Everything `CachedNetworkData` holds, except what is derived at load
+#[derive(Serialize, Deserialize)]
+struct Fixture {
+    recorded_at: i64,
+    #[serde(flatten)]
+    static_data: StaticSnapshot,
+    #[serde(flatten)]
+    dynamic_data: DynamicSnapshot,
+}
+
+impl Fixture {
+    fn from_cache(cache: &CachedNetworkData) -> Self {
+        Fixture {
+            recorded_at: NVTModels::get_current_timestamp(),
+            static_data: StaticSnapshot::from_cache(cache),
+            dynamic_data: DynamicSnapshot::from_cache(cache),
+        }
+    }
+
+    fn to_cache(&self) -> CachedNetworkData {
+        let mut cache = self.static_data.clone().into_cache(self.dynamic_data.clone(), Upstreams::default());
+        cache.last_static_update = self.recorded_at as u64;
+        cache.last_dynamic_update = self.recorded_at as u64;
+        cache
+    }
+}
+
+fn default_fixture_path() -> PathBuf {
+    let mut path = dirs::cache_dir().unwrap_or_else(|| PathBuf::from("."));
+    path.push("tbm_nvt");
+    fs::create_dir_all(&path).ok();
+    path.push("bench_fixture.json");
+    path
+}
+
+// ============================================================================
+// Options
+// ============================================================================
+
+struct BenchOptions {
+    record: bool,
+    fixture: PathBuf,
+    requests: usize,    // Per endpoint and phase
+    concurrency: usize, // Requests in flight at once
+    cycles: usize,      // Refresh cycles timed on their own
+    refresh_delay: Duration, // Simulated feed download time, spent holding the lock like smart_refresh does
+    output: Option<PathBuf>,
+}
+
+impl BenchOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = BenchOptions {
+            record: false,
+            fixture: default_fixture_path(),
+            requests: 200,
+            concurrency: 8,
+            cycles: 20,
+            refresh_delay: Duration::ZERO,
+            output: None,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().cloned().ok_or_else(|| format!("{} needs a value", name));
+            let number = |name: &str, value: String| value.parse::<usize>()
+                .map_err(|_| format!("{} expects a number, got '{}'", name, value));
+            match arg.as_str() {
+                "--record" => options.record = true,
+                "--fixture" => options.fixture = PathBuf::from(value(arg)?),
+                "--requests" => options.requests = number(arg, value(arg)?)?.max(1),
+                "--concurrency" => options.concurrency = number(arg, value(arg)?)?.max(1),
+                "--cycles" => options.cycles = number(arg, value(arg)?)?.max(1),
+                "--refresh-delay-ms" => options.refresh_delay = Duration::from_millis(number(arg, value(arg)?)? as u64),
+                "--output" => options.output = Some(PathBuf::from(value(arg)?)),
+                other => return Err(format!("Unknown bench option '{}'", other)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+// ============================================================================
+// Measurements
+// ============================================================================
+
+#[derive(Debug, Serialize)]
+struct Measurement {
+    name: String,
+    samples: usize,
+    errors: usize,
+    per_second: f64,
+    p50_ms: f64,
+    p95_ms: f64,
+    p99_ms: f64,
+    max_ms: f64,
+}
+
+impl Measurement {
+    fn new(name: &str, mut durations: Vec<Duration>, errors: usize, elapsed: Duration) -> Self {
+        durations.sort();
+        let percentile = |p: f64| {
+            if durations.is_empty() {
+                return 0.0;
+            }
+            let index = ((durations.len() - 1) as f64 * p).round() as usize;
+            durations[index].as_secs_f64() * 1000.0
+        };
+
+        Measurement {
+            name: name.to_string(),
+            samples: durations.len(),
+            errors,
+            per_second: durations.len() as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+            p50_ms: percentile(0.50),
+            p95_ms: percentile(0.95),
+            p99_ms: percentile(0.99),
+            max_ms: percentile(1.0),
+        }
+    }
+
+    fn print(&self) {
+        println!(
+            "  {:<32} {:>9.1}/s {:>9.2} {:>9.2} {:>9.2} {:>9.2} {:>7}",
+            self.name, self.per_second, self.p50_ms, self.p95_ms, self.p99_ms, self.max_ms, self.errors
+        );
+    }
+}
+
+#[derive(Serialize)]
+struct BenchReport {
+    fixture: String,
+    recorded_at: i64,
+    requests: usize,
+    concurrency: usize,
+    refresh_delay_ms: u64,
+    endpoints: Vec<Measurement>,
+    refresh: Measurement,
+    endpoints_during_refresh: Vec<Measurement>,
+}
+
+fn print_table(title: &str, measurements: &[Measurement]) {
+    println!("\n{}", title);
+    println!("  {:<32} {:>11} {:>9} {:>9} {:>9} {:>9} {:>7}", "", "throughput", "p50 ms", "p95 ms", "p99 ms", "max ms", "errors");
+    for measurement in measurements {
+        measurement.print();
+    }
+}
+
+// ============================================================================
+// Load Generation
+// ============================================================================
+
+struct Endpoint {
+    name: String,            // Route template, for the report
+    segments: Vec<String>,   // Path segments, percent-encoded when building the URL
+}
+
+fn endpoint(name: &str, segments: &[&str]) -> Endpoint {
+    Endpoint { name: name.to_string(), segments: segments.iter().map(|s| s.to_string()).collect() }
+}
+
+/// Key endpoints, with a stop and a line of the fixture where the route takes one
+fn endpoints(cache: &CachedNetworkData) -> Vec<Endpoint> {
+    let mut list = vec![
+        endpoint("/network", &["network"]),
+        endpoint("/stops", &["stops"]),
+        endpoint("/lines", &["lines"]),
+        endpoint("/vehicles", &["vehicles"]),
+        endpoint("/alerts", &["alerts"]),
+        endpoint("/stats", &["stats"]),
+    ];
+    if let Some((stop_id, _, _)) = cache.stop_positions().into_iter().next() {
+        list.push(endpoint("/stop/{id}", &["stop", &stop_id]));
+        list.push(endpoint("/stop/{id}/arrivals", &["stop", &stop_id, "arrivals"]));
+    }
+    if let Some(line) = cache.to_network_data().lines.first() {
+        list.push(endpoint("/line/{code}", &["line", &line.line_code]));
+    }
+    list
+}
+
+async fn load_endpoint(client: &reqwest::Client, base: &reqwest::Url, endpoint: &Endpoint, requests: usize, concurrency: usize) -> Measurement {
+    let mut url = base.clone();
+    if let Ok(mut path) = url.path_segments_mut() {
+        path.extend(["api", "tbm"]).extend(&endpoint.segments);
+    }
+
+    let remaining = Arc::new(AtomicUsize::new(requests));
+    let started = Instant::now();
+    let workers: Vec<_> = (0..concurrency.min(requests))
+        .map(|_| {
+            let (client, url, remaining) = (client.clone(), url.clone(), remaining.clone());
+            tokio::spawn(async move {
+                let (mut durations, mut errors) = (Vec::new(), 0);
+                while remaining.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() {
+                    let sent = Instant::now();
+                    let ok = match client.get(url.clone()).send().await {
+                        Ok(response) => response.status().is_success() && response.bytes().await.is_ok(),
+                        Err(_) => false,
+                    };
+                    if ok { durations.push(sent.elapsed()) } else { errors += 1 }
+                }
+                (durations, errors)
+            })
+        })
+        .collect();
+
+    let (mut durations, mut errors) = (Vec::new(), 0);
+    for worker in workers {
+        if let Ok((worker_durations, worker_errors)) = worker.await {
+            durations.extend(worker_durations);
+            errors += worker_errors;
+        }
+    }
+    Measurement::new(&endpoint.name, durations, errors, started.elapsed())
+}
+
+/// One refresh cycle without the network: the fixture's dynamic data replaces the cache's under
+/// the lock, as `NVTModels::refresh_dynamic_data` does with fresh feeds
+fn refresh_cycle(cache: &Mutex<CachedNetworkData>, fixture: &Fixture, refresh_delay: Duration) -> bool {
+    let alerts = fixture.dynamic_data.alerts.clone();
+    let real_time = fixture.dynamic_data.real_time.clone();
+    let trip_updates = fixture.dynamic_data.trip_updates();
+
+    match cache.lock() {
+        Ok(mut cache) => {
+            thread::sleep(refresh_delay);
+            cache.alerts = alerts;
+            cache.real_time = real_time;
+            cache.trip_updates = trip_updates;
+            cache.last_dynamic_update = NVTModels::get_current_timestamp() as u64;
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+// ============================================================================
+// Entry Point
+// ============================================================================
+
+pub fn run(args: &[String]) -> std::io::Result<()> {
+    let options = BenchOptions::parse(args)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    if options.record {
+        println!("📼 Recording bench fixture from the live feeds...");
+        let cache = NVTModels::initialize_cache()
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        let json = serde_json::to_string(&Fixture::from_cache(&cache))
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        fs::write(&options.fixture, json)?;
+        println!("✓ Fixture written to {}", options.fixture.display());
+        return Ok(());
+    }
+
+    let contents = fs::read_to_string(&options.fixture).map_err(|e| std::io::Error::new(
+        e.kind(),
+        format!("Could not read fixture {} ({}), record one with `bench --record`", options.fixture.display(), e),
+    ))?;
+    let fixture: Fixture = serde_json::from_str(&contents)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Invalid fixture: {}", e)))?;
+    println!("📼 Fixture recorded {}", NVTModels::format_timestamp_full(fixture.recorded_at));
+
+    let state = AppState {
+        cache: Arc::new(Mutex::new(fixture.to_cache())),
+        sandbox: Arc::new(Mutex::new(CachedNetworkData::empty(Upstreams::default()))),
+        journey_shares: Arc::new(Mutex::new(JourneyShareStore::default())),
+        history: Arc::new(Mutex::new(HistoryStore::default())),
+        elevation: Arc::new(Mutex::new(ElevationStore::default())),
+        alert_events: AlertBroadcaster::default(),
+        refresh_jobs: RefreshJobs::default(),
+        tiles: TileCache::default(),
+        clock: NVTModels::paris_now,
+    };
+    let endpoints = match state.cache.lock() {
+        Ok(cache) => endpoints(&cache),
+        Err(e) => return Err(std::io::Error::other(format!("Failed to lock cache: {}", e))),
+    };
+
+    // Server on its own thread and actix system, the load generator on a tokio runtime
+    let (started_tx, started_rx) = mpsc::channel();
+    let server_state = state.clone();
+    thread::spawn(move || {
+        actix_web::rt::System::new().block_on(async move {
+            let server = HttpServer::new(move || {
+                App::new()
+                    .app_data(web::Data::new(server_state.clone()))
+                    .configure(api_routes)
+            })
+                .bind(("127.0.0.1", 0));
+            match server {
+                Ok(server) => {
+                    let port = server.addrs().first().map(|addr| addr.port()).unwrap_or_default();
+                    let server = server.run();
+                    started_tx.send(Ok((port, server.handle()))).ok();
+                    server.await.ok();
+                }
+                Err(e) => {
+                    started_tx.send(Err(e)).ok();
+                }
+            }
+        });
+    });
+    let (port, server) = started_rx.recv().map_err(|e| std::io::Error::other(e.to_string()))??;
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let base = reqwest::Url::parse(&format!("http://127.0.0.1:{}/", port))
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(options.concurrency)
+        .build()
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    let load_all = |label: &str| -> Vec<Measurement> {
+        println!("\n⏱️  Benchmarking endpoints ({})...", label);
+        runtime.block_on(async {
+            let mut measurements = Vec::new();
+            for endpoint in &endpoints {
+                measurements.push(load_endpoint(&client, &base, endpoint, options.requests, options.concurrency).await);
+            }
+            measurements
+        })
+    };
+
+    // Warm up connections and lazily built state
+    runtime.block_on(async {
+        for endpoint in &endpoints {
+            load_endpoint(&client, &base, endpoint, options.concurrency, options.concurrency).await;
+        }
+    });
+
+    let idle = load_all("idle");
+
+    println!("\n⏱️  Benchmarking refresh cycles...");
+    let started = Instant::now();
+    let (mut cycles, mut failures) = (Vec::new(), 0);
+    for _ in 0..options.cycles {
+        let cycle_start = Instant::now();
+        if refresh_cycle(&state.cache, &fixture, options.refresh_delay) {
+            cycles.push(cycle_start.elapsed());
+        } else {
+            failures += 1;
+        }
+    }
+    let refresh = Measurement::new("refresh cycle", cycles, failures, started.elapsed());
+
+    let fixture = Arc::new(fixture);
+    let stop = Arc::new(AtomicBool::new(false));
+    let refresher = {
+        let (cache, fixture, stop) = (state.cache.clone(), fixture.clone(), stop.clone());
+        let refresh_delay = options.refresh_delay;
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                refresh_cycle(&cache, &fixture, refresh_delay);
+                thread::sleep(CONTENDED_REFRESH_INTERVAL);
+            }
+        })
+    };
+    let contended = load_all(&format!("refresh every {} ms", CONTENDED_REFRESH_INTERVAL.as_millis()));
+    stop.store(true, Ordering::SeqCst);
+    refresher.join().ok();
+    runtime.block_on(server.stop(true));
+
+    println!("\n╔════════════════════════════════════════════════════════════╗");
+    println!("║  📊 Bench Results                                          ║");
+    println!("╚════════════════════════════════════════════════════════════╝");
+    println!("  {} requests per endpoint, {} in flight, fixture {}", options.requests, options.concurrency, options.fixture.display());
+    print_table("Endpoints (idle):", &idle);
+    print_table("Refresh (no network):", std::slice::from_ref(&refresh));
+    print_table(&format!("Endpoints (refresh every {} ms):", CONTENDED_REFRESH_INTERVAL.as_millis()), &contended);
+
+    if let Some(output) = &options.output {
+        let report = BenchReport {
+            fixture: options.fixture.display().to_string(),
+            recorded_at: fixture.recorded_at,
+            requests: options.requests,
+            concurrency: options.concurrency,
+            refresh_delay_ms: options.refresh_delay.as_millis() as u64,
+            endpoints: idle,
+            refresh,
+            endpoints_during_refresh: contended,
+        };
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        fs::write(output, json)?;
+        println!("\n✓ Report written to {}", output.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,315 @@
+// Chat bot integration (cargo feature "bot")
+// Connects to Telegram (long polling) or Matrix (/sync) with the configured token and answers
+// commands from the cached network data:
+//   /next <stop>    next departures (stop id or name)
+//   /alerts <line>  active alerts for a line code
+
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::config::{BotConfig, BotPlatform};
+use crate::kiosk;
+use crate::tbm_api_models::{CachedNetworkData, NVTModels};
+
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_REPLY_DEPARTURES: usize = 6;
+
+const HELP_TEXT: &str = "Commands:\n\
+    /next <stop> - next departures (stop id or name)\n\
+    /alerts <line> - active alerts for a line";
+
+/// Start the bot on a background thread
+pub fn spawn(config: BotConfig, cache: Arc<Mutex<CachedNetworkData>>) {
+    let Some(token) = config.resolve_token() else {
+        eprintln!("⚠️  Bot configured without a token (set bot.token or NVT_BOT_TOKEN), not starting");
+        return;
+    };
+
+    thread::spawn(move || {
+        println!("🤖 Chat bot started ({:?})", config.platform);
+        match config.platform {
+            BotPlatform::Telegram => run_telegram(&token, &config, &cache),
+            BotPlatform::Matrix => run_matrix(&token, &config, &cache),
+        }
+    });
+}
+
+// ============================================================================
+// Commands
+// ============================================================================
+
+/// Reply to a chat message, or None when it isn't a command for us
+pub fn handle_command(text: &str, cache: &Arc<Mutex<CachedNetworkData>>) -> Option<String> {
+    let text = text.trim();
+    if !text.starts_with('/') {
+        return None;
+    }
+
+    let (command, argument) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    // Telegram group chats address commands as /next@SomeBot
+    let command = command.split('@').next().unwrap_or(command);
+    let argument = argument.trim();
+
+    let reply = match command {
+        "/next" if !argument.is_empty() => next_departures(argument, cache),
+        "/alerts" if !argument.is_empty() => line_alerts(argument, cache),
+        "/next" | "/alerts" | "/help" | "/start" => HELP_TEXT.to_string(),
+        _ => return None,
+    };
+    Some(reply)
+}
+
+fn next_departures(query: &str, cache: &Arc<Mutex<CachedNetworkData>>) -> String {
+    let Ok(cache) = cache.lock() else {
+        return "Data temporarily unavailable, please retry.".to_string();
+    };
+    let network_data = cache.to_network_data();
+
+    let needle = query.to_lowercase();
+    let stop = network_data.stops.iter().find(|s| s.stop_id == query)
+        .or_else(|| network_data.stops.iter().find(|s| s.stop_name.to_lowercase() == needle))
+        .or_else(|| network_data.stops.iter().find(|s| s.stop_name.to_lowercase().contains(&needle)));
+
+    match stop {
+        Some(stop) => {
+            let now = NVTModels::paris_now();
+            let departures = kiosk::stop_departures(stop, &network_data, &cache, now, MAX_REPLY_DEPARTURES, |_| true);
+            kiosk::render_text_board(stop, &departures, now)
+        }
+        None => format!("No stop matching \"{}\".", query),
+    }
+}
+
+fn line_alerts(line_code: &str, cache: &Arc<Mutex<CachedNetworkData>>) -> String {
+    let Ok(cache) = cache.lock() else {
+        return "Data temporarily unavailable, please retry.".to_string();
+    };
+    let network_data = cache.to_network_data();
+
+    let Some(line) = network_data.lines.iter().find(|l| l.line_code.eq_ignore_ascii_case(line_code)) else {
+        return format!("No line \"{}\".", line_code);
+    };
+
+    if line.alerts.is_empty() {
+        return format!("No active alerts for line {} ({}).", line.line_code, line.operator);
+    }
+
+    let mut reply = format!("Alerts for line {} ({}):\n", line.line_code, line.operator);
+    for alert in &line.alerts {
+        reply.push_str(&format!("⚠️ {}\n", alert.text));
+    }
+    reply
+}
+
+// ============================================================================
+// Telegram
+// ============================================================================
+
+fn run_telegram(token: &str, config: &BotConfig, cache: &Arc<Mutex<CachedNetworkData>>) {
+    let base_url = format!("https://api.telegram.org/bot{}", token);
+    let client = match http_client(config.poll_timeout_secs) {
+        Some(client) => client,
+        None => return,
+    };
+    let mut offset: i64 = 0;
+
+    loop {
+        let response = client.get(format!("{}/getUpdates", base_url))
+            .query(&[("timeout", config.poll_timeout_secs.to_string()), ("offset", offset.to_string())])
+            .send()
+            .and_then(|r| r.json::<Value>());
+
+        let updates = match response {
+            Ok(body) if body["ok"] == true => body["result"].as_array().cloned().unwrap_or_default(),
+            Ok(body) => {
+                eprintln!("⚠️  Telegram API error: {}", body["description"].as_str().unwrap_or("unknown error"));
+                thread::sleep(RETRY_DELAY);
+                continue;
+            }
+            Err(e) => {
+                eprintln!("⚠️  Telegram polling failed: {}", e);
+                thread::sleep(RETRY_DELAY);
+                continue;
+            }
+        };
+
+        for update in updates {
+            if let Some(update_id) = update["update_id"].as_i64() {
+                offset = offset.max(update_id + 1);
+            }
+
+            let message = &update["message"];
+            let (Some(text), Some(chat_id)) = (message["text"].as_str(), message["chat"]["id"].as_i64()) else {
+                continue;
+            };
+
+            if let Some(reply) = handle_command(text, cache) {
+                let sent = client.post(format!("{}/sendMessage", base_url))
+                    .json(&json!({ "chat_id": chat_id, "text": reply }))
+                    .send();
+                if let Err(e) = sent {
+                    eprintln!("⚠️  Telegram reply failed: {}", e);
+                }
+            }
+        }
+    }
+}
+
+// ============================================================================
+// Matrix
+// ============================================================================
+
+fn run_matrix(token: &str, config: &BotConfig, cache: &Arc<Mutex<CachedNetworkData>>) {
+    let Some(homeserver) = config.homeserver.as_deref() else {
+        eprintln!("⚠️  Matrix bot needs bot.homeserver, not starting");
+        return;
+    };
+    let base_url = format!("{}/_matrix/client/v3", homeserver.trim_end_matches('/'));
+    let client = match http_client(config.poll_timeout_secs) {
+        Some(client) => client,
+        None => return,
+    };
+
+    let mut since: Option<String> = None;
+    // Transaction ids must stay unique across restarts for the same access token
+    let session = NVTModels::get_current_timestamp();
+    let mut txn_id: u64 = 0;
+
+    loop {
+        let mut request = client.get(format!("{}/sync", base_url))
+            .bearer_auth(token)
+            .query(&[("timeout", (config.poll_timeout_secs * 1000).to_string())]);
+        if let Some(batch) = &since {
+            request = request.query(&[("since", batch)]);
+        }
+
+        let body = match request.send().and_then(|r| r.json::<Value>()) {
+            Ok(body) => body,
+            Err(e) => {
+                eprintln!("⚠️  Matrix sync failed: {}", e);
+                thread::sleep(RETRY_DELAY);
+                continue;
+            }
+        };
+
+        // The first sync only establishes the position; don't answer old history
+        let initial = since.is_none();
+        since = body["next_batch"].as_str().map(String::from);
+        if initial {
+            continue;
+        }
+
+        let Some(rooms) = body["rooms"]["join"].as_object() else { continue };
+        for (room_id, room) in rooms {
+            let events = room["timeline"]["events"].as_array().cloned().unwrap_or_default();
+            for event in events {
+                if event["type"] != "m.room.message" || event["content"]["msgtype"] != "m.text" {
+                    continue;
+                }
+                let Some(text) = event["content"]["body"].as_str() else { continue };
+
+                if let Some(reply) = handle_command(text, cache) {
+                    txn_id += 1;
+                    let url = format!(
+                        "{}/rooms/{}/send/m.room.message/nvt{}-{}",
+                        base_url,
+                        encode_path_segment(room_id),
+                        session,
+                        txn_id
+                    );
+                    let sent = client.put(url)
+                        .bearer_auth(token)
+                        .json(&json!({ "msgtype": "m.text", "body": reply }))
+                        .send();
+                    if let Err(e) = sent {
+                        eprintln!("⚠️  Matrix reply failed: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn encode_path_segment(value: &str) -> String {
+    value.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn http_client(poll_timeout_secs: u64) -> Option<reqwest::blocking::Client> {
+    reqwest::blocking::Client::builder()
+        .timeout(Duration::from_secs(poll_timeout_secs + 15))
+        .build()
+        .map_err(|e| eprintln!("❌ Failed to build bot HTTP client: {}", e))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tbm_api_models::{AlertInfo, Upstreams};
+
+    /// Stop S1 (Quinconces), tram A with a works alert and Lianes 1 without any
+    fn network() -> Arc<Mutex<CachedNetworkData>> {
+        let mut cache = CachedNetworkData::empty(Upstreams::default());
+        cache.tbm_stops_metadata.push(("S1".to_string(), "Quinconces".to_string(), 44.8445, -0.5737, Vec::new()));
+        cache.tbm_lines_metadata.push(("TBM:Line:59".to_string(), "Tram A".to_string(), "A".to_string(), Vec::new()));
+        cache.tbm_lines_metadata.push(("TBM:Line:1".to_string(), "Lianes 1".to_string(), "1".to_string(), Vec::new()));
+        cache.alerts.push(AlertInfo {
+            id: "works".to_string(),
+            text: "Travaux place des Quinconces".to_string(),
+            description: String::new(),
+            url: None,
+            route_ids: vec!["59".to_string()],
+            stop_ids: Vec::new(),
+            active_period_start: None,
+            active_period_end: None,
+            severity: 3,
+            active_periods: Vec::new(),
+            affected_stops_count: 0,
+            affected_stop_ids: None,
+            derived: false,
+            translations: Vec::new(),
+        });
+        Arc::new(Mutex::new(cache))
+    }
+
+    #[test]
+    fn answers_the_known_commands() {
+        let cache = network();
+        for command in ["/next S1", "/next quinconces", "/next@NVTBot  Quinc "] {
+            let reply = handle_command(command, &cache).expect("reply");
+            assert!(reply.starts_with("Quinconces (S1)\nUpdated "), "{}: {}", command, reply);
+            assert!(reply.ends_with("No upcoming departures\n"), "{}: {}", command, reply);
+        }
+
+        assert_eq!(
+            handle_command("/alerts a", &cache).as_deref(),
+            Some("Alerts for line A (TBM):\n⚠️ Travaux place des Quinconces\n"),
+        );
+        assert_eq!(handle_command("/alerts 1", &cache).as_deref(), Some("No active alerts for line 1 (TBM)."));
+        for command in ["/help", "/start", "/next", "/alerts  "] {
+            assert_eq!(handle_command(command, &cache).as_deref(), Some(HELP_TEXT), "{}", command);
+        }
+    }
+
+    #[test]
+    fn leaves_other_messages_alone() {
+        let cache = network();
+        for message in ["/weather Bordeaux", "/nextS1", "next S1", "hello", ""] {
+            assert_eq!(handle_command(message, &cache), None, "{}", message);
+        }
+    }
+
+    #[test]
+    fn says_when_the_stop_or_line_is_unknown() {
+        let cache = network();
+        assert_eq!(handle_command("/next Mériadeck", &cache).as_deref(), Some("No stop matching \"Mériadeck\"."));
+        assert_eq!(handle_command("/alerts Z", &cache).as_deref(), Some("No line \"Z\"."));
+    }
+}
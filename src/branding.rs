@@ -0,0 +1,150 @@
+// Operator branding assets
+// Maps operators to brand colors and logos. Colors and logo files can be set per operator in
+// the configuration (logos live in `branding.assets_dir`); otherwise the brand color is derived
+// from the operator's line colors and an embedded SVG monogram is generated as the logo.
+// Also renders line bullets so clients don't have to duplicate the styling.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::colors::{contrast_text_color, is_hex_color};
+use crate::config::{self, OperatorBranding};
+use crate::operators::{self, operator_slug};
+use crate::tbm_api_models::Line;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Branding {
+    pub operator: String,
+    pub slug: String,
+    pub primary_color: String,
+    pub text_color: String,
+    pub logo_url: String,
+    pub source: String, // "config" or "derived"
+}
+
+/// Find the canonical operator name for a name or slug given in a URL
+pub fn resolve_operator(name: &str, lines: &[Line]) -> Option<String> {
+    lines.iter()
+        .map(|l| &l.operator)
+        .find(|op| operators::matches(op, name))
+        .cloned()
+}
+
+fn configured_branding(operator: &str) -> Option<&'static OperatorBranding> {
+    let operators = &config::get().branding.operators;
+    let slug = operator_slug(operator);
+    operators.get(operator)
+        .or_else(|| operators.iter().find(|(k, _)| operator_slug(k) == slug).map(|(_, v)| v))
+}
+
+fn dominant_line_color(operator: &str, lines: &[Line]) -> String {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for line in lines.iter().filter(|l| l.operator == operator) {
+        if is_hex_color(&line.color) && !line.color.eq_ignore_ascii_case("808080") {
+            *counts.entry(line.color.as_str()).or_insert(0) += 1;
+        }
+    }
+
+    counts.into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+        .map(|(color, _)| color.to_uppercase())
+        .unwrap_or_else(|| "808080".to_string())
+}
+
+pub fn get_branding(operator: &str, lines: &[Line]) -> Branding {
+    let slug = operator_slug(operator);
+    let configured = configured_branding(operator);
+
+    let primary_color = configured
+        .and_then(|b| b.primary_color.clone())
+        .filter(|c| is_hex_color(c))
+        .unwrap_or_else(|| dominant_line_color(operator, lines));
+
+    let text_color = configured
+        .and_then(|b| b.text_color.clone())
+        .filter(|c| is_hex_color(c))
+        .unwrap_or_else(|| contrast_text_color(&primary_color));
+
+    Branding {
+        operator: operator.to_string(),
+        logo_url: format!("/api/tbm/operator/{}/logo", slug),
+        slug,
+        primary_color,
+        text_color,
+        source: if configured.is_some() { "config" } else { "derived" }.to_string(),
+    }
+}
+
+/// Rounded-rect line bullet with the line code, sized to fit the text
+pub fn render_line_badge(line_code: &str, color: &str, text_color: &str) -> String {
+    let color = if is_hex_color(color) { color } else { "808080" };
+    let text_color = if is_hex_color(text_color) { text_color.to_string() } else { contrast_text_color(color) };
+
+    let label = line_code
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    let width = (line_code.chars().count() as u32 * 11 + 16).max(32);
+
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"32\" viewBox=\"0 0 {w} 32\">\
+         <rect width=\"{w}\" height=\"32\" rx=\"6\" fill=\"#{color}\"/>\
+         <text x=\"{cx}\" y=\"16\" dy=\"0.35em\" text-anchor=\"middle\" font-family=\"Helvetica, Arial, sans-serif\" \
+         font-weight=\"bold\" font-size=\"18\" fill=\"#{text_color}\">{label}</text></svg>",
+        w = width,
+        cx = width / 2,
+        color = color,
+        text_color = text_color,
+        label = label,
+    )
+}
+
+/// Logo bytes and content type: the configured file if present, otherwise a generated monogram
+pub fn get_logo(branding: &Branding) -> (Vec<u8>, &'static str) {
+    let assets_dir = config::get().branding.assets_dir.as_ref();
+    let logo_file = configured_branding(&branding.operator).and_then(|b| b.logo.as_ref());
+
+    if let (Some(dir), Some(file)) = (assets_dir, logo_file) {
+        // Only plain file names are accepted, never paths escaping the assets directory
+        if let Some(name) = Path::new(file).file_name() {
+            let path = dir.join(name);
+            match fs::read(&path) {
+                Ok(bytes) => {
+                    let content_type = match path.extension().and_then(|e| e.to_str()) {
+                        Some("svg") => "image/svg+xml",
+                        Some("png") => "image/png",
+                        Some("jpg") | Some("jpeg") => "image/jpeg",
+                        Some("webp") => "image/webp",
+                        _ => "application/octet-stream",
+                    };
+                    return (bytes, content_type);
+                }
+                Err(e) => eprintln!("⚠️  Failed to read logo {:?}: {}", path, e),
+            }
+        }
+    }
+
+    (monogram_svg(branding).into_bytes(), "image/svg+xml")
+}
+
+fn monogram_svg(branding: &Branding) -> String {
+    let initials: String = branding.operator
+        .split(|c: char| !c.is_alphanumeric())
+        .find(|w| !w.is_empty())
+        .map(|w| w.chars().take(4).collect::<String>().to_uppercase())
+        .unwrap_or_else(|| "?".to_string())
+        .replace('&', "&amp;")
+        .replace('<', "&lt;");
+
+    let font_size = if initials.chars().count() > 3 { 22 } else { 28 };
+
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"96\" height=\"96\" viewBox=\"0 0 96 96\">\
+         <rect width=\"96\" height=\"96\" rx=\"18\" fill=\"#{}\"/>\
+         <text x=\"48\" y=\"48\" dy=\"0.35em\" text-anchor=\"middle\" font-family=\"Helvetica, Arial, sans-serif\" \
+         font-weight=\"bold\" font-size=\"{}\" fill=\"#{}\">{}</text></svg>",
+        branding.primary_color, font_size, branding.text_color, initials
+    )
+}
@@ -0,0 +1,111 @@
+// Stop busyness ("popular times" proxy)
+// No ridership data is published, so how busy a stop is at each hour of a day is estimated
+// from the timetable: the arrivals of that hour relative to the busiest hour of the day. Hours
+// whose lines ran irregularly in the history samples (a high spread of their average delays)
+// score higher, since bunched or late vehicles crowd the platform.
+
+use chrono::{NaiveDate, Timelike};
+use chrono_tz::Europe::Paris;
+use serde::Serialize;
+
+use crate::history::HistoryStore;
+use crate::tbm_api_models::{NVTModels, ScheduledArrival};
+
+const DELAY_SPREAD_CAP: f64 = 600.0; // seconds; spreads above count as this
+const DELAY_WEIGHT: f64 = 0.5; // Score gain of an hour at the capped spread
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HourBusyness {
+    pub hour: u32, // 0-23, Europe/Paris
+    pub arrivals: usize,
+    pub delay_stddev: Option<f64>, // seconds, over the history samples of the stop's lines
+    pub score: f64, // 0.0 to 1.0
+    pub level: &'static str, // quiet, moderate, busy or very busy
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StopBusyness {
+    pub stop_id: String,
+    pub date: String, // YYYY-MM-DD
+    pub arrivals: usize,
+    pub peak_hour: Option<u32>,
+    pub hours: Vec<HourBusyness>,
+}
+
+fn level(score: f64) -> &'static str {
+    match score {
+        s if s < 0.25 => "quiet",
+        s if s < 0.5 => "moderate",
+        s if s < 0.75 => "busy",
+        _ => "very busy",
+    }
+}
+
+fn standard_deviation(values: &[f64]) -> Option<f64> {
+    if values.len() < 2 {
+        return None;
+    }
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    Some(variance.sqrt().round())
+}
+
+/// Average line delays of the samples taken during each hour, over the `line_keys`
+/// ("<operator>/<line code>", as in the history samples)
+fn delays_by_hour(history: &HistoryStore, line_keys: &[String]) -> [Vec<f64>; 24] {
+    let mut delays: [Vec<f64>; 24] = Default::default();
+    for sample in &history.samples {
+        let Some(time) = chrono::DateTime::from_timestamp(sample.timestamp, 0) else {
+            continue;
+        };
+        let hour = time.with_timezone(&Paris).hour() as usize;
+        for key in line_keys {
+            if let Some(line) = sample.lines.get(key).filter(|line| line.delayed_vehicles > 0) {
+                delays[hour].push(line.delay_total as f64 / f64::from(line.delayed_vehicles));
+            }
+        }
+    }
+    delays
+}
+
+/// Hourly busyness of a stop from its arrivals on `date`
+pub fn estimate(
+    stop_id: &str,
+    date: NaiveDate,
+    arrivals: &[ScheduledArrival],
+    history: &HistoryStore,
+    line_keys: &[String],
+) -> StopBusyness {
+    // Times past 24:00:00 are the small hours of the next day, counted with the same hour
+    let mut counts = [0usize; 24];
+    for arrival in arrivals {
+        if let Some(seconds) = NVTModels::parse_gtfs_time(&arrival.arrival_time) {
+            counts[(seconds / 3600 % 24) as usize] += 1;
+        }
+    }
+
+    let delays = delays_by_hour(history, line_keys);
+    let busiest = counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+    let hours: Vec<HourBusyness> = (0..24)
+        .map(|hour| {
+            let delay_stddev = standard_deviation(&delays[hour]);
+            let irregularity = delay_stddev.map_or(0.0, |spread| spread.min(DELAY_SPREAD_CAP) / DELAY_SPREAD_CAP);
+            let score = (counts[hour] as f64 / busiest * (1.0 + DELAY_WEIGHT * irregularity)).min(1.0);
+            let score = (score * 100.0).round() / 100.0;
+            HourBusyness { hour: hour as u32, arrivals: counts[hour], delay_stddev, score, level: level(score) }
+        })
+        .collect();
+
+    let peak_hour = hours.iter()
+        .filter(|hour| hour.arrivals > 0)
+        .max_by(|a, b| a.score.total_cmp(&b.score).then_with(|| b.hour.cmp(&a.hour)))
+        .map(|hour| hour.hour);
+
+    StopBusyness {
+        stop_id: stop_id.to_string(),
+        date: date.format("%Y-%m-%d").to_string(),
+        arrivals: arrivals.len(),
+        peak_hour,
+        hours,
+    }
+}
@@ -0,0 +1,414 @@
+// Client bindings (`gen-client` command)
+// Generates typed bindings for downstream apps from the JSON Schema of the API (see schema.rs):
+// one TypeScript interface or Rust struct per model of `$defs` (Stop, Line, AlertInfo,
+// ScheduledArrival, ...), the ApiResponse envelope, and a client with one method per endpoint
+// returning the envelope's data. The schema is the one compiled in, or with --from the one a
+// running server serves at /api/schema, so the bindings can be regenerated against a deployment.
+
+use serde_json::Value;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::schema;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lang {
+    TypeScript,
+    Rust,
+}
+
+pub struct GenOptions {
+    pub lang: Lang,
+    pub out: Option<PathBuf>, // stdout when None
+    pub from: Option<String>, // URL of a served schema, instead of the compiled-in one
+}
+
+impl GenOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut lang = None;
+        let mut options = GenOptions { lang: Lang::TypeScript, out: None, from: None };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().cloned().ok_or_else(|| format!("{} needs a value", name));
+            match arg.as_str() {
+                "--lang" => lang = Some(match value(arg)?.as_str() {
+                    "ts" | "typescript" => Lang::TypeScript,
+                    "rust" => Lang::Rust,
+                    other => return Err(format!("Unknown language '{}', expected ts or rust", other)),
+                }),
+                "--out" => options.out = Some(PathBuf::from(value(arg)?)),
+                "--from" => options.from = Some(value(arg)?),
+                other => return Err(format!("Unknown gen-client option '{}'", other)),
+            }
+        }
+        options.lang = lang.ok_or("--lang ts|rust is required")?;
+        Ok(options)
+    }
+}
+
+/// Type of a property or response, as the schema describes it
+#[derive(Debug, Clone, PartialEq)]
+enum Ty {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    Model(String),
+    Array(Box<Ty>),
+    Tuple(Vec<Ty>),
+    Map(Box<Ty>), // String keys
+    Nullable(Box<Ty>),
+    Any,
+}
+
+impl Ty {
+    fn of(schema: &Value) -> Ty {
+        if let Some(reference) = schema["$ref"].as_str() {
+            return Ty::Model(reference.trim_start_matches("#/$defs/").to_string());
+        }
+        if let Some(any_of) = schema["anyOf"].as_array() {
+            let is_null = |member: &&Value| member["type"] == "null";
+            let members: Vec<&Value> = any_of.iter().filter(|member| !is_null(member)).collect();
+            return match members.as_slice() {
+                [member] if any_of.iter().any(|member| is_null(&member)) => Ty::Nullable(Box::new(Ty::of(member))),
+                [member] => Ty::of(member),
+                _ => Ty::Any,
+            };
+        }
+        if let Some(names) = schema["type"].as_array() {
+            let names: Vec<&Value> = names.iter().filter(|name| *name != "null").collect();
+            let mut inner = schema.clone();
+            return match names.as_slice() {
+                [name] => {
+                    inner["type"] = (*name).clone();
+                    Ty::Nullable(Box::new(Ty::of(&inner)))
+                }
+                _ => Ty::Any,
+            };
+        }
+        match schema["type"].as_str() {
+            Some("string") => Ty::String,
+            Some("integer") => Ty::Integer,
+            Some("number") => Ty::Number,
+            Some("boolean") => Ty::Boolean,
+            Some("array") => match schema["prefixItems"].as_array() {
+                Some(items) => Ty::Tuple(items.iter().map(Ty::of).collect()),
+                None => Ty::Array(Box::new(Ty::of(&schema["items"]))),
+            },
+            Some("object") if schema["additionalProperties"].is_object() => Ty::Map(Box::new(Ty::of(&schema["additionalProperties"]))),
+            _ => Ty::Any,
+        }
+    }
+
+    fn typescript(&self) -> String {
+        match self {
+            Ty::String => "string".to_string(),
+            Ty::Integer | Ty::Number => "number".to_string(),
+            Ty::Boolean => "boolean".to_string(),
+            Ty::Model(name) => name.clone(),
+            Ty::Array(item) => match **item {
+                Ty::Nullable(_) => format!("({})[]", item.typescript()),
+                _ => format!("{}[]", item.typescript()),
+            },
+            Ty::Tuple(items) => format!("[{}]", items.iter().map(Ty::typescript).collect::<Vec<_>>().join(", ")),
+            Ty::Map(value) => format!("Record<string, {}>", value.typescript()),
+            Ty::Nullable(inner) => format!("{} | null", inner.typescript()),
+            Ty::Any => "unknown".to_string(),
+        }
+    }
+
+    fn rust(&self) -> String {
+        match self {
+            Ty::String => "String".to_string(),
+            Ty::Integer => "i64".to_string(),
+            Ty::Number => "f64".to_string(),
+            Ty::Boolean => "bool".to_string(),
+            Ty::Model(name) => name.clone(),
+            Ty::Array(item) => format!("Vec<{}>", item.rust()),
+            Ty::Tuple(items) => format!("({})", items.iter().map(Ty::rust).collect::<Vec<_>>().join(", ")),
+            Ty::Map(value) => format!("HashMap<String, {}>", value.rust()),
+            Ty::Nullable(inner) => format!("Option<{}>", inner.rust()),
+            Ty::Any => "serde_json::Value".to_string(),
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    ty: Ty,
+    required: bool,
+}
+
+struct Model {
+    name: String,
+    properties: Vec<Property>,
+}
+
+/// An endpoint of the schema, e.g. /api/tbm/stop/{id}/schedule as stop_schedule(id)
+struct Endpoint {
+    words: Vec<String>,
+    path: String, // Without the query
+    params: Vec<String>, // Path parameters, then query parameters
+    query: Vec<String>,
+    data: Ty,
+}
+
+impl Endpoint {
+    fn parse(key: &str, envelope: &Value) -> Endpoint {
+        let (path, query) = key.split_once('?').unwrap_or((key, ""));
+        let query: Vec<String> = query.split('&')
+            .filter_map(|pair| pair.split('=').next())
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect();
+        let mut words = Vec::new();
+        let mut params = Vec::new();
+        for segment in path.trim_start_matches("/api/tbm/").trim_start_matches('/').split('/') {
+            match segment.strip_prefix('{').and_then(|name| name.strip_suffix('}')) {
+                Some(param) => params.push(param.to_string()),
+                None => words.extend(segment.split(['-', '.', '_']).filter(|word| !word.is_empty()).map(str::to_lowercase)),
+            }
+        }
+        words.extend(query.iter().cloned());
+        params.extend(query.iter().cloned());
+
+        let data = match Ty::of(&envelope["properties"]["data"]) {
+            Ty::Nullable(inner) => *inner,
+            other => other,
+        };
+        Endpoint { words, path: path.to_string(), params, query, data }
+    }
+
+    fn snake_name(&self) -> String {
+        self.words.join("_")
+    }
+
+    fn camel_name(&self) -> String {
+        self.words.iter().enumerate()
+            .map(|(i, word)| match i {
+                0 => word.clone(),
+                _ => word[..1].to_uppercase() + &word[1..],
+            })
+            .collect()
+    }
+}
+
+struct Bindings {
+    models: Vec<Model>,
+    endpoints: Vec<Endpoint>,
+}
+
+impl Bindings {
+    fn from_schema(document: &Value) -> Bindings {
+        let models = document["$defs"].as_object().into_iter().flatten()
+            .map(|(name, schema)| {
+                let required: Vec<&str> = schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str).collect();
+                Model {
+                    name: name.clone(),
+                    properties: schema["properties"].as_object().into_iter().flatten()
+                        .map(|(property, schema)| Property {
+                            name: property.clone(),
+                            ty: Ty::of(schema),
+                            required: required.contains(&property.as_str()),
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+        let endpoints = document["endpoints"].as_object().into_iter().flatten()
+            .map(|(key, envelope)| Endpoint::parse(key, envelope))
+            .collect();
+        Bindings { models, endpoints }
+    }
+}
+
+// ============================================================================
+// TypeScript
+// ============================================================================
+
+fn typescript(bindings: &Bindings) -> String {
+    let mut out = String::from("// Generated by `NVTWebEdition gen-client --lang ts` from the API schema; do not edit\n\n");
+    for model in &bindings.models {
+        out += &format!("export interface {} {{\n", model.name);
+        for property in &model.properties {
+            let optional = if property.required { "" } else { "?" };
+            out += &format!("  {}{}: {};\n", property.name, optional, property.ty.typescript());
+        }
+        out += "}\n\n";
+    }
+
+    out += "export interface ApiResponse<T> {\n  success: boolean;\n  data?: T | null;\n  error?: string | null;\n  timestamp: number;\n  sources: string[];\n}\n\n";
+    out += "export class NvtClient {\n";
+    out += "  constructor(private readonly baseUrl: string = \"http://localhost:8080\") {}\n\n";
+    out += "  private async get<T>(path: string): Promise<T> {\n";
+    out += "    const response = await fetch(this.baseUrl.replace(/\\/$/, \"\") + path);\n";
+    out += "    const body = (await response.json()) as ApiResponse<T>;\n";
+    out += "    if (!body.success || body.data === undefined || body.data === null) {\n";
+    out += "      throw new Error(body.error ?? `HTTP ${response.status}`);\n";
+    out += "    }\n";
+    out += "    return body.data;\n";
+    out += "  }\n";
+    for endpoint in &bindings.endpoints {
+        let params: Vec<String> = endpoint.params.iter().map(|param| format!("{}: string", param)).collect();
+        let mut url = endpoint.path.clone();
+        for param in endpoint.params.iter().filter(|param| !endpoint.query.contains(param)) {
+            url = url.replace(&format!("{{{}}}", param), &format!("${{encodeURIComponent({})}}", param));
+        }
+        let query: Vec<String> = endpoint.query.iter().map(|param| format!("{0}=${{encodeURIComponent({0})}}", param)).collect();
+        if !query.is_empty() {
+            url = format!("{}?{}", url, query.join("&"));
+        }
+        out += &format!(
+            "\n  {}({}): Promise<{}> {{\n    return this.get(`{}`);\n  }}\n",
+            endpoint.camel_name(),
+            params.join(", "),
+            endpoint.data.typescript(),
+            url,
+        );
+    }
+    out += "}\n";
+    out
+}
+
+// ============================================================================
+// Rust
+// ============================================================================
+
+const RUST_KEYWORDS: &[&str] = &["as", "fn", "impl", "in", "loop", "match", "mod", "move", "ref", "struct", "type", "use", "where"];
+
+fn rust_ident(name: &str) -> String {
+    if RUST_KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn rust(bindings: &Bindings) -> String {
+    let mut out = String::from("//! Generated by `NVTWebEdition gen-client --lang rust` from the API schema; do not edit\n");
+    out += "//! Needs serde (derive), serde_json and reqwest (blocking, json)\n\n";
+    out += "#![allow(dead_code)]\n\nuse serde::Deserialize;\nuse std::collections::HashMap;\n\n";
+    for model in &bindings.models {
+        out += &format!("#[derive(Debug, Clone, Deserialize)]\npub struct {} {{\n", model.name);
+        for property in &model.properties {
+            let ty = match (&property.ty, property.required) {
+                (Ty::Nullable(_), _) | (_, true) => property.ty.rust(),
+                (ty, false) => format!("Option<{}>", ty.rust()),
+            };
+            if !property.required {
+                out += "    #[serde(default)]\n";
+            }
+            out += &format!("    pub {}: {},\n", rust_ident(&property.name), ty);
+        }
+        out += "}\n\n";
+    }
+
+    out += "#[derive(Debug, Clone, Deserialize)]\npub struct ApiResponse<T> {\n    pub success: bool,\n    pub data: Option<T>,\n    pub error: Option<String>,\n    pub timestamp: i64,\n    pub sources: Vec<String>,\n}\n\n";
+    out += "pub struct Client {\n    base_url: String,\n    http: reqwest::blocking::Client,\n}\n\n";
+    out += "impl Client {\n";
+    out += "    pub fn new(base_url: &str) -> Self {\n";
+    out += "        Client { base_url: base_url.trim_end_matches('/').to_string(), http: reqwest::blocking::Client::new() }\n";
+    out += "    }\n\n";
+    out += "    fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, String> {\n";
+    out += "        let response: ApiResponse<T> = self.http.get(format!(\"{}{}\", self.base_url, path))\n";
+    out += "            .send()\n";
+    out += "            .and_then(|response| response.json())\n";
+    out += "            .map_err(|e| e.to_string())?;\n";
+    out += "        match response.data {\n";
+    out += "            Some(data) if response.success => Ok(data),\n";
+    out += "            _ => Err(response.error.unwrap_or_else(|| \"No data in the response\".to_string())),\n";
+    out += "        }\n";
+    out += "    }\n";
+    for endpoint in &bindings.endpoints {
+        let params: Vec<String> = endpoint.params.iter().map(|param| format!("{}: &str", rust_ident(param))).collect();
+        let mut template = endpoint.path.clone();
+        for param in endpoint.params.iter().filter(|param| !endpoint.query.contains(param)) {
+            template = template.replace(&format!("{{{}}}", param), "{}");
+        }
+        let query: Vec<String> = endpoint.query.iter().map(|param| format!("{}={{}}", param)).collect();
+        if !query.is_empty() {
+            template = format!("{}?{}", template, query.join("&"));
+        }
+        let args: Vec<String> = endpoint.params.iter().map(|param| format!(", encode({})", rust_ident(param))).collect();
+        let path = if args.is_empty() {
+            format!("\"{}\"", template)
+        } else {
+            format!("&format!(\"{}\"{})", template, args.concat())
+        };
+        out += &format!(
+            "\n    pub fn {}(&self{}) -> Result<{}, String> {{\n        self.get({})\n    }}\n",
+            endpoint.snake_name(),
+            params.iter().map(|param| format!(", {}", param)).collect::<String>(),
+            endpoint.data.rust(),
+            path,
+        );
+    }
+    out += "}\n\n";
+    out += "/// Percent-encode a path segment or query value\n";
+    out += "fn encode(value: &str) -> String {\n";
+    out += "    value.bytes()\n";
+    out += "        .map(|byte| match byte {\n";
+    out += "            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' => (byte as char).to_string(),\n";
+    out += "            _ => format!(\"%{:02X}\", byte),\n";
+    out += "        })\n";
+    out += "        .collect()\n";
+    out += "}\n";
+    out
+}
+
+/// Bindings in `lang` for the schema `document`
+pub fn generate(document: &Value, lang: Lang) -> String {
+    let bindings = Bindings::from_schema(document);
+    match lang {
+        Lang::TypeScript => typescript(&bindings),
+        Lang::Rust => rust(&bindings),
+    }
+}
+
+fn fetch_schema(url: &str) -> Result<Value, String> {
+    reqwest::blocking::get(url)
+        .and_then(|response| response.error_for_status())
+        .and_then(|response| response.json())
+        .map_err(|e| format!("Failed to fetch the schema from {} ({})", url, e))
+}
+
+/// `gen-client --lang ts|rust [--out FILE] [--from URL]`; returns the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let options = match GenOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            return 2;
+        }
+    };
+
+    let document = match &options.from {
+        Some(url) => match fetch_schema(url) {
+            Ok(document) => document,
+            Err(e) => {
+                eprintln!("❌ {}", e);
+                return 1;
+            }
+        },
+        None => schema::document(),
+    };
+    let bindings = generate(&document, options.lang);
+
+    match &options.out {
+        Some(path) => match fs::write(path, bindings) {
+            Ok(()) => {
+                eprintln!("✓ Client bindings written to {}", path.display());
+                0
+            }
+            Err(e) => {
+                eprintln!("❌ Failed to write {} ({})", path.display(), e);
+                1
+            }
+        },
+        None => {
+            print!("{}", bindings);
+            0
+        }
+    }
+}
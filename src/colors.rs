@@ -0,0 +1,63 @@
+// Line colors
+// Feeds ship route colors that aren't hex, and text colors that are missing or unreadable on
+// them (white on white). Lines carry colors corrected for display: the route color, or gray
+// when it isn't valid hex, and a text color with enough contrast on it, black or white when
+// the feed's has too little. The feed's own values stay available as raw_color and
+// raw_text_color.
+
+pub const FALLBACK_COLOR: &str = "808080";
+
+/// Lowest contrast ratio at which the feed's text color is kept: WCAG AA for large text, which
+/// a bold line badge is
+const MIN_CONTRAST_RATIO: f64 = 3.0;
+
+pub fn is_hex_color(color: &str) -> bool {
+    color.len() == 6 && color.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Six uppercase hex digits without '#', if `color` is a hex color
+pub fn normalize_hex(color: &str) -> Option<String> {
+    let color = color.trim().trim_start_matches('#');
+    is_hex_color(color).then(|| color.to_uppercase())
+}
+
+/// WCAG relative luminance of a hex color
+fn relative_luminance(color: &str) -> f64 {
+    let channel = |i: usize| {
+        let v = u8::from_str_radix(&color[i..i + 2], 16).unwrap_or(0) as f64 / 255.0;
+        if v <= 0.03928 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+    };
+    0.2126 * channel(0) + 0.7152 * channel(2) + 0.0722 * channel(4)
+}
+
+/// WCAG contrast ratio of two hex colors, from 1 (same luminance) to 21 (black on white)
+pub fn contrast_ratio(a: &str, b: &str) -> f64 {
+    let (a, b) = (relative_luminance(a), relative_luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// Black or white, whichever reads better on the given background (WCAG relative luminance)
+pub fn contrast_text_color(background: &str) -> String {
+    if !is_hex_color(background) {
+        return "FFFFFF".to_string();
+    }
+
+    let luminance = relative_luminance(background);
+
+    // Contrast against white vs black; pick the larger one
+    if (1.05 / (luminance + 0.05)) >= ((luminance + 0.05) / 0.05) {
+        "FFFFFF".to_string()
+    } else {
+        "000000".to_string()
+    }
+}
+
+/// (color, text color) to display for the route color and text color of a feed
+pub fn readable_colors(raw_color: &str, raw_text_color: Option<&str>) -> (String, String) {
+    let color = normalize_hex(raw_color).unwrap_or_else(|| FALLBACK_COLOR.to_string());
+    let text_color = raw_text_color
+        .and_then(normalize_hex)
+        .filter(|text_color| contrast_ratio(&color, text_color) >= MIN_CONTRAST_RATIO)
+        .unwrap_or_else(|| contrast_text_color(&color));
+    (color, text_color)
+}
@@ -0,0 +1,182 @@
+// Commune (municipality) lookup
+// Boundaries of the New-Aquitaine communes come from geo.api.gouv.fr and are cached in
+// tbm_nvt/communes.json; each stop is placed in its commune by point-in-polygon at static
+// load, giving the commune name and INSEE code local authorities filter by.
+
+use reqwest::blocking;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::tbm_api_models::{CachedNetworkData, NVTError, Result};
+
+pub const COMMUNES_URL: &str =
+    "https://geo.api.gouv.fr/communes?codeRegion=75&fields=nom,code&format=geojson&geometry=contour";
+const CACHE_MAX_AGE_DAYS: u64 = 90; // boundaries change once a year at most
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+// ============================================================================
+// Data Structures
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Commune {
+    pub name: String,
+    pub insee_code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CommuneArea {
+    commune: Commune,
+    bbox: (f64, f64, f64, f64), // min_lon, min_lat, max_lon, max_lat
+    rings: Vec<Vec<(f64, f64)>>, // (lon, lat); outer rings and holes together, even-odd rule
+}
+
+impl CommuneArea {
+    fn contains(&self, lat: f64, lon: f64) -> bool {
+        let (min_lon, min_lat, max_lon, max_lat) = self.bbox;
+        if lon < min_lon || lon > max_lon || lat < min_lat || lat > max_lat {
+            return false;
+        }
+
+        let mut inside = false;
+        for ring in &self.rings {
+            for (i, &(x1, y1)) in ring.iter().enumerate() {
+                let (x2, y2) = ring[(i + 1) % ring.len()];
+                if (y1 > lat) != (y2 > lat) && lon < x1 + (lat - y1) * (x2 - x1) / (y2 - y1) {
+                    inside = !inside;
+                }
+            }
+        }
+        inside
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CommuneIndex {
+    cached_at: u64,
+    communes: Vec<CommuneArea>,
+}
+
+// ============================================================================
+// Loading
+// ============================================================================
+
+impl CommuneIndex {
+    fn cache_path(cache_dir: &Path) -> PathBuf {
+        fs::create_dir_all(cache_dir).ok();
+        cache_dir.join("communes.json")
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    /// Cached boundaries if fresh enough, otherwise a new download; empty when both fail
+    pub fn load(url: &str, cache_dir: &Path) -> Self {
+        let cached = fs::read_to_string(Self::cache_path(cache_dir)).ok()
+            .and_then(|contents| serde_json::from_str::<CommuneIndex>(&contents).ok())
+            .filter(|index| Self::now().saturating_sub(index.cached_at) < CACHE_MAX_AGE_DAYS * 86400);
+        if let Some(index) = cached {
+            println!("   ✓ Commune boundaries loaded from cache ({} communes)", index.communes.len());
+            return index;
+        }
+
+        match Self::download(url) {
+            Ok(index) => {
+                println!("   ✓ Downloaded boundaries of {} communes", index.communes.len());
+                if let Ok(json) = serde_json::to_string(&index) {
+                    fs::write(Self::cache_path(cache_dir), json).ok();
+                }
+                index
+            }
+            Err(e) => {
+                println!("   ⚠️  Warning: Could not load commune boundaries ({})", e);
+                CommuneIndex::default()
+            }
+        }
+    }
+
+    fn download(url: &str) -> Result<Self> {
+        let client = blocking::Client::builder()
+            .timeout(DOWNLOAD_TIMEOUT)
+            .build()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to create HTTP client: {}", e)))?;
+
+        let response = client.get(url)
+            .send()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to fetch communes: {}", e)))?;
+
+        if !response.status().is_success() {
+            return Err(NVTError::NetworkError(format!("Communes API returned error: {}", response.status())));
+        }
+
+        let json: serde_json::Value = response.json()
+            .map_err(|e| NVTError::ParseError(format!("Invalid communes GeoJSON: {}", e)))?;
+
+        let features = json["features"].as_array()
+            .ok_or_else(|| NVTError::ParseError("Missing communes features".to_string()))?;
+
+        let communes = features.iter().filter_map(Self::parse_feature).collect();
+        Ok(CommuneIndex { cached_at: Self::now(), communes })
+    }
+
+    fn parse_feature(feature: &serde_json::Value) -> Option<CommuneArea> {
+        let commune = Commune {
+            name: feature["properties"]["nom"].as_str()?.to_string(),
+            insee_code: feature["properties"]["code"].as_str()?.to_string(),
+        };
+
+        let parse_ring = |ring: &serde_json::Value| -> Option<Vec<(f64, f64)>> {
+            ring.as_array()?.iter()
+                .map(|point| Some((point[0].as_f64()?, point[1].as_f64()?)))
+                .collect()
+        };
+
+        let geometry = &feature["geometry"];
+        let polygons: Vec<&serde_json::Value> = match geometry["type"].as_str()? {
+            "Polygon" => vec![&geometry["coordinates"]],
+            "MultiPolygon" => geometry["coordinates"].as_array()?.iter().collect(),
+            _ => return None,
+        };
+        let rings: Vec<Vec<(f64, f64)>> = polygons.iter()
+            .filter_map(|polygon| polygon.as_array())
+            .flatten()
+            .filter_map(parse_ring)
+            .filter(|ring| ring.len() >= 3)
+            .collect();
+
+        let bbox = rings.iter().flatten().fold(
+            (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+            |(x0, y0, x1, y1), &(x, y)| (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+        );
+        (!rings.is_empty()).then_some(CommuneArea { commune, bbox, rings })
+    }
+
+    // ============================================================================
+    // Lookup
+    // ============================================================================
+
+    pub fn locate(&self, lat: f64, lon: f64) -> Option<&Commune> {
+        self.communes.iter()
+            .find(|area| area.contains(lat, lon))
+            .map(|area| &area.commune)
+    }
+
+    /// Commune of every stop of every source, keyed by stop id
+    pub fn assign_stops(&self, cache: &CachedNetworkData) -> HashMap<String, Commune> {
+        let assigned: HashMap<String, Commune> = cache.stop_positions().into_iter()
+            .filter_map(|(id, lat, lon)| Some((id, self.locate(lat, lon)?.clone())))
+            .collect();
+
+        if !self.communes.is_empty() {
+            println!("   ✓ Placed {} stops in their commune", assigned.len());
+        }
+        assigned
+    }
+}
@@ -0,0 +1,697 @@
+// Server configuration
+// Optional JSON file read once at startup. The path comes from the NVT_CONFIG environment
+// variable, falling back to ./nvt_config.json. A missing file means built-in defaults.
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+static CONFIG: OnceLock<AppConfig> = OnceLock::new();
+
+// ============================================================================
+// Configuration Structures
+// ============================================================================
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AppConfig {
+    /// Public base URL of this server (e.g. "https://nvt.example.org"), used in printed links.
+    /// Defaults to the scheme and host of the incoming request.
+    pub public_url: Option<String>,
+    /// Digital-signage screens, keyed by the config id used in /api/tbm/kiosk/{config_id}
+    pub kiosks: HashMap<String, KioskConfig>,
+    pub branding: BrandingConfig,
+    /// Chat bot connection; only used when built with the "bot" feature
+    pub bot: Option<BotConfig>,
+    /// Morning email digest of disruptions
+    pub digest: Option<DigestConfig>,
+    /// OpenStreetMap amenity enrichment of stops (shelter, bench, lighting, tactile paving)
+    pub amenities: Option<AmenitiesConfig>,
+    /// Shape synthesis for New-Aquitaine and SNCF routes published without shapes
+    pub map_matching: Option<MapMatchingConfig>,
+    /// Navitia API, asked for the journeys and disruptions the local feeds don't cover
+    pub navitia: Option<NavitiaConfig>,
+    pub request_timeouts: RequestTimeoutsConfig,
+    pub cache_ages: CacheAgesConfig,
+    /// GTFS sources to load in place of the built-in TBM, New-Aquitaine and SNCF feeds
+    pub feeds: Vec<GtfsFeedConfig>,
+    pub health: HealthConfig,
+    pub concurrency: ConcurrencyConfig,
+    pub http_cache: HttpCacheConfig,
+    pub history: HistoryConfig,
+    pub derived_alerts: DerivedAlertsConfig,
+    pub privacy: PrivacyConfig,
+    pub journey_shares: JourneySharesConfig,
+    pub access_log: AccessLogConfig,
+    pub cors: CorsConfig,
+    pub api_keys: ApiKeysConfig,
+    /// Hot standby: the primary serves its cache at /admin/sync, the standby pulls it from there
+    pub sync: Option<SyncConfig>,
+    /// Schedule what-ifs: GTFS fragments posted to /admin/simulate, served under /api/sandbox
+    pub simulation: Option<SimulationConfig>,
+    /// Line code derivation from route ids, per source ("tbm", "naq", "sncf"). The first
+    /// matching rule wins; ids no rule matches keep the built-in derivation.
+    pub line_code_rules: HashMap<String, Vec<IdRule>>,
+    /// Canonical operator names keyed by the names or slugs feeds use for them
+    /// (e.g. "CALIBUS" -> "Calibus (Libourne)")
+    pub operator_aliases: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct KioskConfig {
+    #[serde(default)]
+    pub title: Option<String>,
+    pub stops: Vec<String>,
+    #[serde(default)]
+    pub lines: Vec<String>, // Line codes to display; empty means every line
+    #[serde(default = "KioskConfig::default_rotation_secs")]
+    pub rotation_secs: u64,
+    #[serde(default = "KioskConfig::default_language")]
+    pub language: String,
+    #[serde(default = "KioskConfig::default_departures_per_screen")]
+    pub departures_per_screen: usize,
+}
+
+impl KioskConfig {
+    fn default_rotation_secs() -> u64 { 15 }
+    fn default_language() -> String { "fr".to_string() }
+    fn default_departures_per_screen() -> usize { 8 }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BrandingConfig {
+    /// Directory holding logo files referenced by `OperatorBranding::logo`
+    pub assets_dir: Option<PathBuf>,
+    /// Overrides keyed by operator name or slug
+    pub operators: HashMap<String, OperatorBranding>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OperatorBranding {
+    pub primary_color: Option<String>, // Hex without '#'
+    pub text_color: Option<String>,
+    pub logo: Option<String>, // File name inside assets_dir
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BotPlatform {
+    Telegram,
+    Matrix,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(not(feature = "bot"), allow(dead_code))]
+pub struct BotConfig {
+    pub platform: BotPlatform,
+    #[serde(default)]
+    pub token: Option<String>, // Falls back to the NVT_BOT_TOKEN environment variable
+    #[serde(default)]
+    pub homeserver: Option<String>, // Matrix only, e.g. "https://matrix.org"
+    #[serde(default = "BotConfig::default_poll_timeout_secs")]
+    pub poll_timeout_secs: u64,
+}
+
+impl BotConfig {
+    fn default_poll_timeout_secs() -> u64 { 30 }
+
+    #[cfg_attr(not(feature = "bot"), allow(dead_code))]
+    pub fn resolve_token(&self) -> Option<String> {
+        self.token.clone()
+            .or_else(|| std::env::var("NVT_BOT_TOKEN").ok())
+            .filter(|t| !t.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DigestConfig {
+    pub smtp: SmtpConfig,
+    #[serde(default = "DigestConfig::default_send_at")]
+    pub send_at: String, // HH:MM, Europe/Paris
+    pub subscriptions: Vec<DigestSubscription>,
+}
+
+impl DigestConfig {
+    fn default_send_at() -> String { "07:00".to_string() }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DigestSubscription {
+    pub recipients: Vec<String>,
+    pub lines: Vec<String>, // Line codes
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    Tls,      // Implicit TLS, usually port 465
+    Starttls, // Usually port 587
+    None,     // Local relays only
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "SmtpConfig::default_port")]
+    pub port: u16,
+    #[serde(default = "SmtpConfig::default_security")]
+    pub security: SmtpSecurity,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>, // Falls back to the NVT_SMTP_PASSWORD environment variable
+    pub from: String,
+}
+
+impl SmtpConfig {
+    fn default_port() -> u16 { 465 }
+    fn default_security() -> SmtpSecurity { SmtpSecurity::Tls }
+
+    pub fn resolve_password(&self) -> Option<String> {
+        self.password.clone().or_else(|| std::env::var("NVT_SMTP_PASSWORD").ok())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AmenitiesConfig {
+    #[serde(default = "AmenitiesConfig::default_overpass_url")]
+    pub overpass_url: String,
+    #[serde(default = "AmenitiesConfig::default_request_interval_secs")]
+    pub request_interval_secs: u64, // Pause between Overpass queries, be nice to public instances
+    #[serde(default = "AmenitiesConfig::default_max_age_days")]
+    pub max_age_days: u64,
+}
+
+impl AmenitiesConfig {
+    fn default_overpass_url() -> String { "https://overpass-api.de/api/interpreter".to_string() }
+    fn default_request_interval_secs() -> u64 { 5 }
+    fn default_max_age_days() -> u64 { 30 }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NavitiaConfig {
+    pub api_key: String,
+    #[serde(default = "NavitiaConfig::default_base_url")]
+    pub base_url: String,
+    #[serde(default = "NavitiaConfig::default_coverage")]
+    pub coverage: String, // Navitia region, e.g. "sncf" or "fr-sw"
+    #[serde(default = "NavitiaConfig::default_cache_ttl_secs")]
+    pub cache_ttl_secs: u64, // How long each response is reused, to stay within the key's quota
+}
+
+impl NavitiaConfig {
+    fn default_base_url() -> String { "https://api.sncf.com/v1".to_string() }
+    fn default_coverage() -> String { "sncf".to_string() }
+    fn default_cache_ttl_secs() -> u64 { 300 }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MapMatchingConfig {
+    /// OSRM server routing bus shapes along roads (e.g. "https://router.project-osrm.org").
+    /// Without one, shapes join the stops with straight segments.
+    pub osrm_url: Option<String>,
+    pub osrm_profile: Option<String>, // Defaults to "driving"
+}
+
+/// Per-request deadlines; requests running longer are answered with 504 Gateway Timeout
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RequestTimeoutsConfig {
+    pub default_secs: u64,
+    /// Overrides keyed by path prefix (e.g. "/api/tbm/line"); the longest matching prefix wins
+    pub routes: HashMap<String, u64>,
+}
+
+impl Default for RequestTimeoutsConfig {
+    fn default() -> Self {
+        RequestTimeoutsConfig {
+            default_secs: 30,
+            // A manual refresh may download every GTFS feed
+            routes: HashMap::from([("/api/tbm/refresh".to_string(), 600)]),
+        }
+    }
+}
+
+impl RequestTimeoutsConfig {
+    pub fn deadline_for(&self, path: &str) -> Duration {
+        let secs = self.routes.iter()
+            .filter(|(prefix, _)| path_has_prefix(path, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default_secs, |(_, secs)| *secs);
+        Duration::from_secs(secs)
+    }
+}
+
+/// How long each source's downloaded GTFS feed is reused before a new download, and how often
+/// the static data is reloaded (a reload only downloads the feeds past their age)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CacheAgesConfig {
+    pub tbm_days: u64,
+    pub naq_days: u64,
+    pub sncf_days: u64,
+    pub static_refresh_secs: u64, // Checked at each refresh; TBM stops and lines are fetched again too
+}
+
+impl Default for CacheAgesConfig {
+    fn default() -> Self {
+        CacheAgesConfig { tbm_days: 15, naq_days: 30, sncf_days: 30, static_refresh_secs: 3600 }
+    }
+}
+
+/// A GTFS source of the "feeds" list. TBM, NewAquitaine and SNCF are read by the parsers of
+/// those networks; feeds of any other name are read as plain GTFS and served as sources of
+/// their own under that name, their ids prefixed with `id_prefix` so that they can't collide.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GtfsFeedConfig {
+    pub name: String,
+    pub static_url: String,
+    #[serde(default)]
+    pub rt_urls: Vec<String>, // GTFS-RT trip updates, alerts and vehicle positions, merged in at each refresh
+    #[serde(default = "GtfsFeedConfig::default_max_age_days")]
+    pub max_age_days: u64, // How long the downloaded feed is reused
+    #[serde(default)]
+    pub id_prefix: Option<String>, // e.g. "LYON:", put before every stop, route, trip and service id
+    #[serde(default)]
+    pub mode: Option<crate::tbm_api_models::TransportMode>, // e.g. "coach", for every route whatever its route_type
+}
+
+impl GtfsFeedConfig {
+    fn default_max_age_days() -> u64 {
+        30
+    }
+
+    /// Whether this is a feed of the built-in networks rather than a plain GTFS one
+    pub fn is_built_in(&self) -> bool {
+        crate::tbm_api_models::SOURCES.contains(&self.name.as_str())
+    }
+}
+
+/// Data ages past which /health reports degraded, with 503 Service Unavailable
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    pub max_real_time_age_secs: u64,
+    pub max_static_age_secs: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig { max_real_time_age_secs: 300, max_static_age_secs: 21600 }
+    }
+}
+
+/// Shared limit on simultaneous requests to expensive endpoints; excess requests wait up to
+/// `queue_timeout_ms` for a slot, then get 429 Too Many Requests
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ConcurrencyConfig {
+    pub max_concurrent: usize,
+    pub queue_timeout_ms: u64, // 0 sheds immediately
+    pub routes: Vec<String>, // Path prefixes sharing the limit
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        ConcurrencyConfig {
+            max_concurrent: 4,
+            queue_timeout_ms: 2000,
+            routes: vec![
+                "/api/tbm/network".to_string(),
+                "/tiles".to_string(),
+                "/api/tbm/export".to_string(),
+                "/admin/history/export".to_string(),
+            ],
+        }
+    }
+}
+
+impl ConcurrencyConfig {
+    pub fn is_limited(&self, path: &str) -> bool {
+        self.routes.iter().any(|prefix| path_has_prefix(path, prefix))
+    }
+}
+
+/// Cache-Control max-age of the responses of real-time and static endpoints
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpCacheConfig {
+    pub real_time_max_age_secs: u64,
+    pub static_max_age_secs: u64,
+}
+
+impl Default for HttpCacheConfig {
+    fn default() -> Self {
+        HttpCacheConfig { real_time_max_age_secs: 20, static_max_age_secs: 3600 }
+    }
+}
+
+/// Retention of the alert archive (history.json)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub retention_days: u64, // Alerts that ended longer ago are dropped; 0 keeps everything
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig { retention_days: 365 }
+    }
+}
+
+/// Informational alerts for cancelled or late trips that no published alert covers
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DerivedAlertsConfig {
+    pub enabled: bool,
+    pub delay_threshold_secs: i32, // Trips at least this late get an alert
+}
+
+impl Default for DerivedAlertsConfig {
+    fn default() -> Self {
+        DerivedAlertsConfig { enabled: true, delay_threshold_secs: 900 }
+    }
+}
+
+/// Client addresses in the request log, and how long request data is kept
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PrivacyConfig {
+    pub client_ips: IpMode,
+    pub salt_rotation_hours: u64, // Hash mode: a new random salt this often
+    pub retention_days: u64, // Stored request data (shared journeys) is deleted after this
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        PrivacyConfig { client_ips: IpMode::Full, salt_rotation_hours: 24, retention_days: 30 }
+    }
+}
+
+/// Limits on shared journey links (POST /api/tbm/journey/share), which anyone can create
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JourneySharesConfig {
+    pub max_entries: usize, // Live shared journeys kept; further shares get 503 until some expire. 0: no cap
+    pub per_client_per_minute: u32, // Shares from one address per minute, past which it gets 429. 0: no limit
+}
+
+impl Default for JourneySharesConfig {
+    fn default() -> Self {
+        JourneySharesConfig { max_entries: 10_000, per_client_per_minute: 10 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpMode {
+    Full,
+    Truncate, // IPv4 /24, IPv6 /48
+    Hash,     // Salted hash of the address; the salt is never stored
+}
+
+/// Request log lines, to stdout or to a file rotated by size
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AccessLogConfig {
+    pub format: AccessLogFormat,
+    pub file: Option<PathBuf>, // stdout when unset
+    pub max_file_mb: u64, // The file moves to <file>.1 past this size
+    pub max_files: usize, // Rotated files kept
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        AccessLogConfig { format: AccessLogFormat::Text, file: None, max_file_mb: 100, max_files: 5 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    Text,
+    Json, // One object per line
+}
+
+/// Browser cross-origin access. Origins are exact ("https://nvt.example.org") or "*" for any; the
+/// admin routes have their own, stricter list. The server's own origin is always allowed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    pub allowed_origins: Vec<String>, // Replaced by the NVT_CORS_ORIGINS environment variable (comma separated)
+    pub allowed_methods: Vec<String>,
+    pub max_age_secs: usize, // How long browsers may cache a preflight answer
+    pub admin: AdminCorsConfig,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: vec!["GET".to_string(), "HEAD".to_string(), "POST".to_string()],
+            max_age_secs: 3600,
+            admin: AdminCorsConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AdminCorsConfig {
+    pub allowed_origins: Vec<String>, // Replaced by NVT_CORS_ADMIN_ORIGINS; none by default
+    pub allowed_methods: Vec<String>,
+    pub routes: Vec<String>, // Path prefixes under this policy
+}
+
+impl Default for AdminCorsConfig {
+    fn default() -> Self {
+        AdminCorsConfig {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".to_string(), "POST".to_string(), "DELETE".to_string()],
+            routes: vec![
+                "/admin".to_string(),
+                "/api/tbm/refresh".to_string(),
+                "/api/tbm/cache".to_string(),
+            ],
+        }
+    }
+}
+
+impl CorsConfig {
+    /// The policy with the origins of the environment variables, when set
+    pub fn resolve(&self) -> Self {
+        let origins = |name: &str| -> Option<Vec<String>> {
+            let value = std::env::var(name).ok()?;
+            Some(value.split(',').map(str::trim).filter(|o| !o.is_empty()).map(String::from).collect())
+        };
+        let mut resolved = self.clone();
+        if let Some(allowed) = origins("NVT_CORS_ORIGINS") {
+            resolved.allowed_origins = allowed;
+        }
+        if let Some(allowed) = origins("NVT_CORS_ADMIN_ORIGINS") {
+            resolved.admin.allowed_origins = allowed;
+        }
+        resolved
+    }
+
+    /// Whether a browser at `origin` may send `method` to `path`; `host` is the Host header
+    pub fn allows(&self, origin: &str, host: Option<&str>, method: &str, path: &str) -> bool {
+        let same_origin = origin.split_once("://").is_some_and(|(_, authority)| Some(authority) == host);
+        let (origins, methods) = if self.admin.routes.iter().any(|prefix| path_has_prefix(path, prefix)) {
+            (&self.admin.allowed_origins, &self.admin.allowed_methods)
+        } else {
+            (&self.allowed_origins, &self.allowed_methods)
+        };
+        let method_allowed = method.eq_ignore_ascii_case("OPTIONS") || methods.iter().any(|m| m.eq_ignore_ascii_case(method));
+        same_origin || (method_allowed && origins.iter().any(|o| o == "*" || o.eq_ignore_ascii_case(origin)))
+    }
+
+    /// Methods of either policy, as answered to preflight requests
+    pub fn methods(&self) -> Vec<String> {
+        let mut methods: Vec<String> = self.allowed_methods.iter().chain(&self.admin.allowed_methods)
+            .map(|m| m.to_ascii_uppercase())
+            .collect();
+        methods.sort();
+        methods.dedup();
+        methods
+    }
+}
+
+/// Keys the admin and heavy routes require in the X-Api-Key header; without keys those routes
+/// stay open to anyone, except the sync and simulation ones guarded by their own token
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ApiKeysConfig {
+    pub keys: Vec<ApiKeyConfig>,
+    pub routes: Vec<String>, // Path prefixes needing a key
+}
+
+impl Default for ApiKeysConfig {
+    fn default() -> Self {
+        ApiKeysConfig {
+            keys: Vec::new(),
+            routes: vec![
+                "/admin".to_string(),
+                "/api/tbm/refresh".to_string(),
+                "/api/tbm/cache".to_string(),
+                "/api/tbm/export".to_string(),
+            ],
+        }
+    }
+}
+
+impl ApiKeysConfig {
+    pub fn is_protected(&self, path: &str) -> bool {
+        self.routes.iter().any(|prefix| path_has_prefix(path, prefix))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKeyConfig {
+    pub name: String, // Logged instead of the key
+    pub key: String,
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>, // Unlimited when unset
+    #[serde(default)]
+    pub routes: Vec<String>, // Path prefixes the key is limited to; every protected route when empty
+}
+
+impl ApiKeyConfig {
+    /// Whether the key is limited to routes that `path` is one of
+    pub fn covers(&self, path: &str) -> bool {
+        self.routes.iter().any(|prefix| path_has_prefix(path, prefix))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SyncConfig {
+    #[serde(default)]
+    pub token: Option<String>, // Same on both instances; falls back to the NVT_SYNC_TOKEN environment variable
+    #[serde(default)]
+    pub primary_url: Option<String>, // Standby only, e.g. "http://10.0.0.1:8080"
+}
+
+impl SyncConfig {
+    pub fn resolve_token(&self) -> Option<String> {
+        self.token.clone()
+            .or_else(|| std::env::var("NVT_SYNC_TOKEN").ok())
+            .filter(|t| !t.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SimulationConfig {
+    #[serde(default)]
+    pub token: Option<String>, // Falls back to the NVT_SIMULATION_TOKEN environment variable
+    #[serde(default = "default_max_upload_bytes")]
+    pub max_upload_bytes: usize, // Of a posted GTFS fragment
+}
+
+fn default_max_upload_bytes() -> usize {
+    SimulationConfig::DEFAULT_MAX_UPLOAD_BYTES
+}
+
+impl SimulationConfig {
+    pub const DEFAULT_MAX_UPLOAD_BYTES: usize = 64 * 1024 * 1024;
+
+    /// Body limit of POST /admin/simulate, the default one while simulation is off
+    pub fn upload_limit() -> usize {
+        get().simulation.as_ref().map_or(Self::DEFAULT_MAX_UPLOAD_BYTES, |simulation| simulation.max_upload_bytes)
+    }
+
+    pub fn resolve_token(&self) -> Option<String> {
+        self.token.clone()
+            .or_else(|| std::env::var("NVT_SIMULATION_TOKEN").ok())
+            .filter(|t| !t.is_empty())
+    }
+}
+
+/// Display code of matching ids, e.g. {"pattern": "^CA_DU_LIBOURNAIS:Line:L?(\\d+)$", "template": "$1"}
+#[derive(Debug, Clone, Deserialize)]
+pub struct IdRule {
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub pattern: Regex,
+    pub template: String, // $1, ${name}: capture groups of the pattern
+}
+
+impl IdRule {
+    pub fn apply(&self, id: &str) -> Option<String> {
+        let captures = self.pattern.captures(id)?;
+        let mut code = String::new();
+        captures.expand(&self.template, &mut code);
+        Some(code)
+    }
+}
+
+/// Code given by the first of `rules` matching `id`
+pub fn apply_id_rules(rules: &[IdRule], id: &str) -> Option<String> {
+    rules.iter().find_map(|rule| rule.apply(id))
+}
+
+fn deserialize_regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(serde::de::Error::custom)
+}
+
+/// Whether `path` is `prefix` or below it, segment-wise ("/api/tbm/line" covers "/api/tbm/line/A")
+fn path_has_prefix(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix.trim_end_matches('/'))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+// ============================================================================
+// Loading
+// ============================================================================
+
+impl AppConfig {
+    pub fn config_path() -> PathBuf {
+        std::env::var_os("NVT_CONFIG")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("nvt_config.json"))
+    }
+
+    pub fn load() -> Self {
+        let path = Self::config_path();
+
+        match Self::read(&path) {
+            Ok(Some(config)) => {
+                println!("✓ Configuration loaded from {:?}", path);
+                println!("  • {} kiosk configs", config.kiosks.len());
+                println!("  • {} operator brandings", config.branding.operators.len());
+                config
+            }
+            Ok(None) => {
+                println!("ℹ️  No config file at {:?}, using defaults", path);
+                AppConfig::default()
+            }
+            Err(e) => {
+                eprintln!("⚠️  {}, using defaults", e);
+                AppConfig::default()
+            }
+        }
+    }
+
+    /// The config file at `path`, None when there is none
+    pub fn read(path: &Path) -> Result<Option<Self>, String> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {:?} ({})", path, e))?;
+        serde_json::from_str::<AppConfig>(&contents)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse config {:?} ({})", path, e))
+    }
+}
+
+/// Global configuration, loaded on first access
+pub fn get() -> &'static AppConfig {
+    CONFIG.get_or_init(AppConfig::load)
+}
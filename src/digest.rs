@@ -0,0 +1,289 @@
+// Email digest of disruptions
+// Morning email listing active and upcoming alerts for each subscriber's lines of interest,
+// sent once a day at the configured Europe/Paris time through a minimal SMTP client.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{NaiveTime, TimeZone, Utc};
+use chrono_tz::Europe::Paris;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time;
+
+use crate::config::{self, DigestConfig, SmtpConfig, SmtpSecurity};
+use crate::tbm_api_models::{AlertInfo, CachedNetworkData, NVTError, NVTModels, Result};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+// ============================================================================
+// Scheduling
+// ============================================================================
+
+fn state_path() -> PathBuf {
+    let mut path = dirs::cache_dir().unwrap_or_else(|| PathBuf::from("."));
+    path.push("tbm_nvt");
+    fs::create_dir_all(&path).ok();
+    path.push("digest_last_sent.txt");
+    path
+}
+
+/// Sends the digest once per day, at the first check after `send_at`
+pub async fn digest_task(config: DigestConfig, cache: Arc<Mutex<CachedNetworkData>>) {
+    let Ok(send_at) = NaiveTime::parse_from_str(&config.send_at, "%H:%M") else {
+        eprintln!("⚠️  Invalid digest send_at '{}' (expected HH:MM), digest disabled", config.send_at);
+        return;
+    };
+
+    // Remember the last sent day across restarts so a restart doesn't send twice
+    let mut last_sent = fs::read_to_string(state_path()).ok().map(|s| s.trim().to_string());
+    let mut interval = time::interval(CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let now = Utc::now().with_timezone(&Paris);
+        let today = now.format("%Y-%m-%d").to_string();
+        if now.time() < send_at || last_sent.as_deref() == Some(today.as_str()) {
+            continue;
+        }
+
+        let messages = match cache.lock() {
+            Ok(cache) => build_messages(&config, &cache),
+            Err(e) => {
+                eprintln!("❌ Failed to lock cache: {}", e);
+                continue;
+            }
+        };
+
+        let smtp = config.smtp.clone();
+        let count = messages.len();
+        let result = tokio::task::spawn_blocking(move || {
+            for (recipient, subject, body) in &messages {
+                send_mail(&smtp, recipient, subject, body)?;
+            }
+            Ok::<(), NVTError>(())
+        }).await;
+
+        match result {
+            Ok(Ok(())) => println!("📧 Disruption digest sent to {} recipients", count),
+            Ok(Err(e)) => eprintln!("⚠️  Failed to send disruption digest: {}", e),
+            Err(e) => eprintln!("❌ Digest task panicked: {}", e),
+        }
+
+        // Marked as sent even on failure: one attempt per day, no mail storms
+        last_sent = Some(today.clone());
+        fs::write(state_path(), &today).ok();
+    }
+}
+
+// ============================================================================
+// Digest Content
+// ============================================================================
+
+fn format_local(timestamp: i64) -> String {
+    Utc.timestamp_opt(timestamp, 0).single()
+        .map(|dt| dt.with_timezone(&Paris).format("%d/%m %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+fn describe_alert(alert: &AlertInfo, now: i64) -> String {
+    let upcoming = alert.active_period_start.is_some_and(|start| start > now);
+
+    let mut entry = format!("  - [{}] {}\n", if upcoming { "upcoming" } else { "active" }, alert.text);
+    if !alert.description.is_empty() && alert.description != alert.text {
+        entry.push_str(&format!("    {}\n", alert.description));
+    }
+    match (alert.active_period_start, alert.active_period_end) {
+        (Some(start), Some(end)) if upcoming => {
+            entry.push_str(&format!("    From {} to {}\n", format_local(start), format_local(end)));
+        }
+        (Some(start), None) if upcoming => entry.push_str(&format!("    From {}\n", format_local(start))),
+        (_, Some(end)) => entry.push_str(&format!("    Until {}\n", format_local(end))),
+        _ => {}
+    }
+    if let Some(url) = &alert.url {
+        entry.push_str(&format!("    {}\n", url));
+    }
+    entry
+}
+
+/// One (recipient, subject, body) per recipient, with all their subscribed lines
+pub fn build_messages(config: &DigestConfig, cache: &CachedNetworkData) -> Vec<(String, String, String)> {
+    let network_data = cache.to_network_data();
+    let now = NVTModels::get_current_timestamp();
+
+    let mut lines_by_recipient: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    for subscription in &config.subscriptions {
+        for recipient in &subscription.recipients {
+            lines_by_recipient.entry(recipient.as_str())
+                .or_default()
+                .extend(subscription.lines.iter().map(|l| l.to_uppercase()));
+        }
+    }
+
+    let date = Utc::now().with_timezone(&Paris).format("%A %d %B %Y").to_string();
+    let subject = format!("Transit disruptions - {}", date);
+
+    lines_by_recipient.into_iter()
+        .map(|(recipient, line_codes)| {
+            let mut body = format!("Disruptions digest for {}\n\n", date);
+
+            for code in &line_codes {
+                let matching: Vec<_> = network_data.lines.iter()
+                    .filter(|l| l.line_code.eq_ignore_ascii_case(code))
+                    .collect();
+
+                if matching.is_empty() {
+                    body.push_str(&format!("Line {}\n  Unknown line\n\n", code));
+                    continue;
+                }
+
+                for line in matching {
+                    body.push_str(&format!("Line {} - {} ({})\n", line.line_code, line.line_name, line.operator));
+
+                    let alerts: Vec<_> = line.alerts.iter()
+                        .filter(|a| a.active_period_end.is_none_or(|end| end > now))
+                        .collect();
+                    if alerts.is_empty() {
+                        body.push_str("  No disruptions reported.\n");
+                    }
+                    for alert in alerts {
+                        body.push_str(&describe_alert(alert, now));
+                    }
+                    body.push('\n');
+                }
+            }
+
+            body.push_str("--\nSent by NVT Web Edition");
+            match &config::get().public_url {
+                Some(url) => body.push_str(&format!(" - live map: {}\n", url)),
+                None => body.push('\n'),
+            }
+            (recipient.to_string(), subject.clone(), body)
+        })
+        .collect()
+}
+
+// ============================================================================
+// SMTP Client
+// ============================================================================
+
+trait SmtpStream: Read + Write + Send + std::fmt::Debug {}
+impl<T: Read + Write + Send + std::fmt::Debug> SmtpStream for T {}
+
+struct SmtpConnection {
+    reader: BufReader<Box<dyn SmtpStream>>,
+}
+
+impl SmtpConnection {
+    fn read_reply(&mut self) -> Result<(u16, String)> {
+        let mut text = String::new();
+        loop {
+            let mut line = String::new();
+            self.reader.read_line(&mut line)
+                .map_err(|e| NVTError::NetworkError(format!("SMTP read failed: {}", e)))?;
+            if line.len() < 4 {
+                return Err(NVTError::NetworkError(format!("Malformed SMTP reply: {:?}", line)));
+            }
+            text.push_str(line.get(4..).unwrap_or("").trim_end());
+            text.push(' ');
+            // "250-..." continues a multi-line reply, "250 ..." ends it
+            if line.as_bytes()[3] != b'-' {
+                let code = line.get(..3).and_then(|c| c.parse::<u16>().ok())
+                    .ok_or_else(|| NVTError::NetworkError(format!("Malformed SMTP reply: {:?}", line)))?;
+                return Ok((code, text));
+            }
+        }
+    }
+
+    fn command(&mut self, command: &str, expected: u16) -> Result<String> {
+        self.reader.get_mut().write_all(format!("{}\r\n", command).as_bytes())
+            .map_err(|e| NVTError::NetworkError(format!("SMTP write failed: {}", e)))?;
+        self.expect(expected)
+    }
+
+    fn expect(&mut self, expected: u16) -> Result<String> {
+        let (code, text) = self.read_reply()?;
+        if code != expected {
+            return Err(NVTError::NetworkError(format!("SMTP error {}: {}", code, text.trim())));
+        }
+        Ok(text)
+    }
+}
+
+fn tls_wrap(host: &str, stream: Box<dyn SmtpStream>) -> Result<Box<dyn SmtpStream>> {
+    let connector = native_tls::TlsConnector::new()
+        .map_err(|e| NVTError::NetworkError(format!("TLS setup failed: {}", e)))?;
+    let tls = connector.connect(host, stream)
+        .map_err(|e| NVTError::NetworkError(format!("TLS handshake with {} failed: {}", host, e)))?;
+    Ok(Box::new(tls))
+}
+
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!("=?UTF-8?B?{}?=", BASE64.encode(value))
+    }
+}
+
+pub fn send_mail(smtp: &SmtpConfig, to: &str, subject: &str, body: &str) -> Result<()> {
+    let address = (smtp.host.as_str(), smtp.port).to_socket_addrs()
+        .map_err(|e| NVTError::NetworkError(format!("Cannot resolve {}: {}", smtp.host, e)))?
+        .next()
+        .ok_or_else(|| NVTError::NetworkError(format!("Cannot resolve {}", smtp.host)))?;
+    let tcp = TcpStream::connect_timeout(&address, SMTP_TIMEOUT)
+        .map_err(|e| NVTError::NetworkError(format!("SMTP connect to {} failed: {}", smtp.host, e)))?;
+    tcp.set_read_timeout(Some(SMTP_TIMEOUT)).ok();
+    tcp.set_write_timeout(Some(SMTP_TIMEOUT)).ok();
+
+    let stream: Box<dyn SmtpStream> = match smtp.security {
+        SmtpSecurity::Tls => tls_wrap(&smtp.host, Box::new(tcp))?,
+        SmtpSecurity::Starttls | SmtpSecurity::None => Box::new(tcp),
+    };
+    let mut conn = SmtpConnection { reader: BufReader::new(stream) };
+
+    conn.expect(220)?;
+    conn.command("EHLO nvt-web-edition", 250)?;
+
+    if matches!(smtp.security, SmtpSecurity::Starttls) {
+        conn.command("STARTTLS", 220)?;
+        let plain = conn.reader.into_inner();
+        conn = SmtpConnection { reader: BufReader::new(tls_wrap(&smtp.host, plain)?) };
+        conn.command("EHLO nvt-web-edition", 250)?;
+    }
+
+    if let Some(username) = &smtp.username {
+        let password = smtp.resolve_password().unwrap_or_default();
+        let credentials = BASE64.encode(format!("\0{}\0{}", username, password));
+        conn.command(&format!("AUTH PLAIN {}", credentials), 235)?;
+    }
+
+    conn.command(&format!("MAIL FROM:<{}>", smtp.from), 250)?;
+    conn.command(&format!("RCPT TO:<{}>", to), 250)?;
+    conn.command("DATA", 354)?;
+
+    // Normalize line endings and dot-stuff lines starting with '.'
+    let body: String = body.lines()
+        .map(|line| if line.starts_with('.') { format!(".{}\r\n", line) } else { format!("{}\r\n", line) })
+        .collect();
+    let message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+         Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n{}.",
+        smtp.from,
+        to,
+        encode_header(subject),
+        Utc::now().to_rfc2822(),
+        body
+    );
+    conn.command(&message, 250)?;
+    conn.command("QUIT", 221).ok();
+
+    Ok(())
+}
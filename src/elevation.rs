@@ -0,0 +1,212 @@
+// Elevation profiles of route shapes
+// Shapes are resampled along their length and the samples looked up in IGN RGE ALTI through
+// the Géoplateforme altimetry service. Profiles (cumulative distance, elevation) and the
+// derived gain/loss are cached per shape in tbm_nvt/elevation.json.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::tbm_api_models::{NVTError, NVTModels, Result, ShapePoint};
+
+const ALTIMETRY_URL: &str = "https://data.geopf.fr/altimetrie/1.0/calcul/alti/rest/elevation.json";
+const ALTIMETRY_RESOURCE: &str = "ign_rge_alti_wld";
+const NO_DATA: f64 = -99999.0;
+const MAX_SAMPLES: usize = 200;
+const MIN_SAMPLE_SPACING_METERS: f64 = 25.0;
+const PROFILE_MAX_AGE_DAYS: i64 = 90; // shapes rarely move, terrain never does
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// ============================================================================
+// Data Structures
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfilePoint {
+    pub distance_m: f64, // From the start of the shape
+    pub elevation_m: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShapeProfile {
+    pub shape_id: String,
+    pub length_m: f64,
+    pub gain_m: f64,
+    pub loss_m: f64,
+    pub min_elevation_m: Option<f64>,
+    pub max_elevation_m: Option<f64>,
+    pub profile: Vec<ProfilePoint>,
+    pub computed_at: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ElevationStore {
+    pub profiles: HashMap<String, ShapeProfile>, // key: shape_id
+}
+
+// ============================================================================
+// Store Implementation
+// ============================================================================
+
+impl ElevationStore {
+    pub fn store_path() -> PathBuf {
+        let mut path = dirs::cache_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("tbm_nvt");
+        fs::create_dir_all(&path).ok();
+        path.push("elevation.json");
+        path
+    }
+
+    pub fn load() -> Self {
+        let store = match fs::read_to_string(Self::store_path()) {
+            Ok(contents) => serde_json::from_str::<ElevationStore>(&contents).unwrap_or_else(|e| {
+                println!("⚠️  Failed to parse elevation cache ({}), starting empty", e);
+                ElevationStore::default()
+            }),
+            Err(_) => ElevationStore::default(),
+        };
+
+        println!("✓ Loaded elevation cache: {} shape profiles", store.profiles.len());
+        store
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(self)
+            .map_err(|e| NVTError::FileError(format!("Failed to serialize elevation cache: {}", e)))?;
+
+        fs::write(Self::store_path(), json)
+            .map_err(|e| NVTError::FileError(format!("Failed to write elevation cache: {}", e)))
+    }
+
+    /// Cached profile, if computed recently enough
+    pub fn get(&self, shape_id: &str) -> Option<&ShapeProfile> {
+        let now = NVTModels::get_current_timestamp();
+        self.profiles.get(shape_id)
+            .filter(|p| now - p.computed_at < PROFILE_MAX_AGE_DAYS * 86400)
+    }
+
+    pub fn insert(&mut self, profile: ShapeProfile) {
+        self.profiles.insert(profile.shape_id.clone(), profile);
+    }
+}
+
+// ============================================================================
+// Profile Computation
+// ============================================================================
+
+/// Evenly spaced (distance, lat, lon) samples along the shape, ends included
+fn resample(points: &[ShapePoint]) -> (f64, Vec<(f64, f64, f64)>) {
+    let mut ordered: Vec<&ShapePoint> = points.iter().collect();
+    ordered.sort_by_key(|p| p.sequence);
+
+    let mut cumulative = vec![0.0];
+    for pair in ordered.windows(2) {
+        let step = NVTModels::distance_meters(pair[0].latitude, pair[0].longitude, pair[1].latitude, pair[1].longitude);
+        cumulative.push(cumulative.last().copied().unwrap_or(0.0) + step);
+    }
+    let length = cumulative.last().copied().unwrap_or(0.0);
+
+    let Some(first) = ordered.first() else {
+        return (0.0, Vec::new());
+    };
+    if length == 0.0 {
+        return (0.0, vec![(0.0, first.latitude, first.longitude)]);
+    }
+
+    let count = ((length / MIN_SAMPLE_SPACING_METERS) as usize + 1).clamp(2, MAX_SAMPLES);
+    let mut samples = Vec::with_capacity(count);
+    let mut segment = 0;
+    for i in 0..count {
+        let target = length * i as f64 / (count - 1) as f64;
+        while segment + 2 < cumulative.len() && cumulative[segment + 1] < target {
+            segment += 1;
+        }
+        let (a, b) = (ordered[segment], ordered[(segment + 1).min(ordered.len() - 1)]);
+        let span = cumulative.get(segment + 1).copied().unwrap_or(length) - cumulative[segment];
+        let t = if span > 0.0 { ((target - cumulative[segment]) / span).clamp(0.0, 1.0) } else { 0.0 };
+        samples.push((
+            target,
+            a.latitude + (b.latitude - a.latitude) * t,
+            a.longitude + (b.longitude - a.longitude) * t,
+        ));
+    }
+    (length, samples)
+}
+
+fn fetch_elevations(samples: &[(f64, f64, f64)]) -> Result<Vec<f64>> {
+    let join = |values: Vec<String>| values.join("|");
+    let lons = join(samples.iter().map(|(_, _, lon)| format!("{:.6}", lon)).collect());
+    let lats = join(samples.iter().map(|(_, lat, _)| format!("{:.6}", lat)).collect());
+
+    let client = reqwest::blocking::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| NVTError::NetworkError(format!("Failed to create HTTP client: {}", e)))?;
+
+    let response = client.get(ALTIMETRY_URL)
+        .query(&[
+            ("lon", lons.as_str()),
+            ("lat", lats.as_str()),
+            ("resource", ALTIMETRY_RESOURCE),
+            ("delimiter", "|"),
+            ("zonly", "true"),
+        ])
+        .send()
+        .map_err(|e| NVTError::NetworkError(format!("Failed to query elevation service: {}", e)))?;
+
+    if !response.status().is_success() {
+        return Err(NVTError::NetworkError(format!("Elevation service returned error: {}", response.status())));
+    }
+
+    let json: serde_json::Value = response.json()
+        .map_err(|e| NVTError::ParseError(format!("Invalid elevation response: {}", e)))?;
+
+    let elevations: Vec<f64> = json["elevations"].as_array()
+        .ok_or_else(|| NVTError::ParseError("Missing elevations".to_string()))?
+        .iter()
+        // `zonly` gives bare numbers; accept full {lon, lat, z} objects too
+        .map(|e| e.as_f64().or_else(|| e["z"].as_f64()).unwrap_or(NO_DATA))
+        .collect();
+
+    if elevations.len() != samples.len() {
+        return Err(NVTError::ParseError(format!(
+            "Elevation service returned {} values for {} points", elevations.len(), samples.len()
+        )));
+    }
+    Ok(elevations)
+}
+
+/// Look up the elevation profile of a shape (blocking network call)
+pub fn compute_profile(shape_id: &str, points: &[ShapePoint]) -> Result<ShapeProfile> {
+    let (length, samples) = resample(points);
+    let elevations = if samples.is_empty() { Vec::new() } else { fetch_elevations(&samples)? };
+
+    // Points outside the DEM coverage (sea, abroad) come back as NO_DATA and are left out
+    let profile: Vec<ProfilePoint> = samples.iter()
+        .zip(elevations)
+        .filter(|(_, elevation)| *elevation > NO_DATA)
+        .map(|((distance, _, _), elevation)| ProfilePoint {
+            distance_m: distance.round(),
+            elevation_m: (elevation * 10.0).round() / 10.0,
+        })
+        .collect();
+
+    let (mut gain, mut loss) = (0.0, 0.0);
+    for pair in profile.windows(2) {
+        let delta = pair[1].elevation_m - pair[0].elevation_m;
+        if delta > 0.0 { gain += delta } else { loss -= delta }
+    }
+
+    Ok(ShapeProfile {
+        shape_id: shape_id.to_string(),
+        length_m: length.round(),
+        gain_m: gain.round(),
+        loss_m: loss.round(),
+        min_elevation_m: profile.iter().map(|p| p.elevation_m).reduce(f64::min),
+        max_elevation_m: profile.iter().map(|p| p.elevation_m).reduce(f64::max),
+        profile,
+        computed_at: NVTModels::get_current_timestamp(),
+    })
+}
@@ -0,0 +1,227 @@
+// Bike share stations (GBFS, V³ in Bordeaux)
+// The V³ self-service bikes publish GBFS feeds: gbfs.json lists the feed URLs, station_information
+// gives the name, position and capacity of each station and station_status the bikes and docks
+// available. Both the GBFS 2.x and 3.0 layouts are read. The status is fetched at each real-time
+// refresh; the feed list and station information, which rarely change, once their ttl has passed
+// and at most hourly. Stations missing from either feed are left out.
+
+use reqwest::{blocking, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::tbm_api_models::{NVTError, Result};
+
+pub const GBFS_URL: &str =
+    "https://bdx.mecatran.com/utw/ws/gbfs/bordeaux/v3/gbfs.json?apiKey=opendata-bordeaux-metropole-flux-gtfs-rt";
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(20);
+const MIN_INFORMATION_TTL_SECS: u64 = 3600;
+
+// ============================================================================
+// Data Structures
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BikeStation {
+    pub station_id: String,
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub capacity: Option<u32>, // Docks in all, when the feed gives it
+    pub bikes_available: u32,
+    pub docks_available: Option<u32>,
+    pub is_renting: bool,
+    pub is_returning: bool,
+    pub last_reported: Option<i64>, // Unix time the station last reported its status
+}
+
+#[derive(Debug, Clone)]
+struct StationInformation {
+    name: String,
+    latitude: f64,
+    longitude: f64,
+    capacity: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+struct Feeds {
+    station_information: String,
+    station_status: String,
+    expires_at: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BikeShare {
+    pub stations: Vec<BikeStation>,
+    pub last_updated: u64, // Unix time of the status feed, 0 before the first fetch
+    #[serde(skip)]
+    feeds: Option<Feeds>,
+    #[serde(skip)]
+    information: HashMap<String, StationInformation>, // key: station_id
+    #[serde(skip)]
+    information_expires_at: u64,
+}
+
+// ============================================================================
+// Parsing
+// ============================================================================
+
+/// Unix time of a GBFS timestamp, a number in 2.x and an RFC 3339 date in 3.0
+fn timestamp(value: &Value) -> Option<i64> {
+    value.as_i64().or_else(|| {
+        chrono::DateTime::parse_from_rfc3339(value.as_str()?).ok().map(|date| date.timestamp())
+    })
+}
+
+/// Flags are booleans, or 0/1 in older feeds; absent ones count as set
+fn flag(value: &Value) -> bool {
+    value.as_bool().or_else(|| value.as_u64().map(|n| n != 0)).unwrap_or(true)
+}
+
+/// Station ids are strings, some feeds send numbers
+fn id(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_string).or_else(|| value.as_u64().map(|n| n.to_string()))
+}
+
+/// A 2.x name, or the French (else first) text of a 3.0 localized name
+fn name(value: &Value) -> Option<String> {
+    if let Some(name) = value.as_str() {
+        return Some(name.to_string());
+    }
+    let names = value.as_array()?;
+    names.iter()
+        .find(|name| name["language"].as_str().is_some_and(|language| language.starts_with("fr")))
+        .or_else(|| names.first())
+        .and_then(|name| name["text"].as_str())
+        .map(str::to_string)
+}
+
+/// Lifetime of a feed in seconds, as its ttl says
+fn ttl(json: &Value) -> u64 {
+    json["ttl"].as_u64().unwrap_or(0)
+}
+
+impl Feeds {
+    /// Feed URLs of gbfs.json, its 2.x feeds of the first language or its 3.0 feeds. Relative
+    /// URLs are taken from the gbfs.json URL.
+    fn parse(json: &Value, base: &str, now: u64) -> Result<Self> {
+        let data = &json["data"];
+        let feeds = data["feeds"].as_array()
+            .or_else(|| data.as_object()?.values().find_map(|language| language["feeds"].as_array()))
+            .ok_or_else(|| NVTError::ParseError("Missing GBFS feeds".to_string()))?;
+
+        let base = Url::parse(base)
+            .map_err(|e| NVTError::ParseError(format!("Invalid GBFS URL: {}", e)))?;
+        let url = |feed_name: &str| -> Result<String> {
+            let url = feeds.iter()
+                .find(|feed| feed["name"].as_str() == Some(feed_name))
+                .and_then(|feed| feed["url"].as_str())
+                .ok_or_else(|| NVTError::ParseError(format!("No {} feed in gbfs.json", feed_name)))?;
+            base.join(url)
+                .map(|url| url.to_string())
+                .map_err(|e| NVTError::ParseError(format!("Invalid {} URL: {}", feed_name, e)))
+        };
+
+        Ok(Feeds {
+            station_information: url("station_information")?,
+            station_status: url("station_status")?,
+            expires_at: now + ttl(json).max(MIN_INFORMATION_TTL_SECS),
+        })
+    }
+}
+
+fn stations(json: &Value) -> Result<&Vec<Value>> {
+    json["data"]["stations"].as_array()
+        .ok_or_else(|| NVTError::ParseError("Missing GBFS stations".to_string()))
+}
+
+fn parse_information(json: &Value) -> Result<HashMap<String, StationInformation>> {
+    Ok(stations(json)?.iter()
+        .filter_map(|station| {
+            let information = StationInformation {
+                name: name(&station["name"])?,
+                latitude: station["lat"].as_f64()?,
+                longitude: station["lon"].as_f64()?,
+                capacity: station["capacity"].as_u64().map(|capacity| capacity as u32),
+            };
+            Some((id(&station["station_id"])?, information))
+        })
+        .collect())
+}
+
+impl BikeShare {
+    /// The stations of the status feed that the station information knows, in its order
+    fn join(&self, status: &Value) -> Result<Vec<BikeStation>> {
+        Ok(stations(status)?.iter()
+            .filter_map(|station| {
+                let station_id = id(&station["station_id"])?;
+                let information = self.information.get(&station_id)?;
+                let bikes_available = station["num_bikes_available"].as_u64()
+                    .or_else(|| station["num_vehicles_available"].as_u64())?;
+                Some(BikeStation {
+                    name: information.name.clone(),
+                    latitude: information.latitude,
+                    longitude: information.longitude,
+                    capacity: information.capacity,
+                    bikes_available: bikes_available as u32,
+                    docks_available: station["num_docks_available"].as_u64().map(|docks| docks as u32),
+                    is_renting: flag(&station["is_renting"]),
+                    is_returning: flag(&station["is_returning"]),
+                    last_reported: timestamp(&station["last_reported"]),
+                    station_id,
+                })
+            })
+            .collect())
+    }
+
+    // ============================================================================
+    // Loading
+    // ============================================================================
+
+    fn fetch(client: &blocking::Client, url: &str) -> Result<Value> {
+        let response = client.get(url)
+            .send()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to fetch GBFS feed: {}", e)))?;
+
+        if !response.status().is_success() {
+            return Err(NVTError::NetworkError(format!("GBFS feed returned error: {}", response.status())));
+        }
+
+        response.json()
+            .map_err(|e| NVTError::ParseError(format!("Invalid GBFS feed: {}", e)))
+    }
+
+    /// Fetch the station status from the feeds of `url` (a gbfs.json), and the feed list and
+    /// station information if they expired; on error the stations stay as they were
+    pub fn refresh(&mut self, url: &str, now: u64) -> Result<()> {
+        let client = blocking::Client::builder()
+            .timeout(DOWNLOAD_TIMEOUT)
+            .build()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to create HTTP client: {}", e)))?;
+
+        let feeds = match self.feeds.clone().filter(|feeds| now < feeds.expires_at) {
+            Some(feeds) => feeds,
+            None => Feeds::parse(&Self::fetch(&client, url)?, url, now)?,
+        };
+        if self.information.is_empty() || now >= self.information_expires_at {
+            let json = Self::fetch(&client, &feeds.station_information)?;
+            self.information = parse_information(&json)?;
+            self.information_expires_at = now + ttl(&json).max(MIN_INFORMATION_TTL_SECS);
+        }
+
+        let status = Self::fetch(&client, &feeds.station_status)?;
+        self.stations = self.join(&status)?;
+        self.last_updated = timestamp(&status["last_updated"]).map_or(now, |updated| updated.max(0) as u64);
+        self.feeds = Some(feeds);
+        Ok(())
+    }
+
+    // ============================================================================
+    // Lookup
+    // ============================================================================
+
+    pub fn station(&self, station_id: &str) -> Option<&BikeStation> {
+        self.stations.iter().find(|station| station.station_id == station_id)
+    }
+}
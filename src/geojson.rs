@@ -0,0 +1,97 @@
+// GeoJSON output (?format=geojson)
+// Stops, vehicles and shapes as FeatureCollections that Leaflet, MapLibre, uMap or QGIS open
+// as they are: coordinates in [lon, lat] order, the other fields of each item as properties
+// (nested alerts and real-time arrays stay JSON), and each vehicle or shape carrying the
+// color, code and operator of its line. Served as application/geo+json, outside the
+// ApiResponse envelope.
+
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+use crate::tbm_api_models::{Line, NetworkData, VehicleWithProgress};
+
+pub const CONTENT_TYPE: &str = "application/geo+json";
+
+/// Whether ?format= asks for GeoJSON; plain JSON is the default
+pub fn wants_geojson(format: Option<&str>) -> Result<bool, String> {
+    match format.map(str::to_lowercase).as_deref() {
+        None | Some("json") => Ok(false),
+        Some("geojson") => Ok(true),
+        Some(other) => Err(format!("Unknown format '{}' (expected json or geojson)", other)),
+    }
+}
+
+pub fn feature_collection(features: Vec<Value>) -> Value {
+    json!({ "type": "FeatureCollection", "features": features })
+}
+
+/// Point features from serialized items: `latitude` and `longitude` become the geometry (null
+/// when a ?fields= selection left them out), the other fields the properties
+pub fn point_features(items: Vec<Value>) -> Vec<Value> {
+    items.into_iter()
+        .map(|item| {
+            let mut properties = match item {
+                Value::Object(fields) => fields,
+                _ => Map::new(),
+            };
+            let geometry = match (properties.remove("latitude"), properties.remove("longitude")) {
+                (Some(lat), Some(lon)) => json!({ "type": "Point", "coordinates": [lon, lat] }),
+                _ => Value::Null,
+            };
+            json!({ "type": "Feature", "geometry": geometry, "properties": properties })
+        })
+        .collect()
+}
+
+/// Line properties shared by vehicles and shapes; `stroke` is the simplestyle color uMap uses
+fn line_properties(properties: &mut Map<String, Value>, line: Option<&Line>) {
+    properties.insert("line_ref".to_string(), json!(line.map(|l| &l.line_ref)));
+    properties.insert("line_code".to_string(), json!(line.map(|l| &l.line_code)));
+    properties.insert("line_color".to_string(), json!(line.map(|l| &l.color)));
+    properties.insert("operator".to_string(), json!(line.map(|l| &l.operator)));
+    properties.insert("stroke".to_string(), json!(line.map(|l| format!("#{}", l.color))));
+}
+
+pub fn vehicles(vehicles: &[VehicleWithProgress], network: &NetworkData) -> Value {
+    let lines: HashMap<&str, &Line> = network.lines.iter()
+        .flat_map(|line| line.real_time.iter().map(move |rt| (rt.vehicle_id.as_str(), line)))
+        .collect();
+
+    let items: Vec<Value> = vehicles.iter()
+        .map(|vehicle| {
+            let mut item = serde_json::to_value(vehicle).unwrap_or(Value::Null);
+            if let Value::Object(properties) = &mut item {
+                line_properties(properties, lines.get(vehicle.vehicle.vehicle_id.as_str()).copied());
+            }
+            item
+        })
+        .collect();
+    feature_collection(point_features(items))
+}
+
+/// One LineString per shape, in shape id order, with the line drawing it
+pub fn shapes(network: &NetworkData) -> Value {
+    // Reversed so that the first line listing a shape wins
+    let lines: HashMap<&str, &Line> = network.lines.iter().rev()
+        .flat_map(|line| line.shape_ids.iter().map(move |id| (id.as_str(), line)))
+        .collect();
+
+    let mut shape_ids: Vec<&String> = network.shapes.keys().collect();
+    shape_ids.sort();
+    let features = shape_ids.into_iter()
+        .map(|shape_id| {
+            let coordinates: Vec<[f64; 2]> = network.shapes[shape_id].iter()
+                .map(|point| [point.longitude, point.latitude])
+                .collect();
+            let mut properties = Map::new();
+            properties.insert("shape_id".to_string(), json!(shape_id));
+            line_properties(&mut properties, lines.get(shape_id.as_str()).copied());
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "LineString", "coordinates": coordinates },
+                "properties": properties,
+            })
+        })
+        .collect();
+    feature_collection(features)
+}
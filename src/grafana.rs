@@ -0,0 +1,153 @@
+// Grafana SimpleJSON datasource
+// Implements the contract of the SimpleJSON / JSON datasource plugins over the history store:
+// /search lists the metrics, /query returns their time series from the samples taken at each
+// refresh, /annotations the archived alerts. Metrics are `vehicles` and `delay` (average, in
+// seconds) for the whole network, and `vehicles/<operator>/<line code>` and
+// `delay/<operator>/<line code>` per line.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use crate::history::{HistoryStore, LineSample, MetricsSample};
+
+// ============================================================================
+// Requests and Responses
+// ============================================================================
+
+#[derive(Debug, Deserialize)]
+pub struct SearchRequest {
+    #[serde(default)]
+    pub target: String, // Substring of the metric names; empty lists them all
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimeRange {
+    pub from: String, // RFC3339
+    pub to: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QueryTarget {
+    #[serde(default)]
+    pub target: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryRequest {
+    pub range: TimeRange,
+    pub targets: Vec<QueryTarget>,
+    #[serde(default)]
+    pub max_data_points: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimeSeries {
+    pub target: String,
+    pub datapoints: Vec<(f64, i64)>, // (value, unix milliseconds)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnnotationQuery {
+    #[serde(default)]
+    pub query: Option<String>, // Route id; none or empty means every alert
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnnotationRequest {
+    pub range: TimeRange,
+    pub annotation: serde_json::Value, // Echoed back, as the contract requires
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Annotation {
+    pub annotation: serde_json::Value,
+    pub time: i64, // unix milliseconds
+    pub time_end: i64,
+    pub title: String,
+    pub text: String,
+    pub tags: Vec<String>,
+}
+
+// ============================================================================
+// Metrics
+// ============================================================================
+
+/// Unix seconds of a Grafana time range
+pub fn parse_range(range: &TimeRange) -> Result<(i64, i64), String> {
+    let parse = |value: &str| chrono::DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.timestamp())
+        .map_err(|_| format!("Invalid time '{}' (expected RFC3339)", value));
+    Ok((parse(&range.from)?, parse(&range.to)?))
+}
+
+/// Metric names containing `filter`, network-wide ones first
+pub fn search(history: &HistoryStore, filter: &str) -> Vec<String> {
+    let lines: BTreeSet<&String> = history.samples.iter().flat_map(|sample| sample.lines.keys()).collect();
+    let mut names = vec!["vehicles".to_string(), "delay".to_string()];
+    names.extend(lines.iter().map(|line| format!("vehicles/{}", line)));
+    names.extend(lines.iter().map(|line| format!("delay/{}", line)));
+    names.retain(|name| name.to_lowercase().contains(&filter.to_lowercase()));
+    names
+}
+
+/// Value of a metric in one sample; None when no vehicle reported a delay
+fn metric_value(sample: &MetricsSample, target: &str) -> Option<f64> {
+    let (metric, line) = target.split_once('/').map_or((target, None), |(metric, line)| (metric, Some(line)));
+    let total = sample.lines.iter()
+        .filter(|(key, _)| line.is_none_or(|line| key.as_str() == line))
+        .fold(LineSample::default(), |total, (_, s)| LineSample {
+            vehicles: total.vehicles + s.vehicles,
+            delay_total: total.delay_total + s.delay_total,
+            delayed_vehicles: total.delayed_vehicles + s.delayed_vehicles,
+        });
+
+    match metric {
+        "vehicles" => Some(f64::from(total.vehicles)),
+        "delay" if total.delayed_vehicles > 0 => Some(total.delay_total as f64 / f64::from(total.delayed_vehicles)),
+        _ => None,
+    }
+}
+
+/// Time series of each target over [from, to], thinned to max_data_points
+pub fn query(history: &HistoryStore, request: &QueryRequest, from: i64, to: i64) -> Vec<TimeSeries> {
+    let samples: Vec<&MetricsSample> = history.samples.iter()
+        .filter(|sample| (from..=to).contains(&sample.timestamp))
+        .collect();
+    let stride = match request.max_data_points {
+        Some(max) if max > 0 => samples.len().div_ceil(max).max(1),
+        _ => 1,
+    };
+
+    request.targets.iter()
+        .filter(|target| !target.target.is_empty())
+        .map(|target| TimeSeries {
+            target: target.target.clone(),
+            datapoints: samples.iter()
+                .step_by(stride)
+                .filter_map(|sample| metric_value(sample, &target.target).map(|value| (value, sample.timestamp * 1000)))
+                .collect(),
+        })
+        .collect()
+}
+
+/// Archived alerts overlapping [from, to] as annotation regions
+pub fn annotations(history: &HistoryStore, request: &AnnotationRequest, from: i64, to: i64) -> Vec<Annotation> {
+    let query: Option<AnnotationQuery> = serde_json::from_value(request.annotation.clone()).ok();
+    let route_ids: Option<Vec<String>> = query.and_then(|q| q.query).filter(|q| !q.is_empty()).map(|q| vec![q]);
+
+    history.query_alerts(Some(from), Some(to), route_ids.as_deref()).into_iter()
+        .map(|alert| {
+            let (start, end) = alert.effective_period();
+            Annotation {
+                annotation: request.annotation.clone(),
+                time: start * 1000,
+                time_end: end * 1000,
+                title: alert.text.clone(),
+                text: alert.description.clone(),
+                tags: alert.route_ids.clone(),
+            }
+        })
+        .collect()
+}
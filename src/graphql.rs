@@ -0,0 +1,1007 @@
+// GraphQL over the network (POST/GET /api/graphql)
+// A small executor for the query subset clients need to fetch related data in one round trip:
+// stop → lines → vehicles → next stop, and so on. Supported: query operations (named or not,
+// with `operationName`), aliases, arguments given as literals or `$variables` (with defaults),
+// and `__typename`. Fragments, directives, mutations, subscriptions and introspection are not;
+// the schema is published as SDL at /api/graphql/schema instead. Responses follow the GraphQL
+// spec (`data` and `errors`, no ApiResponse envelope): a field that fails is null in `data`
+// and its error is listed with the path to it.
+
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+
+use crate::operators;
+use crate::tbm_api_models::{
+    AlertFilter, AlertInfo, BoundingBox, CachedNetworkData, Line, NVTModels, NetworkData, RealTimeInfo,
+    ScheduledArrival, Stop, TripDetails, TripStopTime,
+};
+
+pub const SDL_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+const MAX_DEPTH: usize = 10; // Nested selections, so a query can't walk relations forever
+const DEFAULT_LIMIT: usize = 100; // Items of a root list without a `limit` argument
+const MAX_LIMIT: usize = 1000;
+const DEFAULT_ARRIVALS: usize = 10;
+
+pub const SDL: &str = r#"type Query {
+  stop(id: String!): Stop
+  stops(ids: [String!], bbox: String, limit: Int = 100): [Stop!]!
+  line(code: String!, operator: String): Line
+  lines(operator: String, limit: Int = 100): [Line!]!
+  vehicle(id: String!): Vehicle
+  vehicles(line: String, bbox: String, limit: Int = 100): [Vehicle!]!
+  alerts(route: String, stop: String, severityMin: Int): [Alert!]!
+  trip(id: String!): Trip
+  agency(name: String!): Agency
+  agencies: [Agency!]!
+}
+
+type Stop {
+  id: String!
+  name: String!
+  latitude: Float!
+  longitude: Float!
+  commune: String
+  inseeCode: String
+  lines: [Line!]!
+  alerts: [Alert!]!
+  vehicles: [Vehicle!]!
+  arrivals(limit: Int = 10): [Arrival!]!
+}
+
+type Line {
+  ref: String!
+  code: String!
+  name: String!
+  routeId: String!
+  color: String!
+  textColor: String!
+  operator: String!
+  mode: String
+  agency: Agency!
+  destinations: [String!]!
+  stops(direction: Int = 0): [Stop!]!
+  vehicles: [Vehicle!]!
+  alerts: [Alert!]!
+}
+
+type Vehicle {
+  id: String!
+  tripId: String!
+  routeId: String
+  directionId: Int
+  destination: String
+  latitude: Float!
+  longitude: Float!
+  bearing: Float
+  delay: Int
+  timestamp: Int
+  occupancy: String
+  currentStopSequence: Int
+  line: Line
+  trip: Trip
+  currentStop: Stop
+  nextStop: Stop
+}
+
+type Alert {
+  id: String!
+  text: String!
+  description: String!
+  url: String
+  severity: Int!
+  activePeriodStart: Int
+  activePeriodEnd: Int
+  affectedStopsCount: Int!
+  derived: Boolean!
+  lines: [Line!]!
+  stops: [Stop!]!
+}
+
+type Trip {
+  id: String!
+  headsign: String
+  directionId: Int
+  serviceId: String!
+  realTime: Boolean!
+  line: Line
+  stopTimes: [StopTime!]!
+}
+
+type StopTime {
+  stopSequence: Int!
+  arrivalTime: String!
+  departureTime: String!
+  delay: Int
+  predictedTime: Int
+  skipped: Boolean!
+  stop: Stop
+}
+
+type Arrival {
+  tripId: String!
+  lineCode: String!
+  arrivalTime: String!
+  departureTime: String!
+  serviceDate: String!
+  destination: String
+  operator: String!
+  line: Line
+  trip: Trip
+}
+
+type Agency {
+  name: String!
+  slug: String!
+  lines: [Line!]!
+}
+"#;
+
+/// Body of POST /api/graphql; GET takes the same as query parameters, `variables` as JSON
+#[derive(Debug, Deserialize)]
+pub struct GraphQLRequest {
+    pub query: String,
+    #[serde(default)]
+    pub variables: Option<Map<String, Value>>,
+    #[serde(default, rename = "operationName")]
+    pub operation_name: Option<String>,
+}
+
+// ---- Parsing ----
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Punct(char),
+    Spread,
+    Name(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum InputValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Enum(String),
+    List(Vec<InputValue>),
+    Object(Vec<(String, InputValue)>),
+    Variable(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Field {
+    alias: Option<String>,
+    name: String,
+    arguments: Vec<(String, InputValue)>,
+    selection: Vec<Field>,
+}
+
+impl Field {
+    fn response_key(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Debug)]
+struct Operation {
+    name: Option<String>,
+    variables: Vec<(String, Option<InputValue>)>, // (name, default)
+    selection: Vec<Field>,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            // Commas are insignificant in GraphQL
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => i += 1,
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '{' | '}' | '(' | ')' | '[' | ']' | ':' | '!' | '$' | '=' | '@' | '|' | '&' => {
+                tokens.push(Token::Punct(c));
+                i += 1;
+            }
+            '.' if chars[i..].starts_with(&['.', '.', '.']) => {
+                tokens.push(Token::Spread);
+                i += 3;
+            }
+            '"' => {
+                if chars[i..].starts_with(&['"', '"', '"']) {
+                    return Err("Block strings are not supported".to_string());
+                }
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None | Some('\n') => return Err("Unterminated string".to_string()),
+                        Some('"') => break,
+                        Some('\\') => {
+                            let escaped = match chars.get(i + 1) {
+                                Some('"') => '"',
+                                Some('\\') => '\\',
+                                Some('/') => '/',
+                                Some('b') => '\u{8}',
+                                Some('f') => '\u{c}',
+                                Some('n') => '\n',
+                                Some('r') => '\r',
+                                Some('t') => '\t',
+                                Some('u') => {
+                                    let hex: String = chars.iter().skip(i + 2).take(4).collect();
+                                    i += 4;
+                                    u32::from_str_radix(&hex, 16).ok()
+                                        .and_then(char::from_u32)
+                                        .ok_or_else(|| format!("Invalid unicode escape '\\u{}'", hex))?
+                                }
+                                _ => return Err("Invalid escape in string".to_string()),
+                            };
+                            value.push(escaped);
+                            i += 2;
+                        }
+                        Some(&other) => {
+                            value.push(other);
+                            i += 1;
+                        }
+                    }
+                }
+                tokens.push(Token::Str(value));
+                i += 1;
+            }
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let start = i;
+                while i < chars.len() && (chars[i] == '_' || chars[i].is_ascii_alphanumeric()) {
+                    i += 1;
+                }
+                tokens.push(Token::Name(chars[start..i].iter().collect()));
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let start = i;
+                i += 1;
+                let mut float = false;
+                while i < chars.len() {
+                    match chars[i] {
+                        '0'..='9' => {}
+                        '.' | 'e' | 'E' => float = true,
+                        '+' | '-' if matches!(chars[i - 1], 'e' | 'E') => {}
+                        _ => break,
+                    }
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let token = if float {
+                    text.parse().map(Token::Float).ok()
+                } else {
+                    text.parse().map(Token::Int).ok()
+                };
+                tokens.push(token.ok_or_else(|| format!("Invalid number '{}'", text))?);
+            }
+            other => return Err(format!("Unexpected character '{}'", other)),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or("Unexpected end of document")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    /// Consume `c` if it comes next
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(&Token::Punct(c));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.next()? {
+            Token::Punct(found) if found == c => Ok(()),
+            other => Err(format!("Expected '{}', found {}", c, describe(&other))),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            other => Err(format!("Expected a name, found {}", describe(&other))),
+        }
+    }
+
+    fn document(&mut self) -> Result<Vec<Operation>, String> {
+        let mut operations = Vec::new();
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Punct('{') => operations.push(Operation {
+                    name: None,
+                    variables: Vec::new(),
+                    selection: self.selection_set()?,
+                }),
+                Token::Name(keyword) if keyword == "query" => {
+                    self.pos += 1;
+                    let name = match self.peek() {
+                        Some(Token::Name(_)) => Some(self.name()?),
+                        _ => None,
+                    };
+                    let variables = if self.eat('(') { self.variable_definitions()? } else { Vec::new() };
+                    if self.peek() == Some(&Token::Punct('@')) {
+                        return Err("Directives are not supported".to_string());
+                    }
+                    operations.push(Operation { name, variables, selection: self.selection_set()? });
+                }
+                Token::Name(keyword) if keyword == "mutation" || keyword == "subscription" => {
+                    return Err(format!("{} operations are not supported", keyword));
+                }
+                Token::Name(keyword) if keyword == "fragment" => {
+                    return Err("Fragments are not supported".to_string());
+                }
+                other => return Err(format!("Unexpected {}", describe(other))),
+            }
+        }
+        if operations.is_empty() {
+            return Err("The document has no operation".to_string());
+        }
+        Ok(operations)
+    }
+
+    /// `$name: Type = default` entries, after the opening parenthesis
+    fn variable_definitions(&mut self) -> Result<Vec<(String, Option<InputValue>)>, String> {
+        let mut variables = Vec::new();
+        while !self.eat(')') {
+            self.expect('$')?;
+            let name = self.name()?;
+            self.expect(':')?;
+            self.skip_type()?;
+            let default = if self.eat('=') { Some(self.value()?) } else { None };
+            variables.push((name, default));
+        }
+        Ok(variables)
+    }
+
+    /// Variable types are not checked, arguments are as each field reads them
+    fn skip_type(&mut self) -> Result<(), String> {
+        if self.eat('[') {
+            self.skip_type()?;
+            self.expect(']')?;
+        } else {
+            self.name()?;
+        }
+        self.eat('!');
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<InputValue, String> {
+        Ok(match self.next()? {
+            Token::Punct('$') => InputValue::Variable(self.name()?),
+            Token::Int(value) => InputValue::Int(value),
+            Token::Float(value) => InputValue::Float(value),
+            Token::Str(value) => InputValue::String(value),
+            Token::Name(name) => match name.as_str() {
+                "true" => InputValue::Bool(true),
+                "false" => InputValue::Bool(false),
+                "null" => InputValue::Null,
+                _ => InputValue::Enum(name),
+            },
+            Token::Punct('[') => {
+                let mut items = Vec::new();
+                while !self.eat(']') {
+                    items.push(self.value()?);
+                }
+                InputValue::List(items)
+            }
+            Token::Punct('{') => {
+                let mut fields = Vec::new();
+                while !self.eat('}') {
+                    let name = self.name()?;
+                    self.expect(':')?;
+                    fields.push((name, self.value()?));
+                }
+                InputValue::Object(fields)
+            }
+            other => return Err(format!("Expected a value, found {}", describe(&other))),
+        })
+    }
+
+    fn selection_set(&mut self) -> Result<Vec<Field>, String> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        while !self.eat('}') {
+            match self.peek() {
+                Some(Token::Spread) => return Err("Fragments are not supported".to_string()),
+                Some(Token::Name(_)) => fields.push(self.field()?),
+                Some(other) => return Err(format!("Expected a field, found {}", describe(other))),
+                None => return Err("Unexpected end of document".to_string()),
+            }
+        }
+        if fields.is_empty() {
+            return Err("Selection sets can't be empty".to_string());
+        }
+        Ok(fields)
+    }
+
+    fn field(&mut self) -> Result<Field, String> {
+        let first = self.name()?;
+        let (alias, name) = if self.eat(':') { (Some(first), self.name()?) } else { (None, first) };
+        let mut arguments = Vec::new();
+        if self.eat('(') {
+            while !self.eat(')') {
+                let argument = self.name()?;
+                self.expect(':')?;
+                arguments.push((argument, self.value()?));
+            }
+        }
+        if self.peek() == Some(&Token::Punct('@')) {
+            return Err("Directives are not supported".to_string());
+        }
+        let selection = if self.peek() == Some(&Token::Punct('{')) { self.selection_set()? } else { Vec::new() };
+        Ok(Field { alias, name, arguments, selection })
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Punct(c) => format!("'{}'", c),
+        Token::Spread => "'...'".to_string(),
+        Token::Name(name) => format!("'{}'", name),
+        Token::Int(value) => value.to_string(),
+        Token::Float(value) => value.to_string(),
+        Token::Str(value) => format!("\"{}\"", value),
+    }
+}
+
+fn parse(source: &str) -> Result<Vec<Operation>, String> {
+    Parser { tokens: tokenize(source)?, pos: 0 }.document()
+}
+
+fn depth(selection: &[Field]) -> usize {
+    selection.iter().map(|field| 1 + depth(&field.selection)).max().unwrap_or(0)
+}
+
+// ---- Execution ----
+
+/// Run a request against the cache; the result is the whole GraphQL response
+pub fn execute(request: &GraphQLRequest, cache: &CachedNetworkData) -> Value {
+    let operations = match parse(&request.query) {
+        Ok(operations) => operations,
+        Err(message) => return json!({ "errors": [{ "message": message }] }),
+    };
+    let operation = match &request.operation_name {
+        Some(name) => operations.iter().find(|operation| operation.name.as_ref() == Some(name))
+            .ok_or_else(|| format!("Unknown operation '{}'", name)),
+        None if operations.len() == 1 => Ok(&operations[0]),
+        None => Err("operationName is required when the document has several operations".to_string()),
+    };
+    let operation = match operation {
+        Ok(operation) => operation,
+        Err(message) => return json!({ "errors": [{ "message": message }] }),
+    };
+    if depth(&operation.selection) > MAX_DEPTH {
+        return json!({ "errors": [{ "message": format!("Query is nested deeper than {} levels", MAX_DEPTH) }] });
+    }
+
+    let network = cache.to_network_data();
+    let context = Context::new(cache, &network, &operation.variables, request.variables.clone().unwrap_or_default());
+    let data = context.object(&operation.selection, "Query", &[], |field, path| context.query(field, path));
+    let errors = context.errors.into_inner();
+    if errors.is_empty() {
+        json!({ "data": data })
+    } else {
+        json!({ "data": data, "errors": errors })
+    }
+}
+
+struct Context<'a> {
+    cache: &'a CachedNetworkData,
+    network: &'a NetworkData,
+    stops: HashMap<&'a str, &'a Stop>,
+    lines: HashMap<&'a str, &'a Line>, // key: line_ref and route_id
+    vehicle_lines: HashMap<&'a str, &'a Line>, // key: vehicle_id
+    variables: Map<String, Value>,
+    errors: RefCell<Vec<Value>>,
+}
+
+type Resolved = Result<Value, String>;
+
+fn unknown_field(field: &Field, type_name: &str) -> String {
+    format!("Cannot query field '{}' on type '{}'", field.name, type_name)
+}
+
+fn subselection<'f>(field: &'f Field, type_name: &str) -> Result<&'f [Field], String> {
+    if field.selection.is_empty() {
+        return Err(format!("Field '{}' of type '{}' must have a selection of subfields", field.name, type_name));
+    }
+    Ok(&field.selection)
+}
+
+/// Leaf value, refusing a selection set on it
+fn scalar(field: &Field, value: Value) -> Resolved {
+    if !field.selection.is_empty() {
+        return Err(format!("Field '{}' is a scalar and can't have a selection", field.name));
+    }
+    Ok(value)
+}
+
+impl<'a> Context<'a> {
+    fn new(
+        cache: &'a CachedNetworkData,
+        network: &'a NetworkData,
+        definitions: &[(String, Option<InputValue>)],
+        mut variables: Map<String, Value>,
+    ) -> Self {
+        let mut lines = HashMap::new();
+        let mut vehicle_lines = HashMap::new();
+        for line in &network.lines {
+            lines.entry(line.route_id.as_str()).or_insert(line);
+            lines.entry(line.line_ref.as_str()).or_insert(line);
+            for vehicle in &line.real_time {
+                vehicle_lines.entry(vehicle.vehicle_id.as_str()).or_insert(line);
+            }
+        }
+        let mut context = Context {
+            cache,
+            network,
+            stops: network.stops.iter().map(|stop| (stop.stop_id.as_str(), stop)).collect(),
+            lines,
+            vehicle_lines,
+            variables: Map::new(),
+            errors: RefCell::new(Vec::new()),
+        };
+        // Defaults can't refer to variables, so they are read with an empty set
+        for (name, default) in definitions {
+            if !variables.contains_key(name)
+                && let Some(default) = default {
+                variables.insert(name.clone(), context.to_json(default));
+            }
+        }
+        context.variables = variables;
+        context
+    }
+
+    fn to_json(&self, value: &InputValue) -> Value {
+        match value {
+            InputValue::Null => Value::Null,
+            InputValue::Bool(value) => json!(value),
+            InputValue::Int(value) => json!(value),
+            InputValue::Float(value) => json!(value),
+            InputValue::String(value) | InputValue::Enum(value) => json!(value),
+            InputValue::List(items) => Value::Array(items.iter().map(|item| self.to_json(item)).collect()),
+            InputValue::Object(fields) => Value::Object(
+                fields.iter().map(|(name, value)| (name.clone(), self.to_json(value))).collect()
+            ),
+            InputValue::Variable(name) => self.variables.get(name).cloned().unwrap_or(Value::Null),
+        }
+    }
+
+    /// Argument value, None when absent or null
+    fn argument(&self, field: &Field, name: &str) -> Option<Value> {
+        field.arguments.iter()
+            .find(|(argument, _)| argument == name)
+            .map(|(_, value)| self.to_json(value))
+            .filter(|value| !value.is_null())
+    }
+
+    fn string_argument(&self, field: &Field, name: &str) -> Result<Option<String>, String> {
+        match self.argument(field, name) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value)),
+            Some(_) => Err(format!("Argument '{}' of '{}' must be a String", name, field.name)),
+        }
+    }
+
+    fn required_string(&self, field: &Field, name: &str) -> Result<String, String> {
+        self.string_argument(field, name)?
+            .ok_or_else(|| format!("Argument '{}' of '{}' is required", name, field.name))
+    }
+
+    fn int_argument(&self, field: &Field, name: &str) -> Result<Option<u64>, String> {
+        match self.argument(field, name) {
+            None => Ok(None),
+            Some(value) => value.as_u64().map(Some)
+                .ok_or_else(|| format!("Argument '{}' of '{}' must be a non-negative Int", name, field.name)),
+        }
+    }
+
+    fn limit(&self, field: &Field, default: usize) -> Result<usize, String> {
+        Ok(self.int_argument(field, "limit")?.map_or(default, |limit| (limit as usize).min(MAX_LIMIT)))
+    }
+
+    fn bbox(&self, field: &Field) -> Result<Option<BoundingBox>, String> {
+        self.string_argument(field, "bbox")?.map(|bbox| BoundingBox::parse(&bbox)).transpose()
+    }
+
+    /// Resolve the selection on one object; fields that fail are null, their errors recorded
+    fn object(
+        &self,
+        selection: &[Field],
+        type_name: &str,
+        path: &[Value],
+        resolve: impl Fn(&Field, &[Value]) -> Resolved,
+    ) -> Value {
+        let mut object = Map::new();
+        for field in selection {
+            let mut field_path = path.to_vec();
+            field_path.push(json!(field.response_key()));
+            let value = if field.name == "__typename" {
+                scalar(field, json!(type_name))
+            } else {
+                resolve(field, &field_path)
+            };
+            let value = value.unwrap_or_else(|message| {
+                self.errors.borrow_mut().push(json!({ "message": message, "path": field_path }));
+                Value::Null
+            });
+            object.insert(field.response_key().to_string(), value);
+        }
+        Value::Object(object)
+    }
+
+    fn list<T>(
+        &self,
+        items: impl IntoIterator<Item = T>,
+        path: &[Value],
+        resolve: impl Fn(T, &[Value]) -> Value,
+    ) -> Value {
+        Value::Array(items.into_iter().enumerate().map(|(index, item)| {
+            let mut item_path = path.to_vec();
+            item_path.push(json!(index));
+            resolve(item, &item_path)
+        }).collect())
+    }
+
+    fn optional<T>(&self, item: Option<T>, resolve: impl FnOnce(T) -> Value) -> Value {
+        item.map_or(Value::Null, resolve)
+    }
+
+    fn query(&self, field: &Field, path: &[Value]) -> Resolved {
+        Ok(match field.name.as_str() {
+            "stop" => {
+                let selection = subselection(field, "Stop")?;
+                let id = self.required_string(field, "id")?;
+                self.optional(self.stops.get(id.as_str()), |stop| self.stop(stop, selection, path))
+            }
+            "stops" => {
+                let selection = subselection(field, "Stop")?;
+                let ids: Option<Vec<String>> = match self.argument(field, "ids") {
+                    None => None,
+                    Some(ids) => Some(serde_json::from_value(ids)
+                        .map_err(|_| format!("Argument 'ids' of '{}' must be a list of String", field.name))?),
+                };
+                let bbox = self.bbox(field)?;
+                let limit = self.limit(field, DEFAULT_LIMIT)?;
+                let stops: Vec<&Stop> = match &ids {
+                    Some(ids) => ids.iter().filter_map(|id| self.stops.get(id.as_str()).copied()).collect(),
+                    None => self.network.stops.iter().collect(),
+                };
+                let stops = stops.into_iter()
+                    .filter(|stop| bbox.as_ref().is_none_or(|bbox| bbox.contains(stop.latitude, stop.longitude)))
+                    .take(limit);
+                self.list(stops, path, |stop, path| self.stop(stop, selection, path))
+            }
+            "line" => {
+                let selection = subselection(field, "Line")?;
+                let code = self.required_string(field, "code")?;
+                let operator = self.string_argument(field, "operator")?;
+                let line = self.network.lines.iter().find(|line| {
+                    (line.line_code.eq_ignore_ascii_case(&code) || line.line_ref == code)
+                        && operator.as_ref().is_none_or(|operator| operators::matches(&line.operator, operator))
+                });
+                self.optional(line, |line| self.line(line, selection, path))
+            }
+            "lines" => {
+                let selection = subselection(field, "Line")?;
+                let operator = self.string_argument(field, "operator")?;
+                let limit = self.limit(field, DEFAULT_LIMIT)?;
+                let lines = self.network.lines.iter()
+                    .filter(|line| operator.as_ref().is_none_or(|operator| operators::matches(&line.operator, operator)))
+                    .take(limit);
+                self.list(lines, path, |line, path| self.line(line, selection, path))
+            }
+            "vehicle" => {
+                let selection = subselection(field, "Vehicle")?;
+                let id = self.required_string(field, "id")?;
+                let vehicle = self.cache.real_time.iter().find(|vehicle| vehicle.vehicle_id == id);
+                self.optional(vehicle, |vehicle| self.vehicle(vehicle, selection, path))
+            }
+            "vehicles" => {
+                let selection = subselection(field, "Vehicle")?;
+                let line_code = self.string_argument(field, "line")?;
+                let bbox = self.bbox(field)?;
+                let limit = self.limit(field, DEFAULT_LIMIT)?;
+                let vehicles = self.cache.real_time.iter()
+                    .filter(|vehicle| line_code.as_ref().is_none_or(|code| {
+                        self.vehicle_line(vehicle).is_some_and(|line| line.line_code.eq_ignore_ascii_case(code))
+                    }))
+                    .filter(|vehicle| bbox.as_ref().is_none_or(|bbox| bbox.contains(vehicle.latitude, vehicle.longitude)))
+                    .take(limit);
+                self.list(vehicles, path, |vehicle, path| self.vehicle(vehicle, selection, path))
+            }
+            "alerts" => {
+                let selection = subselection(field, "Alert")?;
+                // A GTFS route_id or a line code, as /alerts?route= takes it
+                let route_ids = self.string_argument(field, "route")?.map(|route| {
+                    let mut ids = vec![route.clone()];
+                    for line in self.network.lines.iter().filter(|line| line.line_code.eq_ignore_ascii_case(&route)) {
+                        ids.push(line.route_id.clone());
+                        ids.push(line.line_ref.clone());
+                    }
+                    ids
+                });
+                let filter = AlertFilter {
+                    route_ids,
+                    stop_id: self.string_argument(field, "stop")?,
+                    severity_min: self.int_argument(field, "severityMin")?.map(|severity| severity as u32),
+                    active_at: None,
+                };
+                let alerts = filter.apply(&self.cache.alerts, &self.cache.alert_stops);
+                self.list(alerts, path, |alert, path| self.alert(alert, selection, path))
+            }
+            "trip" => {
+                let selection = subselection(field, "Trip")?;
+                let id = self.required_string(field, "id")?;
+                self.optional(self.trip_details(&id), |trip| self.trip(&trip, selection, path))
+            }
+            "agency" => {
+                let selection = subselection(field, "Agency")?;
+                let name = self.required_string(field, "name")?;
+                let agency = self.agencies().into_iter().find(|agency| operators::matches(agency, &name));
+                self.optional(agency, |agency| self.agency(agency, selection, path))
+            }
+            "agencies" => {
+                let selection = subselection(field, "Agency")?;
+                self.list(self.agencies(), path, |agency, path| self.agency(agency, selection, path))
+            }
+            _ => return Err(unknown_field(field, "Query")),
+        })
+    }
+
+    fn agencies(&self) -> Vec<&'a str> {
+        let names: BTreeSet<&str> = self.network.lines.iter().map(|line| line.operator.as_str()).collect();
+        names.into_iter().collect()
+    }
+
+    fn vehicle_line(&self, vehicle: &RealTimeInfo) -> Option<&'a Line> {
+        self.vehicle_lines.get(vehicle.vehicle_id.as_str())
+            .or_else(|| vehicle.route_id.as_deref().and_then(|route_id| self.lines.get(route_id)))
+            .copied()
+    }
+
+    fn trip_details(&self, trip_id: &str) -> Option<TripDetails> {
+        NVTModels::trip_details_in(trip_id, self.cache, &self.network.lines)
+    }
+
+    fn lines_of<'i>(&self, refs: impl IntoIterator<Item = &'i String>) -> Vec<&'a Line> {
+        let mut lines: Vec<&Line> = Vec::new();
+        for line in refs.into_iter().filter_map(|id| self.lines.get(id.as_str())) {
+            if !lines.iter().any(|seen| std::ptr::eq(*seen, *line)) {
+                lines.push(line);
+            }
+        }
+        lines
+    }
+
+    fn stop(&self, stop: &'a Stop, selection: &[Field], path: &[Value]) -> Value {
+        self.object(selection, "Stop", path, |field, path| match field.name.as_str() {
+            "id" => scalar(field, json!(stop.stop_id)),
+            "name" => scalar(field, json!(stop.stop_name)),
+            "latitude" => scalar(field, json!(stop.latitude)),
+            "longitude" => scalar(field, json!(stop.longitude)),
+            "commune" => scalar(field, json!(stop.commune)),
+            "inseeCode" => scalar(field, json!(stop.insee_code)),
+            "lines" => {
+                let selection = subselection(field, "Line")?;
+                Ok(self.list(self.lines_of(&stop.lines), path, |line, path| self.line(line, selection, path)))
+            }
+            "alerts" => {
+                let selection = subselection(field, "Alert")?;
+                Ok(self.list(&stop.alerts, path, |alert, path| self.alert(alert, selection, path)))
+            }
+            "vehicles" => {
+                let selection = subselection(field, "Vehicle")?;
+                let vehicles = self.cache.real_time.iter()
+                    .filter(|vehicle| vehicle.stop_id.as_deref() == Some(stop.stop_id.as_str()));
+                Ok(self.list(vehicles, path, |vehicle, path| self.vehicle(vehicle, selection, path)))
+            }
+            "arrivals" => {
+                let selection = subselection(field, "Arrival")?;
+                let limit = self.limit(field, DEFAULT_ARRIVALS)?;
+                let arrivals = NVTModels::get_scheduled_arrivals(&stop.stop_id, self.cache, NVTModels::paris_now().naive_local(), limit);
+                Ok(self.list(&arrivals, path, |arrival, path| self.arrival(arrival, selection, path)))
+            }
+            _ => Err(unknown_field(field, "Stop")),
+        })
+    }
+
+    fn line(&self, line: &'a Line, selection: &[Field], path: &[Value]) -> Value {
+        self.object(selection, "Line", path, |field, path| match field.name.as_str() {
+            "ref" => scalar(field, json!(line.line_ref)),
+            "code" => scalar(field, json!(line.line_code)),
+            "name" => scalar(field, json!(line.line_name)),
+            "routeId" => scalar(field, json!(line.route_id)),
+            "color" => scalar(field, json!(line.color)),
+            "textColor" => scalar(field, json!(line.text_color)),
+            "operator" => scalar(field, json!(line.operator)),
+            "mode" => scalar(field, json!(line.mode)),
+            "agency" => Ok(self.agency(&line.operator, subselection(field, "Agency")?, path)),
+            "destinations" => {
+                let destinations: Vec<&str> = line.destinations.iter().map(|(_, name)| name.as_str()).collect();
+                scalar(field, json!(destinations))
+            }
+            "stops" => {
+                let selection = subselection(field, "Stop")?;
+                let direction = self.int_argument(field, "direction")?.unwrap_or(0) as u32;
+                let stops = NVTModels::line_stop_sequence(line, direction, self.cache).iter()
+                    .filter_map(|(_, stop_id)| self.stops.get(stop_id.as_str()).copied());
+                Ok(self.list(stops, path, |stop, path| self.stop(stop, selection, path)))
+            }
+            "vehicles" => {
+                let selection = subselection(field, "Vehicle")?;
+                Ok(self.list(&line.real_time, path, |vehicle, path| self.vehicle(vehicle, selection, path)))
+            }
+            "alerts" => {
+                let selection = subselection(field, "Alert")?;
+                Ok(self.list(&line.alerts, path, |alert, path| self.alert(alert, selection, path)))
+            }
+            _ => Err(unknown_field(field, "Line")),
+        })
+    }
+
+    fn vehicle(&self, vehicle: &'a RealTimeInfo, selection: &[Field], path: &[Value]) -> Value {
+        self.object(selection, "Vehicle", path, |field, path| match field.name.as_str() {
+            "id" => scalar(field, json!(vehicle.vehicle_id)),
+            "tripId" => scalar(field, json!(vehicle.trip_id)),
+            "routeId" => scalar(field, json!(vehicle.route_id)),
+            "directionId" => scalar(field, json!(vehicle.direction_id)),
+            "destination" => scalar(field, json!(vehicle.destination)),
+            "latitude" => scalar(field, json!(vehicle.latitude)),
+            "longitude" => scalar(field, json!(vehicle.longitude)),
+            "bearing" => scalar(field, json!(vehicle.bearing)),
+            "delay" => scalar(field, json!(vehicle.delay)),
+            "timestamp" => scalar(field, json!(vehicle.timestamp)),
+            "occupancy" => scalar(field, json!(vehicle.occupancy)),
+            "currentStopSequence" => scalar(field, json!(vehicle.current_stop_sequence)),
+            "line" => {
+                let selection = subselection(field, "Line")?;
+                Ok(self.optional(self.vehicle_line(vehicle), |line| self.line(line, selection, path)))
+            }
+            "trip" => {
+                let selection = subselection(field, "Trip")?;
+                Ok(self.optional(self.trip_details(&vehicle.trip_id), |trip| self.trip(&trip, selection, path)))
+            }
+            "currentStop" => {
+                let selection = subselection(field, "Stop")?;
+                let stop = vehicle.stop_id.as_deref().and_then(|stop_id| self.stops.get(stop_id));
+                Ok(self.optional(stop, |stop| self.stop(stop, selection, path)))
+            }
+            "nextStop" => {
+                let selection = subselection(field, "Stop")?;
+                // First stop of the trip after the one the vehicle reported
+                let stop = vehicle.current_stop_sequence
+                    .and_then(|current| self.cache.trip_index.get(&vehicle.trip_id)?
+                        .iter()
+                        .find(|(sequence, _)| *sequence > current))
+                    .and_then(|(_, stop_id)| self.stops.get(stop_id.as_str()));
+                Ok(self.optional(stop, |stop| self.stop(stop, selection, path)))
+            }
+            _ => Err(unknown_field(field, "Vehicle")),
+        })
+    }
+
+    fn alert(&self, alert: &AlertInfo, selection: &[Field], path: &[Value]) -> Value {
+        self.object(selection, "Alert", path, |field, path| match field.name.as_str() {
+            "id" => scalar(field, json!(alert.id)),
+            "text" => scalar(field, json!(alert.text)),
+            "description" => scalar(field, json!(alert.description)),
+            "url" => scalar(field, json!(alert.url)),
+            "severity" => scalar(field, json!(alert.severity)),
+            "activePeriodStart" => scalar(field, json!(alert.active_period_start)),
+            "activePeriodEnd" => scalar(field, json!(alert.active_period_end)),
+            "affectedStopsCount" => scalar(field, json!(alert.affected_stops_count)),
+            "derived" => scalar(field, json!(alert.derived)),
+            "lines" => {
+                let selection = subselection(field, "Line")?;
+                Ok(self.list(self.lines_of(&alert.route_ids), path, |line, path| self.line(line, selection, path)))
+            }
+            "stops" => {
+                let selection = subselection(field, "Stop")?;
+                let stops = self.cache.alert_stops.get(&alert.id).into_iter().flatten()
+                    .filter_map(|stop_id| self.stops.get(stop_id.as_str()).copied());
+                Ok(self.list(stops, path, |stop, path| self.stop(stop, selection, path)))
+            }
+            _ => Err(unknown_field(field, "Alert")),
+        })
+    }
+
+    fn trip(&self, trip: &TripDetails, selection: &[Field], path: &[Value]) -> Value {
+        self.object(selection, "Trip", path, |field, path| match field.name.as_str() {
+            "id" => scalar(field, json!(trip.trip.trip_id)),
+            "headsign" => scalar(field, json!(trip.trip.trip_headsign)),
+            "directionId" => scalar(field, json!(trip.trip.direction_id)),
+            "serviceId" => scalar(field, json!(trip.trip.service_id)),
+            "realTime" => scalar(field, json!(trip.real_time)),
+            "line" => {
+                let selection = subselection(field, "Line")?;
+                let line = self.lines.get(trip.trip.route_id.as_str()).copied();
+                Ok(self.optional(line, |line| self.line(line, selection, path)))
+            }
+            "stopTimes" => {
+                let selection = subselection(field, "StopTime")?;
+                Ok(self.list(&trip.stop_times, path, |stop_time, path| self.stop_time(stop_time, selection, path)))
+            }
+            _ => Err(unknown_field(field, "Trip")),
+        })
+    }
+
+    fn stop_time(&self, stop_time: &TripStopTime, selection: &[Field], path: &[Value]) -> Value {
+        self.object(selection, "StopTime", path, |field, path| match field.name.as_str() {
+            "stopSequence" => scalar(field, json!(stop_time.stop_time.stop_sequence)),
+            "arrivalTime" => scalar(field, json!(stop_time.stop_time.arrival_time)),
+            "departureTime" => scalar(field, json!(stop_time.stop_time.departure_time)),
+            "delay" => scalar(field, json!(stop_time.delay)),
+            "predictedTime" => scalar(field, json!(stop_time.predicted_time)),
+            "skipped" => scalar(field, json!(stop_time.skipped)),
+            "stop" => {
+                let selection = subselection(field, "Stop")?;
+                let stop = self.stops.get(stop_time.stop_time.stop_id.as_str()).copied();
+                Ok(self.optional(stop, |stop| self.stop(stop, selection, path)))
+            }
+            _ => Err(unknown_field(field, "StopTime")),
+        })
+    }
+
+    fn arrival(&self, arrival: &ScheduledArrival, selection: &[Field], path: &[Value]) -> Value {
+        self.object(selection, "Arrival", path, |field, path| match field.name.as_str() {
+            "tripId" => scalar(field, json!(arrival.trip_id)),
+            "lineCode" => scalar(field, json!(arrival.line_code)),
+            "arrivalTime" => scalar(field, json!(arrival.arrival_time)),
+            "departureTime" => scalar(field, json!(arrival.departure_time)),
+            "serviceDate" => scalar(field, json!(arrival.service_date)),
+            "destination" => scalar(field, json!(arrival.destination)),
+            "operator" => scalar(field, json!(arrival.operator)),
+            "line" => {
+                let selection = subselection(field, "Line")?;
+                let line = self.lines.get(arrival.route_id.as_str()).copied();
+                Ok(self.optional(line, |line| self.line(line, selection, path)))
+            }
+            "trip" => {
+                let selection = subselection(field, "Trip")?;
+                Ok(self.optional(self.trip_details(&arrival.trip_id), |trip| self.trip(&trip, selection, path)))
+            }
+            _ => Err(unknown_field(field, "Arrival")),
+        })
+    }
+
+    fn agency(&self, name: &str, selection: &[Field], path: &[Value]) -> Value {
+        self.object(selection, "Agency", path, |field, path| match field.name.as_str() {
+            "name" => scalar(field, json!(name)),
+            "slug" => scalar(field, json!(operators::operator_slug(name))),
+            "lines" => {
+                let selection = subselection(field, "Line")?;
+                let lines = self.network.lines.iter().filter(|line| line.operator == name);
+                Ok(self.list(lines, path, |line, path| self.line(line, selection, path)))
+            }
+            _ => Err(unknown_field(field, "Agency")),
+        })
+    }
+}
@@ -0,0 +1,284 @@
+// Merged GTFS export (GET /api/tbm/export/gtfs.zip)
+// The static timetables of TBM, New-Aquitaine and SNCF written back out as one GTFS feed, for
+// OpenTripPlanner, Transitland and other tools that take a single regional feed. Ids overlap
+// between the source feeds (TBM stop "3671" and route "1" are plain numbers), so every stop,
+// route, trip, service and agency id is prefixed with its source: "TBM:", "NAQ:" or "SNCF:"
+// (SNCF stops by the UIC code they are kept under, "SNCF:87581009"), and the name of a plain
+// feed of the configuration for its ids.
+// Only what the caches keep is written: route names come from the line lists, a route_type
+// missing from the source is given as 2 (rail) for SNCF and 3 (bus) otherwise, and trips carry
+// no shape_id, so shapes.txt is left out. Rows are sorted by id so that the same data gives the same feed.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{Cursor, Write};
+
+use crate::tbm_api_models::{CachedNetworkData, GTFSCache, NVTError, NVTModels, Result};
+
+pub const CONTENT_TYPE: &str = "application/zip";
+
+/// Id prefix and GTFS data of each source, in gtfs_caches order
+fn prefixed_sources(cache: &CachedNetworkData) -> Vec<(&str, &GTFSCache)> {
+    cache.gtfs_sources().into_iter()
+        .map(|(source, gtfs_cache)| (if source == "New-Aquitaine" { "NAQ" } else { source }, gtfs_cache))
+        .collect()
+}
+
+const TIMEZONE: &str = "Europe/Paris";
+
+/// One file of the merged feed: its name, header row and rows
+struct ExportFile<'a> {
+    name: &'static str,
+    header: &'static [&'static str],
+    rows: &'a [Vec<String>],
+}
+
+/// Rows of the files of the merged feed
+#[derive(Default)]
+struct Feed {
+    agency: Vec<Vec<String>>,
+    stops: Vec<Vec<String>>,
+    routes: Vec<Vec<String>>,
+    trips: Vec<Vec<String>>,
+    stop_times: Vec<Vec<String>>,
+    calendar: Vec<Vec<String>>,
+    calendar_dates: Vec<Vec<String>>,
+    transfers: Vec<Vec<String>>,
+    frequencies: Vec<Vec<String>>,
+}
+
+fn export_error(e: impl std::fmt::Display) -> NVTError {
+    NVTError::ParseError(format!("Failed to write GTFS export: {}", e))
+}
+
+/// The merged feed as a GTFS zip
+pub fn write_feed(cache: &CachedNetworkData) -> Result<Vec<u8>> {
+    let mut feed = Feed::default();
+    for (prefix, gtfs_cache) in prefixed_sources(cache) {
+        add_source(&mut feed, prefix, gtfs_cache, &route_names(cache, prefix));
+    }
+
+    let files = [
+        ExportFile { name: "agency.txt", header: &["agency_id", "agency_name", "agency_url", "agency_timezone", "agency_phone"], rows: &feed.agency },
+        ExportFile { name: "stops.txt", header: &["stop_id", "stop_name", "stop_lat", "stop_lon", "wheelchair_boarding"], rows: &feed.stops },
+        ExportFile { name: "routes.txt", header: &["route_id", "agency_id", "route_short_name", "route_long_name", "route_type", "route_color", "route_text_color"], rows: &feed.routes },
+        ExportFile { name: "trips.txt", header: &["route_id", "service_id", "trip_id", "trip_headsign", "direction_id", "wheelchair_accessible", "bikes_allowed"], rows: &feed.trips },
+        ExportFile { name: "stop_times.txt", header: &["trip_id", "arrival_time", "departure_time", "stop_id", "stop_sequence", "stop_headsign"], rows: &feed.stop_times },
+        ExportFile { name: "calendar.txt", header: &["service_id", "monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday", "start_date", "end_date"], rows: &feed.calendar },
+        ExportFile { name: "calendar_dates.txt", header: &["service_id", "date", "exception_type"], rows: &feed.calendar_dates },
+        ExportFile { name: "transfers.txt", header: &["from_stop_id", "to_stop_id", "transfer_type", "min_transfer_time"], rows: &feed.transfers },
+        ExportFile { name: "frequencies.txt", header: &["trip_id", "start_time", "end_time", "headway_secs", "exact_times"], rows: &feed.frequencies },
+    ];
+
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for ExportFile { name, header, rows } in files {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(header).map_err(export_error)?;
+        for row in rows {
+            writer.write_record(row).map_err(export_error)?;
+        }
+        let contents = writer.into_inner().map_err(export_error)?;
+        zip.start_file(name, options).map_err(export_error)?;
+        zip.write_all(&contents).map_err(export_error)?;
+    }
+    Ok(zip.finish().map_err(export_error)?.into_inner())
+}
+
+/// SNCF stops are kept under their UIC code, the timetables and real-time feeds name them by
+/// their GTFS stop_id
+fn stop_key(prefix: &str, raw: &str) -> String {
+    match prefix {
+        "SNCF" => NVTModels::extract_sncf_stop_id(raw).unwrap_or_else(|| raw.to_string()),
+        _ => raw.to_string(),
+    }
+}
+
+/// GTFS accessibility value: 1 yes, 2 no, empty unknown
+fn accessibility_flag(value: Option<bool>) -> String {
+    match value {
+        Some(true) => "1",
+        Some(false) => "2",
+        None => "",
+    }.to_string()
+}
+
+/// (route_short_name, route_long_name) by route_id, from the line list of a source
+fn route_names(cache: &CachedNetworkData, prefix: &str) -> HashMap<String, (String, String)> {
+    match prefix {
+        "TBM" => cache.tbm_lines_metadata.iter()
+            .filter_map(|(line_ref, name, code, _)| {
+                Some((NVTModels::extract_line_id(line_ref)?.to_string(), (code.clone(), name.clone())))
+            })
+            .collect(),
+        _ => {
+            let lines = match prefix {
+                "NAQ" => &cache.transgironde_lines[..],
+                "SNCF" => &cache.sncf_lines[..],
+                feed => cache.feeds.get(feed).map_or(&[][..], |feed| &feed.lines[..]),
+            };
+            lines.iter()
+                .map(|line| (line.route_id.clone(), (line.line_code.clone(), line.line_name.clone())))
+                .collect()
+        }
+    }
+}
+
+fn add_source(feed: &mut Feed, prefix: &str, gtfs_cache: &GTFSCache, names: &HashMap<String, (String, String)>) {
+    let id = |raw: &str| format!("{}:{}", prefix, raw);
+    let stop_id = |raw: &str| id(&stop_key(prefix, raw));
+    let flag = |value: bool| if value { "1" } else { "0" }.to_string();
+
+    let mut agencies: Vec<_> = gtfs_cache.agencies.values().collect();
+    agencies.sort_by(|a, b| a.agency_id.cmp(&b.agency_id));
+    feed.agency.extend(agencies.iter().map(|agency| vec![
+        id(&agency.agency_id),
+        agency.agency_name.clone(),
+        agency.agency_url.clone(),
+        if agency.agency_timezone.is_empty() { TIMEZONE.to_string() } else { agency.agency_timezone.clone() },
+        agency.agency_phone.clone(),
+    ]));
+
+    // The caches keep routes only by their color and agency, so take them from the trips too
+    let route_ids: BTreeSet<&String> = gtfs_cache.routes.keys()
+        .chain(gtfs_cache.route_agencies.keys())
+        .chain(gtfs_cache.trips.values().map(|trip| &trip.route_id))
+        .collect();
+    // Routes of agencies missing from agency.txt, or of none, get a placeholder agency:
+    // the one they name, or one named after the source
+    let mut placeholders = BTreeSet::new();
+    for route_id in route_ids {
+        let agency_id = gtfs_cache.route_agencies.get(route_id).map_or(prefix, String::as_str);
+        if !gtfs_cache.agencies.contains_key(agency_id) {
+            placeholders.insert(agency_id);
+        }
+        let (short_name, long_name) = names.get(route_id).cloned()
+            .unwrap_or_else(|| (route_id.clone(), String::new()));
+        feed.routes.push(vec![
+            id(route_id),
+            id(agency_id),
+            short_name,
+            long_name,
+            gtfs_cache.route_types.get(route_id)
+                .map_or_else(|| if prefix == "SNCF" { "2" } else { "3" }.to_string(), u16::to_string),
+            gtfs_cache.routes.get(route_id).cloned().unwrap_or_default(),
+            gtfs_cache.route_text_colors.get(route_id).cloned().unwrap_or_default(),
+        ]);
+    }
+    feed.agency.extend(placeholders.into_iter().map(|agency_id| vec![
+        id(agency_id),
+        agency_id.to_string(),
+        String::new(),
+        TIMEZONE.to_string(),
+        String::new(),
+    ]));
+
+    let mut stops: Vec<_> = gtfs_cache.stops.iter().collect();
+    stops.sort_by(|a, b| a.0.cmp(&b.0));
+    feed.stops.extend(stops.iter().map(|(stop_id, name, lat, lon)| vec![
+        id(stop_id),
+        name.clone(),
+        lat.to_string(),
+        lon.to_string(),
+        accessibility_flag(gtfs_cache.accessibility.wheelchair_boarding.get(stop_id).copied()),
+    ]));
+
+    let mut trips: Vec<_> = gtfs_cache.trips.values().collect();
+    trips.sort_by(|a, b| a.trip_id.cmp(&b.trip_id));
+    feed.trips.extend(trips.iter().map(|trip| vec![
+        id(&trip.route_id),
+        id(&trip.service_id),
+        id(&trip.trip_id),
+        trip.trip_headsign.clone().unwrap_or_default(),
+        trip.direction_id.map(|d| d.to_string()).unwrap_or_default(),
+        accessibility_flag(trip.wheelchair_accessible),
+        accessibility_flag(trip.bikes_allowed),
+    ]));
+
+    let mut stop_times: Vec<_> = gtfs_cache.stop_times.values().flatten().collect();
+    stop_times.sort_by(|a, b| (&a.trip_id, a.stop_sequence).cmp(&(&b.trip_id, b.stop_sequence)));
+    feed.stop_times.extend(stop_times.iter().map(|stop_time| vec![
+        id(&stop_time.trip_id),
+        stop_time.arrival_time.clone(),
+        stop_time.departure_time.clone(),
+        stop_id(&stop_time.stop_id),
+        stop_time.stop_sequence.to_string(),
+        stop_time.stop_headsign.clone().unwrap_or_default(),
+    ]));
+
+    let mut calendar: Vec<_> = gtfs_cache.calendar.values().collect();
+    calendar.sort_by(|a, b| a.service_id.cmp(&b.service_id));
+    feed.calendar.extend(calendar.iter().map(|service| vec![
+        id(&service.service_id),
+        flag(service.monday),
+        flag(service.tuesday),
+        flag(service.wednesday),
+        flag(service.thursday),
+        flag(service.friday),
+        flag(service.saturday),
+        flag(service.sunday),
+        service.start_date.clone(),
+        service.end_date.clone(),
+    ]));
+
+    let mut calendar_dates: Vec<_> = gtfs_cache.calendar_dates.values().flatten().collect();
+    calendar_dates.sort_by(|a, b| (&a.service_id, &a.date).cmp(&(&b.service_id, &b.date)));
+    feed.calendar_dates.extend(calendar_dates.iter().map(|date| vec![
+        id(&date.service_id),
+        date.date.clone(),
+        date.exception_type.to_string(),
+    ]));
+
+    feed.transfers.extend(gtfs_cache.transfers.iter().map(|transfer| vec![
+        stop_id(&transfer.from_stop_id),
+        stop_id(&transfer.to_stop_id),
+        transfer.transfer_type.to_string(),
+        transfer.min_transfer_time.map(|t| t.to_string()).unwrap_or_default(),
+    ]));
+
+    let mut frequencies: Vec<_> = gtfs_cache.frequencies.iter()
+        .flat_map(|(trip_id, windows)| windows.iter().map(move |window| (trip_id, window)))
+        .collect();
+    frequencies.sort_by(|a, b| (a.0, &a.1.start_time).cmp(&(b.0, &b.1.start_time)));
+    feed.frequencies.extend(frequencies.iter().map(|(trip_id, window)| vec![
+        id(trip_id),
+        window.start_time.clone(),
+        window.end_time.clone(),
+        window.headway_secs.to_string(),
+        flag(window.exact_times),
+    ]));
+}
+
+/// The ids of the merged feed for ids of the source feeds, for the real-time feeds that go
+/// with it (see gtfs_rt_feed.rs). An id is taken as one of the first source, in gtfs_caches
+/// order, that has it; ids no source has are kept as they are.
+pub struct Namespaces<'a> {
+    sources: Vec<(&'a str, &'a GTFSCache, HashSet<&'a str>)>,
+}
+
+impl<'a> Namespaces<'a> {
+    pub fn new(cache: &'a CachedNetworkData) -> Self {
+        let sources = prefixed_sources(cache).into_iter()
+            .map(|(prefix, gtfs_cache)| (prefix, gtfs_cache, gtfs_cache.stops.iter().map(|stop| stop.0.as_str()).collect()))
+            .collect();
+        Namespaces { sources }
+    }
+
+    pub fn trip(&self, trip_id: &str) -> String {
+        self.sources.iter()
+            .find(|(_, gtfs_cache, _)| gtfs_cache.trips.contains_key(trip_id))
+            .map_or_else(|| trip_id.to_string(), |(prefix, _, _)| format!("{}:{}", prefix, trip_id))
+    }
+
+    pub fn route(&self, route_id: &str) -> String {
+        self.sources.iter()
+            .find(|(_, gtfs_cache, _)| gtfs_cache.routes.contains_key(route_id) || gtfs_cache.route_agencies.contains_key(route_id))
+            .map_or_else(|| route_id.to_string(), |(prefix, _, _)| format!("{}:{}", prefix, route_id))
+    }
+
+    pub fn stop(&self, stop_id: &str) -> String {
+        self.sources.iter()
+            .map(|(prefix, _, stops)| (prefix, stops, stop_key(prefix, stop_id)))
+            .find(|(_, stops, key)| stops.contains(key.as_str()))
+            .map_or_else(|| stop_id.to_string(), |(prefix, _, key)| format!("{}:{}", prefix, key))
+    }
+}
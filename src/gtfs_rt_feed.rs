@@ -0,0 +1,151 @@
+// Merged GTFS-RT output (GET /api/tbm/gtfs-rt/vehicles, /alerts and /trip-updates)
+// The cached real-time data of every source encoded back as GTFS-RT FeedMessages, so that any
+// GTFS-RT consumer can read the aggregate and not only the web UI. Trip, route and stop ids are
+// those of the merged GTFS export (GET /api/tbm/export/gtfs.zip, see gtfs_export.rs), which the
+// three feeds go with. Each is a full dataset stamped with the time of the last refresh; what
+// the caches do not keep (alert causes and effects) is left out.
+
+use gtfs_rt::feed_header::Incrementality;
+use gtfs_rt::translated_string::Translation;
+use gtfs_rt::vehicle_position::OccupancyStatus;
+use gtfs_rt::{
+    Alert, EntitySelector, FeedEntity, FeedHeader, FeedMessage, Position, TimeRange, TranslatedString, TripDescriptor,
+    TripUpdate, VehicleDescriptor, VehiclePosition,
+};
+use prost::Message;
+
+use crate::gtfs_export::Namespaces;
+use crate::tbm_api_models::{AlertInfo, AlertTranslation, CachedNetworkData, RealTimeInfo};
+
+pub const CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// Id parse_vehicle_positions gives vehicles whose feed entry names no trip
+const UNKNOWN_TRIP: &str = "Unknown";
+
+fn feed_message(cache: &CachedNetworkData, entity: Vec<FeedEntity>) -> Vec<u8> {
+    FeedMessage {
+        header: FeedHeader {
+            gtfs_realtime_version: "2.0".to_string(),
+            incrementality: Some(Incrementality::FullDataset as i32),
+            timestamp: Some(cache.last_dynamic_update),
+        },
+        entity,
+    }.encode_to_vec()
+}
+
+fn text(value: &str) -> Option<TranslatedString> {
+    Some(TranslatedString {
+        translation: vec![Translation { text: value.to_string(), language: None }],
+    })
+}
+
+pub fn vehicles(cache: &CachedNetworkData) -> Vec<u8> {
+    let ids = Namespaces::new(cache);
+    let entity = cache.real_time.iter()
+        .map(|vehicle| FeedEntity {
+            id: vehicle.vehicle_id.clone(),
+            vehicle: Some(vehicle_position(&ids, vehicle)),
+            ..Default::default()
+        })
+        .collect();
+    feed_message(cache, entity)
+}
+
+fn vehicle_position(ids: &Namespaces, vehicle: &RealTimeInfo) -> VehiclePosition {
+    let trip = (vehicle.trip_id != UNKNOWN_TRIP).then(|| TripDescriptor {
+        trip_id: Some(ids.trip(&vehicle.trip_id)),
+        route_id: vehicle.route_id.as_deref().map(|route_id| ids.route(route_id)),
+        direction_id: vehicle.direction_id,
+        ..Default::default()
+    });
+    let position = (vehicle.latitude != 0.0 || vehicle.longitude != 0.0).then(|| Position {
+        latitude: vehicle.latitude as f32,
+        longitude: vehicle.longitude as f32,
+        bearing: vehicle.bearing,
+        ..Default::default()
+    });
+
+    VehiclePosition {
+        trip,
+        vehicle: Some(VehicleDescriptor {
+            id: Some(vehicle.vehicle_id.clone()),
+            label: vehicle.destination.clone(),
+            ..Default::default()
+        }),
+        position,
+        stop_id: vehicle.stop_id.as_deref().map(|stop_id| ids.stop(stop_id)),
+        current_stop_sequence: vehicle.current_stop_sequence,
+        timestamp: vehicle.timestamp.map(|timestamp| timestamp as u64),
+        occupancy_status: vehicle.occupancy.as_deref()
+            .and_then(OccupancyStatus::from_str_name)
+            .map(|status| status as i32),
+        ..Default::default()
+    }
+}
+
+pub fn trip_updates(cache: &CachedNetworkData) -> Vec<u8> {
+    let ids = Namespaces::new(cache);
+    let entity = cache.trip_updates.iter()
+        .enumerate()
+        .map(|(index, update)| {
+            let mut update: TripUpdate = update.clone();
+            update.trip.trip_id = update.trip.trip_id.as_deref().map(|trip_id| ids.trip(trip_id));
+            update.trip.route_id = update.trip.route_id.as_deref().map(|route_id| ids.route(route_id));
+            for stop_time in &mut update.stop_time_update {
+                stop_time.stop_id = stop_time.stop_id.as_deref().map(|stop_id| ids.stop(stop_id));
+            }
+            FeedEntity {
+                id: update.trip.trip_id.clone().unwrap_or_else(|| format!("trip-update-{}", index)),
+                trip_update: Some(update),
+                ..Default::default()
+            }
+        })
+        .collect();
+    feed_message(cache, entity)
+}
+
+pub fn alerts(cache: &CachedNetworkData) -> Vec<u8> {
+    let ids = Namespaces::new(cache);
+    let entity = cache.alerts.iter()
+        .map(|alert| FeedEntity {
+            id: alert.id.clone(),
+            alert: Some(alert_message(&ids, alert)),
+            ..Default::default()
+        })
+        .collect();
+    feed_message(cache, entity)
+}
+
+/// Every translation the feed gave of a text of an alert, else `value` in no language
+fn translated(
+    alert: &AlertInfo,
+    value: Option<&str>,
+    field: impl Fn(&AlertTranslation) -> Option<&String>,
+) -> Option<TranslatedString> {
+    let translation: Vec<Translation> = alert.translations.iter()
+        .filter_map(|t| field(t).map(|text| Translation { text: text.clone(), language: Some(t.language.clone()) }))
+        .collect();
+    if translation.is_empty() {
+        return value.and_then(text);
+    }
+    Some(TranslatedString { translation })
+}
+
+fn alert_message(ids: &Namespaces, alert: &AlertInfo) -> Alert {
+    let routes = alert.route_ids.iter()
+        .map(|route_id| EntitySelector { route_id: Some(ids.route(route_id)), ..Default::default() });
+    let stops = alert.stop_ids.iter()
+        .map(|stop_id| EntitySelector { stop_id: Some(ids.stop(stop_id)), ..Default::default() });
+
+    Alert {
+        active_period: alert.active_periods.iter()
+            .map(|(start, end)| TimeRange { start: start.map(|s| s as u64), end: end.map(|e| e as u64) })
+            .collect(),
+        informed_entity: routes.chain(stops).collect(),
+        url: translated(alert, alert.url.as_deref(), |t| t.url.as_ref()),
+        header_text: translated(alert, Some(&alert.text), |t| t.text.as_ref()),
+        description_text: translated(alert, Some(&alert.description), |t| t.description.as_ref()),
+        severity_level: (alert.severity != 0).then_some(alert.severity as i32),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,170 @@
+// Headways of each line (GET /api/tbm/line/{code}/frequency)
+// At each static load, the departures of every route from the first stop of its trips are taken
+// on three representative days, the first Tuesday, Saturday and Sunday from the load on, and the
+// gaps between them averaged over the day and over time bands, so that clients can show "every
+// ~10 min" without fetching the timetable. Directions are apart; trips without a direction_id
+// count as direction 0. A day or band with fewer than two departures has no headway.
+
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::tbm_api_models::{CachedNetworkData, NVTModels};
+
+/// (name, start hour, end hour) of the time bands; GTFS service days run past midnight
+const BANDS: [(&str, u32, u32); 6] = [
+    ("early", 0, 7),
+    ("morning_peak", 7, 9),
+    ("day", 9, 16),
+    ("evening_peak", 16, 19),
+    ("evening", 19, 24),
+    ("night", 24, 30),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DayType {
+    Weekday,
+    Saturday,
+    Sunday,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BandHeadway {
+    pub band: &'static str,
+    pub start: String, // HH:MM
+    pub end: String,
+    pub departures: usize,
+    pub average_headway_min: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Headways {
+    pub direction_id: u32,
+    pub day_type: DayType,
+    pub date: String, // YYYY-MM-DD, the day the departures were taken on
+    pub departures: usize,
+    pub first_departure: Option<String>, // GTFS HH:MM:SS
+    pub last_departure: Option<String>,
+    pub average_headway_min: Option<f64>, // Over the service span
+    pub peak_headway_min: Option<f64>, // Shortest band average
+    pub bands: Vec<BandHeadway>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HeadwayIndex {
+    routes: HashMap<String, Vec<Headways>>, // key: route_id
+}
+
+/// The first Tuesday, Saturday and Sunday on or after `from`
+fn representative_days(from: NaiveDate) -> [(DayType, NaiveDate); 3] {
+    let next = |weekday: Weekday| {
+        let offset = (7 + weekday.num_days_from_monday() - from.weekday().num_days_from_monday()) % 7;
+        from + Duration::days(i64::from(offset))
+    };
+    [
+        (DayType::Weekday, next(Weekday::Tue)),
+        (DayType::Saturday, next(Weekday::Sat)),
+        (DayType::Sunday, next(Weekday::Sun)),
+    ]
+}
+
+fn gtfs_time(seconds: u32) -> String {
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+/// Mean gap between sorted departures, in minutes to a tenth
+fn average_headway(departures: &[u32]) -> Option<f64> {
+    let (first, last) = (departures.first()?, departures.last()?);
+    (departures.len() >= 2).then(|| {
+        let minutes = f64::from(last - first) / 60.0 / (departures.len() - 1) as f64;
+        (minutes * 10.0).round() / 10.0
+    })
+}
+
+fn headways(direction_id: u32, day_type: DayType, date: NaiveDate, departures: &[u32]) -> Headways {
+    let bands: Vec<BandHeadway> = BANDS.iter()
+        .map(|&(band, start, end)| {
+            let within: Vec<u32> = departures.iter().copied()
+                .filter(|&departure| (start * 3600..end * 3600).contains(&departure))
+                .collect();
+            BandHeadway {
+                band,
+                start: format!("{:02}:00", start),
+                end: format!("{:02}:00", end),
+                departures: within.len(),
+                average_headway_min: average_headway(&within),
+            }
+        })
+        .collect();
+
+    Headways {
+        direction_id,
+        day_type,
+        date: date.format("%Y-%m-%d").to_string(),
+        departures: departures.len(),
+        first_departure: departures.first().copied().map(gtfs_time),
+        last_departure: departures.last().copied().map(gtfs_time),
+        average_headway_min: average_headway(departures),
+        peak_headway_min: bands.iter().filter_map(|band| band.average_headway_min).min_by(f64::total_cmp),
+        bands,
+    }
+}
+
+impl HeadwayIndex {
+    /// Headways of every route on the representative days from `today` on
+    pub fn build(cache: &CachedNetworkData, today: NaiveDate) -> Self {
+        let days = representative_days(today);
+        let mut routes: HashMap<String, Vec<Headways>> = HashMap::new();
+
+        for gtfs_cache in cache.gtfs_caches() {
+            // Departure of each trip from its first stop
+            let mut first_stops: HashMap<&str, (u32, u32)> = HashMap::new(); // key: trip_id, value: (stop_sequence, departure)
+            for stop_time in gtfs_cache.stop_times.values().flatten() {
+                let time = if stop_time.departure_time.is_empty() { &stop_time.arrival_time } else { &stop_time.departure_time };
+                let Some(departure) = NVTModels::parse_gtfs_time(time) else { continue };
+                let first = first_stops.entry(&stop_time.trip_id).or_insert((stop_time.stop_sequence, departure));
+                if stop_time.stop_sequence < first.0 {
+                    *first = (stop_time.stop_sequence, departure);
+                }
+            }
+
+            let mut departures: HashMap<(&str, u32), Vec<(&str, u32)>> = HashMap::new(); // key: (route_id, direction), value: (service_id, departure)
+            for (trip_id, (_, departure)) in first_stops {
+                if let Some(trip) = gtfs_cache.trips.get(trip_id) {
+                    departures.entry((&trip.route_id, trip.direction_id.unwrap_or(0)))
+                        .or_default()
+                        .push((&trip.service_id, departure));
+                }
+            }
+
+            let mut services: HashMap<(&str, NaiveDate), bool> = HashMap::new();
+            let mut source_routes: HashMap<String, Vec<Headways>> = HashMap::new();
+            for ((route_id, direction_id), trips) in departures {
+                for (day_type, date) in days {
+                    let mut times: Vec<u32> = trips.iter()
+                        .filter(|(service_id, _)| *services.entry((service_id, date))
+                            .or_insert_with(|| NVTModels::service_runs(gtfs_cache, service_id, date)))
+                        .map(|(_, departure)| *departure)
+                        .collect();
+                    times.sort_unstable();
+                    source_routes.entry(route_id.to_string()).or_default()
+                        .push(headways(direction_id, day_type, date, &times));
+                }
+            }
+
+            // Route ids of the first source that has them, as for route_text_color
+            for (route_id, mut headways) in source_routes {
+                headways.sort_by_key(|h| (h.direction_id, h.day_type as u8));
+                routes.entry(route_id).or_insert(headways);
+            }
+        }
+
+        HeadwayIndex { routes }
+    }
+
+    /// Headways of a route, by direction then day type
+    pub fn route(&self, route_id: &str) -> &[Headways] {
+        self.routes.get(route_id).map_or(&[], Vec::as_slice)
+    }
+}
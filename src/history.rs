@@ -0,0 +1,433 @@
+// History store
+// Keeps data that the live feeds forget: every alert seen is archived with its active period
+// and first/last sighting, so past disruptions can be queried by time range and route, or
+// exported as CSV for analysis. Persisted as JSON in the cache directory (tbm_nvt/history.json).
+// Vehicle counts and delays per line are sampled at each refresh for the Grafana endpoints;
+// those samples cover the last MAX_SAMPLES refreshes and are kept in memory only.
+// The delays of each trip (trip updates) are followed while it runs (saved with the alerts) and appended to
+// tbm_nvt/delays.csv once it leaves the feed, so the archive is never rewritten at a refresh.
+// Alerts and delays export as CSV, JSON or Parquet (parquet.rs).
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::parquet::{self, Column};
+use crate::tbm_api_models::{AlertInfo, Line, NVTError, NVTModels, NetworkData, Result};
+
+const MAX_SAMPLES: usize = 2880; // 24 hours at the 30 s refresh interval
+const TRIP_END_AFTER_SECS: i64 = 600; // A trip out of the feed this long has ended
+
+// ============================================================================
+// Data Structures
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedAlert {
+    pub id: String,
+    pub text: String,
+    pub description: String,
+    pub url: Option<String>,
+    pub route_ids: Vec<String>,
+    pub stop_ids: Vec<String>,
+    pub severity: u32,
+    pub active_period_start: Option<i64>,
+    pub active_period_end: Option<i64>,
+    pub first_seen: i64,
+    pub last_seen: i64,
+}
+
+impl ArchivedAlert {
+    /// Period the alert applied: the feed's active period, or when we saw it in the feed
+    pub fn effective_period(&self) -> (i64, i64) {
+        (
+            self.active_period_start.unwrap_or(self.first_seen),
+            self.active_period_end.unwrap_or(self.last_seen),
+        )
+    }
+}
+
+/// Real-time activity of one line at a refresh
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LineSample {
+    pub vehicles: u32,
+    pub delay_total: i64, // seconds, over the vehicles reporting a delay
+    pub delayed_vehicles: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct MetricsSample {
+    pub timestamp: i64,
+    pub lines: BTreeMap<String, LineSample>, // key: "<operator>/<line code>"
+}
+
+/// Delays reported for one run of a trip, from the refreshes that saw it in the feed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchivedDelay {
+    pub operator: String,
+    pub line_code: String,
+    pub trip_id: String,
+    pub vehicle_id: String,
+    pub first_seen: i64,
+    pub last_seen: i64,
+    pub samples: u32,    // refreshes reporting a delay
+    pub mean_delay: f64, // seconds
+    pub max_delay: i32,
+    pub last_delay: i32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HistoryStore {
+    pub alerts: HashMap<String, ArchivedAlert>, // key: alert id
+    #[serde(default)]
+    pub trips: HashMap<String, ArchivedDelay>, // running trips, key: "<operator>/<trip id>"
+    #[serde(skip)]
+    pub samples: VecDeque<MetricsSample>, // oldest first
+    #[serde(skip)]
+    pub delays_path: Option<PathBuf>, // archive of ended trips; None keeps them nowhere (tests)
+    #[serde(skip)]
+    delays_cutoff: i64, // retention cutoff of the last pruning of the archive
+}
+
+// ============================================================================
+// Store Implementation
+// ============================================================================
+
+impl HistoryStore {
+    pub fn store_path() -> PathBuf {
+        Self::cache_file("history.json")
+    }
+
+    pub fn delays_store_path() -> PathBuf {
+        Self::cache_file("delays.csv")
+    }
+
+    fn cache_file(name: &str) -> PathBuf {
+        let mut path = dirs::cache_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("tbm_nvt");
+        fs::create_dir_all(&path).ok();
+        path.push(name);
+        path
+    }
+
+    pub fn load() -> Self {
+        let mut store = match fs::read_to_string(Self::store_path()) {
+            Ok(contents) => serde_json::from_str::<HistoryStore>(&contents).unwrap_or_else(|e| {
+                println!("⚠️  Failed to parse history ({}), starting empty", e);
+                HistoryStore::default()
+            }),
+            Err(_) => HistoryStore::default(),
+        };
+        store.delays_path = Some(Self::delays_store_path());
+
+        println!("✓ Loaded history: {} archived alerts", store.alerts.len());
+        store
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let json = serde_json::to_string(self)
+            .map_err(|e| NVTError::FileError(format!("Failed to serialize history: {}", e)))?;
+
+        fs::write(Self::store_path(), json)
+            .map_err(|e| NVTError::FileError(format!("Failed to write history: {}", e)))
+    }
+
+    /// Archive the alerts currently in the feed, extending the sighting of known ones
+    pub fn record_alerts(&mut self, alerts: &[AlertInfo]) {
+        let now = NVTModels::get_current_timestamp();
+
+        for alert in alerts {
+            let entry = self.alerts.entry(alert.id.clone()).or_insert_with(|| ArchivedAlert {
+                id: alert.id.clone(),
+                text: alert.text.clone(),
+                description: alert.description.clone(),
+                url: alert.url.clone(),
+                route_ids: Vec::new(),
+                stop_ids: Vec::new(),
+                severity: alert.severity,
+                active_period_start: alert.active_period_start,
+                active_period_end: alert.active_period_end,
+                first_seen: now,
+                last_seen: now,
+            });
+
+            // Feeds update wording and periods while an alert is live; keep the latest
+            entry.text = alert.text.clone();
+            entry.description = alert.description.clone();
+            entry.url = alert.url.clone();
+            entry.severity = alert.severity;
+            entry.active_period_start = alert.active_period_start.or(entry.active_period_start);
+            entry.active_period_end = alert.active_period_end.or(entry.active_period_end);
+            entry.last_seen = now;
+
+            for route_id in &alert.route_ids {
+                if !entry.route_ids.contains(route_id) {
+                    entry.route_ids.push(route_id.clone());
+                }
+            }
+            for stop_id in &alert.stop_ids {
+                if !entry.stop_ids.contains(stop_id) {
+                    entry.stop_ids.push(stop_id.clone());
+                }
+            }
+        }
+    }
+
+    /// Sample the vehicles and delays of each line, dropping the oldest sample past MAX_SAMPLES
+    pub fn record_sample(&mut self, network: &NetworkData) {
+        let mut lines: BTreeMap<String, LineSample> = BTreeMap::new();
+        for line in network.lines.iter().filter(|line| !line.real_time.is_empty()) {
+            let sample = lines.entry(format!("{}/{}", line.operator, line.line_code)).or_default();
+            for vehicle in &line.real_time {
+                sample.vehicles += 1;
+                if let Some(delay) = vehicle.delay {
+                    sample.delay_total += i64::from(delay);
+                    sample.delayed_vehicles += 1;
+                }
+            }
+        }
+
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(MetricsSample { timestamp: NVTModels::get_current_timestamp(), lines });
+    }
+
+    /// Follow the delays of the trips in the feeds (trip updates, and vehicles reporting a delay),
+    /// returning the trips that ended since the last refresh (out of the feeds for
+    /// TRIP_END_AFTER_SECS), for archive_delays
+    pub fn record_delays(&mut self, network: &NetworkData, trip_updates: &[gtfs_rt::TripUpdate]) -> Vec<ArchivedDelay> {
+        let now = NVTModels::get_current_timestamp();
+
+        // (line, trip, vehicle, delay); a trip's delay is its own, or that of its next stop
+        let mut observed: Vec<(&Line, &str, &str, i32)> = Vec::new();
+        for update in trip_updates {
+            let (Some(trip_id), Some(route_id)) = (update.trip.trip_id.as_deref(), update.trip.route_id.as_deref()) else { continue };
+            let next_stop = update.stop_time_update.first()
+                .and_then(|stop_time| stop_time.departure.as_ref().or(stop_time.arrival.as_ref()))
+                .and_then(|event| event.delay);
+            let Some(delay) = update.delay.or(next_stop) else { continue };
+            let Some(line) = network.lines.iter().find(|line| line.route_id == route_id) else { continue };
+            let vehicle_id = update.vehicle.as_ref().and_then(|vehicle| vehicle.id.as_deref())
+                .or_else(|| line.real_time.iter().find(|vehicle| vehicle.trip_id == trip_id).map(|vehicle| vehicle.vehicle_id.as_str()))
+                .unwrap_or_default();
+            observed.push((line, trip_id, vehicle_id, delay));
+        }
+        for line in &network.lines {
+            for vehicle in line.real_time.iter().filter(|vehicle| !vehicle.trip_id.is_empty()) {
+                let Some(delay) = vehicle.delay else { continue };
+                if !observed.iter().any(|(_, trip_id, _, _)| *trip_id == vehicle.trip_id) {
+                    observed.push((line, &vehicle.trip_id, &vehicle.vehicle_id, delay));
+                }
+            }
+        }
+
+        for (line, trip_id, vehicle_id, delay) in observed {
+            let trip = self.trips.entry(format!("{}/{}", line.operator, trip_id))
+                .or_insert_with(|| ArchivedDelay {
+                    operator: line.operator.clone(),
+                    line_code: line.line_code.clone(),
+                    trip_id: trip_id.to_string(),
+                    vehicle_id: vehicle_id.to_string(),
+                    first_seen: now,
+                    last_seen: now,
+                    samples: 0,
+                    mean_delay: 0.0,
+                    max_delay: delay,
+                    last_delay: delay,
+                });
+
+            trip.samples += 1;
+            trip.mean_delay += (f64::from(delay) - trip.mean_delay) / f64::from(trip.samples);
+            trip.max_delay = trip.max_delay.max(delay);
+            trip.last_delay = delay;
+            trip.last_seen = now;
+            if trip.vehicle_id.is_empty() {
+                trip.vehicle_id = vehicle_id.to_string();
+            }
+        }
+
+        let ended: Vec<String> = self.trips.iter()
+            .filter(|(_, trip)| now - trip.last_seen >= TRIP_END_AFTER_SECS)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut ended: Vec<ArchivedDelay> = ended.iter().filter_map(|key| self.trips.remove(key)).collect();
+        ended.sort_by_key(|trip| trip.last_seen);
+        ended
+    }
+
+    /// Append ended trips to the delay archive, writing its header when the file is new
+    pub fn archive_delays(&self, ended: &[ArchivedDelay]) -> Result<()> {
+        let Some(path) = &self.delays_path else { return Ok(()) };
+        if ended.is_empty() {
+            return Ok(());
+        }
+
+        let file = fs::OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|e| NVTError::FileError(format!("Failed to open delay archive: {}", e)))?;
+        let is_new = file.metadata().map(|metadata| metadata.len() == 0).unwrap_or(true);
+        let mut writer = csv::WriterBuilder::new().has_headers(is_new).from_writer(file);
+        for trip in ended {
+            writer.serialize(trip)
+                .map_err(|e| NVTError::FileError(format!("Failed to write delay archive: {}", e)))?;
+        }
+        writer.flush().map_err(|e| NVTError::FileError(format!("Failed to write delay archive: {}", e)))
+    }
+
+    /// Drop the archived trips that ended more than `retention_days` ago, returning how many. The
+    /// cutoff moves by whole days (UTC), so the archive is rewritten at most once a day
+    pub fn prune_delays(&mut self, retention_days: u64) -> Result<usize> {
+        let Some(path) = self.delays_path.clone() else { return Ok(0) };
+        if retention_days == 0 {
+            return Ok(0);
+        }
+        let cutoff = NVTModels::get_current_timestamp() - (retention_days * 86_400) as i64;
+        let cutoff = cutoff - cutoff.rem_euclid(86_400);
+        if cutoff <= self.delays_cutoff {
+            return Ok(0);
+        }
+        self.delays_cutoff = cutoff;
+
+        let archived = read_delays(&path);
+        let kept: Vec<&ArchivedDelay> = archived.iter().filter(|trip| trip.last_seen >= cutoff).collect();
+        if kept.len() == archived.len() {
+            return Ok(0);
+        }
+
+        let temporary = path.with_extension("csv.tmp");
+        fs::write(&temporary, Self::delays_csv(&kept)?)
+            .and_then(|_| fs::rename(&temporary, &path))
+            .map_err(|e| NVTError::FileError(format!("Failed to rewrite delay archive: {}", e)))?;
+        Ok(archived.len() - kept.len())
+    }
+
+    /// Trips overlapping [from, to), from the archive and the running ones, oldest first. Takes
+    /// the archive path and running trips rather than the store, so the file is read unlocked
+    pub fn query_delays(archive: Option<&Path>, running: Vec<ArchivedDelay>, from: Option<i64>, to: Option<i64>) -> Vec<ArchivedDelay> {
+        let mut results: Vec<ArchivedDelay> = archive.map(read_delays).unwrap_or_default()
+            .into_iter()
+            .chain(running)
+            .filter(|trip| from.is_none_or(|f| trip.last_seen >= f) && to.is_none_or(|t| trip.first_seen < t))
+            .collect();
+
+        results.sort_by(|a, b| a.first_seen.cmp(&b.first_seen).then_with(|| a.trip_id.cmp(&b.trip_id)));
+        results
+    }
+
+    /// Drop the alerts whose period ended more than `retention_days` ago, returning how many
+    pub fn prune(&mut self, retention_days: u64) -> usize {
+        if retention_days == 0 {
+            return 0;
+        }
+        let cutoff = NVTModels::get_current_timestamp() - (retention_days * 86_400) as i64;
+        let before = self.alerts.len();
+        self.alerts.retain(|_, alert| alert.effective_period().1 >= cutoff);
+        before - self.alerts.len()
+    }
+
+    /// Archived alerts overlapping [from, to), optionally limited to some routes, oldest first
+    pub fn query_alerts(&self, from: Option<i64>, to: Option<i64>, route_ids: Option<&[String]>) -> Vec<&ArchivedAlert> {
+        let mut results: Vec<&ArchivedAlert> = self.alerts.values()
+            .filter(|alert| {
+                let (start, end) = alert.effective_period();
+                from.is_none_or(|f| end >= f) && to.is_none_or(|t| start < t)
+            })
+            .filter(|alert| {
+                route_ids.is_none_or(|routes| alert.route_ids.iter().any(|r| routes.contains(r)))
+            })
+            .collect();
+
+        results.sort_by_key(|alert| alert.effective_period().0);
+        results
+    }
+
+    /// CSV of archived alerts, one row each; route and stop ids are joined with ';'
+    pub fn alerts_csv(alerts: &[&ArchivedAlert]) -> Result<String> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let to_csv_error = |e: csv::Error| NVTError::ParseError(format!("Failed to write CSV: {}", e));
+        let optional = |value: Option<i64>| value.map(|v| v.to_string()).unwrap_or_default();
+
+        writer.write_record([
+            "id", "text", "description", "url", "route_ids", "stop_ids", "severity",
+            "active_period_start", "active_period_end", "first_seen", "last_seen",
+        ]).map_err(to_csv_error)?;
+        for alert in alerts {
+            writer.write_record([
+                alert.id.clone(),
+                alert.text.clone(),
+                alert.description.clone(),
+                alert.url.clone().unwrap_or_default(),
+                alert.route_ids.join(";"),
+                alert.stop_ids.join(";"),
+                alert.severity.to_string(),
+                optional(alert.active_period_start),
+                optional(alert.active_period_end),
+                alert.first_seen.to_string(),
+                alert.last_seen.to_string(),
+            ]).map_err(to_csv_error)?;
+        }
+
+        let bytes = writer.into_inner().map_err(|e| NVTError::ParseError(format!("Failed to write CSV: {}", e)))?;
+        String::from_utf8(bytes).map_err(|e| NVTError::ParseError(format!("Failed to write CSV: {}", e)))
+    }
+
+    /// Parquet file of archived alerts, with the columns of alerts_csv
+    pub fn alerts_parquet(alerts: &[&ArchivedAlert]) -> Vec<u8> {
+        parquet::write(&[
+            Column::utf8("id", alerts.iter().map(|a| a.id.clone())),
+            Column::utf8("text", alerts.iter().map(|a| a.text.clone())),
+            Column::utf8("description", alerts.iter().map(|a| a.description.clone())),
+            Column::optional_utf8("url", alerts.iter().map(|a| a.url.clone())),
+            Column::utf8("route_ids", alerts.iter().map(|a| a.route_ids.join(";"))),
+            Column::utf8("stop_ids", alerts.iter().map(|a| a.stop_ids.join(";"))),
+            Column::int64("severity", alerts.iter().map(|a| i64::from(a.severity))),
+            Column::optional_int64("active_period_start", alerts.iter().map(|a| a.active_period_start)),
+            Column::optional_int64("active_period_end", alerts.iter().map(|a| a.active_period_end)),
+            Column::int64("first_seen", alerts.iter().map(|a| a.first_seen)),
+            Column::int64("last_seen", alerts.iter().map(|a| a.last_seen)),
+        ])
+    }
+
+    /// CSV of trip delays, one row each, in the layout of the archive file
+    pub fn delays_csv(delays: &[&ArchivedDelay]) -> Result<String> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for trip in delays {
+            writer.serialize(trip).map_err(|e| NVTError::ParseError(format!("Failed to write CSV: {}", e)))?;
+        }
+        if delays.is_empty() {
+            writer.write_record([
+                "operator", "line_code", "trip_id", "vehicle_id", "first_seen", "last_seen",
+                "samples", "mean_delay", "max_delay", "last_delay",
+            ]).map_err(|e| NVTError::ParseError(format!("Failed to write CSV: {}", e)))?;
+        }
+
+        let bytes = writer.into_inner().map_err(|e| NVTError::ParseError(format!("Failed to write CSV: {}", e)))?;
+        String::from_utf8(bytes).map_err(|e| NVTError::ParseError(format!("Failed to write CSV: {}", e)))
+    }
+
+    /// Parquet file of trip delays, with the columns of delays_csv
+    pub fn delays_parquet(delays: &[&ArchivedDelay]) -> Vec<u8> {
+        parquet::write(&[
+            Column::utf8("operator", delays.iter().map(|d| d.operator.clone())),
+            Column::utf8("line_code", delays.iter().map(|d| d.line_code.clone())),
+            Column::utf8("trip_id", delays.iter().map(|d| d.trip_id.clone())),
+            Column::utf8("vehicle_id", delays.iter().map(|d| d.vehicle_id.clone())),
+            Column::int64("first_seen", delays.iter().map(|d| d.first_seen)),
+            Column::int64("last_seen", delays.iter().map(|d| d.last_seen)),
+            Column::int64("samples", delays.iter().map(|d| i64::from(d.samples))),
+            Column::double("mean_delay", delays.iter().map(|d| d.mean_delay)),
+            Column::int64("max_delay", delays.iter().map(|d| i64::from(d.max_delay))),
+            Column::int64("last_delay", delays.iter().map(|d| i64::from(d.last_delay))),
+        ])
+    }
+}
+
+/// Rows of the delay archive; a missing file is empty and unreadable rows (a write cut short) are
+/// skipped
+fn read_delays(path: &Path) -> Vec<ArchivedDelay> {
+    match csv::Reader::from_path(path) {
+        Ok(mut reader) => reader.deserialize().filter_map(|row| row.ok()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
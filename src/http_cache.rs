@@ -0,0 +1,129 @@
+// HTTP caching of API responses (Cache-Control, Last-Modified, If-Modified-Since)
+// Browsers and CDNs may reuse the responses of real-time endpoints for a few seconds and those of
+// static endpoints for an hour (see HttpCacheConfig). Last-Modified is the refresh a response was
+// built from, the last real-time refresh or the last static load, so that conditional requests
+// get 304 Not Modified until the next one. Endpoints reading the clock (departures, timetables)
+// or carrying the alerts and vehicles of lines (lines, line, operator) count as real-time and
+// are never staler than one refresh. Admin, health, POST and streaming
+// endpoints are left alone.
+
+use actix_web::http::header::{HeaderMap, HttpDate, IF_MODIFIED_SINCE, IF_NONE_MATCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::HttpCacheConfig;
+use crate::tbm_api_models::CachedNetworkData;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheClass {
+    RealTime,
+    Static,
+}
+
+/// GET routes by class, as registered; "{...}" segments match any value
+const ROUTES: &[(&str, CacheClass)] = &[
+    ("/api/tbm/network", CacheClass::RealTime),
+    ("/api/tbm/stops", CacheClass::RealTime),
+    ("/api/tbm/stops/nearby", CacheClass::RealTime),
+    ("/api/tbm/around", CacheClass::RealTime),
+    ("/api/tbm/vehicles", CacheClass::RealTime),
+    ("/api/tbm/bikes", CacheClass::RealTime),
+    ("/api/tbm/bikes/{station_id}", CacheClass::RealTime),
+    ("/api/tbm/alerts", CacheClass::RealTime),
+    ("/api/tbm/stop/{id}", CacheClass::RealTime),
+    ("/api/tbm/stop/{id}/schedule", CacheClass::RealTime),
+    ("/api/tbm/stop/{id}/arrivals", CacheClass::RealTime),
+    ("/api/tbm/stop/{id}/departures", CacheClass::RealTime),
+    ("/api/tbm/stop/{id}/departures.txt", CacheClass::RealTime),
+    ("/api/tbm/vehicle/{id}", CacheClass::RealTime),
+    ("/api/tbm/trip/{id}", CacheClass::RealTime),
+    ("/api/tbm/lines", CacheClass::RealTime),
+    ("/api/tbm/line/{code}", CacheClass::RealTime),
+    ("/api/tbm/line/{code}/timetable", CacheClass::RealTime),
+    ("/api/tbm/line/{code}/vehicles.geojson", CacheClass::RealTime),
+    ("/api/tbm/operator/{name}", CacheClass::RealTime),
+    ("/api/tbm/kiosk/{config_id}", CacheClass::RealTime),
+    ("/api/tbm/gtfs-rt/vehicles", CacheClass::RealTime),
+    ("/api/tbm/gtfs-rt/alerts", CacheClass::RealTime),
+    ("/api/tbm/gtfs-rt/trip-updates", CacheClass::RealTime),
+    ("/api/siri/2.0/stop-monitoring.json", CacheClass::RealTime),
+    ("/api/tbm/stops/clusters", CacheClass::Static),
+    ("/api/tbm/search", CacheClass::Static),
+    ("/api/tbm/shapes", CacheClass::Static),
+    ("/tiles/{z}/{x}/{y}.mvt", CacheClass::Static),
+    ("/api/tbm/stop/{id}/qr.svg", CacheClass::Static),
+    ("/api/tbm/line/{code}/badge.svg", CacheClass::Static),
+    ("/api/tbm/line/{code}/frequency", CacheClass::Static),
+    ("/api/tbm/line/{code}/stops", CacheClass::Static),
+    ("/api/tbm/line/{code}/shape", CacheClass::Static),
+    ("/api/tbm/line/{code}/map.png", CacheClass::Static),
+    ("/api/tbm/line/{code}/elevation", CacheClass::Static),
+    ("/api/tbm/operators", CacheClass::Static),
+    ("/api/tbm/modes", CacheClass::Static),
+    ("/api/tbm/agencies", CacheClass::Static),
+    ("/api/tbm/agency/{id}/lines", CacheClass::Static),
+    ("/api/tbm/export/gtfs.zip", CacheClass::Static),
+];
+
+/// Whether a path segment fits a route segment; "{y}.mvt" takes any segment ending in ".mvt"
+fn segment_matches(route: &str, segment: &str) -> bool {
+    match route.strip_prefix('{').and_then(|rest| rest.split_once('}')) {
+        Some((_, suffix)) => segment.len() > suffix.len() && segment.ends_with(suffix),
+        None => route == segment,
+    }
+}
+
+/// Class of the GET route `path` belongs to, None for routes left uncached
+pub fn classify(path: &str) -> Option<CacheClass> {
+    let segments: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    ROUTES.iter()
+        .find(|(route, _)| {
+            let route_segments: Vec<&str> = route.split('/').collect();
+            route_segments.len() == segments.len()
+                && route_segments.iter().zip(&segments).all(|(route, segment)| segment_matches(route, segment))
+        })
+        .map(|(_, class)| *class)
+}
+
+/// Routes of ROUTES, to check them against the registered ones
+#[cfg(test)]
+pub fn routes() -> impl Iterator<Item = &'static str> {
+    ROUTES.iter().map(|(route, _)| *route)
+}
+
+impl CacheClass {
+    pub fn cache_control(self, config: &HttpCacheConfig) -> String {
+        let max_age = match self {
+            CacheClass::RealTime => config.real_time_max_age_secs,
+            CacheClass::Static => config.static_max_age_secs,
+        };
+        format!("public, max-age={}", max_age)
+    }
+
+    /// Unix time the data of the class last changed, None before it was first loaded
+    pub fn last_modified(self, cache: &CachedNetworkData) -> Option<u64> {
+        let updated = match self {
+            CacheClass::RealTime => cache.last_dynamic_update.max(cache.last_static_update),
+            CacheClass::Static => cache.last_static_update,
+        };
+        (updated > 0).then_some(updated)
+    }
+}
+
+pub fn http_date(unix: u64) -> String {
+    HttpDate::from(UNIX_EPOCH + Duration::from_secs(unix)).to_string()
+}
+
+/// Whether a conditional request already has the data as of `last_modified`. If-None-Match takes
+/// precedence over If-Modified-Since, so requests carrying one are left to the ETag handling.
+pub fn not_modified_since(headers: &HeaderMap, last_modified: u64) -> bool {
+    if headers.contains_key(IF_NONE_MATCH) {
+        return false;
+    }
+    let Some(since) = headers.get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<HttpDate>().ok())
+    else {
+        return false;
+    };
+    SystemTime::from(since) >= UNIX_EPOCH + Duration::from_secs(last_modified)
+}
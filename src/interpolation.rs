@@ -0,0 +1,234 @@
+// Interpolated vehicle positions (GET /api/tbm/vehicles?interpolate=true)
+// The vehicle feeds are refreshed every 30 s and their GPS fixes can be older still, so map
+// markers jump from one fix to the next. A vehicle whose fix is more than STALE_SECS old is
+// projected onto the shape of its route and moved along it by as far as the timetable of its
+// trip, shifted by the trip's delay, says it went since the fix. Routes without shapes are
+// followed stop to stop. Vehicles off their timetable (no stop times, or outside the hours of
+// their trip) keep their fix.
+
+use chrono::{Duration, NaiveDate};
+use chrono_tz::Europe::Paris;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::tbm_api_models::{CachedNetworkData, GTFSCache, InterpolatedPosition, NVTModels, RealTimeInfo, VehicleWithProgress};
+use crate::time_format;
+
+/// Fixes younger than this are shown as they are
+pub const STALE_SECS: i64 = 15;
+
+/// Trips are followed a little before their first departure and after their last arrival
+const SLACK_SECS: i64 = 600;
+
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// Polyline in meters on a plane tangent near the route, with the distance along it of each point
+struct Path {
+    origin: (f64, f64), // (latitude, longitude)
+    points: Vec<(f64, f64)>, // (x, y) meters
+    along: Vec<f64>,
+}
+
+impl Path {
+    fn new(positions: &[(f64, f64)]) -> Option<Self> {
+        let origin = *positions.first()?;
+        let mut path = Path { origin, points: Vec::new(), along: Vec::new() };
+        for &position in positions {
+            let point = path.to_plane(position);
+            let along = match (path.points.last(), path.along.last()) {
+                (Some(previous), Some(distance)) => distance + distance_between(*previous, point),
+                _ => 0.0,
+            };
+            path.points.push(point);
+            path.along.push(along);
+        }
+        (path.points.len() >= 2).then_some(path)
+    }
+
+    fn to_plane(&self, (lat, lon): (f64, f64)) -> (f64, f64) {
+        let scale_x = METERS_PER_DEGREE * self.origin.0.to_radians().cos();
+        ((lon - self.origin.1) * scale_x, (lat - self.origin.0) * METERS_PER_DEGREE)
+    }
+
+    fn to_geographic(&self, (x, y): (f64, f64)) -> (f64, f64) {
+        let scale_x = METERS_PER_DEGREE * self.origin.0.to_radians().cos();
+        (self.origin.0 + y / METERS_PER_DEGREE, self.origin.1 + x / scale_x)
+    }
+
+    fn length(&self) -> f64 {
+        self.along.last().copied().unwrap_or_default()
+    }
+
+    /// (distance along the path, distance off it) of the nearest point of the path
+    fn project(&self, position: (f64, f64)) -> (f64, f64) {
+        let point = self.to_plane(position);
+        self.points.windows(2).zip(&self.along)
+            .map(|(segment, along)| {
+                let (start, end) = (segment[0], segment[1]);
+                let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+                let length_squared = dx * dx + dy * dy;
+                let t = if length_squared == 0.0 {
+                    0.0
+                } else {
+                    (((point.0 - start.0) * dx + (point.1 - start.1) * dy) / length_squared).clamp(0.0, 1.0)
+                };
+                let nearest = (start.0 + t * dx, start.1 + t * dy);
+                (along + t * length_squared.sqrt(), distance_between(point, nearest))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or_default()
+    }
+
+    /// (latitude, longitude, bearing) at a distance along the path
+    fn point_at(&self, distance: f64) -> (f64, f64, f32) {
+        let segment = self.along.windows(2)
+            .position(|along| distance <= along[1])
+            .unwrap_or(self.points.len() - 2);
+        let (start, end) = (self.points[segment], self.points[segment + 1]);
+        let length = self.along[segment + 1] - self.along[segment];
+        let t = if length == 0.0 { 0.0 } else { ((distance - self.along[segment]) / length).clamp(0.0, 1.0) };
+        let (lat, lon) = self.to_geographic((start.0 + t * (end.0 - start.0), start.1 + t * (end.1 - start.1)));
+        let bearing = (end.0 - start.0).atan2(end.1 - start.1).to_degrees().rem_euclid(360.0);
+        (lat, lon, bearing as f32)
+    }
+}
+
+fn distance_between(a: (f64, f64), b: (f64, f64)) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+/// Position of every stop by stop_id; SNCF stop times name stops that are kept by UIC code
+fn stop_positions(cache: &CachedNetworkData) -> HashMap<String, (f64, f64)> {
+    cache.stop_positions().into_iter()
+        .map(|(stop_id, lat, lon)| (stop_id, (lat, lon)))
+        .collect()
+}
+
+fn stop_position(positions: &HashMap<String, (f64, f64)>, stop_id: &str) -> Option<(f64, f64)> {
+    positions.get(stop_id).copied()
+        .or_else(|| NVTModels::extract_sncf_stop_id(stop_id).and_then(|uic| positions.get(&uic).copied()))
+}
+
+/// Delay of a trip: the vehicle's own, else the first one its trip update gives
+fn trip_delay(vehicle: &RealTimeInfo, cache: &CachedNetworkData) -> Option<i32> {
+    vehicle.delay.or_else(|| {
+        cache.trip_updates.iter()
+            .filter(|update| update.trip.trip_id.as_deref() == Some(vehicle.trip_id.as_str()))
+            .flat_map(|update| &update.stop_time_update)
+            .find_map(|stop_time| {
+                stop_time.departure.as_ref().and_then(|event| event.delay)
+                    .or_else(|| stop_time.arrival.as_ref().and_then(|event| event.delay))
+            })
+    })
+}
+
+/// (unix time, stop position) of each timed stop of a trip run on the service day around `now`
+fn timed_stops(
+    vehicle: &RealTimeInfo,
+    gtfs_cache: &GTFSCache,
+    service_id: &str,
+    stops: &[(u32, String)],
+    positions: &HashMap<String, (f64, f64)>,
+    now: i64,
+) -> Option<Vec<(i64, (f64, f64))>> {
+    let times: Vec<(&str, (f64, f64))> = stops.iter()
+        .filter_map(|(sequence, stop_id)| {
+            let stop_time = gtfs_cache.stop_times.get(stop_id)?.iter()
+                .find(|stop_time| stop_time.trip_id == vehicle.trip_id && stop_time.stop_sequence == *sequence)?;
+            let time = if stop_time.departure_time.is_empty() { &stop_time.arrival_time } else { &stop_time.departure_time };
+            Some((time.as_str(), stop_position(positions, stop_id)?))
+        })
+        .collect();
+
+    // Trips run past midnight on the service day before
+    let today = chrono::DateTime::from_timestamp(now, 0)?.with_timezone(&Paris).date_naive();
+    [today, today - Duration::days(1)].into_iter()
+        .filter(|service_date| NVTModels::service_runs(gtfs_cache, service_id, *service_date))
+        .filter_map(|service_date: NaiveDate| {
+            times.iter()
+                .map(|(time, position)| Some((time_format::gtfs_datetime(service_date, time)?.timestamp(), *position)))
+                .collect::<Option<Vec<_>>>()
+        })
+        .find(|timed| match (timed.first(), timed.last()) {
+            (Some(first), Some(last)) => first.0 - SLACK_SECS <= now && now <= last.0 + SLACK_SECS,
+            _ => false,
+        })
+        .filter(|timed| timed.len() >= 2)
+}
+
+/// The position and bearing of a stale vehicle moved along its trip to `now`, and whether a
+/// delay was known for it
+fn interpolate(
+    vehicle: &RealTimeInfo,
+    cache: &CachedNetworkData,
+    positions: &HashMap<String, (f64, f64)>,
+    now: i64,
+) -> Option<(f64, f64, f32, bool)> {
+    let fixed_at = vehicle.timestamp?;
+    if now - fixed_at <= STALE_SECS || (vehicle.latitude == 0.0 && vehicle.longitude == 0.0) {
+        return None;
+    }
+    let stops = cache.trip_index.get(&vehicle.trip_id)?;
+    let gtfs_cache = cache.gtfs_caches().into_iter()
+        .find(|gtfs_cache| gtfs_cache.trips.contains_key(&vehicle.trip_id))?;
+    let trip = &gtfs_cache.trips[&vehicle.trip_id];
+    let timed = timed_stops(vehicle, gtfs_cache, &trip.service_id, stops, positions, now)?;
+    let delay = trip_delay(vehicle, cache);
+
+    // The shape of the route running closest to the first and last stops, else the stops
+    let (first, last) = (timed.first()?.1, timed.last()?.1);
+    let shape = gtfs_cache.route_to_shapes.get(&trip.route_id).into_iter().flatten()
+        .filter_map(|shape_id| gtfs_cache.shapes.get(shape_id))
+        .filter_map(|points| {
+            let mut points = points.clone();
+            points.sort_by_key(|point| point.sequence);
+            Path::new(&points.iter().map(|point| (point.latitude, point.longitude)).collect::<Vec<_>>())
+        })
+        .min_by(|a, b| (a.project(first).1 + a.project(last).1).total_cmp(&(b.project(first).1 + b.project(last).1)));
+    let path = match shape {
+        Some(path) => path,
+        None => Path::new(&timed.iter().map(|(_, position)| *position).collect::<Vec<_>>())?,
+    };
+
+    // Distance along the path the timetable puts the vehicle at, at a time; stops projected
+    // backwards (loops, shapes drawn past the stop) are held where the previous one was
+    let delay_secs = i64::from(delay.unwrap_or(0));
+    let mut schedule: Vec<(i64, f64)> = Vec::with_capacity(timed.len());
+    for (time, position) in &timed {
+        let along = path.project(*position).0.max(schedule.last().map_or(0.0, |(_, along)| *along));
+        schedule.push((time + delay_secs, along));
+    }
+    let scheduled_at = |time: i64| -> f64 {
+        match schedule.windows(2).find(|pair| time < pair[1].0) {
+            Some(pair) if time <= pair[0].0 => pair[0].1,
+            Some(pair) => {
+                let t = (time - pair[0].0) as f64 / (pair[1].0 - pair[0].0) as f64;
+                pair[0].1 + t * (pair[1].1 - pair[0].1)
+            }
+            None => schedule.last().map_or(0.0, |(_, along)| *along),
+        }
+    };
+
+    let fix = path.project((vehicle.latitude, vehicle.longitude)).0;
+    let along = (fix + scheduled_at(now) - scheduled_at(fixed_at)).clamp(0.0, path.length());
+    let (lat, lon, bearing) = path.point_at(along);
+    Some((lat, lon, bearing, delay.is_some()))
+}
+
+/// Moves the stale vehicles of `vehicles` to where they should be at `now`
+pub fn apply(vehicles: &mut [VehicleWithProgress], cache: &CachedNetworkData, now: i64) {
+    let positions = stop_positions(cache);
+    for vehicle in vehicles {
+        let Some((lat, lon, bearing, delayed)) = interpolate(&vehicle.vehicle, cache, &positions, now) else {
+            continue;
+        };
+        vehicle.interpolated = Some(InterpolatedPosition {
+            gps_latitude: vehicle.vehicle.latitude,
+            gps_longitude: vehicle.vehicle.longitude,
+            gps_age_secs: now - vehicle.vehicle.timestamp.unwrap_or(now),
+            based_on: if delayed { "trip_update" } else { "schedule" }.to_string(),
+        });
+        let moved = Cow::to_mut(&mut vehicle.vehicle);
+        (moved.latitude, moved.longitude, moved.bearing) = (lat, lon, Some(bearing));
+    }
+}
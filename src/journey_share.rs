@@ -0,0 +1,373 @@
+// Shared journey storage
+// Persists itineraries computed by the trip planner (GET /api/tbm/journey, see routing.rs) under
+// short tokens so a planned trip can be sent to someone else as a link (/j/{token}).
+// Each share is appended to tbm_nvt/journey_shares.jsonl, one journey per line. The file is only
+// rewritten once expired journeys outnumber the live ones. Shares are capped in number and per
+// client and minute (JourneySharesConfig), since anyone can post them.
+
+use chrono::DateTime;
+use chrono_tz::Europe::Paris;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use rand::Rng;
+use rand::distr::Alphanumeric;
+
+use crate::config::{self, JourneySharesConfig};
+use crate::routing::{Journey, JourneyLeg};
+use crate::tbm_api_models::NVTModels;
+
+const WINDOW_SECS: u64 = 60;
+const COMPACT_SLACK: usize = 100; // Expired lines tolerated in the file beyond the live ones
+
+// ============================================================================
+// Data Structures
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedJourney {
+    pub token: String,
+    pub itinerary: Journey,
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ShareError {
+    Invalid(String),
+    RateLimited { retry_after_secs: u64 },
+    Full, // max_entries live journeys
+    Storage(String),
+}
+
+impl std::fmt::Display for ShareError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShareError::Invalid(e) => write!(f, "{}", e),
+            ShareError::RateLimited { retry_after_secs } => write!(f, "Too many shared journeys, retry in {} s", retry_after_secs),
+            ShareError::Full => write!(f, "Too many shared journeys are stored, please retry later"),
+            ShareError::Storage(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct JourneyShareStore {
+    pub journeys: HashMap<String, SharedJourney>,
+    pub limits: JourneySharesConfig,
+    path: Option<PathBuf>, // Of the file shares are appended to; None keeps them in memory
+    file_lines: usize, // Journeys in the file, expired ones included
+    windows: HashMap<IpAddr, (u64, u32)>, // key: client, value: (window start, shares in it)
+}
+
+// ============================================================================
+// Store Implementation
+// ============================================================================
+
+impl JourneyShareStore {
+    const TOKEN_LENGTH: usize = 8;
+    const MAX_LEGS: usize = 12;
+
+    pub fn store_path() -> PathBuf {
+        let mut path = dirs::cache_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push("tbm_nvt");
+        fs::create_dir_all(&path).ok();
+        path.push("journey_shares.jsonl");
+        path
+    }
+
+    pub fn load() -> Self {
+        let mut store = Self::open(Self::store_path());
+        store.limits = config::get().journey_shares.clone();
+        println!("✓ Loaded {} shared journeys", store.journeys.len());
+        store
+    }
+
+    /// Store appending to `path`, with the live journeys of the file and of journey_shares.json
+    /// next to it (written whole at each share by earlier versions, then removed)
+    pub fn open(path: PathBuf) -> Self {
+        let mut store = JourneyShareStore::default();
+
+        // A line cut short by a crash is skipped
+        if let Ok(contents) = fs::read_to_string(&path) {
+            for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+                match serde_json::from_str::<SharedJourney>(line) {
+                    Ok(journey) => {
+                        store.journeys.insert(journey.token.clone(), journey);
+                    }
+                    Err(e) => println!("⚠️  Skipped an unreadable shared journey ({})", e),
+                }
+            }
+        }
+
+        #[derive(Deserialize)]
+        struct Legacy {
+            journeys: HashMap<String, SharedJourney>,
+        }
+        let legacy = path.with_extension("json");
+        let migrated = match fs::read_to_string(&legacy) {
+            Ok(contents) => match serde_json::from_str::<Legacy>(&contents) {
+                Ok(old) => {
+                    store.journeys.extend(old.journeys);
+                    true
+                }
+                Err(e) => {
+                    println!("⚠️  Failed to parse {} ({}), left in place", legacy.display(), e);
+                    false
+                }
+            },
+            Err(_) => false,
+        };
+
+        store.path = Some(path);
+        store.prune_expired();
+        match store.compact() {
+            Ok(()) if migrated => {
+                fs::remove_file(&legacy).ok();
+            }
+            Ok(()) => {}
+            Err(e) => eprintln!("⚠️  {}", e),
+        }
+        store
+    }
+
+    /// Rewrite the file with the live journeys only
+    fn compact(&mut self) -> Result<(), ShareError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut lines = String::new();
+        for journey in self.journeys.values() {
+            let line = serde_json::to_string(journey)
+                .map_err(|e| ShareError::Storage(format!("Failed to serialize shared journeys: {}", e)))?;
+            lines.push_str(&line);
+            lines.push('\n');
+        }
+
+        let temporary = path.with_extension("jsonl.tmp");
+        fs::write(&temporary, lines)
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|e| ShareError::Storage(format!("Failed to write shared journeys: {}", e)))?;
+        self.file_lines = self.journeys.len();
+        Ok(())
+    }
+
+    fn append(&mut self, journey: &SharedJourney) -> Result<(), ShareError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut line = serde_json::to_string(journey)
+            .map_err(|e| ShareError::Storage(format!("Failed to serialize shared journey: {}", e)))?;
+        line.push('\n');
+        fs::OpenOptions::new().create(true).append(true).open(path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| ShareError::Storage(format!("Failed to write shared journey: {}", e)))?;
+
+        self.file_lines += 1;
+        if self.file_lines > 2 * self.journeys.len() + COMPACT_SLACK {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Validate and persist an itinerary of the trip planner shared by `client`, returning its
+    /// share token
+    pub fn share(&mut self, itinerary: Journey, client: Option<IpAddr>) -> Result<SharedJourney, ShareError> {
+        if itinerary.legs.is_empty() {
+            return Err(ShareError::Invalid("Itinerary has no legs".to_string()));
+        }
+        if itinerary.legs.len() > Self::MAX_LEGS {
+            return Err(ShareError::Invalid(format!(
+                "Itinerary has too many legs ({} > {})", itinerary.legs.len(), Self::MAX_LEGS
+            )));
+        }
+        if let Some(leg) = itinerary.legs.iter().find(|leg| !["transit", "walk", "bike"].contains(&leg.mode.as_str())) {
+            return Err(ShareError::Invalid(format!("Unknown leg mode '{}'", leg.mode)));
+        }
+        if itinerary.legs.iter().any(|leg| leg.arrival < leg.departure) {
+            return Err(ShareError::Invalid("A leg arrives before it departs".to_string()));
+        }
+
+        let now = NVTModels::get_current_timestamp();
+        self.count_share(client, now as u64)?;
+        self.prune_expired();
+        if self.limits.max_entries > 0 && self.journeys.len() >= self.limits.max_entries {
+            return Err(ShareError::Full);
+        }
+
+        let mut token = Self::generate_token();
+        while self.journeys.contains_key(&token) {
+            token = Self::generate_token();
+        }
+
+        let journey = SharedJourney {
+            token: token.clone(),
+            itinerary,
+            created_at: now,
+            expires_at: now + Self::retention_secs(),
+        };
+
+        self.journeys.insert(token.clone(), journey.clone());
+        if let Err(e) = self.append(&journey) {
+            self.journeys.remove(&token);
+            return Err(e);
+        }
+
+        Ok(journey)
+    }
+
+    /// Count a share of `client` in its one-minute window, refusing it past the limit
+    fn count_share(&mut self, client: Option<IpAddr>, now: u64) -> Result<(), ShareError> {
+        let (Some(client), limit) = (client, self.limits.per_client_per_minute) else {
+            return Ok(());
+        };
+        if limit == 0 {
+            return Ok(());
+        }
+
+        let window_start = now - now % WINDOW_SECS;
+        self.windows.retain(|_, (start, _)| *start == window_start);
+        let (_, count) = self.windows.entry(client).or_insert((window_start, 0));
+        if *count >= limit {
+            return Err(ShareError::RateLimited { retry_after_secs: window_start + WINDOW_SECS - now });
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    pub fn get(&self, token: &str) -> Option<&SharedJourney> {
+        let now = NVTModels::get_current_timestamp();
+        self.journeys.get(token).filter(|j| Self::is_live(j, now))
+    }
+
+    /// Privacy retention period; also applies to journeys shared before it was shortened
+    fn retention_secs() -> i64 {
+        (config::get().privacy.retention_days.max(1) * 86_400) as i64
+    }
+
+    fn is_live(journey: &SharedJourney, now: i64) -> bool {
+        journey.expires_at > now && journey.created_at + Self::retention_secs() > now
+    }
+
+    fn prune_expired(&mut self) {
+        let now = NVTModels::get_current_timestamp();
+        self.journeys.retain(|_, j| Self::is_live(j, now));
+    }
+
+    fn generate_token() -> String {
+        rand::rng()
+            .sample_iter(&Alphanumeric)
+            .take(Self::TOKEN_LENGTH)
+            .map(char::from)
+            .collect()
+    }
+}
+
+// ============================================================================
+// Share Page Rendering
+// ============================================================================
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Local time of a unix timestamp, as the share page shows it
+fn clock(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|dt| dt.with_timezone(&Paris).format("%H:%M").to_string())
+        .unwrap_or_default()
+}
+
+/// A walk or a V³ ride, between the vehicles
+fn is_street(leg: &JourneyLeg) -> bool {
+    leg.mode == "walk" || leg.mode == "bike"
+}
+
+/// Render a standalone HTML page describing a shared journey
+pub fn render_share_page(journey: &SharedJourney) -> String {
+    let itinerary = &journey.itinerary;
+    let first = &itinerary.legs[0];
+    let last = &itinerary.legs[itinerary.legs.len() - 1];
+
+    let mut legs_html = String::new();
+    for (index, leg) in itinerary.legs.iter().enumerate() {
+        // Walks between two vehicles are the transfer themselves
+        if index > 0 && !is_street(leg) && !is_street(&itinerary.legs[index - 1]) {
+            legs_html.push_str(&format!(
+                "<li class=\"transfer\">↔️ Transfer at {}</li>\n",
+                escape_html(&leg.from_stop_name)
+            ));
+        }
+
+        if is_street(leg) {
+            let what = if leg.mode == "bike" { "🚲 V³ bike" } else { "🚶 Walk" };
+            legs_html.push_str(&format!(
+                "<li class=\"walk\">{} {} from {} to {} <small>({} min)</small></li>\n",
+                clock(leg.departure),
+                what,
+                escape_html(&leg.from_stop_name),
+                escape_html(&leg.to_stop_name),
+                ((leg.arrival - leg.departure) as f64 / 60.0).ceil() as i64
+            ));
+            continue;
+        }
+
+        let color = leg.line_color.as_deref()
+            .filter(|c| c.len() == 6 && c.chars().all(|ch| ch.is_ascii_hexdigit()))
+            .unwrap_or("808080");
+
+        legs_html.push_str(&format!(
+            "<li>{} <span class=\"badge\" style=\"background:#{}\">{}</span> {} → {} {}{}</li>\n",
+            clock(leg.departure),
+            color,
+            escape_html(leg.line_code.as_deref().unwrap_or("?")),
+            escape_html(&leg.from_stop_name),
+            clock(leg.arrival),
+            escape_html(&leg.to_stop_name),
+            leg.operator.as_ref()
+                .map(|o| format!(" <small>({})</small>", escape_html(o)))
+                .unwrap_or_default()
+        ));
+    }
+
+    let duration = (itinerary.arrival - itinerary.departure) / 60;
+
+    format!(
+        "<!DOCTYPE html>\n\
+         <html lang=\"en\">\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>Trip: {from} → {to}</title>\n\
+         <style>\n\
+         body {{ font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', sans-serif; max-width: 640px; margin: 2em auto; padding: 0 1em; color: #222; }}\n\
+         ul {{ list-style: none; padding: 0; }}\n\
+         li {{ padding: 0.5em 0; border-bottom: 1px solid #eee; }}\n\
+         li.transfer {{ color: #b36b00; }}\n\
+         li.walk {{ color: #555; }}\n\
+         .badge {{ display: inline-block; min-width: 2em; padding: 0.1em 0.4em; border-radius: 4px; color: #fff; font-weight: bold; text-align: center; }}\n\
+         </style>\n\
+         </head>\n\
+         <body>\n\
+         <h1>{from} → {to}</h1>\n\
+         <p>{departure} → {arrival} · {duration} min · {transfers} transfer(s)</p>\n\
+         <ul>\n{legs}</ul>\n\
+         <p><a href=\"/\">Open the live map</a></p>\n\
+         </body>\n\
+         </html>\n",
+        from = escape_html(&first.from_stop_name),
+        to = escape_html(&last.to_stop_name),
+        departure = clock(itinerary.departure),
+        arrival = clock(itinerary.arrival),
+        duration = duration,
+        transfers = itinerary.transfers,
+        legs = legs_html,
+    )
+}
@@ -0,0 +1,372 @@
+// Departure board kiosks
+// Assembles the rotating screens payload for an admin-defined kiosk config: one screen
+// per configured stop with its next departures (real-time first, timetable fallback),
+// plus the compact plain-text board used by SMS gateways, terminals and e-paper displays.
+
+use serde::Serialize;
+use chrono::{DateTime, Timelike, TimeZone, Utc};
+use chrono_tz::{Europe::Paris, Tz};
+use std::collections::HashSet;
+
+use crate::config::KioskConfig;
+use crate::tbm_api_models::{CachedNetworkData, NVTModels, NetworkData, Stop};
+
+// ============================================================================
+// Data Structures
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KioskDeparture {
+    pub line_code: String,
+    pub line_color: String,
+    pub destination: Option<String>,
+    pub departure_time: String, // HH:MM, Europe/Paris
+    pub minutes: i64,
+    pub realtime: bool,
+    pub delay: Option<i32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KioskAlert {
+    pub text: String,
+    pub severity: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KioskScreen {
+    pub stop_id: String,
+    pub stop_name: String,
+    pub departures: Vec<KioskDeparture>,
+    pub alerts: Vec<KioskAlert>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KioskLabels {
+    pub departures: &'static str,
+    pub minutes: &'static str,
+    pub now: &'static str,
+    pub no_departures: &'static str,
+    pub alerts: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KioskPayload {
+    pub config_id: String,
+    pub title: Option<String>,
+    pub language: String,
+    pub rotation_secs: u64,
+    pub generated_at: i64,
+    pub labels: KioskLabels,
+    pub screens: Vec<KioskScreen>,
+}
+
+// ============================================================================
+// Payload Assembly
+// ============================================================================
+
+fn labels_for(language: &str) -> KioskLabels {
+    if language.eq_ignore_ascii_case("en") {
+        KioskLabels {
+            departures: "Next departures",
+            minutes: "min",
+            now: "Now",
+            no_departures: "No upcoming departures",
+            alerts: "Service alerts",
+        }
+    } else {
+        KioskLabels {
+            departures: "Prochains départs",
+            minutes: "min",
+            now: "À quai",
+            no_departures: "Aucun départ prévu",
+            alerts: "Perturbations",
+        }
+    }
+}
+
+/// Next departures at a stop after `now` (NVTModels::paris_now): real-time predictions first,
+/// timetable fallback for the rest. `wants_line` filters by line code.
+pub fn stop_departures(
+    stop: &Stop,
+    network_data: &NetworkData,
+    cache: &CachedNetworkData,
+    now: DateTime<Tz>,
+    limit: usize,
+    wants_line: impl Fn(&str) -> bool,
+) -> Vec<KioskDeparture> {
+    let now_ts = now.timestamp();
+    let current_seconds = now.hour() * 3600 + now.minute() * 60 + now.second();
+
+    let mut departures = Vec::new();
+    let mut realtime_trips = HashSet::new();
+
+    // Real-time predictions matched from trip updates (TBM)
+    for rt in stop.real_time.iter().filter(|rt| rt.vehicle_id == "scheduled") {
+        let Some(ts) = rt.timestamp else { continue };
+        let line = rt.route_id.as_ref().and_then(|rid| {
+            network_data.lines.iter().find(|l| &l.route_id == rid)
+        });
+        let line_code = line.map(|l| l.line_code.clone())
+            .or_else(|| rt.route_id.clone())
+            .unwrap_or_default();
+
+        if !wants_line(&line_code) {
+            continue;
+        }
+
+        realtime_trips.insert(rt.trip_id.clone());
+        departures.push(KioskDeparture {
+            line_code,
+            line_color: line.map(|l| l.color.clone()).unwrap_or_else(|| "808080".to_string()),
+            destination: rt.destination.clone(),
+            departure_time: Utc.timestamp_opt(ts, 0).single()
+                .map(|dt| dt.with_timezone(&Paris).format("%H:%M").to_string())
+                .unwrap_or_default(),
+            minutes: ((ts - now_ts) / 60).max(0),
+            realtime: true,
+            delay: rt.delay,
+        });
+    }
+
+    // Timetable fallback for trips without a prediction
+    let scheduled = NVTModels::get_scheduled_arrivals(&stop.stop_id, cache, now.naive_local(), limit * 2);
+    for arrival in scheduled {
+        if realtime_trips.contains(&arrival.trip_id) || !wants_line(&arrival.line_code) {
+            continue;
+        }
+        let Some(seconds) = NVTModels::parse_gtfs_time(&arrival.departure_time) else { continue };
+        // Trips of the previous or next service day count from that day's midnight
+        let day_offset = arrival.service_day()
+            .map(|date| (date - now.date_naive()).num_days() * 86400)
+            .unwrap_or(0);
+
+        departures.push(KioskDeparture {
+            line_code: arrival.line_code,
+            line_color: arrival.line_color,
+            destination: arrival.destination.or(arrival.stop_headsign),
+            departure_time: format!("{:02}:{:02}", (seconds / 3600) % 24, (seconds / 60) % 60),
+            minutes: ((seconds as i64 + day_offset - current_seconds as i64) / 60).max(0),
+            realtime: false,
+            delay: None,
+        });
+    }
+
+    departures.sort_by_key(|d| d.minutes);
+    departures.truncate(limit);
+    departures
+}
+
+/// Build the rotating screens payload for a kiosk config
+pub fn build_payload(config_id: &str, kiosk: &KioskConfig, cache: &CachedNetworkData, now: DateTime<Tz>) -> KioskPayload {
+    let network_data = cache.to_network_data();
+    let now_ts = now.timestamp();
+
+    let wants_line = |code: &str| {
+        kiosk.lines.is_empty() || kiosk.lines.iter().any(|l| l.eq_ignore_ascii_case(code))
+    };
+
+    let mut screens = Vec::new();
+
+    for stop_id in &kiosk.stops {
+        let Some(stop) = network_data.stops.iter().find(|s| &s.stop_id == stop_id) else {
+            println!("⚠️  Kiosk {}: stop not found: {}", config_id, stop_id);
+            continue;
+        };
+
+        let departures = stop_departures(stop, &network_data, cache, now, kiosk.departures_per_screen, wants_line);
+
+        let alerts = stop.alerts.iter()
+            .map(|a| KioskAlert { text: a.text.clone(), severity: a.severity })
+            .collect();
+
+        screens.push(KioskScreen {
+            stop_id: stop.stop_id.clone(),
+            stop_name: stop.stop_name.clone(),
+            departures,
+            alerts,
+        });
+    }
+
+    KioskPayload {
+        config_id: config_id.to_string(),
+        title: kiosk.title.clone(),
+        language: kiosk.language.clone(),
+        rotation_secs: kiosk.rotation_secs,
+        generated_at: now_ts,
+        labels: labels_for(&kiosk.language),
+        screens,
+    }
+}
+
+// ============================================================================
+// Plain-Text Board
+// ============================================================================
+
+/// Compact plain-text departures board: line, destination, minutes, updated at `now`
+pub fn render_text_board(stop: &Stop, departures: &[KioskDeparture], now: DateTime<Tz>) -> String {
+    const DESTINATION_WIDTH: usize = 24;
+
+    let updated = now.format("%H:%M");
+    let mut board = format!("{} ({})\nUpdated {}\n", stop.stop_name, stop.stop_id, updated);
+
+    if departures.is_empty() {
+        board.push_str("No upcoming departures\n");
+        return board;
+    }
+
+    let code_width = departures.iter().map(|d| d.line_code.chars().count()).max().unwrap_or(1);
+    for departure in departures {
+        let destination: String = departure.destination.as_deref()
+            .unwrap_or("-")
+            .chars()
+            .take(DESTINATION_WIDTH)
+            .collect();
+        let when = if departure.minutes == 0 {
+            "now".to_string()
+        } else {
+            format!("{} min", departure.minutes)
+        };
+
+        board.push_str(&format!(
+            "{:<cw$}  {:<dw$}  {:>6}{}\n",
+            departure.line_code,
+            destination,
+            when,
+            if departure.realtime { "" } else { " ~" },
+            cw = code_width,
+            dw = DESTINATION_WIDTH,
+        ));
+    }
+
+    if departures.iter().any(|d| !d.realtime) {
+        board.push_str("~ = timetable, no real-time data\n");
+    }
+    board
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tbm_api_models::{RealTimeInfo, ServiceCalendar, StopTime, Trip, Upstreams};
+
+    fn paris(hour: u32, minute: u32) -> DateTime<Tz> {
+        Paris.with_ymd_and_hms(2026, 5, 4, hour, minute, 0).single().expect("time")
+    }
+
+    /// Stop S1, served every day by T1 at 08:10 and T2 at 08:30
+    fn timetable() -> CachedNetworkData {
+        let mut cache = CachedNetworkData::empty(Upstreams::default());
+        let gtfs = &mut cache.tbm_gtfs_cache;
+        for (trip_id, time) in [("T1", "08:10:00"), ("T2", "08:30:00")] {
+            gtfs.trips.insert(trip_id.to_string(), Trip {
+                trip_id: trip_id.to_string(),
+                route_id: "R1".to_string(),
+                service_id: "ALL".to_string(),
+                trip_headsign: Some("Gare Saint-Jean".to_string()),
+                direction_id: Some(0),
+                wheelchair_accessible: None,
+                bikes_allowed: None,
+            });
+            gtfs.stop_times.entry("S1".to_string()).or_default().push(StopTime {
+                trip_id: trip_id.to_string(),
+                arrival_time: time.to_string(),
+                departure_time: time.to_string(),
+                stop_id: "S1".to_string(),
+                stop_sequence: 1,
+                stop_headsign: None,
+            });
+        }
+        gtfs.calendar.insert("ALL".to_string(), ServiceCalendar {
+            service_id: "ALL".to_string(),
+            monday: true, tuesday: true, wednesday: true, thursday: true, friday: true, saturday: true, sunday: true,
+            start_date: "20260101".to_string(),
+            end_date: "20261231".to_string(),
+        });
+        cache
+    }
+
+    fn stop(real_time: Vec<RealTimeInfo>) -> Stop {
+        Stop {
+            stop_id: "S1".to_string(),
+            stop_name: "Quinconces".to_string(),
+            latitude: 44.8445,
+            longitude: -0.5737,
+            lines: Vec::new(),
+            alerts: Vec::new(),
+            real_time,
+            commune: None,
+            insee_code: None,
+            amenities: None,
+            wheelchair_boarding: None,
+            level: None,
+            pathways: Vec::new(),
+        }
+    }
+
+    fn departure(line_code: &str, destination: &str, minutes: i64, realtime: bool) -> KioskDeparture {
+        KioskDeparture {
+            line_code: line_code.to_string(),
+            line_color: "808080".to_string(),
+            destination: Some(destination.to_string()),
+            departure_time: String::new(),
+            minutes,
+            realtime,
+            delay: None,
+        }
+    }
+
+    #[test]
+    fn counts_minutes_from_the_given_paris_time() {
+        let cache = timetable();
+        let network_data = cache.to_network_data();
+        let predicted = RealTimeInfo {
+            vehicle_id: "scheduled".to_string(),
+            trip_id: "T1".to_string(),
+            route_id: Some("R1".to_string()),
+            direction_id: Some(0),
+            destination: Some("Gare Saint-Jean".to_string()),
+            latitude: 44.8445,
+            longitude: -0.5737,
+            stop_id: Some("S1".to_string()),
+            current_stop_sequence: None,
+            timestamp: Some(paris(8, 12).timestamp()),
+            delay: Some(120),
+            bearing: None,
+            occupancy: None,
+            source: None,
+            start_date: None,
+        };
+
+        // 08:05 in Paris: T1 predicted 2 minutes late, T2 from the timetable
+        let departures = stop_departures(&stop(vec![predicted]), &network_data, &cache, paris(8, 5), 2, |_| true);
+        let rows: Vec<(&str, i64, bool)> = departures.iter()
+            .map(|d| (d.departure_time.as_str(), d.minutes, d.realtime))
+            .collect();
+        assert_eq!(rows, [("08:12", 7, true), ("08:30", 25, false)]);
+
+        // Late evening: the next morning's trips, counted across midnight
+        let departures = stop_departures(&stop(Vec::new()), &network_data, &cache, paris(23, 50), 2, |_| true);
+        let rows: Vec<(&str, i64)> = departures.iter().map(|d| (d.departure_time.as_str(), d.minutes)).collect();
+        assert_eq!(rows, [("08:10", 8 * 60 + 20), ("08:30", 8 * 60 + 40)]);
+
+        assert!(stop_departures(&stop(Vec::new()), &network_data, &cache, paris(8, 5), 2, |code| code == "B").is_empty());
+    }
+
+    #[test]
+    fn renders_the_text_board_at_the_given_time() {
+        let departures = [
+            departure("A", "Le Haillan Rostand", 0, true),
+            departure("Lianes 1", "Aéroport Mérignac via la Gare Saint-Jean", 12, false),
+        ];
+        assert_eq!(render_text_board(&stop(Vec::new()), &departures, paris(8, 5)), "\
+            Quinconces (S1)\nUpdated 08:05\n\
+            A         Le Haillan Rostand           now\n\
+            Lianes 1  Aéroport Mérignac via la  12 min ~\n\
+            ~ = timetable, no real-time data\n");
+
+        assert_eq!(
+            render_text_board(&stop(Vec::new()), &[], paris(23, 50)),
+            "Quinconces (S1)\nUpdated 23:50\nNo upcoming departures\n",
+        );
+    }
+}
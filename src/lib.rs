@@ -0,0 +1,16 @@
+// Data layer of the server: fetching, parsing and merging the TBM, New-Aquitaine and SNCF feeds
+// Built as a library so the fuzz targets (fuzz/) can link the parsers; the server binary
+// (main.rs) adds the HTTP API, frontend and background tasks on top.
+
+pub mod tbm_api_models;
+pub mod config;
+pub mod communes;
+pub mod amenities;
+pub mod gbfs;
+pub mod navitia;
+pub mod map_matching;
+pub mod spatial;
+pub mod search;
+pub mod headways;
+pub mod operators;
+pub mod colors;
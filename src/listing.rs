@@ -0,0 +1,74 @@
+// Paging and field selection for list endpoints
+// /stops and /lines return every item of every source, each with its alerts and real-time
+// arrays. With ?page / ?per_page they return one page (in id order, so pages are stable
+// between requests) and the totals in X-Total-Count / X-Total-Pages headers, leaving the
+// payload shape unchanged; ?fields=a,b keeps only those fields of each item.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+const DEFAULT_PER_PAGE: usize = 100;
+const MAX_PER_PAGE: usize = 1000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Page {
+    pub page: usize, // 1-based
+    pub per_page: usize,
+}
+
+impl Page {
+    /// None when neither parameter is given: the whole list, as before paging existed
+    pub fn from_query(page: Option<usize>, per_page: Option<usize>) -> Result<Option<Self>, String> {
+        if page.is_none() && per_page.is_none() {
+            return Ok(None);
+        }
+        let page = page.unwrap_or(1);
+        if page == 0 {
+            return Err("page starts at 1".to_string());
+        }
+        let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE).clamp(1, MAX_PER_PAGE);
+        Ok(Some(Page { page, per_page }))
+    }
+
+    /// Items of this page; past the last page it is empty
+    pub fn slice<T>(&self, items: Vec<T>) -> Vec<T> {
+        let start = (self.page - 1).saturating_mul(self.per_page);
+        items.into_iter().skip(start).take(self.per_page).collect()
+    }
+
+    pub fn total_pages(&self, total: usize) -> usize {
+        total.div_ceil(self.per_page)
+    }
+}
+
+/// Parse ?fields=a,b,c, rejecting names that are not fields of the items
+pub fn parse_fields(fields: Option<&str>, known: &[String]) -> Result<Option<Vec<String>>, String> {
+    let Some(fields) = fields else {
+        return Ok(None);
+    };
+    let selected: Vec<String> = fields.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect();
+    if let Some(unknown) = selected.iter().find(|name| !known.contains(name)) {
+        return Err(format!("Unknown field '{}' (expected some of: {})", unknown, known.join(", ")));
+    }
+    Ok(Some(selected))
+}
+
+/// Items reduced to the selected fields
+pub fn select_fields<T: Serialize>(items: &[T], fields: &[String]) -> Vec<Value> {
+    items.iter()
+        .map(|item| match serde_json::to_value(item) {
+            Ok(Value::Object(mut all)) => {
+                let selected: Map<String, Value> = fields.iter()
+                    .filter_map(|name| all.remove(name).map(|value| (name.clone(), value)))
+                    .collect();
+                Value::Object(selected)
+            }
+            Ok(other) => other,
+            Err(_) => Value::Null,
+        })
+        .collect()
+}
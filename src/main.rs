@@ -1,15 +1,65 @@
 // Backend API server with embedded frontend
 // TBM + TransGironde Transit API Server with integrated web UI
 
-use actix_web::{web, App, HttpServer, HttpResponse, middleware};
+use actix_web::{web, App, HttpServer, HttpRequest, HttpResponse, middleware};
+use actix_web::dev::Service;
+use tokio::sync::Semaphore;
 use actix_cors::Cors;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::time;
 
-mod tbm_api_models;
-use tbm_api_models::{NVTModels, CachedNetworkData};
+// The data layer lives in the library crate (lib.rs)
+use nvtweb::{amenities, colors, communes, config, gbfs, headways, navitia, operators, search, spatial, tbm_api_models};
+mod journey_share;
+mod kiosk;
+mod branding;
+mod static_map;
+mod qr;
+#[cfg(feature = "bot")]
+mod bot;
+mod digest;
+mod history;
+mod parquet;
+mod elevation;
+mod time_format;
+mod bench;
+mod schema;
+mod listing;
+mod geojson;
+mod grafana;
+mod wfs;
+mod polyline;
+mod busyness;
+mod alert_stream;
+mod graphql;
+mod api_index;
+mod preflight;
+mod privacy;
+mod snapshot;
+mod sync;
+mod version;
+mod routing;
+mod simulation;
+mod access_log;
+mod client_gen;
+mod refresh_jobs;
+mod gtfs_export;
+mod gtfs_rt_feed;
+mod siri;
+mod vector_tiles;
+mod interpolation;
+mod http_cache;
+mod api_keys;
+#[cfg(test)]
+mod pipeline_tests;
+use tbm_api_models::{BoundingBox, NVTModels, CachedNetworkData};
+use journey_share::JourneyShareStore;
+use history::HistoryStore;
+use elevation::ElevationStore;
+use time_format::TimeOptions;
+use listing::Page;
 
 // Embed static files at compile time
 const INDEX_HTML: &str = include_str!("../static/nvtweb.html");
@@ -18,6 +68,14 @@ const TRANSIT_JS: &str = include_str!("../static/tbm-transit-no-key.js");
 #[derive(Clone)]
 struct AppState {
     cache: Arc<Mutex<CachedNetworkData>>,
+    sandbox: Arc<Mutex<CachedNetworkData>>, // Served under /api/sandbox, see simulation.rs
+    journey_shares: Arc<Mutex<JourneyShareStore>>,
+    history: Arc<Mutex<HistoryStore>>,
+    elevation: Arc<Mutex<ElevationStore>>,
+    alert_events: alert_stream::AlertBroadcaster,
+    refresh_jobs: refresh_jobs::RefreshJobs, // Forced refreshes (POST /api/tbm/refresh)
+    tiles: vector_tiles::TileCache, // Rendered /tiles, see vector_tiles.rs
+    clock: fn() -> chrono::DateTime<chrono_tz::Tz>, // NVTModels::paris_now, fixed by tests
 }
 
 #[derive(Serialize)]
@@ -71,147 +129,2855 @@ async fn serve_js() -> HttpResponse {
 // API Endpoints (keeping your existing ones)
 // ============================================================================
 
-async fn get_network_data(state: web::Data<AppState>) -> HttpResponse {
+#[derive(Deserialize)]
+struct NetworkQuery {
+    #[serde(default)]
+    dedupe: bool,
+    bbox: Option<String>, // minLon,minLat,maxLon,maxLat
+}
+
+/// ?bbox= parsed, or the 400 response for an invalid one
+fn bbox_param(value: Option<&str>) -> Result<Option<BoundingBox>, HttpResponse> {
+    value.map(BoundingBox::parse).transpose()
+        .map_err(|message| HttpResponse::BadRequest().json(ApiResponse::<String>::error(message)))
+}
+
+/// Douglas-Peucker tolerance in meters from ?tolerance= (meters) or ?zoom= (the map zoom the
+/// shapes are drawn at); None leaves the shapes whole
+fn simplify_param(tolerance: Option<f64>, zoom: Option<u8>) -> Result<Option<f64>, HttpResponse> {
+    let bad_request = |message: &str| HttpResponse::BadRequest().json(ApiResponse::<String>::error(message.to_string()));
+    match (tolerance, zoom) {
+        (Some(_), Some(_)) => Err(bad_request("Give either tolerance or zoom, not both")),
+        (Some(tolerance), None) if !tolerance.is_finite() || tolerance < 0.0 => {
+            Err(bad_request("tolerance must be a distance in meters, 0 or more"))
+        }
+        (None, Some(zoom)) if zoom > vector_tiles::MAX_ZOOM => {
+            Err(bad_request(&format!("zoom must be between 0 and {}", vector_tiles::MAX_ZOOM)))
+        }
+        (tolerance, zoom) => Ok(tolerance.or(zoom.map(polyline::zoom_tolerance))),
+    }
+}
+
+fn simplify_shapes(shapes: &mut std::collections::HashMap<String, Vec<tbm_api_models::ShapePoint>>, tolerance: Option<f64>) {
+    if let Some(tolerance) = tolerance {
+        for points in shapes.values_mut() {
+            *points = polyline::simplify(points, tolerance);
+        }
+    }
+}
+
+/// Weak ETag of a response built from the cache, from the versions of the data it depends on
+/// (`last_static_update`, and `last_dynamic_update` unless it only uses static data)
+fn cache_etag(cache: &CachedNetworkData, dynamic: bool) -> String {
+    match dynamic {
+        true => format!("W/\"{}-{}\"", cache.last_static_update, cache.last_dynamic_update),
+        false => format!("W/\"{}\"", cache.last_static_update),
+    }
+}
+
+fn if_none_match(req: &HttpRequest) -> Option<String> {
+    req.headers().get(actix_web::http::header::IF_NONE_MATCH)?.to_str().ok().map(String::from)
+}
+
+/// 304 Not Modified when the request's If-None-Match names `etag` (weak comparison)
+fn not_modified(req: &HttpRequest, etag: &str) -> Option<HttpResponse> {
+    not_modified_for(if_none_match(req).as_deref(), etag)
+}
+
+/// `not_modified` for an If-None-Match taken from the request beforehand
+fn not_modified_for(if_none_match: Option<&str>, etag: &str) -> Option<HttpResponse> {
+    let if_none_match = if_none_match?;
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match.split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+        .then(|| HttpResponse::NotModified().insert_header((actix_web::http::header::ETAG, etag)).finish())
+}
+
+fn with_etag(mut response: HttpResponse, etag: &str) -> HttpResponse {
+    if let Ok(value) = actix_web::http::header::HeaderValue::from_str(etag) {
+        response.headers_mut().insert(actix_web::http::header::ETAG, value);
+    }
+    response
+}
+
+/// Runs the work of a heavy handler, cache lock included, on the blocking thread pool so the
+/// request deadline can answer 504 while it runs (see request_deadline). The deadline only drops
+/// the wait: `work` runs to its end, so it must release the cache before its long part (see
+/// network_snapshot). HttpResponse isn't Send, so it comes back as its status, headers and body,
+/// which `work` must not stream.
+async fn off_the_workers(work: impl FnOnce() -> HttpResponse + Send + 'static) -> HttpResponse {
+    let answered = web::block(move || {
+        let (head, body) = work().into_parts();
+        (head.status(), head.headers().clone(), actix_web::body::MessageBody::try_into_bytes(body).unwrap_or_default())
+    }).await;
+
+    match answered {
+        Ok((status, headers, body)) => {
+            let mut response = HttpResponse::with_body(status, body).map_into_boxed_body();
+            *response.headers_mut() = headers;
+            response
+        }
+        Err(e) => {
+            eprintln!("❌ Handler task failed: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Request failed".to_string()
+                ))
+        }
+    }
+}
+
+/// The ETag of the cache and its network data, taken under the lock. The guard is dropped before
+/// the handler's work starts, so that a task the request deadline gave up on doesn't keep holding
+/// the cache. Err is a 304 when `if_none_match` already has the ETag, or a 500 with `failure`.
+fn network_snapshot(state: &AppState, dynamic: bool, if_none_match: Option<&str>, failure: &str) -> Result<(String, tbm_api_models::NetworkData), HttpResponse> {
+    let cache = state.cache.lock().map_err(|e| {
+        eprintln!("❌ Failed to lock cache: {}", e);
+        HttpResponse::InternalServerError()
+            .json(ApiResponse::<String>::error(failure.to_string()))
+    })?;
+    let etag = cache_etag(&cache, dynamic);
+    if let Some(response) = not_modified_for(if_none_match, &etag) {
+        return Err(response);
+    }
+    Ok((etag, cache.to_network_data()))
+}
+
+async fn get_network_data(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<NetworkQuery>,
+) -> HttpResponse {
+    let bbox = match bbox_param(query.bbox.as_deref()) {
+        Ok(bbox) => bbox,
+        Err(response) => return response,
+    };
+    let if_none_match = if_none_match(&req);
+
+    off_the_workers(move || {
+        let (etag, mut network_data) = match network_snapshot(&state, true, if_none_match.as_deref(), "Failed to retrieve network data") {
+            Ok(snapshot) => snapshot,
+            Err(response) => return response,
+        };
+        println!("📊 Network data requested: {} stops, {} lines, {} shapes",
+                 network_data.stops.len(),
+                 network_data.lines.len(),
+                 network_data.shapes.len());
+
+        if let Some(bbox) = &bbox {
+            network_data = network_data.within(bbox);
+            println!("   ↳ {} stops, {} lines, {} shapes in the bbox",
+                     network_data.stops.len(),
+                     network_data.lines.len(),
+                     network_data.shapes.len());
+        }
+
+        if query.dedupe {
+            let deduped = tbm_api_models::DedupedNetworkData {
+                stops: NVTModels::dedupe_stops(network_data.stops),
+                lines: network_data.lines,
+                shapes: network_data.shapes,
+            };
+            println!("   ↳ Deduplicated to {} stops", deduped.stops.len());
+            return with_etag(HttpResponse::Ok().json(ApiResponse::success(deduped)), &etag);
+        }
+        with_etag(HttpResponse::Ok().json(ApiResponse::success(network_data)), &etag)
+    }).await
+}
+
+#[derive(Deserialize)]
+struct StopsQuery {
+    #[serde(default)]
+    dedupe: bool,
+    #[serde(default)]
+    accessible: bool, // only stops with wheelchair boarding
+    commune: Option<String>, // commune name or INSEE code
+    operator: Option<String>, // stops served by a line of this operator
+    bbox: Option<String>, // minLon,minLat,maxLon,maxLat
+    page: Option<usize>,
+    per_page: Option<usize>,
+    fields: Option<String>, // comma-separated
+    format: Option<String>, // json (default) or geojson
+}
+
+async fn get_stops(state: web::Data<AppState>, req: HttpRequest, query: web::Query<StopsQuery>) -> HttpResponse {
+    let mut known_fields = schema::properties::<tbm_api_models::Stop>();
+    if query.dedupe {
+        known_fields.push("merged_ids".to_string());
+    }
+    let (page, fields) = match (
+        Page::from_query(query.page, query.per_page),
+        listing::parse_fields(query.fields.as_deref(), &known_fields),
+    ) {
+        (Ok(page), Ok(fields)) => (page, fields),
+        (Err(message), _) | (_, Err(message)) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(message));
+        }
+    };
+    let bbox = match bbox_param(query.bbox.as_deref()) {
+        Ok(bbox) => bbox,
+        Err(response) => return response,
+    };
+    let as_geojson = match geojson::wants_geojson(query.format.as_deref()) {
+        Ok(as_geojson) => as_geojson,
+        Err(message) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(message));
+        }
+    };
+    let if_none_match = if_none_match(&req);
+
+    off_the_workers(move || {
+        let (etag, network_data) = match network_snapshot(&state, true, if_none_match.as_deref(), "Failed to retrieve stops") {
+            Ok(snapshot) => snapshot,
+            Err(response) => return response,
+        };
+        let mut stops = network_data.stops;
+
+        if let Some(bbox) = &bbox {
+            stops.retain(|stop| bbox.contains(stop.latitude, stop.longitude));
+        }
+
+        if let Some(commune) = &query.commune {
+            stops.retain(|stop| {
+                stop.insee_code.as_deref() == Some(commune.as_str())
+                    || stop.commune.as_deref().is_some_and(|name| name.eq_ignore_ascii_case(commune))
+            });
+            println!("📍 Stops requested in commune '{}': {} found", commune, stops.len());
+        } else {
+            println!("📍 Stops requested: {} total", stops.len());
+        }
+
+        if let Some(operator) = &query.operator {
+            let line_refs: std::collections::HashSet<&str> = network_data.lines.iter()
+                .filter(|line| operators::matches(&line.operator, operator))
+                .map(|line| line.line_ref.as_str())
+                .collect();
+            stops.retain(|stop| stop.lines.iter().any(|line_ref| line_refs.contains(line_ref.as_str())));
+            println!("   ↳ {} served by {}", stops.len(), operator);
+        }
+
+        if query.accessible {
+            stops.retain(|stop| stop.wheelchair_boarding == Some(true));
+            println!("   ↳ {} wheelchair accessible", stops.len());
+        }
+
+        if query.dedupe {
+            let mut deduped = NVTModels::dedupe_stops(stops);
+            println!("   ↳ Deduplicated to {} stops", deduped.len());
+            if page.is_some() {
+                deduped.sort_by(|a, b| a.stop.stop_id.cmp(&b.stop.stop_id));
+            }
+            return with_etag(list_response(deduped, page, fields.as_deref(), as_geojson), &etag);
+        }
+        if page.is_some() {
+            stops.sort_by(|a, b| a.stop_id.cmp(&b.stop_id));
+        }
+        with_etag(list_response(stops, page, fields.as_deref(), as_geojson), &etag)
+    }).await
+}
+
+/// A list, or one page of it with the totals in headers, optionally reduced to some fields
+/// and as a GeoJSON FeatureCollection of points
+fn list_response<T: Serialize>(items: Vec<T>, page: Option<Page>, fields: Option<&[String]>, as_geojson: bool) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    let items = match page {
+        Some(page) => {
+            let total = items.len();
+            response
+                .insert_header(("X-Total-Count", total.to_string()))
+                .insert_header(("X-Total-Pages", page.total_pages(total).to_string()));
+            page.slice(items)
+        }
+        None => items,
+    };
+
+    if as_geojson {
+        let values: Vec<serde_json::Value> = match fields {
+            Some(fields) => listing::select_fields(&items, fields),
+            None => items.iter().filter_map(|item| serde_json::to_value(item).ok()).collect(),
+        };
+        return response
+            .content_type(geojson::CONTENT_TYPE)
+            .json(geojson::feature_collection(geojson::point_features(values)));
+    }
+    match fields {
+        Some(fields) => response.json(ApiResponse::success(listing::select_fields(&items, fields))),
+        None => response.json(ApiResponse::success(items)),
+    }
+}
+
+#[derive(Deserialize)]
+struct NearbyQuery {
+    lat: Option<f64>,
+    lon: Option<f64>,
+    radius: Option<f64>, // meters
+    limit: Option<usize>,
+}
+
+const DEFAULT_NEARBY_RADIUS: f64 = 500.0;
+const MAX_NEARBY_RADIUS: f64 = 5000.0;
+const MAX_NEARBY_LIMIT: usize = 100;
+
+/// The required ?lat= and ?lon= of /stops/nearby and /around
+fn position_param(lat: Option<f64>, lon: Option<f64>) -> Result<(f64, f64), HttpResponse> {
+    let (Some(lat), Some(lon)) = (lat, lon) else {
+        return Err(HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error("lat and lon are required".to_string())));
+    };
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return Err(HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error(format!("Invalid position {}, {}", lat, lon))));
+    }
+    Ok((lat, lon))
+}
+
+async fn get_nearby_stops(state: web::Data<AppState>, query: web::Query<NearbyQuery>) -> HttpResponse {
+    let (lat, lon) = match position_param(query.lat, query.lon) {
+        Ok(position) => position,
+        Err(response) => return response,
+    };
+    let radius = query.radius.unwrap_or(DEFAULT_NEARBY_RADIUS).clamp(1.0, MAX_NEARBY_RADIUS);
+    let limit = query.limit.unwrap_or(20).clamp(1, MAX_NEARBY_LIMIT);
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let stops = spatial::nearby_stops(&cache, lat, lon, radius, limit);
+            println!("📍 Nearby stops requested at {:.5}, {:.5} ({} m): {} found", lat, lon, radius, stops.len());
+            HttpResponse::Ok().json(ApiResponse::success(stops))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<Vec<spatial::NearbyStop>>::error(
+                    "Failed to retrieve nearby stops".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct StopClustersQuery {
+    bbox: Option<String>, // minLon,minLat,maxLon,maxLat
+    zoom: Option<u8>,
+}
+
+async fn get_stop_clusters(state: web::Data<AppState>, query: web::Query<StopClustersQuery>) -> HttpResponse {
+    let bbox = match bbox_param(query.bbox.as_deref()) {
+        Ok(bbox) => bbox,
+        Err(response) => return response,
+    };
+    let zoom = match query.zoom {
+        Some(zoom) if zoom <= spatial::MAX_ZOOM => zoom,
+        Some(zoom) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(
+                    format!("Invalid zoom {} (expected 0 to {})", zoom, spatial::MAX_ZOOM)
+                ));
+        }
+        None => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error("zoom is required".to_string()));
+        }
+    };
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let clusters = cache.stop_index.clusters(bbox.as_ref(), zoom);
+            println!("📍 Stop clusters requested at zoom {}: {} clusters", zoom, clusters.len());
+            HttpResponse::Ok().json(ApiResponse::success(clusters))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<Vec<spatial::StopCluster>>::error(
+                    "Failed to retrieve stop clusters".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: Option<String>,
+    limit: Option<usize>,
+}
+
+const MAX_SEARCH_LIMIT: usize = 50;
+
+async fn search_network(state: web::Data<AppState>, query: web::Query<SearchQuery>) -> HttpResponse {
+    let Some(q) = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) else {
+        return HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error("q is required".to_string()));
+    };
+    let limit = query.limit.unwrap_or(20).clamp(1, MAX_SEARCH_LIMIT);
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let results = cache.search_index.search(q, limit);
+            println!("🔎 Search '{}': {} results", q, results.len());
+            HttpResponse::Ok().json(ApiResponse::success(results))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<Vec<search::SearchResult>>::error(
+                    "Failed to search".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LinesQuery {
+    operator: Option<String>,
+    mode: Option<String>, // tram, bus, train, ferry or coach
+    page: Option<usize>,
+    per_page: Option<usize>,
+    fields: Option<String>, // comma-separated
+}
+
+async fn get_lines(state: web::Data<AppState>, req: HttpRequest, query: web::Query<LinesQuery>) -> HttpResponse {
+    let known_fields = schema::properties::<tbm_api_models::Line>();
+    let (page, fields) = match (
+        Page::from_query(query.page, query.per_page),
+        listing::parse_fields(query.fields.as_deref(), &known_fields),
+    ) {
+        (Ok(page), Ok(fields)) => (page, fields),
+        (Err(message), _) | (_, Err(message)) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(message));
+        }
+    };
+    let mode = match query.mode.as_deref().map(tbm_api_models::TransportMode::parse).transpose() {
+        Ok(mode) => mode,
+        Err(message) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(message));
+        }
+    };
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let etag = cache_etag(&cache, true);
+            if let Some(response) = not_modified(&req, &etag) {
+                return response;
+            }
+            let mut lines = cache.to_network_data().lines;
+            println!("🚌 Lines requested: {} total", lines.len());
+
+            if let Some(operator) = &query.operator {
+                lines.retain(|line| operators::matches(&line.operator, operator));
+                println!("   ↳ {} operated by {}", lines.len(), operator);
+            }
+            if let Some(mode) = mode {
+                lines.retain(|line| line.mode == Some(mode));
+                println!("   ↳ {} by {}", lines.len(), mode.as_str());
+            }
+            if page.is_some() {
+                lines.sort_by(|a, b| a.line_ref.cmp(&b.line_ref));
+            }
+            with_etag(list_response(lines, page, fields.as_deref(), false), &etag)
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<Vec<tbm_api_models::Line>>::error(
+                    "Failed to retrieve lines".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct VehiclesQuery {
+    bbox: Option<String>, // minLon,minLat,maxLon,maxLat
+    format: Option<String>, // json (default) or geojson
+    zoom: Option<u8>, // Map zoom level; thins vehicles to one per map cell below spatial::FULL_DETAIL_ZOOM
+    since: Option<i64>, // Unix time; only the vehicles changed since, and those removed
+    interpolate: Option<bool>, // Move vehicles with stale fixes along their trip (see interpolation.rs)
+}
+
+async fn get_vehicles(state: web::Data<AppState>, query: web::Query<VehiclesQuery>) -> HttpResponse {
+    let bbox = match bbox_param(query.bbox.as_deref()) {
+        Ok(bbox) => bbox,
+        Err(response) => return response,
+    };
+    let as_geojson = match geojson::wants_geojson(query.format.as_deref()) {
+        Ok(as_geojson) => as_geojson,
+        Err(message) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(message));
+        }
+    };
+    if let Some(zoom) = query.zoom.filter(|&zoom| zoom > spatial::MAX_ZOOM) {
+        return HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error(
+                format!("Invalid zoom {} (expected 0 to {})", zoom, spatial::MAX_ZOOM)
+            ));
+    }
+    if query.since.is_some() && (as_geojson || query.zoom.is_some()) {
+        return HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error(
+                "since cannot be combined with zoom or format=geojson".to_string()
+            ));
+    }
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let now = NVTModels::get_current_timestamp();
+            if let Some(since) = query.since {
+                let mut delta = NVTModels::vehicles_since(&cache, since);
+                if query.interpolate == Some(true) {
+                    interpolation::apply(&mut delta.vehicles, &cache, now);
+                }
+                if let Some(bbox) = &bbox {
+                    delta.vehicles.retain(|v| bbox.contains(v.vehicle.latitude, v.vehicle.longitude));
+                }
+                println!("🚗 Vehicles since {} requested: {} changed, {} removed", since, delta.vehicles.len(), delta.removed.len());
+                return HttpResponse::Ok().json(ApiResponse::success(delta));
+            }
+
+            let mut vehicles = NVTModels::vehicles_with_progress(&cache);
+            println!("🚗 Vehicles requested: {} active", vehicles.len());
+
+            if query.interpolate == Some(true) {
+                interpolation::apply(&mut vehicles, &cache, now);
+                println!("   ↳ {} interpolated", vehicles.iter().filter(|v| v.interpolated.is_some()).count());
+            }
+
+            if let Some(bbox) = &bbox {
+                vehicles.retain(|v| bbox.contains(v.vehicle.latitude, v.vehicle.longitude));
+                println!("   ↳ {} in the bbox", vehicles.len());
+            }
+            if let Some(zoom) = query.zoom {
+                vehicles = spatial::thin_by_zoom(vehicles, zoom, |v| (v.vehicle.latitude, v.vehicle.longitude))
+                    .into_iter()
+                    .map(|(vehicle, cluster_size)| tbm_api_models::VehicleWithProgress { cluster_size: Some(cluster_size), ..vehicle })
+                    .collect();
+                println!("   ↳ {} after thinning at zoom {}", vehicles.len(), zoom);
+            }
+            if as_geojson {
+                return HttpResponse::Ok()
+                    .content_type(geojson::CONTENT_TYPE)
+                    .json(geojson::vehicles(&vehicles, &cache.to_network_data()));
+            }
+            HttpResponse::Ok().json(ApiResponse::success(vehicles))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<Vec<tbm_api_models::RealTimeInfo>>::error(
+                    "Failed to retrieve vehicles".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BikesQuery {
+    bbox: Option<String>, // minLon,minLat,maxLon,maxLat
+}
+
+async fn get_bike_stations(state: web::Data<AppState>, query: web::Query<BikesQuery>) -> HttpResponse {
+    let bbox = match bbox_param(query.bbox.as_deref()) {
+        Ok(bbox) => bbox,
+        Err(response) => return response,
+    };
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let mut stations = cache.bike_share.stations.clone();
+            println!("🚲 Bike stations requested: {} stations", stations.len());
+
+            if let Some(bbox) = &bbox {
+                stations.retain(|station| bbox.contains(station.latitude, station.longitude));
+                println!("   ↳ {} in the bbox", stations.len());
+            }
+            HttpResponse::Ok().json(ApiResponse::success(stations))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<Vec<gbfs::BikeStation>>::error(
+                    "Failed to retrieve bike stations".to_string()
+                ))
+        }
+    }
+}
+
+async fn get_bike_station(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let station_id = path.into_inner();
+
+    match state.cache.lock() {
+        Ok(cache) => match cache.bike_share.station(&station_id) {
+            Some(station) => {
+                println!("🚲 Bike station retrieved: {} ({} bikes)", station_id, station.bikes_available);
+                HttpResponse::Ok().json(ApiResponse::success(station.clone()))
+            }
+            None => {
+                println!("⚠️  Bike station not found: {}", station_id);
+                HttpResponse::NotFound()
+                    .json(ApiResponse::<String>::error(
+                        format!("Bike station '{}' not found", station_id)
+                    ))
+            }
+        },
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve bike station".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ShapesQuery {
+    line: Option<String>, // line code
+    bbox: Option<String>, // minLon,minLat,maxLon,maxLat
+    format: Option<String>, // json (default), polyline or geojson
+    tolerance: Option<f64>, // Douglas-Peucker tolerance in meters
+    zoom: Option<u8>, // Or the map zoom to simplify for
+}
+
+async fn get_shapes(state: web::Data<AppState>, req: HttpRequest, query: web::Query<ShapesQuery>) -> HttpResponse {
+    let bbox = match bbox_param(query.bbox.as_deref()) {
+        Ok(bbox) => bbox,
+        Err(response) => return response,
+    };
+    let format = query.format.as_deref().map(str::to_lowercase);
+    if let Some(other) = format.as_deref().filter(|f| !["json", "polyline", "geojson"].contains(f)) {
+        return HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error(format!("Unknown format '{}' (expected json, polyline or geojson)", other)));
+    }
+    let tolerance = match simplify_param(query.tolerance, query.zoom) {
+        Ok(tolerance) => tolerance,
+        Err(response) => return response,
+    };
+    let if_none_match = if_none_match(&req);
+
+    off_the_workers(move || {
+        // Shapes only change with the static data
+        let (etag, mut network_data) = match network_snapshot(&state, false, if_none_match.as_deref(), "Failed to retrieve shapes") {
+            Ok(snapshot) => snapshot,
+            Err(response) => return response,
+        };
+        if let Some(bbox) = &bbox {
+            network_data.shapes.retain(|_, points| bbox.crosses_shape(points));
+        }
+        if let Some(code) = &query.line {
+            let shape_ids: std::collections::HashSet<String> = network_data.lines.iter()
+                .filter(|line| line.line_code.eq_ignore_ascii_case(code))
+                .flat_map(|line| line.shape_ids.iter().cloned())
+                .collect();
+            network_data.shapes.retain(|shape_id, _| shape_ids.contains(shape_id));
+        }
+        simplify_shapes(&mut network_data.shapes, tolerance);
+        println!("🗺️  Shapes requested: {}", network_data.shapes.len());
+
+        match format.as_deref() {
+            Some("polyline") => {
+                let encoded: std::collections::HashMap<&String, String> = network_data.shapes.iter()
+                    .map(|(shape_id, points)| (shape_id, polyline::encode(points)))
+                    .collect();
+                with_etag(HttpResponse::Ok().json(ApiResponse::success(encoded)), &etag)
+            }
+            Some("geojson") => with_etag(HttpResponse::Ok()
+                .content_type(geojson::CONTENT_TYPE)
+                .json(geojson::shapes(&network_data)), &etag),
+            _ => with_etag(HttpResponse::Ok().json(ApiResponse::success(network_data.shapes)), &etag),
+        }
+    }).await
+}
+
+/// Vector tile of the stops and shapes (see vector_tiles.rs)
+async fn get_vector_tile(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+) -> HttpResponse {
+    let (z, x, y) = path.into_inner();
+    let tile = match vector_tiles::TileId::parse(&z, &x, &y) {
+        Ok(tile) => tile,
+        Err(message) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(message));
+        }
+    };
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            // Stops and shapes only change with the static data
+            let etag = cache_etag(&cache, false);
+            if let Some(response) = not_modified(&req, &etag) {
+                return response;
+            }
+            let body = match state.tiles.get(&etag, &tile) {
+                Some(body) => body,
+                None => {
+                    let body = vector_tiles::render(&tile, &cache.to_network_data());
+                    println!("🧩 Vector tile {}/{}/{} rendered: {} bytes", tile.z, tile.x, tile.y, body.len());
+                    state.tiles.insert(&etag, tile, body.clone());
+                    body
+                }
+            };
+            with_etag(HttpResponse::Ok()
+                .content_type(vector_tiles::CONTENT_TYPE)
+                .body(body), &etag)
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to render tile".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AlertsQuery {
+    route: Option<String>, // GTFS route_id or line code
+    stop: Option<String>,
+    severity_min: Option<u32>,
+    active_at: Option<String>, // Unix timestamp or YYYY-MM-DD
+    expand: Option<String>, // "stops" lists affected_stop_ids
+    lang: Option<String>, // Overrides Accept-Language
+}
+
+async fn get_alerts(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<AlertsQuery>,
+) -> HttpResponse {
+    let active_at = match query.active_at.as_deref().map(|value| (value, parse_time_param(value))) {
+        None => None,
+        Some((_, Some(timestamp))) => Some(timestamp),
+        Some((value, None)) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(
+                    format!("Invalid time '{}' (expected unix timestamp or YYYY-MM-DD)", value)
+                ));
+        }
+    };
+
+    let expand_stops = match query.expand.as_deref() {
+        None => false,
+        Some("stops") => true,
+        Some(other) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(
+                    format!("Unknown expand '{}' (expected stops)", other)
+                ));
+        }
+    };
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            // Accept a GTFS route_id or a line code, as /alerts/history does
+            let route_ids = query.route.as_ref().map(|route| {
+                let mut ids = vec![route.clone()];
+                for line in cache.to_network_data().lines.iter().filter(|l| l.line_code.eq_ignore_ascii_case(route)) {
+                    ids.push(line.route_id.clone());
+                    ids.push(line.line_ref.clone());
+                }
+                ids
+            });
+            let filter = tbm_api_models::AlertFilter {
+                route_ids,
+                stop_id: query.stop.clone(),
+                severity_min: query.severity_min,
+                active_at,
+            };
+
+            let languages = alert_languages(&req, query.lang.as_deref());
+            let alerts: Vec<tbm_api_models::AlertInfo> = filter.apply(&cache.alerts, &cache.alert_stops).into_iter()
+                .map(|alert| {
+                    let mut alert = alert.localized(&languages);
+                    if expand_stops {
+                        alert.affected_stop_ids = Some(cache.alert_stops.get(&alert.id).cloned().unwrap_or_default());
+                    }
+                    alert
+                })
+                .collect();
+            println!("⚠️  Alerts requested: {} of {} active", alerts.len(), cache.alerts.len());
+            HttpResponse::Ok().json(ApiResponse::success(alerts))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<Vec<tbm_api_models::AlertInfo>>::error(
+                    "Failed to retrieve alerts".to_string()
+                ))
+        }
+    }
+}
+
+/// Server-Sent Events of alert changes, sent after each refresh (see alert_stream.rs)
+async fn stream_alerts(state: web::Data<AppState>) -> HttpResponse {
+    println!("📣 Alert stream opened");
+    HttpResponse::Ok()
+        .content_type(alert_stream::CONTENT_TYPE)
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("Content-Encoding", "identity")) // Keeps Compress from buffering events
+        .body(state.alert_events.subscribe())
+}
+
+#[derive(Deserialize)]
+struct AlertHistoryQuery {
+    from: Option<String>,
+    to: Option<String>,
+    route: Option<String>,
+}
+
+/// Languages to give alerts in, most preferred first: ?lang= when given, else those of the
+/// Accept-Language header by quality ("en-GB,en;q=0.8,fr;q=0.5")
+fn alert_languages(req: &HttpRequest, lang: Option<&str>) -> Vec<String> {
+    if let Some(lang) = lang.filter(|lang| !lang.is_empty()) {
+        return vec![lang.to_string()];
+    }
+    let header = req.headers().get("accept-language").and_then(|h| h.to_str().ok()).unwrap_or_default();
+    let mut languages: Vec<(f32, &str)> = header.split(',')
+        .filter_map(|part| {
+            let mut params = part.split(';');
+            let tag = params.next()?.trim();
+            let quality = match params.find_map(|param| param.trim().strip_prefix("q=")) {
+                Some(quality) => quality.parse::<f32>().ok()?,
+                None => 1.0,
+            };
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((quality, tag))
+        })
+        .collect();
+    languages.sort_by(|a, b| b.0.total_cmp(&a.0)); // Stable, equal qualities keep the header order
+    languages.into_iter().map(|(_, tag)| tag.to_string()).collect()
+}
+
+/// Unix timestamp, or a YYYY-MM-DD date taken as midnight Europe/Paris
+fn parse_time_param(value: &str) -> Option<i64> {
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Some(timestamp);
+    }
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .and_then(|midnight| midnight.and_local_timezone(chrono_tz::Europe::Paris).earliest())
+        .map(|dt| dt.timestamp())
+}
+
+/// ?from= / ?to= parsed, or the 400 response for an invalid one
+fn time_range_params(from: Option<&str>, to: Option<&str>) -> Result<(Option<i64>, Option<i64>), HttpResponse> {
+    let parse = |value: Option<&str>| match value {
+        None => Ok(None),
+        Some(value) => parse_time_param(value).map(Some).ok_or_else(|| {
+            HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(
+                    format!("Invalid time '{}' (expected unix timestamp or YYYY-MM-DD)", value)
+                ))
+        }),
+    };
+    Ok((parse(from)?, parse(to)?))
+}
+
+async fn get_alert_history(
+    state: web::Data<AppState>,
+    query: web::Query<AlertHistoryQuery>,
+) -> HttpResponse {
+    let (from, to) = match time_range_params(query.from.as_deref(), query.to.as_deref()) {
+        Ok(range) => range,
+        Err(response) => return response,
+    };
+
+    // Accept a GTFS route_id or a line code
+    let route_ids: Option<Vec<String>> = match (&query.route, state.cache.lock()) {
+        (None, _) => None,
+        (Some(route), Ok(cache)) => {
+            let mut ids = vec![route.clone()];
+            for line in cache.to_network_data().lines.iter().filter(|l| l.line_code.eq_ignore_ascii_case(route)) {
+                ids.push(line.route_id.clone());
+                ids.push(line.line_ref.clone());
+            }
+            Some(ids)
+        }
+        (Some(_), Err(e)) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve alert history".to_string()
+                ));
+        }
+    };
+
+    match state.history.lock() {
+        Ok(history) => {
+            let alerts = history.query_alerts(from, to, route_ids.as_deref());
+            println!("📚 Alert history requested: {} alerts", alerts.len());
+            HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
+                "from": from,
+                "to": to,
+                "route": query.route,
+                "count": alerts.len(),
+                "alerts": alerts,
+            })))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock history: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve alert history".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct HistoryExportQuery {
+    table: Option<String>, // alerts (default) or delays
+    from: Option<String>,
+    to: Option<String>,
+    format: Option<String>, // csv (default), json or parquet
+}
+
+/// Archived alerts or trip delays as a file for analysis tools (pandas, DuckDB, spreadsheets)
+async fn export_history(
+    state: web::Data<AppState>,
+    query: web::Query<HistoryExportQuery>,
+) -> HttpResponse {
+    let table = query.table.as_deref().unwrap_or("alerts").to_string();
+    if table != "alerts" && table != "delays" {
+        return HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error(
+                format!("Table '{}' is unknown (available: alerts, delays)", table)
+            ));
+    }
+    let format = query.format.as_deref().unwrap_or("csv").to_lowercase();
+    if !["csv", "json", "parquet"].contains(&format.as_str()) {
+        return HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error(
+                format!("Unsupported format '{}' (expected csv, json or parquet)", format)
+            ));
+    }
+    let (from, to) = match time_range_params(query.from.as_deref(), query.to.as_deref()) {
+        Ok(range) => range,
+        Err(response) => return response,
+    };
+
+    let history = state.history.clone();
+    off_the_workers(move || {
+        let failure = || HttpResponse::InternalServerError()
+            .json(ApiResponse::<String>::error(
+                "Failed to export history".to_string()
+            ));
+        let exported = if table == "delays" {
+            // The archive file is read with the history unlocked
+            let (archive, running) = match history.lock() {
+                Ok(history) => (history.delays_path.clone(), history.trips.values().cloned().collect()),
+                Err(e) => {
+                    eprintln!("❌ Failed to lock history: {}", e);
+                    return failure();
+                }
+            };
+            let delays = HistoryStore::query_delays(archive.as_deref(), running, from, to);
+            let delays: Vec<&history::ArchivedDelay> = delays.iter().collect();
+            println!("📤 History export requested: {} trip delays as {}", delays.len(), format);
+            match format.as_str() {
+                "json" => serde_json::to_vec(&delays).map_err(|e| e.to_string()),
+                "parquet" => Ok(HistoryStore::delays_parquet(&delays)),
+                _ => HistoryStore::delays_csv(&delays).map(String::into_bytes).map_err(|e| e.to_string()),
+            }
+        } else {
+            let history = match history.lock() {
+                Ok(history) => history,
+                Err(e) => {
+                    eprintln!("❌ Failed to lock history: {}", e);
+                    return failure();
+                }
+            };
+            let alerts = history.query_alerts(from, to, None);
+            println!("📤 History export requested: {} alerts as {}", alerts.len(), format);
+            match format.as_str() {
+                "json" => serde_json::to_vec(&alerts).map_err(|e| e.to_string()),
+                "parquet" => Ok(HistoryStore::alerts_parquet(&alerts)),
+                _ => HistoryStore::alerts_csv(&alerts).map(String::into_bytes).map_err(|e| e.to_string()),
+            }
+        };
+
+        let content_type = match format.as_str() {
+            "json" => "application/json",
+            "parquet" => "application/vnd.apache.parquet",
+            _ => "text/csv; charset=utf-8",
+        };
+        match exported {
+            Ok(body) => HttpResponse::Ok()
+                .content_type(content_type)
+                .insert_header(("Content-Disposition", format!("attachment; filename=\"{}.{}\"", table, format)))
+                .body(body),
+            Err(e) => {
+                eprintln!("❌ Failed to export history: {}", e);
+                failure()
+            }
+        }
+    }).await
+}
+
+#[derive(Deserialize)]
+struct SyncQuery {
+    static_since: Option<u64>, // last_static_update of the standby; the static data is left out when it matches
+}
+
+/// Cache snapshots for a standby instance (see sync.rs)
+async fn get_sync_snapshot(
+    state: web::Data<AppState>,
+    query: web::Query<SyncQuery>,
+) -> HttpResponse {
+    // The token itself is checked by the API key guard
+    if config::get().sync.as_ref().and_then(config::SyncConfig::resolve_token).is_none() {
+        return HttpResponse::NotFound()
+            .json(ApiResponse::<String>::error(
+                "Sync is not enabled on this instance".to_string()
+            ));
+    }
+
+    let payload = match state.cache.lock() {
+        Ok(cache) => sync::payload(&cache, query.static_since),
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve sync snapshot".to_string()
+                ));
+        }
+    };
+    println!("🔁 Sync snapshot requested: {}",
+             if payload.static_data.is_some() { "static and real-time data" } else { "real-time data" });
+    HttpResponse::Ok().json(payload)
+}
+
+/// The 404 response of a simulation request to an instance without a simulation token, if any;
+/// the token itself is checked by the API key guard
+fn simulation_refusal() -> Option<HttpResponse> {
+    config::get().simulation.as_ref().and_then(config::SimulationConfig::resolve_token).is_none().then(|| {
+        HttpResponse::NotFound()
+            .json(ApiResponse::<String>::error(
+                "Simulation is not enabled on this instance".to_string()
+            ))
+    })
+}
+
+#[derive(Deserialize)]
+struct SimulateQuery {
+    source: Option<String>, // Feed the fragment is merged into: tbm (default), naq or sncf
+}
+
+/// Merge a GTFS fragment (zip body) into a copy of the live cache, served under /api/sandbox
+async fn post_simulation(
+    state: web::Data<AppState>,
+    query: web::Query<SimulateQuery>,
+    body: web::Bytes,
+) -> HttpResponse {
+    if let Some(refusal) = simulation_refusal() {
+        return refusal;
+    }
+    let source = query.source.as_deref().unwrap_or("tbm").to_lowercase();
+
+    // Unzipping and parsing the fragment, then cloning the cache, is blocking work
+    off_the_workers(move || {
+        let fragment = match NVTModels::parse_gtfs_fragment(&source, &body) {
+            Ok(fragment) => fragment,
+            Err(e) => {
+                println!("⚠️  Rejected simulation fragment: {}", e);
+                return HttpResponse::BadRequest()
+                    .json(ApiResponse::<String>::error(
+                        format!("Invalid GTFS fragment: {}", e)
+                    ));
+            }
+        };
+
+        let result = state.cache.lock()
+            .map_err(|e| tbm_api_models::NVTError::NetworkError(format!("Failed to lock cache: {}", e)))
+            .map(|live| live.clone())
+            .and_then(|sandbox| simulation::simulate(sandbox, &source, fragment));
+
+        match result {
+            Ok((sandbox, summary)) => match state.sandbox.lock() {
+                Ok(mut current) => {
+                    *current = sandbox;
+                    println!("🧪 Simulation loaded into the sandbox: {} fragment with {} trips and {} stops",
+                             summary.source, summary.trips, summary.stops);
+                    HttpResponse::Ok().json(ApiResponse::success(summary))
+                }
+                Err(e) => {
+                    eprintln!("❌ Failed to lock sandbox: {}", e);
+                    HttpResponse::InternalServerError()
+                        .json(ApiResponse::<String>::error(
+                            "Failed to store simulation".to_string()
+                        ))
+                }
+            },
+            Err(e) => {
+                eprintln!("⚠️  Simulation failed: {}", e);
+                HttpResponse::InternalServerError()
+                    .json(ApiResponse::<String>::error(
+                        format!("Simulation failed: {}", e)
+                    ))
+            }
+        }
+    }).await
+}
+
+/// Drop the sandbox
+async fn delete_simulation(
+    state: web::Data<AppState>,
+) -> HttpResponse {
+    if let Some(refusal) = simulation_refusal() {
+        return refusal;
+    }
+    match state.sandbox.lock() {
+        Ok(mut sandbox) => {
+            *sandbox = CachedNetworkData::empty(sandbox.upstreams.clone());
+            println!("🧪 Sandbox cleared");
+            HttpResponse::Ok().json(ApiResponse::success("Sandbox cleared"))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock sandbox: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to clear sandbox".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LangQuery {
+    lang: Option<String>, // Language of the alerts, overrides Accept-Language
+}
+
+async fn get_stop_by_id(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    req: HttpRequest,
+    query: web::Query<LangQuery>,
+) -> HttpResponse {
+    let stop_id = path.into_inner();
+    let languages = alert_languages(&req, query.lang.as_deref());
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let network_data = cache.to_network_data();
+            match network_data.stops.iter().find(|s| s.stop_id == stop_id) {
+                Some(stop) => {
+                    println!("📍 Stop retrieved: {} ({})", stop.stop_name, stop.stop_id);
+                    let stop = tbm_api_models::Stop {
+                        alerts: stop.alerts.iter().map(|alert| alert.localized(&languages)).collect(),
+                        ..stop.clone()
+                    };
+                    HttpResponse::Ok().json(ApiResponse::success(stop))
+                }
+                None => {
+                    println!("⚠️  Stop not found: {}", stop_id);
+                    HttpResponse::NotFound()
+                        .json(ApiResponse::<String>::error(
+                            format!("Stop '{}' not found", stop_id)
+                        ))
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve stop".to_string()
+                ))
+        }
+    }
+}
+
+async fn get_line_by_code(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let line_code = path.into_inner();
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let network_data = cache.to_network_data();
+            match network_data.lines.iter().find(|l|
+                l.line_code.eq_ignore_ascii_case(&line_code)
+            ) {
+                Some(line) => {
+                    println!("🚌 Line retrieved: {} ({}) - {}",
+                             line.line_code, line.line_name, line.operator);
+                    HttpResponse::Ok().json(ApiResponse::success(line))
+                }
+                None => {
+                    println!("⚠️  Line not found: {}", line_code);
+                    HttpResponse::NotFound()
+                        .json(ApiResponse::<String>::error(
+                            format!("Line '{}' not found", line_code)
+                        ))
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve line".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LineStopsQuery {
+    direction: Option<u32>, // GTFS direction_id, 0 (default) or 1
+}
+
+async fn get_line_stops(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<LineStopsQuery>,
+) -> HttpResponse {
+    let line_code = path.into_inner();
+    let direction = query.direction.unwrap_or(0);
+    if direction > 1 {
+        return HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error(format!("Invalid direction {} (expected 0 or 1)", direction)));
+    }
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let network_data = cache.to_network_data();
+            let Some(line) = network_data.lines.iter().find(|l| l.line_code.eq_ignore_ascii_case(&line_code)) else {
+                println!("⚠️  Line not found: {}", line_code);
+                return HttpResponse::NotFound()
+                    .json(ApiResponse::<String>::error(
+                        format!("Line '{}' not found", line_code)
+                    ));
+            };
+
+            let stops_by_id: std::collections::HashMap<&str, &tbm_api_models::Stop> = network_data.stops.iter()
+                .map(|stop| (stop.stop_id.as_str(), stop))
+                .collect();
+            let stops: Vec<tbm_api_models::LineStop> = NVTModels::line_stop_sequence(line, direction, &cache).iter()
+                .filter_map(|(stop_sequence, stop_id)| {
+                    stops_by_id.get(stop_id.as_str()).map(|stop| tbm_api_models::LineStop {
+                        stop_sequence: *stop_sequence,
+                        stop: (*stop).clone(),
+                    })
+                })
+                .collect();
+            println!("🚏 Stops of line {} (direction {}): {}", line.line_code, direction, stops.len());
+            HttpResponse::Ok().json(ApiResponse::success(stops))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve line stops".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LineTimetableQuery {
+    stop: Option<String>,
+    date: Option<String>, // YYYY-MM-DD, today by default
+    tz: Option<String>,
+    time_format: Option<String>,
+}
+
+/// Every departure of a line at a stop on a service date, from calendar.txt and calendar_dates.txt
+async fn get_line_timetable(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<LineTimetableQuery>,
+) -> HttpResponse {
+    let line_code = path.into_inner();
+    let Some(stop_id) = query.stop.as_deref() else {
+        return HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error("stop is required".to_string()));
+    };
+    let date = match query.date.as_deref() {
+        None => (state.clock)().date_naive(),
+        Some(value) => match chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            Ok(date) => date,
+            Err(_) => {
+                return HttpResponse::BadRequest()
+                    .json(ApiResponse::<String>::error(
+                        format!("Invalid date '{}' (expected YYYY-MM-DD)", value)
+                    ));
+            }
+        },
+    };
+    let time_options = match TimeOptions::from_query(query.tz.as_deref(), query.time_format.as_deref()) {
+        Ok(options) => options,
+        Err(message) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(message));
+        }
+    };
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let network_data = cache.to_network_data();
+            let Some(line) = network_data.lines.iter().find(|l| l.line_code.eq_ignore_ascii_case(&line_code)) else {
+                println!("⚠️  Line not found: {}", line_code);
+                return HttpResponse::NotFound()
+                    .json(ApiResponse::<String>::error(
+                        format!("Line '{}' not found", line_code)
+                    ));
+            };
+
+            let departures = NVTModels::line_timetable(line, stop_id, date, &cache);
+            println!("📅 Timetable of line {} at stop {} on {}: {} departures", line.line_code, stop_id, date, departures.len());
+            let timed: Vec<tbm_api_models::TimedArrival> = departures.iter()
+                .map(|arrival| tbm_api_models::TimedArrival {
+                    arrival,
+                    arrival_at: time_options.timestamp(date, &arrival.arrival_time),
+                    departure_at: time_options.timestamp(date, &arrival.departure_time),
+                })
+                .collect();
+            HttpResponse::Ok().json(ApiResponse::success(timed))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve timetable".to_string()
+                ))
+        }
+    }
+}
+
+/// Headways of a line on representative days, as computed at the last static load (see headways.rs)
+async fn get_line_frequency(state: web::Data<AppState>, path: web::Path<String>) -> HttpResponse {
+    let line_code = path.into_inner();
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let network_data = cache.to_network_data();
+            let Some(line) = network_data.lines.iter().find(|l| l.line_code.eq_ignore_ascii_case(&line_code)) else {
+                println!("⚠️  Line not found: {}", line_code);
+                return HttpResponse::NotFound()
+                    .json(ApiResponse::<String>::error(
+                        format!("Line '{}' not found", line_code)
+                    ));
+            };
+
+            let headways: &[headways::Headways] = cache.headways.route(&line.route_id);
+            println!("⏱️  Frequency of line {}: {} day and direction entries", line.line_code, headways.len());
+            HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
+                "line_code": line.line_code,
+                "route_id": line.route_id,
+                "headways": headways,
+            })))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve frequency".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LineShapeQuery {
+    format: Option<String>, // json (default), polyline or geojson
+    tolerance: Option<f64>, // Douglas-Peucker tolerance in meters
+    zoom: Option<u8>, // Or the map zoom to simplify for
+}
+
+/// Shapes of one line, without pulling the whole shapes map through /network
+async fn get_line_shape(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<LineShapeQuery>,
+) -> HttpResponse {
+    let line_code = path.into_inner();
+    let format = query.format.as_deref().map(str::to_lowercase);
+    if let Some(other) = format.as_deref().filter(|f| !["json", "polyline", "geojson"].contains(f)) {
+        return HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error(format!("Unknown format '{}' (expected json, polyline or geojson)", other)));
+    }
+    let tolerance = match simplify_param(query.tolerance, query.zoom) {
+        Ok(tolerance) => tolerance,
+        Err(response) => return response,
+    };
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let mut network_data = cache.to_network_data();
+            let Some(line) = network_data.lines.iter().find(|l| l.line_code.eq_ignore_ascii_case(&line_code)) else {
+                println!("⚠️  Line not found: {}", line_code);
+                return HttpResponse::NotFound()
+                    .json(ApiResponse::<String>::error(
+                        format!("Line '{}' not found", line_code)
+                    ));
+            };
+            let shape_ids: std::collections::HashSet<String> = line.shape_ids.iter().cloned().collect();
+            println!("🗺️  Shape of line {} requested: {} shapes", line.line_code, shape_ids.len());
+            network_data.shapes.retain(|shape_id, _| shape_ids.contains(shape_id));
+            simplify_shapes(&mut network_data.shapes, tolerance);
+
+            match format.as_deref() {
+                Some("polyline") => {
+                    let encoded: std::collections::HashMap<&String, String> = network_data.shapes.iter()
+                        .map(|(shape_id, points)| (shape_id, polyline::encode(points)))
+                        .collect();
+                    HttpResponse::Ok().json(ApiResponse::success(encoded))
+                }
+                Some("geojson") => HttpResponse::Ok()
+                    .content_type(geojson::CONTENT_TYPE)
+                    .json(geojson::shapes(&network_data)),
+                _ => HttpResponse::Ok().json(ApiResponse::success(network_data.shapes)),
+            }
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve line shape".to_string()
+                ))
+        }
+    }
+}
+
+/// Live vehicles of a line as GeoJSON points, for GIS tools polling a URL (uMap, QGIS)
+async fn get_line_vehicles_geojson(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let line_code = path.into_inner();
+
+    off_the_workers(move || {
+        // Released before the work, see network_snapshot
+        let (network_data, vehicles) = match state.cache.lock() {
+            Ok(cache) => {
+                let vehicles: Vec<tbm_api_models::VehicleWithProgress<'static>> = NVTModels::vehicles_with_progress(&cache).into_iter()
+                    .map(|vehicle| tbm_api_models::VehicleWithProgress { vehicle: std::borrow::Cow::Owned(vehicle.vehicle.into_owned()), ..vehicle })
+                    .collect();
+                (cache.to_network_data(), vehicles)
+            }
+            Err(e) => {
+                eprintln!("❌ Failed to lock cache: {}", e);
+                return HttpResponse::InternalServerError()
+                    .json(ApiResponse::<String>::error(
+                        "Failed to retrieve vehicles".to_string()
+                    ));
+            }
+        };
+        let Some(line) = network_data.lines.iter().find(|l| l.line_code.eq_ignore_ascii_case(&line_code)) else {
+            println!("⚠️  Line not found: {}", line_code);
+            return HttpResponse::NotFound()
+                .json(ApiResponse::<String>::error(
+                    format!("Line '{}' not found", line_code)
+                ));
+        };
+
+        let vehicles: Vec<tbm_api_models::VehicleWithProgress> = vehicles.into_iter()
+            .filter(|v| line.real_time.iter().any(|rt| rt.vehicle_id == v.vehicle.vehicle_id))
+            .collect();
+        println!("🚗 Vehicles of line {} requested as GeoJSON: {}", line.line_code, vehicles.len());
+        HttpResponse::Ok()
+            .content_type(geojson::CONTENT_TYPE)
+            .json(geojson::vehicles(&vehicles, &network_data))
+    }).await
+}
+
+async fn get_line_badge(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let line_code = path.into_inner();
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let network_data = cache.to_network_data();
+            match network_data.lines.iter().find(|l|
+                l.line_code.eq_ignore_ascii_case(&line_code)
+            ) {
+                Some(line) => {
+                    let svg = branding::render_line_badge(&line.line_code, &line.color, &line.text_color);
+                    HttpResponse::Ok()
+                        .content_type("image/svg+xml")
+                        .insert_header(("Cache-Control", "public, max-age=86400"))
+                        .body(svg)
+                }
+                None => {
+                    println!("⚠️  Line not found: {}", line_code);
+                    HttpResponse::NotFound()
+                        .json(ApiResponse::<String>::error(
+                            format!("Line '{}' not found", line_code)
+                        ))
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to render line badge".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MapImageQuery {
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+async fn get_line_map(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<MapImageQuery>,
+) -> HttpResponse {
+    let line_code = path.into_inner();
+
+    // Collect what the renderer needs, then draw without holding the cache lock
+    let map_data = match state.cache.lock() {
+        Ok(cache) => {
+            let network_data = cache.to_network_data();
+            network_data.lines.iter()
+                .find(|l| l.line_code.eq_ignore_ascii_case(&line_code))
+                .map(|line| {
+                    let shapes: Vec<Vec<tbm_api_models::ShapePoint>> = line.shape_ids.iter()
+                        .filter_map(|id| cache.gtfs_caches().into_iter().find_map(|g| g.shapes.get(id)))
+                        .cloned()
+                        .collect();
+                    let stops: Vec<tbm_api_models::Stop> = network_data.stops.iter()
+                        .filter(|s| s.lines.contains(&line.route_id) || s.lines.contains(&line.line_ref))
+                        .cloned()
+                        .collect();
+                    (line.clone(), shapes, stops)
+                })
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to render line map".to_string()
+                ));
+        }
+    };
+
+    match map_data {
+        Some((line, shapes, stops)) => {
+            let shape_refs: Vec<_> = shapes.iter().collect();
+            let stop_refs: Vec<_> = stops.iter().collect();
+            let png = static_map::render_line_map(
+                &line,
+                &shape_refs,
+                &stop_refs,
+                query.width.unwrap_or(static_map::DEFAULT_WIDTH),
+                query.height.unwrap_or(static_map::DEFAULT_HEIGHT),
+            );
+            println!("🗺️  Map rendered for line {}: {} shapes, {} stops", line.line_code, shapes.len(), stops.len());
+            HttpResponse::Ok()
+                .content_type("image/png")
+                .insert_header(("Cache-Control", "public, max-age=3600"))
+                .body(png)
+        }
+        None => {
+            println!("⚠️  Line not found: {}", line_code);
+            HttpResponse::NotFound()
+                .json(ApiResponse::<String>::error(
+                    format!("Line '{}' not found", line_code)
+                ))
+        }
+    }
+}
+
+async fn get_line_elevation(state: web::Data<AppState>, path: web::Path<String>) -> HttpResponse {
+    let line_code = path.into_inner();
+
+    let line_shapes = match state.cache.lock() {
+        Ok(cache) => {
+            let network_data = cache.to_network_data();
+            network_data.lines.iter()
+                .find(|l| l.line_code.eq_ignore_ascii_case(&line_code))
+                .map(|line| {
+                    let shapes: Vec<(String, Vec<tbm_api_models::ShapePoint>)> = line.shape_ids.iter()
+                        .filter_map(|id| {
+                            let points = cache.gtfs_caches().into_iter().find_map(|g| g.shapes.get(id))?;
+                            Some((id.clone(), points.clone()))
+                        })
+                        .collect();
+                    (line.line_code.clone(), line.operator.clone(), shapes)
+                })
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to compute elevation profile".to_string()
+                ));
+        }
+    };
+
+    let Some((code, operator, shapes)) = line_shapes else {
+        println!("⚠️  Line not found: {}", line_code);
+        return HttpResponse::NotFound()
+            .json(ApiResponse::<String>::error(
+                format!("Line '{}' not found", line_code)
+            ));
+    };
+
+    // Profiles already cached, and the shapes still to look up
+    let (mut profiles, missing) = match state.elevation.lock() {
+        Ok(store) => {
+            let mut cached = Vec::new();
+            let mut missing = Vec::new();
+            for (shape_id, points) in shapes {
+                match store.get(&shape_id) {
+                    Some(profile) => cached.push(profile.clone()),
+                    None => missing.push((shape_id, points)),
+                }
+            }
+            (cached, missing)
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock elevation cache: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to compute elevation profile".to_string()
+                ));
+        }
+    };
+
+    if !missing.is_empty() {
+        let computed = tokio::task::spawn_blocking(move || {
+            missing.iter()
+                .map(|(shape_id, points)| elevation::compute_profile(shape_id, points))
+                .collect::<tbm_api_models::Result<Vec<_>>>()
+        }).await;
+
+        match computed {
+            Ok(Ok(computed)) => {
+                if let Ok(mut store) = state.elevation.lock() {
+                    for profile in &computed {
+                        store.insert(profile.clone());
+                    }
+                    if let Err(e) = store.save() {
+                        eprintln!("⚠️  Failed to save elevation cache: {}", e);
+                    }
+                }
+                profiles.extend(computed);
+            }
+            Ok(Err(e)) => {
+                eprintln!("⚠️  Elevation lookup failed for line {}: {}", code, e);
+                return HttpResponse::BadGateway()
+                    .json(ApiResponse::<String>::error(
+                        "Elevation service unavailable".to_string()
+                    ));
+            }
+            Err(e) => {
+                eprintln!("❌ Elevation task panicked: {}", e);
+                return HttpResponse::InternalServerError()
+                    .json(ApiResponse::<String>::error(
+                        "Failed to compute elevation profile".to_string()
+                    ));
+            }
+        }
+    }
+
+    profiles.sort_by(|a, b| a.shape_id.cmp(&b.shape_id));
+    println!("⛰️  Elevation requested for line {}: {} shapes", code, profiles.len());
+    HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
+        "line_code": code,
+        "operator": operator,
+        "shapes": profiles,
+    })))
+}
+
+async fn get_lines_by_operator(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let operator = path.into_inner();
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let network_data = cache.to_network_data();
+            let filtered_lines: Vec<_> = network_data.lines
+                .into_iter()
+                .filter(|l| operators::matches(&l.operator, &operator))
+                .collect();
+
+            if filtered_lines.is_empty() {
+                println!("⚠️  No lines found for operator: {}", operator);
+                HttpResponse::NotFound()
+                    .json(ApiResponse::<Vec<tbm_api_models::Line>>::error(
+                        format!("No lines found for operator '{}'", operator)
+                    ))
+            } else {
+                println!("🚌 Lines retrieved for {}: {} lines", operator, filtered_lines.len());
+                HttpResponse::Ok().json(ApiResponse::success(filtered_lines))
+            }
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<Vec<tbm_api_models::Line>>::error(
+                    "Failed to retrieve lines".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct StatsQuery {
+    format: Option<String>, // json (default) or text
+}
+
+async fn get_stats(state: web::Data<AppState>, query: web::Query<StatsQuery>) -> HttpResponse {
+    let format = query.format.as_deref().unwrap_or("json").to_lowercase();
+    if format != "json" && format != "text" {
+        return HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error(
+                format!("Unsupported format '{}' (expected json or text)", format)
+            ));
+    }
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let stats = NVTModels::get_cache_stats(&cache);
+            println!("📊 Stats requested");
+            if format == "text" {
+                return HttpResponse::Ok()
+                    .content_type("text/plain; charset=utf-8")
+                    .body(stats.to_string());
+            }
+            HttpResponse::Ok().json(ApiResponse::success(stats))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve stats".to_string()
+                ))
+        }
+    }
+}
+
+async fn get_feed_quality(state: web::Data<AppState>) -> HttpResponse {
+    match state.cache.lock() {
+        Ok(cache) => {
+            let report: std::collections::HashMap<&str, &tbm_api_models::FeedQuality> = cache.gtfs_caches()
+                .into_iter()
+                .map(|gtfs| (gtfs.source.as_str(), &gtfs.quality))
+                .collect();
+            println!("🩺 Feed quality report requested");
+            HttpResponse::Ok().json(ApiResponse::success(report))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve feed quality".to_string()
+                ))
+        }
+    }
+}
+
+/// The merged static timetables as one GTFS feed (see gtfs_export.rs)
+async fn export_gtfs(state: web::Data<AppState>) -> HttpResponse {
+    let live = state.cache.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let cache = live.lock()
+            .map_err(|e| tbm_api_models::NVTError::NetworkError(format!("Failed to lock cache: {}", e)))?
+            .clone();
+        gtfs_export::write_feed(&cache)
+    }).await;
+
+    match result {
+        Ok(Ok(feed)) => {
+            println!("📦 GTFS export requested: {} bytes", feed.len());
+            HttpResponse::Ok()
+                .content_type(gtfs_export::CONTENT_TYPE)
+                .insert_header(("Content-Disposition", "attachment; filename=\"gtfs.zip\""))
+                .body(feed)
+        }
+        Ok(Err(e)) => {
+            eprintln!("❌ Failed to export GTFS: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to export GTFS".to_string()
+                ))
+        }
+        Err(e) => {
+            eprintln!("❌ GTFS export task panicked: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "GTFS export task panicked".to_string()
+                ))
+        }
+    }
+}
+
+/// A merged GTFS-RT feed of the cache (see gtfs_rt_feed.rs)
+fn gtfs_rt_response(state: &AppState, feed: &str, encode: fn(&tbm_api_models::CachedNetworkData) -> Vec<u8>) -> HttpResponse {
+    match state.cache.lock() {
+        Ok(cache) => {
+            let body = encode(&cache);
+            println!("📡 GTFS-RT {} feed requested: {} bytes", feed, body.len());
+            HttpResponse::Ok()
+                .content_type(gtfs_rt_feed::CONTENT_TYPE)
+                .body(body)
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve GTFS-RT feed".to_string()
+                ))
+        }
+    }
+}
+
+async fn get_gtfs_rt_vehicles(state: web::Data<AppState>) -> HttpResponse {
+    gtfs_rt_response(&state, "vehicles", gtfs_rt_feed::vehicles)
+}
+
+async fn get_gtfs_rt_alerts(state: web::Data<AppState>) -> HttpResponse {
+    gtfs_rt_response(&state, "alerts", gtfs_rt_feed::alerts)
+}
+
+async fn get_gtfs_rt_trip_updates(state: web::Data<AppState>) -> HttpResponse {
+    gtfs_rt_response(&state, "trip updates", gtfs_rt_feed::trip_updates)
+}
+
+// ============================================================================
+// Grafana Datasource (SimpleJSON contract, see grafana.rs)
+// ============================================================================
+
+/// Connection test of the datasource settings page
+async fn grafana_health() -> HttpResponse {
+    HttpResponse::Ok().body("OK")
+}
+
+async fn grafana_search(state: web::Data<AppState>, body: web::Json<grafana::SearchRequest>) -> HttpResponse {
+    match state.history.lock() {
+        Ok(history) => HttpResponse::Ok().json(grafana::search(&history, &body.target)),
+        Err(e) => {
+            eprintln!("❌ Failed to lock history: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve metrics".to_string()
+                ))
+        }
+    }
+}
+
+async fn grafana_query(state: web::Data<AppState>, body: web::Json<grafana::QueryRequest>) -> HttpResponse {
+    let (from, to) = match grafana::parse_range(&body.range) {
+        Ok(range) => range,
+        Err(message) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(message));
+        }
+    };
+
+    match state.history.lock() {
+        Ok(history) => {
+            let series = grafana::query(&history, &body, from, to);
+            println!("📈 Grafana query: {} series", series.len());
+            HttpResponse::Ok().json(series)
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock history: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve metrics".to_string()
+                ))
+        }
+    }
+}
+
+async fn grafana_annotations(state: web::Data<AppState>, body: web::Json<grafana::AnnotationRequest>) -> HttpResponse {
+    let (from, to) = match grafana::parse_range(&body.range) {
+        Ok(range) => range,
+        Err(message) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(message));
+        }
+    };
+
+    match state.history.lock() {
+        Ok(history) => HttpResponse::Ok().json(grafana::annotations(&history, &body, from, to)),
+        Err(e) => {
+            eprintln!("❌ Failed to lock history: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve alert history".to_string()
+                ))
+        }
+    }
+}
+
+/// Base URL links are built on: `public_url` from the config, or the request's own
+fn public_base_url(req: &HttpRequest) -> String {
+    config::get().public_url.clone().unwrap_or_else(|| {
+        let info = req.connection_info();
+        format!("{}://{}", info.scheme(), info.host())
+    })
+}
+
+async fn get_api_index(state: web::Data<AppState>, req: HttpRequest) -> HttpResponse {
+    match state.cache.lock() {
+        Ok(cache) => {
+            println!("🧭 API index requested");
+            HttpResponse::Ok().json(ApiResponse::success(api_index::index(ENDPOINTS, &cache, &public_base_url(&req))))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve API index".to_string()
+                ))
+        }
+    }
+}
+
+/// JSON Schema of the responses (see schema.rs); served as is, outside the ApiResponse envelope
+async fn get_api_schema() -> HttpResponse {
+    println!("📐 API schema requested");
+    HttpResponse::Ok().json(schema::document())
+}
+
+async fn wfs_service(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<std::collections::HashMap<String, String>>,
+) -> HttpResponse {
+    let request = match wfs::parse_request(&query) {
+        Ok(request) => request,
+        Err(error) => {
+            return HttpResponse::BadRequest()
+                .content_type(wfs::XML_CONTENT_TYPE)
+                .body(error.report());
+        }
+    };
+    let base_url = public_base_url(&req);
+    let service_url = format!("{}/api/wfs", base_url.trim_end_matches('/'));
+
+    match state.cache.lock() {
+        Ok(cache) => match request {
+            wfs::WfsRequest::GetCapabilities => {
+                println!("🗺️  WFS capabilities requested");
+                HttpResponse::Ok()
+                    .content_type(wfs::XML_CONTENT_TYPE)
+                    .body(wfs::capabilities(&service_url, &cache.to_network_data()))
+            }
+            wfs::WfsRequest::DescribeFeatureType(feature_types) => HttpResponse::Ok()
+                .content_type(wfs::XML_CONTENT_TYPE)
+                .body(wfs::describe_feature_type(&service_url, &feature_types)),
+            wfs::WfsRequest::GetFeature { feature_type, bbox, count, start_index } => {
+                let mut network_data = cache.to_network_data();
+                if let Some(bbox) = &bbox {
+                    network_data = network_data.within(bbox);
+                }
+                let collection = wfs::features(feature_type, &network_data, count, start_index);
+                println!("🗺️  WFS features requested: {:?}, {} returned", feature_type, collection["numberReturned"]);
+                HttpResponse::Ok()
+                    .content_type(geojson::CONTENT_TYPE)
+                    .json(collection)
+            }
+        },
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve features".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQLQuery {
+    query: String,
+    variables: Option<String>, // JSON object
+    #[serde(rename = "operationName")]
+    operation_name: Option<String>,
+}
+
+async fn graphql_get(
+    state: web::Data<AppState>,
+    query: web::Query<GraphQLQuery>,
+) -> HttpResponse {
+    let query = query.into_inner();
+    let variables = match query.variables.as_deref().map(serde_json::from_str) {
+        None => None,
+        Some(Ok(variables)) => Some(variables),
+        Some(Err(e)) => {
+            return HttpResponse::BadRequest()
+                .json(serde_json::json!({ "errors": [{ "message": format!("Invalid variables: {}", e) }] }));
+        }
+    };
+    run_graphql(&state, graphql::GraphQLRequest { query: query.query, variables, operation_name: query.operation_name })
+}
+
+async fn graphql_post(
+    state: web::Data<AppState>,
+    request: web::Json<graphql::GraphQLRequest>,
+) -> HttpResponse {
+    run_graphql(&state, request.into_inner())
+}
+
+fn run_graphql(state: &AppState, request: graphql::GraphQLRequest) -> HttpResponse {
+    match state.cache.lock() {
+        Ok(cache) => {
+            let response = graphql::execute(&request, &cache);
+            let errors = response.get("errors").and_then(|errors| errors.as_array()).map_or(0, Vec::len);
+            println!("🔮 GraphQL query executed ({} errors)", errors);
+            HttpResponse::Ok().json(response)
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "errors": [{ "message": "Failed to execute the query" }] }))
+        }
+    }
+}
+
+async fn graphql_schema() -> HttpResponse {
+    println!("🔮 GraphQL schema requested");
+    HttpResponse::Ok()
+        .content_type(graphql::SDL_CONTENT_TYPE)
+        .body(graphql::SDL)
+}
+
+async fn get_operators(state: web::Data<AppState>) -> HttpResponse {
+    match state.cache.lock() {
+        Ok(cache) => {
+            let network_data = cache.to_network_data();
+
+            let mut operators = std::collections::HashMap::new();
+            for line in &network_data.lines {
+                *operators.entry(line.operator.clone()).or_insert(0) += 1;
+            }
+
+            let operator_info: Vec<_> = operators.iter()
+                .map(|(name, count)| {
+                    serde_json::json!({
+                        "name": name,
+                        "slug": operators::operator_slug(name),
+                        "lines_count": count
+                    })
+                })
+                .collect();
+
+            println!("🏢 Operators requested: {} operators", operator_info.len());
+            HttpResponse::Ok().json(ApiResponse::success(operator_info))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve operators".to_string()
+                ))
+        }
+    }
+}
+
+/// Lines of each transport mode, in TransportMode order; lines of no known mode are counted apart
+async fn get_modes(state: web::Data<AppState>) -> HttpResponse {
+    match state.cache.lock() {
+        Ok(cache) => {
+            let network_data = cache.to_network_data();
+
+            let mut modes: std::collections::BTreeMap<tbm_api_models::TransportMode, (usize, std::collections::BTreeSet<&str>)> =
+                std::collections::BTreeMap::new();
+            let mut unknown = 0;
+            for line in &network_data.lines {
+                match line.mode {
+                    Some(mode) => {
+                        let (count, operators) = modes.entry(mode).or_default();
+                        *count += 1;
+                        operators.insert(&line.operator);
+                    }
+                    None => unknown += 1,
+                }
+            }
+
+            let mode_info: Vec<_> = modes.iter()
+                .map(|(mode, (count, operators))| {
+                    serde_json::json!({
+                        "mode": mode,
+                        "lines_count": count,
+                        "operators": operators,
+                    })
+                })
+                .collect();
+
+            println!("🚋 Modes requested: {} modes", mode_info.len());
+            HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
+                "modes": mode_info,
+                "unknown_lines_count": unknown,
+            })))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve modes".to_string()
+                ))
+        }
+    }
+}
+
+async fn get_agencies(state: web::Data<AppState>) -> HttpResponse {
+    match state.cache.lock() {
+        Ok(cache) => {
+            let agencies = NVTModels::agencies(&cache);
+            println!("🏢 Agencies requested: {} agencies", agencies.len());
+            HttpResponse::Ok().json(ApiResponse::success(agencies))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve agencies".to_string()
+                ))
+        }
+    }
+}
+
+async fn get_agency_lines(state: web::Data<AppState>, path: web::Path<String>) -> HttpResponse {
+    let agency_id = path.into_inner();
+
+    match state.cache.lock() {
+        Ok(cache) => match NVTModels::agency_lines(&cache, &agency_id) {
+            Some(lines) => {
+                println!("🏢 Lines of agency {}: {}", agency_id, lines.len());
+                HttpResponse::Ok().json(ApiResponse::success(lines))
+            }
+            None => {
+                println!("⚠️  Agency not found: {}", agency_id);
+                HttpResponse::NotFound()
+                    .json(ApiResponse::<String>::error(
+                        format!("Agency '{}' not found", agency_id)
+                    ))
+            }
+        },
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve agency lines".to_string()
+                ))
+        }
+    }
+}
+
+async fn get_operator_branding(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let name = path.into_inner();
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let network_data = cache.to_network_data();
+
+            match branding::resolve_operator(&name, &network_data.lines) {
+                Some(operator) => {
+                    let brand = branding::get_branding(&operator, &network_data.lines);
+                    println!("🎨 Branding retrieved for {}", operator);
+                    HttpResponse::Ok().json(ApiResponse::success(brand))
+                }
+                None => {
+                    println!("⚠️  Operator not found: {}", name);
+                    HttpResponse::NotFound()
+                        .json(ApiResponse::<branding::Branding>::error(
+                            format!("Operator '{}' not found", name)
+                        ))
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve branding".to_string()
+                ))
+        }
+    }
+}
+
+async fn get_operator_logo(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let name = path.into_inner();
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let network_data = cache.to_network_data();
+
+            match branding::resolve_operator(&name, &network_data.lines) {
+                Some(operator) => {
+                    let brand = branding::get_branding(&operator, &network_data.lines);
+                    let (bytes, content_type) = branding::get_logo(&brand);
+                    HttpResponse::Ok()
+                        .content_type(content_type)
+                        .insert_header(("Cache-Control", "public, max-age=86400"))
+                        .body(bytes)
+                }
+                None => {
+                    println!("⚠️  Operator not found: {}", name);
+                    HttpResponse::NotFound()
+                        .json(ApiResponse::<String>::error(
+                            format!("Operator '{}' not found", name)
+                        ))
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve logo".to_string()
+                ))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct DeparturesTextQuery {
+    limit: Option<usize>,
+}
+
+async fn get_stop_departures_text(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<DeparturesTextQuery>,
+) -> HttpResponse {
+    let stop_id = path.into_inner();
+    let limit = query.limit.unwrap_or(8).clamp(1, 30);
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let network_data = cache.to_network_data();
+            match network_data.stops.iter().find(|s| s.stop_id == stop_id) {
+                Some(stop) => {
+                    let now = (state.clock)();
+                    let departures = kiosk::stop_departures(stop, &network_data, &cache, now, limit, |_| true);
+                    println!("📟 Text departures for stop {}: {} entries", stop_id, departures.len());
+                    HttpResponse::Ok()
+                        .content_type("text/plain; charset=utf-8")
+                        .body(kiosk::render_text_board(stop, &departures, now))
+                }
+                None => {
+                    println!("⚠️  Stop not found: {}", stop_id);
+                    HttpResponse::NotFound()
+                        .content_type("text/plain; charset=utf-8")
+                        .body(format!("Stop '{}' not found\n", stop_id))
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .content_type("text/plain; charset=utf-8")
+                .body("Failed to retrieve departures\n")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct QrQuery {
+    scale: Option<usize>,
+    ec: Option<String>, // Error correction level: L, M (default), Q or H
+}
+
+async fn get_stop_qr(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<QrQuery>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let stop_id = path.into_inner();
+    let Some(level) = query.ec.as_deref().map_or(Some(qr::EcLevel::M), qr::EcLevel::parse) else {
+        return HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error(
+                "ec must be L, M, Q or H".to_string()
+            ));
+    };
+
+    let exists = match state.cache.lock() {
+        Ok(cache) => cache.to_network_data().stops.iter().any(|s| s.stop_id == stop_id),
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to generate QR code".to_string()
+                ));
+        }
+    };
+
+    if !exists {
+        println!("⚠️  Stop not found: {}", stop_id);
+        return HttpResponse::NotFound()
+            .json(ApiResponse::<String>::error(
+                format!("Stop '{}' not found", stop_id)
+            ));
+    }
+
+    // Deep link to the live map focused on this stop
+    let base_url = public_base_url(&req);
+    let link = format!("{}/?stop={}", base_url.trim_end_matches('/'),
+                       url_encode_component(&stop_id));
+
+    match qr::QrCode::encode(&link, level) {
+        Some(code) => {
+            println!("🔳 QR code generated for stop {}", stop_id);
+            HttpResponse::Ok()
+                .content_type("image/svg+xml")
+                .insert_header(("Cache-Control", "public, max-age=86400"))
+                .body(code.to_svg(query.scale.unwrap_or(8).clamp(1, 32)))
+        }
+        None => {
+            eprintln!("❌ Link too long for a QR code: {}", link);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to generate QR code".to_string()
+                ))
+        }
+    }
+}
+
+fn url_encode_component(value: &str) -> String {
+    value.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b':' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct TimeFormatQuery {
+    tz: Option<String>,
+    time_format: Option<String>,
+}
+
+async fn get_stop_schedule(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<TimeFormatQuery>,
+) -> HttpResponse {
+    scheduled_arrivals_response(&state, &path.into_inner(), 10, &query)
+}
+
+#[derive(Deserialize)]
+struct ArrivalsQuery {
+    limit: Option<usize>,
+    tz: Option<String>,
+    time_format: Option<String>,
+    #[serde(default)]
+    accessible: bool, // only wheelchair accessible trips, on /departures
+}
+
+const MAX_ARRIVALS_LIMIT: usize = 100;
+
+async fn get_stop_arrivals(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ArrivalsQuery>,
+) -> HttpResponse {
+    let ArrivalsQuery { limit, tz, time_format, .. } = query.into_inner();
+    let limit = limit.unwrap_or(10).clamp(1, MAX_ARRIVALS_LIMIT);
+    scheduled_arrivals_response(&state, &path.into_inner(), limit, &TimeFormatQuery { tz, time_format })
+}
+
+/// Upcoming timetable arrivals at a stop, shared by /schedule and /arrivals
+fn scheduled_arrivals_response(state: &AppState, stop_id: &str, limit: usize, query: &TimeFormatQuery) -> HttpResponse {
+    let time_options = match TimeOptions::from_query(query.tz.as_deref(), query.time_format.as_deref()) {
+        Ok(options) => options,
+        Err(message) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(message));
+        }
+    };
+
+    match state.cache.lock() {
+        Ok(cache) => {
+            let now = (state.clock)().naive_local();
+            let scheduled_arrivals = NVTModels::get_scheduled_arrivals(stop_id, &cache, now, limit);
+            
+            if scheduled_arrivals.is_empty() {
+                println!("📅 No scheduled arrivals found for stop: {}", stop_id);
+            } else {
+                println!("📅 Scheduled arrivals retrieved for stop {}: {} arrivals", 
+                         stop_id, scheduled_arrivals.len());
+            }
+
+            let timed: Vec<tbm_api_models::TimedArrival> = scheduled_arrivals.iter()
+                .map(|arrival| {
+                    let service_date = arrival.service_day().unwrap_or(now.date());
+                    tbm_api_models::TimedArrival {
+                        arrival,
+                        arrival_at: time_options.timestamp(service_date, &arrival.arrival_time),
+                        departure_at: time_options.timestamp(service_date, &arrival.departure_time),
+                    }
+                })
+                .collect();
+            HttpResponse::Ok().json(ApiResponse::success(timed))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<Vec<tbm_api_models::ScheduledArrival>>::error(
+                    "Failed to retrieve schedule".to_string()
+                ))
+        }
+    }
+}
+
+/// Timetable departures of a stop with the real-time rows build_stops matched to it, in
+/// expected departure order
+fn merge_departures<'a>(
+    arrivals: &'a [tbm_api_models::ScheduledArrival],
+    real_time: &[tbm_api_models::RealTimeInfo],
+    now: chrono::NaiveDateTime,
+    time_options: &TimeOptions,
+) -> Vec<tbm_api_models::Departure<'a>> {
+    // Real-time data is about one run of a trip: the one of its start_date, or, when the feed
+    // leaves it out, the trip's next run (its first in `arrivals`, which are in time order)
+    let mut next_runs: std::collections::HashMap<&str, &str> = std::collections::HashMap::new();
+    for arrival in arrivals {
+        next_runs.entry(arrival.trip_id.as_str()).or_insert(arrival.service_date.as_str());
+    }
+
+    let mut departures: Vec<tbm_api_models::Departure> = arrivals.iter()
+        .map(|arrival| {
+            let service_date = arrival.service_day().unwrap_or(now.date());
+            let start_date = service_date.format("%Y%m%d").to_string();
+            let delay = real_time.iter()
+                .filter(|rt| rt.trip_id == arrival.trip_id)
+                .filter(|rt| match rt.start_date.as_deref() {
+                    Some(date) => date == start_date,
+                    None => next_runs.get(arrival.trip_id.as_str()) == Some(&arrival.service_date.as_str()),
+                })
+                .find_map(|rt| rt.delay.or_else(|| {
+                    // Trip updates may only carry the predicted arrival time
+                    let predicted = rt.timestamp.filter(|_| rt.vehicle_id == "scheduled")?;
+                    let scheduled = time_format::gtfs_datetime(service_date, &arrival.arrival_time)?;
+                    i32::try_from(predicted - scheduled.timestamp()).ok()
+                }));
+            let expected = time_format::gtfs_datetime(service_date, &arrival.departure_time)
+                .map(|dt| dt + chrono::Duration::seconds(i64::from(delay.unwrap_or(0))));
+
+            tbm_api_models::Departure {
+                arrival,
+                status: if delay.is_some() { "realtime" } else { "scheduled" }.to_string(),
+                delay,
+                expected_departure: expected.map(|dt| dt.timestamp()),
+                expected_departure_at: expected
+                    .filter(|_| time_options.timestamps)
+                    .map(|dt| dt.with_timezone(&time_options.tz).to_rfc3339()),
+            }
+        })
+        .collect();
+    departures.sort_by_key(|d| d.expected_departure.unwrap_or(i64::MAX));
+    departures
+}
+
+async fn get_stop_departures(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ArrivalsQuery>,
+) -> HttpResponse {
+    let stop_id = path.into_inner();
+    let ArrivalsQuery { limit, tz, time_format, accessible } = query.into_inner();
+    let limit = limit.unwrap_or(10).clamp(1, MAX_ARRIVALS_LIMIT);
+    let time_options = match TimeOptions::from_query(tz.as_deref(), time_format.as_deref()) {
+        Ok(options) => options,
+        Err(message) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(message));
+        }
+    };
+
     match state.cache.lock() {
         Ok(cache) => {
+            let now = (state.clock)().naive_local();
+            let scheduled_arrivals = if accessible {
+                // Filtered before the limit, out of the most a stop lists
+                let mut arrivals = NVTModels::get_scheduled_arrivals(&stop_id, &cache, now, MAX_ARRIVALS_LIMIT);
+                arrivals.retain(|arrival| arrival.wheelchair_accessible == Some(true));
+                arrivals.truncate(limit);
+                arrivals
+            } else {
+                NVTModels::get_scheduled_arrivals(&stop_id, &cache, now, limit)
+            };
             let network_data = cache.to_network_data();
-            println!("📊 Network data requested: {} stops, {} lines, {} shapes",
-                     network_data.stops.len(),
-                     network_data.lines.len(),
-                     network_data.shapes.len());
-            HttpResponse::Ok().json(ApiResponse::success(network_data))
+            let real_time = network_data.stops.iter()
+                .find(|s| s.stop_id == stop_id)
+                .map(|s| s.real_time.as_slice())
+                .unwrap_or_default();
+            let departures = merge_departures(&scheduled_arrivals, real_time, now, &time_options);
+
+            let live = departures.iter().filter(|d| d.delay.is_some()).count();
+            println!("🚏 Departures retrieved for stop {}: {} departures ({} real-time)",
+                     stop_id, departures.len(), live);
+            if accessible {
+                println!("   ↳ Wheelchair accessible trips only");
+            }
+            HttpResponse::Ok().json(ApiResponse::success(departures))
         }
         Err(e) => {
             eprintln!("❌ Failed to lock cache: {}", e);
             HttpResponse::InternalServerError()
                 .json(ApiResponse::<String>::error(
-                    "Failed to retrieve network data".to_string()
+                    "Failed to retrieve departures".to_string()
                 ))
         }
     }
 }
 
-async fn get_stops(state: web::Data<AppState>) -> HttpResponse {
+#[derive(Deserialize)]
+struct AroundQuery {
+    lat: Option<f64>,
+    lon: Option<f64>,
+    radius: Option<f64>, // meters
+    limit: Option<usize>, // stops
+    tz: Option<String>,
+    time_format: Option<String>,
+    lang: Option<String>,
+}
+
+/// Departures listed for each stop of /around
+const AROUND_DEPARTURES: usize = 3;
+const DEFAULT_AROUND_LIMIT: usize = 10;
+
+/// Stops around a point with their next departures and active alerts, the home screen of a
+/// mobile client in one request instead of /stops/nearby and one /departures per stop
+async fn get_around(state: web::Data<AppState>, req: HttpRequest, query: web::Query<AroundQuery>) -> HttpResponse {
+    let (lat, lon) = match position_param(query.lat, query.lon) {
+        Ok(position) => position,
+        Err(response) => return response,
+    };
+    let radius = query.radius.unwrap_or(DEFAULT_NEARBY_RADIUS).clamp(1.0, MAX_NEARBY_RADIUS);
+    let limit = query.limit.unwrap_or(DEFAULT_AROUND_LIMIT).clamp(1, MAX_NEARBY_LIMIT);
+    let time_options = match TimeOptions::from_query(query.tz.as_deref(), query.time_format.as_deref()) {
+        Ok(options) => options,
+        Err(message) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(message));
+        }
+    };
+
     match state.cache.lock() {
         Ok(cache) => {
-            let network_data = cache.to_network_data();
-            println!("📍 Stops requested: {} total", network_data.stops.len());
-            HttpResponse::Ok().json(ApiResponse::success(network_data.stops))
+            let now = (state.clock)().naive_local();
+            let timestamp = NVTModels::get_current_timestamp();
+            let languages = alert_languages(&req, query.lang.as_deref());
+            let stops: Vec<serde_json::Value> = spatial::nearby_stops(&cache, lat, lon, radius, limit).into_iter()
+                .map(|nearby| {
+                    let stop = nearby.stop;
+                    let arrivals = NVTModels::get_scheduled_arrivals(&stop.stop_id, &cache, now, AROUND_DEPARTURES);
+                    let departures = merge_departures(&arrivals, &stop.real_time, now, &time_options);
+                    let alerts: Vec<tbm_api_models::AlertInfo> = stop.alerts.iter()
+                        .filter(|alert| alert.is_active_at(timestamp))
+                        .map(|alert| alert.localized(&languages))
+                        .collect();
+                    serde_json::json!({
+                        "stop_id": stop.stop_id,
+                        "stop_name": stop.stop_name,
+                        "latitude": stop.latitude,
+                        "longitude": stop.longitude,
+                        "lines": stop.lines,
+                        "distance_m": nearby.distance_m,
+                        "departures": departures,
+                        "alerts": alerts,
+                    })
+                })
+                .collect();
+            println!("📍 Around requested at {:.5}, {:.5} ({} m): {} stops", lat, lon, radius, stops.len());
+            HttpResponse::Ok().json(ApiResponse::success(stops))
         }
         Err(e) => {
             eprintln!("❌ Failed to lock cache: {}", e);
             HttpResponse::InternalServerError()
-                .json(ApiResponse::<Vec<tbm_api_models::Stop>>::error(
-                    "Failed to retrieve stops".to_string()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve stops around".to_string()
                 ))
         }
     }
 }
 
-async fn get_lines(state: web::Data<AppState>) -> HttpResponse {
+#[derive(Deserialize)]
+struct StopsBatchQuery {
+    departures: Option<bool>,
+    limit: Option<usize>, // departures per stop
+    tz: Option<String>,
+    time_format: Option<String>,
+    lang: Option<String>,
+}
+
+const MAX_BATCH_STOPS: usize = 100;
+
+/// Stops of a JSON array of stop_ids, in the order asked, so that clients with saved favorites
+/// fetch them in one request instead of one /stop/{id} each; ?departures=true adds the next
+/// merged departures of each stop
+async fn get_stops_batch(
+    state: web::Data<AppState>,
+    req: HttpRequest,
+    query: web::Query<StopsBatchQuery>,
+    body: web::Json<Vec<String>>,
+) -> HttpResponse {
+    let mut stop_ids = body.into_inner();
+    let mut seen = std::collections::HashSet::new();
+    stop_ids.retain(|stop_id| seen.insert(stop_id.clone()));
+    if stop_ids.len() > MAX_BATCH_STOPS {
+        return HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error(
+                format!("At most {} stops can be asked at once", MAX_BATCH_STOPS)
+            ));
+    }
+    let with_departures = query.departures.unwrap_or(false);
+    let languages = alert_languages(&req, query.lang.as_deref());
+    let limit = query.limit.unwrap_or(AROUND_DEPARTURES).clamp(1, MAX_ARRIVALS_LIMIT);
+    let time_options = match TimeOptions::from_query(query.tz.as_deref(), query.time_format.as_deref()) {
+        Ok(options) => options,
+        Err(message) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(message));
+        }
+    };
+
     match state.cache.lock() {
         Ok(cache) => {
+            let now = (state.clock)().naive_local();
             let network_data = cache.to_network_data();
-            println!("🚌 Lines requested: {} total", network_data.lines.len());
-            HttpResponse::Ok().json(ApiResponse::success(network_data.lines))
+            let by_id: std::collections::HashMap<&str, &tbm_api_models::Stop> = network_data.stops.iter()
+                .map(|stop| (stop.stop_id.as_str(), stop))
+                .collect();
+            let mut stops = Vec::new();
+            let mut not_found = Vec::new();
+            for stop_id in stop_ids {
+                let Some(stop) = by_id.get(stop_id.as_str()) else {
+                    not_found.push(stop_id);
+                    continue;
+                };
+                let stop = tbm_api_models::Stop {
+                    alerts: stop.alerts.iter().map(|alert| alert.localized(&languages)).collect(),
+                    ..(*stop).clone()
+                };
+                let mut value = serde_json::to_value(&stop).unwrap_or_default();
+                if with_departures && let serde_json::Value::Object(fields) = &mut value {
+                    let arrivals = NVTModels::get_scheduled_arrivals(&stop.stop_id, &cache, now, limit);
+                    let departures = merge_departures(&arrivals, &stop.real_time, now, &time_options);
+                    fields.insert("departures".to_string(), serde_json::json!(departures));
+                }
+                stops.push(value);
+            }
+            println!("📍 Stop batch retrieved: {} stops ({} not found)", stops.len(), not_found.len());
+            HttpResponse::Ok().json(ApiResponse::success(serde_json::json!({
+                "stops": stops,
+                "not_found": not_found,
+            })))
         }
         Err(e) => {
             eprintln!("❌ Failed to lock cache: {}", e);
             HttpResponse::InternalServerError()
-                .json(ApiResponse::<Vec<tbm_api_models::Line>>::error(
-                    "Failed to retrieve lines".to_string()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve stops".to_string()
                 ))
         }
     }
 }
 
-async fn get_vehicles(state: web::Data<AppState>) -> HttpResponse {
+#[derive(Deserialize)]
+struct StopMonitoringQuery {
+    #[serde(rename = "MonitoringRef")]
+    monitoring_ref: Option<String>,
+    #[serde(rename = "MaximumStopVisits")]
+    maximum_stop_visits: Option<usize>,
+    #[serde(rename = "LineRef")]
+    line_ref: Option<String>, // route_id, line code or TBM SIRI LineRef
+}
+
+/// SIRI-Lite StopMonitoring of the merged departures of a stop (see siri.rs)
+async fn siri_stop_monitoring(
+    state: web::Data<AppState>,
+    query: web::Query<StopMonitoringQuery>,
+) -> HttpResponse {
+    let now = NVTModels::get_current_timestamp();
+    let Some(monitoring_ref) = query.monitoring_ref.as_deref().filter(|r| !r.is_empty()) else {
+        return HttpResponse::BadRequest()
+            .json(siri::error_delivery("", "MonitoringRef is required", now));
+    };
+    let limit = query.maximum_stop_visits.unwrap_or(10).clamp(1, MAX_ARRIVALS_LIMIT);
+
     match state.cache.lock() {
         Ok(cache) => {
-            println!("🚗 Vehicles requested: {} active", cache.real_time.len());
-            HttpResponse::Ok().json(ApiResponse::success(&cache.real_time))
+            let network_data = cache.to_network_data();
+            let stop = siri::stop_id(monitoring_ref, |id| network_data.stops.iter().any(|s| s.stop_id == id))
+                .and_then(|stop_id| network_data.stops.iter().find(|s| s.stop_id == stop_id));
+            let Some(stop) = stop else {
+                return HttpResponse::NotFound()
+                    .json(siri::error_delivery(monitoring_ref, &format!("Unknown stop '{}'", monitoring_ref), now));
+            };
+
+            let local_now = (state.clock)().naive_local();
+            // A LineRef filters the departures, so take as many as allowed before filtering
+            let fetched = if query.line_ref.is_some() { MAX_ARRIVALS_LIMIT } else { limit };
+            let mut arrivals = NVTModels::get_scheduled_arrivals(&stop.stop_id, &cache, local_now, fetched);
+            if let Some(line_ref) = query.line_ref.as_deref() {
+                let route_id = NVTModels::extract_line_id(line_ref).filter(|_| line_ref.contains(":Line:"));
+                arrivals.retain(|arrival| {
+                    arrival.route_id == line_ref || arrival.line_code == line_ref || route_id == Some(arrival.route_id.as_str())
+                });
+            }
+            let time_options = TimeOptions { tz: chrono_tz::Europe::Paris, timestamps: false };
+            let mut departures = merge_departures(&arrivals, &stop.real_time, local_now, &time_options);
+            departures.truncate(limit);
+
+            println!("🚏 SIRI StopMonitoring for {}: {} visits", monitoring_ref, departures.len());
+            HttpResponse::Ok().json(siri::stop_monitoring(
+                monitoring_ref, &stop.stop_name, &departures, cache.last_dynamic_update as i64, now,
+            ))
         }
         Err(e) => {
             eprintln!("❌ Failed to lock cache: {}", e);
             HttpResponse::InternalServerError()
-                .json(ApiResponse::<Vec<tbm_api_models::RealTimeInfo>>::error(
-                    "Failed to retrieve vehicles".to_string()
-                ))
+                .json(siri::error_delivery(monitoring_ref, "Failed to retrieve departures", now))
         }
     }
 }
 
-async fn get_alerts(state: web::Data<AppState>) -> HttpResponse {
-    match state.cache.lock() {
+#[derive(Deserialize)]
+struct BusynessQuery {
+    date: Option<String>, // YYYY-MM-DD, today by default
+}
+
+async fn get_stop_busyness(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<BusynessQuery>,
+) -> HttpResponse {
+    let stop_id = path.into_inner();
+    let date = match query.date.as_deref() {
+        None => (state.clock)().date_naive(),
+        Some(value) => match chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            Ok(date) => date,
+            Err(_) => {
+                return HttpResponse::BadRequest()
+                    .json(ApiResponse::<String>::error(
+                        format!("Invalid date '{}' (expected YYYY-MM-DD)", value)
+                    ));
+            }
+        },
+    };
+
+    // Arrivals and the history keys of the stop's lines, before locking the history
+    let (arrivals, line_keys) = match state.cache.lock() {
         Ok(cache) => {
-            println!("⚠️  Alerts requested: {} active", cache.alerts.len());
-            HttpResponse::Ok().json(ApiResponse::success(&cache.alerts))
+            let network_data = cache.to_network_data();
+            let Some(stop) = network_data.stops.iter().find(|s| s.stop_id == stop_id) else {
+                println!("⚠️  Stop not found: {}", stop_id);
+                return HttpResponse::NotFound()
+                    .json(ApiResponse::<String>::error(
+                        format!("Stop '{}' not found", stop_id)
+                    ));
+            };
+            let line_keys: Vec<String> = network_data.lines.iter()
+                .filter(|line| stop.lines.contains(&line.line_ref) || stop.lines.contains(&line.route_id))
+                .map(|line| format!("{}/{}", line.operator, line.line_code))
+                .collect();
+            (NVTModels::scheduled_arrivals_on(&stop_id, date, &cache), line_keys)
         }
         Err(e) => {
             eprintln!("❌ Failed to lock cache: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve busyness".to_string()
+                ));
+        }
+    };
+
+    match state.history.lock() {
+        Ok(history) => {
+            let busyness = busyness::estimate(&stop_id, date, &arrivals, &history, &line_keys);
+            println!("👥 Busyness requested for stop {} on {}: {} arrivals, peak at {:?}h",
+                     stop_id, busyness.date, busyness.arrivals, busyness.peak_hour);
+            HttpResponse::Ok().json(ApiResponse::success(busyness))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock history: {}", e);
             HttpResponse::InternalServerError()
-                .json(ApiResponse::<Vec<tbm_api_models::AlertInfo>>::error(
-                    "Failed to retrieve alerts".to_string()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve busyness".to_string()
                 ))
         }
     }
 }
 
-async fn get_stop_by_id(
+async fn get_trip_details(
     state: web::Data<AppState>,
     path: web::Path<String>,
 ) -> HttpResponse {
-    let stop_id = path.into_inner();
+    let trip_id = path.into_inner();
 
     match state.cache.lock() {
-        Ok(cache) => {
-            let network_data = cache.to_network_data();
-            match network_data.stops.iter().find(|s| s.stop_id == stop_id) {
-                Some(stop) => {
-                    println!("📍 Stop retrieved: {} ({})", stop.stop_name, stop.stop_id);
-                    HttpResponse::Ok().json(ApiResponse::success(stop))
-                }
-                None => {
-                    println!("⚠️  Stop not found: {}", stop_id);
-                    HttpResponse::NotFound()
-                        .json(ApiResponse::<String>::error(
-                            format!("Stop '{}' not found", stop_id)
-                        ))
-                }
+        Ok(cache) => match NVTModels::get_trip_details(&trip_id, &cache) {
+            Some(details) => {
+                println!("🧭 Trip retrieved: {} (line {}, {} stops)",
+                         trip_id, details.line_code, details.stop_times.len());
+                HttpResponse::Ok().json(ApiResponse::success(details))
             }
-        }
+            None => {
+                println!("⚠️  Trip not found: {}", trip_id);
+                HttpResponse::NotFound()
+                    .json(ApiResponse::<String>::error(
+                        format!("Trip '{}' not found", trip_id)
+                    ))
+            }
+        },
         Err(e) => {
             eprintln!("❌ Failed to lock cache: {}", e);
             HttpResponse::InternalServerError()
                 .json(ApiResponse::<String>::error(
-                    "Failed to retrieve stop".to_string()
+                    "Failed to retrieve trip".to_string()
                 ))
         }
     }
 }
 
-async fn get_line_by_code(
+async fn get_vehicle_details(
     state: web::Data<AppState>,
     path: web::Path<String>,
 ) -> HttpResponse {
-    let line_code = path.into_inner();
+    let vehicle_id = path.into_inner();
 
     match state.cache.lock() {
         Ok(cache) => {
-            let network_data = cache.to_network_data();
-            match network_data.lines.iter().find(|l|
-                l.line_code.eq_ignore_ascii_case(&line_code)
-            ) {
-                Some(line) => {
-                    println!("🚌 Line retrieved: {} ({}) - {}",
-                             line.line_code, line.line_name, line.operator);
-                    HttpResponse::Ok().json(ApiResponse::success(line))
+            let vehicle_details = NVTModels::get_vehicle_details(&vehicle_id, &cache);
+            
+            match vehicle_details {
+                Some(details) => {
+                    println!("🚗 Vehicle details retrieved: {}", vehicle_id);
+                    HttpResponse::Ok().json(ApiResponse::success(details))
                 }
                 None => {
-                    println!("⚠️  Line not found: {}", line_code);
+                    println!("⚠️  Vehicle not found: {}", vehicle_id);
                     HttpResponse::NotFound()
                         .json(ApiResponse::<String>::error(
-                            format!("Line '{}' not found", line_code)
+                            format!("Vehicle '{}' not found", vehicle_id)
                         ))
                 }
             }
@@ -220,208 +2986,679 @@ async fn get_line_by_code(
             eprintln!("❌ Failed to lock cache: {}", e);
             HttpResponse::InternalServerError()
                 .json(ApiResponse::<String>::error(
-                    "Failed to retrieve line".to_string()
+                    "Failed to retrieve vehicle details".to_string()
                 ))
         }
     }
 }
 
-async fn get_lines_by_operator(
+async fn get_kiosk(
     state: web::Data<AppState>,
     path: web::Path<String>,
 ) -> HttpResponse {
-    let operator = path.into_inner();
+    let config_id = path.into_inner();
+
+    let Some(kiosk_config) = config::get().kiosks.get(&config_id) else {
+        println!("⚠️  Kiosk config not found: {}", config_id);
+        return HttpResponse::NotFound()
+            .json(ApiResponse::<String>::error(
+                format!("Kiosk config '{}' not found", config_id)
+            ));
+    };
 
     match state.cache.lock() {
         Ok(cache) => {
-            let network_data = cache.to_network_data();
-            let filtered_lines: Vec<_> = network_data.lines
-                .into_iter()
-                .filter(|l| l.operator.eq_ignore_ascii_case(&operator))
-                .collect();
+            let payload = kiosk::build_payload(&config_id, kiosk_config, &cache, (state.clock)());
+            println!("🖥️  Kiosk {} requested: {} screens", config_id, payload.screens.len());
+            HttpResponse::Ok().json(ApiResponse::success(payload))
+        }
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve kiosk".to_string()
+                ))
+        }
+    }
+}
 
-            if filtered_lines.is_empty() {
-                println!("⚠️  No lines found for operator: {}", operator);
-                HttpResponse::NotFound()
-                    .json(ApiResponse::<Vec<tbm_api_models::Line>>::error(
-                        format!("No lines found for operator '{}'", operator)
-                    ))
-            } else {
-                println!("🚌 Lines retrieved for {}: {} lines", operator, filtered_lines.len());
-                HttpResponse::Ok().json(ApiResponse::success(filtered_lines))
-            }
+async fn get_version(state: web::Data<AppState>) -> HttpResponse {
+    match state.cache.lock() {
+        Ok(cache) => {
+            println!("🏷️  Version requested");
+            HttpResponse::Ok().json(ApiResponse::success(version::info(&cache)))
         }
         Err(e) => {
             eprintln!("❌ Failed to lock cache: {}", e);
             HttpResponse::InternalServerError()
-                .json(ApiResponse::<Vec<tbm_api_models::Line>>::error(
-                    "Failed to retrieve lines".to_string()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve version".to_string()
                 ))
         }
     }
 }
 
-async fn get_stats(state: web::Data<AppState>) -> HttpResponse {
-    match state.cache.lock() {
-        Ok(cache) => {
-            let stats = NVTModels::get_cache_stats(&cache);
-            println!("📊 Stats requested");
-            HttpResponse::Ok().json(ApiResponse::success(stats))
+/// Service status with the data ages; 503 when the data or a source's feeds are older than the
+/// config::HealthConfig thresholds, so load balancers and monitoring notice failing upstreams
+async fn health_check(state: web::Data<AppState>) -> HttpResponse {
+    let report = match state.cache.lock() {
+        Ok(cache) => NVTModels::get_health(&cache, &config::get().health, NVTModels::get_current_timestamp() as u64),
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve health".to_string()
+                ));
+        }
+    };
+    if report.degraded {
+        println!("⚠️  Health check: degraded");
+    }
+
+    let body = serde_json::json!({
+        "status": if report.degraded { "degraded" } else { "healthy" },
+        "degraded": report.degraded,
+        "service": "TBM + TransGironde + SNCF Transit API",
+        "version": version::VERSION,
+        "git_commit": version::GIT_COMMIT,
+        "sources": ["TBM", "TransGironde", "SNCF"],
+        "refreshing": state.refresh_jobs.rebuilding(),
+        "dynamic_age_secs": report.dynamic_age_secs,
+        "static_age_secs": report.static_age_secs,
+        "freshness": report.freshness,
+        "timestamp": NVTModels::get_current_timestamp(),
+        "embedded_frontend": true
+    });
+    if report.degraded {
+        HttpResponse::ServiceUnavailable().json(body)
+    } else {
+        HttpResponse::Ok().json(body)
+    }
+}
+
+/// Liveness probe: the process is up and answering
+async fn health_live() -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "live",
+        "uptime_secs": version::uptime_secs(),
+        "timestamp": NVTModels::get_current_timestamp(),
+    }))
+}
+
+/// Readiness probe: the initial cache load is done and at least one source is fresh, so traffic
+/// isn't routed to an instance still downloading its feeds
+async fn health_ready(state: web::Data<AppState>) -> HttpResponse {
+    let (loaded, report) = match state.cache.lock() {
+        Ok(cache) => (
+            cache.last_static_update != 0,
+            NVTModels::get_health(&cache, &config::get().health, NVTModels::get_current_timestamp() as u64),
+        ),
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            return HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to retrieve health".to_string()
+                ));
+        }
+    };
+
+    let body = serde_json::json!({
+        "status": if report.ready { "ready" } else { "not_ready" },
+        "ready": report.ready,
+        "loaded": loaded,
+        "fresh_sources": report.freshness.iter()
+            .filter(|(_, source)| !source.stale)
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>(),
+        "timestamp": NVTModels::get_current_timestamp(),
+    });
+    if report.ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+#[derive(Deserialize)]
+struct RefreshQuery {
+    full: Option<bool>, // Reload the static data too, even when not due
+    #[serde(rename = "static")]
+    static_data: Option<bool>, // Reload the static data only (both with dynamic=true)
+    dynamic: Option<bool>, // Refresh the real-time data only
+}
+
+impl RefreshQuery {
+    /// Scope of ?static= / ?dynamic=, None without either
+    fn scope(&self) -> Option<tbm_api_models::RefreshScope> {
+        use tbm_api_models::RefreshScope;
+        match (self.static_data.unwrap_or(false), self.dynamic.unwrap_or(false)) {
+            (true, true) => Some(RefreshScope::All),
+            (true, false) => Some(RefreshScope::Static),
+            (false, true) => Some(RefreshScope::Dynamic),
+            (false, false) => None,
+        }
+    }
+}
+
+/// Optional body of POST /api/tbm/refresh, e.g. {"sources":["SNCF"],"scope":"static"}
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RefreshRequest {
+    sources: Option<Vec<String>>, // Static data to reload, all sources by default
+    #[serde(default)]
+    scope: tbm_api_models::RefreshScope,
+}
+
+/// How long clients are told to wait while another forced refresh runs
+const REBUILD_RETRY_AFTER_SECS: u64 = 60;
+const REFRESH_RETRY_AFTER_SECS: u64 = 5;
+
+/// Refusal of a refresh request while `running`, a different refresh, isn't done
+fn refresh_busy(running: &refresh_jobs::RefreshJob) -> HttpResponse {
+    println!("⚠️  Refresh job {} ({}) still running", running.id, running.request);
+    let (retry_after, error) = if running.rebuild {
+        (REBUILD_RETRY_AFTER_SECS, "A full rebuild is in progress")
+    } else {
+        (REFRESH_RETRY_AFTER_SECS, "Another refresh is in progress")
+    };
+    HttpResponse::ServiceUnavailable()
+        .insert_header(("Retry-After", retry_after.to_string()))
+        .json(serde_json::json!({
+            "success": false,
+            "refreshing": running.rebuild,
+            "error": error,
+            "job": running,
+            "timestamp": NVTModels::get_current_timestamp(),
+        }))
+}
+
+fn job_location(job: &refresh_jobs::RefreshJob) -> (&'static str, String) {
+    ("Location", format!("/api/tbm/refresh/{}", job.id))
+}
+
+/// Run the `refresh` of an admitted job on the blocking pool, finishing the job with its outcome
+fn spawn_refresh_job(
+    state: &AppState,
+    job: &refresh_jobs::RefreshJob,
+    refresh: impl FnOnce(&Mutex<CachedNetworkData>) -> tbm_api_models::Result<()> + Send + 'static,
+) {
+    println!("🔄 Refresh job {} started ({})", job.id, job.request);
+    let (cache, jobs, id) = (state.cache.clone(), state.refresh_jobs.clone(), job.id);
+    tokio::spawn(async move {
+        let result = match tokio::task::spawn_blocking(move || refresh(&cache)).await {
+            Ok(Ok(())) => {
+                println!("✓ Refresh job {} completed successfully", id);
+                Ok(())
+            }
+            Ok(Err(e)) => {
+                eprintln!("⚠️  Refresh job {} failed: {}", id, e);
+                Err(e.to_string())
+            }
+            Err(e) => {
+                eprintln!("❌ Refresh job {} panicked: {}", id, e);
+                Err("Refresh task panicked".to_string())
+            }
+        };
+        jobs.finish(id, result);
+    });
+}
+
+/// Start `refresh` as a job, or join the same one running; 202 with the job to poll
+fn start_refresh(
+    state: &AppState,
+    request: &str,
+    rebuild: bool,
+    refresh: impl FnOnce(&Mutex<CachedNetworkData>) -> tbm_api_models::Result<()> + Send + 'static,
+) -> HttpResponse {
+    let job = match state.refresh_jobs.admit(request, rebuild) {
+        refresh_jobs::Admission::Started(job) => {
+            spawn_refresh_job(state, &job, refresh);
+            job
+        }
+        refresh_jobs::Admission::Joined(job) => {
+            println!("🔄 Joining running refresh job {}", job.id);
+            job
+        }
+        refresh_jobs::Admission::Busy(running) => return refresh_busy(&running),
+    };
+    HttpResponse::Accepted()
+        .insert_header(job_location(&job))
+        .json(ApiResponse::success(job))
+}
+
+async fn force_refresh(state: web::Data<AppState>, query: web::Query<RefreshQuery>, body: web::Bytes) -> HttpResponse {
+    let full = query.full.unwrap_or(false);
+    let request = if body.iter().all(u8::is_ascii_whitespace) {
+        None
+    } else {
+        match serde_json::from_slice::<RefreshRequest>(&body) {
+            Ok(request) => Some(request),
+            Err(e) => {
+                return HttpResponse::BadRequest()
+                    .json(ApiResponse::<String>::error(
+                        format!("Invalid refresh request: {}", e)
+                    ));
+            }
+        }
+    };
+    let request = match (request, query.scope()) {
+        (Some(_), _) | (_, Some(_)) if full => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(
+                    "full can't be combined with a refresh scope".to_string()
+                ));
+        }
+        (Some(_), Some(_)) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error(
+                    "Give the refresh scope in the body or the query, not both".to_string()
+                ));
+        }
+        (None, Some(scope)) => Some(RefreshRequest { sources: None, scope }),
+        (request, None) => request,
+    };
+
+    match request {
+        None => {
+            println!("🔄 Manual {}refresh requested...", if full { "full " } else { "" });
+            let request = if full { "full" } else { "smart" };
+            start_refresh(&state, request, full, move |cache| NVTModels::smart_refresh_shared(cache, full))
+        }
+        Some(request) => {
+            let upstreams = match served_upstreams(&state) {
+                Ok(upstreams) => upstreams,
+                Err(response) => return response,
+            };
+            let sources: Vec<String> = match &request.sources {
+                None => upstreams.sources(),
+                Some(names) => match names.iter().map(|name| upstreams.source_named(name).ok_or(name)).collect::<Result<Vec<_>, _>>() {
+                    // In Upstreams::sources order, so the same request joins a running job however it's written
+                    Ok(named) => upstreams.sources().into_iter().filter(|source| named.contains(source)).collect(),
+                    Err(name) => {
+                        return HttpResponse::BadRequest()
+                            .json(ApiResponse::<String>::error(
+                                format!("Unknown source '{}' (expected {})", name, upstreams.sources().join(", "))
+                            ));
+                    }
+                },
+            };
+            let scope = request.scope;
+            let rebuild = scope != tbm_api_models::RefreshScope::Dynamic;
+            let request = if rebuild {
+                format!("{}: {}", scope, sources.join(", "))
+            } else {
+                scope.to_string()
+            };
+            println!("🔄 Manual refresh requested ({})...", request);
+            start_refresh(&state, &request, rebuild, move |cache| {
+                NVTModels::scoped_refresh_shared(cache, scope, &sources.iter().map(String::as_str).collect::<Vec<_>>())
+            })
+        }
+    }
+}
+
+/// Status of a refresh job
+async fn get_refresh_job(state: web::Data<AppState>, path: web::Path<String>) -> HttpResponse {
+    match path.parse().ok().and_then(|id| state.refresh_jobs.get(id)) {
+        Some(job) => HttpResponse::Ok().json(ApiResponse::success(job)),
+        None => HttpResponse::NotFound()
+            .json(ApiResponse::<String>::error(
+                format!("Refresh job '{}' not found", path)
+            )),
+    }
+}
+
+/// Upstreams of the served cache, or the 500 to answer when it can't be locked
+fn served_upstreams(state: &AppState) -> Result<tbm_api_models::Upstreams, HttpResponse> {
+    match state.cache.lock() {
+        Ok(cache) => Ok(cache.upstreams.clone()),
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            Err(HttpResponse::InternalServerError()
+                .json(ApiResponse::<String>::error(
+                    "Failed to read the configured sources".to_string()
+                )))
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CachePurge {
+    source: String,
+    purged: bool, // A saved feed was deleted
+    job: refresh_jobs::RefreshJob, // Reloading the source
+}
+
+/// Delete the saved GTFS feed of a source and reload it from the upstream, as a refresh job
+async fn purge_cache(state: web::Data<AppState>, path: web::Path<String>) -> HttpResponse {
+    let upstreams = match served_upstreams(&state) {
+        Ok(upstreams) => upstreams,
+        Err(response) => return response,
+    };
+    let Some(source) = upstreams.source_named(&path) else {
+        return HttpResponse::NotFound()
+            .json(ApiResponse::<String>::error(
+                format!("Unknown source '{}' (expected {})", path, upstreams.sources().join(", "))
+            ));
+    };
+    println!("🗑️  GTFS cache purge requested for {}", source);
+
+    let (job, purged) = match state.refresh_jobs.admit(&format!("purge: {}", source), true) {
+        refresh_jobs::Admission::Started(job) => {
+            let purged = match tbm_api_models::GTFSCache::purge(&upstreams.cache_dir, &source) {
+                Ok(purged) => purged,
+                Err(e) => {
+                    eprintln!("⚠️  {} GTFS cache purge failed: {}", source, e);
+                    state.refresh_jobs.finish(job.id, Err(e.to_string()));
+                    return HttpResponse::InternalServerError()
+                        .json(ApiResponse::<String>::error(
+                            format!("Purge failed: {}", e)
+                        ));
+                }
+            };
+            let reloaded = source.clone();
+            spawn_refresh_job(&state, &job, move |cache| {
+                NVTModels::scoped_refresh_shared(cache, tbm_api_models::RefreshScope::Static, &[&reloaded])
+            });
+            (job, purged)
+        }
+        refresh_jobs::Admission::Joined(job) => (job, false),
+        refresh_jobs::Admission::Busy(running) => return refresh_busy(&running),
+    };
+    HttpResponse::Accepted()
+        .insert_header(job_location(&job))
+        .json(ApiResponse::success(CachePurge { source, purged, job }))
+}
+
+#[derive(Deserialize)]
+struct JourneyQuery {
+    from_stop: Option<String>,
+    to_stop: Option<String>,
+    from: Option<String>, // "lat,lon", instead of from_stop
+    to: Option<String>,
+    departure: Option<String>, // unix seconds, RFC3339 or local YYYY-MM-DDTHH:MM[:SS]; now by default
+    arrive_by: Option<String>, // Instead of departure, as it
+    criteria: Option<String>, // "arrival", "transfers", "walking", comma-separated
+    #[serde(default)]
+    wheelchair: bool, // only wheelchair accessible stops, trips and pathways
+    #[serde(default)]
+    bikes: bool, // only trips taking bikes, and V³ rides first and last
+    count: Option<usize>,
+}
+
+/// Departure time of a journey request, as unix seconds
+fn parse_departure(value: &str) -> Option<i64> {
+    use chrono::TimeZone;
+
+    if let Ok(timestamp) = value.parse::<i64>() {
+        return Some(timestamp);
+    }
+    if let Ok(datetime) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Some(datetime.timestamp());
+    }
+    let local = chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M"))
+        .ok()?;
+    chrono_tz::Europe::Paris.from_local_datetime(&local).earliest().map(|datetime| datetime.timestamp())
+}
+
+/// Itineraries between two stops, earliest arrival first (see routing.rs)
+async fn get_journey(
+    state: web::Data<AppState>,
+    query: web::Query<JourneyQuery>,
+) -> HttpResponse {
+    let JourneyQuery { from_stop, to_stop, from, to, departure, arrive_by, criteria, wheelchair, bikes, count } = query.into_inner();
+    let place = |stop: Option<String>, point: Option<String>, end: &str| match (stop, point) {
+        (Some(stop_id), None) => Ok(routing::Place::Stop(stop_id)),
+        (None, Some(point)) => routing::Place::parse_point(&point)
+            .ok_or_else(|| format!("Invalid {} '{}' (expected lat,lon)", end, point)),
+        (Some(_), Some(_)) => Err(format!("Give {}_stop or {}, not both", end, end)),
+        (None, None) => Err(format!("{}_stop or {} is required", end, end)),
+    };
+    let (from, to) = match (place(from_stop, from, "from"), place(to_stop, to, "to")) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(message), _) | (_, Err(message)) => {
+            return HttpResponse::BadRequest().json(ApiResponse::<String>::error(message));
         }
-        Err(e) => {
-            eprintln!("❌ Failed to lock cache: {}", e);
-            HttpResponse::InternalServerError()
+    };
+    let (name, value) = match (departure.as_deref(), arrive_by.as_deref()) {
+        (Some(_), Some(_)) => {
+            return HttpResponse::BadRequest()
+                .json(ApiResponse::<String>::error("Give departure or arrive_by, not both".to_string()));
+        }
+        (None, Some(value)) => ("arrive_by", Some(value)),
+        (value, None) => ("departure", value),
+    };
+    let timestamp = match value {
+        None => NVTModels::get_current_timestamp(),
+        Some(value) => match parse_departure(value) {
+            Some(timestamp) => timestamp,
+            None => {
+                return HttpResponse::BadRequest()
+                    .json(ApiResponse::<String>::error(
+                        format!("Invalid {} '{}' (expected unix seconds, RFC3339 or YYYY-MM-DDTHH:MM)", name, value)
+                    ));
+            }
+        },
+    };
+    let time = if name == "arrive_by" { routing::Time::ArriveBy(timestamp) } else { routing::Time::DepartAt(timestamp) };
+    let criteria = match criteria.as_deref().map(routing::Criteria::parse) {
+        None => routing::Criteria::default(),
+        Some(Some(criteria)) => criteria,
+        Some(None) => {
+            return HttpResponse::BadRequest()
                 .json(ApiResponse::<String>::error(
-                    "Failed to retrieve stats".to_string()
-                ))
+                    format!("Invalid criteria '{}' (expected arrival, transfers or walking)", criteria.unwrap_or_default())
+                ));
         }
+    };
+    if criteria.is_multi() && name == "arrive_by" {
+        return HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error("criteria only applies to departure, not arrive_by".to_string()));
+    }
+    let options = routing::Options { criteria, wheelchair, bikes };
+    let count = count.unwrap_or(3);
+    if !(1..=routing::MAX_ITINERARIES).contains(&count) {
+        return HttpResponse::BadRequest()
+            .json(ApiResponse::<String>::error(
+                format!("count must be between 1 and {}", routing::MAX_ITINERARIES)
+            ));
     }
-}
 
-async fn get_operators(state: web::Data<AppState>) -> HttpResponse {
-    match state.cache.lock() {
+    let (journeys, fallback) = match state.cache.lock() {
         Ok(cache) => {
-            let network_data = cache.to_network_data();
-
-            let mut operators = std::collections::HashMap::new();
-            for line in &network_data.lines {
-                *operators.entry(line.operator.clone()).or_insert(0) += 1;
-            }
-
-            let operator_info: Vec<_> = operators.iter()
-                .map(|(name, count)| {
-                    serde_json::json!({
-                        "name": name,
-                        "lines_count": count
-                    })
-                })
-                .collect();
+            let (Some(from_position), Some(to_position)) = (from.position(&cache), to.position(&cache)) else {
+                let unknown = if from.position(&cache).is_some() { &to } else { &from };
+                println!("⚠️  Stop not found: {}", unknown);
+                return HttpResponse::NotFound()
+                    .json(ApiResponse::<String>::error(
+                        format!("Stop '{}' not found", unknown)
+                    ));
+            };
 
-            println!("🏢 Operators requested: {} operators", operator_info.len());
-            HttpResponse::Ok().json(ApiResponse::success(operator_info))
+            let journeys = routing::plan(&cache, &from, &to, time, options, count);
+            let fallback = cache.upstreams.navitia.clone().filter(|_| journeys.is_empty()).map(|navitia| (navitia, from_position, to_position));
+            (journeys, fallback)
         }
         Err(e) => {
             eprintln!("❌ Failed to lock cache: {}", e);
-            HttpResponse::InternalServerError()
+            return HttpResponse::InternalServerError()
                 .json(ApiResponse::<String>::error(
-                    "Failed to retrieve operators".to_string()
-                ))
+                    "Failed to plan journey".to_string()
+                ));
+        }
+    };
+
+    // No local itinerary: ask Navitia, outside the cache lock
+    if let Some((navitia, from_position, to_position)) = fallback {
+        let now = NVTModels::get_current_timestamp() as u64;
+        let options = navitia::JourneyOptions { arrival: matches!(time, routing::Time::ArriveBy(_)), count, wheelchair, bikes };
+        let response = tokio::task::spawn_blocking(move || navitia.journeys(from_position, to_position, timestamp, options, now)).await;
+        match response {
+            Ok(Ok(json)) => {
+                let journeys: Vec<routing::Journey> = routing::from_navitia(&json, time).into_iter().take(count).collect();
+                println!("🧭 Journeys planned by Navitia from {} to {}: {} itineraries", from, to, journeys.len());
+                let mut response = ApiResponse::success(journeys);
+                response.sources.push("Navitia".to_string());
+                return HttpResponse::Ok().json(response);
+            }
+            Ok(Err(e)) => eprintln!("⚠️  Navitia journey lookup failed: {}", e),
+            Err(e) => eprintln!("❌ Navitia task panicked: {}", e),
         }
     }
+
+    println!("🧭 Journeys planned from {} to {}: {} itineraries", from, to, journeys.len());
+    HttpResponse::Ok().json(ApiResponse::success(journeys))
 }
 
-async fn get_stop_schedule(
+async fn share_journey(
+    req: HttpRequest,
     state: web::Data<AppState>,
-    path: web::Path<String>,
+    body: web::Json<routing::Journey>,
 ) -> HttpResponse {
-    let stop_id = path.into_inner();
-
-    match state.cache.lock() {
-        Ok(cache) => {
-            let scheduled_arrivals = NVTModels::get_scheduled_arrivals(&stop_id, &cache, 10);
-            
-            if scheduled_arrivals.is_empty() {
-                println!("📅 No scheduled arrivals found for stop: {}", stop_id);
-                HttpResponse::Ok().json(ApiResponse::success(scheduled_arrivals))
-            } else {
-                println!("📅 Scheduled arrivals retrieved for stop {}: {} arrivals", 
-                         stop_id, scheduled_arrivals.len());
-                HttpResponse::Ok().json(ApiResponse::success(scheduled_arrivals))
+    let client = privacy::remote_ip(&req.connection_info());
+    match state.journey_shares.lock() {
+        Ok(mut store) => {
+            match store.share(body.into_inner(), client) {
+                Ok(journey) => {
+                    println!("🔗 Journey shared: {} ({} legs)", journey.token, journey.itinerary.legs.len());
+                    HttpResponse::Created().json(ApiResponse::success(serde_json::json!({
+                        "token": journey.token,
+                        "url": format!("/j/{}", journey.token),
+                        "expires_at": journey.expires_at
+                    })))
+                }
+                Err(journey_share::ShareError::Storage(e)) => {
+                    eprintln!("❌ Failed to persist shared journey: {}", e);
+                    HttpResponse::InternalServerError()
+                        .json(ApiResponse::<String>::error(
+                            "Failed to store shared journey".to_string()
+                        ))
+                }
+                Err(e @ journey_share::ShareError::RateLimited { retry_after_secs }) => {
+                    HttpResponse::TooManyRequests()
+                        .insert_header(("Retry-After", retry_after_secs.to_string()))
+                        .json(ApiResponse::<String>::error(e.to_string()))
+                }
+                Err(e @ journey_share::ShareError::Full) => {
+                    println!("⚠️  Refused shared journey: {}", e);
+                    HttpResponse::ServiceUnavailable()
+                        .json(ApiResponse::<String>::error(e.to_string()))
+                }
+                Err(e) => {
+                    println!("⚠️  Rejected shared journey: {}", e);
+                    HttpResponse::BadRequest()
+                        .json(ApiResponse::<String>::error(e.to_string()))
+                }
             }
         }
         Err(e) => {
-            eprintln!("❌ Failed to lock cache: {}", e);
+            eprintln!("❌ Failed to lock journey shares: {}", e);
             HttpResponse::InternalServerError()
-                .json(ApiResponse::<Vec<tbm_api_models::ScheduledArrival>>::error(
-                    "Failed to retrieve schedule".to_string()
+                .json(ApiResponse::<String>::error(
+                    "Failed to store shared journey".to_string()
                 ))
         }
     }
 }
 
-async fn get_vehicle_details(
+async fn get_shared_journey(
     state: web::Data<AppState>,
     path: web::Path<String>,
+    req: HttpRequest,
 ) -> HttpResponse {
-    let vehicle_id = path.into_inner();
+    let token = path.into_inner();
 
-    match state.cache.lock() {
-        Ok(cache) => {
-            let vehicle_details = NVTModels::get_vehicle_details(&vehicle_id, &cache);
-            
-            match vehicle_details {
-                Some(details) => {
-                    println!("🚗 Vehicle details retrieved: {}", vehicle_id);
-                    HttpResponse::Ok().json(ApiResponse::success(details))
+    // Browsers get the share page; API clients asking for JSON get the itinerary itself
+    let wants_json = req.query_string().split('&').any(|p| p == "format=json") ||
+        req.headers().get("Accept")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.contains("application/json") && !v.contains("text/html"))
+            .unwrap_or(false);
+
+    match state.journey_shares.lock() {
+        Ok(store) => {
+            match store.get(&token) {
+                Some(journey) => {
+                    println!("🔗 Shared journey retrieved: {}", token);
+                    if wants_json {
+                        HttpResponse::Ok().json(ApiResponse::success(journey))
+                    } else {
+                        HttpResponse::Ok()
+                            .content_type("text/html; charset=utf-8")
+                            .body(journey_share::render_share_page(journey))
+                    }
                 }
                 None => {
-                    println!("⚠️  Vehicle not found: {}", vehicle_id);
+                    println!("⚠️  Shared journey not found: {}", token);
                     HttpResponse::NotFound()
                         .json(ApiResponse::<String>::error(
-                            format!("Vehicle '{}' not found", vehicle_id)
+                            format!("Shared journey '{}' not found or expired", token)
                         ))
                 }
             }
         }
         Err(e) => {
-            eprintln!("❌ Failed to lock cache: {}", e);
+            eprintln!("❌ Failed to lock journey shares: {}", e);
             HttpResponse::InternalServerError()
                 .json(ApiResponse::<String>::error(
-                    "Failed to retrieve vehicle details".to_string()
+                    "Failed to retrieve shared journey".to_string()
                 ))
         }
     }
 }
 
-async fn health_check() -> HttpResponse {
-    HttpResponse::Ok().json(serde_json::json!({
-        "status": "healthy",
-        "service": "TBM + TransGironde + SNCF Transit API",
-        "version": "1.2.0",
-        "sources": ["TBM", "TransGironde", "SNCF"],
-        "timestamp": NVTModels::get_current_timestamp(),
-        "embedded_frontend": true
-    }))
-}
-
-async fn force_refresh(state: web::Data<AppState>) -> HttpResponse {
-    println!("🔄 Manual refresh requested...");
+// ============================================================================
+// Background Task
+// ============================================================================
 
-    let state_clone = state.cache.clone();
-    match tokio::task::spawn_blocking(move || {
-        match state_clone.lock() {
-            Ok(mut cache) => NVTModels::smart_refresh(&mut cache),
-            Err(e) => Err(tbm_api_models::NVTError::NetworkError(
-                format!("Failed to lock cache: {}", e)
-            ))
-        }
-    }).await {
-        Ok(Ok(())) => {
-            println!("✓ Manual refresh completed successfully");
-            HttpResponse::Ok().json(ApiResponse::success("Data refreshed successfully"))
-        }
-        Ok(Err(e)) => {
-            eprintln!("⚠️  Manual refresh failed: {}", e);
-            HttpResponse::InternalServerError()
-                .json(ApiResponse::<String>::error(
-                    format!("Refresh failed: {}", e)
-                ))
-        }
+/// Archive the alerts and trip delays currently in the cache
+fn record_history(cache: &Arc<Mutex<CachedNetworkData>>, history: &Arc<Mutex<HistoryStore>>) {
+    let (alerts, trip_updates, network_data) = match cache.lock() {
+        Ok(cache) => (cache.alerts.clone(), cache.trip_updates.clone(), cache.to_network_data()),
         Err(e) => {
-            eprintln!("❌ Manual refresh task panicked: {}", e);
-            HttpResponse::InternalServerError()
-                .json(ApiResponse::<String>::error(
-                    "Refresh task panicked".to_string()
-                ))
+            eprintln!("❌ Failed to lock cache: {}", e);
+            return;
+        }
+    };
+
+    match history.lock() {
+        Ok(mut history) => {
+            history.record_alerts(&alerts);
+            history.record_sample(&network_data);
+            let ended = history.record_delays(&network_data, &trip_updates);
+            if let Err(e) = history.archive_delays(&ended) {
+                eprintln!("⚠️  Failed to archive trip delays: {}", e);
+            }
+            let retention_days = config::get().history.retention_days;
+            let pruned = history.prune(retention_days);
+            if pruned > 0 {
+                println!("🗄️  Dropped {} archived alerts past the retention period", pruned);
+            }
+            match history.prune_delays(retention_days) {
+                Ok(0) => {}
+                Ok(pruned) => println!("🗄️  Dropped {} archived trip delays past the retention period", pruned),
+                Err(e) => eprintln!("⚠️  Failed to prune trip delays: {}", e),
+            }
+            if let Err(e) = history.save() {
+                eprintln!("⚠️  Failed to save history: {}", e);
+            }
         }
+        Err(e) => eprintln!("❌ Failed to lock history: {}", e),
     }
 }
 
-// ============================================================================
-// Background Task
-// ============================================================================
+/// Primary URL and sync token when this instance is a standby (see sync.rs)
+fn standby_of() -> Option<(String, String)> {
+    let sync = config::get().sync.as_ref()?;
+    Some((sync.primary_url.clone()?, sync.resolve_token()?))
+}
 
-async fn data_refresh_task(state: Arc<Mutex<CachedNetworkData>>) {
+async fn data_refresh_task(
+    state: Arc<Mutex<CachedNetworkData>>,
+    history: Arc<Mutex<HistoryStore>>,
+    alert_events: alert_stream::AlertBroadcaster,
+) {
     let mut interval = time::interval(Duration::from_secs(30));
 
     loop {
@@ -431,16 +3668,31 @@ async fn data_refresh_task(state: Arc<Mutex<CachedNetworkData>>) {
 
         let state_clone = state.clone();
         match tokio::task::spawn_blocking(move || {
-            match state_clone.lock() {
-                Ok(mut cache) => NVTModels::smart_refresh(&mut cache),
-                Err(e) => Err(tbm_api_models::NVTError::NetworkError(
-                    format!("Failed to lock cache: {}", e)
-                ))
+            // A standby takes the primary's data, and only falls back to the upstreams without it
+            if let Some((primary_url, token)) = standby_of() {
+                match sync::pull(&state_clone, &primary_url, &token) {
+                    Ok(alerts) => return Ok(alerts),
+                    Err(e) => eprintln!("⚠️  Sync from the primary failed ({}), refreshing from the upstreams", e),
+                }
             }
+            let alerts = || state_clone.lock()
+                .map(|cache| cache.alerts.clone())
+                .map_err(|e| tbm_api_models::NVTError::NetworkError(format!("Failed to lock cache: {}", e)));
+            let previous_alerts = alerts()?;
+            NVTModels::smart_refresh_shared(&state_clone, false)?;
+            Ok::<_, tbm_api_models::NVTError>((previous_alerts, alerts()?))
         }).await {
-            Ok(Ok(())) => {
+            Ok(Ok((previous_alerts, alerts))) => {
                 println!("✓ Auto-refresh completed successfully at {}",
                          NVTModels::format_timestamp_full(NVTModels::get_current_timestamp()));
+
+                let changes = alert_events.publish(&previous_alerts, &alerts);
+                if changes > 0 {
+                    println!("📣 {} alert changes sent to the alert stream", changes);
+                }
+
+                let (cache, history) = (state.clone(), history.clone());
+                tokio::task::spawn_blocking(move || record_history(&cache, &history)).await.ok();
             }
             Ok(Err(e)) => {
                 eprintln!("⚠️  Auto-refresh failed: {}", e);
@@ -456,17 +3708,388 @@ async fn data_refresh_task(state: Arc<Mutex<CachedNetworkData>>) {
 // Server Setup
 // ============================================================================
 
-async fn run_server(cache: CachedNetworkData) -> std::io::Result<()> {
-    let app_state = AppState {
-        cache: Arc::new(Mutex::new(cache)),
+/// 429 for expensive requests that found no free slot (see ConcurrencyConfig)
+fn too_many_requests_error() -> actix_web::Error {
+    println!("🚦 Expensive endpoint busy, request shed");
+    actix_web::error::InternalError::from_response(
+        "too many concurrent requests",
+        HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", "1"))
+            .json(ApiResponse::<String>::error(
+                "Server busy, retry shortly".to_string()
+            )),
+    ).into()
+}
+
+/// 504 for requests past their deadline. The deadline applies at await points: handlers doing
+/// heavy blocking work run it with off_the_workers or spawn_blocking so it can be abandoned.
+fn request_timeout_error(deadline: std::time::Duration) -> actix_web::Error {
+    eprintln!("⏱️  Request aborted after {}s deadline", deadline.as_secs());
+    actix_web::error::InternalError::from_response(
+        "request deadline exceeded",
+        HttpResponse::GatewayTimeout()
+            .json(ApiResponse::<String>::error(
+                format!("Request took longer than {}s and was aborted", deadline.as_secs())
+            )),
+    ).into()
+}
+
+/// Gives `srv` until the deadline of the request's route to answer, then 504
+fn request_deadline<S, B>(
+    timeouts: &config::RequestTimeoutsConfig,
+    req: actix_web::dev::ServiceRequest,
+    srv: &S,
+) -> impl std::future::Future<Output = Result<actix_web::dev::ServiceResponse<B>, actix_web::Error>> + use<S, B>
+where
+    S: Service<actix_web::dev::ServiceRequest, Response = actix_web::dev::ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    let deadline = timeouts.deadline_for(req.path());
+    let response = srv.call(req);
+    async move {
+        match tokio::time::timeout(deadline, response).await {
+            Ok(result) => result,
+            Err(_) => Err(request_timeout_error(deadline)),
+        }
+    }
+}
+
+/// 401/429 for requests to the key-protected routes without a valid or with an exhausted key
+fn api_key_error(refusal: api_keys::Refusal) -> actix_web::Error {
+    let response = match refusal {
+        api_keys::Refusal::MissingKey | api_keys::Refusal::UnknownKey => {
+            println!("🔒 Request refused: {}", if refusal == api_keys::Refusal::MissingKey { "no API key" } else { "unknown API key" });
+            HttpResponse::Unauthorized()
+                .insert_header(("WWW-Authenticate", api_keys::HEADER))
+                .json(ApiResponse::<String>::error(
+                    format!("A valid {} header is required", api_keys::HEADER)
+                ))
+        }
+        api_keys::Refusal::RateLimited { key, retry_after_secs } => {
+            println!("🚦 API key '{}' over its rate limit", key);
+            HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after_secs.to_string()))
+                .json(ApiResponse::<String>::error(
+                    format!("Rate limit reached, retry in {}s", retry_after_secs)
+                ))
+        }
+    };
+    actix_web::error::InternalError::from_response("api key refused", response).into()
+}
+
+/// Lets requests through to `srv` when the API keys allow them (see api_keys.rs)
+fn api_key_gate<S, B>(
+    guard: &api_keys::ApiKeyGuard,
+    req: actix_web::dev::ServiceRequest,
+    srv: &S,
+) -> impl std::future::Future<Output = Result<actix_web::dev::ServiceResponse<B>, actix_web::Error>> + use<S, B>
+where
+    S: Service<actix_web::dev::ServiceRequest, Response = actix_web::dev::ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    let header = |name| req.headers().get(name).and_then(|value| value.to_str().ok());
+    let given = api_keys::given_key(header(api_keys::HEADER), header(actix_web::http::header::AUTHORIZATION.as_str()));
+    let response = guard.check(req.path(), given, NVTModels::get_current_timestamp() as u64)
+        .map(|key| {
+            if let Some(key) = key {
+                println!("🔑 {} {} with API key '{}'", req.method(), req.path(), key);
+            }
+            srv.call(req)
+        });
+    async move {
+        match response {
+            Ok(response) => response.await,
+            Err(refusal) => Err(api_key_error(refusal)),
+        }
+    }
+}
+
+/// Cache-Control and Last-Modified of GET responses by endpoint class, and 304 for conditional
+/// requests made since the data last changed (see http_cache.rs)
+async fn cache_headers(
+    req: actix_web::dev::ServiceRequest,
+    next: middleware::Next<impl actix_web::body::MessageBody + 'static>,
+) -> Result<actix_web::dev::ServiceResponse<actix_web::body::BoxBody>, actix_web::Error> {
+    use actix_web::http::header::{HeaderValue, CACHE_CONTROL, LAST_MODIFIED, VARY};
+
+    let is_get = matches!(*req.method(), actix_web::http::Method::GET | actix_web::http::Method::HEAD);
+    let Some(class) = is_get.then(|| http_cache::classify(req.path())).flatten() else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+    // Never waits on the cache on the async workers: while a refresh or a slow handler holds it,
+    // the response goes out without Last-Modified (and conditional requests get the full body)
+    let last_modified = req.app_data::<web::Data<AppState>>()
+        .and_then(|state| state.cache.try_lock().ok().and_then(|cache| class.last_modified(&cache)));
+    let cache_control = class.cache_control(&config::get().http_cache);
+
+    let add_headers = |headers: &mut actix_web::http::header::HeaderMap| {
+        if !headers.contains_key(CACHE_CONTROL) && let Ok(value) = HeaderValue::from_str(&cache_control) {
+            headers.insert(CACHE_CONTROL, value);
+        }
+        if let Some(last_modified) = last_modified
+            && !headers.contains_key(LAST_MODIFIED)
+            && let Ok(value) = HeaderValue::from_str(&http_cache::http_date(last_modified)) {
+            headers.insert(LAST_MODIFIED, value);
+        }
+        // Alerts of real-time responses are in the language asked for
+        if class == http_cache::CacheClass::RealTime {
+            headers.append(VARY, HeaderValue::from_static("Accept-Language"));
+        }
+    };
+
+    if let Some(last_modified) = last_modified && http_cache::not_modified_since(req.headers(), last_modified) {
+        let mut response = HttpResponse::NotModified().finish();
+        add_headers(response.headers_mut());
+        return Ok(req.into_response(response));
+    }
+    let mut response = next.call(req).await?;
+    let status = response.status();
+    if status.is_success() || status == actix_web::http::StatusCode::NOT_MODIFIED {
+        add_headers(response.headers_mut());
+    }
+    Ok(response.map_into_boxed_body())
+}
+
+/// CORS middleware of a policy (see CorsConfig); requests from origins it refuses get 400
+fn cors(policy: Arc<config::CorsConfig>) -> Cors {
+    let (methods, max_age) = (policy.methods(), policy.max_age_secs);
+    Cors::default()
+        .allowed_origin_fn(move |origin, head| {
+            let (Ok(origin), host) = (origin.to_str(), head.headers().get("host").and_then(|h| h.to_str().ok())) else {
+                return false;
+            };
+            // Preflight requests ask for the method of the request to come
+            let method = head.headers().get("access-control-request-method")
+                .and_then(|m| m.to_str().ok())
+                .unwrap_or(head.method.as_str());
+            policy.allows(origin, host, method, head.uri.path())
+        })
+        .allowed_methods(methods.iter().map(String::as_str))
+        .allow_any_header()
+        .expose_any_header()
+        .supports_credentials()
+        .max_age(max_age)
+        .block_on_origin_mismatch(true)
+}
+
+/// API routes, in registration order; GET /api lists them
+const ENDPOINTS: &[api_index::Endpoint] = &[
+    endpoint("GET", "/api/schema", "JSON Schema of the API", &[], || web::get().to(get_api_schema)),
+    endpoint("GET", "/admin/history/export", "Alert archive or trip delays as CSV, JSON or Parquet", &["table", "from", "to", "format"], || web::get().to(export_history)),
+    endpoint("POST", "/admin/simulate", "Load a GTFS what-if into the sandbox", &["source"], || web::post().to(post_simulation)),
+    endpoint("DELETE", "/admin/simulate", "Drop the sandbox", &[], || web::delete().to(delete_simulation)),
+    endpoint("GET", "/admin/sync", "Cache snapshot for a standby", &["static_since"], || web::get().to(get_sync_snapshot)),
+    endpoint("GET", "/api/wfs", "WFS for GIS tools", &["service", "version", "request", "typeNames", "bbox", "count", "startIndex"], || web::get().to(wfs_service)),
+    endpoint("POST", "/api/graphql", "GraphQL queries", &["query", "variables", "operationName"], || web::post().to(graphql_post)),
+    endpoint("GET", "/api/graphql", "GraphQL queries", &["query", "variables", "operationName"], || web::get().to(graphql_get)),
+    endpoint("GET", "/api/graphql/schema", "GraphQL schema (SDL)", &[], || web::get().to(graphql_schema)),
+    endpoint("POST", "/graphql", "GraphQL queries", &["query", "variables", "operationName"], || web::post().to(graphql_post)),
+    endpoint("GET", "/graphql", "GraphQL queries", &["query", "variables", "operationName"], || web::get().to(graphql_get)),
+    endpoint("GET", "/api/grafana", "Grafana datasource health", &[], || web::get().to(grafana_health)),
+    endpoint("POST", "/api/grafana/search", "Grafana metric names", &[], || web::post().to(grafana_search)),
+    endpoint("POST", "/api/grafana/query", "Grafana time series", &[], || web::post().to(grafana_query)),
+    endpoint("POST", "/api/grafana/annotations", "Grafana alert annotations", &[], || web::post().to(grafana_annotations)),
+    endpoint("GET", "/api/tbm/network", "Full network data", &["dedupe", "bbox"], || web::get().to(get_network_data)),
+    endpoint("GET", "/api/tbm/stops", "All stops", &["dedupe", "accessible", "commune", "operator", "bbox", "page", "per_page", "fields", "format"], || web::get().to(get_stops)),
+    endpoint("GET", "/api/tbm/stops/nearby", "Stops around a point", &["lat", "lon", "radius", "limit"], || web::get().to(get_nearby_stops)),
+    endpoint("POST", "/api/tbm/stops/batch", "Stops by a list of IDs", &["departures", "limit", "tz", "time_format", "lang"], || web::post().to(get_stops_batch)),
+    endpoint("GET", "/api/tbm/around", "Stops around with departures", &["lat", "lon", "radius", "limit", "tz", "time_format", "lang"], || web::get().to(get_around)),
+    endpoint("GET", "/api/tbm/stops/clusters", "Stop map clusters", &["zoom", "bbox"], || web::get().to(get_stop_clusters)),
+    endpoint("GET", "/api/tbm/search", "Search stops and lines", &["q", "limit"], || web::get().to(search_network)),
+    endpoint("GET", "/api/tbm/lines", "All lines", &["operator", "mode", "page", "per_page", "fields"], || web::get().to(get_lines)),
+    endpoint("GET", "/api/tbm/vehicles", "Real-time vehicles", &["bbox", "format", "zoom", "since", "interpolate"], || web::get().to(get_vehicles)),
+    endpoint("GET", "/api/tbm/bikes", "Bike share stations", &["bbox"], || web::get().to(get_bike_stations)),
+    endpoint("GET", "/api/tbm/bikes/{station_id}", "Bike station status", &[], || web::get().to(get_bike_station)),
+    endpoint("GET", "/api/tbm/shapes", "Line shapes", &["line", "bbox", "format", "tolerance", "zoom"], || web::get().to(get_shapes)),
+    endpoint("GET", "/tiles/{z}/{x}/{y}.mvt", "Stops and shapes as vector tiles", &[], || web::get().to(get_vector_tile)),
+    endpoint("GET", "/api/tbm/alerts", "Active alerts", &["route", "stop", "severity_min", "active_at", "expand", "lang"], || web::get().to(get_alerts)),
+    endpoint("GET", "/api/tbm/alerts/history", "Archived alerts", &["from", "to", "route"], || web::get().to(get_alert_history)),
+    endpoint("GET", "/api/tbm/alerts/stream", "Alert changes (SSE)", &[], || web::get().to(stream_alerts)),
+    endpoint("GET", "/api/tbm/stop/{id}", "Stop by ID", &["lang"], || web::get().to(get_stop_by_id)),
+    endpoint("GET", "/api/tbm/stop/{id}/schedule", "Next scheduled arrivals", &["tz", "time_format"], || web::get().to(get_stop_schedule)),
+    endpoint("GET", "/api/tbm/stop/{id}/arrivals", "Timetable arrivals", &["limit", "tz", "time_format"], || web::get().to(get_stop_arrivals)),
+    endpoint("GET", "/api/tbm/stop/{id}/departures", "Merged departures", &["limit", "tz", "time_format", "accessible"], || web::get().to(get_stop_departures)),
+    endpoint("GET", "/api/tbm/stop/{id}/busyness", "Hourly busyness", &["date"], || web::get().to(get_stop_busyness)),
+    endpoint("GET", "/api/tbm/stop/{id}/qr.svg", "Stop QR code (SVG)", &["scale", "ec"], || web::get().to(get_stop_qr)),
+    endpoint("GET", "/api/tbm/stop/{id}/departures.txt", "Text board", &["limit"], || web::get().to(get_stop_departures_text)),
+    endpoint("GET", "/api/tbm/vehicle/{id}", "Vehicle details", &[], || web::get().to(get_vehicle_details)),
+    endpoint("GET", "/api/tbm/trip/{id}", "Trip with stop times", &[], || web::get().to(get_trip_details)),
+    endpoint("GET", "/api/tbm/line/{code}", "Line by code", &[], || web::get().to(get_line_by_code)),
+    endpoint("GET", "/api/tbm/line/{code}/badge.svg", "Line bullet (SVG)", &[], || web::get().to(get_line_badge)),
+    endpoint("GET", "/api/tbm/line/{code}/timetable", "Day timetable at a stop", &["stop", "date", "tz", "time_format"], || web::get().to(get_line_timetable)),
+    endpoint("GET", "/api/tbm/line/{code}/frequency", "Headways by time band", &[], || web::get().to(get_line_frequency)),
+    endpoint("GET", "/api/tbm/line/{code}/stops", "Stops in travel order", &["direction"], || web::get().to(get_line_stops)),
+    endpoint("GET", "/api/tbm/line/{code}/shape", "Line geometry", &["format", "tolerance", "zoom"], || web::get().to(get_line_shape)),
+    endpoint("GET", "/api/tbm/line/{code}/vehicles.geojson", "Live vehicles", &[], || web::get().to(get_line_vehicles_geojson)),
+    endpoint("GET", "/api/tbm/line/{code}/map.png", "Static line map (PNG)", &["width", "height"], || web::get().to(get_line_map)),
+    endpoint("GET", "/api/tbm/line/{code}/elevation", "Elevation profile", &[], || web::get().to(get_line_elevation)),
+    endpoint("GET", "/api/tbm/operator/{name}", "Lines by operator", &[], || web::get().to(get_lines_by_operator)),
+    endpoint("GET", "/api/tbm/operator/{name}/branding", "Brand colors/logo", &[], || web::get().to(get_operator_branding)),
+    endpoint("GET", "/api/tbm/operator/{name}/logo", "Operator logo", &[], || web::get().to(get_operator_logo)),
+    endpoint("GET", "/api/tbm/operators", "List all operators", &[], || web::get().to(get_operators)),
+    endpoint("GET", "/api/tbm/modes", "Lines by transport mode", &[], || web::get().to(get_modes)),
+    endpoint("GET", "/api/tbm/agencies", "GTFS agencies", &[], || web::get().to(get_agencies)),
+    endpoint("GET", "/api/tbm/agency/{id}/lines", "Lines of an agency", &[], || web::get().to(get_agency_lines)),
+    endpoint("GET", "/api/tbm/kiosk/{config_id}", "Kiosk screens", &[], || web::get().to(get_kiosk)),
+    endpoint("GET", "/api/tbm/version", "Build and runtime info", &[], || web::get().to(get_version)),
+    endpoint("GET", "/api/tbm/stats", "Cache statistics", &["format"], || web::get().to(get_stats)),
+    endpoint("GET", "/api/tbm/quality", "GTFS feed quality", &[], || web::get().to(get_feed_quality)),
+    endpoint("GET", "/api/tbm/export/gtfs.zip", "Merged GTFS feed", &[], || web::get().to(export_gtfs)),
+    endpoint("GET", "/api/tbm/gtfs-rt/vehicles", "Merged GTFS-RT vehicle positions", &[], || web::get().to(get_gtfs_rt_vehicles)),
+    endpoint("GET", "/api/tbm/gtfs-rt/alerts", "Merged GTFS-RT alerts", &[], || web::get().to(get_gtfs_rt_alerts)),
+    endpoint("GET", "/api/tbm/gtfs-rt/trip-updates", "Merged GTFS-RT trip updates", &[], || web::get().to(get_gtfs_rt_trip_updates)),
+    endpoint("GET", "/api/siri/2.0/stop-monitoring.json", "SIRI-Lite StopMonitoring", &["MonitoringRef", "MaximumStopVisits", "LineRef"], || web::get().to(siri_stop_monitoring)),
+    endpoint("POST", "/api/tbm/refresh", "Force refresh data", &["full", "static", "dynamic"], || web::post().to(force_refresh)),
+    endpoint("GET", "/api/tbm/refresh/{job_id}", "Refresh job status", &[], || web::get().to(get_refresh_job)),
+    endpoint("DELETE", "/api/tbm/cache/{source}", "Purge a GTFS cache file", &[], || web::delete().to(purge_cache)),
+    endpoint("GET", "/api/tbm/journey", "Plan a journey", &["from_stop", "to_stop", "from", "to", "departure", "arrive_by", "criteria", "wheelchair", "bikes", "count"], || web::get().to(get_journey)),
+    endpoint("POST", "/api/tbm/journey/share", "Share an itinerary", &[], || web::post().to(share_journey)),
+];
+
+const fn endpoint(
+    method: &'static str,
+    path: &'static str,
+    summary: &'static str,
+    params: &'static [&'static str],
+    route: fn() -> actix_web::Route,
+) -> api_index::Endpoint {
+    api_index::Endpoint { method, path, summary, params, route }
+}
+
+/// The GET endpoints of /api/tbm answering from the sandbox, as /api/sandbox/tbm/...; until a
+/// simulation is loaded, nothing matches
+fn sandbox_routes(state: &AppState) -> actix_web::Scope {
+    let sandbox = state.sandbox.clone();
+    let sandbox_state = AppState { cache: state.sandbox.clone(), ..state.clone() };
+    let loaded = actix_web::guard::fn_guard(move |_| sandbox.lock().is_ok_and(|sandbox| simulation::is_loaded(&sandbox)));
+
+    ENDPOINTS.iter()
+        .filter(|endpoint| endpoint.method == "GET")
+        .filter_map(|endpoint| endpoint.path.strip_prefix("/api").filter(|path| path.starts_with("/tbm/")).map(|path| (path, endpoint.route)))
+        .fold(web::scope("/api/sandbox").guard(loaded).app_data(web::Data::new(sandbox_state)), |scope, (path, route)| scope.route(path, route()))
+}
+
+/// API routes, shared by the server and the `bench` command
+fn api_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/api", web::get().to(get_api_index));
+    for endpoint in ENDPOINTS {
+        match (endpoint.method, endpoint.path) {
+            // GTFS fragments are zips, well over the default 256 KiB body limit
+            ("POST", "/admin/simulate") => cfg.service(web::resource(endpoint.path)
+                .guard(actix_web::guard::Post())
+                .app_data(web::PayloadConfig::new(config::SimulationConfig::upload_limit()))
+                .route((endpoint.route)())),
+            _ => cfg.route(endpoint.path, (endpoint.route)()),
+        };
+    }
+}
+
+/// Load the initial cache behind the running server, then start the tasks working on it;
+/// /health/ready answers 503 until then
+async fn start_with_initial_cache(app_state: AppState) {
+    let cache = match tokio::task::spawn_blocking(initial_cache).await {
+        Ok(cache) => cache,
+        Err(e) => {
+            eprintln!("❌ Cache initialization panicked: {}", e);
+            std::process::exit(1);
+        }
     };
+    match app_state.cache.lock() {
+        Ok(mut current) => *current = cache,
+        Err(e) => {
+            eprintln!("❌ Failed to lock cache: {}", e);
+            std::process::exit(1);
+        }
+    }
+    record_history(&app_state.cache, &app_state.history);
 
     // Start background refresh task
     let refresh_cache = app_state.cache.clone();
+    let refresh_history = app_state.history.clone();
+    let refresh_alert_events = app_state.alert_events.clone();
     tokio::spawn(async move {
-        data_refresh_task(refresh_cache).await;
+        data_refresh_task(refresh_cache, refresh_history, refresh_alert_events).await;
+    });
+
+    // Optional morning disruption digest
+    if let Some(digest_config) = config::get().digest.clone() {
+        let digest_cache = app_state.cache.clone();
+        println!("📧 Disruption digest scheduled daily at {}", digest_config.send_at);
+        tokio::spawn(async move {
+            digest::digest_task(digest_config, digest_cache).await;
+        });
+    }
+
+    // Optional OpenStreetMap amenity enrichment
+    if let Some(amenities_config) = config::get().amenities.clone() {
+        amenities::spawn(amenities_config, app_state.cache.clone());
+    }
+
+    // Optional chat bot
+    match config::get().bot.clone() {
+        #[cfg(feature = "bot")]
+        Some(bot_config) => bot::spawn(bot_config, app_state.cache.clone()),
+        #[cfg(not(feature = "bot"))]
+        Some(_) => println!("⚠️  Bot configured but this build lacks the \"bot\" feature, ignoring"),
+        None => {}
+    }
+}
+
+/// The initial cache, from the primary for a standby, otherwise from the upstreams; exits
+/// without it
+fn initial_cache() -> CachedNetworkData {
+    println!("\n📡 Initializing network data cache...");
+    println!("   This includes TBM, TransGironde, and SNCF data...\n");
+
+    let from_primary = standby_of().and_then(|(primary_url, token)| {
+        println!("🔁 Standby of {}, loading the primary's data...", primary_url);
+        sync::initial_cache(&primary_url, &token)
+            .inspect_err(|e| eprintln!("⚠️  {}, loading from the upstreams", e))
+            .ok()
     });
 
+    let initialized = match from_primary {
+        Some(cache) => Ok(cache),
+        None => NVTModels::initialize_cache(),
+    };
+    match initialized {
+        Ok(cache) => {
+            println!("\n╔════════════════════════════════════════════════════════════╗");
+            println!("║  ✅ Cache Initialized Successfully!                        ║");
+            println!("╚════════════════════════════════════════════════════════════╝");
+            cache
+        }
+        Err(e) => {
+            eprintln!("\n╔════════════════════════════════════════════════════════════╗");
+            eprintln!("║  ❌ INITIALIZATION FAILED                                  ║");
+            eprintln!("╚════════════════════════════════════════════════════════════╝");
+            eprintln!("\n❌ Failed to initialize cache: {}", e);
+            eprintln!("Server cannot start without initial data.");
+            eprintln!("\n💡 Troubleshooting:");
+            eprintln!("   1. Check your internet connection");
+            eprintln!("   2. Verify API endpoints are accessible");
+            eprintln!("   3. Check firewall settings");
+            eprintln!("   4. Review error message above for specific issues\n");
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run_server() -> std::io::Result<()> {
+    let upstreams = tbm_api_models::Upstreams::default();
+    let app_state = AppState {
+        sandbox: Arc::new(Mutex::new(CachedNetworkData::empty(upstreams.clone()))),
+        cache: Arc::new(Mutex::new(CachedNetworkData::empty(upstreams))),
+        journey_shares: Arc::new(Mutex::new(JourneyShareStore::load())),
+        history: Arc::new(Mutex::new(HistoryStore::load())),
+        elevation: Arc::new(Mutex::new(ElevationStore::load())),
+        alert_events: alert_stream::AlertBroadcaster::default(),
+        refresh_jobs: refresh_jobs::RefreshJobs::default(),
+        tiles: vector_tiles::TileCache::default(),
+        clock: NVTModels::paris_now,
+    };
+    let initial_state = app_state.clone();
+
     println!("\n╔════════════════════════════════════════════════════════════╗");
     println!("║  🚀 TBM + TransGironde + SNCF Transit Server (Embedded UI)║");
     println!("╚════════════════════════════════════════════════════════════╝\n");
@@ -484,20 +4107,79 @@ async fn run_server(cache: CachedNetworkData) -> std::io::Result<()> {
     println!("│ API - Network Data:                                         │");
     println!("│   GET  /api/tbm/network            - Full network data      │");
     println!("│   GET  /api/tbm/stops              - All stops              │");
+    println!("│   GET  /api/tbm/stops/nearby       - Stops around a point   │");
+    println!("│   POST /api/tbm/stops/batch        - Stops by IDs           │");
+    println!("│   GET  /api/tbm/around             - Stops, departures      │");
+    println!("│   GET  /api/tbm/stops/clusters     - Stop map clusters      │");
+    println!("│   GET  /api/tbm/search?q=          - Search stops and lines │");
     println!("│   GET  /api/tbm/lines              - All lines              │");
     println!("│   GET  /api/tbm/vehicles           - Real-time vehicles     │");
+    println!("│   GET  /api/tbm/bikes              - Bike share stations    │");
+    println!("│   GET  /api/tbm/bikes/:station_id  - Bike station status    │");
+    println!("│   GET  /api/tbm/shapes             - Line shapes            │");
+    println!("│   GET  /tiles/:z/:x/:y.mvt         - Vector tiles (MVT)     │");
     println!("│   GET  /api/tbm/alerts             - Active alerts          │");
+    println!("│   GET  /api/tbm/alerts/history     - Archived alerts        │");
+    println!("│   GET  /api/tbm/alerts/stream      - Alert changes (SSE)    │");
     println!("├─────────────────────────────────────────────────────────────┤");
     println!("│ API - Specific Resources:                                   │");
     println!("│   GET  /api/tbm/stop/:id           - Stop by ID             │");
+    println!("│   GET  /api/tbm/stop/:id/arrivals  - Timetable arrivals     │");
+    println!("│   GET  /api/tbm/stop/:id/departures - Merged departures     │");
+    println!("│   GET  /api/tbm/stop/:id/busyness  - Hourly busyness        │");
+    println!("│   GET  /api/tbm/stop/:id/qr.svg    - Stop QR code (SVG)     │");
+    println!("│   GET  /api/tbm/stop/:id/departures.txt - Text board        │");
+    println!("│   GET  /api/tbm/vehicle/:id        - Vehicle details        │");
+    println!("│   GET  /api/tbm/trip/:id           - Trip with stop times   │");
     println!("│   GET  /api/tbm/line/:code         - Line by code           │");
+    println!("│   GET  /api/tbm/line/:code/stops   - Stops in travel order  │");
+    println!("│   GET  /api/tbm/line/:code/timetable - Day timetable        │");
+    println!("│   GET  /api/tbm/line/:code/frequency - Headways             │");
+    println!("│   GET  /api/tbm/line/:code/shape   - Line geometry          │");
+    println!("│   GET  /api/tbm/line/:code/badge.svg - Line bullet (SVG)    │");
+    println!("│   GET  /api/tbm/line/:code/vehicles.geojson - Live vehicles │");
+    println!("│   GET  /api/tbm/line/:code/map.png - Static line map (PNG)  │");
+    println!("│   GET  /api/tbm/line/:code/elevation - Elevation profile    │");
     println!("│   GET  /api/tbm/operator/:name     - Lines by operator      │");
+    println!("│   GET  /api/tbm/operator/:name/branding - Brand colors/logo │");
+    println!("│   GET  /api/tbm/kiosk/:config_id   - Kiosk screens          │");
+    println!("├─────────────────────────────────────────────────────────────┤");
+    println!("│ API - Journeys:                                             │");
+    println!("│   GET  /api/tbm/journey            - Plan a journey         │");
+    println!("│   POST /api/tbm/journey/share      - Share an itinerary     │");
+    println!("│   GET  /j/:token                   - Shared journey page    │");
     println!("├─────────────────────────────────────────────────────────────┤");
     println!("│ API - Meta & Control:                                       │");
     println!("│   GET  /api/tbm/operators          - List all operators     │");
+    println!("│   GET  /api/tbm/modes              - Lines by mode          │");
+    println!("│   GET  /api/tbm/agencies           - GTFS agencies          │");
+    println!("│   GET  /api/tbm/agency/:id/lines   - Lines of an agency     │");
+    println!("│   GET  /api/tbm/version            - Build and runtime info │");
     println!("│   GET  /api/tbm/stats              - Cache statistics       │");
+    println!("│   GET  /api/tbm/quality            - GTFS feed quality      │");
+    println!("│   GET  /api/tbm/export/gtfs.zip    - Merged GTFS feed (zip) │");
+    println!("│   GET  /api/tbm/gtfs-rt/vehicles   - GTFS-RT vehicles       │");
+    println!("│   GET  /api/tbm/gtfs-rt/alerts     - GTFS-RT alerts         │");
+    println!("│   GET  /api/tbm/gtfs-rt/trip-updates - GTFS-RT trip updates │");
+    println!("│   GET  /api/siri/2.0/stop-monitoring.json - SIRI-Lite SM    │");
+    println!("│   GET  /api                        - Index of the endpoints │");
+    println!("│   GET  /api/schema                 - JSON Schema of the API │");
+    println!("│   GET  /admin/history/export       - Alerts/delays as files │");
+    println!("│   GET  /admin/sync                 - Standby cache snapshot │");
+    println!("│   POST /admin/simulate             - GTFS what-if sandbox   │");
+    println!("│   DEL  /admin/simulate             - Drop the sandbox       │");
+    println!("│   GET  /api/sandbox/tbm/...        - API on the sandbox     │");
+    println!("│   POST /api/grafana/query          - Grafana datasource     │");
+    println!("│   GET  /api/wfs                    - WFS for GIS tools      │");
+    println!("│   POST /api/graphql                - GraphQL queries        │");
+    println!("│   GET  /api/graphql/schema         - GraphQL schema (SDL)   │");
+    println!("│   POST /graphql                    - Same as /api/graphql   │");
     println!("│   POST /api/tbm/refresh            - Force refresh data     │");
+    println!("│   GET  /api/tbm/refresh/:job_id    - Refresh job status     │");
+    println!("│   DEL  /api/tbm/cache/:source      - Purge a GTFS cache     │");
     println!("│   GET  /health                     - Health check           │");
+    println!("│   GET  /health/live                - Liveness probe         │");
+    println!("│   GET  /health/ready               - Readiness probe        │");
     println!("└─────────────────────────────────────────────────────────────┘\n");
 
     println!("💡 Quick Start:");
@@ -505,40 +4187,86 @@ async fn run_server(cache: CachedNetworkData) -> std::io::Result<()> {
     println!("   2. The map will load automatically!");
     println!("   3. API available at: http://localhost:8080/api/tbm/*\n");
 
-    HttpServer::new(move || {
-        let cors = Cors::permissive();
+    let expensive_slots = Arc::new(Semaphore::new(config::get().concurrency.max_concurrent.max(1)));
+    let anonymizer = Arc::new(privacy::IpAnonymizer::new(&config::get().privacy));
+    let access_log = Arc::new(access_log::AccessLog::new(&config::get().access_log));
+    let cors_policy = Arc::new(config::get().cors.resolve());
+    let api_key_guard = Arc::new(api_keys::ApiKeyGuard::from_config(config::get()));
+    if api_key_guard.enabled() {
+        println!("🔑 API keys required on {}", api_key_guard.routes().join(", "));
+    } else {
+        println!("⚠️  No API keys configured, {} are open to anyone", api_key_guard.routes().join(", "));
+    }
+
+    let server = HttpServer::new(move || {
+        let cors = cors(cors_policy.clone());
+        let api_key_guard = api_key_guard.clone();
+        let expensive_slots = expensive_slots.clone();
+        let anonymizer = anonymizer.clone();
+        let access_log = access_log.clone();
 
         App::new()
             .app_data(web::Data::new(app_state.clone()))
+            // Queue or shed requests to expensive endpoints beyond the concurrency limit
+            .wrap_fn(move |req, srv| {
+                let concurrency = &config::get().concurrency;
+                let slots = concurrency.is_limited(req.path()).then(|| expensive_slots.clone());
+                let queue_timeout = std::time::Duration::from_millis(concurrency.queue_timeout_ms);
+                let response = srv.call(req);
+                async move {
+                    let _permit = match slots {
+                        Some(slots) => match tokio::time::timeout(queue_timeout, slots.acquire_owned()).await {
+                            Ok(Ok(permit)) => Some(permit),
+                            _ => return Err(too_many_requests_error()),
+                        },
+                        None => None,
+                    };
+                    response.await
+                }
+            })
+            // Innermost, so the 504 still goes through CORS, logging and compression
+            .wrap_fn(|req, srv| request_deadline(&config::get().request_timeouts, req, srv))
+            .wrap(middleware::from_fn(cache_headers))
+            .wrap_fn(move |req, srv| api_key_gate(&api_key_guard, req, srv))
             .wrap(cors)
-            .wrap(middleware::Logger::default())
+            // One access log line per request, with the uncompressed response size
+            .wrap_fn(move |req, srv| {
+                let pending = access_log::PendingEntry::start(&req, &anonymizer);
+                let response = srv.call(req);
+                let access_log = access_log.clone();
+                async move {
+                    match response.await {
+                        Ok(res) => {
+                            access_log.record(&pending.finish(&res));
+                            Ok(res)
+                        }
+                        Err(e) => {
+                            access_log.record(&pending.failed(&e));
+                            Err(e)
+                        }
+                    }
+                }
+            })
             .wrap(middleware::Compress::default())
             // Frontend routes
             .route("/", web::get().to(serve_index))
             .route("/tbm-transit.js", web::get().to(serve_js))
             // Health check
             .route("/health", web::get().to(health_check))
+            .route("/health/live", web::get().to(health_live))
+            .route("/health/ready", web::get().to(health_ready))
+            // Shared journey links
+            .route("/j/{token}", web::get().to(get_shared_journey))
             // API routes
-            .service(
-                web::scope("/api/tbm")
-                    .route("/network", web::get().to(get_network_data))
-                    .route("/stops", web::get().to(get_stops))
-                    .route("/lines", web::get().to(get_lines))
-                    .route("/vehicles", web::get().to(get_vehicles))
-                    .route("/alerts", web::get().to(get_alerts))
-                    .route("/stop/{id}", web::get().to(get_stop_by_id))
-                    .route("/stop/{id}/schedule", web::get().to(get_stop_schedule))
-                    .route("/vehicle/{id}", web::get().to(get_vehicle_details))
-                    .route("/line/{code}", web::get().to(get_line_by_code))
-                    .route("/operator/{name}", web::get().to(get_lines_by_operator))
-                    .route("/operators", web::get().to(get_operators))
-                    .route("/stats", web::get().to(get_stats))
-                    .route("/refresh", web::post().to(force_refresh))
-            )
+            .configure(api_routes)
+            .service(sandbox_routes(&app_state))
     })
         .bind(("0.0.0.0", 8080))?
-        .run()
-        .await
+        .run();
+
+    // Bound first, so liveness probes answer during the long initial download
+    tokio::spawn(start_with_initial_cache(initial_state));
+    server.await
 }
 
 // ============================================================================
@@ -546,41 +4274,36 @@ async fn run_server(cache: CachedNetworkData) -> std::io::Result<()> {
 // ============================================================================
 
 fn main() -> std::io::Result<()> {
+    version::mark_started();
+
+    // `bench [options]` runs the load-test harness instead of the server (see bench.rs)
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("bench") {
+        return bench::run(&args[1..]);
+    }
+    // `check [options]` runs the preflight checks and exits with their verdict (see preflight.rs)
+    if args.first().map(String::as_str) == Some("check") {
+        std::process::exit(preflight::run(&args[1..]));
+    }
+    // `gen-client --lang ts|rust [options]` writes typed client bindings (see client_gen.rs)
+    if args.first().map(String::as_str) == Some("gen-client") {
+        std::process::exit(client_gen::run(&args[1..]));
+    }
+
     println!("\n╔════════════════════════════════════════════════════════════╗");
     println!("║                                                            ║");
     println!("║    🚀 TBM + TransGironde + SNCF Transit Server             ║");
     println!("║       with Embedded Web UI                                ║");
     println!("║                                                            ║");
-    println!("║    Version: 1.2.0                                          ║");
+    println!("║    Version: {:<47}║", version::VERSION);
+    println!("║    Commit: {:<48}║", version::GIT_COMMIT);
+    println!("║    Built: {:<49}║", NVTModels::format_timestamp_full(version::build_timestamp()));
     println!("║    User: Cyclolysisss                                      ║");
-    println!("║    Date: 2025-11-21 10:54:00                               ║");
     println!("║                                                            ║");
     println!("╚════════════════════════════════════════════════════════════╝\n");
 
-    println!("📡 Initializing network data cache...");
-    println!("   This includes TBM, TransGironde, and SNCF data...\n");
-
-    let cache = match NVTModels::initialize_cache() {
-        Ok(cache) => {
-            println!("\n╔════════════════════════════════════════════════════════════╗");
-            println!("║  ✅ Cache Initialized Successfully!                        ║");
-            println!("╚════════════════════════════════════════════════════════════╝");
-            cache
-        }
-        Err(e) => {
-            eprintln!("\n╔════════════════════════════════════════════════════════════╗");
-            eprintln!("║  ❌ INITIALIZATION FAILED                                  ║");
-            eprintln!("╚════════════════════════════════════════════════════════════╝");
-            eprintln!("\n❌ Failed to initialize cache: {}", e);
-            eprintln!("Server cannot start without initial data.");
-            eprintln!("\n💡 Troubleshooting:");
-            eprintln!("   1. Check your internet connection");
-            eprintln!("   2. Verify API endpoints are accessible");
-            eprintln!("   3. Check firewall settings");
-            eprintln!("   4. Review error message above for specific issues\n");
-            std::process::exit(1);
-        }
-    };
+    // Load the configuration up front so problems show before the long cache initialization
+    config::get();
 
-    actix_web::rt::System::new().block_on(run_server(cache))
+    actix_web::rt::System::new().block_on(run_server())
 }
\ No newline at end of file
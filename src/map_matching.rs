@@ -0,0 +1,171 @@
+// Map matching of shapeless routes (optional, see `map_matching` in the config)
+// Many New-Aquitaine routes come without shapes.txt entries and draw nothing on the map. For
+// each direction of such a route, the longest trip's stop sequence becomes a synthetic shape:
+// routed along roads by an OSRM server when one is configured, straight segments otherwise.
+// Runs once per GTFS download; the result is saved with the GTFS cache.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::{self, MapMatchingConfig};
+use crate::tbm_api_models::{GTFSCache, NVTError, Result, ShapePoint};
+
+const OSRM_MAX_WAYPOINTS: usize = 100; // Stay under the waypoint limits of public instances
+const OSRM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Add (or, when map matching is disabled, remove) synthetic shapes on a freshly loaded cache
+pub fn apply(mut cache: GTFSCache, cache_dir: &Path) -> GTFSCache {
+    let Some(config) = config::get().map_matching.as_ref() else {
+        if !cache.synthetic_shape_ids.is_empty() {
+            remove_synthetic(&mut cache);
+        }
+        return cache;
+    };
+    if cache.map_matched || cache.source == "TBM" {
+        return cache;
+    }
+
+    println!("🧭 Synthesizing shapes for shapeless {} routes...", cache.source);
+    let count = synthesize(&mut cache, config);
+    println!("   ✓ Synthesized {} shapes", count);
+
+    cache.map_matched = true;
+    if let Err(e) = cache.save(cache_dir) {
+        eprintln!("⚠️  Warning: Could not save {} cache with synthetic shapes: {}", cache.source, e);
+    }
+    cache
+}
+
+fn remove_synthetic(cache: &mut GTFSCache) {
+    for shape_id in &cache.synthetic_shape_ids {
+        cache.shapes.remove(shape_id);
+    }
+    for shape_ids in cache.route_to_shapes.values_mut() {
+        shape_ids.retain(|id| !cache.synthetic_shape_ids.contains(id));
+    }
+    cache.route_to_shapes.retain(|_, shape_ids| !shape_ids.is_empty());
+    cache.synthetic_shape_ids.clear();
+    cache.map_matched = false;
+}
+
+struct StopPattern {
+    route_id: String,
+    direction: u32,
+    points: Vec<(f64, f64)>, // (lat, lon) of the stops in order
+}
+
+/// Longest trip of each (route, direction) among shapeless routes
+fn stop_patterns(cache: &GTFSCache) -> Vec<StopPattern> {
+    let shapeless: HashSet<&str> = cache.routes.keys()
+        .filter(|route_id| cache.route_to_shapes.get(*route_id).is_none_or(|shapes| shapes.is_empty()))
+        .map(String::as_str)
+        .collect();
+    if shapeless.is_empty() {
+        return Vec::new();
+    }
+
+    let mut trip_stops: HashMap<&str, Vec<(u32, &str)>> = HashMap::new();
+    for stop_time in cache.stop_times.values().flatten() {
+        trip_stops.entry(stop_time.trip_id.as_str())
+            .or_default()
+            .push((stop_time.stop_sequence, stop_time.stop_id.as_str()));
+    }
+
+    let mut longest: HashMap<(&str, u32), &Vec<(u32, &str)>> = HashMap::new();
+    for (trip_id, trip) in &cache.trips {
+        if !shapeless.contains(trip.route_id.as_str()) {
+            continue;
+        }
+        let Some(stops) = trip_stops.get(trip_id.as_str()) else { continue };
+        let entry = longest.entry((trip.route_id.as_str(), trip.direction_id.unwrap_or(0))).or_insert(stops);
+        if stops.len() > entry.len() {
+            *entry = stops;
+        }
+    }
+
+    let coordinates: HashMap<&str, (f64, f64)> = cache.stops.iter()
+        .map(|(id, _, lat, lon)| (id.as_str(), (*lat, *lon)))
+        .collect();
+
+    longest.into_iter()
+        .filter_map(|((route_id, direction), stops)| {
+            let mut ordered = stops.clone();
+            ordered.sort_by_key(|(sequence, _)| *sequence);
+            let points: Vec<(f64, f64)> = ordered.iter()
+                .filter_map(|(_, stop_id)| coordinates.get(stop_id).copied())
+                .collect();
+            (points.len() >= 2).then(|| StopPattern { route_id: route_id.to_string(), direction, points })
+        })
+        .collect()
+}
+
+fn synthesize(cache: &mut GTFSCache, config: &MapMatchingConfig) -> usize {
+    let patterns = stop_patterns(cache);
+
+    // Trains don't follow roads: only the regional bus feed goes through OSRM
+    let osrm = config.osrm_url.as_deref().filter(|_| cache.source == "NewAquitaine");
+    let profile = config.osrm_profile.as_deref().unwrap_or("driving");
+    let client = reqwest::blocking::Client::builder().timeout(OSRM_TIMEOUT).build().ok();
+
+    for StopPattern { route_id, direction, points } in patterns {
+        let routed = match (osrm, &client) {
+            (Some(url), Some(client)) => osrm_route(client, url, profile, &points)
+                .map_err(|e| eprintln!("⚠️  OSRM failed for {} ({}), using straight segments", route_id, e))
+                .ok(),
+            _ => None,
+        };
+        let points = routed.unwrap_or(points);
+
+        let shape_id = format!("synthetic:{}:{}", route_id, direction);
+        let shape = points.iter()
+            .enumerate()
+            .map(|(i, (latitude, longitude))| ShapePoint { latitude: *latitude, longitude: *longitude, sequence: i as u32 })
+            .collect();
+
+        cache.shapes.insert(shape_id.clone(), shape);
+        cache.route_to_shapes.entry(route_id).or_default().push(shape_id.clone());
+        cache.synthetic_shape_ids.insert(shape_id);
+    }
+
+    cache.synthetic_shape_ids.len()
+}
+
+/// Road geometry through the given (lat, lon) stops, in chunks the server accepts
+fn osrm_route(client: &reqwest::blocking::Client, base_url: &str, profile: &str, stops: &[(f64, f64)]) -> Result<Vec<(f64, f64)>> {
+    let mut geometry: Vec<(f64, f64)> = Vec::new();
+
+    // Consecutive chunks share their boundary stop so the legs join up
+    let mut start = 0;
+    loop {
+        let end = (start + OSRM_MAX_WAYPOINTS).min(stops.len());
+        let chunk = &stops[start..end];
+        let waypoints: Vec<String> = chunk.iter().map(|(lat, lon)| format!("{:.6},{:.6}", lon, lat)).collect();
+        let url = format!(
+            "{}/route/v1/{}/{}?overview=full&geometries=geojson",
+            base_url.trim_end_matches('/'),
+            profile,
+            waypoints.join(";")
+        );
+
+        let json: serde_json::Value = client.get(&url)
+            .send()
+            .and_then(|r| r.json())
+            .map_err(|e| NVTError::NetworkError(format!("OSRM request failed: {}", e)))?;
+
+        if json["code"] != "Ok" {
+            return Err(NVTError::NetworkError(format!("OSRM returned {}", json["code"])));
+        }
+
+        let coordinates = json["routes"][0]["geometry"]["coordinates"].as_array()
+            .ok_or_else(|| NVTError::ParseError("OSRM response without geometry".to_string()))?;
+        geometry.extend(coordinates.iter().filter_map(|c| Some((c[1].as_f64()?, c[0].as_f64()?))));
+
+        if end == stops.len() {
+            break;
+        }
+        start = end - 1;
+    }
+
+    Ok(geometry)
+}
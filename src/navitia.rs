@@ -0,0 +1,225 @@
+// Navitia fallback (SNCF / Hove API)
+// Optional, enabled by the `navitia` section of the configuration. Journeys the local timetables
+// don't find (GET /api/tbm/journey) are asked to Navitia, and its disruptions are merged with
+// the GTFS-RT alerts at each real-time refresh, unless a feed already published the same text.
+// Every response is kept for cache_ttl_secs, keyed by URL, so that refreshes and repeated
+// queries stay within the API key's quota.
+
+use chrono::TimeZone;
+use reqwest::blocking;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::config::NavitiaConfig;
+use crate::tbm_api_models::{AlertInfo, NVTError, Result};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+const MAX_DISRUPTIONS: usize = 200;
+pub const ID_PREFIX: &str = "navitia:"; // Before the ids of the alerts made from its disruptions
+
+// ============================================================================
+// Client
+// ============================================================================
+
+/// What a journeys request asks for besides its ends and time
+#[derive(Debug, Clone, Copy)]
+pub struct JourneyOptions {
+    pub arrival: bool, // The time is the arrival, not the departure
+    pub count: usize,
+    pub wheelchair: bool,
+    pub bikes: bool, // Bike share first and last
+}
+
+#[derive(Debug)]
+pub struct Navitia {
+    config: NavitiaConfig,
+    responses: Mutex<HashMap<String, (u64, Value)>>, // key: URL, value: (fetched at, body)
+}
+
+impl Navitia {
+    pub fn new(config: NavitiaConfig) -> Self {
+        Navitia { config, responses: Mutex::new(HashMap::new()) }
+    }
+
+    /// Body of `path` under the coverage, from the response cache while it is fresh
+    fn get(&self, path: &str, query: &[(&str, String)], now: u64) -> Result<Value> {
+        let base = format!("{}/coverage/{}/{}", self.config.base_url.trim_end_matches('/'), self.config.coverage, path);
+        let url = reqwest::Url::parse_with_params(&base, query)
+            .map_err(|e| NVTError::ParseError(format!("Invalid Navitia URL: {}", e)))?
+            .to_string();
+
+        let ttl = self.config.cache_ttl_secs;
+        if let Ok(responses) = self.responses.lock()
+            && let Some((fetched_at, body)) = responses.get(&url)
+            && now.saturating_sub(*fetched_at) < ttl {
+            return Ok(body.clone());
+        }
+
+        let client = blocking::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to create HTTP client: {}", e)))?;
+        let response = client.get(&url)
+            .header(reqwest::header::AUTHORIZATION, &self.config.api_key)
+            .send()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to query Navitia: {}", e)))?;
+
+        if !response.status().is_success() {
+            return Err(NVTError::NetworkError(format!("Navitia returned error: {}", response.status())));
+        }
+
+        let body: Value = response.json()
+            .map_err(|e| NVTError::ParseError(format!("Invalid Navitia response: {}", e)))?;
+        if let Ok(mut responses) = self.responses.lock() {
+            responses.retain(|_, (fetched_at, _)| now.saturating_sub(*fetched_at) < ttl);
+            responses.insert(url, (now, body.clone()));
+        }
+        Ok(body)
+    }
+
+    /// Journeys response between two (latitude, longitude) positions at `time`
+    pub fn journeys(&self, from: (f64, f64), to: (f64, f64), time: i64, options: JourneyOptions, now: u64) -> Result<Value> {
+        let place = |(latitude, longitude): (f64, f64)| format!("{:.6};{:.6}", longitude, latitude);
+        let datetime = chrono_tz::Europe::Paris.timestamp_opt(time, 0).single()
+            .ok_or_else(|| NVTError::ParseError(format!("Invalid journey time {}", time)))?;
+        let mut query = vec![
+            ("from", place(from)),
+            ("to", place(to)),
+            ("datetime", datetime.format("%Y%m%dT%H%M%S").to_string()),
+            ("datetime_represents", if options.arrival { "arrival" } else { "departure" }.to_string()),
+            ("count", options.count.to_string()),
+            ("data_freshness", "realtime".to_string()),
+        ];
+        if options.wheelchair {
+            query.push(("wheelchair", "true".to_string()));
+        }
+        if options.bikes {
+            for mode in ["first_section_mode[]", "last_section_mode[]"] {
+                query.push((mode, "walking".to_string()));
+                query.push((mode, "bss".to_string()));
+            }
+        }
+        self.get("journeys", &query, now)
+    }
+
+    /// Current and coming disruptions of the coverage as alerts
+    pub fn disruptions(&self, now: u64) -> Result<Vec<AlertInfo>> {
+        let json = self.get("disruptions", &[("count", MAX_DISRUPTIONS.to_string())], now)?;
+        Ok(parse_disruptions(&json))
+    }
+}
+
+// ============================================================================
+// Parsing
+// ============================================================================
+
+/// Unix time of a Navitia date time ("20261014T083000", Europe/Paris)
+pub fn parse_time(value: &Value) -> Option<i64> {
+    let local = chrono::NaiveDateTime::parse_from_str(value.as_str()?, "%Y%m%dT%H%M%S").ok()?;
+    chrono_tz::Europe::Paris.from_local_datetime(&local).earliest().map(|datetime| datetime.timestamp())
+}
+
+/// GTFS-RT severity level of a Navitia effect
+fn severity(effect: &str) -> u32 {
+    match effect {
+        "NO_SERVICE" => 4, // SEVERE
+        "REDUCED_SERVICE" | "SIGNIFICANT_DELAYS" | "DETOUR" | "STOP_MOVED" => 3, // WARNING
+        _ => 2, // INFO
+    }
+}
+
+/// Alerts of a disruptions response, past disruptions left out. The header is the message of a
+/// "title" channel, else the first one; the description the longest message.
+pub fn parse_disruptions(json: &Value) -> Vec<AlertInfo> {
+    let Some(disruptions) = json["disruptions"].as_array() else {
+        return Vec::new();
+    };
+    disruptions.iter()
+        .filter(|disruption| disruption["status"].as_str() != Some("past"))
+        .filter_map(|disruption| {
+            let id = disruption["id"].as_str()?;
+            let messages = disruption["messages"].as_array().map(Vec::as_slice).unwrap_or_default();
+            let texts: Vec<&str> = messages.iter().filter_map(|message| message["text"].as_str()).collect();
+            let text = messages.iter()
+                .find(|message| message["channel"]["types"].as_array()
+                    .is_some_and(|types| types.iter().any(|kind| kind == "title")))
+                .and_then(|message| message["text"].as_str())
+                .or_else(|| texts.first().copied())
+                .or_else(|| disruption["cause"].as_str())?;
+            let description = texts.iter().max_by_key(|text| text.len()).copied().unwrap_or(text);
+
+            let mut route_ids = Vec::new();
+            let mut stop_ids = Vec::new();
+            for object in disruption["impacted_objects"].as_array().into_iter().flatten() {
+                let object = &object["pt_object"];
+                let Some(object_id) = object["id"].as_str() else { continue };
+                match object["embedded_type"].as_str() {
+                    Some("line" | "route") => route_ids.push(object_id.to_string()),
+                    Some("stop_area" | "stop_point") => stop_ids.push(object_id.to_string()),
+                    _ => {}
+                }
+            }
+
+            let active_periods: Vec<(Option<i64>, Option<i64>)> = disruption["application_periods"].as_array()
+                .into_iter()
+                .flatten()
+                .map(|period| (parse_time(&period["begin"]), parse_time(&period["end"])))
+                .collect();
+            let (active_period_start, active_period_end) = active_periods.first().copied().unwrap_or((None, None));
+
+            Some(AlertInfo {
+                id: format!("{}{}", ID_PREFIX, id),
+                text: text.to_string(),
+                description: description.to_string(),
+                url: None,
+                route_ids,
+                stop_ids,
+                active_period_start,
+                active_period_end,
+                severity: severity(disruption["severity"]["effect"].as_str().unwrap_or_default()),
+                active_periods,
+                affected_stops_count: 0,
+                affected_stop_ids: None,
+                derived: false,
+                translations: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+/// UIC code of an SNCF stop id, the 8 digits starting with 87 in "stop_area:SNCF:87581009" or
+/// "StopPoint:OCETrain TER-87581009"
+pub fn uic_code(stop_id: &str) -> Option<&str> {
+    let bytes = stop_id.as_bytes();
+    (0..bytes.len().saturating_sub(7))
+        .find(|&start| {
+            bytes[start..start + 2] == *b"87"
+                && bytes[start..start + 8].iter().all(u8::is_ascii_digit)
+                && (start == 0 || !bytes[start - 1].is_ascii_digit())
+                && bytes.get(start + 8).is_none_or(|next| !next.is_ascii_digit())
+        })
+        .map(|start| &stop_id[start..start + 8])
+}
+
+/// Navitia stop ids of `alerts` replaced by the `local` stops of the same UIC code, where any
+pub fn localize_stops(alerts: &mut [AlertInfo], local: &[&str]) {
+    let mut by_uic: HashMap<&str, Vec<&str>> = HashMap::new();
+    for &stop_id in local {
+        if let Some(uic) = uic_code(stop_id) {
+            by_uic.entry(uic).or_default().push(stop_id);
+        }
+    }
+    for alert in alerts {
+        let mut stop_ids = Vec::new();
+        for stop_id in std::mem::take(&mut alert.stop_ids) {
+            match uic_code(&stop_id).and_then(|uic| by_uic.get(uic)) {
+                Some(local) => stop_ids.extend(local.iter().map(|id| id.to_string())),
+                None => stop_ids.push(stop_id),
+            }
+        }
+        stop_ids.dedup();
+        alert.stop_ids = stop_ids;
+    }
+}
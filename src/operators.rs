@@ -0,0 +1,51 @@
+// Operator names
+// The New-Aquitaine feed names the same operator differently from one export to the next
+// ("Calibus (Libourne)", "CALIBUS"). Lines carry the canonical name, given by the
+// `operator_aliases` table of the configuration, and filters and routes take any name, alias
+// or slug of an operator.
+
+use std::collections::HashMap;
+
+use crate::config;
+
+/// URL-friendly operator identifier: "Calibus (Libourne)" -> "calibus-libourne"
+pub fn operator_slug(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars() {
+        let c = match c {
+            'à' | 'á' | 'â' | 'ä' | 'À' | 'Â' | 'Ä' => 'a',
+            'é' | 'è' | 'ê' | 'ë' | 'É' | 'È' | 'Ê' | 'Ë' => 'e',
+            'î' | 'ï' | 'Î' | 'Ï' => 'i',
+            'ô' | 'ö' | 'Ô' | 'Ö' => 'o',
+            'ù' | 'û' | 'ü' | 'Ù' | 'Û' | 'Ü' => 'u',
+            'ç' | 'Ç' => 'c',
+            other => other,
+        };
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.ends_with('-') && !slug.is_empty() {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Canonical name of an operator: the target of the alias matching its name or slug, or the
+/// name itself with whitespace tidied
+pub fn canonical_name_with(aliases: &HashMap<String, String>, name: &str) -> String {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    let slug = operator_slug(&name);
+    aliases.get(&name)
+        .or_else(|| aliases.iter().find(|(alias, _)| operator_slug(alias) == slug).map(|(_, canonical)| canonical))
+        .cloned()
+        .unwrap_or(name)
+}
+
+pub fn canonical_name(name: &str) -> String {
+    canonical_name_with(&config::get().operator_aliases, name)
+}
+
+/// Whether `query`, a name, alias or slug as given in a filter or URL, designates `operator`
+pub fn matches(operator: &str, query: &str) -> bool {
+    operator.eq_ignore_ascii_case(query) || operator_slug(operator) == operator_slug(&canonical_name(query))
+}
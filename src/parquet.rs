@@ -0,0 +1,286 @@
+// Parquet writer
+// Just enough of the format for the history export (history.rs): flat columns in a single row
+// group, one uncompressed PLAIN-encoded data page per column, optional columns carrying their
+// definition levels as RLE runs. The footer is written in Thrift's compact protocol by hand,
+// Arrow and Thrift not being dependencies. pandas, DuckDB and Polars read the result as is.
+
+const MAGIC: &[u8; 4] = b"PAR1";
+
+// Thrift compact protocol field types
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+// Parquet enums, as numbered in parquet.thrift
+const TYPE_INT64: i32 = 2;
+const TYPE_DOUBLE: i32 = 5;
+const TYPE_BYTE_ARRAY: i32 = 6;
+const REQUIRED: i32 = 0;
+const OPTIONAL: i32 = 1;
+const CONVERTED_UTF8: i32 = 0;
+const ENCODING_PLAIN: i32 = 0;
+const ENCODING_RLE: i32 = 3;
+const CODEC_UNCOMPRESSED: i32 = 0;
+const PAGE_DATA: i32 = 0;
+
+// ============================================================================
+// Columns
+// ============================================================================
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int64(i64),
+    Double(f64),
+    Utf8(String),
+}
+
+/// One column of the file; `None` values are nulls, only found in optional columns
+#[derive(Debug, Clone)]
+pub struct Column {
+    name: String,
+    physical_type: i32,
+    optional: bool,
+    values: Vec<Option<Value>>,
+}
+
+impl Column {
+    fn new(name: &str, physical_type: i32, optional: bool, values: Vec<Option<Value>>) -> Self {
+        Column { name: name.to_string(), physical_type, optional, values }
+    }
+
+    pub fn int64(name: &str, values: impl IntoIterator<Item = i64>) -> Self {
+        Self::new(name, TYPE_INT64, false, values.into_iter().map(|v| Some(Value::Int64(v))).collect())
+    }
+
+    pub fn optional_int64(name: &str, values: impl IntoIterator<Item = Option<i64>>) -> Self {
+        Self::new(name, TYPE_INT64, true, values.into_iter().map(|v| v.map(Value::Int64)).collect())
+    }
+
+    pub fn double(name: &str, values: impl IntoIterator<Item = f64>) -> Self {
+        Self::new(name, TYPE_DOUBLE, false, values.into_iter().map(|v| Some(Value::Double(v))).collect())
+    }
+
+    pub fn utf8(name: &str, values: impl IntoIterator<Item = String>) -> Self {
+        Self::new(name, TYPE_BYTE_ARRAY, false, values.into_iter().map(|v| Some(Value::Utf8(v))).collect())
+    }
+
+    pub fn optional_utf8(name: &str, values: impl IntoIterator<Item = Option<String>>) -> Self {
+        Self::new(name, TYPE_BYTE_ARRAY, true, values.into_iter().map(|v| v.map(Value::Utf8)).collect())
+    }
+
+    /// The page body: definition levels (optional columns only), then the non-null values
+    fn encode_page(&self) -> Vec<u8> {
+        let mut page = Vec::new();
+        if self.optional {
+            let levels = definition_levels(&self.values);
+            page.extend_from_slice(&(levels.len() as u32).to_le_bytes());
+            page.extend_from_slice(&levels);
+        }
+        for value in self.values.iter().flatten() {
+            match value {
+                Value::Int64(v) => page.extend_from_slice(&v.to_le_bytes()),
+                Value::Double(v) => page.extend_from_slice(&v.to_le_bytes()),
+                Value::Utf8(v) => {
+                    page.extend_from_slice(&(v.len() as u32).to_le_bytes());
+                    page.extend_from_slice(v.as_bytes());
+                }
+            }
+        }
+        page
+    }
+}
+
+/// Definition levels (1 present, 0 null) in the RLE/bit-packing hybrid, as RLE runs only
+fn definition_levels(values: &[Option<Value>]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut i = 0;
+    while i < values.len() {
+        let present = values[i].is_some();
+        let run = values[i..].iter().take_while(|v| v.is_some() == present).count();
+        write_varint(&mut encoded, (run as u64) << 1);
+        encoded.push(present as u8); // bit width 1 fits in one byte
+        i += run;
+    }
+    encoded
+}
+
+// ============================================================================
+// Thrift Compact Protocol
+// ============================================================================
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Encoder for the Thrift structs of the format, tracking the last field id of each open struct
+struct Thrift {
+    out: Vec<u8>,
+    last_field: Vec<i16>,
+}
+
+impl Thrift {
+    fn new() -> Self {
+        Thrift { out: Vec::new(), last_field: vec![0] }
+    }
+
+    fn field(&mut self, id: i16, kind: u8) {
+        let last = self.last_field.last_mut().expect("open struct");
+        let delta = id - *last;
+        if (1..=15).contains(&delta) {
+            self.out.push(((delta as u8) << 4) | kind);
+        } else {
+            self.out.push(kind);
+            write_varint(&mut self.out, ((id << 1) ^ (id >> 15)) as u16 as u64);
+        }
+        *last = id;
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, I32);
+        write_varint(&mut self.out, ((value << 1) ^ (value >> 31)) as u32 as u64);
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, I64);
+        write_varint(&mut self.out, ((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, BINARY);
+        self.bytes(value);
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        write_varint(&mut self.out, value.len() as u64);
+        self.out.extend_from_slice(value);
+    }
+
+    fn list(&mut self, id: i16, element: u8, size: usize) {
+        self.field(id, LIST);
+        if size < 15 {
+            self.out.push(((size as u8) << 4) | element);
+        } else {
+            self.out.push(0xF0 | element);
+            write_varint(&mut self.out, size as u64);
+        }
+    }
+
+    fn list_i32(&mut self, value: i32) {
+        write_varint(&mut self.out, ((value << 1) ^ (value >> 31)) as u32 as u64);
+    }
+
+    /// A struct field; list elements open theirs with `element_begin`
+    fn struct_begin(&mut self, id: i16) {
+        self.field(id, STRUCT);
+        self.element_begin();
+    }
+
+    fn element_begin(&mut self) {
+        self.last_field.push(0);
+    }
+
+    fn struct_end(&mut self) {
+        self.out.push(0);
+        self.last_field.pop();
+    }
+}
+
+// ============================================================================
+// File
+// ============================================================================
+
+struct ChunkLayout {
+    offset: usize,
+    size: usize,
+}
+
+/// A Parquet file of the columns, which must all hold the same number of rows
+pub fn write(columns: &[Column]) -> Vec<u8> {
+    let num_rows = columns.first().map(|column| column.values.len()).unwrap_or(0);
+    debug_assert!(columns.iter().all(|column| column.values.len() == num_rows), "ragged columns");
+
+    let mut file = MAGIC.to_vec();
+    let mut chunks = Vec::new();
+    if num_rows > 0 {
+        for column in columns {
+            let page = column.encode_page();
+            let mut header = Thrift::new();
+            header.i32(1, PAGE_DATA);
+            header.i32(2, page.len() as i32);
+            header.i32(3, page.len() as i32);
+            header.struct_begin(5);
+            header.i32(1, num_rows as i32);
+            header.i32(2, ENCODING_PLAIN);
+            header.i32(3, ENCODING_RLE);
+            header.i32(4, ENCODING_RLE);
+            header.struct_end();
+            header.struct_end();
+
+            chunks.push(ChunkLayout { offset: file.len(), size: header.out.len() + page.len() });
+            file.extend_from_slice(&header.out);
+            file.extend_from_slice(&page);
+        }
+    }
+
+    let mut footer = Thrift::new();
+    footer.i32(1, 1);
+    footer.list(2, STRUCT, columns.len() + 1);
+    footer.element_begin();
+    footer.binary(4, b"schema");
+    footer.i32(5, columns.len() as i32);
+    footer.struct_end();
+    for column in columns {
+        footer.element_begin();
+        footer.i32(1, column.physical_type);
+        footer.i32(3, if column.optional { OPTIONAL } else { REQUIRED });
+        footer.binary(4, column.name.as_bytes());
+        if column.physical_type == TYPE_BYTE_ARRAY {
+            footer.i32(6, CONVERTED_UTF8);
+            footer.struct_begin(10); // LogicalType, the STRING member
+            footer.struct_begin(1);
+            footer.struct_end();
+            footer.struct_end();
+        }
+        footer.struct_end();
+    }
+    footer.i64(3, num_rows as i64);
+    footer.list(4, STRUCT, chunks.len().min(1));
+    if !chunks.is_empty() {
+        footer.element_begin();
+        footer.list(1, STRUCT, chunks.len());
+        for (column, chunk) in columns.iter().zip(&chunks) {
+            footer.element_begin();
+            footer.i64(2, chunk.offset as i64);
+            footer.struct_begin(3);
+            footer.i32(1, column.physical_type);
+            footer.list(2, I32, 2);
+            footer.list_i32(ENCODING_PLAIN);
+            footer.list_i32(ENCODING_RLE);
+            footer.list(3, BINARY, 1);
+            footer.bytes(column.name.as_bytes());
+            footer.i32(4, CODEC_UNCOMPRESSED);
+            footer.i64(5, num_rows as i64);
+            footer.i64(6, chunk.size as i64);
+            footer.i64(7, chunk.size as i64);
+            footer.i64(9, chunk.offset as i64);
+            footer.struct_end();
+            footer.struct_end();
+        }
+        footer.i64(2, chunks.iter().map(|chunk| chunk.size as i64).sum());
+        footer.i64(3, num_rows as i64);
+        footer.struct_end();
+    }
+    footer.binary(6, b"NVTWebEdition");
+    footer.struct_end();
+
+    file.extend_from_slice(&footer.out);
+    file.extend_from_slice(&(footer.out.len() as u32).to_le_bytes());
+    file.extend_from_slice(MAGIC);
+    file
+}
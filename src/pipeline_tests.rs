@@ -0,0 +1,3229 @@
+// End-to-end tests of the parse/merge/serve pipeline
+// A local actix server stands in for the SIRI-Lite, GTFS-RT, GTFS and communes upstreams and
+// serves the small feeds under tests/fixtures (GTFS directories are zipped on the fly, GTFS-RT
+// feeds are built below). The merged network data and API responses are compared with the
+// golden files under tests/golden; run with UPDATE_GOLDEN=1 to rewrite them after an intended
+// change, and review the diff.
+
+use actix_web::http::StatusCode;
+use actix_web::test::{call_service, init_service, read_body_json, try_call_service, TestRequest};
+use actix_web::{middleware, web, App, HttpRequest, HttpResponse, HttpServer};
+use gtfs_rt::trip_descriptor::ScheduleRelationship;
+use gtfs_rt::vehicle_position::OccupancyStatus;
+use gtfs_rt::{
+    translated_string::Translation, trip_update::{StopTimeEvent, StopTimeUpdate}, Alert, EntitySelector, FeedEntity,
+    FeedHeader, FeedMessage, Position, TimeRange, TranslatedString, TripDescriptor, TripUpdate, VehicleDescriptor,
+    VehiclePosition,
+};
+use prost::Message;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Cursor, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::access_log::{AccessLog, PendingEntry};
+use crate::alert_stream::{self, AlertBroadcaster, AlertChange};
+use crate::api_keys::{self, ApiKeyGuard, Refusal};
+use crate::client_gen::{self, Lang};
+use crate::colors;
+use crate::config::{
+    AccessLogConfig, AccessLogFormat, ApiKeyConfig, ApiKeysConfig, CacheAgesConfig, CorsConfig, GtfsFeedConfig, HealthConfig, IpMode, NavitiaConfig, PrivacyConfig,
+    RequestTimeoutsConfig, SmtpConfig, SmtpSecurity,
+};
+use crate::digest;
+use crate::elevation::ElevationStore;
+use crate::grafana;
+use crate::graphql;
+use crate::history::{ArchivedDelay, HistoryStore};
+use crate::http_cache;
+use crate::journey_share::JourneyShareStore;
+use crate::navitia::Navitia;
+use crate::interpolation;
+use crate::polyline;
+use crate::preflight::{self, PreflightOptions, Status};
+use crate::privacy::IpAnonymizer;
+use crate::refresh_jobs::{Admission, JobStatus, RefreshJob, RefreshJobs};
+use crate::snapshot::{DynamicSnapshot, StaticSnapshot};
+use crate::sync::{self, SyncPayload};
+use crate::tbm_api_models::{CachedNetworkData, FeedKind, GTFSCache, NVTModels, ShapePoint, TransportMode, Upstreams};
+use crate::time_format;
+use crate::vector_tiles::{self, TileCache, TileId};
+use crate::wfs;
+use crate::simulation;
+use crate::{api_key_gate, api_routes, cache_headers, cors, get_shared_journey, request_deadline, sandbox_routes, AppState, ENDPOINTS};
+
+const FAR_FUTURE: u64 = 4102444800; // 2100-01-01, so fixture real-time data never ages out
+
+const VEHICLES_PATH: &str = "/tbm/gtfsfeed/vehicles/bordeaux";
+const ALERTS_PATH: &str = "/tbm/gtfsfeed/alerts/bordeaux";
+const TRIP_UPDATES_PATH: &str = "/tbm/gtfsfeed/realtime/bordeaux";
+
+fn fixtures_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures")
+}
+
+fn fixture(name: &str) -> Vec<u8> {
+    fs::read(fixtures_dir().join(name)).unwrap_or_else(|e| panic!("fixture {}: {}", name, e))
+}
+
+// ============================================================================
+// Fixture Feeds
+// ============================================================================
+
+fn gtfs_zip(feed: &str) -> Vec<u8> {
+    zip_files(&gtfs_files(feed))
+}
+
+/// (name, contents) of the files of a GTFS fixture directory
+fn gtfs_files(feed: &str) -> Vec<(String, Vec<u8>)> {
+    let mut files: Vec<PathBuf> = fs::read_dir(fixtures_dir().join(feed).join("gtfs"))
+        .expect("GTFS fixture directory")
+        .map(|entry| entry.expect("GTFS fixture file").path())
+        .collect();
+    files.sort();
+
+    files.iter()
+        .map(|path| (path.file_name().expect("file name").to_string_lossy().to_string(), fs::read(path).expect("GTFS fixture file")))
+        .collect()
+}
+
+fn zip_files<T: AsRef<[u8]>>(files: &[(impl AsRef<str>, T)]) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in files {
+        zip.start_file(name.as_ref(), zip::write::FileOptions::default()).expect("zip entry");
+        zip.write_all(contents.as_ref()).expect("zip write");
+    }
+    zip.finish().expect("zip archive").into_inner()
+}
+
+fn feed(entity: Vec<FeedEntity>) -> Vec<u8> {
+    FeedMessage {
+        header: FeedHeader {
+            gtfs_realtime_version: "2.0".to_string(),
+            timestamp: Some(FAR_FUTURE),
+            ..Default::default()
+        },
+        entity,
+    }.encode_to_vec()
+}
+
+fn text(value: &str) -> Option<TranslatedString> {
+    Some(TranslatedString {
+        translation: vec![Translation { text: value.to_string(), language: Some("fr".to_string()) }],
+    })
+}
+
+fn vehicle(id: &str, trip_id: &str, route_id: &str, label: &str, position: (f32, f32), stop: (&str, u32)) -> FeedEntity {
+    FeedEntity {
+        id: id.to_string(),
+        vehicle: Some(VehiclePosition {
+            trip: Some(TripDescriptor {
+                trip_id: Some(trip_id.to_string()),
+                route_id: Some(route_id.to_string()),
+                direction_id: Some(0),
+                ..Default::default()
+            }),
+            vehicle: Some(VehicleDescriptor {
+                id: Some(id.to_string()),
+                label: Some(label.to_string()),
+                ..Default::default()
+            }),
+            position: Some(Position { latitude: position.0, longitude: position.1, bearing: Some(45.0), ..Default::default() }),
+            stop_id: Some(stop.0.to_string()),
+            current_stop_sequence: Some(stop.1),
+            occupancy_status: Some(OccupancyStatus::FewSeatsAvailable as i32),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn alert(id: &str, header: &str, route_ids: &[&str], stop_ids: &[&str]) -> FeedEntity {
+    let routes = route_ids.iter().map(|route_id| EntitySelector { route_id: Some(route_id.to_string()), ..Default::default() });
+    let stops = stop_ids.iter().map(|stop_id| EntitySelector { stop_id: Some(stop_id.to_string()), ..Default::default() });
+
+    FeedEntity {
+        id: id.to_string(),
+        alert: Some(Alert {
+            active_period: vec![TimeRange { start: Some(1_700_000_000), end: Some(FAR_FUTURE) }],
+            informed_entity: routes.chain(stops).collect(),
+            header_text: text(header),
+            description_text: text(&format!("{} (fixture)", header)),
+            severity_level: Some(3),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn trip_update(trip_id: &str, route_id: &str, stop_id: &str, delay: i32) -> FeedEntity {
+    FeedEntity {
+        id: trip_id.to_string(),
+        trip_update: Some(TripUpdate {
+            trip: TripDescriptor {
+                trip_id: Some(trip_id.to_string()),
+                route_id: Some(route_id.to_string()),
+                direction_id: Some(0),
+                ..Default::default()
+            },
+            stop_time_update: vec![StopTimeUpdate {
+                stop_id: Some(stop_id.to_string()),
+                arrival: Some(StopTimeEvent { delay: Some(delay), time: Some(FAR_FUTURE as i64), ..Default::default() }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+fn default_responses() -> HashMap<String, (u16, Vec<u8>)> {
+    let ok = |body: Vec<u8>| (200, body);
+    HashMap::from([
+        ("/tbm/siri/2.0/bordeaux/stoppoints-discovery.json".to_string(), ok(fixture("tbm/stoppoints-discovery.json"))),
+        ("/tbm/siri/2.0/bordeaux/lines-discovery.json".to_string(), ok(fixture("tbm/lines-discovery.json"))),
+        (VEHICLES_PATH.to_string(), ok(feed(vec![
+            vehicle("V-A-1", "A-1", "59", "Le Haillan Rostand", (44.8430, -0.5741), ("3672", 2)),
+            vehicle("V-1-1", "1-1", "1", "Aéroport", (44.8447, -0.5735), ("3671", 1)),
+        ]))),
+        (ALERTS_PATH.to_string(), ok(feed(vec![
+            alert("TBM-1", "Travaux place de la Comédie", &["59"], &["3672"]),
+        ]))),
+        (TRIP_UPDATES_PATH.to_string(), ok(feed(vec![
+            trip_update("A-1", "59", "3673", 120),
+        ]))),
+        ("/tbm/gtfs.zip".to_string(), ok(gtfs_zip("tbm"))),
+        ("/naq/gtfs.zip".to_string(), ok(gtfs_zip("naq"))),
+        ("/sncf/gtfs.zip".to_string(), ok(gtfs_zip("sncf"))),
+        ("/sncf/trip-updates".to_string(), ok(feed(vec![
+            trip_update("OCESN864201", "OCESN:Line:TER-43", "StopPoint:OCETrain TER-87584052", 300),
+        ]))),
+        ("/sncf/alerts".to_string(), ok(feed(vec![
+            alert("SNCF-1", "Trafic perturbé Bordeaux - Libourne", &["OCESN:Line:TER-43"], &[]),
+        ]))),
+        ("/communes".to_string(), ok(fixture("communes.geojson"))),
+        ("/gbfs/gbfs.json".to_string(), ok(fixture("gbfs/gbfs.json"))),
+        ("/gbfs/station_information.json".to_string(), ok(fixture("gbfs/station_information.json"))),
+        ("/gbfs/station_status.json".to_string(), ok(fixture("gbfs/station_status.json"))),
+    ])
+}
+
+// ============================================================================
+// Mock Upstreams
+// ============================================================================
+
+type Responses = Mutex<HashMap<String, (u16, Vec<u8>)>>; // key: request path, value: (status, body)
+type ETags = Mutex<HashMap<String, String>>; // key: request path
+
+async fn serve_fixture(req: HttpRequest, responses: web::Data<Responses>, etags: web::Data<ETags>) -> HttpResponse {
+    let etag = etags.lock().ok().and_then(|etags| etags.get(req.path()).cloned());
+    if let Some(etag) = &etag
+        && req.headers().get("If-None-Match").is_some_and(|value| value.as_bytes() == etag.as_bytes()) {
+        return HttpResponse::NotModified().insert_header(("ETag", etag.as_str())).finish();
+    }
+    let response = responses.lock().ok().and_then(|responses| responses.get(req.path()).cloned());
+    match response {
+        Some((status, body)) => {
+            let mut response = HttpResponse::build(StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR));
+            if let Some(etag) = etag {
+                response.insert_header(("ETag", etag));
+            }
+            response.body(body)
+        }
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Fake upstream servers on a local port, with a scratch cache directory
+struct MockUpstreams {
+    base_url: String,
+    responses: web::Data<Responses>,
+    etags: web::Data<ETags>,
+    system: actix_web::rt::System,
+    cache_dir: PathBuf,
+}
+
+impl MockUpstreams {
+    fn start(name: &str) -> Self {
+        let responses = web::Data::new(Mutex::new(default_responses()));
+        let server_responses = responses.clone();
+        let etags = web::Data::new(Mutex::new(HashMap::new()));
+        let server_etags = etags.clone();
+
+        let (started_tx, started_rx) = mpsc::channel();
+        thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                let server = HttpServer::new(move || {
+                    App::new()
+                        .app_data(server_responses.clone())
+                        .app_data(server_etags.clone())
+                        .default_service(web::to(serve_fixture))
+                })
+                    .workers(1)
+                    .bind(("127.0.0.1", 0))
+                    .expect("mock upstream bind");
+                let port = server.addrs()[0].port();
+                started_tx.send((port, actix_web::rt::System::current())).ok();
+                server.run().await.ok();
+            });
+        });
+        let (port, system) = started_rx.recv().expect("mock upstream start");
+
+        let cache_dir = std::env::temp_dir().join(format!("nvt-test-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&cache_dir).ok();
+
+        MockUpstreams { base_url: format!("http://127.0.0.1:{}", port), responses, etags, system, cache_dir }
+    }
+
+    fn upstreams(&self) -> Upstreams {
+        let url = |path: &str| format!("{}{}", self.base_url, path);
+        let mut feeds = NVTModels::built_in_feeds(&CacheAgesConfig::default());
+        for feed in &mut feeds {
+            let (static_url, rt_urls) = match feed.name.as_str() {
+                "TBM" => (url("/tbm/gtfs.zip"), vec![]),
+                "NewAquitaine" => (url("/naq/gtfs.zip"), vec![]),
+                _ => (url("/sncf/gtfs.zip"), vec![url("/sncf/trip-updates"), url("/sncf/alerts")]),
+            };
+            (feed.static_url, feed.rt_urls) = (static_url, rt_urls);
+        }
+        Upstreams {
+            tbm_api_url: url("/tbm"),
+            feeds,
+            communes_url: url("/communes"),
+            gbfs_url: url("/gbfs/gbfs.json"),
+            navitia: None,
+            cache_dir: self.cache_dir.clone(),
+            cache_ages: CacheAgesConfig::default(),
+        }
+    }
+
+    fn respond(&self, path: &str, status: u16, body: Vec<u8>) {
+        self.responses.lock().expect("mock responses").insert(path.to_string(), (status, body));
+    }
+
+    /// Serve `path` with an ETag, answering 304 to requests that send it back
+    fn tag(&self, path: &str, etag: &str) {
+        self.etags.lock().expect("mock etags").insert(path.to_string(), etag.to_string());
+    }
+
+    fn load(&self) -> CachedNetworkData {
+        NVTModels::initialize_cache_with(self.upstreams()).expect("cache from fixtures")
+    }
+}
+
+impl Drop for MockUpstreams {
+    fn drop(&mut self) {
+        self.system.stop();
+        fs::remove_dir_all(&self.cache_dir).ok();
+    }
+}
+
+/// Bodies of successful GET requests to the API routes, served from `cache`
+fn app_state(cache: CachedNetworkData) -> AppState {
+    AppState {
+        sandbox: Arc::new(Mutex::new(CachedNetworkData::empty(cache.upstreams.clone()))),
+        cache: Arc::new(Mutex::new(cache)),
+        journey_shares: Arc::new(Mutex::new(JourneyShareStore::default())),
+        history: Arc::new(Mutex::new(HistoryStore::default())),
+        elevation: Arc::new(Mutex::new(ElevationStore::default())),
+        alert_events: AlertBroadcaster::default(),
+        refresh_jobs: RefreshJobs::default(),
+        tiles: TileCache::default(),
+        clock: NVTModels::paris_now,
+    }
+}
+
+fn call_api(cache: CachedNetworkData, paths: &[&str]) -> Vec<(String, Value)> {
+    let state = app_state(cache);
+
+    actix_web::rt::System::new().block_on(async move {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state))
+                .configure(api_routes)
+        ).await;
+
+        let mut responses = Vec::new();
+        for path in paths {
+            let response = call_service(&app, TestRequest::get().uri(path).to_request()).await;
+            assert!(response.status().is_success(), "{} returned {}", path, response.status());
+            responses.push((path.to_string(), read_body_json(response).await));
+        }
+        responses
+    })
+}
+
+// ============================================================================
+// Golden Files
+// ============================================================================
+
+/// Sort what comes out of hash maps and sets, so golden files don't depend on iteration order
+fn normalize(value: &mut Value) {
+    match value {
+        Value::Array(items) => {
+            items.iter_mut().for_each(normalize);
+            let key = |item: &Value| {
+                item.get("stop_name").and(item.get("stop_id"))
+                    .or_else(|| item.get("line_ref"))
+                    .or_else(|| item.get("lines_count").and(item.get("name"))) // operators
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+            };
+            // Nearby stops come sorted by distance and line stops in travel order, which is part of the contract
+            let ordered = items.iter().any(|item| item.get("distance_m").or_else(|| item.get("stop_sequence")).is_some());
+            if !ordered && items.iter().all(|item| key(item).is_some()) {
+                items.sort_by_key(key);
+            }
+        }
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                normalize(field);
+                if let (true, Value::Array(lines)) = (name == "lines", &mut *field)
+                    && lines.iter().all(Value::is_string)
+                {
+                    lines.sort_by(|a, b| a.as_str().cmp(&b.as_str()));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn assert_golden(name: &str, mut actual: Value) {
+    normalize(&mut actual);
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden").join(name);
+    let rendered = serde_json::to_string_pretty(&actual).expect("serializable") + "\n";
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, rendered).expect("golden file write");
+        return;
+    }
+
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("{}: {} (run with UPDATE_GOLDEN=1 to create it)", path.display(), e));
+    let expected: Value = serde_json::from_str(&expected).expect("golden file JSON");
+    assert!(expected == actual, "{} no longer matches, got:\n{}", name, rendered);
+}
+
+// ============================================================================
+// API Schema
+// ============================================================================
+
+/// Mismatches between `value` and a schema of /api/schema, for the subset of JSON Schema that
+/// schema.rs emits ($ref, anyOf, type, properties, required, additionalProperties, items)
+fn schema_errors(value: &Value, schema: &Value, defs: &Value, path: &str, errors: &mut Vec<String>) {
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        let name = reference.trim_start_matches("#/$defs/");
+        return schema_errors(value, &defs[name], defs, path, errors);
+    }
+    if let Some(options) = schema.get("anyOf").and_then(Value::as_array) {
+        let matches = options.iter().any(|option| {
+            let mut option_errors = Vec::new();
+            schema_errors(value, option, defs, path, &mut option_errors);
+            option_errors.is_empty()
+        });
+        if !matches {
+            errors.push(format!("{}: {} matches no alternative", path, value));
+        }
+        return;
+    }
+
+    let expected: Vec<&str> = match &schema["type"] {
+        Value::String(name) => vec![name.as_str()],
+        Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    let actual = match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    };
+    if !expected.iter().any(|name| *name == actual || (*name == "number" && actual == "integer")) {
+        errors.push(format!("{}: expected {:?}, got {}", path, expected, actual));
+        return;
+    }
+
+    match value {
+        Value::Object(fields) => {
+            for required in schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str) {
+                if !fields.contains_key(required) {
+                    errors.push(format!("{}: missing {}", path, required));
+                }
+            }
+            for (name, field) in fields {
+                let field_path = format!("{}.{}", path, name);
+                match (schema["properties"].get(name), &schema["additionalProperties"]) {
+                    (Some(property), _) => schema_errors(field, property, defs, &field_path, errors),
+                    (None, Value::Bool(false)) => errors.push(format!("{}: not in the schema", field_path)),
+                    (None, Value::Object(_)) => {
+                        schema_errors(field, &schema["additionalProperties"], defs, &field_path, errors)
+                    }
+                    (None, _) => {}
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(prefix) = schema["prefixItems"].as_array() {
+                if items.len() != prefix.len() {
+                    errors.push(format!("{}: expected {} items, got {}", path, prefix.len(), items.len()));
+                }
+                for (i, (item, item_schema)) in items.iter().zip(prefix).enumerate() {
+                    schema_errors(item, item_schema, defs, &format!("{}[{}]", path, i), errors);
+                }
+            } else if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    schema_errors(item, item_schema, defs, &format!("{}[{}]", path, i), errors);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+// ============================================================================
+// Tests
+// ============================================================================
+
+#[test]
+fn merged_network_matches_golden() {
+    let mock = MockUpstreams::start("network");
+    let cache = mock.load();
+
+    assert_golden("network.json", serde_json::to_value(cache.to_network_data()).expect("serializable"));
+}
+
+#[test]
+fn api_responses_match_golden() {
+    const PATHS: [&str; 43] = [
+        "/api/tbm/network?bbox=-0.58,44.835,-0.574,44.844",
+        "/api/tbm/stops?bbox=-0.58,44.835,-0.574,44.844",
+        "/api/tbm/vehicles?bbox=-0.58,44.835,-0.574,44.844",
+        "/api/tbm/stop/3672",
+        "/api/tbm/stops?commune=Libourne",
+        "/api/tbm/stops?operator=calibus%20(libourne)&fields=stop_id,stop_name,lines",
+        "/api/tbm/lines?operator=TBM&page=2&per_page=2&fields=line_ref,line_code",
+        "/api/tbm/lines?operator=calibus-libourne&fields=line_ref,operator",
+        "/api/tbm/lines?mode=Tram&fields=line_ref,operator,mode",
+        "/api/tbm/stops/nearby?lat=44.8425&lon=-0.5745&radius=800",
+        "/api/tbm/stops/clusters?zoom=10",
+        "/api/tbm/stop/3672/busyness?date=2026-05-04",
+        "/api/tbm/stops/clusters?zoom=16&bbox=-0.58,44.835,-0.574,44.844",
+        "/api/tbm/search?q=grand%20theatre",
+        "/api/tbm/search?q=libourn",
+        "/api/tbm/search?q=quinconse",
+        "/api/tbm/line/A",
+        "/api/tbm/line/A/stops",
+        "/api/tbm/line/1/stops?direction=1",
+        "/api/tbm/line/a/timetable?date=2026-05-04&stop=3671",
+        "/api/tbm/line/A/shape?format=polyline",
+        "/api/tbm/vehicles",
+        "/api/tbm/vehicle/V-A-1",
+        "/api/tbm/trip/A-1",
+        "/api/tbm/stops?commune=Libourne&format=geojson&page=1&fields=stop_id,stop_name,latitude,longitude",
+        "/api/tbm/vehicles?format=geojson",
+        "/api/tbm/vehicles?zoom=10",
+        "/api/tbm/vehicles?zoom=10&format=geojson",
+        "/api/tbm/line/a/vehicles.geojson",
+        "/api/tbm/shapes?line=A",
+        "/api/tbm/shapes?format=geojson&bbox=-0.58,44.835,-0.574,44.844",
+        "/api/tbm/shapes?line=A&format=polyline&zoom=12",
+        "/api/wfs?SERVICE=WFS&REQUEST=GetFeature&TYPENAMES=nvt:stops&BBOX=44.835,-0.58,44.844,-0.574,urn:ogc:def:crs:EPSG::4326",
+        "/api/wfs?service=WFS&version=2.0.0&request=GetFeature&typeName=lines&count=2&startIndex=1",
+        "/api/tbm/alerts",
+        "/api/tbm/alerts?route=A&stop=3672&active_at=2024-01-01",
+        "/api/tbm/alerts?severity_min=4",
+        "/api/tbm/alerts?expand=stops",
+        "/api/tbm/operators",
+        "/api/tbm/modes",
+        "/api/tbm/agencies",
+        "/api/tbm/agency/CALIBUS/lines",
+        "/api/tbm/quality",
+    ];
+
+    let mock = MockUpstreams::start("api");
+    // GeoJSON comes without the ApiResponse envelope
+    let responses = call_api(mock.load(), &PATHS).into_iter()
+        .map(|(path, body)| match body.get("success") {
+            Some(_) => (path, body["data"].clone()),
+            None => (path, body),
+        })
+        .collect();
+
+    assert_golden("api.json", Value::Object(responses));
+}
+
+#[test]
+fn api_schema_matches_golden() {
+    assert_golden("schema.json", crate::schema::document());
+}
+
+#[test]
+fn api_index_lists_the_registered_routes() {
+    let mock = MockUpstreams::start("index");
+    let state = app_state(mock.load());
+
+    actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let index: Value = read_body_json(call_service(&app, TestRequest::get().uri("/api").to_request()).await).await;
+        let index = &index["data"];
+        assert_eq!(index["version"], env!("CARGO_PKG_VERSION"));
+        let sources: Vec<(&str, bool)> = index["sources"].as_array().expect("sources").iter()
+            .map(|source| (source["name"].as_str().unwrap_or_default(), source["enabled"] == true))
+            .collect();
+        assert_eq!(sources, [("TBM", true), ("New-Aquitaine", true), ("SNCF", true)]);
+
+        let endpoints = index["endpoints"].as_array().expect("endpoints");
+        let departures = endpoints.iter().find(|e| e["path"] == "/api/tbm/stop/{id}/departures").expect("departures listed");
+        assert_eq!(departures["templated"], true);
+        assert_eq!(departures["href"], "http://localhost:8080/api/tbm/stop/{id}/departures");
+        assert_eq!(departures["params"], serde_json::json!(["limit", "tz", "time_format", "accessible"]));
+
+        // Every listed route is answered: a handler's 404 has a body, the router's has none
+        for endpoint in endpoints {
+            let method = endpoint["method"].as_str().unwrap_or_default();
+            let path = ["{id}", "{code}", "{name}", "{config_id}"].iter()
+                .fold(endpoint["path"].as_str().unwrap_or_default().to_string(), |path, param| path.replace(param, "none"));
+            let request = match method {
+                "POST" => TestRequest::post(),
+                "DELETE" => TestRequest::delete(),
+                _ => TestRequest::get(),
+            };
+            let response = call_service(&app, request.uri(&path).to_request()).await;
+            if response.status() == StatusCode::NOT_FOUND {
+                let body = actix_web::test::read_body(response).await;
+                assert!(!body.is_empty(), "{} {} is listed but not routed", method, path);
+            }
+        }
+    });
+}
+
+#[test]
+fn unchanged_data_is_not_sent_again() {
+    let mock = MockUpstreams::start("etag");
+    let state = app_state(mock.load());
+    let cache = state.cache.clone();
+
+    actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let get = |path: &'static str, etag: Option<String>| {
+            let mut request = TestRequest::get().uri(path);
+            if let Some(etag) = etag {
+                request = request.insert_header(("If-None-Match", etag));
+            }
+            request.to_request()
+        };
+        let etag = |response: &actix_web::dev::ServiceResponse| {
+            response.headers().get("ETag").and_then(|value| value.to_str().ok()).map(String::from)
+        };
+
+        let network = call_service(&app, get("/api/tbm/network", None)).await;
+        assert_eq!(network.status(), StatusCode::OK);
+        let network_etag = etag(&network).expect("network ETag");
+        let shapes_etag = etag(&call_service(&app, get("/api/tbm/shapes", None)).await).expect("shapes ETag");
+
+        let cached = call_service(&app, get("/api/tbm/network", Some(format!("\"other\", {}", network_etag)))).await;
+        assert_eq!(cached.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(etag(&cached).as_ref(), Some(&network_etag));
+        assert!(actix_web::test::read_body(cached).await.is_empty());
+
+        // A dynamic refresh changes the network, not the shapes
+        cache.lock().expect("cache").last_dynamic_update += 30;
+        let refreshed = call_service(&app, get("/api/tbm/network", Some(network_etag.clone()))).await;
+        assert_eq!(refreshed.status(), StatusCode::OK);
+        assert_ne!(etag(&refreshed), Some(network_etag));
+        let shapes = call_service(&app, get("/api/tbm/shapes", Some(shapes_etag))).await;
+        assert_eq!(shapes.status(), StatusCode::NOT_MODIFIED);
+    });
+}
+
+#[test]
+fn client_bindings_cover_every_model_and_endpoint() {
+    let document = crate::schema::document();
+    let typescript = client_gen::generate(&document, Lang::TypeScript);
+    let rust = client_gen::generate(&document, Lang::Rust);
+    for model in document["$defs"].as_object().expect("models").keys() {
+        assert!(typescript.contains(&format!("export interface {} {{", model)), "{}", model);
+        assert!(rust.contains(&format!("pub struct {} {{", model)), "{}", model);
+    }
+
+    assert!(typescript.contains("  stop_headsign?: string | null;"));
+    assert!(typescript.contains("  stopSchedule(id: string): Promise<TimedArrival[]> {"));
+    assert!(typescript.contains("`/api/tbm/vehicles?since=${encodeURIComponent(since)}`"));
+    assert!(rust.contains("    pub service_date: String,"));
+    assert!(rust.contains("    pub fn vehicles_since(&self, since: &str) -> Result<VehiclesDelta, String> {"));
+    assert!(rust.contains("    pub fn line_shape(&self, code: &str) -> Result<HashMap<String, Vec<ShapePoint>>, String> {"));
+}
+
+#[test]
+fn responses_match_api_schema() {
+    // (route in the schema, request); the first three are what tbm-transit.js reads
+    const REQUESTS: [(&str, &str); 20] = [
+        ("/api/tbm/network", "/api/tbm/network"),
+        ("/api/tbm/stop/{id}/schedule", "/api/tbm/stop/3672/schedule?time_format=rfc3339"),
+        ("/api/tbm/vehicle/{id}", "/api/tbm/vehicle/V-A-1"),
+        ("/api/tbm/trip/{id}", "/api/tbm/trip/A-1"),
+        ("/api/tbm/stops", "/api/tbm/stops"),
+        ("/api/tbm/lines", "/api/tbm/lines"),
+        ("/api/tbm/vehicles", "/api/tbm/vehicles"),
+        ("/api/tbm/vehicles?since=", "/api/tbm/vehicles?since=0"),
+        ("/api/tbm/shapes", "/api/tbm/shapes"),
+        ("/api/tbm/alerts", "/api/tbm/alerts"),
+        ("/api/tbm/stop/{id}", "/api/tbm/stop/3672"),
+        ("/api/tbm/stop/{id}/arrivals", "/api/tbm/stop/3672/arrivals?limit=50"),
+        ("/api/tbm/stop/{id}/departures", "/api/tbm/stop/3673/departures?limit=50&time_format=rfc3339"),
+        ("/api/tbm/line/{code}", "/api/tbm/line/A"),
+        ("/api/tbm/line/{code}/stops", "/api/tbm/line/A/stops"),
+        ("/api/tbm/line/{code}/timetable", "/api/tbm/line/A/timetable?date=2026-05-04&stop=3671&time_format=rfc3339"),
+        ("/api/tbm/line/{code}/shape", "/api/tbm/line/A/shape"),
+        ("/api/tbm/agencies", "/api/tbm/agencies"),
+        ("/api/tbm/agency/{id}/lines", "/api/tbm/agency/CALIBUS/lines"),
+        ("/api/tbm/stats", "/api/tbm/stats"),
+    ];
+
+    let mock = MockUpstreams::start("schema");
+    let mut paths = vec!["/api/schema"];
+    paths.extend(REQUESTS.iter().map(|(_, path)| *path));
+    let responses = call_api(mock.load(), &paths);
+
+    let document = &responses[0].1;
+    assert_eq!(*document, crate::schema::document(), "/api/schema serves the schema document");
+    let endpoints = document["endpoints"].as_object().expect("endpoints");
+    for route in endpoints.keys() {
+        assert!(REQUESTS.iter().any(|(r, _)| r == route), "no request checks {} against its schema", route);
+    }
+
+    for ((route, _), (path, body)) in REQUESTS.iter().zip(&responses[1..]) {
+        let mut errors = Vec::new();
+        schema_errors(body, &endpoints[*route], &document["$defs"], "$", &mut errors);
+        assert!(errors.is_empty(), "{} does not match the schema of {}:\n{}", path, route, errors.join("\n"));
+    }
+}
+
+#[test]
+fn archived_alerts_export_as_csv() {
+    let mock = MockUpstreams::start("export");
+    let cache = mock.load();
+    let mut history = HistoryStore::default();
+    history.record_alerts(&cache.alerts);
+
+    let csv = HistoryStore::alerts_csv(&history.query_alerts(None, None, None)).expect("csv");
+    let mut reader = csv::Reader::from_reader(csv.as_bytes());
+    let headers = reader.headers().expect("header row").clone();
+    let rows: Vec<csv::StringRecord> = reader.records().collect::<Result<_, _>>().expect("csv rows");
+
+    assert_eq!(rows.len(), cache.alerts.len());
+    let column = |name: &str| headers.iter().position(|h| h == name).expect("column");
+    let tbm = rows.iter().find(|row| &row[column("id")] == "TBM-1").expect("TBM-1 exported");
+    let expected = cache.alerts.iter().find(|a| a.id == "TBM-1").expect("TBM-1 in the feed");
+    assert_eq!(&tbm[column("route_ids")], expected.route_ids.join(";"));
+    assert_eq!(&tbm[column("text")], expected.text);
+
+}
+
+#[test]
+fn ended_trips_are_archived_and_export_with_the_alerts() {
+    let mock = MockUpstreams::start("export-delays");
+    let cache = mock.load();
+    let (network, mut trip_updates) = (cache.to_network_data(), cache.trip_updates.clone());
+    let archive = std::env::temp_dir().join(format!("nvt-test-delays-{}.csv", std::process::id()));
+    std::fs::remove_file(&archive).ok();
+
+    // Tram A-1 runs 120 s late; once out of the feed long enough, its run goes to the archive
+    let mut history = HistoryStore::default();
+    history.delays_path = Some(archive.clone());
+    history.record_alerts(&cache.alerts);
+    assert!(history.record_delays(&network, &trip_updates).is_empty());
+    let running = history.trips.get("TBM/A-1").expect("A-1 followed").clone();
+    assert_eq!((running.line_code.as_str(), running.samples, running.last_delay), ("A", 1, 120));
+
+    trip_updates.clear();
+    assert!(history.record_delays(&network, &trip_updates).is_empty(), "a trip missing one refresh has not ended");
+    for trip in history.trips.values_mut() {
+        (trip.first_seen, trip.last_seen) = (trip.first_seen - 600, trip.last_seen - 600);
+    }
+    let ended = history.record_delays(&network, &trip_updates);
+    assert!(ended.iter().any(|trip| trip.trip_id == "A-1"));
+    assert!(history.trips.is_empty());
+    history.archive_delays(&ended).expect("archive written");
+    history.archive_delays(&ended[..1]).expect("archive appended");
+    let archived = HistoryStore::query_delays(Some(&archive), Vec::new(), None, None);
+    assert_eq!(archived.len(), ended.len() + 1, "appending adds rows without a second header");
+    assert!(HistoryStore::query_delays(Some(&archive), Vec::new(), Some(running.last_seen - 599), None).is_empty());
+
+    let state = app_state(cache);
+    *state.history.lock().expect("history") = history;
+    let exports = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let mut exports = Vec::new();
+        for uri in [
+            "/admin/history/export?table=delays",
+            "/admin/history/export?table=delays&format=json",
+            "/admin/history/export?table=delays&format=parquet",
+            "/admin/history/export?format=parquet",
+            "/admin/history/export?table=positions",
+            "/admin/history/export?format=xlsx",
+        ] {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            let content_type = response.headers().get("content-type")
+                .and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+            exports.push((response.status(), content_type, actix_web::test::read_body(response).await.to_vec()));
+        }
+        exports
+    });
+    std::fs::remove_file(&archive).ok();
+
+    let mut csv = csv::Reader::from_reader(exports[0].2.as_slice());
+    let rows: Vec<ArchivedDelay> = csv.deserialize().collect::<Result<_, _>>().expect("delay rows");
+    let tram = rows.iter().find(|trip| trip.trip_id == "A-1").expect("A-1 exported");
+    assert_eq!((tram.operator.as_str(), tram.max_delay, tram.mean_delay), ("TBM", 120, 120.0));
+    assert_eq!(exports[0].1, "text/csv; charset=utf-8");
+
+    let json: Vec<ArchivedDelay> = serde_json::from_slice(&exports[1].2).expect("delay json");
+    assert_eq!(json, rows);
+
+    // Parquet: magic at both ends, the footer length before the last one, string columns as is
+    for (status, content_type, body) in &exports[2..4] {
+        assert_eq!((*status, content_type.as_str()), (StatusCode::OK, "application/vnd.apache.parquet"));
+        assert!(body.starts_with(b"PAR1") && body.ends_with(b"PAR1"));
+        let footer_len = u32::from_le_bytes(body[body.len() - 8..body.len() - 4].try_into().expect("footer length"));
+        assert!((footer_len as usize) < body.len() - 12);
+    }
+    let delays_parquet = &exports[2].2;
+    assert!(delays_parquet.windows(b"mean_delay".len()).any(|w| w == b"mean_delay"));
+    assert!(delays_parquet.windows(3).any(|w| w == b"A-1"));
+    assert!(exports[3].2.windows(b"TBM-1".len()).any(|w| w == b"TBM-1"));
+
+    let errors: Vec<(StatusCode, String)> = exports[4..].iter()
+        .map(|(status, _, body)| {
+            let body: Value = serde_json::from_slice(body).expect("error body");
+            (*status, body["error"].as_str().unwrap_or_default().to_string())
+        })
+        .collect();
+    assert_eq!(errors, [
+        (StatusCode::BAD_REQUEST, "Table 'positions' is unknown (available: alerts, delays)".to_string()),
+        (StatusCode::BAD_REQUEST, "Unsupported format 'xlsx' (expected csv, json or parquet)".to_string()),
+    ]);
+}
+
+#[test]
+fn merged_gtfs_export_namespaces_every_source() {
+    let mock = MockUpstreams::start("gtfs-export");
+    let cache = mock.load();
+    let trips: usize = cache.gtfs_caches().iter().map(|gtfs| gtfs.trips.len()).sum();
+    let state = app_state(cache);
+
+    let body = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let response = call_service(&app, TestRequest::get().uri("/api/tbm/export/gtfs.zip").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("content-type").and_then(|v| v.to_str().ok()), Some("application/zip"));
+        actix_web::test::read_body(response).await
+    });
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(body.to_vec())).expect("GTFS zip");
+    let mut table = |name: &str| -> Vec<HashMap<String, String>> {
+        let mut contents = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name(name).expect(name), &mut contents).expect("utf-8");
+        let mut reader = csv::Reader::from_reader(contents.as_bytes());
+        let headers = reader.headers().expect("header row").clone();
+        reader.records()
+            .map(|row| headers.iter().zip(row.expect("csv row").iter()).map(|(h, v)| (h.to_string(), v.to_string())).collect())
+            .collect()
+    };
+    let column = |rows: &[HashMap<String, String>], name: &str| -> Vec<String> {
+        rows.iter().map(|row| row[name].clone()).collect()
+    };
+    let (agencies, stops, routes, trip_rows, stop_times) =
+        (table("agency.txt"), table("stops.txt"), table("routes.txt"), table("trips.txt"), table("stop_times.txt"));
+
+    assert_eq!(trip_rows.len(), trips);
+    for prefix in ["TBM:", "NAQ:", "SNCF:"] {
+        assert!(column(&stops, "stop_id").iter().any(|id| id.starts_with(prefix)), "no {} stops", prefix);
+    }
+    let tram_a = routes.iter().find(|route| route["route_id"] == "TBM:59").expect("tram A exported");
+    assert_eq!((tram_a["route_short_name"].as_str(), tram_a["route_color"].as_str()), ("A", "814997"));
+    assert!(column(&stops, "stop_id").contains(&"TBM:3671".to_string()));
+
+    // Every reference resolves within the merged feed
+    let (stop_ids, route_ids, trip_ids, agency_ids) =
+        (column(&stops, "stop_id"), column(&routes, "route_id"), column(&trip_rows, "trip_id"), column(&agencies, "agency_id"));
+    assert!(column(&routes, "agency_id").iter().all(|id| agency_ids.contains(id)));
+    assert!(column(&trip_rows, "route_id").iter().all(|id| route_ids.contains(id)));
+    assert!(column(&stop_times, "trip_id").iter().all(|id| trip_ids.contains(id)));
+    assert!(column(&stop_times, "stop_id").iter().all(|id| stop_ids.contains(id)));
+}
+
+#[test]
+fn gtfs_rt_feeds_use_the_export_ids() {
+    let mock = MockUpstreams::start("gtfs-rt");
+    let mut cache = mock.load();
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh"); // Merges in SNCF real-time data
+    let alerts = cache.alerts.len();
+    let state = app_state(cache);
+
+    let feeds = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let mut feeds = Vec::new();
+        for path in ["/api/tbm/gtfs-rt/vehicles", "/api/tbm/gtfs-rt/alerts", "/api/tbm/gtfs-rt/trip-updates"] {
+            let response = call_service(&app, TestRequest::get().uri(path).to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers().get("content-type").and_then(|v| v.to_str().ok()), Some("application/x-protobuf"));
+            let body = actix_web::test::read_body(response).await;
+            feeds.push(FeedMessage::decode(body.as_ref()).expect("GTFS-RT feed"));
+        }
+        feeds
+    });
+    let [vehicles, alert_feed, trip_updates] = <[FeedMessage; 3]>::try_from(feeds).expect("three feeds");
+
+    let tram = vehicles.entity.iter().find(|e| e.id == "V-A-1").and_then(|e| e.vehicle.as_ref()).expect("tram A vehicle");
+    let trip = tram.trip.as_ref().expect("trip");
+    assert_eq!((trip.trip_id.as_deref(), trip.route_id.as_deref()), (Some("TBM:A-1"), Some("TBM:59")));
+    assert_eq!(tram.stop_id.as_deref(), Some("TBM:3672"));
+    assert_eq!(tram.occupancy_status, Some(OccupancyStatus::FewSeatsAvailable as i32));
+
+    assert_eq!(alert_feed.entity.len(), alerts);
+    let sncf_alert = alert_feed.entity.iter().find(|e| e.id == "SNCF-1").and_then(|e| e.alert.as_ref()).expect("SNCF alert");
+    assert_eq!(sncf_alert.informed_entity[0].route_id.as_deref(), Some("SNCF:OCESN:Line:TER-43"));
+
+    let train = trip_updates.entity.iter().find(|e| e.id == "SNCF:OCESN864201").and_then(|e| e.trip_update.as_ref()).expect("SNCF trip update");
+    assert_eq!(train.stop_time_update[0].stop_id.as_deref(), Some("SNCF:87584052"));
+    assert!(trip_updates.entity.iter().any(|e| e.id == "TBM:A-1"));
+}
+
+#[test]
+fn around_lists_nearby_stops_with_their_departures() {
+    let mock = MockUpstreams::start("around");
+    let state = app_state(mock.load());
+
+    let responses = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let mut responses = Vec::new();
+        for query in ["lat=44.8425&lon=-0.5745&radius=800", "lat=44.8425"] {
+            let uri = format!("/api/tbm/around?{}", query);
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            let status = response.status().as_u16();
+            responses.push((status, read_body_json::<Value, _>(response).await));
+        }
+        responses
+    });
+
+    let (status, body) = &responses[0];
+    assert_eq!(*status, 200);
+    let stops = body["data"].as_array().expect("stops");
+    assert!(!stops.is_empty());
+    let distances: Vec<f64> = stops.iter().filter_map(|stop| stop["distance_m"].as_f64()).collect();
+    assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]), "nearest first: {:?}", distances);
+    for stop in stops {
+        let departures = stop["departures"].as_array().expect("departures");
+        assert!(departures.len() <= 3, "{}", stop["stop_id"]);
+        assert!(departures.iter().all(|departure| departure["status"] == "realtime" || departure["status"] == "scheduled"));
+        assert!(stop["alerts"].is_array());
+    }
+    let hotel_de_ville = stops.iter().find(|stop| stop["stop_id"] == "3673").expect("Hôtel de Ville is around");
+    assert!(!hotel_de_ville["departures"].as_array().expect("departures").is_empty());
+
+    assert_eq!(responses[1].0, 400);
+}
+
+#[test]
+fn stop_batch_returns_the_stops_asked_in_order() {
+    let mock = MockUpstreams::start("stops_batch");
+    let state = app_state(mock.load());
+
+    let responses = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let too_many: Vec<String> = (0..101).map(|i| i.to_string()).collect();
+        let mut responses = Vec::new();
+        for (query, body) in [
+            ("", serde_json::json!(["3673", "9999", "3671", "3673"])),
+            ("?departures=true&limit=2", serde_json::json!(["3673"])),
+            ("", serde_json::json!(too_many)),
+        ] {
+            let uri = format!("/api/tbm/stops/batch{}", query);
+            let response = call_service(&app, TestRequest::post().uri(&uri).set_json(body).to_request()).await;
+            let status = response.status().as_u16();
+            responses.push((status, read_body_json::<Value, _>(response).await));
+        }
+        responses
+    });
+
+    let (status, body) = &responses[0];
+    assert_eq!(*status, 200);
+    let stop_ids: Vec<&str> = body["data"]["stops"].as_array().expect("stops").iter()
+        .filter_map(|stop| stop["stop_id"].as_str())
+        .collect();
+    assert_eq!(stop_ids, ["3673", "3671"]);
+    assert_eq!(body["data"]["not_found"], serde_json::json!(["9999"]));
+    assert!(body["data"]["stops"][0].get("departures").is_none());
+
+    let (status, body) = &responses[1];
+    assert_eq!(*status, 200);
+    let departures = body["data"]["stops"][0]["departures"].as_array().expect("departures");
+    assert!(!departures.is_empty() && departures.len() <= 2);
+
+    assert_eq!(responses[2].0, 400);
+}
+
+#[test]
+fn alerts_are_given_in_the_accepted_language() {
+    let mock = MockUpstreams::start("alert_languages");
+    let bilingual = |fr: &str, en: &str| Some(TranslatedString {
+        translation: vec![
+            Translation { text: fr.to_string(), language: Some("fr".to_string()) },
+            Translation { text: en.to_string(), language: Some("en".to_string()) },
+        ],
+    });
+    let mut sncf_alert = alert("SNCF-1", "Trafic perturbé", &["OCESN:Line:TER-43"], &[]);
+    if let Some(alert) = sncf_alert.alert.as_mut() {
+        alert.header_text = bilingual("Trafic perturbé", "Disrupted traffic");
+        alert.description_text = bilingual("Travaux", "Engineering works");
+    }
+    mock.respond("/sncf/alerts", 200, feed(vec![sncf_alert]));
+    let mut cache = mock.load();
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh"); // Merges in SNCF real-time data
+    let state = app_state(cache);
+
+    let headers = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let mut headers = Vec::new();
+        for (uri, accept_language) in [
+            ("/api/tbm/alerts", None),
+            ("/api/tbm/alerts", Some("en-GB,en;q=0.9,fr;q=0.5")),
+            ("/api/tbm/alerts", Some("de, fr;q=0.8, en;q=0.2")),
+            ("/api/tbm/alerts?lang=en", Some("fr")),
+        ] {
+            let mut request = TestRequest::get().uri(uri);
+            if let Some(accept_language) = accept_language {
+                request = request.insert_header(("Accept-Language", accept_language));
+            }
+            let body: Value = read_body_json(call_service(&app, request.to_request()).await).await;
+            let alert = body["data"].as_array().into_iter().flatten().find(|alert| alert["id"] == "SNCF-1").cloned().unwrap_or_default();
+            headers.push((alert["text"].clone(), alert["description"].clone(), alert["translations"].as_array().map_or(0, Vec::len)));
+        }
+        headers
+    });
+
+    assert_eq!(headers[0], (Value::from("Trafic perturbé"), Value::from("Travaux"), 2), "feed order without a preference");
+    assert_eq!(headers[1], (Value::from("Disrupted traffic"), Value::from("Engineering works"), 2));
+    assert_eq!(headers[2].0, "Trafic perturbé", "first accepted language the alert has");
+    assert_eq!(headers[3].0, "Disrupted traffic", "?lang= wins over the header");
+}
+
+#[test]
+fn protected_routes_require_a_known_api_key() {
+    let mock = MockUpstreams::start("api_keys");
+    let state = app_state(mock.load());
+    let key = |name: &str, key: &str, requests_per_minute, routes: &[&str]| ApiKeyConfig {
+        name: name.to_string(),
+        key: key.to_string(),
+        requests_per_minute,
+        routes: routes.iter().map(|route| route.to_string()).collect(),
+    };
+    let guard = Arc::new(ApiKeyGuard::new(&ApiKeysConfig {
+        keys: vec![
+            key("ops", "ops-secret", None, &[]),
+            key("ci", "ci-secret", Some(1), &[]),
+            key("sync", "sync-secret", None, &["/admin/sync"]),
+        ],
+        ..ApiKeysConfig::default()
+    }));
+    let open_guard = ApiKeyGuard::new(&ApiKeysConfig::default());
+    assert_eq!(open_guard.check("/api/tbm/refresh", None, 0), Ok(None), "no keys, no protection");
+    let sync_only = ApiKeyGuard::new(&ApiKeysConfig { keys: vec![key("sync", "sync-secret", None, &["/admin/sync"])], ..ApiKeysConfig::default() });
+    assert!(!sync_only.enabled());
+    assert_eq!(sync_only.check("/api/tbm/refresh", None, 0), Ok(None), "a token only guards its own route");
+    assert_eq!(sync_only.check("/admin/sync", None, 0), Err(Refusal::MissingKey));
+    assert_eq!(sync_only.check("/admin/sync", Some("sync-secre"), 0), Err(Refusal::UnknownKey));
+    assert_eq!(api_keys::given_key(None, Some("Bearer sync-secret")), Some("sync-secret"));
+    assert_eq!(api_keys::given_key(None, Some("sync-secret")), None);
+
+    let responses = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new()
+            .app_data(web::Data::new(state))
+            .wrap_fn(move |req, srv| api_key_gate(&guard, req, srv))
+            .configure(api_routes)).await;
+        let mut responses = Vec::new();
+        for (uri, header) in [
+            ("/api/tbm/refresh/unknown-job", None),
+            ("/api/tbm/refresh/unknown-job", Some(("X-Api-Key", "guess"))),
+            ("/api/tbm/refresh/unknown-job", Some(("X-Api-Key", "ops-secret"))),
+            ("/api/tbm/refresh/unknown-job", Some(("X-Api-Key", "ci-secret"))),
+            ("/api/tbm/refresh/unknown-job", Some(("X-Api-Key", "ci-secret"))),
+            ("/api/tbm/lines", None),
+            ("/admin/history/export", None),
+            ("/admin/sync", None),
+            ("/admin/sync", Some(("Authorization", "Bearer sync-secret"))),
+            ("/api/tbm/refresh/unknown-job", Some(("Authorization", "Bearer sync-secret"))),
+            ("/admin/sync", Some(("X-Api-Key", "ops-secret"))),
+        ] {
+            let mut request = TestRequest::get().uri(uri);
+            if let Some(header) = header {
+                request = request.insert_header(header);
+            }
+            // Refusals are errors of the middleware, rendered as responses by the server
+            let response = match try_call_service(&app, request.to_request()).await {
+                Ok(response) => response.into_parts().1.map_into_boxed_body(),
+                Err(e) => e.error_response(),
+            };
+            let retry_after = response.headers().get("retry-after").and_then(|h| h.to_str().ok()).map(String::from);
+            responses.push((response.status().as_u16(), retry_after));
+        }
+        responses
+    });
+
+    let statuses: Vec<u16> = responses.iter().map(|(status, _)| *status).collect();
+    // Past the guard, /admin/sync answers 404 as sync is off in the test configuration
+    assert_eq!(statuses, [401, 401, 404, 404, 429, 200, 401, 401, 404, 401, 404]);
+    assert!(responses[4].1.as_deref().and_then(|secs| secs.parse::<u64>().ok()).is_some_and(|secs| (1..=60).contains(&secs)));
+}
+
+#[test]
+fn responses_carry_cache_headers_by_endpoint_class() {
+    for route in http_cache::routes() {
+        assert!(ENDPOINTS.iter().any(|e| e.method == "GET" && e.path == route), "{} is not a GET route", route);
+    }
+
+    let mock = MockUpstreams::start("http_cache");
+    let state = app_state(mock.load());
+
+    let responses = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).wrap(middleware::from_fn(cache_headers)).configure(api_routes)).await;
+        let header = |response: &actix_web::dev::ServiceResponse, name: &str| {
+            response.headers().get(name).and_then(|h| h.to_str().ok()).map(String::from)
+        };
+        let shapes = call_service(&app, TestRequest::get().uri("/api/tbm/shapes").to_request()).await;
+        let last_modified = header(&shapes, "last-modified").expect("Last-Modified");
+        let mut responses = vec![(shapes.status().as_u16(), header(&shapes, "cache-control"), header(&shapes, "vary"))];
+        for (uri, if_modified_since) in [
+            ("/api/tbm/vehicles", None),
+            ("/api/tbm/shapes", Some(last_modified.as_str())),
+            ("/api/tbm/shapes", Some("Mon, 01 Jan 2001 00:00:00 GMT")),
+            ("/api/tbm/stats", None),
+        ] {
+            let mut request = TestRequest::get().uri(uri);
+            if let Some(since) = if_modified_since {
+                request = request.insert_header(("If-Modified-Since", since));
+            }
+            let response = call_service(&app, request.to_request()).await;
+            responses.push((response.status().as_u16(), header(&response, "cache-control"), header(&response, "vary")));
+        }
+        responses
+    });
+
+    assert_eq!(responses[0], (200, Some("public, max-age=3600".to_string()), None));
+    assert_eq!(responses[1], (200, Some("public, max-age=20".to_string()), Some("Accept-Language".to_string())));
+    assert_eq!(responses[2].0, 304, "not modified since the static load");
+    assert_eq!(responses[3].0, 200);
+    assert_eq!(responses[4], (200, None, None), "uncached endpoint");
+}
+
+#[test]
+fn line_responses_are_modified_by_each_dynamic_refresh() {
+    let mock = MockUpstreams::start("http_cache_lines");
+    let state = app_state(mock.load());
+    let cache = state.cache.clone();
+
+    actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).wrap(middleware::from_fn(cache_headers)).configure(api_routes)).await;
+        let get = |uri: &str, since: Option<&str>| {
+            let mut request = TestRequest::get().uri(uri);
+            if let Some(since) = since {
+                request = request.insert_header(("If-Modified-Since", since.to_string()));
+            }
+            request.to_request()
+        };
+        let last_modified = |response: &actix_web::dev::ServiceResponse| {
+            response.headers().get("last-modified").and_then(|h| h.to_str().ok()).map(String::from).expect("Last-Modified")
+        };
+
+        // They carry the alerts and vehicles of the lines
+        for uri in ["/api/tbm/lines", "/api/tbm/line/A", "/api/tbm/operator/TBM"] {
+            let response = call_service(&app, get(uri, None)).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+            assert_eq!(response.headers().get("cache-control").and_then(|h| h.to_str().ok()), Some("public, max-age=20"), "{}", uri);
+            let before = last_modified(&response);
+            assert_eq!(call_service(&app, get(uri, Some(&before))).await.status(), StatusCode::NOT_MODIFIED, "{}", uri);
+
+            cache.lock().expect("cache").last_dynamic_update += 30;
+            let refreshed = call_service(&app, get(uri, Some(&before))).await;
+            assert_eq!(refreshed.status(), StatusCode::OK, "{} changed with the refresh", uri);
+            assert_ne!(last_modified(&refreshed), before, "{}", uri);
+        }
+    });
+}
+
+#[test]
+fn heavy_requests_past_their_deadline_get_a_504() {
+    let mock = MockUpstreams::start("deadline");
+    let state = app_state(mock.load());
+    let (cache, cache_after) = (state.cache.clone(), state.cache.clone());
+    let timeouts = RequestTimeoutsConfig { default_secs: 1, routes: HashMap::new() };
+
+    let statuses = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new()
+            .app_data(web::Data::new(state))
+            .wrap_fn(move |req, srv| request_deadline(&timeouts, req, srv))
+            // Outside the deadline, as in main: it must not wait on the cache either
+            .wrap(middleware::from_fn(cache_headers))
+            .configure(api_routes)).await;
+        let status = |result: Result<actix_web::dev::ServiceResponse, actix_web::Error>| match result {
+            Ok(response) => response.status(),
+            Err(e) => e.error_response().status(),
+        };
+
+        // A refresh holding the cache lock keeps the handlers waiting on it
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let refresh = thread::spawn(move || {
+            let _cache = cache.lock().expect("cache");
+            locked_tx.send(()).ok();
+            release_rx.recv().ok();
+        });
+        locked_rx.recv().expect("cache locked");
+
+        let mut statuses = Vec::new();
+        for uri in ["/api/tbm/network", "/api/tbm/stops?format=geojson", "/api/tbm/shapes", "/api/tbm/line/A/vehicles.geojson"] {
+            statuses.push(status(try_call_service(&app, TestRequest::get().uri(uri).to_request()).await));
+        }
+        release_tx.send(()).ok();
+        refresh.join().expect("refresh");
+        statuses.push(status(try_call_service(&app, TestRequest::get().uri("/api/tbm/network").to_request()).await));
+        // The abandoned handlers let go of the cache once they had their copy
+        let released = (0..50).any(|_| {
+            thread::sleep(Duration::from_millis(20));
+            cache_after.try_lock().is_ok()
+        });
+        assert!(released, "an abandoned handler still holds the cache");
+        statuses
+    });
+
+    assert_eq!(statuses, [
+        StatusCode::GATEWAY_TIMEOUT,
+        StatusCode::GATEWAY_TIMEOUT,
+        StatusCode::GATEWAY_TIMEOUT,
+        StatusCode::GATEWAY_TIMEOUT,
+        StatusCode::OK,
+    ]);
+}
+
+#[test]
+fn cors_policy_keeps_admin_routes_to_their_origins() {
+    let mock = MockUpstreams::start("cors");
+    let state = app_state(mock.load());
+    let policy = CorsConfig { allowed_origins: vec!["https://maps.example.org".to_string()], ..CorsConfig::default() };
+
+    let statuses = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).wrap(cors(Arc::new(policy))).configure(api_routes)).await;
+        let mut statuses = Vec::new();
+        for (origin, method, path) in [
+            ("https://maps.example.org", "GET", "/api/tbm/lines"),
+            ("https://elsewhere.example.org", "GET", "/api/tbm/lines"),
+            ("https://maps.example.org", "DELETE", "/api/tbm/lines"),
+            ("https://maps.example.org", "POST", "/api/tbm/refresh"),
+            ("http://localhost:8080", "POST", "/api/tbm/refresh"),
+        ] {
+            let request = TestRequest::default().method(actix_web::http::Method::OPTIONS).uri(path)
+                .insert_header(("Host", "localhost:8080"))
+                .insert_header(("Origin", origin))
+                .insert_header(("Access-Control-Request-Method", method))
+                .to_request();
+            let response = call_service(&app, request).await;
+            let allowed_origin = response.headers().get("access-control-allow-origin")
+                .and_then(|h| h.to_str().ok()).map(String::from);
+            statuses.push((response.status().as_u16(), allowed_origin));
+        }
+        statuses
+    });
+
+    assert_eq!(statuses[0], (200, Some("https://maps.example.org".to_string())));
+    assert_eq!(statuses[1].0, 400, "unlisted origin");
+    assert_eq!(statuses[2].0, 400, "method outside the public policy");
+    assert_eq!(statuses[3].0, 400, "public origins cannot reach the admin routes");
+    assert_eq!(statuses[4], (200, Some("http://localhost:8080".to_string())), "same origin");
+}
+
+#[test]
+fn siri_stop_monitoring_lists_the_merged_departures() {
+    let mock = MockUpstreams::start("siri");
+    let state = app_state(mock.load());
+
+    let responses = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let mut responses = Vec::new();
+        for query in [
+            "MonitoringRef=bordeaux:StopPoint:BP:3673:LOC",
+            "MonitoringRef=3673&LineRef=bordeaux:Line:1:LOC",
+            "MonitoringRef=3673&MaximumStopVisits=1",
+            "MonitoringRef=9999",
+            "",
+        ] {
+            let uri = format!("/api/siri/2.0/stop-monitoring.json?{}", query);
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            let status = response.status().as_u16();
+            responses.push((status, read_body_json::<Value, _>(response).await));
+        }
+        responses
+    });
+    let delivery = |body: &Value| body["Siri"]["ServiceDelivery"]["StopMonitoringDelivery"][0].clone();
+    let visits = |body: &Value| delivery(body)["MonitoredStopVisit"].as_array().cloned().unwrap_or_default();
+    let lines = |body: &Value| -> Vec<String> {
+        visits(body).iter()
+            .map(|visit| visit["MonitoredVehicleJourney"]["LineRef"]["value"].as_str().unwrap_or_default().to_string())
+            .collect()
+    };
+
+    let (status, all) = &responses[0];
+    assert_eq!(*status, 200);
+    assert_eq!(delivery(all)["Status"], true);
+    let call = &visits(all)[0]["MonitoredVehicleJourney"]["MonitoredCall"];
+    assert_eq!(call["StopPointRef"]["value"], "bordeaux:StopPoint:BP:3673:LOC");
+    assert_eq!(call["StopPointName"][0]["value"], "Hôtel de Ville");
+    assert!(call["AimedDepartureTime"].as_str().is_some_and(|time| time.ends_with("+01:00") || time.ends_with("+02:00")));
+    assert!(call.get("ExpectedDepartureTime").is_none_or(Value::is_string), "unknown times are left out");
+    assert!(lines(all).contains(&"59".to_string()) && lines(all).contains(&"1".to_string()));
+
+    assert!(lines(&responses[1].1).iter().all(|line| line == "1"), "LineRef filters the visits");
+    assert_eq!(visits(&responses[2].1).len(), 1);
+    assert_eq!((responses[3].0, delivery(&responses[3].1)["Status"].clone()), (404, Value::Bool(false)));
+    assert_eq!(responses[4].0, 400);
+}
+
+#[test]
+fn vector_tiles_carry_stops_and_line_shapes() {
+    let mock = MockUpstreams::start("tiles");
+    let state = app_state(mock.load());
+    let tiles = state.tiles.clone();
+
+    // Tile of Quinconces at zoom 14
+    let (lat, lon): (f64, f64) = (44.8449, -0.5736);
+    let world = 2f64.powi(14);
+    let x = ((lon + 180.0) / 360.0 * world) as u32;
+    let y = ((1.0 - (lat.to_radians().tan() + 1.0 / lat.to_radians().cos()).ln() / std::f64::consts::PI) / 2.0 * world) as u32;
+    let uri = format!("/tiles/14/{}/{}.mvt", x, y);
+
+    let (first, second, etag, not_modified, invalid) = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("content-type").and_then(|v| v.to_str().ok()), Some("application/vnd.mapbox-vector-tile"));
+        let etag = response.headers().get("etag").and_then(|v| v.to_str().ok()).map(String::from).expect("etag");
+        let first = actix_web::test::read_body(response).await;
+        let second = actix_web::test::read_body(call_service(&app, TestRequest::get().uri(&uri).to_request()).await).await;
+        let not_modified = call_service(&app, TestRequest::get().uri(&uri).insert_header(("If-None-Match", etag.clone())).to_request()).await.status();
+        let invalid = call_service(&app, TestRequest::get().uri("/tiles/3/8/0.mvt").to_request()).await.status();
+        (first, second, etag, not_modified, invalid)
+    });
+    assert_eq!(first, second, "cached tile served again");
+    assert_eq!((not_modified, invalid), (StatusCode::NOT_MODIFIED, StatusCode::BAD_REQUEST));
+    assert_eq!(tiles.get(&etag, &TileId { z: 14, x, y }).as_deref(), Some(first.as_ref()));
+
+    let tile = vector_tiles::Tile::decode(first.as_ref()).expect("vector tile");
+    let layer_names: Vec<&str> = tile.layers.iter().map(|layer| layer.name.as_str()).collect();
+    assert_eq!(layer_names, ["stops", "shapes"]);
+    // (key, value) pairs of every feature of a layer
+    let attributes = |layer: &vector_tiles::Layer| -> Vec<HashMap<String, String>> {
+        layer.features.iter()
+            .map(|feature| feature.tags.chunks(2)
+                .map(|tag| {
+                    let value = &layer.values[tag[1] as usize];
+                    let value = value.string_value.clone().or_else(|| value.uint_value.map(|v| v.to_string())).unwrap_or_default();
+                    (layer.keys[tag[0] as usize].clone(), value)
+                })
+                .collect())
+            .collect()
+    };
+
+    let stops = attributes(&tile.layers[0]);
+    assert!(tile.layers[0].features.iter().all(|f| f.geom_type == Some(vector_tiles::POINT)));
+    let quinconces = stops.iter().find(|stop| stop["stop_id"] == "3671").expect("Quinconces in its tile");
+    assert_eq!(quinconces["name"], "Quinconces");
+
+    let shapes = attributes(&tile.layers[1]);
+    assert!(tile.layers[1].features.iter().all(|f| f.geom_type == Some(vector_tiles::LINESTRING) && f.geometry.len() >= 6));
+    let tram = shapes.iter().find(|shape| shape.get("line_code").map(String::as_str) == Some("A")).expect("tram A shape");
+    assert!(tram["color"].starts_with('#') && tram.contains_key("operator"));
+}
+
+/// CRC-32 of PNG chunks (ISO 3309), bit by bit
+fn png_crc(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 })
+    })
+}
+
+#[test]
+fn line_maps_are_valid_png_images() {
+    let mock = MockUpstreams::start("static_map");
+    let state = app_state(mock.load());
+    let images = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let mut images = Vec::new();
+        for uri in ["/api/tbm/line/A/map.png?width=320&height=200", "/api/tbm/line/A/map.png?width=10&height=5000"] {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(response.headers().get("content-type").and_then(|h| h.to_str().ok()), Some("image/png"));
+            images.push(actix_web::test::read_body(response).await.to_vec());
+        }
+        images
+    });
+
+    let mut sizes = Vec::new();
+    for png in &images {
+        assert_eq!(png[..8], [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A], "signature");
+        // Chunks: length, type, data, CRC of type and data
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(rest[..4].try_into().expect("length")) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + length]);
+            let crc = u32::from_be_bytes(rest[8 + length..12 + length].try_into().expect("crc"));
+            assert_eq!(crc, png_crc(&rest[4..8 + length]), "CRC of {}", String::from_utf8_lossy(kind));
+            chunks.push((String::from_utf8_lossy(kind).into_owned(), data.to_vec()));
+            rest = &rest[12 + length..];
+        }
+        assert_eq!(chunks.iter().map(|(kind, _)| kind.as_str()).collect::<Vec<_>>(), ["IHDR", "IDAT", "IEND"]);
+
+        let ihdr = &chunks[0].1;
+        let (width, height) = (u32::from_be_bytes(ihdr[..4].try_into().expect("width")), u32::from_be_bytes(ihdr[4..8].try_into().expect("height")));
+        assert_eq!(ihdr[8..], [8, 2, 0, 0, 0], "8-bit truecolor, not interlaced");
+        sizes.push((width, height));
+
+        // One filter byte then the RGB pixels of each scanline, with tram A's color drawn
+        let mut raw = Vec::new();
+        std::io::Read::read_to_end(&mut flate2::read::ZlibDecoder::new(chunks[1].1.as_slice()), &mut raw).expect("zlib data");
+        let row_len = width as usize * 3 + 1;
+        assert_eq!(raw.len(), row_len * height as usize);
+        assert!(raw.chunks(row_len).all(|row| row[0] == 0));
+        assert!(raw.chunks(row_len).any(|row| row[1..].chunks(3).any(|pixel| pixel == [0x81, 0x49, 0x97])));
+    }
+    assert_eq!(sizes, [(320, 200), (64, 2048)], "sizes are clamped to 64-2048");
+}
+
+#[test]
+fn grafana_series_come_from_refresh_samples() {
+    let mock = MockUpstreams::start("grafana");
+    let cache = mock.load();
+    let mut history = HistoryStore::default();
+    history.record_sample(&cache.to_network_data());
+    history.record_alerts(&cache.alerts);
+
+    let names = grafana::search(&history, "");
+    assert_eq!(names[..2], ["vehicles", "delay"]);
+    assert!(names.contains(&"vehicles/TBM/A".to_string()), "{:?}", names);
+    assert_eq!(grafana::search(&history, "tbm/1"), ["vehicles/TBM/1", "delay/TBM/1"]);
+
+    let request: grafana::QueryRequest = serde_json::from_value(serde_json::json!({
+        "range": { "from": "2000-01-01T00:00:00Z", "to": "2100-01-01T00:00:00Z" },
+        "targets": [{ "target": "vehicles" }, { "target": "vehicles/TBM/A" }, { "target": "delay" }],
+        "maxDataPoints": 100,
+    })).expect("SimpleJSON query");
+    let (from, to) = grafana::parse_range(&request.range).expect("range");
+    let series = grafana::query(&history, &request, from, to);
+    let values: Vec<Vec<f64>> = series.iter().map(|s| s.datapoints.iter().map(|p| p.0).collect()).collect();
+    assert_eq!(values, [vec![2.0], vec![1.0], vec![]]); // The fixture vehicles report no delay
+
+    let request: grafana::AnnotationRequest = serde_json::from_value(serde_json::json!({
+        "range": { "from": "2000-01-01T00:00:00Z", "to": "2100-01-01T00:00:00Z" },
+        "annotation": { "name": "Alerts", "query": "" },
+    })).expect("SimpleJSON annotation query");
+    let annotations = grafana::annotations(&history, &request, from, to);
+    assert_eq!(annotations.len(), history.alerts.len());
+}
+
+#[test]
+fn stale_vehicles_are_moved_along_their_trip() {
+    let mock = MockUpstreams::start("interpolation");
+    let cache = mock.load();
+    let at = |time: &str| time_format::gtfs_datetime(chrono::NaiveDate::from_ymd_opt(2026, 5, 4).expect("date"), time)
+        .expect("time").timestamp();
+
+    // Tram A-1 runs 2 minutes late (its trip update): Quinconces 08:02, Grand Théâtre 08:04,
+    // Hôtel de Ville 08:07:30. Fixed at 08:03 between the first two, it is past Grand Théâtre
+    // by 08:05:45.
+    let mut vehicles = NVTModels::vehicles_with_progress(&cache);
+    for vehicle in &mut vehicles {
+        let fixed_at = if vehicle.vehicle.vehicle_id == "V-A-1" { at("08:03:00") } else { at("08:05:45") };
+        Cow::to_mut(&mut vehicle.vehicle).timestamp = Some(fixed_at);
+    }
+    interpolation::apply(&mut vehicles, &cache, at("08:05:45"));
+
+    let tram = vehicles.iter().find(|v| v.vehicle.vehicle_id == "V-A-1").expect("tram A vehicle");
+    let interpolated = tram.interpolated.as_ref().expect("stale fix interpolated");
+    assert_eq!((interpolated.gps_latitude, interpolated.gps_age_secs), (f64::from(44.8430f32), 165));
+    assert_eq!(interpolated.based_on, "trip_update");
+    assert!(44.8378 < tram.vehicle.latitude && tram.vehicle.latitude < 44.8421, "{}", tram.vehicle.latitude);
+    assert!(tram.vehicle.bearing.is_some_and(|bearing| (180.0..270.0).contains(&bearing)), "{:?}", tram.vehicle.bearing);
+
+    let fresh = vehicles.iter().find(|v| v.vehicle.vehicle_id == "V-1-1").expect("bus 1 vehicle");
+    assert!(fresh.interpolated.is_none(), "fresh fixes are left alone");
+    assert_eq!((fresh.vehicle.latitude, fresh.vehicle.longitude), (f64::from(44.8447f32), f64::from(-0.5735f32)));
+}
+
+#[test]
+fn bike_stations_join_information_and_status() {
+    let mock = MockUpstreams::start("gbfs");
+    let mut cache = mock.load();
+
+    // Station 3 has no status and station 9 no information, both are left out
+    let stations: Vec<(&str, &str, u32, bool)> = cache.bike_share.stations.iter()
+        .map(|station| (station.station_id.as_str(), station.name.as_str(), station.bikes_available, station.is_renting))
+        .collect();
+    assert_eq!(stations, [("1", "Quinconces", 12, true), ("2", "Grand Théâtre", 0, false)]);
+    let quinconces = cache.bike_share.station("1").expect("station 1");
+    assert_eq!((quinconces.capacity, quinconces.docks_available), (Some(40), Some(27)));
+    assert_eq!(quinconces.last_reported, Some(1777874430));
+    assert_eq!(cache.bike_share.last_updated, 1777874460);
+
+    // A new status is fetched on refresh, and kept when the feed fails
+    mock.respond("/gbfs/station_status.json", 200, br#"{"last_updated": 1777874520, "ttl": 60, "data": {"stations": [
+        {"station_id": "1", "num_bikes_available": 11, "num_docks_available": 28, "is_renting": 1, "is_returning": 1, "last_reported": 1777874500}
+    ]}}"#.to_vec());
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh");
+    mock.respond("/gbfs/station_status.json", 503, b"Service Unavailable".to_vec());
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh tolerates a failing GBFS feed");
+    let station_ids: Vec<&str> = cache.bike_share.stations.iter().map(|station| station.station_id.as_str()).collect();
+    assert_eq!(station_ids, ["1"]);
+    assert_eq!(cache.bike_share.station("1").map(|station| station.bikes_available), Some(11));
+
+    let state = app_state(cache);
+    actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+
+        let body: Value = read_body_json(call_service(&app, TestRequest::get().uri("/api/tbm/bikes?bbox=-0.58,44.84,-0.57,44.85").to_request()).await).await;
+        assert_eq!(body["data"][0]["station_id"], "1");
+        assert_eq!(body["data"][0]["docks_available"], 28);
+
+        let response = call_service(&app, TestRequest::get().uri("/api/tbm/bikes?bbox=-0.56,44.82,-0.55,44.83").to_request()).await;
+        assert_eq!(read_body_json::<Value, _>(response).await["data"].as_array().map(Vec::len), Some(0));
+
+        let response = call_service(&app, TestRequest::get().uri("/api/tbm/bikes/1").to_request()).await;
+        assert_eq!(read_body_json::<Value, _>(response).await["data"]["name"], "Quinconces");
+        let response = call_service(&app, TestRequest::get().uri("/api/tbm/bikes/2").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    });
+}
+
+#[test]
+fn polylines_match_the_reference_encoding() {
+    // The worked example of Google's format documentation
+    let points: Vec<ShapePoint> = [(38.5, -120.2), (40.7, -120.95), (43.252, -126.453)].iter().enumerate()
+        .map(|(i, &(latitude, longitude))| ShapePoint { latitude, longitude, sequence: i as u32 })
+        .collect();
+    assert_eq!(polyline::encode(&points), "_p~iF~ps|U_ulLnnqC_mqNvxq`@");
+    assert_eq!(polyline::encode(&[]), "");
+}
+
+#[test]
+fn simplification_drops_points_within_the_tolerance() {
+    // A straight line along the meridian with a 5 m wiggle, then a 500 m kink
+    let points: Vec<ShapePoint> = [(0.0, 0.0), (0.0001, 0.0), (0.0002, 0.000045), (0.0003, 0.0), (0.0004, 0.0), (0.0008, 0.0045), (0.0012, 0.0)]
+        .iter().enumerate()
+        .map(|(i, &(dlat, dlon))| ShapePoint { latitude: 44.84 + dlat * 10.0, longitude: -0.57 + dlon, sequence: i as u32 })
+        .collect();
+
+    let simplified = polyline::simplify(&points, 10.0);
+    let sequences: Vec<u32> = simplified.iter().map(|point| point.sequence).collect();
+    assert_eq!(sequences, [0, 4, 5, 6]);
+    // The wiggle stays under a 1 m tolerance, and the ends are always kept
+    assert_eq!(polyline::simplify(&points, 1.0).len(), points.len());
+    assert_eq!(polyline::simplify(&points, 1e9).len(), 2);
+    assert!(polyline::zoom_tolerance(10) > polyline::zoom_tolerance(16));
+}
+
+#[test]
+fn wfs_describes_the_network_layer() {
+    let mock = MockUpstreams::start("wfs");
+    let network = mock.load().to_network_data();
+    let params = |query: &[(&str, &str)]| -> HashMap<String, String> {
+        query.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    };
+
+    let capabilities = wfs::capabilities("http://localhost:8080/api/wfs", &network);
+    assert!(capabilities.contains("<wfs:Name>nvt:stops</wfs:Name>"), "{}", capabilities);
+    assert!(capabilities.contains("<wfs:Name>nvt:lines</wfs:Name>"), "{}", capabilities);
+    assert!(capabilities.contains(r#"<ows:Get xlink:href="http://localhost:8080/api/wfs"/>"#), "{}", capabilities);
+
+    // Latitude first with the EPSG:4326 URN, longitude first otherwise
+    let urn = wfs::parse_request(&params(&[("request", "GetFeature"), ("typeNames", "nvt:stops"),
+        ("bbox", "44.835,-0.58,44.844,-0.574,urn:ogc:def:crs:EPSG::4326")])).expect("GetFeature");
+    let plain = wfs::parse_request(&params(&[("REQUEST", "getfeature"), ("TYPENAME", "stops"),
+        ("BBOX", "-0.58,44.835,-0.574,44.844")])).expect("GetFeature");
+    assert_eq!(urn, plain);
+
+    let schema = wfs::describe_feature_type("http://localhost:8080/api/wfs", &[wfs::FeatureType::Lines]);
+    assert!(schema.contains(r#"<xsd:element name="line_code" type="xsd:string" minOccurs="0"/>"#), "{}", schema);
+    assert!(!schema.contains("stop_id"), "{}", schema);
+
+    let error = wfs::parse_request(&params(&[("request", "GetFeature"), ("typeNames", "nvt:stops"),
+        ("bbox", "6400000,4100000,6500000,4200000,EPSG:2154")])).expect_err("Lambert-93 bbox");
+    assert_eq!(error.code, "InvalidParameterValue");
+    assert!(error.report().contains(r#"locator="bbox""#));
+    let error = wfs::parse_request(&params(&[("request", "Transaction")])).expect_err("Transaction");
+    assert_eq!(error.code, "OperationNotSupported");
+}
+
+#[test]
+fn graphql_resolves_nested_relations() {
+    let mock = MockUpstreams::start("graphql");
+    let cache = mock.load();
+    let run = |query: &str, variables: Value| graphql::execute(&graphql::GraphQLRequest {
+        query: query.to_string(),
+        variables: variables.as_object().cloned(),
+        operation_name: None,
+    }, &cache);
+
+    let response = run(r#"
+        query StopBoard($id: String!) {
+            stop(id: $id) {
+                name
+                lines { code operator vehicles { id nextStop { id } line { code } } }
+                here: vehicles { trip { headsign stopTimes { stopSequence stop { id } } } }
+            }
+        }"#, serde_json::json!({ "id": "3672" }));
+    assert!(response.get("errors").is_none(), "{}", response);
+    let stop = &response["data"]["stop"];
+    let line_a = stop["lines"].as_array().expect("lines").iter()
+        .find(|line| line["code"] == "A").expect("line A serves 3672");
+    assert_eq!(line_a["vehicles"][0]["id"], "V-A-1");
+    assert_eq!(line_a["vehicles"][0]["nextStop"]["id"], "3673");
+    assert_eq!(line_a["vehicles"][0]["line"]["code"], "A");
+    let sequences: Vec<&Value> = stop["here"][0]["trip"]["stopTimes"].as_array().expect("stop times").iter()
+        .map(|stop_time| &stop_time["stopSequence"]).collect();
+    assert_eq!(sequences, [1, 2, 3]);
+
+    // A failing field is null, with its error and path; the rest of the query still resolves
+    let response = run("{ alerts(severityMin: 1) { id __typename } stop(id: \"3672\") { id platform } }", Value::Null);
+    assert!(response["data"]["alerts"].as_array().is_some_and(|alerts| alerts.iter().any(|a| a["id"] == "TBM-1")), "{}", response);
+    assert_eq!(response["data"]["alerts"][0]["__typename"], "Alert");
+    assert_eq!(response["data"]["stop"]["platform"], Value::Null);
+    assert_eq!(response["errors"][0]["path"], serde_json::json!(["stop", "platform"]));
+
+    let response = run("{ stop(id: \"3672\") { ...Board } }", Value::Null);
+    assert!(response.get("data").is_none(), "{}", response);
+    assert_eq!(response["errors"][0]["message"], "Fragments are not supported");
+
+    // Also served at /graphql, where GraphQL clients look by default
+    let responses = call_api(cache, &["/graphql?query=%7B%20stop%28id%3A%20%223672%22%29%20%7B%20name%20%7D%20%7D", "/api/graphql?query=%7B%20stop%28id%3A%20%223672%22%29%20%7B%20name%20%7D%20%7D"]);
+    assert_eq!(responses[0].1["data"]["stop"]["name"], "Grand Théâtre");
+    assert_eq!(responses[0].1, responses[1].1);
+}
+
+#[test]
+fn refresh_merges_new_real_time_data() {
+    let mock = MockUpstreams::start("refresh");
+    let mut cache = mock.load();
+
+    // The tram moves one stop on
+    mock.respond(VEHICLES_PATH, 200, feed(vec![
+        vehicle("V-A-1", "A-1", "59", "Le Haillan Rostand", (44.8378, -0.5795), ("3673", 3)),
+    ]));
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh");
+
+    let details = NVTModels::get_vehicle_details("V-A-1", &cache).expect("vehicle still tracked");
+    assert_eq!(details.current_stop.map(|s| s.stop_name).as_deref(), Some("Hôtel de Ville"));
+    assert_eq!(details.previous_stop.map(|s| s.stop_name).as_deref(), Some("Grand Théâtre"));
+    assert!(details.next_stop.is_none(), "3673 is the last stop of A-1");
+    assert!(NVTModels::get_vehicle_details("V-1-1", &cache).is_none(), "vehicles gone from the feed are dropped");
+
+    // SNCF real-time data is merged in on refresh
+    let alert_ids: Vec<&str> = cache.alerts.iter().map(|a| a.id.as_str()).collect();
+    assert_eq!(alert_ids, ["TBM-1", "SNCF-1"]);
+    assert_eq!(cache.trip_updates.len(), 2);
+}
+
+#[test]
+fn feed_vehicle_positions_are_tagged_and_kept_when_their_feed_fails() {
+    let mock = MockUpstreams::start("feed-vehicles");
+    let mut cache = mock.load();
+    mock.respond("/sncf/vehicles", 200, feed(vec![
+        vehicle("TER-864201", "OCESN864201", "OCESN:Line:TER-43", "Libourne", (44.8256, -0.5567), ("StopPoint:OCETrain TER-87584052", 1)),
+    ]));
+    cache.upstreams.feed_mut("SNCF").expect("SNCF feed").rt_urls.push(format!("{}/sncf/vehicles", mock.base_url));
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh");
+
+    let sources: Vec<(&str, Option<&str>)> = cache.real_time.iter()
+        .map(|v| (v.vehicle_id.as_str(), v.source.as_deref()))
+        .collect();
+    assert_eq!(sources, [("V-A-1", Some("TBM")), ("V-1-1", Some("TBM")), ("TER-864201", Some("SNCF"))]);
+    let network = cache.to_network_data();
+    let line = network.lines.iter().find(|line| line.route_id == "OCESN:Line:TER-43").expect("TER 43");
+    assert_eq!(line.real_time.iter().map(|v| v.vehicle_id.as_str()).collect::<Vec<_>>(), ["TER-864201"]);
+
+    // The train keeps its last position while its feed is down, the TBM vehicles move on
+    mock.respond("/sncf/vehicles", 500, Vec::new());
+    mock.respond(VEHICLES_PATH, 200, feed(vec![
+        vehicle("V-A-1", "A-1", "59", "Le Haillan Rostand", (44.8378, -0.5795), ("3673", 3)),
+    ]));
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh");
+    let ids: Vec<&str> = cache.real_time.iter().map(|v| v.vehicle_id.as_str()).collect();
+    assert_eq!(ids, ["V-A-1", "TER-864201"]);
+    assert!(cache.source_freshness["SNCF"].real_time.as_ref().is_some_and(|feeds| feeds.error.is_some()));
+}
+
+#[test]
+fn vehicles_since_lists_changes_and_removals() {
+    let mock = MockUpstreams::start("vehicles-since");
+    let mut cache = mock.load();
+    assert!(cache.vanished_vehicles.is_empty());
+
+    // The tram reports again, another one last reported before `since`, and the bus is gone
+    let reported_at = |mut entity: FeedEntity, timestamp: u64| {
+        if let Some(position) = entity.vehicle.as_mut() {
+            position.timestamp = Some(timestamp);
+        }
+        entity
+    };
+    mock.respond(VEHICLES_PATH, 200, feed(vec![
+        reported_at(vehicle("V-A-1", "A-1", "59", "Le Haillan Rostand", (44.8378, -0.5795), ("3673", 3)), 2000),
+        reported_at(vehicle("V-A-2", "A-1", "59", "Le Haillan Rostand", (44.8400, -0.5750), ("3671", 1)), 1000),
+    ]));
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh");
+
+    let responses = call_api(cache, &["/api/tbm/vehicles?since=1500"]);
+    let delta = &responses[0].1["data"];
+    let ids: Vec<&str> = delta["vehicles"].as_array().expect("vehicles").iter()
+        .filter_map(|v| v["vehicle_id"].as_str())
+        .collect();
+    assert_eq!(ids, ["V-A-1"]);
+    assert_eq!(delta["removed"], serde_json::json!(["V-1-1"]));
+    assert_eq!(delta["next_since"], 2000);
+}
+
+#[test]
+fn unannounced_disruptions_become_derived_alerts() {
+    let mock = MockUpstreams::start("derived-alerts");
+    let mut cache = mock.load();
+    assert!(cache.alerts.iter().all(|alert| !alert.derived), "fixture delays are under the threshold");
+
+    // Line 1's trip is cancelled and the tram runs 21 minutes late, with no TBM alert left
+    let mut cancelled = trip_update("1-1", "1", "3671", 0);
+    if let Some(update) = cancelled.trip_update.as_mut() {
+        update.trip.schedule_relationship = Some(ScheduleRelationship::Canceled as i32);
+    }
+    mock.respond(ALERTS_PATH, 200, feed(vec![]));
+    mock.respond(TRIP_UPDATES_PATH, 200, feed(vec![cancelled, trip_update("A-1", "59", "3673", 1260)]));
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh");
+
+    let derived: Vec<(&str, &str, bool)> = cache.alerts.iter()
+        .map(|alert| (alert.id.as_str(), alert.text.as_str(), alert.derived))
+        .collect();
+    assert_eq!(derived, [
+        ("SNCF-1", "Trafic perturbé Bordeaux - Libourne", false),
+        ("derived:1-1", "1 08:10 to Aéroport cancelled", true),
+        ("derived:A-1", "A 08:00 to Le Haillan Rostand running 21 min late", true),
+    ]);
+    assert!(cache.alert_stops["derived:A-1"].contains(&"3672".to_string()), "derived alerts reach the route's stops");
+
+    // Once TBM publishes an alert on the route, the derived one goes away
+    mock.respond(ALERTS_PATH, 200, feed(vec![alert("TBM-2", "Retards ligne A", &["59"], &[])]));
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh");
+    let ids: Vec<&str> = cache.alerts.iter().map(|alert| alert.id.as_str()).collect();
+    assert_eq!(ids, ["TBM-2", "SNCF-1", "derived:1-1"]);
+}
+
+#[test]
+fn alert_stream_sends_only_changes() {
+    let mock = MockUpstreams::start("alert-stream");
+    let mut cache = mock.load();
+    let changes = |previous: &[_], current: &[_]| -> Vec<(AlertChange, String)> {
+        alert_stream::diff(previous, current).into_iter().map(|event| (event.change, event.alert.id)).collect()
+    };
+    assert!(changes(&cache.alerts, &cache.alerts).is_empty());
+
+    // TBM-1 is reworded and SNCF-1 shows up
+    let initial = cache.alerts.clone();
+    mock.respond(ALERTS_PATH, 200, feed(vec![
+        alert("TBM-1", "Travaux place de la Comédie prolongés", &["59"], &["3672"]),
+    ]));
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh");
+    assert_eq!(changes(&initial, &cache.alerts), [
+        (AlertChange::Updated, "TBM-1".to_string()),
+        (AlertChange::New, "SNCF-1".to_string()),
+    ]);
+
+    let previous = cache.alerts.clone();
+    mock.respond(ALERTS_PATH, 200, feed(vec![]));
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh");
+    let events = alert_stream::diff(&previous, &cache.alerts);
+    assert_eq!(events.len(), 1);
+    let message = String::from_utf8(events[0].to_sse().to_vec()).expect("UTF-8");
+    assert!(message.starts_with("event: expired\nid: TBM-1\ndata: {"), "{}", message);
+    assert!(message.contains("prolongés") && message.ends_with("}\n\n"), "{}", message);
+}
+
+#[test]
+fn static_feeds_are_downloaded_again_past_their_max_age() {
+    let mock = MockUpstreams::start("cache-ages");
+    let mut cache = mock.load();
+    let recolored: Vec<(String, Vec<u8>)> = gtfs_files("tbm").into_iter()
+        .map(|(name, contents)| match name.as_str() {
+            "routes.txt" => (name, String::from_utf8_lossy(&contents).replace("814997", "123456").into_bytes()),
+            _ => (name, contents),
+        })
+        .collect();
+    mock.respond("/tbm/gtfs.zip", 200, zip_files(&recolored));
+
+    // Within its age the saved feed is reused
+    NVTModels::refresh_static_data(&mut cache).expect("static refresh");
+    assert_eq!(cache.tbm_gtfs_cache.routes.get("59").map(String::as_str), Some("814997"));
+
+    cache.upstreams.feed_mut("TBM").expect("TBM feed").max_age_days = 0;
+    NVTModels::refresh_static_data(&mut cache).expect("static refresh");
+    assert_eq!(cache.tbm_gtfs_cache.routes.get("59").map(String::as_str), Some("123456"));
+}
+
+#[test]
+fn unchanged_feed_downloads_keep_the_parsed_copy() {
+    let mock = MockUpstreams::start("feed-info");
+    let versioned = |version: &str, color: &str| -> Vec<u8> {
+        let mut files: Vec<(String, Vec<u8>)> = gtfs_files("tbm").into_iter()
+            .map(|(name, contents)| match name.as_str() {
+                "routes.txt" => (name, String::from_utf8_lossy(&contents).replace("814997", color).into_bytes()),
+                _ => (name, contents),
+            })
+            .collect();
+        files.push(("feed_info.txt".to_string(), format!(
+            "feed_publisher_name,feed_publisher_url,feed_lang,feed_start_date,feed_end_date,feed_version\n\
+             Bordeaux Métropole,https://www.tbm.example,fr,20260101,20261231,{}\n", version).into_bytes()));
+        zip_files(&files)
+    };
+    mock.respond("/tbm/gtfs.zip", 200, versioned("2026.1", "814997"));
+    let mut cache = mock.load();
+    let info = cache.tbm_gtfs_cache.feed_info.clone().expect("feed info");
+    assert_eq!((info.publisher_name.as_str(), info.version.as_deref(), info.end_date.as_deref()), ("Bordeaux Métropole", Some("2026.1"), Some("20261231")));
+    cache.upstreams.feed_mut("TBM").expect("TBM feed").max_age_days = 0;
+    let color = |cache: &CachedNetworkData| cache.tbm_gtfs_cache.routes.get("59").cloned();
+
+    // Same feed_version: the new colors are not parsed
+    mock.respond("/tbm/gtfs.zip", 200, versioned("2026.1", "123456"));
+    NVTModels::refresh_static_data(&mut cache).expect("static refresh");
+    assert_eq!(color(&cache).as_deref(), Some("814997"));
+
+    // Not modified by its ETag: nothing is downloaded
+    mock.respond("/tbm/gtfs.zip", 200, versioned("2026.2", "123456"));
+    mock.tag("/tbm/gtfs.zip", "\"v2\"");
+    NVTModels::refresh_static_data(&mut cache).expect("static refresh");
+    assert_eq!(color(&cache).as_deref(), Some("123456"), "the first tagged download is parsed");
+    assert_eq!(cache.tbm_gtfs_cache.etag.as_deref(), Some("\"v2\""));
+    mock.respond("/tbm/gtfs.zip", 200, versioned("2026.3", "654321"));
+    NVTModels::refresh_static_data(&mut cache).expect("static refresh");
+    assert_eq!(color(&cache).as_deref(), Some("123456"), "answered 304");
+
+    let responses = call_api(cache, &["/api/tbm/stats"]);
+    let tbm = responses[0].1["data"]["sources"].as_array().expect("sources").iter()
+        .find(|source| source["source"] == "TBM")
+        .cloned()
+        .expect("TBM stats");
+    assert_eq!(tbm["feed_info"]["version"], "2026.2");
+}
+
+#[test]
+fn configured_feeds_are_sources_of_their_own_under_their_name() {
+    let mock = MockUpstreams::start("feeds");
+    let mut upstreams = mock.upstreams();
+    upstreams.feeds.push(GtfsFeedConfig {
+        name: "Libournais".to_string(),
+        static_url: format!("{}/naq/gtfs.zip", mock.base_url),
+        rt_urls: vec![format!("{}/sncf/alerts", mock.base_url)],
+        max_age_days: 30,
+        id_prefix: Some("LIB:".to_string()),
+        mode: None,
+    });
+    let mut cache = NVTModels::initialize_cache_with(upstreams).expect("cache from fixtures");
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh");
+
+    let feed = &cache.feeds["Libournais"];
+    let stop_ids: Vec<&str> = feed.stops.iter().map(|stop| stop.stop_id.as_str()).collect();
+    assert!(stop_ids.contains(&"LIB:CALIBUS:1"), "{:?}", stop_ids);
+    assert!(!stop_ids.contains(&"LIB:TBM:3671"), "TBM stops of the feed are left to TBM");
+    assert!(cache.transgironde_stops.iter().all(|stop| !stop.stop_id.starts_with("LIB:")), "not folded into New-Aquitaine");
+    let trip = &feed.gtfs_cache.trips["LIB:CAL-A-1"];
+    assert_eq!((trip.route_id.as_str(), trip.service_id.as_str()), ("LIB:CA_DU_LIBOURNAIS:Line:A", "LIB:CAL-WEEK"));
+    assert!(cache.trip_index.contains_key("LIB:CAL-A-1"));
+    assert!(cache.to_network_data().stops.iter().any(|stop| stop.stop_id == "LIB:CALIBUS:1"));
+    assert!(GTFSCache::cache_path(&cache.upstreams.cache_dir, "Libournais").exists(), "the feed is saved on its own");
+
+    // Its GTFS-RT feed is merged in with the same prefix
+    let alert_routes: Vec<&str> = cache.alerts.iter().flat_map(|alert| alert.route_ids.iter().map(String::as_str)).collect();
+    assert!(alert_routes.contains(&"OCESN:Line:TER-43") && alert_routes.contains(&"LIB:OCESN:Line:TER-43"), "{:?}", alert_routes);
+
+    // Stats, health and the loaded sources name it
+    let stats = NVTModels::get_cache_stats(&cache);
+    assert_eq!(stats.sources.iter().map(|source| source.source.as_str()).collect::<Vec<_>>(), ["TBM", "NewAquitaine", "SNCF", "Libournais"]);
+    assert_eq!(stats.sources[3].stops, feed.stops.len());
+    let health = NVTModels::get_health(&cache, &HealthConfig::default(), NVTModels::get_current_timestamp() as u64);
+    let libournais = &health.freshness["Libournais"];
+    assert!(libournais.static_age_secs.is_some() && libournais.real_time_age_secs.is_some(), "{:?}", health.freshness);
+    assert!(health.freshness["NewAquitaine"].real_time_age_secs.is_none());
+    assert!(cache.loaded_sources().contains(&"Libournais"));
+
+    // A standby gets it from the snapshot, and a refresh can name it
+    let snapshot = StaticSnapshot::from_cache(&cache);
+    assert_eq!(snapshot.sources.keys().map(String::as_str).collect::<Vec<_>>(), ["Libournais", "NewAquitaine", "SNCF"]);
+    let standby = snapshot.into_cache(DynamicSnapshot::from_cache(&cache), cache.upstreams.clone());
+    assert_eq!(standby.feeds["Libournais"].stops.len(), feed.stops.len());
+    assert_eq!(standby.transgironde_stops.len(), cache.transgironde_stops.len());
+    assert!(standby.trip_index.contains_key("LIB:CAL-A-1"));
+    assert_eq!(cache.upstreams.source_named("libournais").as_deref(), Some("Libournais"));
+    assert_eq!(cache.upstreams.sources(), ["TBM", "NewAquitaine", "SNCF", "Libournais"]);
+    NVTModels::refresh_static_sources(&mut cache, &["Libournais"]).expect("feed refresh");
+    assert!(cache.feeds["Libournais"].gtfs_cache.trips.contains_key("LIB:CAL-A-1"));
+}
+
+#[test]
+fn coach_feeds_tag_their_lines_with_their_operator_and_mode() {
+    let mock = MockUpstreams::start("coach-feed");
+    let coaches: Vec<(String, Vec<u8>)> = gtfs_files("naq").into_iter()
+        .map(|(name, contents)| match name.as_str() {
+            "agency.txt" => (name, String::from_utf8_lossy(&contents).replace("Calibus (Libourne)", "FlixBus").into_bytes()),
+            _ => (name, contents),
+        })
+        .collect();
+    mock.respond("/flixbus/gtfs.zip", 200, zip_files(&coaches));
+    let mut upstreams = mock.upstreams();
+    upstreams.feeds.push(GtfsFeedConfig {
+        name: "Flixbus".to_string(),
+        static_url: format!("{}/flixbus/gtfs.zip", mock.base_url),
+        rt_urls: Vec::new(),
+        max_age_days: 30,
+        id_prefix: Some("FLIX:".to_string()),
+        mode: Some(TransportMode::Coach),
+    });
+    let cache = NVTModels::initialize_cache_with(upstreams).expect("cache from fixtures");
+
+    let responses = call_api(cache, &["/api/tbm/lines?mode=coach"]);
+    let lines: Vec<(&str, &str, &str)> = responses[0].1["data"].as_array().expect("lines").iter()
+        .filter_map(|line| Some((line["route_id"].as_str()?, line["operator"].as_str()?, line["mode"].as_str()?)))
+        .collect();
+    assert_eq!(lines.len(), 2, "{:?}", lines);
+    assert!(lines.iter().all(|&(route_id, operator, mode)| route_id.starts_with("FLIX:") && operator == "FlixBus" && mode == "coach"), "{:?}", lines);
+}
+
+#[test]
+fn accessible_filters_keep_wheelchair_stops_and_trips() {
+    let mock = MockUpstreams::start("accessibility");
+    let mut files: Vec<(String, Vec<u8>)> = gtfs_files("naq").into_iter()
+        .map(|(name, contents)| {
+            let contents = String::from_utf8_lossy(&contents).to_string();
+            let contents = match name.as_str() {
+                "stops.txt" => "stop_id,stop_name,stop_lat,stop_lon,stop_code,stop_desc,location_type,parent_station,wheelchair_boarding,level_id\n\
+                    CALIBUS:0,Libourne Gare,44.9153,-0.2437,,,1,,1,\n\
+                    CALIBUS:1,Libourne Gare,44.9153,-0.2437,,,0,CALIBUS:0,,L0\n\
+                    CALIBUS:1E,Libourne Gare entrance,44.9151,-0.2439,,,2,CALIBUS:0,,L0\n\
+                    CALIBUS:2,Libourne Centre,44.9172,-0.2419,,,0,,2,\n\
+                    CALIBUS:3,Libourne Hôpital,44.9210,-0.2350,,,0,,0,\n".to_string(),
+                "trips.txt" => contents.replace("CAL-B-1,Libourne Hôpital,0,,1", "CAL-B-1,Libourne Hôpital,0,,2"),
+                "calendar.txt" => contents.replace("1,1,1,1,1,0,0", "1,1,1,1,1,1,1"),
+                _ => contents,
+            };
+            (name, contents.into_bytes())
+        })
+        .collect();
+    files.push(("levels.txt".to_string(), b"level_id,level_index,level_name\nL0,0,Street\n".to_vec()));
+    files.push(("pathways.txt".to_string(),
+        b"pathway_id,from_stop_id,to_stop_id,pathway_mode,is_bidirectional,length,traversal_time\nP1,CALIBUS:1E,CALIBUS:1,1,1,40,30\n".to_vec()));
+    mock.respond("/naq/gtfs.zip", 200, zip_files(&files));
+    let cache = mock.load();
+
+    let responses = call_api(cache, &[
+        "/api/tbm/stops",
+        "/api/tbm/stops?accessible=true",
+        "/api/tbm/stop/CALIBUS:1/departures?limit=10",
+        "/api/tbm/stop/CALIBUS:1/departures?limit=10&accessible=true",
+    ]);
+    let stops = responses[0].1["data"].as_array().expect("stops");
+    let gare = stops.iter().find(|stop| stop["stop_id"] == "CALIBUS:1").expect("Libourne Gare");
+    assert_eq!(gare["wheelchair_boarding"], true, "inherited from the station");
+    assert_eq!(gare["level"]["level_name"], "Street");
+    assert_eq!(gare["pathways"][0]["pathway_id"], "P1");
+    let centre = stops.iter().find(|stop| stop["stop_id"] == "CALIBUS:2").expect("Libourne Centre");
+    assert_eq!((centre["wheelchair_boarding"].clone(), centre["pathways"].clone()), (Value::from(false), serde_json::json!([])));
+
+    let mut accessible: Vec<&str> = responses[1].1["data"].as_array().expect("stops").iter()
+        .filter_map(|stop| stop["stop_id"].as_str())
+        .collect();
+    accessible.sort();
+    assert_eq!(accessible, ["CALIBUS:0", "CALIBUS:1", "CALIBUS:1E"], "the station and its platform and entrance");
+
+    let trips = |body: &Value| -> Vec<String> {
+        let mut trips: Vec<String> = body["data"].as_array().into_iter().flatten()
+            .filter_map(|departure| departure["trip_id"].as_str().map(String::from))
+            .collect();
+        trips.dedup();
+        trips
+    };
+    assert!(trips(&responses[2].1).contains(&"CAL-B-1".to_string()), "{}", responses[2].1);
+    assert!(!trips(&responses[3].1).is_empty());
+    assert!(trips(&responses[3].1).iter().all(|trip| trip == "CAL-A-1"), "{}", responses[3].1);
+    assert_eq!(responses[3].1["data"][0]["wheelchair_accessible"], true);
+}
+
+#[test]
+fn frequency_based_trips_repeat_over_their_windows() {
+    let mock = MockUpstreams::start("frequencies");
+    let mut files = gtfs_files("naq");
+    files.push(("frequencies.txt".to_string(),
+        b"trip_id,start_time,end_time,headway_secs,exact_times\nCAL-A-1,07:30:00,08:00:00,600,1\nCAL-A-1,23:50:00,24:10:00,1200,0\n".to_vec()));
+    mock.respond("/naq/gtfs.zip", 200, zip_files(&files));
+    let cache = mock.load();
+    assert_eq!(cache.transgironde_gtfs_cache.frequencies["CAL-A-1"][0].trip_start, 7 * 3600 + 30 * 60);
+
+    let monday = chrono::NaiveDate::from_ymd_opt(2026, 5, 4).expect("date");
+    let times = |stop_id: &str| -> Vec<(String, String)> {
+        NVTModels::scheduled_arrivals_on(stop_id, monday, &cache).into_iter()
+            .filter(|arrival| arrival.trip_id == "CAL-A-1")
+            .map(|arrival| (arrival.arrival_time, arrival.service_date))
+            .collect()
+    };
+    let at = |times: &[&str]| -> Vec<(String, String)> {
+        times.iter().map(|time| (time.to_string(), "2026-05-04".to_string())).collect()
+    };
+    assert_eq!(times("CALIBUS:1"), at(&["07:30:00", "07:40:00", "07:50:00", "23:50:00"]), "end_time is left out");
+    assert_eq!(times("CALIBUS:2"), at(&["07:36:00", "07:46:00", "07:56:00", "23:56:00"]), "shifted by the template's offset");
+
+    // A trip without frequencies keeps its single run
+    let other: Vec<String> = NVTModels::scheduled_arrivals_on("CALIBUS:3", monday, &cache).into_iter()
+        .map(|arrival| arrival.arrival_time)
+        .collect();
+    assert_eq!(other, ["07:48:00"]);
+}
+
+/// A refresh job (see refresh_jobs.rs) once done
+async fn finished_job(jobs: &RefreshJobs, id: u64) -> RefreshJob {
+    for _ in 0..500 {
+        match jobs.get(id) {
+            Some(job) if job.status != JobStatus::Running => return job,
+            _ => actix_web::rt::time::sleep(Duration::from_millis(20)).await,
+        }
+    }
+    panic!("refresh job {} still running", id);
+}
+
+#[test]
+fn full_rebuild_swaps_in_a_new_snapshot() {
+    let mock = MockUpstreams::start("rebuild");
+    let mut cache = mock.load();
+    cache.upstreams.feed_mut("TBM").expect("TBM feed").max_age_days = 0;
+    let vehicles = cache.real_time.len();
+    let recolored: Vec<(String, Vec<u8>)> = gtfs_files("tbm").into_iter()
+        .map(|(name, contents)| match name.as_str() {
+            "routes.txt" => (name, String::from_utf8_lossy(&contents).replace("814997", "123456").into_bytes()),
+            _ => (name, contents),
+        })
+        .collect();
+    mock.respond("/tbm/gtfs.zip", 200, zip_files(&recolored));
+    let state = app_state(cache);
+    let (cache, jobs) = (state.cache.clone(), state.refresh_jobs.clone());
+
+    actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+
+        let response = call_service(&app, TestRequest::post().uri("/api/tbm/refresh?full=true").to_request()).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let location = response.headers().get("Location").and_then(|value| value.to_str().ok()).map(String::from).expect("job location");
+        let body: Value = read_body_json(response).await;
+        let id = body["data"]["id"].as_u64().expect("job id");
+        assert_eq!(finished_job(&jobs, id).await.status, JobStatus::Succeeded);
+        let polled: Value = read_body_json(call_service(&app, TestRequest::get().uri(&location).to_request()).await).await;
+        assert_eq!(polled["data"]["status"], "succeeded");
+        assert!(!jobs.rebuilding());
+        {
+            let cache = cache.lock().expect("cache");
+            assert_eq!(cache.tbm_gtfs_cache.routes.get("59").map(String::as_str), Some("123456"));
+            assert_eq!(cache.real_time.len(), vehicles);
+        }
+
+        // While a rebuild runs, the same request joins it and others are turned away
+        let Admission::Started(running) = jobs.admit("full", true) else { panic!("no refresh running") };
+        let response = call_service(&app, TestRequest::post().uri("/api/tbm/refresh?full=true").to_request()).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body: Value = read_body_json(response).await;
+        assert_eq!(body["data"]["id"], running.id);
+        let response = call_service(&app, TestRequest::post().uri("/api/tbm/refresh").to_request()).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key("Retry-After"));
+        let body: Value = read_body_json(response).await;
+        assert_eq!(body["refreshing"], true);
+        assert_eq!(body["job"]["id"], running.id);
+        assert!(jobs.rebuilding(), "a turned away refresh leaves the running one");
+        jobs.finish(running.id, Ok(()));
+        assert!(!jobs.rebuilding());
+
+        let response = call_service(&app, TestRequest::get().uri("/api/tbm/refresh/999").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    });
+}
+
+#[test]
+fn sources_are_refreshed_and_purged_one_at_a_time() {
+    let mock = MockUpstreams::start("purge");
+    let recolored: Vec<(String, Vec<u8>)> = gtfs_files("tbm").into_iter()
+        .map(|(name, contents)| match name.as_str() {
+            "routes.txt" => (name, String::from_utf8_lossy(&contents).replace("814997", "123456").into_bytes()),
+            _ => (name, contents),
+        })
+        .collect();
+    let state = app_state(mock.load());
+    mock.respond("/tbm/gtfs.zip", 200, zip_files(&recolored));
+    let (cache, jobs) = (state.cache.clone(), state.refresh_jobs.clone());
+    let route_color = move || cache.lock().expect("cache").tbm_gtfs_cache.routes.get("59").cloned();
+
+    actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let refresh = |body: &str| TestRequest::post().uri("/api/tbm/refresh").set_payload(body.to_string()).to_request();
+
+        let response = call_service(&app, refresh(r#"{"sources":["SNCF","tbm"],"scope":"static"}"#)).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body: Value = read_body_json(response).await;
+        assert_eq!(body["data"]["request"], "static: TBM, SNCF");
+        let job = finished_job(&jobs, body["data"]["id"].as_u64().expect("job id")).await;
+        assert_eq!(job.status, JobStatus::Succeeded, "{:?}", job.error);
+        assert_eq!(route_color().as_deref(), Some("814997"), "the saved TBM feed is still current");
+
+        let response = call_service(&app, TestRequest::delete().uri("/api/tbm/cache/tbm").to_request()).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body: Value = read_body_json(response).await;
+        assert_eq!(body["data"]["source"], "TBM");
+        assert_eq!(body["data"]["purged"], true);
+        let job = finished_job(&jobs, body["data"]["job"]["id"].as_u64().expect("job id")).await;
+        assert_eq!(job.status, JobStatus::Succeeded, "{:?}", job.error);
+        assert_eq!(route_color().as_deref(), Some("123456"));
+
+        // Real-time data only, from the query
+        let response = call_service(&app, TestRequest::post().uri("/api/tbm/refresh?dynamic=true").to_request()).await;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body: Value = read_body_json(response).await;
+        assert_eq!(body["data"]["request"], "dynamic");
+        assert_eq!(body["data"]["rebuild"], false);
+        finished_job(&jobs, body["data"]["id"].as_u64().expect("job id")).await;
+        let response = call_service(&app, TestRequest::post().uri("/api/tbm/refresh?static=true").to_request()).await;
+        let body: Value = read_body_json(response).await;
+        assert_eq!(body["data"]["request"], "static: TBM, NewAquitaine, SNCF");
+        finished_job(&jobs, body["data"]["id"].as_u64().expect("job id")).await;
+        let conflicting = TestRequest::post().uri("/api/tbm/refresh?static=true").set_payload(r#"{"scope":"dynamic"}"#).to_request();
+        assert_eq!(call_service(&app, conflicting).await.status(), StatusCode::BAD_REQUEST);
+
+        let response = call_service(&app, refresh(r#"{"sources":["RATP"]}"#)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = call_service(&app, refresh(r#"{"scope":"weekly"}"#)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = call_service(&app, TestRequest::delete().uri("/api/tbm/cache/ratp").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    });
+}
+
+#[test]
+fn refresh_keeps_last_data_when_upstreams_fail() {
+    let mock = MockUpstreams::start("outage");
+    let mut cache = mock.load();
+    let vehicles: Vec<String> = cache.real_time.iter().map(|v| v.vehicle_id.clone()).collect();
+
+    for path in [VEHICLES_PATH, ALERTS_PATH, TRIP_UPDATES_PATH] {
+        mock.respond(path, 503, b"Service Unavailable".to_vec());
+    }
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh tolerates upstream failures");
+
+    let after: Vec<String> = cache.real_time.iter().map(|v| v.vehicle_id.clone()).collect();
+    assert_eq!(after, vehicles);
+    assert!(cache.alerts.iter().any(|a| a.id == "TBM-1"), "last TBM alerts are kept");
+    assert!(!cache.to_network_data().stops.is_empty());
+}
+
+#[test]
+fn stats_report_the_last_refresh_outcome() {
+    let mock = MockUpstreams::start("stats");
+    let mut cache = mock.load();
+    let stats = NVTModels::get_cache_stats(&cache);
+    assert_eq!(stats.sources.iter().map(|source| source.source.as_str()).collect::<Vec<_>>(), ["TBM", "NewAquitaine", "SNCF"]);
+    assert!(stats.sources.iter().all(|source| source.cache_file_bytes.is_some_and(|bytes| bytes > 0)));
+    assert_eq!(stats.last_refresh.at, 0);
+
+    mock.respond("/tbm/siri/2.0/bordeaux/stoppoints-discovery.json", 503, b"Service Unavailable".to_vec());
+    cache.last_static_update = 0;
+    assert!(NVTModels::smart_refresh(&mut cache).is_err());
+    let stats = NVTModels::get_cache_stats(&cache);
+    assert!(!stats.last_refresh.succeeded);
+    assert!(stats.last_refresh.error.is_some());
+    assert!(stats.to_string().contains("Last refresh: "), "{}", stats);
+}
+
+#[test]
+fn health_degrades_when_a_source_stops_answering() {
+    let mock = MockUpstreams::start("health");
+    let mut cache = mock.load();
+    let settings = HealthConfig::default();
+    let now = NVTModels::get_current_timestamp() as u64;
+    assert!(!NVTModels::get_health(&cache, &settings, now).degraded);
+
+    for path in [VEHICLES_PATH, ALERTS_PATH, TRIP_UPDATES_PATH] {
+        mock.respond(path, 503, b"Service Unavailable".to_vec());
+    }
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh tolerates upstream failures");
+    let health = NVTModels::get_health(&cache, &settings, now);
+    assert!(!health.freshness["TBM"].succeeded);
+    assert!(health.freshness["TBM"].errors.iter().any(|error| error.contains("vehicle positions")), "{:?}", health.freshness["TBM"].errors);
+    assert!(health.freshness["SNCF"].succeeded);
+    assert!(!health.degraded, "the last TBM data is still recent");
+
+    // The refresh itself keeps running, only the TBM feeds are an hour old
+    let later = now + 3600;
+    cache.last_dynamic_update = later;
+    cache.last_static_update = later;
+    cache.record_fetches(later, &[("SNCF", FeedKind::RealTime)], &[]);
+    let health = NVTModels::get_health(&cache, &settings, later);
+    assert!(health.degraded);
+    assert!(health.freshness["TBM"].stale);
+    assert!(!health.freshness["SNCF"].stale);
+    assert_eq!(health.freshness["NewAquitaine"].real_time_age_secs, None);
+}
+
+#[test]
+fn readiness_waits_for_the_initial_load_and_a_fresh_source() {
+    let mock = MockUpstreams::start("ready");
+    let settings = HealthConfig::default();
+    let now = NVTModels::get_current_timestamp() as u64;
+    let empty = CachedNetworkData::empty(mock.upstreams());
+    assert!(!NVTModels::get_health(&empty, &settings, now).ready);
+
+    let cache = mock.load();
+    assert!(NVTModels::get_health(&cache, &settings, now).ready);
+
+    // No source answered since
+    let later = now + settings.max_static_age_secs + 60;
+    assert!(!NVTModels::get_health(&cache, &settings, later).ready);
+}
+
+#[test]
+fn preflight_reports_unreachable_upstreams() {
+    let mock = MockUpstreams::start("preflight");
+    mock.respond("/communes", 503, b"Service Unavailable".to_vec());
+    let mut upstreams = mock.upstreams();
+    upstreams.feed_mut("SNCF").expect("SNCF feed").rt_urls[1] = "http://127.0.0.1:9/sncf/alerts".to_string(); // Discard port, nothing listens
+
+    let options = PreflightOptions { min_free_mb: 0, timeout: Duration::from_secs(5) };
+    let checks = preflight::run_checks(&upstreams.cache_dir.join("missing.json"), &upstreams, &options);
+    let status = |name: &str| checks.iter().find(|check| check.name == name).map(|check| check.status);
+
+    assert_eq!(status("config"), Some(Status::Pass), "a missing config file means defaults");
+    assert_eq!(status("upstream TBM GTFS"), Some(Status::Pass));
+    assert_eq!(status("upstream Communes"), Some(Status::Fail));
+    assert_eq!(status("upstream SNCF GTFS-RT 2"), Some(Status::Fail));
+    assert_eq!(status("cache directory"), Some(Status::Pass));
+    assert_ne!(status("disk space"), Some(Status::Fail));
+}
+
+#[test]
+fn client_ips_are_truncated_or_hashed_in_privacy_mode() {
+    let ip: IpAddr = "203.0.113.77".parse().expect("IPv4");
+    let ipv6: IpAddr = "2001:db8:aa:bb:cc::1".parse().expect("IPv6");
+    let anonymizer = |client_ips| IpAnonymizer::new(&PrivacyConfig { client_ips, ..PrivacyConfig::default() });
+
+    assert_eq!(anonymizer(IpMode::Full).anonymize(ip), "203.0.113.77");
+    let truncate = anonymizer(IpMode::Truncate);
+    assert_eq!(truncate.anonymize(ip), "203.0.113.0");
+    assert_eq!(truncate.anonymize(ipv6), "2001:db8:aa::");
+
+    let hash = anonymizer(IpMode::Hash);
+    let hashed = hash.anonymize(ip);
+    assert_eq!(hashed.len(), 16);
+    assert!(!hashed.contains("203"), "{}", hashed);
+    assert_eq!(hash.anonymize(ip), hashed, "stable until the salt rotates");
+    assert_ne!(hash.anonymize("203.0.113.78".parse().expect("IPv4")), hashed);
+    assert_ne!(anonymizer(IpMode::Hash).anonymize(ip), hashed, "each salt is random");
+}
+
+#[test]
+fn access_log_writes_json_lines_and_rotates() {
+    let dir = std::env::temp_dir().join(format!("nvt-test-access-log-{}", std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    let path = dir.join("access.log");
+    let log = AccessLog::new(&AccessLogConfig {
+        format: AccessLogFormat::Json,
+        file: Some(path.clone()),
+        max_file_mb: 1,
+        max_files: 2,
+    });
+    let anonymizer = IpAnonymizer::new(&PrivacyConfig { client_ips: IpMode::Truncate, ..PrivacyConfig::default() });
+
+    let req = TestRequest::get()
+        .uri("/api/tbm/network")
+        .peer_addr("203.0.113.77:40000".parse().expect("address"))
+        .insert_header(("User-Agent", "curl/8.0"))
+        .to_srv_request();
+    let pending = PendingEntry::start(&req, &anonymizer);
+    let entry = pending.finish(&req.into_response(HttpResponse::NotModified().finish()));
+    log.record(&entry);
+
+    let line: Value = serde_json::from_str(fs::read_to_string(&path).expect("access log").trim()).expect("JSON line");
+    assert_eq!(line["client"], "203.0.113.0");
+    assert_eq!(line["route"], "/api/tbm/network");
+    assert_eq!((line["status"].as_u64(), line["bytes"].as_u64(), line["cache_hit"].as_bool()), (Some(304), Some(0), Some(true)));
+    assert_eq!(line["user_agent"], "curl/8.0");
+
+    // 1 MB per file, the current one and two rotated files
+    let line_bytes = serde_json::to_string(&entry).expect("JSON").len() + 1;
+    for _ in 0..3 * (1024 * 1024 / line_bytes) + 10 {
+        log.record(&entry);
+    }
+    let rotated = |n: usize| PathBuf::from(format!("{}.{}", path.display(), n));
+    assert!(rotated(1).exists() && rotated(2).exists());
+    assert!(!rotated(3).exists());
+    assert!(fs::metadata(&path).expect("current file").len() <= 1024 * 1024);
+    fs::remove_dir_all(&dir).ok();
+}
+
+/// One-connection SMTP server answering `refusals` (command prefix, reply) in place of the usual
+/// replies; gives back the lines it received
+fn mock_smtp(refusals: &'static [(&'static str, &'static str)]) -> (u16, thread::JoinHandle<Vec<String>>) {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).expect("smtp bind");
+    let port = listener.local_addr().expect("smtp address").port();
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().expect("smtp connection");
+        let mut writer = stream.try_clone().expect("smtp stream");
+        let mut reader = std::io::BufReader::new(stream);
+        let mut received = Vec::new();
+        let mut in_data = false;
+        writer.write_all(b"220 mock ESMTP\r\n").ok();
+        loop {
+            let mut line = String::new();
+            if std::io::BufRead::read_line(&mut reader, &mut line).unwrap_or(0) == 0 {
+                break;
+            }
+            let line = line.trim_end_matches("\r\n").to_string();
+            received.push(line.clone());
+            if in_data {
+                if line == "." {
+                    in_data = false;
+                    writer.write_all(b"250 queued\r\n").ok();
+                }
+                continue;
+            }
+            let reply = match refusals.iter().find(|(command, _)| line.starts_with(command)) {
+                Some((_, reply)) => reply.to_string(),
+                None if line.starts_with("EHLO") => "250-mock\r\n250-AUTH PLAIN\r\n250 8BITMIME".to_string(),
+                None if line.starts_with("AUTH") => "235 accepted".to_string(),
+                None if line == "DATA" => {
+                    in_data = true;
+                    "354 go ahead".to_string()
+                }
+                None if line == "QUIT" => "221 bye".to_string(),
+                None => "250 ok".to_string(),
+            };
+            writer.write_all(format!("{}\r\n", reply).as_bytes()).ok();
+            if line == "QUIT" || !reply.starts_with(['2', '3']) {
+                break;
+            }
+        }
+        received
+    });
+    (port, server)
+}
+
+#[test]
+fn digest_mail_follows_the_smtp_dialogue() {
+    let smtp = |port| SmtpConfig {
+        host: "127.0.0.1".to_string(),
+        port,
+        security: SmtpSecurity::None,
+        username: Some("digest".to_string()),
+        password: Some("s3cret".to_string()),
+        from: "nvt@example.org".to_string(),
+    };
+
+    let (port, server) = mock_smtp(&[]);
+    digest::send_mail(&smtp(port), "ops@example.org", "Perturbations à Bordeaux", "Line A\n.hidden dot\nEnd")
+        .expect("mail sent");
+    let received = server.join().expect("smtp server");
+    let commands: Vec<&str> = received.iter()
+        .map(String::as_str)
+        .filter(|line| ["EHLO", "AUTH", "MAIL", "RCPT", "DATA", "QUIT"].iter().any(|command| line.starts_with(command)))
+        .collect();
+    assert_eq!(commands, [
+        "EHLO nvt-web-edition",
+        "AUTH PLAIN AGRpZ2VzdABzM2NyZXQ=",
+        "MAIL FROM:<nvt@example.org>",
+        "RCPT TO:<ops@example.org>",
+        "DATA",
+        "QUIT",
+    ]);
+    assert!(received.contains(&"Subject: =?UTF-8?B?UGVydHVyYmF0aW9ucyDDoCBCb3JkZWF1eA==?=".to_string()), "{:?}", received);
+    assert!(received.contains(&"..hidden dot".to_string()), "dot-stuffed: {:?}", received);
+    assert_eq!(received.iter().filter(|line| *line == ".").count(), 1, "only the end of the data is a lone dot");
+
+    // Error replies end the dialogue with the server's code and text
+    for (refusals, expected) in [
+        (&[("AUTH", "535 5.7.8 authentication failed")][..], "SMTP error 535: 5.7.8 authentication failed"),
+        (&[("RCPT", "550 5.1.1 no such user")][..], "SMTP error 550: 5.1.1 no such user"),
+        (&[("MAIL", "x")][..], "Malformed SMTP reply"),
+    ] {
+        let (port, server) = mock_smtp(refusals);
+        let error = digest::send_mail(&smtp(port), "ops@example.org", "Digest", "Body").expect_err("refused");
+        assert!(error.to_string().contains(expected), "{} instead of {}", error, expected);
+        server.join().expect("smtp server");
+    }
+}
+
+#[test]
+fn standby_mirrors_the_primary_snapshot() {
+    let mock = MockUpstreams::start("sync");
+    let mut primary = mock.load();
+    let mut standby = mock.load();
+    standby.last_static_update = primary.last_static_update;
+
+    mock.respond(VEHICLES_PATH, 200, feed(vec![
+        vehicle("V-A-1", "A-1", "59", "Le Haillan Rostand", (44.8378, -0.5795), ("3673", 3)),
+    ]));
+    NVTModels::refresh_dynamic_data(&mut primary).expect("refresh");
+
+    // Same static data: only the real-time data goes over the wire
+    let over_the_wire = |payload: SyncPayload| -> SyncPayload {
+        serde_json::from_slice(&serde_json::to_vec(&payload).expect("payload JSON")).expect("payload")
+    };
+    let payload = over_the_wire(sync::payload(&primary, Some(standby.last_static_update)));
+    assert!(payload.static_data.is_none());
+    sync::apply(payload, &mut standby);
+    let vehicles = |cache: &CachedNetworkData| -> Vec<(String, Option<String>)> {
+        cache.real_time.iter().map(|v| (v.vehicle_id.clone(), v.stop_id.clone())).collect()
+    };
+    assert_eq!(vehicles(&standby), vehicles(&primary));
+    assert_eq!(standby.vanished_vehicles.keys().collect::<Vec<_>>(), ["V-1-1"]);
+    assert_eq!(standby.last_dynamic_update, primary.last_dynamic_update);
+
+    // Outdated static data is replaced, with the indexes rebuilt
+    let payload = over_the_wire(sync::payload(&primary, Some(0)));
+    assert!(payload.static_data.is_some());
+    let mut fresh = CachedNetworkData { last_static_update: 0, ..standby.clone() };
+    sync::apply(payload, &mut fresh);
+    assert_eq!(fresh.last_static_update, primary.last_static_update);
+    assert_eq!(fresh.trip_index, primary.trip_index);
+    assert_eq!(fresh.to_network_data().stops.len(), primary.to_network_data().stops.len());
+    assert_eq!(fresh.alert_stops.len(), primary.alert_stops.len());
+}
+
+#[test]
+fn version_reports_the_running_build() {
+    let mock = MockUpstreams::start("version");
+    let responses = call_api(mock.load(), &["/api/tbm/version", "/api"]);
+    let info = &responses[0].1["data"];
+
+    assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(info["version"], responses[1].1["data"]["version"], "/api reports the same version");
+    assert!(!info["git_commit"].as_str().unwrap_or_default().is_empty());
+    assert!(info["build_timestamp"].as_i64().unwrap_or_default() > 1_700_000_000);
+    assert_eq!(info["sources"], serde_json::json!(["TBM", "New-Aquitaine", "SNCF"]));
+    assert_eq!(info["features"].as_array().map(Vec::len), Some(usize::from(cfg!(feature = "bot"))));
+    assert!(info["uptime_secs"].is_u64());
+}
+
+#[test]
+fn navitia_fills_in_missing_journeys_and_disruptions() {
+    let mock = MockUpstreams::start("navitia");
+    let disruption = |id: &str, status: &str, text: &str, stop_id: &str| serde_json::json!({
+        "id": id,
+        "status": status,
+        "severity": {"effect": "NO_SERVICE"},
+        "messages": [{"text": text, "channel": {"types": ["title"]}}],
+        "application_periods": [{"begin": "20260504T000000", "end": "20260505T000000"}],
+        "impacted_objects": [{"pt_object": {"id": stop_id, "embedded_type": "stop_area"}}],
+    });
+    mock.respond("/navitia/coverage/sncf/disruptions", 200, serde_json::to_vec(&serde_json::json!({"disruptions": [
+        disruption("strike", "active", "Mouvement social régional", "stop_area:SNCF:87584052"),
+        disruption("same", "active", "Trafic perturbé Bordeaux - Libourne", "stop_area:SNCF:87581009"),
+        disruption("over", "past", "Travaux terminés", "stop_area:SNCF:87581009"),
+    ]})).expect("disruptions"));
+    mock.respond("/navitia/coverage/sncf/journeys", 200, serde_json::to_vec(&serde_json::json!({"journeys": [{
+        "departure_date_time": "20400106T080000",
+        "arrival_date_time": "20400106T083500",
+        "nb_transfers": 0,
+        "sections": [
+            {"type": "street_network", "from": {"id": "3671", "name": "Grand Théâtre"}, "to": {"id": "stop_point:SNCF:87581009:Train", "name": "Bordeaux Saint-Jean"},
+             "departure_date_time": "20400106T080000", "arrival_date_time": "20400106T081000"},
+            {"type": "waiting", "departure_date_time": "20400106T081000", "arrival_date_time": "20400106T081500"},
+            {"type": "public_transport", "from": {"id": "stop_point:SNCF:87581009:Train", "name": "Bordeaux Saint-Jean"}, "to": {"id": "stop_point:SNCF:87584052:Train", "name": "Libourne"},
+             "departure_date_time": "20400106T081700", "arrival_date_time": "20400106T083500",
+             "base_departure_date_time": "20400106T081500", "base_arrival_date_time": "20400106T083500",
+             "display_informations": {"code": "43", "color": "1E90FF", "network": "TER Nouvelle-Aquitaine", "headsign": "Libourne"},
+             "links": [{"type": "vehicle_journey", "id": "vehicle_journey:SNCF:864201"}]},
+        ],
+    }]})).expect("journeys"));
+
+    let mut upstreams = mock.upstreams();
+    upstreams.navitia = Some(Arc::new(Navitia::new(NavitiaConfig {
+        api_key: "test-key".to_string(),
+        base_url: format!("{}/navitia", mock.base_url),
+        coverage: "sncf".to_string(),
+        cache_ttl_secs: 300,
+    })));
+    let mut cache = NVTModels::initialize_cache_with(upstreams).expect("cache from fixtures");
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh");
+
+    // Only the disruption no feed published, on the local stops of its UIC code
+    let navitia_alerts: Vec<(&str, &[String], u32)> = cache.alerts.iter()
+        .filter(|alert| alert.id.starts_with("navitia:"))
+        .map(|alert| (alert.id.as_str(), alert.stop_ids.as_slice(), alert.severity))
+        .collect();
+    assert_eq!(navitia_alerts, [("navitia:strike", ["87584052".to_string()].as_slice(), 4)]);
+
+    // Responses are reused within cache_ttl_secs, and a refresh doesn't add them twice
+    mock.respond("/navitia/coverage/sncf/disruptions", 200, b"{\"disruptions\": []}".to_vec());
+    NVTModels::refresh_dynamic_data(&mut cache).expect("refresh");
+    assert_eq!(cache.alerts.iter().filter(|alert| alert.id.starts_with("navitia:")).count(), 1);
+
+    // No timetable runs that far ahead, Navitia answers in the same shape
+    let responses = call_api(cache, &["/api/tbm/journey?from_stop=3671&to_stop=87584052&departure=2040-01-06T08:00"]);
+    let body = &responses[0].1;
+    assert!(body["sources"].as_array().is_some_and(|sources| sources.contains(&Value::from("Navitia"))), "{}", body);
+    let journey = &body["data"][0];
+    assert_eq!(journey["duration_secs"], 2100);
+    let modes: Vec<&str> = journey["legs"].as_array().expect("legs").iter().filter_map(|leg| leg["mode"].as_str()).collect();
+    assert_eq!(modes, ["walk", "transit"]);
+    let train = &journey["legs"][1];
+    assert_eq!((train["line_code"].as_str(), train["operator"].as_str()), (Some("43"), Some("TER Nouvelle-Aquitaine")));
+    assert_eq!((train["scheduled_departure"].as_str(), train["departure_delay"].as_i64(), train["wait_secs"].as_i64()), (Some("08:15:00"), Some(120), Some(420)));
+}
+
+#[test]
+fn journey_planner_ranks_itineraries_by_arrival() {
+    let mock = MockUpstreams::start("journey");
+    let responses = call_api(mock.load(), &[
+        "/api/tbm/journey?from_stop=3671&to_stop=3673&departure=2026-05-04T07:50",
+        "/api/tbm/journey?from_stop=3672&to_stop=3673&departure=2026-05-04T08:03",
+    ]);
+    let legs = |journey: &Value| -> Vec<(String, String, String)> {
+        journey["legs"].as_array().into_iter().flatten()
+            .map(|leg| (leg["mode"].as_str().unwrap_or_default().to_string(),
+                        leg["to_stop_id"].as_str().unwrap_or_default().to_string(),
+                        leg["line_code"].as_str().unwrap_or_default().to_string()))
+            .collect()
+    };
+
+    // Line A gets there first even with the 2 minutes of delay of its trip update
+    let journeys = responses[0].1["data"].as_array().expect("itineraries");
+    assert_eq!(journeys.iter().map(legs).collect::<Vec<_>>(), [
+        vec![("transit".to_string(), "3673".to_string(), "A".to_string())],
+        vec![("transit".to_string(), "3673".to_string(), "1".to_string())],
+    ]);
+    let tram = &journeys[0]["legs"][0];
+    assert_eq!(tram["scheduled_arrival"], "08:05:00");
+    assert_eq!(tram["arrival_delay"], 120);
+    assert_eq!(tram["stop_ids"], serde_json::json!(["3671", "3672", "3673"]));
+    assert_eq!(tram["wait_secs"], 600);
+    assert!(journeys[0]["arrival"].as_i64() < journeys[1]["arrival"].as_i64());
+
+    // Once the tram has left Grand Théâtre, walk to Quinconces for line 1
+    let journeys = responses[1].1["data"].as_array().expect("itineraries");
+    assert_eq!(journeys.iter().map(legs).collect::<Vec<_>>(), [vec![
+        ("walk".to_string(), "3671".to_string(), String::new()),
+        ("transit".to_string(), "3673".to_string(), "1".to_string()),
+    ]]);
+    let (walk, bus) = (&journeys[0]["legs"][0], &journeys[0]["legs"][1]);
+    assert_eq!(bus["wait_secs"].as_i64(), Some(bus["departure"].as_i64().unwrap_or_default() - walk["arrival"].as_i64().unwrap_or_default()));
+    assert_eq!(journeys[0]["transfers"], 0);
+}
+
+#[test]
+fn arrive_by_journeys_leave_as_late_as_they_can() {
+    let mock = MockUpstreams::start("journey_arrive_by");
+    let responses = call_api(mock.load(), &[
+        "/api/tbm/journey?from_stop=3671&to_stop=3673&arrive_by=2026-05-04T08:15",
+        "/api/tbm/journey?from_stop=3671&to_stop=3673&arrive_by=2026-05-04T08:10",
+        "/api/tbm/journey?from_stop=3672&to_stop=3673&arrive_by=2026-05-04T08:15&count=1",
+        "/api/tbm/journey?from_stop=3671&to_stop=3673&arrive_by=2026-05-04T08:06",
+    ]);
+    let lines = |body: &Value| -> Vec<(String, i64)> {
+        body["data"].as_array().into_iter().flatten()
+            .map(|journey| (journey["legs"].as_array().into_iter().flatten()
+                .filter_map(|leg| leg["line_code"].as_str())
+                .collect::<Vec<_>>()
+                .join("+"), journey["arrival"].as_i64().unwrap_or_default()))
+            .collect()
+    };
+    let at = |time: &str| crate::parse_departure(&format!("2026-05-04T{}", time)).expect("time");
+
+    // The bus leaves last, then the tram arrives before it
+    assert_eq!(lines(&responses[0].1), [("1".to_string(), at("08:14")), ("A".to_string(), at("08:07"))]);
+    let bus = &responses[0].1["data"][0];
+    assert_eq!((bus["departure"].as_i64(), bus["duration_secs"].as_i64()), (Some(at("08:10")), Some(240)));
+    assert_eq!(lines(&responses[1].1), [("A".to_string(), at("08:07"))], "the bus arrives too late");
+
+    // The walk to Quinconces leaves just in time for the bus
+    let journey = &responses[2].1["data"][0];
+    let (walk, bus) = (&journey["legs"][0], &journey["legs"][1]);
+    assert_eq!((walk["mode"].as_str(), bus["line_code"].as_str()), (Some("walk"), Some("1")));
+    assert_eq!(walk["arrival"], bus["departure"]);
+    assert_eq!(bus["wait_secs"], 0);
+    assert_eq!(journey["departure"], walk["departure"]);
+
+    assert_eq!(responses[3].1["data"], serde_json::json!([]), "nothing gets there by 08:06");
+}
+
+#[test]
+fn criteria_weigh_transfers_and_walking_against_arrival() {
+    let mock = MockUpstreams::start("journey_criteria");
+    // Libourne Gare to Hôpital: change at Centre (07:43), walk there (07:43, no change) or wait for the direct bus (07:48)
+    let files: Vec<(String, Vec<u8>)> = gtfs_files("naq").into_iter()
+        .map(|(name, contents)| {
+            let mut contents = String::from_utf8_lossy(&contents).to_string();
+            match name.as_str() {
+                "trips.txt" => contents.push_str("CA_DU_LIBOURNAIS:Line:B,CAL-WEEK,CAL-B-2,Libourne Hôpital,0,,1,\n"),
+                "stop_times.txt" => contents.push_str("CAL-B-2,07:39:00,07:39:00,CALIBUS:2,1,\nCAL-B-2,07:43:00,07:43:00,CALIBUS:3,2,\n"),
+                _ => {}
+            }
+            (name, contents.into_bytes())
+        })
+        .collect();
+    mock.respond("/naq/gtfs.zip", 200, zip_files(&files));
+    let cache = mock.load();
+
+    let invalid = actix_web::rt::System::new().block_on(async {
+        let app = init_service(App::new().app_data(web::Data::new(app_state(cache.clone()))).configure(api_routes)).await;
+        let mut invalid = Vec::new();
+        for uri in ["/api/tbm/journey?from_stop=CALIBUS:1&to_stop=CALIBUS:3&criteria=transfers,comfort",
+                    "/api/tbm/journey?from_stop=CALIBUS:1&to_stop=CALIBUS:3&arrive_by=2026-05-04T08:00&criteria=walking"] {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            let status = response.status();
+            let body: Value = read_body_json(response).await;
+            invalid.push((status, body["error"].as_str().unwrap_or_default().to_string()));
+        }
+        invalid
+    });
+    assert_eq!(invalid, [
+        (StatusCode::BAD_REQUEST, "Invalid criteria 'transfers,comfort' (expected arrival, transfers or walking)".to_string()),
+        (StatusCode::BAD_REQUEST, "criteria only applies to departure, not arrive_by".to_string()),
+    ]);
+
+    let journey = "/api/tbm/journey?from_stop=CALIBUS:1&to_stop=CALIBUS:3&departure=2026-05-04T07:25";
+    let responses = call_api(cache, &[
+        format!("{}&criteria=arrival,transfers,walking&count=5", journey).as_str(),
+        format!("{}&criteria=transfers", journey).as_str(),
+        format!("{}&criteria=walking", journey).as_str(),
+        format!("{}&criteria=transfers,walking&count=2", journey).as_str(),
+        "/api/tbm/journey?from_stop=3671&to_stop=3673&departure=2026-05-04T07:50&criteria=transfers,walking",
+    ]);
+    let alternatives = |body: &Value| -> Vec<(String, u64, i64)> {
+        body["data"].as_array().into_iter().flatten()
+            .map(|journey| (journey["legs"].as_array().into_iter().flatten()
+                .map(|leg| leg["trip_id"].as_str().unwrap_or("walk").to_string())
+                .collect::<Vec<_>>()
+                .join("+"), journey["transfers"].as_u64().unwrap_or_default(), journey["walk_secs"].as_i64().unwrap_or_default()))
+            .collect()
+    };
+    let option = |legs: &str, transfers: u64, walk_secs: i64| (legs.to_string(), transfers, walk_secs);
+
+    assert_eq!(alternatives(&responses[0].1), [
+        option("walk+CAL-B-2", 0, 300),
+        option("CAL-A-1+CAL-B-2", 1, 0),
+        option("CAL-B-1", 0, 0),
+    ]);
+    assert_eq!(alternatives(&responses[1].1), [option("walk+CAL-B-2", 0, 300)], "as early with no change");
+    assert_eq!(alternatives(&responses[2].1), [option("CAL-A-1+CAL-B-2", 1, 0)], "as early with no walk");
+    assert_eq!(alternatives(&responses[3].1), [option("walk+CAL-B-2", 0, 300), option("CAL-A-1+CAL-B-2", 1, 0)]);
+
+    // The tram's real-time delay holds for its leg
+    let tram = &responses[4].1["data"];
+    assert_eq!(tram.as_array().map(Vec::len), Some(1), "the tram beats the bus on everything: {}", tram);
+    let leg = &tram[0]["legs"][0];
+    assert_eq!((leg["trip_id"].as_str(), leg["arrival_delay"].as_i64()), (Some("A-1"), Some(120)));
+    assert_eq!(leg["arrival"].as_i64(), crate::parse_departure("2026-05-04T08:07"));
+}
+
+#[test]
+fn wheelchair_journeys_keep_to_accessible_stops_trips_and_pathways() {
+    let mock = MockUpstreams::start("journey_wheelchair");
+    // Centre has no wheelchair boarding and the direct bus isn't accessible; Hôpital's second
+    // platform has stairs and a slower lift, its third one only stairs
+    let mut files: Vec<(String, Vec<u8>)> = gtfs_files("naq").into_iter()
+        .map(|(name, contents)| {
+            let mut contents = String::from_utf8_lossy(&contents).to_string();
+            match name.as_str() {
+                "stops.txt" => contents = "stop_id,stop_name,stop_lat,stop_lon,stop_code,stop_desc,location_type,parent_station,wheelchair_boarding\n\
+                    CALIBUS:1,Libourne Gare,44.9153,-0.2437,,,0,,1\n\
+                    CALIBUS:2,Libourne Centre,44.9172,-0.2419,,,0,,2\n\
+                    CALIBUS:3,Libourne Hôpital,44.9210,-0.2350,,,0,,1\n\
+                    CALIBUS:3Q,Libourne Hôpital quai 2,44.9211,-0.2351,,,0,,1\n\
+                    CALIBUS:3S,Libourne Hôpital quai 3,44.9209,-0.2351,,,0,,1\n".to_string(),
+                "trips.txt" => {
+                    contents = contents.replace("CAL-B-1,Libourne Hôpital,0,,1", "CAL-B-1,Libourne Hôpital,0,,2");
+                    contents.push_str("CA_DU_LIBOURNAIS:Line:B,CAL-WEEK,CAL-B-2,Libourne Hôpital,0,,1,\n");
+                }
+                "stop_times.txt" => contents.push_str("CAL-B-2,07:50:00,07:50:00,CALIBUS:1,1,\nCAL-B-2,07:53:00,07:53:00,CALIBUS:2,2,\nCAL-B-2,07:58:00,07:58:00,CALIBUS:3,3,\n"),
+                _ => {}
+            }
+            (name, contents.into_bytes())
+        })
+        .collect();
+    files.push(("pathways.txt".to_string(), b"pathway_id,from_stop_id,to_stop_id,pathway_mode,is_bidirectional,length,traversal_time,stair_count\n\
+        P1,CALIBUS:3,CALIBUS:3Q,2,1,15,60,24\n\
+        P2,CALIBUS:3,CALIBUS:3Q,5,1,,90,\n\
+        P3,CALIBUS:3,CALIBUS:3S,2,1,15,60,24\n".to_vec()));
+    mock.respond("/naq/gtfs.zip", 200, zip_files(&files));
+
+    let journey = |to: &str, wheelchair: bool| format!("/api/tbm/journey?from_stop=CALIBUS:1&to_stop={}&departure=2026-05-04T07:25&count=1{}",
+        to, if wheelchair { "&wheelchair=true" } else { "" });
+    let responses = call_api(mock.load(), &[
+        journey("CALIBUS:3", false).as_str(),
+        journey("CALIBUS:3", true).as_str(),
+        journey("CALIBUS:2", true).as_str(),
+        journey("CALIBUS:3Q", false).as_str(),
+        journey("CALIBUS:3Q", true).as_str(),
+        journey("CALIBUS:3S", true).as_str(),
+    ]);
+    let legs = |body: &Value| -> Vec<(String, i64)> {
+        body["data"][0]["legs"].as_array().into_iter().flatten()
+            .map(|leg| (leg["trip_id"].as_str().unwrap_or("walk").to_string(), leg["arrival"].as_i64().unwrap_or_default() - leg["departure"].as_i64().unwrap_or_default()))
+            .collect()
+    };
+    let leg = |trip: &str, secs: i64| (trip.to_string(), secs);
+
+    assert_eq!(legs(&responses[0].1), [leg("CAL-B-1", 480)]);
+    assert_eq!(legs(&responses[1].1), [leg("CAL-B-2", 480)], "the accessible bus");
+    assert_eq!(responses[1].1["data"][0]["legs"][0]["stop_ids"], serde_json::json!(["CALIBUS:1", "CALIBUS:2", "CALIBUS:3"]), "through Centre");
+    assert_eq!(responses[2].1["data"], serde_json::json!([]), "Centre has no wheelchair boarding");
+    assert_eq!(legs(&responses[3].1), [leg("CAL-B-1", 480), leg("walk", 60)], "up the stairs");
+    assert_eq!(legs(&responses[4].1), [leg("CAL-B-2", 480), leg("walk", 90)], "in the lift");
+    assert_eq!(responses[5].1["data"], serde_json::json!([]), "only stairs lead there");
+}
+
+#[test]
+fn bike_journeys_ride_trips_taking_bikes_and_v3_bikes_first_and_last() {
+    let mock = MockUpstreams::start("journey_bikes");
+    let files: Vec<(String, Vec<u8>)> = gtfs_files("tbm").into_iter()
+        .map(|(name, contents)| match name.as_str() {
+            "trips.txt" => (name, String::from_utf8_lossy(&contents)
+                .replace("shape_id\n", "shape_id,bikes_allowed\n")
+                .replace("A-0\n", "A-0,1\n")
+                .replace("L1-0\n", "L1-0,2\n")
+                .replace("BAT-0\n", "BAT-0,\n")
+                .into_bytes()),
+            _ => (name, contents),
+        })
+        .collect();
+    mock.respond("/tbm/gtfs.zip", 200, zip_files(&files));
+    // Rent in Chartrons and return at Quinconces, or rent at Hôtel de Ville and return at Victoire
+    mock.respond("/gbfs/station_information.json", 200, r#"{"last_updated": 1777874400, "ttl": 3600, "data": {"stations": [
+        {"station_id": "1", "name": "Quinconces", "lat": 44.8445, "lon": -0.5745},
+        {"station_id": "4", "name": "Chartrons", "lat": 44.8600, "lon": -0.5750},
+        {"station_id": "5", "name": "Hôtel de Ville", "lat": 44.8380, "lon": -0.5790},
+        {"station_id": "6", "name": "Victoire", "lat": 44.8252, "lon": -0.5898}
+    ]}}"#.as_bytes().to_vec());
+    mock.respond("/gbfs/station_status.json", 200, br#"{"last_updated": 1777874460, "ttl": 60, "data": {"stations": [
+        {"station_id": "1", "num_bikes_available": 0, "num_docks_available": 27, "is_renting": 0, "is_returning": 1},
+        {"station_id": "4", "num_bikes_available": 5, "num_docks_available": 10, "is_renting": 1, "is_returning": 1},
+        {"station_id": "5", "num_bikes_available": 3, "num_docks_available": 0, "is_renting": 1, "is_returning": 0},
+        {"station_id": "6", "num_bikes_available": 0, "num_docks_available": 12, "is_renting": 0, "is_returning": 1}
+    ]}}"#.to_vec());
+
+    let responses = call_api(mock.load(), &[
+        "/api/tbm/journey?from_stop=3671&to_stop=3673&departure=2026-05-04T07:50&count=2",
+        "/api/tbm/journey?from_stop=3671&to_stop=3673&departure=2026-05-04T07:50&count=2&bikes=true",
+        "/api/tbm/journey?from=44.8602,-0.5752&to_stop=3673&departure=2026-05-04T07:45&count=1",
+        "/api/tbm/journey?from=44.8602,-0.5752&to_stop=3673&departure=2026-05-04T07:45&count=1&bikes=true",
+        "/api/tbm/journey?from_stop=3671&to=44.8250,-0.5900&departure=2026-05-04T07:50&count=1&bikes=true",
+    ]);
+    let trips = |body: &Value| -> Vec<String> {
+        body["data"].as_array().into_iter().flatten()
+            .filter_map(|journey| journey["legs"][0]["trip_id"].as_str().map(String::from))
+            .collect()
+    };
+    let legs = |body: &Value| -> Vec<(String, String, String)> {
+        body["data"][0]["legs"].as_array().into_iter().flatten()
+            .map(|leg| (leg["mode"].as_str().unwrap_or_default().to_string(),
+                leg["trip_id"].as_str().unwrap_or_else(|| leg["from_stop_name"].as_str().unwrap_or_default()).to_string(),
+                leg["to_stop_name"].as_str().unwrap_or_default().to_string()))
+            .collect()
+    };
+    let leg = |mode: &str, from: &str, to: &str| (mode.to_string(), from.to_string(), to.to_string());
+
+    assert_eq!(trips(&responses[0].1), ["A-1", "1-1"]);
+    assert_eq!(trips(&responses[1].1), ["A-1"], "the bus takes no bikes");
+
+    assert_eq!(responses[2].1["data"], serde_json::json!([]), "too far to walk");
+    assert_eq!(legs(&responses[3].1), [
+        leg("walk", "Origin", "Chartrons"),
+        leg("bike", "Chartrons", "Quinconces"),
+        leg("walk", "Quinconces", "Quinconces"),
+        leg("transit", "A-1", "Hôtel de Ville"),
+    ]);
+    let journey = &responses[3].1["data"][0];
+    let ride = &journey["legs"][1];
+    assert_eq!((ride["from_stop_id"].as_str(), ride["to_stop_id"].as_str()), (Some("4"), Some("1")));
+    assert_eq!(ride["arrival"].as_i64().unwrap_or_default() - ride["departure"].as_i64().unwrap_or_default(), 60 + 431, "1724 m at 4 m/s, and the docking");
+    assert_eq!(journey["legs"][0]["arrival"], ride["departure"]);
+    assert_eq!(journey["walk_secs"], 23 + 69);
+
+    assert_eq!(legs(&responses[4].1), [
+        leg("transit", "A-1", "Hôtel de Ville"),
+        leg("walk", "Hôtel de Ville", "Hôtel de Ville"),
+        leg("bike", "Hôtel de Ville", "Victoire"),
+        leg("walk", "Victoire", "Destination"),
+    ]);
+}
+
+#[test]
+fn journeys_between_coordinates_walk_to_and_from_their_stops() {
+    let mock = MockUpstreams::start("journey_points");
+    let cache = mock.load();
+    let invalid = actix_web::rt::System::new().block_on(async {
+        let app = init_service(App::new().app_data(web::Data::new(app_state(cache.clone()))).configure(api_routes)).await;
+        let mut invalid = Vec::new();
+        for uri in ["/api/tbm/journey?from=44.8450&to_stop=3673", "/api/tbm/journey?from=44.8450,-0.5730&from_stop=3671&to_stop=3673",
+                    "/api/tbm/journey?from_stop=3671&to_stop=3673&departure=2026-05-04T07:50&arrive_by=2026-05-04T08:15"] {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            let status = response.status();
+            let body: Value = read_body_json(response).await;
+            invalid.push((status, body["error"].as_str().unwrap_or_default().to_string()));
+        }
+        invalid
+    });
+    assert_eq!(invalid, [
+        (StatusCode::BAD_REQUEST, "Invalid from '44.8450' (expected lat,lon)".to_string()),
+        (StatusCode::BAD_REQUEST, "Give from_stop or from, not both".to_string()),
+        (StatusCode::BAD_REQUEST, "Give departure or arrive_by, not both".to_string()),
+    ]);
+
+    let responses = call_api(cache, &[
+        "/api/tbm/journey?from=44.8450,-0.5730&to=44.8376,-0.5800&departure=2026-05-04T07:50",
+        "/api/tbm/journey?from=44.8450,-0.5730&to=44.8440,-0.5740&departure=2026-05-04T07:50",
+        "/api/tbm/journey?from_stop=3672&to=44.8360,-0.5815&departure=2026-05-04T08:03",
+    ]);
+    let legs = |body: &Value| -> Vec<(String, String, String)> {
+        body["data"][0]["legs"].as_array().into_iter().flatten()
+            .map(|leg| (leg["mode"].as_str().unwrap_or_default().to_string(),
+                        leg["from_stop_id"].as_str().unwrap_or_default().to_string(),
+                        leg["to_stop_id"].as_str().unwrap_or_default().to_string()))
+            .collect()
+    };
+    let leg = |mode: &str, from: &str, to: &str| (mode.to_string(), from.to_string(), to.to_string());
+
+    // Walk to Quinconces for the tram, then on from Hôtel de Ville
+    assert_eq!(legs(&responses[0].1), [
+        leg("walk", "44.845000,-0.573000", "3671"),
+        leg("transit", "3671", "3673"),
+        leg("walk", "3673", "44.837600,-0.580000"),
+    ]);
+    let journey = &responses[0].1["data"][0];
+    let (walk, tram, last) = (&journey["legs"][0], &journey["legs"][1], &journey["legs"][2]);
+    assert_eq!((walk["from_stop_name"].as_str(), last["to_stop_name"].as_str()), (Some("Origin"), Some("Destination")));
+    assert!(walk["arrival"].as_i64() > walk["departure"].as_i64());
+    assert_eq!(last["departure"], tram["arrival"]);
+    assert_eq!(journey["arrival"], last["arrival"]);
+    assert_eq!(journey["transfers"], 0);
+
+    // Close enough to walk all the way
+    assert_eq!(legs(&responses[1].1), [leg("walk", "44.845000,-0.573000", "44.844000,-0.574000")]);
+
+    // A stop to a point, out of walking distance of Grand Théâtre
+    assert_eq!(legs(&responses[2].1), [
+        leg("walk", "3672", "3671"),
+        leg("transit", "3671", "3673"),
+        leg("walk", "3673", "44.836000,-0.581500"),
+    ]);
+}
+
+#[test]
+fn planned_journeys_are_shared_until_they_expire() {
+    let mock = MockUpstreams::start("journey_share");
+    let state = app_state(mock.load());
+    let shares = state.journey_shares.clone();
+
+    let (created, lookups, invalid) = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new()
+            .app_data(web::Data::new(state))
+            .configure(api_routes)
+            .route("/j/{token}", web::get().to(get_shared_journey))).await;
+        let planned: Value = read_body_json(call_service(&app, TestRequest::get()
+            .uri("/api/tbm/journey?from_stop=3672&to_stop=3673&departure=2026-05-04T08:03").to_request()).await).await;
+        let share = TestRequest::post().uri("/api/tbm/journey/share").set_json(&planned["data"][0]).to_request();
+        let created = call_service(&app, share).await;
+        assert_eq!(created.status(), StatusCode::CREATED);
+        let created: Value = read_body_json(created).await;
+        let token = created["data"]["token"].as_str().expect("token").to_string();
+
+        let mut lookups = Vec::new();
+        for uri in [format!("/j/{}?format=json", token), format!("/j/{}", token)] {
+            let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+            lookups.push((response.status(), String::from_utf8_lossy(&actix_web::test::read_body(response).await).into_owned()));
+        }
+        // Once past its expiry, the link is gone
+        if let Some(journey) = shares.lock().expect("shares").journeys.get_mut(&token) {
+            journey.expires_at = NVTModels::get_current_timestamp() - 1;
+        }
+        let response = call_service(&app, TestRequest::get().uri(&format!("/j/{}", token)).to_request()).await;
+        lookups.push((response.status(), String::new()));
+
+        let mut flying = planned["data"][0].clone();
+        flying["legs"][0]["mode"] = Value::from("fly");
+        let invalid = call_service(&app, TestRequest::post().uri("/api/tbm/journey/share").set_json(&flying).to_request()).await.status();
+        (created, lookups, invalid)
+    });
+
+    assert_eq!(created["data"]["url"].as_str().map(|url| url.len()), Some("/j/".len() + 8));
+    let statuses: Vec<StatusCode> = lookups.iter().map(|(status, _)| *status).collect();
+    assert_eq!(statuses, [StatusCode::OK, StatusCode::OK, StatusCode::NOT_FOUND]);
+    let stored: Value = serde_json::from_str(&lookups[0].1).expect("shared journey");
+    let modes: Vec<&str> = stored["data"]["itinerary"]["legs"].as_array().expect("legs").iter().filter_map(|leg| leg["mode"].as_str()).collect();
+    assert_eq!(modes, ["walk", "transit"]);
+    let page = &lookups[1].1;
+    assert!(page.contains("🚶 Walk from"), "{}", page);
+    assert!(page.contains("<li class=\"walk\">08:0"), "local departure time of the walk: {}", page);
+    assert!(page.contains(">1</span>"), "{}", page);
+    assert!(!page.contains("Transfer at"), "the walk is the change itself: {}", page);
+    assert_eq!(invalid, StatusCode::BAD_REQUEST);
+}
+
+#[test]
+fn journey_shares_are_limited_and_appended_to_their_file() {
+    let mock = MockUpstreams::start("journey_share_limits");
+    let path = std::env::temp_dir().join(format!("nvt-test-journey-shares-{}.jsonl", std::process::id()));
+    std::fs::remove_file(&path).ok();
+    let mut store = JourneyShareStore::open(path.clone());
+    store.limits.per_client_per_minute = 2;
+    store.limits.max_entries = 3;
+    let state = AppState { journey_shares: Arc::new(Mutex::new(store)), ..app_state(mock.load()) };
+
+    let responses = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let planned: Value = read_body_json(call_service(&app, TestRequest::get()
+            .uri("/api/tbm/journey?from_stop=3672&to_stop=3673&departure=2026-05-04T08:03").to_request()).await).await;
+        let mut responses = Vec::new();
+        for client in ["203.0.113.1", "203.0.113.1", "203.0.113.1", "203.0.113.2", "203.0.113.3"] {
+            let share = TestRequest::post().uri("/api/tbm/journey/share")
+                .peer_addr(format!("{}:40000", client).parse().expect("address"))
+                .set_json(&planned["data"][0])
+                .to_request();
+            let response = call_service(&app, share).await;
+            responses.push((response.status(), response.headers().get("Retry-After").is_some()));
+        }
+        responses
+    });
+
+    assert_eq!(responses, [
+        (StatusCode::CREATED, false),
+        (StatusCode::CREATED, false),
+        (StatusCode::TOO_MANY_REQUESTS, true), // third share of the minute from that address
+        (StatusCode::CREATED, false),
+        (StatusCode::SERVICE_UNAVAILABLE, false), // three journeys stored already
+    ]);
+    let lines = std::fs::read_to_string(&path).expect("shares file");
+    assert_eq!(lines.lines().count(), 3, "one line appended per share: {}", lines);
+    assert_eq!(JourneyShareStore::open(path.clone()).journeys.len(), 3);
+    std::fs::remove_file(&path).ok();
+}
+
+#[test]
+fn late_arrivals_run_into_the_next_service_day() {
+    let mock = MockUpstreams::start("overnight");
+    let cache = mock.load();
+    let arrivals = |hour, minute| -> Vec<(String, String)> {
+        let at = chrono::NaiveDate::from_ymd_opt(2026, 5, 4).and_then(|date| date.and_hms_opt(hour, minute, 0)).expect("date");
+        NVTModels::get_scheduled_arrivals("3671", &cache, at, 3).into_iter()
+            .map(|arrival| (arrival.service_date, arrival.departure_time))
+            .collect()
+    };
+    let owned = |rows: &[(&str, &str)]| -> Vec<(String, String)> {
+        rows.iter().map(|(date, time)| (date.to_string(), time.to_string())).collect()
+    };
+
+    assert_eq!(arrivals(23, 50), owned(&[("2026-05-05", "08:00:00"), ("2026-05-05", "08:10:00")]));
+    assert_eq!(arrivals(8, 5), owned(&[("2026-05-04", "08:10:00"), ("2026-05-05", "08:00:00"), ("2026-05-05", "08:10:00")]));
+}
+
+#[test]
+fn line_frequency_averages_headways_by_band() {
+    let mock = MockUpstreams::start("frequency");
+    let mut cache = mock.load();
+
+    // Tram A every 10 minutes from 07:00 to 08:50 (A-1 of the fixture leaves at 08:00), then
+    // every 30 minutes around noon
+    let times: Vec<String> = (0..12).filter(|i| *i != 6).map(|i| format!("{:02}:{:02}:00", 7 + i / 6, i % 6 * 10))
+        .chain(["12:00:00".to_string(), "12:30:00".to_string()])
+        .collect();
+    let trips: String = (0..times.len()).map(|i| format!("59,WEEK,F-{},Le Haillan Rostand,0,,\n", i)).collect();
+    let stop_times: String = times.iter().enumerate()
+        .map(|(i, time)| format!("F-{},{},{},3671,1,\n", i, time, time))
+        .collect();
+    let fragment = zip_files(&[
+        ("routes.txt", "route_id,agency_id,route_short_name,route_long_name,route_desc,route_type,route_url,route_color,route_text_color\n\
+                        59,TBM,A,Tram A,,0,,814997,FFFFFF\n".to_string()),
+        ("trips.txt", format!("route_id,service_id,trip_id,trip_headsign,direction_id,block_id,shape_id\n{}", trips)),
+        ("stop_times.txt", format!("trip_id,arrival_time,departure_time,stop_id,stop_sequence,stop_headsign\n{}", stop_times)),
+    ]);
+    let fragment = NVTModels::parse_gtfs_fragment("tbm", &fragment).expect("fragment");
+    NVTModels::merge_gtfs_fragment(&mut cache, "tbm", fragment).expect("merge");
+    let state = app_state(cache);
+
+    let responses = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let mut responses = Vec::new();
+        for path in ["/api/tbm/line/a/frequency", "/api/tbm/line/ZZ/frequency"] {
+            let response = call_service(&app, TestRequest::get().uri(path).to_request()).await;
+            let status = response.status().as_u16();
+            responses.push((status, read_body_json::<Value, _>(response).await));
+        }
+        responses
+    });
+
+    let (status, body) = &responses[0];
+    assert_eq!(*status, 200);
+    assert_eq!(body["data"]["route_id"], "59");
+    let weekday = body["data"]["headways"].as_array().expect("headways").iter()
+        .find(|h| h["direction_id"] == 0 && h["day_type"] == "weekday")
+        .expect("weekday headways of direction 0")
+        .clone();
+    assert_eq!(weekday["departures"], 14);
+    assert_eq!((weekday["first_departure"].clone(), weekday["last_departure"].clone()), (Value::from("07:00:00"), Value::from("12:30:00")));
+    assert_eq!(weekday["average_headway_min"], 25.4);
+    assert_eq!(weekday["peak_headway_min"], 10.0);
+    let band = |name: &str| weekday["bands"].as_array().expect("bands").iter()
+        .find(|band| band["band"] == name).cloned().expect("band");
+    assert_eq!((band("morning_peak")["departures"].clone(), band("morning_peak")["average_headway_min"].clone()), (Value::from(12), Value::from(10.0)));
+    assert_eq!(band("day")["average_headway_min"], 30.0);
+    assert_eq!(band("evening")["average_headway_min"], Value::Null);
+
+    assert_eq!(responses[1].0, 404);
+}
+
+#[test]
+fn simulation_accepts_fragments_over_the_default_body_limit() {
+    let state = app_state(CachedNetworkData::empty(MockUpstreams::start("simulation-limit").upstreams()));
+    let statuses = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let fragment = vec![0u8; 300 * 1024];
+        let post = TestRequest::post().uri("/admin/simulate").set_payload(fragment).to_request();
+        let delete = TestRequest::delete().uri("/admin/simulate").to_request();
+        (call_service(&app, post).await.status(), call_service(&app, delete).await.status())
+    });
+    // Read in full and handed to the handler, which refuses it as simulation is off here
+    assert_eq!(statuses, (StatusCode::NOT_FOUND, StatusCode::NOT_FOUND));
+}
+
+#[test]
+fn line_badges_are_readable_on_light_line_colors() {
+    // A yellow line whose feed gives it white text
+    let mock = MockUpstreams::start("line-badge");
+    let mut cache = mock.load();
+    let fragment = zip_files(&[
+        ("routes.txt", "route_id,agency_id,route_short_name,route_long_name,route_desc,route_type,route_url,route_color,route_text_color\n\
+                        G,TBM,G,Bus Express G,,3,,FFE600,FFFFFF\n".to_string()),
+        ("trips.txt", "route_id,service_id,trip_id,trip_headsign,direction_id,block_id,shape_id\n\
+                       G,WEEK,G-1,Gare Saint-Jean,0,,\n".to_string()),
+        ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence,stop_headsign\n\
+                            G-1,08:20:00,08:20:00,3671,1,\n".to_string()),
+    ]);
+    let fragment = NVTModels::parse_gtfs_fragment("tbm", &fragment).expect("fragment");
+    NVTModels::merge_gtfs_fragment(&mut cache, "tbm", fragment).expect("merge");
+    let state = app_state(cache);
+
+    let responses = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let mut responses = Vec::new();
+        for uri in ["/api/tbm/line/g/badge.svg", "/api/tbm/line/ZZ/badge.svg"] {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            let content_type = response.headers().get("Content-Type").and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+            responses.push((response.status(), content_type, String::from_utf8_lossy(&actix_web::test::read_body(response).await).into_owned()));
+        }
+        responses
+    });
+
+    let (status, content_type, svg) = &responses[0];
+    assert_eq!((*status, content_type.as_str()), (StatusCode::OK, "image/svg+xml"));
+    let fills: Vec<&str> = svg.split("fill=\"#").skip(1).filter_map(|rest| rest.get(..6)).collect();
+    assert_eq!(fills, ["FFE600", "000000"], "{}", svg);
+    assert!(colors::contrast_ratio(fills[0], fills[1]) >= 4.5, "{}", svg);
+    assert!(svg.contains(">G</text>"), "{}", svg);
+
+    let (status, _, body) = &responses[1];
+    assert_eq!(*status, StatusCode::NOT_FOUND);
+    let body: Value = serde_json::from_str(body).expect("error body");
+    assert_eq!(body["error"], "Line 'ZZ' not found");
+}
+
+#[test]
+fn text_board_lays_out_departures_in_columns() {
+    fn morning() -> chrono::DateTime<chrono_tz::Tz> {
+        chrono::TimeZone::with_ymd_and_hms(&chrono_tz::Europe::Paris, 2026, 5, 4, 8, 5, 0).single().expect("time")
+    }
+
+    // A shuttle with a long code and destination at Quinconces, and a stop nothing serves
+    let mock = MockUpstreams::start("text-board");
+    let mut cache = mock.load();
+    let fragment = zip_files(&[
+        ("stops.txt", "stop_id,stop_code,stop_name,stop_desc,stop_lat,stop_lon\n\
+                       3674,PBOU,Porte de Bourgogne,,44.8376,-0.5676\n".to_string()),
+        ("routes.txt", "route_id,agency_id,route_short_name,route_long_name,route_desc,route_type,route_url,route_color,route_text_color\n\
+                        NAVETTE-STADE,TBM,Navette Stade,Navette Stade,,3,,E4007C,FFFFFF\n".to_string()),
+        ("trips.txt", "route_id,service_id,trip_id,trip_headsign,direction_id,block_id,shape_id\n\
+                       NAVETTE-STADE,WEEK,NS-1,Stade Matmut Atlantique par les boulevards,0,,\n".to_string()),
+        ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence,stop_headsign\n\
+                            NS-1,08:20:00,08:20:00,3671,1,\n".to_string()),
+    ]);
+    let fragment = NVTModels::parse_gtfs_fragment("tbm", &fragment).expect("fragment");
+    NVTModels::merge_gtfs_fragment(&mut cache, "tbm", fragment).expect("merge");
+    let state = AppState { clock: morning, ..app_state(cache) };
+
+    let boards = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+        let mut boards = Vec::new();
+        for uri in ["/api/tbm/stop/3671/departures.txt?limit=2", "/api/tbm/stop/3674/departures.txt"] {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+            boards.push(String::from_utf8_lossy(&actix_web::test::read_body(response).await).into_owned());
+        }
+        boards
+    });
+
+    // The code column widens to the longest code, the destination is cut to its column
+    assert_eq!(boards[0], "\
+        Quinconces (3671)\nUpdated 08:05\n\
+        1              Aéroport                   5 min ~\n\
+        NAVETTE-STADE  Stade Matmut Atlantique   15 min ~\n\
+        ~ = timetable, no real-time data\n");
+    assert_eq!(boards[1], "Porte de Bourgogne (3674)\nUpdated 08:05\nNo upcoming departures\n");
+}
+
+#[test]
+fn departures_follow_the_paris_clock_whatever_the_server_zone() {
+    fn morning() -> chrono::DateTime<chrono_tz::Tz> {
+        chrono::TimeZone::with_ymd_and_hms(&chrono_tz::Europe::Paris, 2026, 5, 4, 8, 5, 0).single().expect("time")
+    }
+    fn late_evening() -> chrono::DateTime<chrono_tz::Tz> {
+        chrono::TimeZone::with_ymd_and_hms(&chrono_tz::Europe::Paris, 2026, 5, 4, 23, 50, 0).single().expect("time")
+    }
+
+    // 08:05 in Paris is 06:05 UTC, when a clock in the server's zone would still list the 08:00
+    let mock = MockUpstreams::start("paris-clock");
+    let departures_at = |clock: fn() -> chrono::DateTime<chrono_tz::Tz>| -> Vec<String> {
+        let state = AppState { clock, ..app_state(mock.load()) };
+        actix_web::rt::System::new().block_on(async move {
+            let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+            let mut times = Vec::new();
+            for uri in ["/api/tbm/stop/3671/arrivals?limit=2&time_format=rfc3339", "/api/tbm/stop/3671/departures?limit=2&time_format=rfc3339"] {
+                let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+                assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+                let body: Value = read_body_json(response).await;
+                let rows = body["data"].as_array().cloned().unwrap_or_default();
+                times.extend(rows.iter().map(|row| {
+                    row["departure_at"].as_str().or(row["expected_departure_at"].as_str()).unwrap_or_default().to_string()
+                }));
+            }
+            times
+        })
+    };
+
+    assert_eq!(departures_at(morning), [
+        "2026-05-04T08:10:00+02:00", "2026-05-05T08:00:00+02:00",
+        "2026-05-04T08:10:00+02:00", "2026-05-05T08:00:00+02:00",
+    ]);
+    assert_eq!(departures_at(late_evening), [
+        "2026-05-05T08:00:00+02:00", "2026-05-05T08:10:00+02:00",
+        "2026-05-05T08:00:00+02:00", "2026-05-05T08:10:00+02:00",
+    ]);
+}
+
+#[test]
+fn departures_take_the_delay_of_the_run_of_the_trip_update() {
+    fn after_midnight() -> chrono::DateTime<chrono_tz::Tz> {
+        chrono::TimeZone::with_ymd_and_hms(&chrono_tz::Europe::Paris, 2026, 5, 5, 0, 10, 0).single().expect("time")
+    }
+
+    // 00:10 on 2026-05-05: the A-1 left at Grand Théâtre (3673) is the run of the 5th, at 08:05
+    let mock = MockUpstreams::start("run-dates");
+    let departure_of = |start_date: Option<&str>| -> (String, Value) {
+        let mut update = trip_update("A-1", "59", "3673", 900);
+        if let Some(trip_update) = update.trip_update.as_mut() {
+            trip_update.trip.start_date = start_date.map(str::to_string);
+        }
+        mock.respond(TRIP_UPDATES_PATH, 200, feed(vec![update]));
+        let state = AppState { clock: after_midnight, ..app_state(mock.load()) };
+        let body: Value = actix_web::rt::System::new().block_on(async move {
+            let app = init_service(App::new().app_data(web::Data::new(state)).configure(api_routes)).await;
+            let response = call_service(&app, TestRequest::get().uri("/api/tbm/stop/3673/departures?limit=1").to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            read_body_json(response).await
+        });
+        let departure = &body["data"][0];
+        assert_eq!((departure["trip_id"].as_str(), departure["service_date"].as_str()), (Some("A-1"), Some("2026-05-05")));
+        (departure["status"].as_str().unwrap_or_default().to_string(), departure["delay"].clone())
+    };
+
+    // The run of the 4th, still in the feed past midnight, is not the one at the stop
+    assert_eq!(departure_of(Some("20260504")), ("scheduled".to_string(), Value::Null));
+    assert_eq!(departure_of(Some("20260505")), ("realtime".to_string(), Value::from(900)));
+    assert_eq!(departure_of(None), ("realtime".to_string(), Value::from(900)), "no start date: the trip's next run");
+}
+
+#[test]
+fn simulation_sandbox_leaves_live_data_alone() {
+    let mock = MockUpstreams::start("simulation");
+    let state = app_state(mock.load());
+
+    // A new bus line to a new stop, and tram A retimed to skip Grand Théâtre
+    let fragment = zip_files(&[
+        ("routes.txt", "route_id,agency_id,route_short_name,route_long_name,route_desc,route_type,route_url,route_color,route_text_color\n\
+                        BUS9,TBM,9,Gare Test,,3,,FF0000,FFFFFF\n"),
+        ("trips.txt", "route_id,service_id,trip_id,trip_headsign,direction_id,block_id,shape_id\n\
+                       BUS9,WEEK,B9-1,Gare Test,0,,\n\
+                       59,WEEK,A-1,Le Haillan Rostand,0,,A-0\n"),
+        ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence,stop_headsign\n\
+                            B9-1,09:00:00,09:00:00,3671,1,\n\
+                            B9-1,09:10:00,09:10:00,9001,2,\n\
+                            A-1,08:30:00,08:30:00,3671,1,\n\
+                            A-1,08:36:00,08:36:00,3673,2,\n"),
+        ("stops.txt", "stop_id,stop_code,stop_name,stop_desc,stop_lat,stop_lon\n9001,GTST,Gare Test,,44.8300,-0.5600\n"),
+    ]);
+    let fragment = NVTModels::parse_gtfs_fragment("tbm", &fragment).expect("fragment");
+    let live = state.cache.lock().expect("cache").clone();
+    let (sandbox, summary) = simulation::simulate(live, "tbm", fragment).expect("simulation");
+    assert_eq!((summary.routes, summary.trips, summary.stops, summary.timed_trips), (2, 2, 1, 2));
+
+    let sandbox_state = state.clone();
+    let responses = actix_web::rt::System::new().block_on(async move {
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(state.clone()))
+                .configure(api_routes)
+                .service(sandbox_routes(&state))
+        ).await;
+        let get = |path: &'static str| TestRequest::get().uri(path).to_request();
+
+        let before = call_service(&app, get("/api/sandbox/tbm/lines")).await.status();
+        *sandbox_state.sandbox.lock().expect("sandbox") = sandbox;
+        let mut responses = vec![(before.as_u16(), Value::Null)];
+        for path in [
+            "/api/sandbox/tbm/line/BUS9",
+            "/api/tbm/line/BUS9",
+            "/api/sandbox/tbm/line/A/timetable?date=2026-05-04&stop=3671",
+            "/api/tbm/line/A/timetable?date=2026-05-04&stop=3671",
+            "/api/sandbox/tbm/stop/9001",
+        ] {
+            let response = call_service(&app, get(path)).await;
+            let status = response.status().as_u16();
+            responses.push((status, read_body_json(response).await));
+        }
+        responses
+    });
+
+    assert_eq!(responses[0].0, 404, "nothing is served before a simulation");
+    assert_eq!(responses[1].0, 200);
+    assert_eq!(responses[1].1["data"]["line_code"], "BUS9");
+    assert_eq!(responses[1].1["data"]["color"], "FF0000");
+    assert_eq!(responses[2].0, 404, "the live API doesn't see the new line");
+    let departures = |body: &Value| -> Vec<String> {
+        body["data"].as_array().into_iter().flatten()
+            .map(|arrival| arrival["departure_time"].as_str().unwrap_or_default().to_string())
+            .collect()
+    };
+    assert_eq!(departures(&responses[3].1), ["08:30:00"]);
+    assert_eq!(departures(&responses[4].1), ["08:00:00"]);
+    assert_eq!(responses[5].0, 200);
+    assert_eq!(responses[5].1["data"]["stop_name"], "Gare Test");
+}
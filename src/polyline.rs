@@ -0,0 +1,92 @@
+// Encoded polyline format and shape simplification
+// Google's encoded polyline algorithm at precision 5 (about a meter), which Google Maps, the
+// mobile map SDKs and the Leaflet/MapLibre polyline plugins decode: a shape comes out several
+// times smaller than as a list of points. Douglas-Peucker simplification drops the points a
+// map would not show at a given tolerance; SNCF shapes go from tens of thousands of points to
+// a few hundred at city zooms.
+
+use crate::tbm_api_models::ShapePoint;
+
+/// Meters per pixel of a 256px map tile at zoom 0, on the equator
+const EQUATOR_METERS_PER_PIXEL: f64 = 156_543.034;
+
+/// Latitude the map scale is taken at, that of the region served
+const REFERENCE_LATITUDE: f64 = 45.0;
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+pub fn encode(points: &[ShapePoint]) -> String {
+    let mut encoded = String::new();
+    let (mut previous_lat, mut previous_lon) = (0, 0);
+    for point in points {
+        let lat = (point.latitude * 1e5).round() as i64;
+        let lon = (point.longitude * 1e5).round() as i64;
+        encode_value(lat - previous_lat, &mut encoded);
+        encode_value(lon - previous_lon, &mut encoded);
+        (previous_lat, previous_lon) = (lat, lon);
+    }
+    encoded
+}
+
+/// Zigzag-encoded delta in 5-bit chunks, lowest first, each offset by 63 into printable ASCII
+fn encode_value(delta: i64, encoded: &mut String) {
+    let mut value = if delta < 0 { !(delta << 1) } else { delta << 1 };
+    while value >= 0x20 {
+        encoded.push(char::from((0x20 | (value & 0x1f)) as u8 + 63));
+        value >>= 5;
+    }
+    encoded.push(char::from(value as u8 + 63));
+}
+
+/// Tolerance of simplify for a map at `zoom`: the size of a pixel
+pub fn zoom_tolerance(zoom: u8) -> f64 {
+    EQUATOR_METERS_PER_PIXEL * REFERENCE_LATITUDE.to_radians().cos() / 2f64.powi(i32::from(zoom))
+}
+
+/// Douglas-Peucker: the points of a shape that keep it within `tolerance_m` meters of the
+/// original, first and last always kept
+pub fn simplify(points: &[ShapePoint], tolerance_m: f64) -> Vec<ShapePoint> {
+    if points.len() <= 2 {
+        return points.to_vec();
+    }
+
+    // Meters on a plane tangent at the first point, close enough over the length of a shape
+    let origin = &points[0];
+    let scale_x = EARTH_RADIUS_M * origin.latitude.to_radians().cos();
+    let planar: Vec<(f64, f64)> = points.iter()
+        .map(|point| (
+            (point.longitude - origin.longitude).to_radians() * scale_x,
+            (point.latitude - origin.latitude).to_radians() * EARTH_RADIUS_M,
+        ))
+        .collect();
+
+    let mut kept = vec![false; points.len()];
+    kept[0] = true;
+    kept[points.len() - 1] = true;
+    // Ranges left to split; a stack rather than recursion, shapes can be long
+    let mut ranges = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = ranges.pop() {
+        let farthest = (first + 1..last)
+            .map(|index| (index, segment_distance(planar[index], planar[first], planar[last])))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((index, _)) = farthest.filter(|(_, distance)| *distance > tolerance_m) {
+            kept[index] = true;
+            ranges.extend([(first, index), (index, last)]);
+        }
+    }
+
+    points.iter().zip(kept).filter(|(_, kept)| *kept).map(|(point, _)| point.clone()).collect()
+}
+
+/// Distance from `point` to the segment from `start` to `end`
+fn segment_distance(point: (f64, f64), start: (f64, f64), end: (f64, f64)) -> f64 {
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        (((point.0 - start.0) * dx + (point.1 - start.1) * dy) / length_squared).clamp(0.0, 1.0)
+    };
+    let (x, y) = (start.0 + t * dx, start.1 + t * dy);
+    ((point.0 - x).powi(2) + (point.1 - y).powi(2)).sqrt()
+}
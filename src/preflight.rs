@@ -0,0 +1,220 @@
+// Preflight checks (`check` command)
+// Meant for deployment pipelines, before traffic goes to a new instance: the config file parses
+// and makes sense, every upstream answers a HEAD request, and the cache directory is writable
+// with room for the GTFS caches. Prints one line per check and exits with 0 when none failed,
+// 1 otherwise. Warnings (a check that could not run, an upstream answering 4xx) don't fail.
+
+use chrono::NaiveTime;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use crate::config::AppConfig;
+use crate::tbm_api_models::Upstreams;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: Status, detail: impl Into<String>) -> Self {
+        Check { name: name.into(), status, detail: detail.into() }
+    }
+}
+
+pub struct PreflightOptions {
+    pub min_free_mb: u64, // Free space the cache directory needs
+    pub timeout: Duration, // Per upstream request
+}
+
+impl PreflightOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = PreflightOptions { min_free_mb: 1024, timeout: Duration::from_secs(10) };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().cloned().ok_or_else(|| format!("{} needs a value", name));
+            let number = |name: &str, value: String| value.parse::<u64>()
+                .map_err(|_| format!("{} expects a number, got '{}'", name, value));
+            match arg.as_str() {
+                "--min-free-mb" => options.min_free_mb = number(arg, value(arg)?)?,
+                "--timeout-secs" => options.timeout = Duration::from_secs(number(arg, value(arg)?)?.max(1)),
+                other => return Err(format!("Unknown check option '{}'", other)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+// ============================================================================
+// Checks
+// ============================================================================
+
+/// Parse the config file and check what can be checked offline
+pub fn check_config(path: &Path) -> Vec<Check> {
+    let config = match AppConfig::read(path) {
+        Ok(Some(config)) => config,
+        Ok(None) => return vec![Check::new("config", Status::Pass, format!("no file at {:?}, built-in defaults", path))],
+        Err(e) => return vec![Check::new("config", Status::Fail, e)],
+    };
+
+    let mut checks = vec![Check::new("config", Status::Pass, format!("{:?} parsed", path))];
+    if let Some(url) = config.public_url.as_ref().filter(|url| !url.starts_with("http://") && !url.starts_with("https://")) {
+        checks.push(Check::new("config public_url", Status::Fail, format!("'{}' is not an http(s) URL", url)));
+    }
+    if let Some(digest) = &config.digest {
+        if NaiveTime::parse_from_str(&digest.send_at, "%H:%M").is_err() {
+            checks.push(Check::new("config digest", Status::Fail, format!("send_at '{}' is not HH:MM", digest.send_at)));
+        }
+        if digest.smtp.username.is_some() && digest.smtp.resolve_password().is_none() {
+            checks.push(Check::new("config digest", Status::Fail, "SMTP username without a password (or NVT_SMTP_PASSWORD)"));
+        }
+    }
+    if let Some(bot) = &config.bot {
+        if !cfg!(feature = "bot") {
+            checks.push(Check::new("config bot", Status::Warn, "configured, but this build lacks the \"bot\" feature"));
+        } else if bot.resolve_token().is_none() {
+            checks.push(Check::new("config bot", Status::Fail, "no token (or NVT_BOT_TOKEN)"));
+        }
+    }
+    for (i, feed) in config.feeds.iter().enumerate() {
+        if config.feeds[..i].iter().any(|other| other.name.eq_ignore_ascii_case(&feed.name)) {
+            checks.push(Check::new("config feeds", Status::Fail, format!("feed '{}' is listed twice", feed.name)));
+        }
+        if !feed.is_built_in() && feed.id_prefix.is_none() {
+            checks.push(Check::new("config feeds", Status::Warn, format!("feed '{}' has no id_prefix, its ids may collide", feed.name)));
+        }
+    }
+    if let Some(assets_dir) = config.branding.assets_dir.as_ref().filter(|dir| !dir.is_dir()) {
+        checks.push(Check::new("config branding", Status::Fail, format!("assets_dir {:?} is not a directory", assets_dir)));
+    }
+    checks
+}
+
+/// HEAD request to every upstream the server fetches from
+pub fn check_upstreams(upstreams: &Upstreams, config: &AppConfig, timeout: Duration) -> Vec<Check> {
+    let mut targets = Vec::new();
+    if upstreams.has_source("TBM") {
+        targets.push(("TBM API".to_string(), upstreams.tbm_api_url.clone()));
+    }
+    for feed in &upstreams.feeds {
+        targets.push((format!("{} GTFS", feed.name), feed.static_url.clone()));
+        for (i, url) in feed.rt_urls.iter().enumerate() {
+            targets.push((format!("{} GTFS-RT {}", feed.name, i + 1), url.clone()));
+        }
+    }
+    targets.push(("Communes".to_string(), upstreams.communes_url.clone()));
+    targets.push(("Bike share GBFS".to_string(), upstreams.gbfs_url.clone()));
+    if let Some(amenities) = &config.amenities {
+        targets.push(("Overpass".to_string(), amenities.overpass_url.clone()));
+    }
+    if let Some(osrm_url) = config.map_matching.as_ref().and_then(|map_matching| map_matching.osrm_url.clone()) {
+        targets.push(("OSRM".to_string(), osrm_url));
+    }
+    if let Some(navitia) = &config.navitia {
+        targets.push(("Navitia".to_string(), navitia.base_url.clone()));
+    }
+
+    let client = match reqwest::blocking::Client::builder().timeout(timeout).build() {
+        Ok(client) => client,
+        Err(e) => return vec![Check::new("upstreams", Status::Fail, format!("no HTTP client ({})", e))],
+    };
+    targets.into_iter()
+        .map(|(name, url)| {
+            let name = format!("upstream {}", name);
+            match client.head(&url).send() {
+                Ok(response) if response.status().is_server_error() => {
+                    Check::new(name, Status::Fail, format!("{} answered {}", url, response.status()))
+                }
+                // Reachable; API roots often answer 404 or 405 to HEAD
+                Ok(response) if response.status().is_client_error() => {
+                    Check::new(name, Status::Warn, format!("{} answered {}", url, response.status()))
+                }
+                Ok(response) => Check::new(name, Status::Pass, format!("{} answered {}", url, response.status())),
+                Err(e) => Check::new(name, Status::Fail, format!("{} unreachable ({})", url, e)),
+            }
+        })
+        .collect()
+}
+
+/// The cache directory exists or can be created, takes writes, and has `min_free_mb` free
+pub fn check_cache_dir(cache_dir: &Path, min_free_mb: u64) -> Vec<Check> {
+    let probe = cache_dir.join(".nvt-preflight");
+    let writable = fs::create_dir_all(cache_dir)
+        .and_then(|_| fs::write(&probe, b"preflight"))
+        .and_then(|_| fs::remove_file(&probe));
+    let mut checks = vec![match writable {
+        Ok(()) => Check::new("cache directory", Status::Pass, format!("{:?} is writable", cache_dir)),
+        Err(e) => return vec![Check::new("cache directory", Status::Fail, format!("{:?} is not writable ({})", cache_dir, e))],
+    }];
+
+    checks.push(match free_space_mb(cache_dir) {
+        Some(free) if free >= min_free_mb => Check::new("disk space", Status::Pass, format!("{} MB free", free)),
+        Some(free) => Check::new("disk space", Status::Fail, format!("{} MB free, {} MB needed", free, min_free_mb)),
+        None => Check::new("disk space", Status::Warn, "could not be measured (no df)"),
+    });
+    checks
+}
+
+/// Available space on the filesystem of `path`, from POSIX `df`
+fn free_space_mb(path: &Path) -> Option<u64> {
+    let output = Command::new("df").arg("-Pk").arg(path).output().ok()?;
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let available_kb: u64 = stdout.lines().nth(1)?.split_whitespace().nth(3)?.parse().ok()?;
+    Some(available_kb / 1024)
+}
+
+// ============================================================================
+// Report
+// ============================================================================
+
+pub fn run_checks(config_path: &Path, upstreams: &Upstreams, options: &PreflightOptions) -> Vec<Check> {
+    let config = AppConfig::read(config_path).ok().flatten().unwrap_or_default();
+    let mut checks = check_config(config_path);
+    checks.extend(check_upstreams(upstreams, &config, options.timeout));
+    checks.extend(check_cache_dir(&upstreams.cache_dir, options.min_free_mb));
+    checks
+}
+
+/// `check [--min-free-mb N] [--timeout-secs N]`; returns the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let options = match PreflightOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("❌ {}", e);
+            return 2;
+        }
+    };
+
+    println!("🩺 Preflight checks");
+    let checks = run_checks(&AppConfig::config_path(), &Upstreams::default(), &options);
+    for check in &checks {
+        let icon = match check.status {
+            Status::Pass => "✅",
+            Status::Warn => "⚠️ ",
+            Status::Fail => "❌",
+        };
+        println!("{} {:<28} {}", icon, check.name, check.detail);
+    }
+
+    let failed = checks.iter().filter(|check| check.status == Status::Fail).count();
+    let warned = checks.iter().filter(|check| check.status == Status::Warn).count();
+    if failed > 0 {
+        println!("\n❌ {} of {} checks failed ({} warnings)", failed, checks.len(), warned);
+        1
+    } else {
+        println!("\n✓ All {} checks passed ({} warnings)", checks.len(), warned);
+        0
+    }
+}
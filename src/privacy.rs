@@ -0,0 +1,71 @@
+// Client address privacy
+// Public-sector deployments in France must not keep client IPs they don't need. In the "truncate"
+// and "hash" modes of PrivacyConfig, the access log (access_log.rs) shows a truncated address or a salted hash
+// instead of the address itself. The salt is random, lives only in memory and is replaced every
+// `salt_rotation_hours`, so hashes can't be reversed or linked across periods, yet stay stable
+// long enough to count or throttle clients.
+
+use actix_web::dev::{ConnectionInfo, ServiceRequest};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{IpMode, PrivacyConfig};
+
+pub struct IpAnonymizer {
+    mode: IpMode,
+    rotation: Duration,
+    salt: Mutex<(Instant, RandomState)>, // When the salt was drawn, keyed hasher built from it
+}
+
+impl IpAnonymizer {
+    pub fn new(config: &PrivacyConfig) -> Self {
+        IpAnonymizer {
+            mode: config.client_ips,
+            rotation: Duration::from_secs(config.salt_rotation_hours.max(1) * 3600),
+            salt: Mutex::new((Instant::now(), RandomState::new())),
+        }
+    }
+
+    /// How `ip` may be shown or kept under the configured mode
+    pub fn anonymize(&self, ip: IpAddr) -> String {
+        match self.mode {
+            IpMode::Full => ip.to_string(),
+            IpMode::Truncate => truncate(ip).to_string(),
+            IpMode::Hash => self.hash(ip),
+        }
+    }
+
+    fn hash(&self, ip: IpAddr) -> String {
+        let mut salt = match self.salt.lock() {
+            Ok(salt) => salt,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if salt.0.elapsed() >= self.rotation {
+            *salt = (Instant::now(), RandomState::new());
+        }
+        format!("{:016x}", salt.1.hash_one(ip))
+    }
+}
+
+/// Network part of `ip`: IPv4 addresses keep 24 bits, IPv6 addresses 48
+pub fn truncate(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from(u32::from(ip) & 0xffff_ff00)),
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & !((1u128 << 80) - 1))),
+    }
+}
+
+/// Client address of a request: the proxy-reported one (Forwarded, X-Forwarded-For) or the peer's
+pub fn client_ip(req: &ServiceRequest) -> Option<IpAddr> {
+    remote_ip(&req.connection_info())
+}
+
+/// client_ip, for handlers
+pub fn remote_ip(info: &ConnectionInfo) -> Option<IpAddr> {
+    let addr = info.realip_remote_addr()?;
+    addr.parse::<IpAddr>().ok()
+        .or_else(|| addr.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
@@ -0,0 +1,710 @@
+// QR code encoder
+// Minimal QR Code Model 2 generator (byte mode, error correction levels L to H, versions 1-10),
+// enough for the deep links printed on stop-pole stickers. Follows ISO/IEC 18004.
+
+// ============================================================================
+// Tables
+// ============================================================================
+
+const MAX_VERSION: usize = 10;
+// Indexed by error correction level (L, M, Q, H), then version; index 0 unused
+const ECC_CODEWORDS_PER_BLOCK: [[usize; MAX_VERSION + 1]; 4] = [
+    [0, 7, 10, 15, 20, 26, 18, 20, 24, 30, 18],
+    [0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26],
+    [0, 13, 22, 18, 26, 18, 24, 18, 22, 20, 24],
+    [0, 17, 28, 22, 16, 22, 28, 26, 26, 24, 28],
+];
+const NUM_ECC_BLOCKS: [[usize; MAX_VERSION + 1]; 4] = [
+    [0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 4],
+    [0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5],
+    [0, 1, 1, 2, 2, 4, 4, 6, 6, 8, 8],
+    [0, 1, 1, 2, 4, 4, 4, 5, 6, 8, 8],
+];
+const QUIET_ZONE: usize = 4;
+
+/// Share of the codewords that can be restored: about 7%, 15%, 25% and 30%
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EcLevel {
+    L,
+    M,
+    Q,
+    H,
+}
+
+impl EcLevel {
+    /// Level of a `?ec=` value, any case
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_uppercase().as_str() {
+            "L" => Some(EcLevel::L),
+            "M" => Some(EcLevel::M),
+            "Q" => Some(EcLevel::Q),
+            "H" => Some(EcLevel::H),
+            _ => None,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The two level bits of the format information
+    fn format_bits(self) -> u32 {
+        match self {
+            EcLevel::L => 0b01,
+            EcLevel::M => 0b00,
+            EcLevel::Q => 0b11,
+            EcLevel::H => 0b10,
+        }
+    }
+}
+
+pub struct QrCode {
+    size: usize,
+    level: EcLevel,
+    modules: Vec<Vec<bool>>, // [y][x], true = dark
+}
+
+// ============================================================================
+// Encoding
+// ============================================================================
+
+impl QrCode {
+    /// Encode text in byte mode, picking the smallest version that fits at this level
+    pub fn encode(text: &str, level: EcLevel) -> Option<Self> {
+        let data = text.as_bytes();
+
+        let version = (1..=MAX_VERSION).find(|&v| {
+            let count_bits = if v < 10 { 8 } else { 16 };
+            4 + count_bits + data.len() * 8 <= num_data_codewords(v, level) * 8
+        })?;
+
+        let codewords = add_ecc_and_interleave(&encode_data(data, version, level), version, level);
+
+        let size = version * 4 + 17;
+        let mut qr = QrCode { size, level, modules: vec![vec![false; size]; size] };
+        let mut is_function = vec![vec![false; size]; size];
+
+        qr.draw_function_patterns(version, &mut is_function);
+        qr.draw_codewords(&codewords, &is_function);
+
+        // Keep the mask with the lowest penalty
+        let mut best: Option<(u32, u8)> = None;
+        for mask in 0..8u8 {
+            qr.apply_mask(mask, &is_function);
+            qr.draw_format_bits(mask, &mut is_function);
+            let penalty = qr.penalty_score();
+            if best.is_none_or(|(p, _)| penalty < p) {
+                best = Some((penalty, mask));
+            }
+            qr.apply_mask(mask, &is_function); // XOR again to undo
+        }
+
+        let (_, mask) = best?;
+        qr.apply_mask(mask, &is_function);
+        qr.draw_format_bits(mask, &mut is_function);
+        Some(qr)
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool, is_function: &mut [Vec<bool>]) {
+        self.modules[y][x] = dark;
+        is_function[y][x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize, is_function: &mut [Vec<bool>]) {
+        let size = self.size;
+
+        // Timing patterns
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0, is_function);
+            self.set_function(i, 6, i % 2 == 0, is_function);
+        }
+
+        // Finder patterns with their separators
+        for (cx, cy) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4i32..=4 {
+                for dx in -4i32..=4 {
+                    let (x, y) = (cx as i32 + dx, cy as i32 + dy);
+                    if x >= 0 && y >= 0 && (x as usize) < size && (y as usize) < size {
+                        let dist = dx.abs().max(dy.abs());
+                        self.set_function(x as usize, y as usize, dist != 2 && dist != 4, is_function);
+                    }
+                }
+            }
+        }
+
+        // Alignment patterns, except where they would overlap the finders
+        let positions = alignment_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &cy) in positions.iter().enumerate() {
+            for (j, &cx) in positions.iter().enumerate() {
+                if (i == 0 && (j == 0 || j == last)) || (i == last && j == 0) {
+                    continue;
+                }
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        let dark = dx.abs().max(dy.abs()) != 1;
+                        self.set_function((cx as i32 + dx) as usize, (cy as i32 + dy) as usize, dark, is_function);
+                    }
+                }
+            }
+        }
+
+        // Reserve format areas (real bits drawn once the mask is known)
+        self.draw_format_bits(0, is_function);
+
+        // Version information (version 7 and up)
+        if version >= 7 {
+            let mut rem = version as u32;
+            for _ in 0..12 {
+                rem = (rem << 1) ^ ((rem >> 11) * 0x1F25);
+            }
+            let bits = ((version as u32) << 12) | rem;
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 != 0;
+                let a = size - 11 + i % 3;
+                let b = i / 3;
+                self.set_function(a, b, dark, is_function);
+                self.set_function(b, a, dark, is_function);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u8, is_function: &mut [Vec<bool>]) {
+        let data = (self.level.format_bits() << 3) | mask as u32;
+        let mut rem = data;
+        for _ in 0..10 {
+            rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+        }
+        let bits = ((data << 10) | rem) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+        let size = self.size;
+
+        // First copy, around the top-left finder
+        for i in 0..=5 {
+            self.set_function(8, i, bit(i), is_function);
+        }
+        self.set_function(8, 7, bit(6), is_function);
+        self.set_function(8, 8, bit(7), is_function);
+        self.set_function(7, 8, bit(8), is_function);
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i), is_function);
+        }
+
+        // Second copy, split between the other two finders
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i), is_function);
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i), is_function);
+        }
+        self.set_function(8, size - 8, true, is_function); // Always-dark module
+    }
+
+    fn draw_codewords(&mut self, codewords: &[u8], is_function: &[Vec<bool>]) {
+        let size = self.size as i32;
+        let total_bits = codewords.len() * 8;
+        let mut i = 0;
+
+        // Zigzag through column pairs from the right, skipping the vertical timing column
+        let mut right = size - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            let upward = ((right + 1) & 2) == 0;
+            for vert in 0..size {
+                for j in 0..2 {
+                    let x = (right - j) as usize;
+                    let y = if upward { size - 1 - vert } else { vert } as usize;
+                    if !is_function[y][x] && i < total_bits {
+                        self.modules[y][x] = (codewords[i >> 3] >> (7 - (i & 7))) & 1 != 0;
+                        i += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u8, is_function: &[Vec<bool>]) {
+        for (y, row) in self.modules.iter_mut().enumerate() {
+            for (x, module) in row.iter_mut().enumerate() {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                if invert && !is_function[y][x] {
+                    *module = !*module;
+                }
+            }
+        }
+    }
+
+    fn penalty_score(&self) -> u32 {
+        let size = self.size;
+        let get = |x: usize, y: usize| self.modules[y][x];
+        let mut penalty = 0;
+
+        // Runs of 5+ same-colored modules, and finder-like 1:1:3:1:1 patterns, in rows and columns
+        const FINDER_LIKE: [bool; 11] = [true, false, true, true, true, false, true, false, false, false, false];
+        for transpose in [false, true] {
+            for a in 0..size {
+                let line: Vec<bool> = (0..size)
+                    .map(|b| if transpose { get(a, b) } else { get(b, a) })
+                    .collect();
+
+                let mut run = 1;
+                for b in 1..size {
+                    if line[b] == line[b - 1] {
+                        run += 1;
+                        if run == 5 {
+                            penalty += 3;
+                        } else if run > 5 {
+                            penalty += 1;
+                        }
+                    } else {
+                        run = 1;
+                    }
+                }
+
+                for window in line.windows(11) {
+                    let reversed = window.iter().rev().copied().eq(FINDER_LIKE.iter().copied());
+                    if window == FINDER_LIKE || reversed {
+                        penalty += 40;
+                    }
+                }
+            }
+        }
+
+        // 2x2 blocks of the same color
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let c = get(x, y);
+                if c == get(x + 1, y) && c == get(x, y + 1) && c == get(x + 1, y + 1) {
+                    penalty += 3;
+                }
+            }
+        }
+
+        // Overall dark/light balance
+        let dark = self.modules.iter().flatten().filter(|&&m| m).count();
+        let total = size * size;
+        let k = ((dark * 20).abs_diff(total * 10)).div_ceil(total).saturating_sub(1);
+        penalty + k as u32 * 10
+    }
+
+    // ========================================================================
+    // Output
+    // ========================================================================
+
+    /// SVG rendering with a quiet zone, `scale` pixels per module
+    pub fn to_svg(&self, scale: usize) -> String {
+        let dimension = self.size + QUIET_ZONE * 2;
+        let mut path = String::new();
+        for (y, row) in self.modules.iter().enumerate() {
+            for (x, &dark) in row.iter().enumerate() {
+                if dark {
+                    path.push_str(&format!("M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE));
+                }
+            }
+        }
+
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{px}\" height=\"{px}\" viewBox=\"0 0 {d} {d}\" shape-rendering=\"crispEdges\">\
+             <rect width=\"{d}\" height=\"{d}\" fill=\"#FFFFFF\"/>\
+             <path d=\"{path}\" fill=\"#000000\"/></svg>",
+            px = dimension * scale,
+            d = dimension,
+            path = path,
+        )
+    }
+}
+
+// ============================================================================
+// Codeword Construction
+// ============================================================================
+
+fn num_raw_data_modules(version: usize) -> usize {
+    let mut result = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let num_align = version / 7 + 2;
+        result -= (25 * num_align - 10) * num_align - 55;
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+fn num_data_codewords(version: usize, level: EcLevel) -> usize {
+    num_raw_data_modules(version) / 8
+        - ECC_CODEWORDS_PER_BLOCK[level.index()][version] * NUM_ECC_BLOCKS[level.index()][version]
+}
+
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let size = version * 4 + 17;
+    let num_align = version / 7 + 2;
+    let step = (version * 4 + num_align * 2 + 1) / (num_align * 2 - 2) * 2;
+    let mut positions: Vec<usize> = (0..num_align - 1).map(|i| size - 7 - i * step).collect();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+/// Mode indicator, character count, payload, terminator and padding
+fn encode_data(data: &[u8], version: usize, level: EcLevel) -> Vec<u8> {
+    let capacity_bits = num_data_codewords(version, level) * 8;
+    let mut bits: Vec<bool> = Vec::with_capacity(capacity_bits);
+    let push = |value: u32, len: usize, bits: &mut Vec<bool>| {
+        for i in (0..len).rev() {
+            bits.push((value >> i) & 1 != 0);
+        }
+    };
+
+    push(0b0100, 4, &mut bits); // Byte mode
+    push(data.len() as u32, if version < 10 { 8 } else { 16 }, &mut bits);
+    for &byte in data {
+        push(byte as u32, 8, &mut bits);
+    }
+
+    let terminator = (capacity_bits - bits.len()).min(4);
+    push(0, terminator, &mut bits);
+    let align = (8 - bits.len() % 8) % 8;
+    push(0, align, &mut bits);
+
+    let mut bytes: Vec<u8> = bits.chunks(8)
+        .map(|chunk| chunk.iter().fold(0u8, |acc, &b| (acc << 1) | b as u8))
+        .collect();
+    for pad in [0xEC, 0x11].iter().cycle() {
+        if bytes.len() >= capacity_bits / 8 {
+            break;
+        }
+        bytes.push(*pad);
+    }
+    bytes
+}
+
+fn add_ecc_and_interleave(data: &[u8], version: usize, level: EcLevel) -> Vec<u8> {
+    let num_blocks = NUM_ECC_BLOCKS[level.index()][version];
+    let ecc_len = ECC_CODEWORDS_PER_BLOCK[level.index()][version];
+    let raw_codewords = num_raw_data_modules(version) / 8;
+    let num_short_blocks = num_blocks - raw_codewords % num_blocks;
+    let short_block_len = raw_codewords / num_blocks;
+
+    let divisor = reed_solomon_divisor(ecc_len);
+    let mut blocks: Vec<Vec<u8>> = Vec::with_capacity(num_blocks);
+    let mut offset = 0;
+    for i in 0..num_blocks {
+        let data_len = short_block_len - ecc_len + usize::from(i >= num_short_blocks);
+        let block_data = &data[offset..offset + data_len];
+        offset += data_len;
+
+        let mut block = block_data.to_vec();
+        if i < num_short_blocks {
+            block.push(0); // Placeholder so all blocks have equal length
+        }
+        block.extend(reed_solomon_remainder(block_data, &divisor));
+        blocks.push(block);
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in 0..blocks[0].len() {
+        for (j, block) in blocks.iter().enumerate() {
+            if i != short_block_len - ecc_len || j >= num_short_blocks {
+                result.push(block[i]);
+            }
+        }
+    }
+    result
+}
+
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((y as u32 >> i) & 1) * x as u32;
+    }
+    z as u8
+}
+
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree];
+    result[degree - 1] = 1;
+    let mut root: u8 = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result[0];
+        result.remove(0);
+        result.push(0);
+        for (r, &d) in result.iter_mut().zip(divisor) {
+            *r ^= gf_multiply(d, factor);
+        }
+    }
+    result
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reference matrices from an independent encoder (Kazuhiko Arase's QRCode for JavaScript),
+    // set to the mask picked here as its own choice doesn't follow the ISO penalty rules
+    const VERSION_1_L: [&str; 21] = [
+        "#######.##..#.#######",
+        "#.....#..#..#.#.....#",
+        "#.###.#.#.#.#.#.###.#",
+        "#.###.#.#..#..#.###.#",
+        "#.###.#.###...#.###.#",
+        "#.....#.......#.....#",
+        "#######.#.#.#.#######",
+        ".........##..........",
+        "####..#.#.#..#..###.#",
+        "##.###.##.#.#...####.",
+        "##.##.###...###..####",
+        "#......#..#.##.#.#.##",
+        ".###.#######.....####",
+        "........###..#.##...#",
+        "#######..#...##.#....",
+        "#.....#....######.#..",
+        "#.###.#..###..##.##..",
+        "#.###.#.#.#..#.#...#.",
+        "#.###.#.####.#.#..#..",
+        "#.....#.#####.####..#",
+        "#######.#####...###..",
+    ];
+    const VERSION_3_M: [&str; 29] = [
+        "#######.#.##.###..###.#######",
+        "#.....#.####.#..#.#.#.#.....#",
+        "#.###.#....##...#.#...#.###.#",
+        "#.###.#.#...#.##.##.#.#.###.#",
+        "#.###.#....#..#.#..#..#.###.#",
+        "#.....#..#...#.######.#.....#",
+        "#######.#.#.#.#.#.#.#.#######",
+        "........#.##...#####.........",
+        "#.##.###.##.##.###....#..#.##",
+        "######..##...###.#.######...#",
+        "..#...#.######..#.#.##.##.##.",
+        "####....#..##...#.#.#####...#",
+        "####..####..#.####.#.....##..",
+        "..#....#.##.#.##.#######..###",
+        "##...##..##.##...#.##.....###",
+        ".##.#....##.#.#.#...##.##..#.",
+        "##..###.####..#.#.#.##..##.#.",
+        ".#.......####.###.#.#..#.###.",
+        "#...###.#...####.....#....#..",
+        "..#.#..#..#..#####...#....#..",
+        ".#.##.###..#.##..##.#######..",
+        "........##.......##.#...#####",
+        "#######.##.##.#.#.###.#.##.#.",
+        "#.....#.#.##.####.#.#...##...",
+        "#.###.#...##...###..#####.##.",
+        "#.###.#.#.#....#.##.##..##..#",
+        "#.###.#.##....#....#...#..#.#",
+        "#.....#..#....###.###.#..#.#.",
+        "#######.#.#....##..###..#..#.",
+    ];
+    const VERSION_5_Q: [&str; 37] = [
+        "#######.#..#...#.##...#.##..#.#######",
+        "#.....#.......##.#..#.#....#..#.....#",
+        "#.###.#..#.##.#####.##..#.#...#.###.#",
+        "#.###.#..#.###....#...#.####..#.###.#",
+        "#.###.#.####.#....####........#.###.#",
+        "#.....#.#..#####.#..###.#..##.#.....#",
+        "#######.#.#.#.#.#.#.#.#.#.#.#.#######",
+        ".........#..#..##.####.##.#.#........",
+        ".#######.##...#..###..#.....#..##...#",
+        "#.##.#..##..##.##.###..#.#...#...#.##",
+        "##....#...#.##....#.#.#..####.#.#..##",
+        "..#.##.#.#..###.###.#####.#.#.#.#..##",
+        "#.##..#.#.###..####.###.####.####..#.",
+        "..####.#...###..###...###...##.#.##..",
+        "#..##.#..###.#.##.###.#.##########.##",
+        "...#.#.#...........##.....#.#####..##",
+        ".####.######..###..#.##.##..#.#.#.#.#",
+        "..###....###..##..####...#..#..#..#..",
+        "#....###.##.#..###...########.#....##",
+        ".###.#...#..#.##.###.###....#.###..##",
+        ".###..###.#...#.#.#.#.#####..####.###",
+        ".##.#.....##.##..#.##.#..##..###.#..#",
+        "#.##..###.##.####.#.#.#.####..#...###",
+        "#....#..####...#.###.#####...#.#...##",
+        "###.#.#..##.##.##..##.##..#.#.###.###",
+        "#.##.#...####.##.#.#####.#...#.....#.",
+        "#....##..#..#......#.....###..#.....#",
+        "#.#..#.##.###..#.##..#.#....##..#...#",
+        "#.##..##.#.#..#..#..####.########...#",
+        "........####.##...#..#.#.####...#....",
+        "#######.########.#.#.#..###.#.#.##.##",
+        "#.....#.###.#...#...####..#.#...#...#",
+        "#.###.#.######.#####.#...#.######.#..",
+        "#.###.#.#####.#..#..###.#.##..#.##.#.",
+        "#.###.#.#..........#.##...##.#.#.#..#",
+        "#.....#.####..#..#.###..#..###......#",
+        "#######...######.#.#.##..#.....#.####",
+    ];
+    const VERSION_8_H: [&str; 49] = [
+        "#######.#....##..###.....#..##.#.#.#....#.#######",
+        "#.....#.#.#..##.#####.###....##.#.##..###.#.....#",
+        "#.###.#.##.##.##...#..#.##..#....#.#...##.#.###.#",
+        "#.###.#..#...#....#.#.###..#.##.#..###.#..#.###.#",
+        "#.###.#...#.#.#.#.##..#####.#...##........#.###.#",
+        "#.....#.#.##..##.#.##.#...###...#.###.#...#.....#",
+        "#######.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#######",
+        "........##.###..#.#.###...#..#.#.####.##.........",
+        "..###.#.#.#.....#.#.#.#####..#..#...#..#####..###",
+        ".#..##.#.#.....####.#.....#.#....#.##.....#..#..#",
+        "....###..#######.#..#.#.##...#..#.#.######..#####",
+        "#..##..##..##.#...###....##.##....#..##.#.###..#.",
+        "#.#...#.....##.#..#..##.##.##.##.#####.#####.##.#",
+        "#.......#..##....######.#####..#...###.#.#####.#.",
+        ".##.###..###..#.#.........#.##..#.#..#.###.....##",
+        "..###..##...###.#..#...####.#####.##.####.#.#..#.",
+        ".....#####.###.#..###.###..#..#.###.#.#..###.###.",
+        "....##.#.##.##....##.##.######.#..#.####..#..##..",
+        ".###..#.#.#..#.#.##.#..#.......###..#....##..#.##",
+        "##...#.#.##...#..####.#...##.##.#....###..###...#",
+        "....#.##.##...#....####..###..##.#.######.##..#..",
+        "###.#..#...#.##....##.....########.##..#.##..#...",
+        ".########.#..#..#..##.#######.#..####.#######..##",
+        "#.###...##..#.#....#..#...#.####..#..####...#...#",
+        "...##.#.#.#....#.#....#.#.#.......#.#####.#.###..",
+        "...##...#.#####..#...##...#....#...#.#..#...##...",
+        "#.#.#####......#.##.#.#####.###...#####.######.##",
+        ".##.#...##...#...#...###.#.#..#.####.....#.#.#.##",
+        "#..####.#######.#.#....#####...#.#####.###....###",
+        ".##.##..##.#.####......#.##.....#....#.##.###..#.",
+        "##.##.#.#..##.#.#.###...#.#.###.#..#.######.#####",
+        "#..##..#.#..#.###.##..###...###..##.#...#..###.#.",
+        ".#.#..#........###...##.##..#.######.#.####.#.##.",
+        "....##.##.##.####....##..##..#..#.......#.##....#",
+        "..#...#.#..#.#...#.###..#....#####.#.##..###.#.##",
+        "##.##....#.###.###.#.#.##.##...............#.....",
+        "...##.####..#.######.#######.###.#.#############.",
+        ".#..#..##.#.#.#.####.##.#.##..##.#.###.###....#..",
+        ".#...##.###......##.#.#.#.#########.#.###.#.#####",
+        ".###...#.#.##.####..#.###..##...##.#.....#..##..#",
+        "###...##...####...##..#####.##.#....##.#######.##",
+        "........#.###.#....#.##...##..#.##..#...#...#..#.",
+        "#######..##....####...#.#.##.#......##..#.#.##.##",
+        "#.....#......###.##.###...#####...#..####...#..##",
+        "#.###.#.###..#...#....#######.##.############.###",
+        "#.###.#.#..#.####...#####.#######....#.#.###..#.#",
+        "#.###.#.#####....#..#......####..##.#.#.####...##",
+        "#.....#...#.###..#..#.###.#.######.....#####....#",
+        "#######.....#####....###.###..##..###.#.##.##.###",
+    ];
+    const VERSION_10_M: [&str; 57] = [
+        "#######..#....#.#...#.#.#...#.#..#..#..#..#.####..#######",
+        "#.....#.....###......######..#....#.####.#.#...#..#.....#",
+        "#.###.#.##..##.#.#..##.##...#.####.#.##.#...####..#.###.#",
+        "#.###.#.##.....####.##.#...#.#....#.#.##.###.#.#..#.###.#",
+        "#.###.#.##.#####.###..#.#.######.#.#.#..#.#....#..#.###.#",
+        "#.....#.#..###.#.###.#....#...#...###.#..#..#.#...#.....#",
+        "#######.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#.#######",
+        "........#...##.....#....###...###..#....#.###.#..........",
+        "#.#####...#..#..####.#.#.######..####.##.....###..#####..",
+        "...##..##.##....##..#...#..#..#..#.#...#..#.....#..######",
+        "..#.###...##..#..####.#####..#..#.##.##..#.#..#..###.##..",
+        "#.#.#..##.##.....#.#.##.....######.#.#..######.###..#.#.#",
+        "#..##.#.###.#....#..#.#.####......#.##.#.##....#.#.#.#...",
+        "##.#....#.##...####.##.#.#....##...##.....##.#..#.......#",
+        "#..#########.#...#...##...#..#.#..######.#....##.####.##.",
+        "#...##...##.##.#.#####.#....#..#####..###.###...#...#.#.#",
+        "##.####.#..#.###..####.#.###.##..#..#..#.##..#.#.###.#...",
+        ".###......#..#.###.####.##.##.#.##.......##..#..#..##.###",
+        "..###.#.#.##.#...#....##.#.#.#....#.###.....#.#.###..##..",
+        "##..##..###..#.#.#.##.###...#.####.#....#####...##..#.#..",
+        "###.#.#.####...#.#.##.##.###........##.#.#....#..##..#...",
+        "..#.##..#.###....#.###..#.#...##.#.##.....##.#.##..#...##",
+        "....#.###..#...#...##...#..###.##.#..#####.#..##.###..#..",
+        "###.#..###.........#.####...#.###..#.##.#.####.#....#####",
+        "...####.#.#..##........#.###.##..#..####..##.#.#...#.#.#.",
+        "######.#.#.###.#.#.###..#..##.#..#...#.##.##.#..#..#..###",
+        "#.#.#####.#...#.#.#..####.#####...#####..#.#..#.######...",
+        "#..##...####..##..#.###.###...###..#....#.###...#...#.#..",
+        "#..##.#.#..####.#.#.####..#.#.#...#.####.#...##.#.#.##.#.",
+        "#.#.#...#...##....#...#.#.#...####.#....#.###..##...#####",
+        "#.##########..#..#####..########..##.###.#....#######....",
+        "..##.#.#..#####.##.##.....#..#.##..#.#..######.####...##.",
+        "..##.##....#.#...###....#.#.###..#..#.##.....##.....##...",
+        "...###....#########..##.#..#..#.....#..#..#..#...###...#.",
+        ".####.#.##....#........###..#.##.#######.#....#......####",
+        "#...##.#.#.#####..#..##...##.#.#####..####.##..#####..#..",
+        ".#...##....#..###.##.#.#.##.###.....##...##.....##..##...",
+        ".#..#...#.###.####.###..#.##.#.#.#.##..#.#####.##.##..###",
+        "..#.#.###.##.##.#....#...#.#..#...#.###.##.#..#....#.....",
+        ".....#..#...#####.##..###.#..#.###.#....#..##########.##.",
+        ".###..#.#..##.###.#.#.##.##.#.#..##.#.##..#..#....#.##..#",
+        "###.#....#...###.##.###.#..#..#..#..#..#..#..#..#.##.####",
+        "##.#.###..#.#.#........#.#....#...#..#####.#..###.....#..",
+        ".....#.#.....##..#......####.#.##..#....#.#.##.##.#..##.#",
+        "..###.#.#####...#.#..#.#....#.#.....#.##.###......#.##.#.",
+        "..#.##.#.#..#...####..#.#.##.#.#.#.###....#.##.#.....####",
+        "#.#..##.......##.#.#.##.##.#..#.#.###.#..#.##.##...#..#..",
+        "#####...###.#..####.....#.####.####..#..#####..####...###",
+        "......###..#...##..#.###..#####..#..#..#..#..#..######...",
+        "........#.###.###..#....###...#.##.#...##.#.##.##...#####",
+        "#######..#.#.##.###..###..#.#.##..######.#...####.#.##...",
+        "#.....#.##.#..#.##...####.#...###.##.#..######.##...#.#..",
+        "#.###.#.#.#.#...#...#.##..#####...#.####.#....#.######...",
+        "#.###.#.#.###.#...####..###.#.###..#......##.#.#....#.#..",
+        "#.###.#.#...#..##.#..##.##.#......#.######..#.#.##.#..#..",
+        "#.....#..#####.###.##....#....####.#...##.###...#...#.#..",
+        "#######.####......#....#.....#...#..#.....#..#######.#.#.",
+    ];
+
+    fn rows(code: &QrCode) -> Vec<String> {
+        code.modules.iter()
+            .map(|row| row.iter().map(|&dark| if dark { '#' } else { '.' }).collect())
+            .collect()
+    }
+
+    #[test]
+    fn matches_reference_encodings() {
+        let long_link = format!("https://nvt.example.org/?stop={}", "0123456789".repeat(18));
+        for (text, level, expected) in [
+            ("NVT 3671", EcLevel::L, VERSION_1_L.as_slice()),
+            ("https://nvt.example.org/?stop=3671", EcLevel::M, VERSION_3_M.as_slice()),
+            ("https://nvt.example.org/?stop=StopPoint:BORDEAUX:3671", EcLevel::Q, VERSION_5_Q.as_slice()),
+            // Version information, and blocks of two lengths
+            ("https://nvt.example.org/?stop=StopArea:SNCF:87581009:Bordeaux-Saint-Jean", EcLevel::H, VERSION_8_H.as_slice()),
+            // 16-bit character count
+            (long_link.as_str(), EcLevel::M, VERSION_10_M.as_slice()),
+        ] {
+            let code = QrCode::encode(text, level).expect("fits in version 10");
+            assert_eq!(rows(&code), expected, "{} at level {:?}", text, level);
+        }
+    }
+
+    #[test]
+    fn refuses_text_past_version_10() {
+        let link = format!("https://nvt.example.org/?stop={}", "0123456789".repeat(4));
+        assert!(QrCode::encode(&link.repeat(3), EcLevel::M).is_some());
+        assert!(QrCode::encode(&link.repeat(4), EcLevel::H).is_none());
+        assert_eq!(EcLevel::parse("q"), Some(EcLevel::Q));
+        assert_eq!(EcLevel::parse("X"), None);
+    }
+}
@@ -0,0 +1,105 @@
+// Forced refresh jobs (POST /api/tbm/refresh, DELETE /api/tbm/cache/{source})
+// One forced refresh runs at a time. Requests are answered at once with the job doing it, to
+// poll at GET /api/tbm/refresh/{job_id}: a request made again while its job runs joins that job
+// instead of queuing another refresh behind the cache lock, and a different one is turned away
+// with a Retry-After until the running job is done. The last finished jobs are kept for polling.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::tbm_api_models::NVTModels;
+
+/// Finished jobs kept for GET /api/tbm/refresh/{job_id}
+const FINISHED_JOBS_KEPT: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RefreshJob {
+    pub id: u64,
+    pub request: String, // What it refreshes, e.g. "smart", "full" or "static: SNCF"
+    pub rebuild: bool, // Reloads static data
+    pub status: JobStatus,
+    pub error: Option<String>,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+}
+
+/// How a refresh request is taken in
+pub enum Admission {
+    Started(RefreshJob), // Run it, then call finish
+    Joined(RefreshJob), // The same refresh was already running
+    Busy(RefreshJob), // Another refresh is running
+}
+
+#[derive(Default)]
+struct Jobs {
+    next_id: u64,
+    running: Option<RefreshJob>,
+    finished: VecDeque<RefreshJob>,
+}
+
+#[derive(Clone, Default)]
+pub struct RefreshJobs(Arc<Mutex<Jobs>>);
+
+impl RefreshJobs {
+    fn jobs(&self) -> MutexGuard<'_, Jobs> {
+        match self.0.lock() {
+            Ok(jobs) => jobs,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    pub fn admit(&self, request: &str, rebuild: bool) -> Admission {
+        let mut jobs = self.jobs();
+        match &jobs.running {
+            Some(running) if running.request == request => Admission::Joined(running.clone()),
+            Some(running) => Admission::Busy(running.clone()),
+            None => {
+                jobs.next_id += 1;
+                let job = RefreshJob {
+                    id: jobs.next_id,
+                    request: request.to_string(),
+                    rebuild,
+                    status: JobStatus::Running,
+                    error: None,
+                    started_at: NVTModels::get_current_timestamp(),
+                    finished_at: None,
+                };
+                jobs.running = Some(job.clone());
+                Admission::Started(job)
+            }
+        }
+    }
+
+    pub fn finish(&self, id: u64, result: Result<(), String>) {
+        let mut jobs = self.jobs();
+        let Some(mut job) = jobs.running.take_if(|job| job.id == id) else {
+            return;
+        };
+        job.finished_at = Some(NVTModels::get_current_timestamp());
+        (job.status, job.error) = match result {
+            Ok(()) => (JobStatus::Succeeded, None),
+            Err(e) => (JobStatus::Failed, Some(e)),
+        };
+        jobs.finished.push_front(job);
+        jobs.finished.truncate(FINISHED_JOBS_KEPT);
+    }
+
+    pub fn get(&self, id: u64) -> Option<RefreshJob> {
+        let jobs = self.jobs();
+        jobs.running.iter().chain(&jobs.finished).find(|job| job.id == id).cloned()
+    }
+
+    /// A static reload is running
+    pub fn rebuilding(&self) -> bool {
+        self.jobs().running.as_ref().is_some_and(|job| job.rebuild)
+    }
+}
@@ -0,0 +1,1286 @@
+// Journey planning (GET /api/tbm/journey)
+// Earliest-arrival routing with the Connection Scan Algorithm: the hops between consecutive
+// stops of every trip running in the hours after the departure time are scanned once, in
+// departure order. Changing vehicles at a stop takes its transfers.txt min_transfer_time (2
+// minutes without one); walks go to the transfers.txt targets and to any stop within 400 m, so
+// changes between the TBM, New-Aquitaine and SNCF feeds work. Times carry the delays of the
+// GTFS-RT trip updates and vehicle positions. Each further itinerary is the earliest arrival
+// when leaving just after the first departure of the previous one. When none is found and
+// Navitia is configured, its journeys are read into the same shape (see navitia.rs).
+// An end given as coordinates is one more node of the network, with walks to (or from) the
+// stops within 800 m of it found in the same spatial index as the footpaths.
+// Arrive-by requests scan the connections of the hours before the arrival time backwards, by
+// decreasing departure, for the latest time one can leave and still get there; the itinerary
+// is then the earliest arrival leaving at that time. Each further one must arrive before the
+// previous one.
+// With criteria, the scan keeps at each stop every way of getting there that no other beats on
+// arrival, transfers and walking time together (a Pareto set of labels, as in McRAPTOR), and
+// the itineraries are those of the labels left at the destination.
+// pathways.txt links replace the walks between the locations of a station. Wheelchair journeys
+// only board and alight at stops with wheelchair_boarding, ride trips with wheelchair_accessible
+// and leave out the pathways with stairs or escalators; the other stops are passed through.
+// Bike journeys only ride trips with bikes_allowed, and add V³ rides first and last: walking to a
+// station that rents bikes, riding to one that takes them back, then walking on. Such a ride is
+// one more footpath from the origin (or to the destination), when it is faster than walking.
+
+use chrono::{Duration, NaiveDate};
+use chrono_tz::Europe::Paris;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::gbfs::BikeStation;
+use crate::navitia;
+use crate::spatial::StopIndex;
+use crate::tbm_api_models::{CachedNetworkData, Line, NVTModels, StopTime};
+use crate::time_format;
+
+const MIN_CHANGE_SECS: i64 = 120; // At a stop without a transfers.txt time
+const MAX_WALK_METERS: f64 = 400.0;
+const MAX_ACCESS_METERS: f64 = 800.0; // Between a coordinate end and its stops
+const WALK_SPEED: f64 = 1.2; // m/s
+const BIKE_SPEED: f64 = 4.0; // m/s, a V³ bike in town
+const BIKE_HANDLING_SECS: i64 = 60; // Taking the bike out and docking it
+const MAX_BIKE_METERS: f64 = 5000.0;
+const SEARCH_WINDOW_SECS: i64 = 4 * 3600; // Departures considered after (arrive-by: before) the requested time
+pub const MAX_ITINERARIES: usize = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JourneyLeg {
+    pub mode: String, // "transit", "walk" or "bike"
+    pub from_stop_id: String,
+    pub from_stop_name: String,
+    pub to_stop_id: String,
+    pub to_stop_name: String,
+    pub departure: i64, // unix seconds, delay applied
+    pub arrival: i64,
+    #[serde(default)]
+    pub wait_secs: i64, // At from_stop before the leg: after the previous leg, or the requested time
+    pub trip_id: Option<String>, // Transit legs only, as are the fields below
+    pub line_code: Option<String>,
+    pub line_color: Option<String>,
+    pub operator: Option<String>,
+    pub headsign: Option<String>,
+    pub scheduled_departure: Option<String>, // GTFS time
+    pub scheduled_arrival: Option<String>,
+    pub departure_delay: Option<i32>, // Seconds, when real-time data covers the trip
+    pub arrival_delay: Option<i32>,
+    #[serde(default)]
+    pub stop_ids: Vec<String>, // Stops passed, both ends included
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Journey {
+    pub departure: i64, // unix seconds
+    pub arrival: i64,
+    pub duration_secs: i64, // From the requested departure time, or its departure for arrive-by
+    pub transfers: u32,
+    #[serde(default)]
+    pub walk_secs: i64, // Time spent in walk legs
+    pub legs: Vec<JourneyLeg>,
+}
+
+/// When a journey is requested for, as unix seconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Time {
+    DepartAt(i64),
+    ArriveBy(i64),
+}
+
+/// What itineraries are weighed on besides their arrival time
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Criteria {
+    pub transfers: bool,
+    pub walking: bool,
+}
+
+impl Criteria {
+    /// From a comma-separated list of "arrival", "transfers" and "walking"
+    pub fn parse(value: &str) -> Option<Criteria> {
+        let mut criteria = Criteria::default();
+        for name in value.split(',').map(str::trim) {
+            match name {
+                "arrival" => {}
+                "transfers" => criteria.transfers = true,
+                "walking" => criteria.walking = true,
+                _ => return None,
+            }
+        }
+        Some(criteria)
+    }
+
+    /// Whether arrival time is weighed against anything
+    pub fn is_multi(&self) -> bool {
+        self.transfers || self.walking
+    }
+}
+
+/// How a journey is planned, besides its ends and time
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Options {
+    pub criteria: Criteria,
+    pub wheelchair: bool,
+    pub bikes: bool,
+}
+
+/// An end of a journey: a stop of one of the feeds, or a point walked from or to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Place {
+    Stop(String),
+    Point(f64, f64), // (latitude, longitude)
+}
+
+impl Place {
+    /// A point from "lat,lon"
+    pub fn parse_point(value: &str) -> Option<Place> {
+        let (lat, lon) = value.split_once(',')?;
+        let (lat, lon) = (lat.trim().parse::<f64>().ok()?, lon.trim().parse::<f64>().ok()?);
+        ((-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)).then_some(Place::Point(lat, lon))
+    }
+
+    /// (latitude, longitude) of the place, None for a stop no feed has
+    pub fn position(&self, cache: &CachedNetworkData) -> Option<(f64, f64)> {
+        match self {
+            Place::Stop(stop_id) => stop_position(cache, stop_id),
+            Place::Point(lat, lon) => Some((*lat, *lon)),
+        }
+    }
+}
+
+impl std::fmt::Display for Place {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Place::Stop(stop_id) => write!(f, "{}", stop_id),
+            Place::Point(lat, lon) => write!(f, "{:.6},{:.6}", lat, lon),
+        }
+    }
+}
+
+struct StopNode<'a> {
+    stop_id: Cow<'a, str>, // "lat,lon" for a coordinate end
+    name: Cow<'a, str>,
+    wheelchair_boarding: bool, // stops.txt wheelchair_boarding=1; true for a coordinate end
+}
+
+/// A stop of a trip on one service day
+struct RunStop<'a> {
+    stop: usize,
+    stop_time: &'a StopTime,
+    arrival: i64, // unix seconds, delay applied
+    departure: i64,
+    arrival_delay: Option<i32>,
+    departure_delay: Option<i32>,
+}
+
+/// A trip on one service day
+struct TripRun<'a> {
+    trip_id: &'a str,
+    headsign: Option<&'a str>,
+    stops: Vec<RunStop<'a>>,
+}
+
+/// The hop of a trip run from stops[position] to stops[position + 1]
+struct Connection {
+    run: usize,
+    position: usize,
+    from: usize,
+    to: usize,
+    departure: i64,
+    arrival: i64,
+}
+
+/// A V³ ride making up a footpath: walk to a station, ride to another, walk on
+#[derive(Clone, Copy)]
+struct Cycle<'a> {
+    pick_up: &'a BikeStation,
+    drop_off: &'a BikeStation,
+    walk_to: i64, // To the pick-up station
+    ride: i64, // Handling included
+    walk_from: i64,
+}
+
+impl Cycle<'_> {
+    fn secs(&self) -> i64 {
+        self.walk_to + self.ride + self.walk_from
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Via {
+    Ride { run: usize, board: usize, alight: usize },
+    Walk { from: usize, to: usize, secs: i64 },
+}
+
+/// A way of getting to a stop in the multi-criteria scan
+struct Label {
+    arrival: i64,
+    ready: i64,
+    rides: u32,
+    walk_secs: i64,
+    via: Option<Via>, // None at the origin
+    parent: Option<usize>, // Label the step starts from
+}
+
+impl Label {
+    /// At least as good as `other` on every criterion weighed (and boardable as early but at
+    /// the destination, where nothing is boarded)
+    fn covers(&self, other: &Label, criteria: Criteria, at_target: bool) -> bool {
+        self.arrival <= other.arrival
+            && (at_target || self.ready <= other.ready)
+            && (!criteria.transfers || self.rides <= other.rides)
+            && (!criteria.walking || self.walk_secs <= other.walk_secs)
+    }
+}
+
+struct Network<'a> {
+    stops: Vec<StopNode<'a>>,
+    stop_ids: HashMap<&'a str, usize>,
+    change_secs: Vec<Option<i64>>, // None where transfers.txt forbids changing
+    footpaths: Vec<Vec<(usize, i64)>>,
+    cycles: HashMap<(usize, usize), Cycle<'a>>, // The footpaths ridden on a V³ bike
+    index: StopIndex, // Positions of the stops, for the footpaths and coordinate ends
+    wheelchair: bool,
+    bikes: bool,
+    runs: Vec<TripRun<'a>>,
+    connections: Vec<Connection>, // By departure
+}
+
+/// Absolute start of a service day (noon minus 12h, see time_format::gtfs_datetime)
+fn service_day_start(date: NaiveDate) -> Option<i64> {
+    time_format::gtfs_datetime(date, "00:00:00").map(|dt| dt.timestamp())
+}
+
+impl<'a> Network<'a> {
+    fn build(cache: &'a CachedNetworkData, departure: i64, options: Options) -> Self {
+        let mut stops = Vec::new();
+        let mut stop_ids = HashMap::new();
+        let mut positions = Vec::new();
+        for gtfs_cache in cache.gtfs_caches() {
+            for (stop_id, name, lat, lon) in &gtfs_cache.stops {
+                if stop_ids.contains_key(stop_id.as_str()) {
+                    continue;
+                }
+                stop_ids.insert(stop_id.as_str(), stops.len());
+                stops.push(StopNode {
+                    stop_id: Cow::Borrowed(stop_id),
+                    name: Cow::Borrowed(name),
+                    wheelchair_boarding: gtfs_cache.accessibility.wheelchair_boarding.get(stop_id) == Some(&true),
+                });
+                if *lat != 0.0 || *lon != 0.0 {
+                    positions.push((stop_id.clone(), *lat, *lon));
+                }
+            }
+        }
+
+        let mut network = Network {
+            change_secs: vec![Some(MIN_CHANGE_SECS); stops.len()],
+            footpaths: vec![Vec::new(); stops.len()],
+            cycles: HashMap::new(),
+            stops,
+            stop_ids,
+            index: StopIndex::build(positions.clone()),
+            wheelchair: options.wheelchair,
+            bikes: options.bikes,
+            runs: Vec::new(),
+            connections: Vec::new(),
+        };
+        network.add_footpaths(cache, &positions);
+        network.add_pathways(cache);
+        if options.wheelchair {
+            network.keep_wheelchair_boarding();
+        }
+        network.add_trips(cache, departure);
+        network
+    }
+
+    /// Walks to the stops within MAX_WALK_METERS, then the transfers.txt rules on top
+    fn add_footpaths(&mut self, cache: &CachedNetworkData, positions: &[(String, f64, f64)]) {
+        for (stop_id, lat, lon) in positions {
+            let from = self.stop_ids[stop_id.as_str()];
+            for (nearby_id, distance) in self.index.within(*lat, *lon, MAX_WALK_METERS) {
+                let to = self.stop_ids[nearby_id];
+                if to != from {
+                    self.footpaths[from].push((to, walk_secs(distance)));
+                }
+            }
+        }
+
+        for gtfs_cache in cache.gtfs_caches() {
+            for transfer in &gtfs_cache.transfers {
+                let (Some(&from), Some(&to)) = (self.stop_ids.get(transfer.from_stop_id.as_str()), self.stop_ids.get(transfer.to_stop_id.as_str())) else {
+                    continue;
+                };
+                let secs = transfer.min_transfer_time.map(i64::from);
+                if from == to {
+                    // transfer_type 3: no changes at this stop
+                    self.change_secs[from] = if transfer.transfer_type == 3 { None } else { Some(secs.unwrap_or(MIN_CHANGE_SECS)) };
+                    continue;
+                }
+                let walk = self.footpaths[from].iter().find(|(stop, _)| *stop == to).map(|(_, secs)| *secs);
+                self.footpaths[from].retain(|(stop, _)| *stop != to);
+                if transfer.transfer_type != 3 {
+                    self.footpaths[from].push((to, secs.or(walk).unwrap_or(MIN_CHANGE_SECS)));
+                }
+            }
+        }
+    }
+
+    /// pathways.txt links instead of the walks between their ends, the fastest of each pair.
+    /// Those with stairs or escalators only rule the walk out for wheelchair journeys.
+    fn add_pathways(&mut self, cache: &CachedNetworkData) {
+        let mut links: HashMap<(usize, usize), Option<i64>> = HashMap::new(); // None: no step-free way
+        for gtfs_cache in cache.gtfs_caches() {
+            for pathway in &gtfs_cache.accessibility.pathways {
+                let (Some(&from), Some(&to)) = (self.stop_ids.get(pathway.from_stop_id.as_str()), self.stop_ids.get(pathway.to_stop_id.as_str())) else {
+                    continue;
+                };
+                let stairs = matches!(pathway.pathway_mode, 2 | 4) || pathway.stair_count.is_some_and(|count| count != 0);
+                let secs = pathway.traversal_time.map(i64::from)
+                    .or_else(|| pathway.length.map(walk_secs))
+                    .unwrap_or(MIN_CHANGE_SECS);
+                let usable = !(self.wheelchair && stairs);
+                let ends = if pathway.is_bidirectional { vec![(from, to), (to, from)] } else { vec![(from, to)] };
+                for ends in ends {
+                    let link = links.entry(ends).or_insert(None);
+                    if usable {
+                        *link = Some(link.map_or(secs, |fastest| fastest.min(secs)));
+                    }
+                }
+            }
+        }
+        for ((from, to), secs) in links {
+            self.footpaths[from].retain(|(stop, _)| *stop != to);
+            if let Some(secs) = secs {
+                self.footpaths[from].push((to, secs));
+            }
+        }
+    }
+
+    /// No changing vehicles nor walking at the stops without wheelchair boarding: riders stay on
+    fn keep_wheelchair_boarding(&mut self) {
+        for stop in 0..self.stops.len() {
+            if !self.stops[stop].wheelchair_boarding {
+                self.change_secs[stop] = None;
+                self.footpaths[stop].clear();
+            }
+        }
+        let stops = &self.stops;
+        for footpaths in &mut self.footpaths {
+            footpaths.retain(|(stop, _)| stops[*stop].wheelchair_boarding);
+        }
+    }
+
+    /// Node of a journey end: the stop, or a new node with walks to the stops within
+    /// MAX_ACCESS_METERS of the point (from them, for a destination). None for an unknown stop,
+    /// or one without wheelchair boarding for a wheelchair journey.
+    fn add_place(&mut self, place: &Place, destination: bool) -> Option<usize> {
+        let (lat, lon) = match place {
+            Place::Stop(stop_id) => {
+                return self.stop_ids.get(stop_id.as_str()).copied()
+                    .filter(|&stop| !self.wheelchair || self.stops[stop].wheelchair_boarding);
+            }
+            Place::Point(lat, lon) => (*lat, *lon),
+        };
+        let node = self.stops.len();
+        let name = if destination { "Destination" } else { "Origin" };
+        self.stops.push(StopNode { stop_id: Cow::Owned(place.to_string()), name: Cow::Borrowed(name), wheelchair_boarding: true });
+        self.change_secs.push(Some(0));
+        self.footpaths.push(Vec::new());
+        let walks: Vec<(usize, i64)> = self.index.within(lat, lon, MAX_ACCESS_METERS).into_iter()
+            .map(|(stop_id, distance)| (self.stop_ids[stop_id], walk_secs(distance)))
+            .filter(|&(stop, _)| !self.wheelchair || self.stops[stop].wheelchair_boarding)
+            .collect();
+        for (stop, secs) in walks {
+            if destination {
+                self.footpaths[stop].push((node, secs));
+            } else {
+                self.footpaths[node].push((stop, secs));
+            }
+        }
+        Some(node)
+    }
+
+    /// Walk straight between two coordinate ends close enough to each other
+    fn add_direct_walk(&mut self, origin: usize, target: usize, from: &Place, to: &Place) {
+        if let (Place::Point(from_lat, from_lon), Place::Point(to_lat, to_lon)) = (from, to) {
+            let distance = NVTModels::distance_meters(*from_lat, *from_lon, *to_lat, *to_lon);
+            if distance <= MAX_ACCESS_METERS {
+                self.footpaths[origin].push((target, walk_secs(distance)));
+            }
+        }
+    }
+
+    /// V³ rides from the origin to the stops around the stations taking bikes back (or to the
+    /// destination), and from the stops around the stations renting them to the destination,
+    /// with the bikes and docks available now
+    fn add_bike_share(&mut self, cache: &'a CachedNetworkData, origin: usize, target: usize, from: (f64, f64), to: (f64, f64)) {
+        let stations = &cache.bike_share.stations;
+        let renting: Vec<&BikeStation> = stations.iter().filter(|station| station.is_renting && station.bikes_available > 0).collect();
+        let returning: Vec<&BikeStation> = stations.iter().filter(|station| station.is_returning && station.docks_available != Some(0)).collect();
+        let distance = |(lat, lon): (f64, f64), station: &BikeStation| NVTModels::distance_meters(lat, lon, station.latitude, station.longitude);
+        let ride = |pick_up: &BikeStation, drop_off: &BikeStation| {
+            let meters = distance((pick_up.latitude, pick_up.longitude), drop_off);
+            (pick_up.station_id != drop_off.station_id && meters <= MAX_BIKE_METERS)
+                .then(|| BIKE_HANDLING_SECS + (meters / BIKE_SPEED).ceil() as i64)
+        };
+
+        // First mile
+        for &pick_up in renting.iter().filter(|station| distance(from, station) <= MAX_ACCESS_METERS) {
+            let walk_to = walk_secs(distance(from, pick_up));
+            for &drop_off in &returning {
+                let Some(secs) = ride(pick_up, drop_off) else {
+                    continue;
+                };
+                let mut ends: Vec<(usize, f64)> = self.index.within(drop_off.latitude, drop_off.longitude, MAX_WALK_METERS).into_iter()
+                    .map(|(stop_id, meters)| (self.stop_ids[stop_id], meters))
+                    .collect();
+                if distance(to, drop_off) <= MAX_ACCESS_METERS {
+                    ends.push((target, distance(to, drop_off)));
+                }
+                for (end, meters) in ends {
+                    self.add_cycle(origin, end, Cycle { pick_up, drop_off, walk_to, ride: secs, walk_from: walk_secs(meters) });
+                }
+            }
+        }
+
+        // Last mile
+        for &drop_off in returning.iter().filter(|station| distance(to, station) <= MAX_ACCESS_METERS) {
+            for &pick_up in &renting {
+                let Some(secs) = ride(pick_up, drop_off) else {
+                    continue;
+                };
+                let walk_from = walk_secs(distance(to, drop_off));
+                let starts: Vec<(usize, f64)> = self.index.within(pick_up.latitude, pick_up.longitude, MAX_WALK_METERS).into_iter()
+                    .map(|(stop_id, meters)| (self.stop_ids[stop_id], meters))
+                    .collect();
+                for (stop, meters) in starts {
+                    self.add_cycle(stop, target, Cycle { pick_up, drop_off, walk_to: walk_secs(meters), ride: secs, walk_from });
+                }
+            }
+        }
+    }
+
+    /// The ride as the footpath from `from` to `to`, unless walking (or another ride) is as fast
+    fn add_cycle(&mut self, from: usize, to: usize, cycle: Cycle<'a>) {
+        if from == to || self.footpaths[from].iter().any(|&(stop, secs)| stop == to && secs <= cycle.secs()) {
+            return;
+        }
+        self.footpaths[from].retain(|(stop, _)| *stop != to);
+        self.footpaths[from].push((to, cycle.secs()));
+        self.cycles.insert((from, to), cycle);
+    }
+
+    /// Runs of the trips of the service days around `departure`, and their connections
+    /// leaving within SEARCH_WINDOW_SECS of it
+    fn add_trips(&mut self, cache: &'a CachedNetworkData, departure: i64) {
+        let Some(today) = chrono::DateTime::from_timestamp(departure, 0).map(|dt| dt.with_timezone(&Paris).date_naive()) else {
+            return;
+        };
+        // Yesterday's trips past midnight, and tomorrow's when the window reaches into it
+        let days: Vec<(NaiveDate, i64)> = [today - Duration::days(1), today, today + Duration::days(1)].into_iter()
+            .filter_map(|date| service_day_start(date).map(|start| (date, start)))
+            .filter(|(date, start)| *date <= today || *start < departure + SEARCH_WINDOW_SECS)
+            .collect();
+
+        let trip_updates: HashMap<&str, &gtfs_rt::TripUpdate> = cache.trip_updates.iter()
+            .filter_map(|update| update.trip.trip_id.as_deref().map(|trip_id| (trip_id, update)))
+            .collect();
+        let vehicle_delays: HashMap<&str, i32> = cache.real_time.iter()
+            .filter_map(|vehicle| vehicle.delay.map(|delay| (vehicle.trip_id.as_str(), delay)))
+            .collect();
+
+        for gtfs_cache in cache.gtfs_caches() {
+            let mut by_trip: HashMap<&str, Vec<&StopTime>> = HashMap::new();
+            for stop_time in gtfs_cache.stop_times.values().flatten() {
+                by_trip.entry(stop_time.trip_id.as_str()).or_default().push(stop_time);
+            }
+
+            for (trip_id, mut stop_times) in by_trip {
+                let Some(trip) = gtfs_cache.trips.get(trip_id)
+                    .filter(|trip| !self.wheelchair || trip.wheelchair_accessible == Some(true))
+                    .filter(|trip| !self.bikes || trip.bikes_allowed == Some(true)) else {
+                    continue;
+                };
+                stop_times.sort_by_key(|stop_time| stop_time.stop_sequence);
+
+                for (date, start) in &days {
+                    if !NVTModels::service_runs(gtfs_cache, &trip.service_id, *date) {
+                        continue;
+                    }
+                    // Real-time data describes the trip of its start date, or of today
+                    let update = trip_updates.get(trip_id).copied().filter(|update| {
+                        update.trip.start_date.as_ref()
+                            .map_or(*date == today, |start_date| *start_date == date.format("%Y%m%d").to_string())
+                    });
+                    let vehicle_delay = vehicle_delays.get(trip_id).copied().filter(|_| *date == today);
+                    let run = self.trip_run(trip_id, trip.trip_headsign.as_deref(), &stop_times, *start, update, vehicle_delay);
+                    if run.stops.len() < 2 || run.stops.iter().all(|stop| stop.departure < departure || stop.departure > departure + SEARCH_WINDOW_SECS) {
+                        continue;
+                    }
+
+                    let index = self.runs.len();
+                    for (position, hop) in run.stops.windows(2).enumerate() {
+                        if hop[0].departure >= departure && hop[0].departure <= departure + SEARCH_WINDOW_SECS {
+                            self.connections.push(Connection {
+                                run: index,
+                                position,
+                                from: hop[0].stop,
+                                to: hop[1].stop,
+                                departure: hop[0].departure,
+                                arrival: hop[1].arrival.max(hop[0].departure),
+                            });
+                        }
+                    }
+                    self.runs.push(run);
+                }
+            }
+        }
+        self.connections.sort_by_key(|connection| (connection.departure, connection.arrival));
+    }
+
+    /// A trip on the service day starting at `start`. As GTFS-RT specifies, a delay holds for
+    /// the following stops until the next update; before the first one it is the vehicle's.
+    fn trip_run(
+        &self,
+        trip_id: &'a str,
+        headsign: Option<&'a str>,
+        stop_times: &[&'a StopTime],
+        start: i64,
+        update: Option<&gtfs_rt::TripUpdate>,
+        vehicle_delay: Option<i32>,
+    ) -> TripRun<'a> {
+        use gtfs_rt::trip_update::StopTimeEvent;
+
+        let mut delay = vehicle_delay;
+        let mut stops = Vec::new();
+        for stop_time in stop_times {
+            let Some(&stop) = self.stop_ids.get(stop_time.stop_id.as_str()) else {
+                continue;
+            };
+            let Some(arrival) = NVTModels::parse_gtfs_time(&stop_time.arrival_time) else {
+                continue;
+            };
+            let departure = NVTModels::parse_gtfs_time(&stop_time.departure_time).unwrap_or(arrival);
+            let (arrival, departure) = (start + i64::from(arrival), start + i64::from(departure));
+
+            let stop_update = update.and_then(|update| {
+                update.stop_time_update.iter().find(|stop_update| match (stop_update.stop_sequence, &stop_update.stop_id) {
+                    (Some(sequence), _) => sequence == stop_time.stop_sequence,
+                    (None, Some(stop_id)) => *stop_id == stop_time.stop_id,
+                    (None, None) => false,
+                })
+            });
+            // Feeds may only give the predicted time
+            let event_delay = |event: Option<&StopTimeEvent>, scheduled: i64| {
+                event.and_then(|event| event.delay.or_else(|| event.time.and_then(|time| i32::try_from(time - scheduled).ok())))
+            };
+            let arrival_delay = event_delay(stop_update.and_then(|u| u.arrival.as_ref()), arrival)
+                .or_else(|| event_delay(stop_update.and_then(|u| u.departure.as_ref()), departure))
+                .or(delay);
+            let departure_delay = event_delay(stop_update.and_then(|u| u.departure.as_ref()), departure)
+                .or(arrival_delay);
+            delay = departure_delay;
+
+            stops.push(RunStop {
+                stop,
+                stop_time,
+                arrival: arrival + i64::from(arrival_delay.unwrap_or(0)),
+                departure: departure + i64::from(departure_delay.unwrap_or(0)),
+                arrival_delay,
+                departure_delay,
+            });
+        }
+        TripRun { trip_id, headsign, stops }
+    }
+
+    /// Earliest arrival at `target` leaving `origin` from `departure`, as the steps taken
+    fn earliest_arrival(&self, origin: usize, target: usize, departure: i64) -> Option<Vec<Via>> {
+        let mut arrival = vec![i64::MAX; self.stops.len()];
+        let mut ready = vec![i64::MAX; self.stops.len()]; // When a vehicle can be boarded there
+        let mut via: Vec<Option<Via>> = vec![None; self.stops.len()];
+        let mut boarded: Vec<Option<usize>> = vec![None; self.runs.len()];
+
+        arrival[origin] = departure;
+        ready[origin] = departure;
+        for &(stop, secs) in &self.footpaths[origin] {
+            arrival[stop] = departure + secs;
+            ready[stop] = departure + secs;
+            via[stop] = Some(Via::Walk { from: origin, to: stop, secs });
+        }
+
+        for connection in &self.connections {
+            if connection.departure < departure {
+                continue;
+            }
+            if connection.departure >= arrival[target] {
+                break;
+            }
+            if boarded[connection.run].is_none() && ready[connection.from] <= connection.departure {
+                boarded[connection.run] = Some(connection.position);
+            }
+            let Some(board) = boarded[connection.run] else {
+                continue;
+            };
+            if connection.arrival >= arrival[connection.to] {
+                continue;
+            }
+
+            let to = connection.to;
+            arrival[to] = connection.arrival;
+            ready[to] = self.change_secs[to].map_or(i64::MAX, |secs| connection.arrival + secs);
+            via[to] = Some(Via::Ride { run: connection.run, board, alight: connection.position + 1 });
+            for &(stop, secs) in &self.footpaths[to] {
+                if connection.arrival + secs < arrival[stop] {
+                    arrival[stop] = connection.arrival + secs;
+                    ready[stop] = connection.arrival + secs;
+                    via[stop] = Some(Via::Walk { from: to, to: stop, secs });
+                }
+            }
+        }
+
+        if target == origin || arrival[target] == i64::MAX {
+            return None;
+        }
+        let mut steps = Vec::new();
+        let mut stop = target;
+        while stop != origin {
+            let step = via[stop]?;
+            stop = match step {
+                Via::Ride { run, board, .. } => self.runs[run].stops[board].stop,
+                Via::Walk { from, .. } => from,
+            };
+            steps.push(step);
+            if steps.len() > self.stops.len() {
+                return None;
+            }
+        }
+        steps.reverse();
+        Some(steps)
+    }
+
+    /// Latest departure from `origin` still reaching `target` by `deadline`, running the scan
+    /// backwards: `board` is the latest boarding at each stop that gets there, `walk` the latest
+    /// time to stand at a stop and walk on to such a boarding (or to the target)
+    fn latest_departure(&self, origin: usize, target: usize, deadline: i64) -> Option<i64> {
+        let mut incoming: Vec<Vec<(usize, i64)>> = vec![Vec::new(); self.stops.len()];
+        for (from, footpaths) in self.footpaths.iter().enumerate() {
+            for &(to, secs) in footpaths {
+                incoming[to].push((from, secs));
+            }
+        }
+        let mut board = vec![i64::MIN; self.stops.len()];
+        let mut walk = vec![i64::MIN; self.stops.len()];
+        let mut reaches = vec![false; self.runs.len()]; // A later hop of the run gets there
+        walk[target] = deadline;
+        for &(stop, secs) in &incoming[target] {
+            walk[stop] = walk[stop].max(deadline - secs);
+        }
+
+        for connection in self.connections.iter().rev() {
+            if connection.arrival > deadline {
+                continue;
+            }
+            let to = connection.to;
+            let alights = connection.arrival <= walk[to]
+                || self.change_secs[to].is_some_and(|secs| connection.arrival + secs <= board[to]);
+            if !reaches[connection.run] && !alights {
+                continue;
+            }
+            reaches[connection.run] = true;
+            let from = connection.from;
+            if connection.departure > board[from] {
+                board[from] = connection.departure;
+                for &(stop, secs) in &incoming[from] {
+                    walk[stop] = walk[stop].max(connection.departure - secs);
+                }
+            }
+        }
+
+        let latest = board[origin].max(walk[origin]);
+        (target != origin && latest != i64::MIN).then_some(latest)
+    }
+
+    /// Itineraries leaving `origin` from `departure` that no other beats on arrival and the
+    /// criteria together, as the steps taken, by arrival
+    fn pareto_arrivals(&self, origin: usize, target: usize, departure: i64, criteria: Criteria) -> Vec<Vec<Via>> {
+        let mut labels: Vec<Label> = Vec::new();
+        let mut bags: Vec<Vec<usize>> = vec![Vec::new(); self.stops.len()]; // Labels not beaten
+        let mut on_board: Vec<Vec<(u32, i64, usize, usize)>> = vec![Vec::new(); self.runs.len()]; // (rides, walk_secs, label boarded from, position)
+
+        // Adds the label to the bag of `stop` unless a label there or at the target beats it
+        let insert = |labels: &mut Vec<Label>, bags: &mut Vec<Vec<usize>>, stop: usize, label: Label| -> Option<usize> {
+            let at_target = stop == target;
+            if bags[stop].iter().any(|&other| labels[other].covers(&label, criteria, at_target))
+                || bags[target].iter().any(|&other| labels[other].covers(&label, criteria, true)) {
+                return None;
+            }
+            bags[stop].retain(|&other| !label.covers(&labels[other], criteria, at_target));
+            bags[stop].push(labels.len());
+            labels.push(label);
+            Some(labels.len() - 1)
+        };
+
+        let start = Label { arrival: departure, ready: departure, rides: 0, walk_secs: 0, via: None, parent: None };
+        let Some(start) = insert(&mut labels, &mut bags, origin, start) else {
+            return Vec::new();
+        };
+        for &(stop, secs) in &self.footpaths[origin] {
+            let walk = Label {
+                arrival: departure + secs,
+                ready: departure + secs,
+                rides: 0,
+                walk_secs: secs,
+                via: Some(Via::Walk { from: origin, to: stop, secs }),
+                parent: Some(start),
+            };
+            insert(&mut labels, &mut bags, stop, walk);
+        }
+
+        for connection in &self.connections {
+            if connection.departure < departure {
+                continue;
+            }
+            let boarding: Vec<(u32, i64, usize, usize)> = bags[connection.from].iter()
+                .filter(|&&label| labels[label].ready <= connection.departure)
+                .map(|&label| (labels[label].rides + 1, labels[label].walk_secs, label, connection.position))
+                .collect();
+            let riders = &mut on_board[connection.run];
+            for candidate in boarding {
+                // Everyone on the run gets off at the same times: only rides and walking differ
+                let beats = |a: &(u32, i64, usize, usize), b: &(u32, i64, usize, usize)| {
+                    (!criteria.transfers || a.0 <= b.0) && (!criteria.walking || a.1 <= b.1)
+                };
+                if riders.iter().any(|rider| beats(rider, &candidate)) {
+                    continue;
+                }
+                riders.retain(|rider| !beats(&candidate, rider));
+                riders.push(candidate);
+            }
+
+            let to = connection.to;
+            for &(rides, walk_secs, parent, board) in &on_board[connection.run] {
+                let ride = Label {
+                    arrival: connection.arrival,
+                    ready: self.change_secs[to].map_or(i64::MAX, |secs| connection.arrival + secs),
+                    rides,
+                    walk_secs,
+                    via: Some(Via::Ride { run: connection.run, board, alight: connection.position + 1 }),
+                    parent: Some(parent),
+                };
+                let Some(ride) = insert(&mut labels, &mut bags, to, ride) else {
+                    continue;
+                };
+                for &(stop, secs) in &self.footpaths[to] {
+                    let walk = Label {
+                        arrival: connection.arrival + secs,
+                        ready: connection.arrival + secs,
+                        rides,
+                        walk_secs: walk_secs + secs,
+                        via: Some(Via::Walk { from: to, to: stop, secs }),
+                        parent: Some(ride),
+                    };
+                    insert(&mut labels, &mut bags, stop, walk);
+                }
+            }
+        }
+
+        if target == origin {
+            return Vec::new();
+        }
+        let mut arrivals: Vec<usize> = bags[target].clone();
+        arrivals.sort_by_key(|&label| (labels[label].arrival, labels[label].rides, labels[label].walk_secs));
+        arrivals.into_iter()
+            .map(|label| {
+                let mut steps = Vec::new();
+                let mut label = &labels[label];
+                while let (Some(step), Some(parent)) = (label.via, label.parent) {
+                    steps.push(step);
+                    label = &labels[parent];
+                }
+                steps.reverse();
+                steps
+            })
+            .collect()
+    }
+
+    fn journey(&self, steps: &[Via], departure: i64, cache: &CachedNetworkData, lines: &[Line]) -> Journey {
+        let mut legs: Vec<JourneyLeg> = Vec::new();
+        for step in steps {
+            let previous_arrival = legs.last().map_or(departure, |leg| leg.arrival);
+            let cycle = match *step {
+                Via::Walk { from, to, .. } => self.cycles.get(&(from, to)).map(|cycle| (from, to, cycle)),
+                Via::Ride { .. } => None,
+            };
+            if let Some((from, to, cycle)) = cycle {
+                let (pick_up, drop_off) = (cycle.pick_up, cycle.drop_off);
+                let (from, to) = (&self.stops[from], &self.stops[to]);
+                let ends = [
+                    ("walk", (from.stop_id.as_ref(), from.name.as_ref()), (pick_up.station_id.as_str(), pick_up.name.as_str()), cycle.walk_to),
+                    ("bike", (pick_up.station_id.as_str(), pick_up.name.as_str()), (drop_off.station_id.as_str(), drop_off.name.as_str()), cycle.ride),
+                    ("walk", (drop_off.station_id.as_str(), drop_off.name.as_str()), (to.stop_id.as_ref(), to.name.as_ref()), cycle.walk_from),
+                ];
+                for (mode, from, to, secs) in ends {
+                    let departure = legs.last().map_or(departure, |leg| leg.arrival);
+                    legs.push(street_leg(mode, from, to, departure, secs));
+                }
+                continue;
+            }
+            let leg = match *step {
+                Via::Ride { run, board, alight } => {
+                    let run = &self.runs[run];
+                    let (from, to) = (&run.stops[board], &run.stops[alight]);
+                    let details = NVTModels::trip_details_in(run.trip_id, cache, lines);
+                    JourneyLeg {
+                        mode: "transit".to_string(),
+                        from_stop_id: self.stops[from.stop].stop_id.to_string(),
+                        from_stop_name: self.stops[from.stop].name.to_string(),
+                        to_stop_id: self.stops[to.stop].stop_id.to_string(),
+                        to_stop_name: self.stops[to.stop].name.to_string(),
+                        departure: from.departure,
+                        arrival: to.arrival,
+                        wait_secs: from.departure - previous_arrival,
+                        trip_id: Some(run.trip_id.to_string()),
+                        line_code: details.as_ref().map(|details| details.line_code.clone()),
+                        line_color: details.as_ref().map(|details| details.line_color.clone()),
+                        operator: details.as_ref().map(|details| details.operator.clone()),
+                        headsign: run.headsign.map(String::from),
+                        scheduled_departure: Some(match from.stop_time.departure_time.as_str() {
+                            "" => from.stop_time.arrival_time.clone(),
+                            time => time.to_string(),
+                        }),
+                        scheduled_arrival: Some(to.stop_time.arrival_time.clone()),
+                        departure_delay: from.departure_delay,
+                        arrival_delay: to.arrival_delay,
+                        stop_ids: run.stops[board..=alight].iter()
+                            .map(|stop| self.stops[stop.stop].stop_id.to_string())
+                            .collect(),
+                    }
+                }
+                Via::Walk { from, to, secs } => {
+                    let (from, to) = (&self.stops[from], &self.stops[to]);
+                    street_leg("walk", (&from.stop_id, &from.name), (&to.stop_id, &to.name), previous_arrival, secs)
+                }
+            };
+            legs.push(leg);
+        }
+
+        let rides = legs.iter().filter(|leg| leg.trip_id.is_some()).count();
+        let arrival = legs.last().map_or(departure, |leg| leg.arrival);
+        Journey {
+            departure: legs.first().map_or(departure, |leg| leg.departure),
+            arrival,
+            duration_secs: arrival - departure,
+            transfers: rides.saturating_sub(1) as u32,
+            walk_secs: walking_secs(&legs),
+            legs,
+        }
+    }
+}
+
+fn walk_secs(distance: f64) -> i64 {
+    (distance / WALK_SPEED).ceil() as i64
+}
+
+/// A walk or V³ ride between two (id, name) places, leaving at `departure`
+fn street_leg(mode: &str, (from_id, from_name): (&str, &str), (to_id, to_name): (&str, &str), departure: i64, secs: i64) -> JourneyLeg {
+    JourneyLeg {
+        mode: mode.to_string(),
+        from_stop_id: from_id.to_string(),
+        from_stop_name: from_name.to_string(),
+        to_stop_id: to_id.to_string(),
+        to_stop_name: to_name.to_string(),
+        departure,
+        arrival: departure + secs,
+        wait_secs: 0,
+        trip_id: None,
+        line_code: None,
+        line_color: None,
+        operator: None,
+        headsign: None,
+        scheduled_departure: None,
+        scheduled_arrival: None,
+        departure_delay: None,
+        arrival_delay: None,
+        stop_ids: vec![from_id.to_string(), to_id.to_string()],
+    }
+}
+
+/// Time spent in the walk legs of an itinerary
+fn walking_secs(legs: &[JourneyLeg]) -> i64 {
+    legs.iter().filter(|leg| leg.mode == "walk").map(|leg| leg.arrival - leg.departure).sum()
+}
+
+/// (latitude, longitude) of a stop of one of the GTFS feeds
+pub fn stop_position(cache: &CachedNetworkData, stop_id: &str) -> Option<(f64, f64)> {
+    cache.gtfs_caches().iter()
+        .find_map(|gtfs_cache| gtfs_cache.stops.iter().find(|(id, _, _, _)| id == stop_id))
+        .map(|(_, _, latitude, longitude)| (*latitude, *longitude))
+}
+
+/// Up to `count` itineraries from `from` to `to` at `time`: by arrival when leaving at a time,
+/// latest departure first when arriving by one, the Pareto set over the criteria of `options`
+/// when they weigh more than arrival (leaving at a time only). Empty when the destination
+/// can't be reached within the window.
+pub fn plan(cache: &CachedNetworkData, from: &Place, to: &Place, time: Time, options: Options, count: usize) -> Vec<Journey> {
+    let criteria = options.criteria;
+    let window_start = match time {
+        Time::DepartAt(departure) => departure,
+        Time::ArriveBy(arrival) => arrival - SEARCH_WINDOW_SECS,
+    };
+    let mut network = Network::build(cache, window_start, options);
+    let (Some(origin), Some(target)) = (network.add_place(from, false), network.add_place(to, true)) else {
+        return Vec::new();
+    };
+    network.add_direct_walk(origin, target, from, to);
+    // Not for wheelchair journeys
+    if let (true, false, Some(from), Some(to)) = (options.bikes, options.wheelchair, from.position(cache), to.position(cache)) {
+        network.add_bike_share(cache, origin, target, from, to);
+    }
+    let lines = cache.to_network_data().lines;
+
+    let departure = match time {
+        Time::DepartAt(departure) => departure,
+        Time::ArriveBy(deadline) => return arriving_by(&network, origin, target, deadline, count, cache, &lines),
+    };
+    if criteria.is_multi() {
+        let journeys: Vec<Journey> = network.pareto_arrivals(origin, target, departure, criteria).iter()
+            .map(|steps| network.journey(steps, departure, cache, &lines))
+            .collect();
+        return best_of(journeys, criteria, count);
+    }
+    let mut journeys: Vec<Journey> = Vec::new();
+    let mut leave_after = departure;
+    while journeys.len() < count {
+        let Some(steps) = network.earliest_arrival(origin, target, leave_after) else {
+            break;
+        };
+        let journey = network.journey(&steps, departure, cache, &lines);
+        // Leaving later only helps if the journey rides something
+        let Some(first_ride) = journey.legs.iter().find(|leg| leg.trip_id.is_some()).map(|leg| leg.departure) else {
+            journeys.push(journey);
+            break;
+        };
+        journeys.push(journey);
+        leave_after = first_ride + 1;
+    }
+    journeys
+}
+
+/// plan for Time::ArriveBy: each itinerary leaves at the latest time that still arrives by the
+/// deadline, the next one's deadline being just before this one's arrival
+fn arriving_by(network: &Network, origin: usize, target: usize, mut deadline: i64, count: usize, cache: &CachedNetworkData, lines: &[Line]) -> Vec<Journey> {
+    let window_start = deadline - SEARCH_WINDOW_SECS;
+    let mut journeys: Vec<Journey> = Vec::new();
+    while journeys.len() < count {
+        let Some(departure) = network.latest_departure(origin, target, deadline).filter(|&departure| departure >= window_start) else {
+            break;
+        };
+        let Some(steps) = network.earliest_arrival(origin, target, departure) else {
+            break;
+        };
+        let journey = network.journey(&steps, departure, cache, lines);
+        let rides = journey.legs.iter().any(|leg| leg.trip_id.is_some());
+        deadline = journey.arrival - 1;
+        journeys.push(journey);
+        if !rides {
+            break;
+        }
+    }
+    journeys
+}
+
+/// `count` of the Pareto-optimal `journeys` (by arrival): the best on each criterion first,
+/// then the earliest arrivals, by arrival
+fn best_of(journeys: Vec<Journey>, criteria: Criteria, count: usize) -> Vec<Journey> {
+    let mut picked: Vec<usize> = Vec::new();
+    let best = |key: &dyn Fn(&Journey) -> (i64, i64)| (0..journeys.len()).min_by_key(|&index| key(&journeys[index]));
+    let fewest_transfers = criteria.transfers.then(|| best(&|journey| (i64::from(journey.transfers), journey.arrival))).flatten();
+    let least_walking = criteria.walking.then(|| best(&|journey| (journey.walk_secs, journey.arrival))).flatten();
+    for index in [Some(0), fewest_transfers, least_walking].into_iter().flatten().chain(0..journeys.len()) {
+        if picked.len() < count && index < journeys.len() && !picked.contains(&index) {
+            picked.push(index);
+        }
+    }
+    picked.sort_unstable();
+    journeys.into_iter().enumerate()
+        .filter(|(index, _)| picked.contains(index))
+        .map(|(_, journey)| journey)
+        .collect()
+}
+
+// ============================================================================
+// Navitia Journeys
+// ============================================================================
+
+/// GTFS time ("HH:MM:SS", Europe/Paris) of a Navitia date time
+fn navitia_gtfs_time(value: &Value) -> Option<String> {
+    let timestamp = navitia::parse_time(value)?;
+    chrono::DateTime::from_timestamp(timestamp, 0).map(|time| time.with_timezone(&Paris).format("%H:%M:%S").to_string())
+}
+
+fn navitia_leg(section: &Value, previous_arrival: i64) -> Option<JourneyLeg> {
+    let mode = match section["type"].as_str()? {
+        "public_transport" => "transit",
+        "street_network" if section["mode"] == "bike" => "bike",
+        "street_network" | "transfer" | "crow_fly" => "walk",
+        _ => return None, // waiting, boarding, alighting...
+    };
+    let place = |place: &Value| (place["id"].as_str().unwrap_or_default().to_string(), place["name"].as_str().unwrap_or_default().to_string());
+    let ((from_stop_id, from_stop_name), (to_stop_id, to_stop_name)) = (place(&section["from"]), place(&section["to"]));
+    let departure = navitia::parse_time(&section["departure_date_time"])?;
+    let arrival = navitia::parse_time(&section["arrival_date_time"])?;
+    let info = &section["display_informations"];
+    let text = |value: &Value| value.as_str().filter(|text| !text.is_empty()).map(String::from);
+    let delay = |actual: i64, base: &Value| navitia::parse_time(base).map(|base| (actual - base) as i32);
+    let transit = mode == "transit";
+
+    let mut stop_ids: Vec<String> = section["stop_date_times"].as_array().into_iter().flatten()
+        .filter_map(|stop| stop["stop_point"]["id"].as_str().map(String::from))
+        .collect();
+    if stop_ids.is_empty() {
+        stop_ids = vec![from_stop_id.clone(), to_stop_id.clone()];
+    }
+
+    Some(JourneyLeg {
+        mode: mode.to_string(),
+        from_stop_id,
+        from_stop_name,
+        to_stop_id,
+        to_stop_name,
+        departure,
+        arrival,
+        wait_secs: if transit { departure - previous_arrival } else { 0 },
+        trip_id: transit.then(|| section["links"].as_array().into_iter().flatten()
+            .find(|link| link["type"] == "vehicle_journey")
+            .and_then(|link| text(&link["id"])))
+            .flatten(),
+        line_code: transit.then(|| text(&info["code"])).flatten(),
+        line_color: transit.then(|| text(&info["color"])).flatten(),
+        operator: transit.then(|| text(&info["network"])).flatten(),
+        headsign: transit.then(|| text(&info["headsign"]).or_else(|| text(&info["direction"]))).flatten(),
+        scheduled_departure: transit.then(|| navitia_gtfs_time(&section["base_departure_date_time"])
+            .or_else(|| navitia_gtfs_time(&section["departure_date_time"]))).flatten(),
+        scheduled_arrival: transit.then(|| navitia_gtfs_time(&section["base_arrival_date_time"])
+            .or_else(|| navitia_gtfs_time(&section["arrival_date_time"]))).flatten(),
+        departure_delay: transit.then(|| delay(departure, &section["base_departure_date_time"])).flatten(),
+        arrival_delay: transit.then(|| delay(arrival, &section["base_arrival_date_time"])).flatten(),
+        stop_ids,
+    })
+}
+
+/// Itineraries of a Navitia journeys response for a request at `time`. Stop, line and trip ids
+/// are Navitia's, not those of the local feeds.
+pub fn from_navitia(json: &Value, time: Time) -> Vec<Journey> {
+    json["journeys"].as_array().into_iter().flatten()
+        .filter_map(|journey| {
+            // Arrive-by journeys count from their own departure
+            let departure = match time {
+                Time::DepartAt(departure) => departure,
+                Time::ArriveBy(arrival) => navitia::parse_time(&journey["departure_date_time"]).unwrap_or(arrival),
+            };
+            let mut legs: Vec<JourneyLeg> = Vec::new();
+            for section in journey["sections"].as_array()? {
+                let previous_arrival = legs.last().map_or(departure, |leg| leg.arrival);
+                legs.extend(navitia_leg(section, previous_arrival));
+            }
+            let arrival = navitia::parse_time(&journey["arrival_date_time"])
+                .unwrap_or_else(|| legs.last().map_or(departure, |leg| leg.arrival));
+            let rides = legs.iter().filter(|leg| leg.mode == "transit").count();
+            Some(Journey {
+                departure: navitia::parse_time(&journey["departure_date_time"])
+                    .unwrap_or_else(|| legs.first().map_or(departure, |leg| leg.departure)),
+                arrival,
+                duration_secs: arrival - departure,
+                transfers: journey["nb_transfers"].as_u64().map_or(rides.saturating_sub(1) as u32, |transfers| transfers as u32),
+                walk_secs: walking_secs(&legs),
+                legs,
+            })
+        })
+        .filter(|journey| !journey.legs.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tbm_api_models::Upstreams;
+
+    fn day_start() -> i64 {
+        service_day_start(NaiveDate::from_ymd_opt(2026, 5, 4).expect("date")).expect("day start")
+    }
+
+    fn at(hour: i64, minute: i64) -> i64 {
+        day_start() + hour * 3600 + minute * 60
+    }
+
+    /// Stop times of a trip calling at (stop, "HH:MM") in order
+    fn trip(trip_id: &str, calls: &[(&str, &str)]) -> Vec<StopTime> {
+        calls.iter().enumerate()
+            .map(|(sequence, (stop_id, time))| StopTime {
+                trip_id: trip_id.to_string(),
+                arrival_time: format!("{}:00", time),
+                departure_time: format!("{}:00", time),
+                stop_id: stop_id.to_string(),
+                stop_sequence: sequence as u32 + 1,
+                stop_headsign: None,
+            })
+            .collect()
+    }
+
+    /// The stops (with their wheelchair boarding), no footpaths, changes of MIN_CHANGE_SECS, and
+    /// the trips running on 4 May 2026
+    fn network<'a>(stops: &[(&'a str, bool)], trips: &'a [Vec<StopTime>]) -> Network<'a> {
+        let mut network = Network {
+            stops: stops.iter()
+                .map(|&(stop_id, wheelchair_boarding)| StopNode { stop_id: Cow::Borrowed(stop_id), name: Cow::Borrowed(stop_id), wheelchair_boarding })
+                .collect(),
+            stop_ids: stops.iter().enumerate().map(|(stop, (stop_id, _))| (*stop_id, stop)).collect(),
+            change_secs: vec![Some(MIN_CHANGE_SECS); stops.len()],
+            footpaths: vec![Vec::new(); stops.len()],
+            cycles: HashMap::new(),
+            index: StopIndex::build(Vec::new()),
+            wheelchair: false,
+            bikes: false,
+            runs: Vec::new(),
+            connections: Vec::new(),
+        };
+        for stop_times in trips {
+            let stop_times: Vec<&StopTime> = stop_times.iter().collect();
+            let run = network.trip_run(&stop_times[0].trip_id, None, &stop_times, day_start(), None, None);
+            for (position, hop) in run.stops.windows(2).enumerate() {
+                network.connections.push(Connection {
+                    run: network.runs.len(),
+                    position,
+                    from: hop[0].stop,
+                    to: hop[1].stop,
+                    departure: hop[0].departure,
+                    arrival: hop[1].arrival,
+                });
+            }
+            network.runs.push(run);
+        }
+        network.connections.sort_by_key(|connection| (connection.departure, connection.arrival));
+        network
+    }
+
+    /// (trip or mode, from, to, departure, arrival) of each leg
+    fn legs(network: &Network, steps: &[Via], departure: i64) -> Vec<(String, String, String, String, String)> {
+        let clock = |time: i64| format!("{:02}:{:02}", (time - day_start()) / 3600, (time - day_start()) % 3600 / 60);
+        let cache = CachedNetworkData::empty(Upstreams::default());
+        network.journey(steps, departure, &cache, &[]).legs.into_iter()
+            .map(|leg| (leg.trip_id.unwrap_or(leg.mode), leg.from_stop_id, leg.to_stop_id, clock(leg.departure), clock(leg.arrival)))
+            .collect()
+    }
+
+    fn leg(trip: &str, from: &str, to: &str, departure: &str, arrival: &str) -> (String, String, String, String, String) {
+        (trip.to_string(), from.to_string(), to.to_string(), departure.to_string(), arrival.to_string())
+    }
+
+    #[test]
+    fn walks_all_the_way_when_it_is_fastest() {
+        let trips = [trip("T1", &[("A", "08:01"), ("B", "08:10")])];
+        let mut network = network(&[("A", true), ("B", true)], &trips);
+        network.footpaths[0].push((1, 300));
+
+        let steps = network.earliest_arrival(0, 1, at(8, 0)).expect("journey");
+        assert_eq!(legs(&network, &steps, at(8, 0)), [leg("walk", "A", "B", "08:00", "08:05")]);
+
+        let criteria = Criteria { transfers: true, walking: true };
+        let journeys = network.pareto_arrivals(0, 1, at(8, 0), criteria);
+        let legs: Vec<_> = journeys.iter().map(|steps| legs(&network, steps, at(8, 0))).collect();
+        // The ride arrives later, but without walking
+        assert_eq!(legs, [
+            vec![leg("walk", "A", "B", "08:00", "08:05")],
+            vec![leg("T1", "A", "B", "08:01", "08:10")],
+        ]);
+
+        assert!(network.earliest_arrival(0, 0, at(8, 0)).is_none());
+    }
+
+    #[test]
+    fn changes_at_a_stop_take_its_change_time() {
+        let trips = [
+            trip("T1", &[("A", "08:00"), ("B", "08:10")]),
+            trip("T2", &[("B", "08:11"), ("C", "08:20")]),
+            trip("T3", &[("B", "08:12"), ("C", "08:25")]),
+        ];
+        let mut network = network(&[("A", true), ("B", true), ("C", true)], &trips);
+
+        // T2 leaves a minute after T1 gets in, too soon for the 2-minute change
+        let steps = network.earliest_arrival(0, 2, at(7, 55)).expect("journey");
+        assert_eq!(legs(&network, &steps, at(7, 55)), [
+            leg("T1", "A", "B", "08:00", "08:10"),
+            leg("T3", "B", "C", "08:12", "08:25"),
+        ]);
+
+        network.change_secs[1] = Some(60);
+        let steps = network.earliest_arrival(0, 2, at(7, 55)).expect("journey");
+        assert_eq!(legs(&network, &steps, at(7, 55))[1], leg("T2", "B", "C", "08:11", "08:20"));
+
+        // transfers.txt transfer_type 3
+        network.change_secs[1] = None;
+        assert!(network.earliest_arrival(0, 2, at(7, 55)).is_none());
+    }
+
+    #[test]
+    fn pareto_set_keeps_one_of_equal_itineraries() {
+        let trips = [
+            trip("T1", &[("A", "08:00"), ("B", "08:10")]),
+            trip("T2", &[("B", "08:15"), ("C", "08:25")]),
+            trip("DIRECT", &[("A", "08:00"), ("C", "08:30")]),
+            trip("TWIN", &[("A", "08:00"), ("C", "08:30")]),
+            trip("T3", &[("A", "08:00"), ("D", "08:20")]),
+        ];
+        let mut network = network(&[("A", true), ("B", true), ("C", true), ("D", true)], &trips);
+        network.footpaths[3].push((2, 600)); // D to C, arriving at 08:30 like DIRECT
+
+        let criteria = Criteria { transfers: true, walking: true };
+        let journeys: Vec<_> = network.pareto_arrivals(0, 2, at(7, 55), criteria).iter()
+            .map(|steps| legs(&network, steps, at(7, 55)))
+            .collect();
+        assert_eq!(journeys.len(), 2, "{:?}", journeys);
+        assert_eq!(journeys[0], [leg("T1", "A", "B", "08:00", "08:10"), leg("T2", "B", "C", "08:15", "08:25")]);
+        // DIRECT and TWIN tie on every criterion, and both beat T3's walk
+        let direct = &journeys[1];
+        assert_eq!(direct.len(), 1, "{:?}", direct);
+        assert!(["DIRECT", "TWIN"].contains(&direct[0].0.as_str()), "{:?}", direct);
+        assert_eq!((direct[0].3.as_str(), direct[0].4.as_str()), ("08:00", "08:30"));
+
+        // On arrival alone, only the fastest is left
+        let fastest = network.pareto_arrivals(0, 2, at(7, 55), Criteria::default());
+        assert_eq!(fastest.len(), 1);
+    }
+
+    #[test]
+    fn wheelchair_trips_pass_the_stops_without_wheelchair_boarding() {
+        let trips = [
+            trip("T1", &[("A", "08:00"), ("B", "08:10"), ("C", "08:20")]),
+            trip("T2", &[("B", "08:15"), ("D", "08:25")]),
+        ];
+        let stops = [("A", true), ("B", false), ("C", true), ("D", true)];
+
+        // Without a wheelchair, changing at B is the way to D
+        let mut network = network(&stops, &trips);
+        network.footpaths[1].push((3, 1200));
+        let steps = network.earliest_arrival(0, 3, at(7, 55)).expect("journey");
+        assert_eq!(legs(&network, &steps, at(7, 55)), [
+            leg("T1", "A", "B", "08:00", "08:10"),
+            leg("T2", "B", "D", "08:15", "08:25"),
+        ]);
+
+        network.wheelchair = true;
+        network.keep_wheelchair_boarding();
+        assert!(network.footpaths[1].is_empty());
+        assert!(network.earliest_arrival(0, 3, at(7, 55)).is_none(), "no getting off at B");
+        assert_eq!(network.add_place(&Place::Stop("B".to_string()), true), None);
+        assert_eq!(network.add_place(&Place::Stop("C".to_string()), true), Some(2));
+
+        // T1 runs through B on the way to C
+        let steps = network.earliest_arrival(0, 2, at(7, 55)).expect("journey");
+        let cache = CachedNetworkData::empty(Upstreams::default());
+        let journey = network.journey(&steps, at(7, 55), &cache, &[]);
+        let stop_ids: Vec<&str> = journey.legs.iter().flat_map(|leg| leg.stop_ids.iter().map(String::as_str)).collect();
+        assert_eq!(stop_ids, ["A", "B", "C"]);
+    }
+}
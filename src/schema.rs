@@ -0,0 +1,571 @@
+// JSON Schema of the API payloads
+// The embedded tbm-transit.js reads these payloads as they are, so their shapes are a contract.
+// Each model's schema is declared below from its field list; the declaration destructures the
+// struct without `..`, so adding, renaming or retyping a field does not compile until the schema
+// follows. The document is served at /api/schema, and pipeline_tests checks real responses
+// against it and snapshots it (tests/golden/schema.json) so breaking changes show up in review.
+
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::amenities::StopAmenities;
+use crate::tbm_api_models::{
+    Agency, AgencyInfo, AlertInfo, AlertTranslation, CacheStats, CalendarDate, Departure, FeedInfo, InterpolatedPosition, Level, Line, LineStop, NetworkData, Pathway, RealTimeInfo, RefreshStatus, ScheduledArrival, ServiceCalendar, ShapePoint,
+    SourceStats, Stop, StopTime, TimedArrival, TransportMode, Trip, TripDetails, TripStopTime, VehicleDetails, VehiclesDelta, VehicleWithProgress,
+};
+
+/// Named object schemas, emitted under `$defs`
+#[derive(Default)]
+pub struct Definitions(BTreeMap<&'static str, Value>);
+
+pub trait JsonSchema {
+    /// May be null, and may be left out of an object (see skip_serializing_if)
+    const NULLABLE: bool = false;
+
+    fn schema(defs: &mut Definitions) -> Value;
+}
+
+impl Definitions {
+    /// `$ref` to the definition of `name`, building it on first use
+    fn reference(&mut self, name: &'static str, build: impl FnOnce(&mut Self) -> Value) -> Value {
+        if !self.0.contains_key(name) {
+            self.0.insert(name, Value::Null); // Placeholder, for models referring to themselves
+            let schema = build(self);
+            self.0.insert(name, schema);
+        }
+        json!({ "$ref": format!("#/$defs/{}", name) })
+    }
+}
+
+macro_rules! primitive_schema {
+    ($($ty:ty => $name:literal),* $(,)?) => {
+        $(impl JsonSchema for $ty {
+            fn schema(_: &mut Definitions) -> Value {
+                json!({ "type": $name })
+            }
+        })*
+    };
+}
+
+primitive_schema! {
+    String => "string",
+    bool => "boolean",
+    f32 => "number",
+    f64 => "number",
+    i32 => "integer",
+    i64 => "integer",
+    u8 => "integer",
+    u32 => "integer",
+    u64 => "integer",
+    usize => "integer",
+}
+
+impl<T: JsonSchema> JsonSchema for Option<T> {
+    const NULLABLE: bool = true;
+
+    fn schema(defs: &mut Definitions) -> Value {
+        let mut inner = T::schema(defs);
+        match inner.get("type").cloned() {
+            Some(Value::String(name)) => {
+                inner["type"] = json!([name, "null"]);
+                inner
+            }
+            _ => json!({ "anyOf": [inner, { "type": "null" }] }),
+        }
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Vec<T> {
+    fn schema(defs: &mut Definitions) -> Value {
+        json!({ "type": "array", "items": T::schema(defs) })
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for HashMap<String, T> {
+    fn schema(defs: &mut Definitions) -> Value {
+        json!({ "type": "object", "additionalProperties": T::schema(defs) })
+    }
+}
+
+impl<A: JsonSchema, B: JsonSchema> JsonSchema for (A, B) {
+    fn schema(defs: &mut Definitions) -> Value {
+        json!({
+            "type": "array",
+            "prefixItems": [A::schema(defs), B::schema(defs)],
+            "minItems": 2,
+            "maxItems": 2,
+        })
+    }
+}
+
+/// Object with these (name, schema, nullable) properties; the non-nullable ones are required
+fn object(fields: Vec<(&str, Value, bool)>) -> Value {
+    let required: Vec<&str> = fields.iter().filter(|(_, _, nullable)| !nullable).map(|(name, _, _)| *name).collect();
+    let properties: Map<String, Value> = fields.into_iter().map(|(name, schema, _)| (name.to_string(), schema)).collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+/// Adds properties to an object schema, for structs flattening another one
+fn flatten(defs: &Definitions, base: &str, fields: Vec<(&str, Value, bool)>) -> Value {
+    let mut schema = defs.0[base].clone();
+    let extra = object(fields);
+    for (name, property) in extra["properties"].as_object().into_iter().flatten() {
+        schema["properties"][name] = property.clone();
+    }
+    if let (Some(required), Some(extra)) = (schema["required"].as_array_mut(), extra["required"].as_array()) {
+        required.extend(extra.iter().cloned());
+    }
+    schema
+}
+
+macro_rules! object_schema {
+    ($($model:ident { $($field:ident: $ty:ty),* $(,)? })*) => {
+        $(impl JsonSchema for $model {
+            fn schema(defs: &mut Definitions) -> Value {
+                // Fails to compile when the schema and the struct disagree
+                let _ = |value: &$model| {
+                    let $model { $($field),* } = value;
+                    $(let _: &$ty = $field;)*
+                };
+                defs.reference(stringify!($model), |defs| object(vec![
+                    $((stringify!($field), <$ty as JsonSchema>::schema(defs), <$ty as JsonSchema>::NULLABLE)),*
+                ]))
+            }
+        })*
+    };
+}
+
+object_schema! {
+    Agency {
+        agency_id: String,
+        agency_name: String,
+        agency_url: String,
+        agency_timezone: String,
+        agency_phone: String,
+    }
+
+    AlertInfo {
+        id: String,
+        text: String,
+        description: String,
+        url: Option<String>,
+        route_ids: Vec<String>,
+        stop_ids: Vec<String>,
+        active_period_start: Option<i64>,
+        active_period_end: Option<i64>,
+        severity: u32,
+        active_periods: Vec<(Option<i64>, Option<i64>)>,
+        affected_stops_count: usize,
+        affected_stop_ids: Option<Vec<String>>,
+        derived: bool,
+        translations: Vec<AlertTranslation>,
+    }
+
+    AlertTranslation {
+        language: String,
+        text: Option<String>,
+        description: Option<String>,
+        url: Option<String>,
+    }
+
+    RealTimeInfo {
+        vehicle_id: String,
+        trip_id: String,
+        route_id: Option<String>,
+        direction_id: Option<u32>,
+        destination: Option<String>,
+        latitude: f64,
+        longitude: f64,
+        stop_id: Option<String>,
+        current_stop_sequence: Option<u32>,
+        timestamp: Option<i64>,
+        delay: Option<i32>,
+        bearing: Option<f32>,
+        occupancy: Option<String>,
+        source: Option<String>,
+        start_date: Option<String>,
+    }
+
+    StopAmenities {
+        shelter: Option<bool>,
+        bench: Option<bool>,
+        lit: Option<bool>,
+        tactile_paving: Option<bool>,
+        osm_node_id: Option<i64>,
+    }
+
+    Stop {
+        stop_id: String,
+        stop_name: String,
+        latitude: f64,
+        longitude: f64,
+        lines: Vec<String>,
+        alerts: Vec<AlertInfo>,
+        real_time: Vec<RealTimeInfo>,
+        commune: Option<String>,
+        insee_code: Option<String>,
+        amenities: Option<StopAmenities>,
+        wheelchair_boarding: Option<bool>,
+        level: Option<Level>,
+        pathways: Vec<Pathway>,
+    }
+
+    Level {
+        level_id: String,
+        level_index: f64,
+        level_name: Option<String>,
+    }
+
+    Pathway {
+        pathway_id: String,
+        from_stop_id: String,
+        to_stop_id: String,
+        pathway_mode: u8,
+        is_bidirectional: bool,
+        length: Option<f64>,
+        traversal_time: Option<u32>,
+        stair_count: Option<i32>,
+    }
+
+    InterpolatedPosition {
+        gps_latitude: f64,
+        gps_longitude: f64,
+        gps_age_secs: i64,
+        based_on: String,
+    }
+
+    ShapePoint {
+        latitude: f64,
+        longitude: f64,
+        sequence: u32,
+    }
+
+    Line {
+        line_ref: String,
+        line_name: String,
+        line_code: String,
+        route_id: String,
+        destinations: Vec<(String, String)>,
+        alerts: Vec<AlertInfo>,
+        real_time: Vec<RealTimeInfo>,
+        color: String,
+        text_color: String,
+        raw_color: String,
+        raw_text_color: Option<String>,
+        shape_ids: Vec<String>,
+        synthetic: bool,
+        operator: String,
+        mode: Option<TransportMode>,
+    }
+
+    NetworkData {
+        stops: Vec<Stop>,
+        lines: Vec<Line>,
+        shapes: HashMap<String, Vec<ShapePoint>>,
+    }
+
+    ScheduledArrival {
+        trip_id: String,
+        route_id: String,
+        line_code: String,
+        line_color: String,
+        arrival_time: String,
+        departure_time: String,
+        destination: Option<String>,
+        stop_headsign: Option<String>,
+        operator: String,
+        service_date: String,
+        wheelchair_accessible: Option<bool>,
+        bikes_allowed: Option<bool>,
+    }
+
+    CacheStats {
+        sources: Vec<SourceStats>,
+        vehicles: usize,
+        alerts: usize,
+        trip_updates: usize,
+        last_static_update: u64,
+        last_dynamic_update: u64,
+        static_age_secs: u64,
+        dynamic_age_secs: u64,
+        last_refresh: RefreshStatus,
+    }
+
+    SourceStats {
+        source: String,
+        stops: usize,
+        lines: usize,
+        routes: usize,
+        shapes: usize,
+        trips: usize,
+        gtfs_age_secs: u64,
+        cache_file_bytes: Option<u64>,
+        feed_info: Option<FeedInfo>,
+    }
+
+    FeedInfo {
+        publisher_name: String,
+        publisher_url: String,
+        lang: Option<String>,
+        version: Option<String>,
+        start_date: Option<String>,
+        end_date: Option<String>,
+    }
+
+    RefreshStatus {
+        at: u64,
+        succeeded: bool,
+        error: Option<String>,
+    }
+
+    VehicleDetails {
+        vehicle_id: String,
+        trip_id: String,
+        route_id: Option<String>,
+        line_code: String,
+        line_name: String,
+        line_color: String,
+        operator: String,
+        destination: Option<String>,
+        current_stop: Option<Stop>,
+        next_stop: Option<Stop>,
+        previous_stop: Option<Stop>,
+        latitude: f64,
+        longitude: f64,
+        timestamp: Option<i64>,
+        delay: Option<i32>,
+    }
+
+    StopTime {
+        trip_id: String,
+        arrival_time: String,
+        departure_time: String,
+        stop_id: String,
+        stop_sequence: u32,
+        stop_headsign: Option<String>,
+    }
+
+    Trip {
+        trip_id: String,
+        route_id: String,
+        service_id: String,
+        trip_headsign: Option<String>,
+        direction_id: Option<u32>,
+        wheelchair_accessible: Option<bool>,
+        bikes_allowed: Option<bool>,
+    }
+
+    ServiceCalendar {
+        service_id: String,
+        monday: bool,
+        tuesday: bool,
+        wednesday: bool,
+        thursday: bool,
+        friday: bool,
+        saturday: bool,
+        sunday: bool,
+        start_date: String,
+        end_date: String,
+    }
+
+    CalendarDate {
+        service_id: String,
+        date: String,
+        exception_type: u32,
+    }
+
+    TripDetails {
+        trip: Trip,
+        line_code: String,
+        line_color: String,
+        operator: String,
+        calendar: Option<ServiceCalendar>,
+        calendar_dates: Vec<CalendarDate>,
+        stop_times: Vec<TripStopTime>,
+        real_time: bool,
+    }
+}
+
+impl JsonSchema for TimedArrival<'_> {
+    fn schema(defs: &mut Definitions) -> Value {
+        let _ = |value: &TimedArrival| {
+            let TimedArrival { arrival, arrival_at, departure_at } = value;
+            let _: (&&ScheduledArrival, &Option<String>, &Option<String>) = (arrival, arrival_at, departure_at);
+        };
+        ScheduledArrival::schema(defs);
+        let timestamps = vec![
+            ("arrival_at", Option::<String>::schema(defs), true),
+            ("departure_at", Option::<String>::schema(defs), true),
+        ];
+        let schema = flatten(defs, "ScheduledArrival", timestamps);
+        defs.reference("TimedArrival", |_| schema)
+    }
+}
+
+impl JsonSchema for Departure<'_> {
+    fn schema(defs: &mut Definitions) -> Value {
+        let _ = |value: &Departure| {
+            let Departure { arrival, status, delay, expected_departure, expected_departure_at } = value;
+            let _: (&&ScheduledArrival, &String, &Option<i32>, &Option<i64>, &Option<String>) =
+                (arrival, status, delay, expected_departure, expected_departure_at);
+        };
+        ScheduledArrival::schema(defs);
+        let real_time = vec![
+            ("status", String::schema(defs), false),
+            ("delay", Option::<i32>::schema(defs), true),
+            ("expected_departure", Option::<i64>::schema(defs), true),
+            ("expected_departure_at", Option::<String>::schema(defs), true),
+        ];
+        let schema = flatten(defs, "ScheduledArrival", real_time);
+        defs.reference("Departure", |_| schema)
+    }
+}
+
+impl JsonSchema for VehicleWithProgress<'_> {
+    fn schema(defs: &mut Definitions) -> Value {
+        let _ = |value: &VehicleWithProgress| {
+            let VehicleWithProgress { vehicle, progress, stops_remaining, cluster_size, interpolated } = value;
+            let _: (&RealTimeInfo, &Option<f32>, &Option<u32>, &Option<usize>, &Option<InterpolatedPosition>) =
+                (vehicle, progress, stops_remaining, cluster_size, interpolated);
+        };
+        RealTimeInfo::schema(defs);
+        let progress = vec![
+            ("progress", Option::<f32>::schema(defs), true),
+            ("stops_remaining", Option::<u32>::schema(defs), true),
+            ("cluster_size", Option::<usize>::schema(defs), true),
+            ("interpolated", Option::<InterpolatedPosition>::schema(defs), true),
+        ];
+        let schema = flatten(defs, "RealTimeInfo", progress);
+        defs.reference("VehicleWithProgress", |_| schema)
+    }
+}
+
+impl JsonSchema for VehiclesDelta<'_> {
+    fn schema(defs: &mut Definitions) -> Value {
+        let _ = |value: &VehiclesDelta| {
+            let VehiclesDelta { since, next_since, vehicles, removed } = value;
+            let _: (&i64, &i64, &Vec<VehicleWithProgress>, &Vec<String>) = (since, next_since, vehicles, removed);
+        };
+        defs.reference("VehiclesDelta", |defs| object(vec![
+            ("since", i64::schema(defs), false),
+            ("next_since", i64::schema(defs), false),
+            ("vehicles", Vec::<VehicleWithProgress>::schema(defs), false),
+            ("removed", Vec::<String>::schema(defs), false),
+        ]))
+    }
+}
+
+impl JsonSchema for TransportMode {
+    fn schema(_: &mut Definitions) -> Value {
+        json!({ "type": "string", "enum": TransportMode::ALL.map(TransportMode::as_str) })
+    }
+}
+
+impl JsonSchema for AgencyInfo {
+    fn schema(defs: &mut Definitions) -> Value {
+        let _ = |value: &AgencyInfo| {
+            let AgencyInfo { agency, source, lines_count } = value;
+            let _: (&Agency, &String, &usize) = (agency, source, lines_count);
+        };
+        Agency::schema(defs);
+        let feed = vec![
+            ("source", String::schema(defs), false),
+            ("lines_count", usize::schema(defs), false),
+        ];
+        let schema = flatten(defs, "Agency", feed);
+        defs.reference("AgencyInfo", |_| schema)
+    }
+}
+
+impl JsonSchema for TripStopTime {
+    fn schema(defs: &mut Definitions) -> Value {
+        let _ = |value: &TripStopTime| {
+            let TripStopTime { stop_time, stop_name, delay, predicted_time, skipped } = value;
+            let _: (&StopTime, &Option<String>, &Option<i32>, &Option<i64>, &bool) =
+                (stop_time, stop_name, delay, predicted_time, skipped);
+        };
+        StopTime::schema(defs);
+        let real_time = vec![
+            ("stop_name", Option::<String>::schema(defs), true),
+            ("delay", Option::<i32>::schema(defs), true),
+            ("predicted_time", Option::<i64>::schema(defs), true),
+            ("skipped", bool::schema(defs), false),
+        ];
+        let schema = flatten(defs, "StopTime", real_time);
+        defs.reference("TripStopTime", |_| schema)
+    }
+}
+
+impl JsonSchema for LineStop {
+    fn schema(defs: &mut Definitions) -> Value {
+        let _ = |value: &LineStop| {
+            let LineStop { stop_sequence, stop } = value;
+            let _: (&u32, &Stop) = (stop_sequence, stop);
+        };
+        Stop::schema(defs);
+        let sequence = vec![("stop_sequence", u32::schema(defs), false)];
+        let schema = flatten(defs, "Stop", sequence);
+        defs.reference("LineStop", |_| schema)
+    }
+}
+
+/// Property names of a model, e.g. the fields a ?fields= selection may name
+pub fn properties<T: JsonSchema>() -> Vec<String> {
+    let mut defs = Definitions::default();
+    let reference = T::schema(&mut defs);
+    let name = reference["$ref"].as_str().unwrap_or_default().trim_start_matches("#/$defs/");
+    defs.0.get(name)
+        .and_then(|schema| schema["properties"].as_object())
+        .map(|properties| properties.keys().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Schema of the ApiResponse envelope around `data`
+fn envelope<T: JsonSchema>(defs: &mut Definitions) -> Value {
+    object(vec![
+        ("success", bool::schema(defs), false),
+        ("data", Option::<T>::schema(defs), true),
+        ("error", Option::<String>::schema(defs), true),
+        ("timestamp", i64::schema(defs), false),
+        ("sources", Vec::<String>::schema(defs), false),
+    ])
+}
+
+/// Schema document: one response schema per endpoint, keyed by route, and the shared models
+pub fn document() -> Value {
+    let mut defs = Definitions::default();
+    let endpoints = json!({
+        "/api/tbm/network": envelope::<NetworkData>(&mut defs),
+        "/api/tbm/stops": envelope::<Vec<Stop>>(&mut defs),
+        "/api/tbm/lines": envelope::<Vec<Line>>(&mut defs),
+        "/api/tbm/vehicles": envelope::<Vec<VehicleWithProgress>>(&mut defs),
+        "/api/tbm/vehicles?since=": envelope::<VehiclesDelta>(&mut defs),
+        "/api/tbm/shapes": envelope::<HashMap<String, Vec<ShapePoint>>>(&mut defs),
+        "/api/tbm/alerts": envelope::<Vec<AlertInfo>>(&mut defs),
+        "/api/tbm/stop/{id}": envelope::<Stop>(&mut defs),
+        "/api/tbm/stop/{id}/schedule": envelope::<Vec<TimedArrival>>(&mut defs),
+        "/api/tbm/stop/{id}/arrivals": envelope::<Vec<TimedArrival>>(&mut defs),
+        "/api/tbm/stop/{id}/departures": envelope::<Vec<Departure>>(&mut defs),
+        "/api/tbm/vehicle/{id}": envelope::<VehicleDetails>(&mut defs),
+        "/api/tbm/trip/{id}": envelope::<TripDetails>(&mut defs),
+        "/api/tbm/line/{code}": envelope::<Line>(&mut defs),
+        "/api/tbm/line/{code}/stops": envelope::<Vec<LineStop>>(&mut defs),
+        "/api/tbm/line/{code}/timetable": envelope::<Vec<TimedArrival>>(&mut defs),
+        "/api/tbm/line/{code}/shape": envelope::<HashMap<String, Vec<ShapePoint>>>(&mut defs),
+        "/api/tbm/agencies": envelope::<Vec<AgencyInfo>>(&mut defs),
+        "/api/tbm/agency/{id}/lines": envelope::<Vec<Line>>(&mut defs),
+        "/api/tbm/stats": envelope::<CacheStats>(&mut defs),
+    });
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "NVT Web Edition API responses",
+        "endpoints": endpoints,
+        "$defs": defs.0,
+    })
+}
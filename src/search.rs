@@ -0,0 +1,218 @@
+// Stop and line search
+// Names and codes of the stops and lines of every source are folded (lowercase, no accents or
+// punctuation) and split into words at each static load. A query matches the entries having,
+// for each of its words, an indexed word that is equal, starts with it, or is within a typo
+// or two of it; only the distinct indexed words are compared, not every record.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::tbm_api_models::{CachedNetworkData, NVTModels};
+
+const SAME_STOP_METERS: f64 = 100.0; // Platforms of one stop share its name; list it once
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchKind {
+    Stop,
+    Line,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResult {
+    pub kind: SearchKind,
+    pub id: String, // stop_id or line_ref
+    pub name: String,
+    pub line_code: Option<String>,
+    pub operator: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub score: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndex {
+    entries: Vec<SearchResult>, // score is set per query
+    words: BTreeMap<String, Vec<(usize, bool)>>, // key: folded word, value: (entry, is a line code)
+}
+
+/// Lowercase without accents, everything but letters and digits as spaces
+pub fn fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => folded.push('a'),
+            'ç' => folded.push('c'),
+            'è' | 'é' | 'ê' | 'ë' => folded.push('e'),
+            'ì' | 'í' | 'î' | 'ï' => folded.push('i'),
+            'ñ' => folded.push('n'),
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' => folded.push('o'),
+            'ù' | 'ú' | 'û' | 'ü' => folded.push('u'),
+            'ý' | 'ÿ' => folded.push('y'),
+            'œ' => folded.push_str("oe"),
+            'æ' => folded.push_str("ae"),
+            c if c.is_alphanumeric() => folded.push(c),
+            _ => folded.push(' '),
+        }
+    }
+    folded
+}
+
+/// Edit distance, giving up (None) past `max`
+fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            current[j + 1] = (previous[j] + usize::from(ca != cb))
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        if current.iter().min().is_some_and(|&best| best > max) {
+            return None;
+        }
+        previous = current;
+    }
+    previous.last().copied().filter(|&distance| distance <= max)
+}
+
+impl SearchIndex {
+    pub fn build(cache: &CachedNetworkData) -> Self {
+        let mut index = SearchIndex::default();
+
+        let stop = |id: &str, name: &str, lat: f64, lon: f64| SearchResult {
+            kind: SearchKind::Stop,
+            id: id.to_string(),
+            name: name.to_string(),
+            line_code: None,
+            operator: None,
+            latitude: Some(lat),
+            longitude: Some(lon),
+            score: 0,
+        };
+        for (id, name, lat, lon, _) in &cache.tbm_stops_metadata {
+            index.add(stop(id, name, *lat, *lon));
+        }
+        for s in cache.gtfs_stops() {
+            index.add(stop(&s.stop_id, &s.stop_name, s.latitude, s.longitude));
+        }
+
+        let line = |line_ref: &str, name: &str, code: &str, operator: &str| SearchResult {
+            kind: SearchKind::Line,
+            id: line_ref.to_string(),
+            name: name.to_string(),
+            line_code: Some(code.to_string()),
+            operator: Some(operator.to_string()),
+            latitude: None,
+            longitude: None,
+            score: 0,
+        };
+        for (line_ref, name, code, _) in &cache.tbm_lines_metadata {
+            index.add(line(line_ref, name, code, "TBM"));
+        }
+        for l in cache.gtfs_lines() {
+            index.add(line(&l.line_ref, &l.line_name, &l.line_code, &l.operator));
+        }
+
+        index
+    }
+
+    fn add(&mut self, entry: SearchResult) {
+        let id = self.entries.len();
+        let code = entry.line_code.as_deref().map(fold);
+        for word in fold(&entry.name).split_whitespace() {
+            self.words.entry(word.to_string()).or_default().push((id, false));
+        }
+        for word in code.iter().flat_map(|code| code.split_whitespace()) {
+            self.words.entry(word.to_string()).or_default().push((id, true));
+        }
+        self.entries.push(entry);
+    }
+
+    /// Score of each entry having a word matching `word`: 3 exact, 2 prefix, 1 typo
+    fn matches(&self, word: &str) -> HashMap<usize, u32> {
+        let mut scores: HashMap<usize, u32> = HashMap::new();
+        let mut record = |entries: &[(usize, bool)], score: u32| {
+            for &(id, is_code) in entries {
+                // Line codes are short: "a" should find tram A, not every line with an A in it
+                let score = if is_code && score == 3 { 4 } else { score };
+                let best = scores.entry(id).or_default();
+                *best = (*best).max(score);
+            }
+        };
+
+        for (indexed, entries) in self.words.range(word.to_string()..) {
+            if !indexed.starts_with(word) {
+                break;
+            }
+            record(entries, if indexed == word { 3 } else { 2 });
+        }
+
+        let max_typos = match word.chars().count() {
+            0..=3 => 0,
+            4..=7 => 1,
+            _ => 2,
+        };
+        if max_typos > 0 {
+            for (indexed, entries) in &self.words {
+                if !indexed.starts_with(word) && edit_distance(indexed, word, max_typos).is_some() {
+                    record(entries, 1);
+                }
+            }
+        }
+        scores
+    }
+
+    /// Entries matching every word of the query, best first
+    pub fn search(&self, query: &str, limit: usize) -> Vec<SearchResult> {
+        let folded = fold(query);
+        let words: Vec<&str> = folded.split_whitespace().collect();
+        let Some((first, rest)) = words.split_first() else {
+            return Vec::new();
+        };
+
+        let mut scores = self.matches(first);
+        for word in rest {
+            let word_scores = self.matches(word);
+            scores.retain(|id, score| match word_scores.get(id) {
+                Some(word_score) => {
+                    *score += word_score;
+                    true
+                }
+                None => false,
+            });
+        }
+
+        let mut ranked: Vec<(usize, u32)> = scores.into_iter().collect();
+        // Best score first, then shorter names (closer to the query), lines before stops
+        ranked.sort_by_key(|&(id, score)| {
+            let entry = &self.entries[id];
+            (std::cmp::Reverse(score), entry.name.len(), entry.kind == SearchKind::Stop, entry.id.clone())
+        });
+
+        let mut results: Vec<SearchResult> = Vec::new();
+        for (id, score) in ranked {
+            let entry = &self.entries[id];
+            if entry.kind == SearchKind::Stop && results.iter().any(|r| Self::same_stop(r, entry)) {
+                continue;
+            }
+            results.push(SearchResult { score, ..entry.clone() });
+            if results.len() == limit {
+                break;
+            }
+        }
+        results
+    }
+
+    fn same_stop(a: &SearchResult, b: &SearchResult) -> bool {
+        let (Some(lat_a), Some(lon_a), Some(lat_b), Some(lon_b)) = (a.latitude, a.longitude, b.latitude, b.longitude) else {
+            return false;
+        };
+        a.name == b.name && NVTModels::distance_meters(lat_a, lon_a, lat_b, lon_b) <= SAME_STOP_METERS
+    }
+}
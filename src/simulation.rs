@@ -0,0 +1,50 @@
+// Schedule what-ifs (POST /admin/simulate)
+// Planners post a partial GTFS feed (a new route, changed timetables, extra service days) which
+// is merged into a copy of the live cache, the sandbox. The GET endpoints of /api/tbm answer from
+// it under /api/sandbox/tbm, so the change can be previewed on the real map while the live data
+// stays untouched. Each simulation starts over from the live data of the moment, whose real-time
+// data the sandbox keeps as it was; DELETE /admin/simulate drops it.
+
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::tbm_api_models::{CachedNetworkData, GTFSCache, NVTModels, Result};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SimulationSummary {
+    pub source: String, // tbm, naq or sncf
+    pub routes: usize, // Routes, trips, stops and timed trips of the fragment
+    pub trips: usize,
+    pub stops: usize,
+    pub timed_trips: usize, // Trips whose stop times the fragment replaces
+    pub lines: usize, // In the sandbox, all sources
+    pub created_at: i64,
+}
+
+/// Whether a sandbox holds a simulation (an empty one has never loaded static data)
+pub fn is_loaded(sandbox: &CachedNetworkData) -> bool {
+    sandbox.last_static_update != 0
+}
+
+/// Merge `fragment` into the `source` feed of `sandbox`, a copy of the live cache
+pub fn simulate(mut sandbox: CachedNetworkData, source: &str, fragment: GTFSCache) -> Result<(CachedNetworkData, SimulationSummary)> {
+    let timed_trips: HashSet<&String> = fragment.stop_times.values().flatten()
+        .map(|stop_time| &stop_time.trip_id)
+        .collect();
+    let mut summary = SimulationSummary {
+        source: source.to_string(),
+        routes: fragment.route_to_shapes.keys().chain(fragment.routes.keys())
+            .chain(fragment.trips.values().map(|trip| &trip.route_id))
+            .collect::<HashSet<_>>()
+            .len(),
+        trips: fragment.trips.len(),
+        stops: fragment.stops.len(),
+        timed_trips: timed_trips.len(),
+        lines: 0,
+        created_at: NVTModels::get_current_timestamp(),
+    };
+
+    NVTModels::merge_gtfs_fragment(&mut sandbox, source, fragment)?;
+    summary.lines = sandbox.to_network_data().lines.len();
+    Ok((sandbox, summary))
+}
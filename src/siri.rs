@@ -0,0 +1,125 @@
+// SIRI-Lite StopMonitoring (GET /api/siri/2.0/stop-monitoring.json)
+// The merged departures of a stop (see get_stop_departures) as a SIRI-Lite 2.0
+// StopMonitoringDelivery, in the JSON shape of the TBM SIRI-Lite service this server reads from,
+// so that displays and apps speaking SIRI can point at it directly. MonitoringRef takes a stop_id
+// of any source, or a TBM SIRI StopPointRef such as "bordeaux:StopPoint:BP:3672:LOC". Times
+// are RFC3339 in Europe/Paris; expected times are only given for departures with real-time data.
+
+use chrono::{DateTime, Duration};
+use chrono_tz::Europe::Paris;
+use chrono_tz::Tz;
+use serde_json::{json, Value};
+
+use crate::tbm_api_models::{Departure, NVTModels};
+use crate::time_format;
+
+const PRODUCER_REF: &str = "NVTWebEdition";
+
+/// Delays within a minute either way count as on time
+const ON_TIME_SECS: i32 = 60;
+
+fn value(text: &str) -> Value {
+    json!({ "value": text })
+}
+
+fn timestamp(unix: i64) -> String {
+    DateTime::from_timestamp(unix, 0)
+        .map(|dt| dt.with_timezone(&Paris).to_rfc3339())
+        .unwrap_or_default()
+}
+
+fn service_delivery(now: i64, delivery: Value) -> Value {
+    json!({
+        "Siri": {
+            "ServiceDelivery": {
+                "ResponseTimestamp": timestamp(now),
+                "ProducerRef": value(PRODUCER_REF),
+                "StopMonitoringDelivery": [delivery],
+            }
+        }
+    })
+}
+
+/// StopMonitoringDelivery of a stop; `recorded_at` is the time of the real-time data
+pub fn stop_monitoring(monitoring_ref: &str, stop_name: &str, departures: &[Departure], recorded_at: i64, now: i64) -> Value {
+    let visits: Vec<Value> = departures.iter()
+        .map(|departure| stop_visit(monitoring_ref, stop_name, departure, recorded_at))
+        .collect();
+    service_delivery(now, json!({
+        "version": "2.0",
+        "ResponseTimestamp": timestamp(now),
+        "Status": true,
+        "MonitoringRef": [value(monitoring_ref)],
+        "MonitoredStopVisit": visits,
+    }))
+}
+
+/// StopMonitoringDelivery refusing the request, e.g. for an unknown MonitoringRef
+pub fn error_delivery(monitoring_ref: &str, message: &str, now: i64) -> Value {
+    service_delivery(now, json!({
+        "version": "2.0",
+        "ResponseTimestamp": timestamp(now),
+        "Status": false,
+        "MonitoringRef": [value(monitoring_ref)],
+        "ErrorCondition": { "ErrorInformation": { "ErrorText": message } },
+    }))
+}
+
+fn stop_visit(monitoring_ref: &str, stop_name: &str, departure: &Departure, recorded_at: i64) -> Value {
+    let arrival = departure.arrival;
+    let aimed = |gtfs_time: &str| -> Option<DateTime<Tz>> {
+        time_format::gtfs_datetime(arrival.service_day()?, gtfs_time)
+    };
+    let expected = |time: Option<DateTime<Tz>>| -> Option<String> {
+        let delay = departure.delay?;
+        Some((time? + Duration::seconds(i64::from(delay))).to_rfc3339())
+    };
+    let (aimed_arrival, aimed_departure) = (aimed(&arrival.arrival_time), aimed(&arrival.departure_time));
+    let status = match departure.delay {
+        None => "noReport",
+        Some(delay) if delay >= ON_TIME_SECS => "delayed",
+        Some(delay) if delay <= -ON_TIME_SECS => "early",
+        Some(_) => "onTime",
+    };
+    let destination = arrival.stop_headsign.as_deref().or(arrival.destination.as_deref()).unwrap_or_default();
+
+    let mut call = json!({
+        "StopPointRef": value(monitoring_ref),
+        "StopPointName": [value(stop_name)],
+        "AimedArrivalTime": aimed_arrival.map(|dt| dt.to_rfc3339()),
+        "ExpectedArrivalTime": expected(aimed_arrival),
+        "AimedDepartureTime": aimed_departure.map(|dt| dt.to_rfc3339()),
+        "ExpectedDepartureTime": expected(aimed_departure),
+        "DepartureStatus": status,
+    });
+    // SIRI leaves out the times it does not know
+    if let Value::Object(fields) = &mut call {
+        fields.retain(|_, field| !field.is_null());
+    }
+
+    json!({
+        "RecordedAtTime": timestamp(recorded_at),
+        "ItemIdentifier": format!("{}:{}:{}", monitoring_ref, arrival.trip_id, arrival.service_date),
+        "MonitoringRef": value(monitoring_ref),
+        "MonitoredVehicleJourney": {
+            "LineRef": value(&arrival.route_id),
+            "FramedVehicleJourneyRef": {
+                "DataFrameRef": value(&arrival.service_date),
+                "DatedVehicleJourneyRef": arrival.trip_id,
+            },
+            "PublishedLineName": [value(&arrival.line_code)],
+            "OperatorRef": value(&arrival.operator),
+            "DestinationName": [value(destination)],
+            "Monitored": departure.delay.is_some(),
+            "MonitoredCall": call,
+        },
+    })
+}
+
+/// stop_id of a MonitoringRef: one of the stops as it is, or the stop of a TBM StopPointRef
+pub fn stop_id(monitoring_ref: &str, known: impl Fn(&str) -> bool) -> Option<String> {
+    if known(monitoring_ref) {
+        return Some(monitoring_ref.to_string());
+    }
+    NVTModels::extract_stop_id(monitoring_ref).filter(|stop_id| monitoring_ref.contains(":StopPoint:") && known(stop_id))
+}
@@ -0,0 +1,137 @@
+// Cache snapshots
+// The serializable part of `CachedNetworkData`: everything but what is derived at load (trip,
+// stop and search indexes, alert scopes). Split in the static data, which changes with the GTFS
+// feeds, and the real-time data of the last refresh. The bench fixture is one of each, and a
+// standby instance pulls them from its primary (see sync.rs).
+
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::amenities::StopAmenities;
+use crate::communes::Commune;
+use crate::gbfs::BikeShare;
+use crate::headways::HeadwayIndex;
+use crate::search::SearchIndex;
+use crate::spatial::StopIndex;
+use crate::tbm_api_models::{
+    AlertInfo, CachedNetworkData, FeedData, GTFSCache, NVTModels, RealTimeInfo, RefreshStatus, TbmLineMetadata, TbmStopMetadata,
+    Upstreams,
+};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StaticSnapshot {
+    #[serde(default)]
+    pub last_static_update: u64,
+    pub tbm_stops_metadata: Vec<TbmStopMetadata>,
+    pub tbm_lines_metadata: Vec<TbmLineMetadata>,
+    pub tbm_gtfs_cache: GTFSCache,
+    pub sources: BTreeMap<String, FeedData>, // key: the other sources, NewAquitaine, SNCF and the plain feeds by name
+    pub stop_communes: HashMap<String, Commune>,
+    pub stop_amenities: HashMap<String, StopAmenities>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DynamicSnapshot {
+    #[serde(default)]
+    pub last_dynamic_update: u64,
+    pub alerts: Vec<AlertInfo>,
+    pub real_time: Vec<RealTimeInfo>,
+    pub trip_updates: Vec<Vec<u8>>, // protobuf-encoded, the gtfs-rt types have no serde support
+    #[serde(default)]
+    pub bike_share: BikeShare,
+}
+
+impl StaticSnapshot {
+    pub fn from_cache(cache: &CachedNetworkData) -> Self {
+        StaticSnapshot {
+            last_static_update: cache.last_static_update,
+            tbm_stops_metadata: cache.tbm_stops_metadata.clone(),
+            tbm_lines_metadata: cache.tbm_lines_metadata.clone(),
+            tbm_gtfs_cache: cache.tbm_gtfs_cache.clone(),
+            sources: [
+                ("NewAquitaine", &cache.transgironde_stops, &cache.transgironde_lines, &cache.transgironde_gtfs_cache),
+                ("SNCF", &cache.sncf_stops, &cache.sncf_lines, &cache.sncf_gtfs_cache),
+            ]
+            .into_iter()
+            .map(|(source, stops, lines, gtfs_cache)| {
+                (source.to_string(), FeedData { stops: stops.clone(), lines: lines.clone(), gtfs_cache: gtfs_cache.clone() })
+            })
+            .chain(cache.feeds.clone())
+            .collect(),
+            stop_communes: cache.stop_communes.clone(),
+            stop_amenities: cache.stop_amenities.clone(),
+        }
+    }
+
+    /// Cache of this static data and `dynamic`, with its indexes built
+    pub fn into_cache(mut self, dynamic: DynamicSnapshot, upstreams: Upstreams) -> CachedNetworkData {
+        let trip_updates = dynamic.trip_updates();
+        let mut source = |name: &str| self.sources.remove(name).unwrap_or_else(|| FeedData::empty(name));
+        let (transgironde, sncf) = (source("NewAquitaine"), source("SNCF"));
+        let mut cache = CachedNetworkData {
+            tbm_stops_metadata: self.tbm_stops_metadata,
+            tbm_lines_metadata: self.tbm_lines_metadata,
+            tbm_gtfs_cache: self.tbm_gtfs_cache,
+            transgironde_stops: transgironde.stops,
+            transgironde_lines: transgironde.lines,
+            transgironde_gtfs_cache: transgironde.gtfs_cache,
+            sncf_stops: sncf.stops,
+            sncf_lines: sncf.lines,
+            sncf_gtfs_cache: sncf.gtfs_cache,
+            feeds: self.sources,
+            trip_index: HashMap::new(),
+            stop_communes: self.stop_communes,
+            stop_index: StopIndex::default(),
+            search_index: SearchIndex::default(),
+            headways: HeadwayIndex::default(),
+            stop_amenities: self.stop_amenities,
+            alert_stops: HashMap::new(),
+            last_static_update: self.last_static_update,
+            alerts: dynamic.alerts,
+            real_time: dynamic.real_time,
+            vanished_vehicles: HashMap::new(),
+            trip_updates,
+            bike_share: dynamic.bike_share,
+            last_dynamic_update: dynamic.last_dynamic_update,
+            last_refresh: RefreshStatus::default(),
+            source_freshness: BTreeMap::new(),
+            upstreams,
+        };
+        cache.trip_index = NVTModels::build_trip_index(&cache.gtfs_caches());
+        cache.stop_index = StopIndex::build(cache.stop_positions());
+        cache.search_index = SearchIndex::build(&cache);
+        cache.headways = HeadwayIndex::build(&cache, NVTModels::paris_now().date_naive());
+        cache.expand_alert_scope();
+        cache
+    }
+}
+
+impl DynamicSnapshot {
+    pub fn from_cache(cache: &CachedNetworkData) -> Self {
+        DynamicSnapshot {
+            last_dynamic_update: cache.last_dynamic_update,
+            alerts: cache.alerts.clone(),
+            real_time: cache.real_time.clone(),
+            trip_updates: cache.trip_updates.iter().map(|update| update.encode_to_vec()).collect(),
+            bike_share: cache.bike_share.clone(),
+        }
+    }
+
+    pub fn trip_updates(&self) -> Vec<gtfs_rt::TripUpdate> {
+        self.trip_updates.iter()
+            .filter_map(|bytes| gtfs_rt::TripUpdate::decode(bytes.as_slice()).ok())
+            .collect()
+    }
+
+    /// Replace the real-time data of `cache`, as a refresh would
+    pub fn apply(self, cache: &mut CachedNetworkData) {
+        cache.trip_updates = self.trip_updates();
+        cache.alerts = self.alerts;
+        cache.bike_share = self.bike_share;
+        let previous_vehicles = std::mem::replace(&mut cache.real_time, self.real_time);
+        cache.last_dynamic_update = self.last_dynamic_update;
+        cache.track_vanished_vehicles(&previous_vehicles, NVTModels::get_current_timestamp() as u64);
+        cache.expand_alert_scope();
+    }
+}
@@ -0,0 +1,179 @@
+// Spatial index of stops
+// Stops of every source are bucketed in a grid of CELL_DEGREES cells, rebuilt at each static
+// load next to the trip index. A radius query only measures the stops of the cells the circle
+// overlaps instead of the whole network (~30k stops).
+// Map markers are thinned the same way for ?zoom=: one item per grid cell of the map tiles,
+// so a zoomed-out view of the SNCF network gets a few hundred vehicles instead of thousands,
+// and stops are sent as clusters of each cell with their centroid and count.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::collections::hash_map::Entry;
+
+use crate::tbm_api_models::{BoundingBox, CachedNetworkData, NVTModels, Stop};
+
+const CELL_DEGREES: f64 = 0.01; // ~1.1 km of latitude, ~0.8 km of longitude around Bordeaux
+const METERS_PER_DEGREE: f64 = 111_320.0;
+const THINNING_CELL_PX: f64 = 48.0; // Roughly a marker with its margin
+const TILE_PX: f64 = 256.0;
+
+/// Zoom levels from which markers are no longer thinned
+pub const FULL_DETAIL_ZOOM: u8 = 13;
+pub const MAX_ZOOM: u8 = 22;
+
+type IndexedStop = (String, f64, f64); // (stop_id, lat, lon)
+
+#[derive(Debug, Clone, Default)]
+pub struct StopIndex {
+    stops: Vec<IndexedStop>,
+    cells: HashMap<(i32, i32), Vec<usize>>, // key: (lat cell, lon cell), value: indices into `stops`
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NearbyStop {
+    #[serde(flatten)]
+    pub stop: Stop,
+    pub distance_m: f64,
+}
+
+/// Stops drawn as one marker at a zoom level, as supercluster would group them
+#[derive(Debug, Clone, Serialize)]
+pub struct StopCluster {
+    pub latitude: f64, // Centroid of its stops
+    pub longitude: f64,
+    pub count: usize,
+    pub stop_id: Option<String>, // The stop of a single-stop cluster
+    pub expansion_zoom: Option<u8>, // First zoom at which it splits; None for a single stop
+}
+
+fn cell(lat: f64, lon: f64) -> (i32, i32) {
+    ((lat / CELL_DEGREES).floor() as i32, (lon / CELL_DEGREES).floor() as i32)
+}
+
+impl StopIndex {
+    pub fn build(stops: Vec<IndexedStop>) -> Self {
+        let mut cells: HashMap<(i32, i32), Vec<usize>> = HashMap::new();
+        for (i, (_, lat, lon)) in stops.iter().enumerate() {
+            cells.entry(cell(*lat, *lon)).or_default().push(i);
+        }
+        StopIndex { stops, cells }
+    }
+
+    /// (stop_id, distance in meters) of the stops within `radius_m`, nearest first
+    pub fn within(&self, lat: f64, lon: f64, radius_m: f64) -> Vec<(&str, f64)> {
+        let lat_span = radius_m / METERS_PER_DEGREE;
+        let lon_span = radius_m / (METERS_PER_DEGREE * lat.to_radians().cos().max(0.01));
+        let (min_lat, min_lon) = cell(lat - lat_span, lon - lon_span);
+        let (max_lat, max_lon) = cell(lat + lat_span, lon + lon_span);
+
+        let mut found: Vec<(&str, f64)> = (min_lat..=max_lat)
+            .flat_map(|lat_cell| (min_lon..=max_lon).map(move |lon_cell| (lat_cell, lon_cell)))
+            .filter_map(|key| self.cells.get(&key))
+            .flatten()
+            .filter_map(|&i| {
+                let (stop_id, stop_lat, stop_lon) = &self.stops[i];
+                let distance = NVTModels::distance_meters(lat, lon, *stop_lat, *stop_lon);
+                (distance <= radius_m).then_some((stop_id.as_str(), distance))
+            })
+            .collect();
+
+        found.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(b.0)));
+        found
+    }
+
+    /// Stops in `bbox` grouped by map cell at `zoom`, in cell order; from FULL_DETAIL_ZOOM on
+    /// every stop is its own cluster
+    pub fn clusters(&self, bbox: Option<&BoundingBox>, zoom: u8) -> Vec<StopCluster> {
+        let mut stops: Vec<&IndexedStop> = self.stops.iter()
+            .filter(|(_, lat, lon)| bbox.is_none_or(|bbox| bbox.contains(*lat, *lon)))
+            .collect();
+        if zoom >= FULL_DETAIL_ZOOM {
+            stops.sort_by(|a, b| a.0.cmp(&b.0));
+            return stops.into_iter().map(|stop| cluster_of(&[stop], None)).collect();
+        }
+
+        group_by_map_cell(&stops, zoom).into_values()
+            .map(|members| {
+                let expansion_zoom = (members.len() > 1).then(|| {
+                    (zoom + 1..FULL_DETAIL_ZOOM)
+                        .find(|&z| group_by_map_cell(&members, z).len() > 1)
+                        .unwrap_or(FULL_DETAIL_ZOOM)
+                });
+                cluster_of(&members, expansion_zoom)
+            })
+            .collect()
+    }
+}
+
+fn group_by_map_cell<'a>(stops: &[&'a IndexedStop], zoom: u8) -> BTreeMap<(i64, i64), Vec<&'a IndexedStop>> {
+    let mut cells: BTreeMap<(i64, i64), Vec<&IndexedStop>> = BTreeMap::new();
+    for &stop in stops {
+        cells.entry(map_cell(stop.1, stop.2, zoom)).or_default().push(stop);
+    }
+    cells
+}
+
+fn cluster_of(members: &[&IndexedStop], expansion_zoom: Option<u8>) -> StopCluster {
+    let count = members.len();
+    let mean = |value: fn(&IndexedStop) -> f64| {
+        let mean = members.iter().map(|stop| value(stop)).sum::<f64>() / count as f64;
+        (mean * 1e6).round() / 1e6
+    };
+    StopCluster {
+        latitude: mean(|stop| stop.1),
+        longitude: mean(|stop| stop.2),
+        count,
+        stop_id: (count == 1).then(|| members[0].0.clone()),
+        expansion_zoom,
+    }
+}
+
+/// Up to `limit` stops within `radius_m` of a point, nearest first, with their live data
+pub fn nearby_stops(cache: &CachedNetworkData, lat: f64, lon: f64, radius_m: f64, limit: usize) -> Vec<NearbyStop> {
+    let mut nearest = cache.stop_index.within(lat, lon, radius_m);
+    nearest.truncate(limit);
+    if nearest.is_empty() {
+        return Vec::new();
+    }
+
+    let mut stops: HashMap<String, Stop> = cache.to_network_data().stops.into_iter()
+        .map(|stop| (stop.stop_id.clone(), stop))
+        .collect();
+
+    nearest.into_iter()
+        .filter_map(|(stop_id, distance)| {
+            stops.remove(stop_id).map(|stop| NearbyStop { stop, distance_m: distance.round() })
+        })
+        .collect()
+}
+
+/// Cell of a point in a THINNING_CELL_PX grid over the web mercator map at `zoom`
+fn map_cell(lat: f64, lon: f64, zoom: u8) -> (i64, i64) {
+    let world_px = TILE_PX * 2f64.powi(i32::from(zoom));
+    let lat = lat.clamp(-85.0511, 85.0511).to_radians();
+    let x = (lon + 180.0) / 360.0 * world_px;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / std::f64::consts::PI) / 2.0 * world_px;
+    ((x / THINNING_CELL_PX).floor() as i64, (y / THINNING_CELL_PX).floor() as i64)
+}
+
+/// The first item of each map cell at `zoom`, in input order, with the number of items in its
+/// cell; from FULL_DETAIL_ZOOM on, every item is kept with a count of 1
+pub fn thin_by_zoom<T>(items: Vec<T>, zoom: u8, position: impl Fn(&T) -> (f64, f64)) -> Vec<(T, usize)> {
+    if zoom >= FULL_DETAIL_ZOOM {
+        return items.into_iter().map(|item| (item, 1)).collect();
+    }
+
+    let mut cells: HashMap<(i64, i64), usize> = HashMap::new(); // value: index into `kept`
+    let mut kept: Vec<(T, usize)> = Vec::new();
+    for item in items {
+        let (lat, lon) = position(&item);
+        match cells.entry(map_cell(lat, lon, zoom)) {
+            Entry::Occupied(entry) => kept[*entry.get()].1 += 1,
+            Entry::Vacant(entry) => {
+                entry.insert(kept.len());
+                kept.push((item, 1));
+            }
+        }
+    }
+    kept
+}
@@ -0,0 +1,213 @@
+// Static map images
+// Renders a line's shapes and stops onto a plain background and encodes the result as PNG,
+// for clients that can't run the interactive map (emails, notifications, previews).
+
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+use std::io::Write;
+
+use crate::tbm_api_models::{Line, ShapePoint, Stop};
+
+pub const DEFAULT_WIDTH: u32 = 600;
+pub const DEFAULT_HEIGHT: u32 = 400;
+pub const MIN_SIZE: u32 = 64;
+pub const MAX_SIZE: u32 = 2048;
+
+const PADDING: f64 = 24.0;
+const BACKGROUND: [u8; 3] = [0xF2, 0xEF, 0xE9];
+const STOP_FILL: [u8; 3] = [0xFF, 0xFF, 0xFF];
+
+// ============================================================================
+// Canvas
+// ============================================================================
+
+struct Canvas {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>, // RGB, row-major
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32, background: [u8; 3]) -> Self {
+        let pixels = background.iter()
+            .copied()
+            .cycle()
+            .take((width * height * 3) as usize)
+            .collect();
+        Canvas { width, height, pixels }
+    }
+
+    fn set_pixel(&mut self, x: i64, y: i64, color: [u8; 3]) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let offset = ((y as u32 * self.width + x as u32) * 3) as usize;
+        self.pixels[offset..offset + 3].copy_from_slice(&color);
+    }
+
+    fn fill_disc(&mut self, cx: f64, cy: f64, radius: f64, color: [u8; 3]) {
+        let r = radius.ceil() as i64;
+        let (px, py) = (cx.round() as i64, cy.round() as i64);
+        for dy in -r..=r {
+            for dx in -r..=r {
+                if ((dx * dx + dy * dy) as f64) <= radius * radius {
+                    self.set_pixel(px + dx, py + dy, color);
+                }
+            }
+        }
+    }
+
+    /// Thick segment drawn by stamping discs along its length
+    fn draw_line(&mut self, from: (f64, f64), to: (f64, f64), thickness: f64, color: [u8; 3]) {
+        let length = ((to.0 - from.0).powi(2) + (to.1 - from.1).powi(2)).sqrt();
+        let steps = length.ceil().max(1.0) as u32;
+        for i in 0..=steps {
+            let t = i as f64 / steps as f64;
+            self.fill_disc(
+                from.0 + (to.0 - from.0) * t,
+                from.1 + (to.1 - from.1) * t,
+                thickness / 2.0,
+                color,
+            );
+        }
+    }
+
+    fn encode_png(&self) -> Vec<u8> {
+        // Raw scanlines, each prefixed with filter type 0 (none)
+        let row_len = (self.width * 3) as usize;
+        let mut raw = Vec::with_capacity((row_len + 1) * self.height as usize);
+        for row in self.pixels.chunks(row_len) {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&raw).ok();
+        let compressed = encoder.finish().unwrap_or_default();
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&self.width.to_be_bytes());
+        ihdr.extend_from_slice(&self.height.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8-bit depth, truecolor, deflate, no filter, no interlace
+
+        let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+        write_chunk(&mut png, b"IHDR", &ihdr);
+        write_chunk(&mut png, b"IDAT", &compressed);
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(kind);
+    hasher.update(data);
+    png.extend_from_slice(&hasher.finalize().to_be_bytes());
+}
+
+// ============================================================================
+// Projection
+// ============================================================================
+
+fn mercator_y(latitude: f64) -> f64 {
+    (std::f64::consts::FRAC_PI_4 + latitude.to_radians() / 2.0).tan().ln()
+}
+
+/// Fits a set of (lat, lon) points into the image, keeping the aspect ratio
+struct Projection {
+    min_x: f64,
+    max_y: f64,
+    scale: f64,
+    offset_x: f64,
+    offset_y: f64,
+}
+
+impl Projection {
+    fn fit(points: &[(f64, f64)], width: u32, height: u32) -> Option<Self> {
+        if points.is_empty() {
+            return None;
+        }
+
+        let xs = points.iter().map(|(_, lon)| lon.to_radians());
+        let ys = points.iter().map(|(lat, _)| mercator_y(*lat));
+        let (min_x, max_x) = xs.fold((f64::MAX, f64::MIN), |(lo, hi), x| (lo.min(x), hi.max(x)));
+        let (min_y, max_y) = ys.fold((f64::MAX, f64::MIN), |(lo, hi), y| (lo.min(y), hi.max(y)));
+
+        let available_w = (width as f64 - 2.0 * PADDING).max(1.0);
+        let available_h = (height as f64 - 2.0 * PADDING).max(1.0);
+        let span_x = (max_x - min_x).max(1e-9);
+        let span_y = (max_y - min_y).max(1e-9);
+        let scale = (available_w / span_x).min(available_h / span_y);
+
+        Some(Projection {
+            min_x,
+            max_y,
+            scale,
+            offset_x: PADDING + (available_w - span_x * scale) / 2.0,
+            offset_y: PADDING + (available_h - span_y * scale) / 2.0,
+        })
+    }
+
+    fn project(&self, latitude: f64, longitude: f64) -> (f64, f64) {
+        (
+            self.offset_x + (longitude.to_radians() - self.min_x) * self.scale,
+            self.offset_y + (self.max_y - mercator_y(latitude)) * self.scale,
+        )
+    }
+}
+
+// ============================================================================
+// Rendering
+// ============================================================================
+
+fn parse_hex_color(color: &str) -> [u8; 3] {
+    let channel = |i: usize| color.get(i..i + 2)
+        .and_then(|c| u8::from_str_radix(c, 16).ok())
+        .unwrap_or(0x80);
+    [channel(0), channel(2), channel(4)]
+}
+
+/// Render a line's shapes and stops as a PNG image
+pub fn render_line_map(line: &Line, shapes: &[&Vec<ShapePoint>], stops: &[&Stop], width: u32, height: u32) -> Vec<u8> {
+    let width = width.clamp(MIN_SIZE, MAX_SIZE);
+    let height = height.clamp(MIN_SIZE, MAX_SIZE);
+    let mut canvas = Canvas::new(width, height, BACKGROUND);
+
+    let points: Vec<(f64, f64)> = shapes.iter()
+        .flat_map(|shape| shape.iter().map(|p| (p.latitude, p.longitude)))
+        .chain(stops.iter().map(|s| (s.latitude, s.longitude)))
+        .collect();
+
+    let Some(projection) = Projection::fit(&points, width, height) else {
+        return canvas.encode_png();
+    };
+
+    let line_color = parse_hex_color(&line.color);
+    let thickness = (width.min(height) as f64 / 120.0).clamp(2.0, 8.0);
+
+    for shape in shapes {
+        let mut ordered: Vec<&ShapePoint> = shape.iter().collect();
+        ordered.sort_by_key(|p| p.sequence);
+
+        for pair in ordered.windows(2) {
+            canvas.draw_line(
+                projection.project(pair[0].latitude, pair[0].longitude),
+                projection.project(pair[1].latitude, pair[1].longitude),
+                thickness,
+                line_color,
+            );
+        }
+    }
+
+    for stop in stops {
+        let (x, y) = projection.project(stop.latitude, stop.longitude);
+        canvas.fill_disc(x, y, thickness * 1.25, line_color);
+        canvas.fill_disc(x, y, thickness * 0.75, STOP_FILL);
+    }
+
+    canvas.encode_png()
+}
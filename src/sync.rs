@@ -0,0 +1,81 @@
+// Hot standby sync
+// A primary with a sync token serves its cache snapshots at GET /admin/sync. A standby (with
+// `primary_url` set) starts from the primary's snapshot instead of downloading the feeds, then
+// pulls it every refresh cycle, so both instances answer from the same data and traffic can be
+// switched over at any time. The static data is only sent when the standby's is out of date
+// (?static_since= its last_static_update). When the primary can't be reached, the standby
+// refreshes from the upstreams itself until it comes back.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::snapshot::{DynamicSnapshot, StaticSnapshot};
+use crate::tbm_api_models::{AlertInfo, CachedNetworkData, NVTError, Result, Upstreams};
+
+const PULL_TIMEOUT: Duration = Duration::from_secs(120); // The static data is tens of MB
+
+#[derive(Serialize, Deserialize)]
+pub struct SyncPayload {
+    pub static_data: Option<StaticSnapshot>, // None when the standby already has it
+    pub dynamic_data: DynamicSnapshot,
+}
+
+/// Snapshot of `cache` for a standby whose static data dates from `static_since`
+pub fn payload(cache: &CachedNetworkData, static_since: Option<u64>) -> SyncPayload {
+    SyncPayload {
+        static_data: (static_since != Some(cache.last_static_update)).then(|| StaticSnapshot::from_cache(cache)),
+        dynamic_data: DynamicSnapshot::from_cache(cache),
+    }
+}
+
+fn fetch(primary_url: &str, token: &str, static_since: Option<u64>) -> Result<SyncPayload> {
+    let mut url = format!("{}/admin/sync", primary_url.trim_end_matches('/'));
+    if let Some(static_since) = static_since {
+        url.push_str(&format!("?static_since={}", static_since));
+    }
+
+    let client = reqwest::blocking::Client::builder()
+        .timeout(PULL_TIMEOUT)
+        .build()
+        .map_err(|e| NVTError::NetworkError(format!("Failed to build sync client: {}", e)))?;
+    let response = client.get(&url).bearer_auth(token).send()
+        .map_err(|e| NVTError::NetworkError(format!("Failed to reach primary {}: {}", primary_url, e)))?;
+    if !response.status().is_success() {
+        return Err(NVTError::NetworkError(format!("Primary {} answered {}", primary_url, response.status())));
+    }
+    response.json::<SyncPayload>()
+        .map_err(|e| NVTError::ParseError(format!("Invalid sync payload: {}", e)))
+}
+
+/// Replace the data of `cache` with the primary's
+pub fn apply(payload: SyncPayload, cache: &mut CachedNetworkData) {
+    match payload.static_data {
+        Some(static_data) => *cache = static_data.into_cache(payload.dynamic_data, cache.upstreams.clone()),
+        None => payload.dynamic_data.apply(cache),
+    }
+}
+
+/// Cache of a standby at startup, from the primary's full snapshot
+pub fn initial_cache(primary_url: &str, token: &str) -> Result<CachedNetworkData> {
+    let payload = fetch(primary_url, token, None)?;
+    let static_data = payload.static_data
+        .ok_or_else(|| NVTError::ParseError("Sync payload has no static data".to_string()))?;
+    Ok(static_data.into_cache(payload.dynamic_data, Upstreams::default()))
+}
+
+/// One refresh cycle of a standby, returning the alerts before and after it. The payload is
+/// downloaded without holding the cache lock.
+pub fn pull(state: &Mutex<CachedNetworkData>, primary_url: &str, token: &str) -> Result<(Vec<AlertInfo>, Vec<AlertInfo>)> {
+    let static_since = state.lock().map_err(lock_error)?.last_static_update;
+    let payload = fetch(primary_url, token, Some(static_since))?;
+
+    let mut cache = state.lock().map_err(lock_error)?;
+    let previous_alerts = cache.alerts.clone();
+    apply(payload, &mut cache);
+    Ok((previous_alerts, cache.alerts.clone()))
+}
+
+fn lock_error<T>(e: PoisonError<T>) -> NVTError {
+    NVTError::NetworkError(format!("Failed to lock cache: {}", e))
+}
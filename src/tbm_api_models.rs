@@ -15,23 +15,38 @@
 
 use reqwest::blocking;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use gtfs_rt::FeedMessage;
+use gtfs_rt::vehicle_position::OccupancyStatus;
 use prost::Message;
 use chrono::{TimeZone, Utc};
 use chrono_tz::Europe::Paris;
 use std::io::Read;
 use std::io::Cursor;
 use zip::ZipArchive;
+use crate::colors;
+use crate::config;
+use crate::operators;
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::path::{Path, PathBuf};
 use std::fs;
 
+use crate::amenities::StopAmenities;
+use crate::spatial::StopIndex;
+use crate::headways::HeadwayIndex;
+use crate::search::SearchIndex;
+use crate::communes::{self, Commune, CommuneIndex};
+use crate::gbfs::{self, BikeShare};
+use crate::map_matching;
+use crate::navitia::{self, Navitia};
+
 // ============================================================================
 // Data Structures
 // ============================================================================
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AlertInfo {
     pub id: String,
     pub text: String,
@@ -42,6 +57,25 @@ pub struct AlertInfo {
     pub active_period_start: Option<i64>,
     pub active_period_end: Option<i64>,
     pub severity: u32,
+    #[serde(default)]
+    pub active_periods: Vec<(Option<i64>, Option<i64>)>, // Every (start, end); the fields above are the first
+    #[serde(default)]
+    pub affected_stops_count: usize, // Stops named by the alert or served by its routes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub affected_stop_ids: Option<Vec<String>>, // Only with /alerts?expand=stops
+    #[serde(default)]
+    pub derived: bool, // Synthesized from a TripUpdate, no operator published it
+    #[serde(default)]
+    pub translations: Vec<AlertTranslation>, // Every language of the feed; text, description and url are in the requested one
+}
+
+/// Texts of an alert in one language, as the feed gives them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlertTranslation {
+    pub language: String, // BCP 47 tag, e.g. "fr" or "en-GB"
+    pub text: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -57,6 +91,14 @@ pub struct RealTimeInfo {
     pub current_stop_sequence: Option<u32>,
     pub timestamp: Option<i64>,
     pub delay: Option<i32>,
+    #[serde(default)]
+    pub bearing: Option<f32>, // Degrees clockwise from north
+    #[serde(default)]
+    pub occupancy: Option<String>, // GTFS-RT OccupancyStatus, e.g. "MANY_SEATS_AVAILABLE"
+    #[serde(default)]
+    pub source: Option<String>, // Feed the position comes from, e.g. "TBM" or "SNCF"
+    #[serde(default)]
+    pub start_date: Option<String>, // YYYYMMDD, the run of the trip (TripDescriptor), when the feed gives it
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -68,6 +110,18 @@ pub struct Stop {
     pub lines: Vec<String>,
     pub alerts: Vec<AlertInfo>,
     pub real_time: Vec<RealTimeInfo>,
+    #[serde(default)]
+    pub commune: Option<String>,
+    #[serde(default)]
+    pub insee_code: Option<String>, // INSEE code of the commune
+    #[serde(default)]
+    pub amenities: Option<StopAmenities>, // From OpenStreetMap, when enrichment is enabled
+    #[serde(default)]
+    pub wheelchair_boarding: Option<bool>, // stops.txt, inherited from the parent station; None when unknown
+    #[serde(default)]
+    pub level: Option<Level>,
+    #[serde(default)]
+    pub pathways: Vec<Pathway>, // pathways.txt links leaving the stop
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -94,6 +148,10 @@ pub struct Trip {
     pub service_id: String,
     pub trip_headsign: Option<String>,
     pub direction_id: Option<u32>,
+    #[serde(default)]
+    pub wheelchair_accessible: Option<bool>, // trips.txt; None when the feed doesn't say
+    #[serde(default)]
+    pub bikes_allowed: Option<bool>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -126,6 +184,15 @@ pub struct Agency {
     pub agency_phone: String,
 }
 
+/// Agency of one of the GTFS feeds, as /api/tbm/agencies lists it
+#[derive(Debug, Clone, Serialize)]
+pub struct AgencyInfo {
+    #[serde(flatten)]
+    pub agency: Agency,
+    pub source: String, // Feed it comes from: "TBM", "New-Aquitaine" or "SNCF"
+    pub lines_count: usize, // Lines of the network data it runs
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transfer {
     pub from_stop_id: String,
@@ -134,6 +201,69 @@ pub struct Transfer {
     pub min_transfer_time: Option<u32>,
 }
 
+/// feed_info.txt of a feed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FeedInfo {
+    pub publisher_name: String,
+    pub publisher_url: String,
+    pub lang: Option<String>,
+    pub version: Option<String>,
+    pub start_date: Option<String>, // YYYYMMDD, first day the feed covers
+    pub end_date: Option<String>,
+}
+
+/// A frequencies.txt window of a headway-based trip, whose stop_times are a template repeated
+/// every headway_secs from start_time until end_time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Frequency {
+    pub start_time: String, // HH:MM:SS, may exceed 24:00:00
+    pub end_time: String,
+    pub headway_secs: u32,
+    pub exact_times: bool, // Departures are scheduled, not only the headway
+    pub trip_start: u32, // Seconds since midnight of the template's first departure
+}
+
+/// A pathways.txt link between two locations of a station
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pathway {
+    pub pathway_id: String,
+    pub from_stop_id: String,
+    pub to_stop_id: String,
+    pub pathway_mode: u8, // 1 walkway, 2 stairs, 3 moving sidewalk, 4 escalator, 5 elevator, 6 fare gate, 7 exit gate
+    pub is_bidirectional: bool,
+    pub length: Option<f64>, // Meters
+    pub traversal_time: Option<u32>, // Seconds
+    pub stair_count: Option<i32>,
+}
+
+/// A levels.txt floor of a station
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Level {
+    pub level_id: String,
+    pub level_index: f64, // 0 at street level, negative underground
+    pub level_name: Option<String>,
+}
+
+/// Accessibility data of a feed: stops.txt wheelchair_boarding and level_id, levels.txt and
+/// pathways.txt. Feeds without them leave it empty.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Accessibility {
+    pub wheelchair_boarding: HashMap<String, bool>, // key: stop_id; 0 and empty are left out
+    pub stop_levels: HashMap<String, String>, // key: stop_id, value: level_id
+    pub levels: HashMap<String, Level>, // key: level_id
+    pub pathways: Vec<Pathway>,
+}
+
+/// Problems found while parsing a GTFS feed, counted per feed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FeedQuality {
+    pub stop_times_total: usize,
+    pub stop_times_rejected: usize, // Dropped: missing fields or invalid stop_sequence
+    pub times_normalized: usize,    // Accepted after fixing, e.g. "7:5:00" -> "07:05:00"
+    pub times_rejected: usize,      // Malformed or past 48h; the row is kept untimed
+    pub untimed: usize,             // No arrival or departure time (allowed for non-timepoints)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScheduledArrival {
     pub trip_id: String,
@@ -145,6 +275,64 @@ pub struct ScheduledArrival {
     pub destination: Option<String>,
     pub stop_headsign: Option<String>,
     pub operator: String,
+    #[serde(default)]
+    pub service_date: String, // YYYY-MM-DD, the service day the GTFS times count from
+    #[serde(default)]
+    pub wheelchair_accessible: Option<bool>, // Of the trip
+    #[serde(default)]
+    pub bikes_allowed: Option<bool>,
+}
+
+impl ScheduledArrival {
+    pub fn service_day(&self) -> Option<chrono::NaiveDate> {
+        chrono::NaiveDate::parse_from_str(&self.service_date, "%Y-%m-%d").ok()
+    }
+}
+
+/// Scheduled arrival with absolute timestamps, when requested (see time_format.rs)
+#[derive(Debug, Clone, Serialize)]
+pub struct TimedArrival<'a> {
+    #[serde(flatten)]
+    pub arrival: &'a ScheduledArrival,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arrival_at: Option<String>, // RFC3339
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub departure_at: Option<String>,
+}
+
+/// Row of a stop's departures board: a timetable departure merged with its trip's real-time data
+#[derive(Debug, Clone, Serialize)]
+pub struct Departure<'a> {
+    #[serde(flatten)]
+    pub arrival: &'a ScheduledArrival,
+    pub status: String, // "realtime" when a delay is known for the trip, else "scheduled"
+    pub delay: Option<i32>, // Seconds
+    pub expected_departure: Option<i64>, // unix seconds, delay applied
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_departure_at: Option<String>, // RFC3339, when requested
+}
+
+/// Stop time of a trip, with what the trip's GTFS-RT TripUpdate says about it
+#[derive(Debug, Clone, Serialize)]
+pub struct TripStopTime {
+    #[serde(flatten)]
+    pub stop_time: StopTime,
+    pub stop_name: Option<String>,
+    pub delay: Option<i32>, // Seconds, carried over from the last updated stop
+    pub predicted_time: Option<i64>, // Unix timestamp, when the update at this stop gives one
+    pub skipped: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TripDetails {
+    pub trip: Trip,
+    pub line_code: String,
+    pub line_color: String,
+    pub operator: String,
+    pub calendar: Option<ServiceCalendar>,
+    pub calendar_dates: Vec<CalendarDate>,
+    pub stop_times: Vec<TripStopTime>,
+    pub real_time: bool, // A TripUpdate covers the trip
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -175,9 +363,135 @@ pub struct Line {
     pub destinations: Vec<(String, String)>,
     pub alerts: Vec<AlertInfo>,
     pub real_time: Vec<RealTimeInfo>,
-    pub color: String,
+    pub color: String, // Hex without '#', corrected for display (see colors::readable_colors)
+    #[serde(default)]
+    pub text_color: String,
+    #[serde(default)]
+    pub raw_color: String, // As the feed gives it
+    #[serde(default)]
+    pub raw_text_color: Option<String>,
     pub shape_ids: Vec<String>,
+    #[serde(default)]
+    pub synthetic: bool, // shape_ids were synthesized by map matching, the feed has no shapes for this route
     pub operator: String, // Operator name (e.g., "TBM", "YELO", "Calibus (Libourne)", "STCLM (Limoges Métropole)", etc.)
+    #[serde(default)]
+    pub mode: Option<TransportMode>, // From the GTFS route_type, None when the feed gives none or an unlisted one
+}
+
+/// Transport mode of a line (GET /api/tbm/lines?mode=, /api/tbm/modes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportMode {
+    Tram,
+    Bus,
+    Train,
+    Ferry,
+    Coach,
+}
+
+impl TransportMode {
+    pub const ALL: [TransportMode; 5] =
+        [TransportMode::Tram, TransportMode::Bus, TransportMode::Train, TransportMode::Ferry, TransportMode::Coach];
+
+    /// Mode of a GTFS route_type, basic or extended (Google's "Extended GTFS Route Types");
+    /// metros and suburban rail count as trains, aerial lifts, funiculars and taxis have none
+    pub fn from_route_type(route_type: u16) -> Option<Self> {
+        match route_type {
+            0 | 5 | 900..=999 => Some(TransportMode::Tram),
+            3 | 11 | 700..=899 => Some(TransportMode::Bus),
+            1 | 2 | 12 | 100..=199 | 400..=499 => Some(TransportMode::Train),
+            4 | 1000..=1099 | 1200..=1299 => Some(TransportMode::Ferry),
+            200..=299 => Some(TransportMode::Coach),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TransportMode::Tram => "tram",
+            TransportMode::Bus => "bus",
+            TransportMode::Train => "train",
+            TransportMode::Ferry => "ferry",
+            TransportMode::Coach => "coach",
+        }
+    }
+
+    /// GTFS route_type of the mode, the extended one for coaches
+    pub fn route_type(self) -> u16 {
+        match self {
+            TransportMode::Tram => 0,
+            TransportMode::Bus => 3,
+            TransportMode::Train => 2,
+            TransportMode::Ferry => 4,
+            TransportMode::Coach => 200,
+        }
+    }
+
+    /// Mode named in a query, case-insensitively
+    pub fn parse(name: &str) -> std::result::Result<Self, String> {
+        Self::ALL.into_iter()
+            .find(|mode| mode.as_str().eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| format!(
+                "Unknown mode '{}' (expected one of {})",
+                name,
+                Self::ALL.map(TransportMode::as_str).join(", "),
+            ))
+    }
+}
+
+/// Vehicle position with its progress along the trip
+#[derive(Debug, Clone, Serialize)]
+pub struct VehicleWithProgress<'a> {
+    #[serde(flatten)]
+    pub vehicle: Cow<'a, RealTimeInfo>, // Owned when ?interpolate= moved it
+    pub progress: Option<f32>, // 0.0 at the first stop, 1.0 at the last
+    pub stops_remaining: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster_size: Option<usize>, // Vehicles of its map cell, when thinned by ?zoom=
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interpolated: Option<InterpolatedPosition>, // Where the feed placed it, when ?interpolate= moved it
+}
+
+/// GPS fix of a vehicle whose position was interpolated along its trip
+#[derive(Debug, Clone, Serialize)]
+pub struct InterpolatedPosition {
+    pub gps_latitude: f64,
+    pub gps_longitude: f64,
+    pub gps_age_secs: i64,
+    pub based_on: String, // "trip_update" when a delay is known for the trip, else "schedule"
+}
+
+/// Vehicles changed since a time, for clients polling /vehicles?since= (see NVTModels::vehicles_since)
+#[derive(Debug, Serialize)]
+pub struct VehiclesDelta<'a> {
+    pub since: i64,
+    pub next_since: i64, // Latest vehicle timestamp; the `since` of the next poll
+    pub vehicles: Vec<VehicleWithProgress<'a>>, // Reported after `since`, or without a timestamp
+    pub removed: Vec<String>, // Vehicle ids that left the feed after `since`
+}
+
+/// Stop of a line in travel order (see NVTModels::line_stop_sequence)
+#[derive(Debug, Clone, Serialize)]
+pub struct LineStop {
+    pub stop_sequence: u32, // In the trip the order was taken from
+    #[serde(flatten)]
+    pub stop: Stop,
+}
+
+/// Representative of stops sharing a name within a few meters, with the ids it stands for
+#[derive(Debug, Clone, Serialize)]
+pub struct DedupedStop {
+    #[serde(flatten)]
+    pub stop: Stop,
+    pub merged_ids: Vec<String>, // includes the representative's own id
+}
+
+/// Network data with duplicate stops collapsed (see NVTModels::dedupe_stops)
+#[derive(Debug, Clone, Serialize)]
+pub struct DedupedNetworkData {
+    pub stops: Vec<DedupedStop>,
+    pub lines: Vec<Line>,
+    pub shapes: HashMap<String, Vec<ShapePoint>>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -187,13 +501,158 @@ pub struct NetworkData {
     pub shapes: HashMap<String, Vec<ShapePoint>>,
 }
 
+impl AlertInfo {
+    /// Whether one of the active periods contains `timestamp`; open bounds are unbounded and an
+    /// alert without periods is always active
+    pub fn is_active_at(&self, timestamp: i64) -> bool {
+        let first = [(self.active_period_start, self.active_period_end)];
+        let periods = if self.active_periods.is_empty() { &first[..] } else { &self.active_periods[..] };
+        periods.iter().any(|(start, end)| {
+            start.is_none_or(|start| start <= timestamp) && end.is_none_or(|end| timestamp <= end)
+        })
+    }
+
+    /// The alert in the first of `languages` (most preferred first) it has a translation in;
+    /// "en" also matches "en-GB" and the other way around. Otherwise the feed's first texts stay.
+    pub fn localized(&self, languages: &[String]) -> AlertInfo {
+        let primary = |tag: &str| tag.split(['-', '_']).next().unwrap_or_default().to_ascii_lowercase();
+        let mut alert = self.clone();
+        let translation = languages.iter().find_map(|language| {
+            self.translations.iter().find(|t| t.language.eq_ignore_ascii_case(language))
+                .or_else(|| self.translations.iter().find(|t| primary(&t.language) == primary(language)))
+        });
+        if let Some(translation) = translation {
+            if let Some(text) = &translation.text {
+                alert.text = text.clone();
+            }
+            if let Some(description) = &translation.description {
+                alert.description = description.clone();
+            }
+            if translation.url.is_some() {
+                alert.url = translation.url.clone();
+            }
+        }
+        alert
+    }
+}
+
+/// Criteria of ?route=, ?stop=, ?severity_min= and ?active_at= on /alerts; all given ones must match
+#[derive(Debug, Clone, Default)]
+pub struct AlertFilter {
+    pub route_ids: Option<Vec<String>>, // Any of them, route_ids or line refs
+    pub stop_id: Option<String>,
+    pub severity_min: Option<u32>,
+    pub active_at: Option<i64>,
+}
+
+impl AlertFilter {
+    /// `affected_stops` are the alert's stops from expand_alert_scope, so that ?stop= also
+    /// finds the alerts of the routes serving the stop
+    pub fn matches(&self, alert: &AlertInfo, affected_stops: &[String]) -> bool {
+        let route = self.route_ids.as_ref()
+            .is_none_or(|ids| alert.route_ids.iter().any(|id| ids.contains(id)));
+        let stop = self.stop_id.as_ref().is_none_or(|stop_id| {
+            affected_stops.contains(stop_id) || alert.stop_ids.iter().any(|id| {
+                id == stop_id || NVTModels::extract_stop_id(id).as_ref() == Some(stop_id)
+            })
+        });
+        let severity = self.severity_min.is_none_or(|min| alert.severity >= min);
+        let active = self.active_at.is_none_or(|timestamp| alert.is_active_at(timestamp));
+        route && stop && severity && active
+    }
+
+    pub fn apply<'a>(&self, alerts: &'a [AlertInfo], alert_stops: &HashMap<String, Vec<String>>) -> Vec<&'a AlertInfo> {
+        alerts.iter()
+            .filter(|alert| self.matches(alert, alert_stops.get(&alert.id).map_or(&[], Vec::as_slice)))
+            .collect()
+    }
+}
+
+/// Map viewport, from ?bbox=minLon,minLat,maxLon,maxLat (GeoJSON axis order)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl BoundingBox {
+    pub fn parse(value: &str) -> std::result::Result<Self, String> {
+        let invalid = || format!("Invalid bbox '{}' (expected minLon,minLat,maxLon,maxLat)", value);
+        let coordinates: Vec<f64> = value.split(',')
+            .map(|c| c.trim().parse::<f64>())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| invalid())?;
+        let [min_lon, min_lat, max_lon, max_lat] = coordinates[..] else {
+            return Err(invalid());
+        };
+        let in_range = (-180.0..=180.0).contains(&min_lon) && (-180.0..=180.0).contains(&max_lon)
+            && (-90.0..=90.0).contains(&min_lat) && (-90.0..=90.0).contains(&max_lat);
+        if !in_range || min_lon > max_lon || min_lat > max_lat {
+            return Err(invalid());
+        }
+        Ok(BoundingBox { min_lon, min_lat, max_lon, max_lat })
+    }
+
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        (self.min_lat..=self.max_lat).contains(&lat) && (self.min_lon..=self.max_lon).contains(&lon)
+    }
+
+    /// Whether a segment's envelope overlaps the box; may keep a segment passing near a corner
+    fn overlaps_segment(&self, a: &ShapePoint, b: &ShapePoint) -> bool {
+        a.latitude.min(b.latitude) <= self.max_lat && a.latitude.max(b.latitude) >= self.min_lat
+            && a.longitude.min(b.longitude) <= self.max_lon && a.longitude.max(b.longitude) >= self.min_lon
+    }
+
+    /// Whether any segment of a shape crosses the box
+    pub fn crosses_shape(&self, points: &[ShapePoint]) -> bool {
+        match points {
+            [point] => self.contains(point.latitude, point.longitude),
+            _ => points.windows(2).any(|segment| self.overlaps_segment(&segment[0], &segment[1])),
+        }
+    }
+}
+
+impl NetworkData {
+    /// Stops and vehicles inside the box, shapes crossing it, and the lines having either.
+    /// Shapes are kept whole so lines don't end abruptly at the edge of the map.
+    pub fn within(self, bbox: &BoundingBox) -> NetworkData {
+        let stops: Vec<Stop> = self.stops.into_iter()
+            .filter(|stop| bbox.contains(stop.latitude, stop.longitude))
+            .collect();
+        let shapes: HashMap<String, Vec<ShapePoint>> = self.shapes.into_iter()
+            .filter(|(_, points)| bbox.crosses_shape(points))
+            .collect();
+
+        let served: HashSet<&str> = stops.iter()
+            .flat_map(|stop| stop.lines.iter().map(String::as_str))
+            .collect();
+        let lines: Vec<Line> = self.lines.into_iter()
+            .filter(|line| {
+                served.contains(line.line_ref.as_str()) || line.shape_ids.iter().any(|id| shapes.contains_key(id))
+            })
+            .map(|mut line| {
+                line.real_time.retain(|vehicle| bbox.contains(vehicle.latitude, vehicle.longitude));
+                line
+            })
+            .collect();
+
+        NetworkData { stops, lines, shapes }
+    }
+}
+
 // ============================================================================
-// GTFS Cache Structure (15-day persistence for TBM, 30-day for New-Aquitaine and SNCF)
+// GTFS Cache Structure (persisted per source, see config::CacheAgesConfig)
 // ============================================================================
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GTFSCache {
     pub routes: HashMap<String, String>,
+    #[serde(default)]
+    pub route_text_colors: HashMap<String, String>, // key: route_id, value: route_text_color
+    #[serde(default)]
+    pub route_types: HashMap<String, u16>, // key: route_id, value: route_type
     pub stops: Vec<(String, String, f64, f64)>,
     pub shapes: HashMap<String, Vec<ShapePoint>>,
     pub route_to_shapes: HashMap<String, Vec<String>>,
@@ -204,11 +663,170 @@ pub struct GTFSCache {
     pub agencies: HashMap<String, Agency>, // key: agency_id, value: agency info
     pub route_agencies: HashMap<String, String>, // key: route_id, value: agency_id
     pub transfers: Vec<Transfer>,
+    #[serde(default)]
+    pub synthetic_shape_ids: HashSet<String>, // Shapes made by map matching for routes the feed left shapeless
+    #[serde(default)]
+    pub map_matched: bool, // Map matching already ran on this cache
+    #[serde(default)]
+    pub quality: FeedQuality,
+    #[serde(default)]
+    pub accessibility: Accessibility,
+    #[serde(default)]
+    pub frequencies: HashMap<String, Vec<Frequency>>, // key: trip_id, for headway-based trips
+    #[serde(default)]
+    pub feed_info: Option<FeedInfo>,
+    #[serde(default)]
+    pub etag: Option<String>, // HTTP validators of the downloaded zip, sent back on the next download
+    #[serde(default)]
+    pub last_modified: Option<String>,
     pub cached_at: u64,
     pub source: String, // "TBM", "NewAquitaine", or "SNCF"
 }
 
 impl GTFSCache {
+    /// Placeholder used when a source could not be loaded
+    pub fn empty(source: &str) -> Self {
+        GTFSCache {
+            routes: HashMap::new(),
+            route_text_colors: HashMap::new(),
+            route_types: HashMap::new(),
+            stops: Vec::new(),
+            shapes: HashMap::new(),
+            route_to_shapes: HashMap::new(),
+            stop_times: HashMap::new(),
+            trips: HashMap::new(),
+            calendar: HashMap::new(),
+            calendar_dates: HashMap::new(),
+            agencies: HashMap::new(),
+            route_agencies: HashMap::new(),
+            transfers: Vec::new(),
+            synthetic_shape_ids: HashSet::new(),
+            map_matched: false,
+            quality: FeedQuality::default(),
+            accessibility: Accessibility::default(),
+            frequencies: HashMap::new(),
+            feed_info: None,
+            etag: None,
+            last_modified: None,
+            cached_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            source: source.to_string(),
+        }
+    }
+
+    /// Overlay a partial feed: its routes, trips, calendars, shapes and stops replace those
+    /// with the same ids, and each trip it times replaces all the stop times of that trip
+    pub fn merge(&mut self, fragment: GTFSCache) {
+        let retimed: HashSet<&String> = fragment.stop_times.values().flatten().map(|stop_time| &stop_time.trip_id).collect();
+        for stop_times in self.stop_times.values_mut() {
+            stop_times.retain(|stop_time| !retimed.contains(&stop_time.trip_id));
+        }
+        for (stop_id, stop_times) in fragment.stop_times {
+            self.stop_times.entry(stop_id).or_default().extend(stop_times);
+        }
+        self.stop_times.retain(|_, stop_times| !stop_times.is_empty());
+
+        for stop in fragment.stops {
+            match self.stops.iter_mut().find(|(stop_id, _, _, _)| *stop_id == stop.0) {
+                Some(existing) => *existing = stop,
+                None => self.stops.push(stop),
+            }
+        }
+        self.routes.extend(fragment.routes);
+        self.route_text_colors.extend(fragment.route_text_colors);
+        self.route_types.extend(fragment.route_types);
+        self.shapes.extend(fragment.shapes);
+        self.route_to_shapes.extend(fragment.route_to_shapes);
+        self.trips.extend(fragment.trips);
+        self.calendar.extend(fragment.calendar);
+        self.calendar_dates.extend(fragment.calendar_dates);
+        self.agencies.extend(fragment.agencies);
+        self.route_agencies.extend(fragment.route_agencies);
+        self.transfers.extend(fragment.transfers);
+        self.synthetic_shape_ids.extend(fragment.synthetic_shape_ids);
+        self.accessibility.wheelchair_boarding.extend(fragment.accessibility.wheelchair_boarding);
+        self.accessibility.stop_levels.extend(fragment.accessibility.stop_levels);
+        self.accessibility.levels.extend(fragment.accessibility.levels);
+        self.accessibility.pathways.extend(fragment.accessibility.pathways);
+        self.frequencies.extend(fragment.frequencies);
+    }
+
+    /// The cache with every route of the `mode` (a feed of coaches that gives them as buses)
+    pub fn with_mode(mut self, mode: TransportMode) -> Self {
+        self.route_types = self.routes.keys().map(|route_id| (route_id.clone(), mode.route_type())).collect();
+        self
+    }
+
+    /// The cache with `prefix` before every stop, route, trip, shape, service and agency id, for
+    /// feeds whose ids could collide with those of the others
+    pub fn with_id_prefix(self, prefix: &str) -> Self {
+        fn keys<V>(prefix: &str, map: HashMap<String, V>) -> HashMap<String, V> {
+            map.into_iter().map(|(key, value)| (format!("{}{}", prefix, key), value)).collect()
+        }
+        let id = |id: String| format!("{}{}", prefix, id);
+
+        GTFSCache {
+            routes: keys(prefix, self.routes),
+            route_text_colors: keys(prefix, self.route_text_colors),
+            route_types: keys(prefix, self.route_types),
+            stops: self.stops.into_iter().map(|(stop_id, name, lat, lon)| (id(stop_id), name, lat, lon)).collect(),
+            shapes: keys(prefix, self.shapes),
+            route_to_shapes: self.route_to_shapes.into_iter()
+                .map(|(route_id, shape_ids)| (id(route_id), shape_ids.into_iter().map(&id).collect()))
+                .collect(),
+            stop_times: self.stop_times.into_iter()
+                .map(|(stop_id, stop_times)| {
+                    let stop_times = stop_times.into_iter()
+                        .map(|stop_time| StopTime { trip_id: id(stop_time.trip_id), stop_id: id(stop_time.stop_id), ..stop_time })
+                        .collect();
+                    (id(stop_id), stop_times)
+                })
+                .collect(),
+            trips: self.trips.into_iter()
+                .map(|(trip_id, trip)| {
+                    let trip = Trip { trip_id: id(trip.trip_id), route_id: id(trip.route_id), service_id: id(trip.service_id), ..trip };
+                    (id(trip_id), trip)
+                })
+                .collect(),
+            calendar: self.calendar.into_iter()
+                .map(|(service_id, calendar)| (id(service_id), ServiceCalendar { service_id: id(calendar.service_id), ..calendar }))
+                .collect(),
+            calendar_dates: self.calendar_dates.into_iter()
+                .map(|(service_id, dates)| {
+                    let dates = dates.into_iter().map(|date| CalendarDate { service_id: id(date.service_id), ..date }).collect();
+                    (id(service_id), dates)
+                })
+                .collect(),
+            agencies: self.agencies.into_iter()
+                .map(|(agency_id, agency)| (id(agency_id), Agency { agency_id: id(agency.agency_id), ..agency }))
+                .collect(),
+            route_agencies: self.route_agencies.into_iter().map(|(route_id, agency_id)| (id(route_id), id(agency_id))).collect(),
+            transfers: self.transfers.into_iter()
+                .map(|transfer| Transfer { from_stop_id: id(transfer.from_stop_id), to_stop_id: id(transfer.to_stop_id), ..transfer })
+                .collect(),
+            synthetic_shape_ids: self.synthetic_shape_ids.into_iter().map(&id).collect(),
+            frequencies: keys(prefix, self.frequencies),
+            accessibility: Accessibility {
+                wheelchair_boarding: keys(prefix, self.accessibility.wheelchair_boarding),
+                stop_levels: self.accessibility.stop_levels.into_iter().map(|(stop_id, level_id)| (id(stop_id), id(level_id))).collect(),
+                levels: self.accessibility.levels.into_iter()
+                    .map(|(level_id, level)| (id(level_id), Level { level_id: id(level.level_id), ..level }))
+                    .collect(),
+                pathways: self.accessibility.pathways.into_iter()
+                    .map(|pathway| Pathway {
+                        pathway_id: id(pathway.pathway_id),
+                        from_stop_id: id(pathway.from_stop_id),
+                        to_stop_id: id(pathway.to_stop_id),
+                        ..pathway
+                    })
+                    .collect(),
+            },
+            ..self
+        }
+    }
+
     pub fn is_expired(&self, max_age_days: u64) -> bool {
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -218,16 +836,41 @@ impl GTFSCache {
         age_days >= max_age_days
     }
 
-    pub fn cache_path(source: &str) -> PathBuf {
-        let mut path = dirs::cache_dir().unwrap_or_else(|| PathBuf::from("."));
-        path.push("tbm_nvt");
-        fs::create_dir_all(&path).ok();
-        path.push(format!("{}_gtfs_cache.json", source.to_lowercase()));
-        path
+    /// The saved feed confirmed current by a new download: its age restarts and the download's
+    /// HTTP validators replace the old ones, then it is saved again
+    fn renewed(mut self, etag: Option<String>, last_modified: Option<String>, cache_dir: &Path) -> Self {
+        self.cached_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.etag = etag.or(self.etag);
+        self.last_modified = last_modified.or(self.last_modified);
+        if let Err(e) = self.save(cache_dir) {
+            eprintln!("⚠️  Warning: Could not save {} GTFS cache: {}", self.source, e);
+        }
+        self
+    }
+
+    pub fn cache_path(cache_dir: &Path, source: &str) -> PathBuf {
+        fs::create_dir_all(cache_dir).ok();
+        cache_dir.join(format!("{}_gtfs_cache.json", source.to_lowercase()))
+    }
+
+    /// Delete the saved feed of `source`, so the next load downloads it; false when none was saved
+    pub fn purge(cache_dir: &Path, source: &str) -> Result<bool> {
+        let path = Self::cache_path(cache_dir, source);
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                println!("🗑️  {} GTFS cache removed: {:?}", source, path);
+                Ok(true)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(NVTError::FileError(format!("Failed to remove cache: {}", e))),
+        }
     }
 
-    pub fn save(&self) -> Result<()> {
-        let path = Self::cache_path(&self.source);
+    pub fn save(&self, cache_dir: &Path) -> Result<()> {
+        let path = Self::cache_path(cache_dir, &self.source);
         let json = serde_json::to_string_pretty(self)
             .map_err(|e| NVTError::FileError(format!("Failed to serialize cache: {}", e)))?;
 
@@ -238,8 +881,14 @@ impl GTFSCache {
         Ok(())
     }
 
-    pub fn load(source: &str, max_age_days: u64) -> Option<Self> {
-        let path = Self::cache_path(source);
+    /// The saved feed of `source` whatever its age, None when there is none or it doesn't parse
+    fn load_outdated(cache_dir: &Path, source: &str) -> Option<Self> {
+        let contents = fs::read_to_string(Self::cache_path(cache_dir, source)).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    pub fn load(cache_dir: &Path, source: &str, max_age_days: u64) -> Option<Self> {
+        let path = Self::cache_path(cache_dir, source);
 
         if !path.exists() {
             println!("ℹ️  No {} GTFS cache found, will download fresh data", source);
@@ -283,11 +932,28 @@ impl GTFSCache {
 // Cache Structure for efficient refresh
 // ============================================================================
 
+pub type TbmStopMetadata = (String, String, f64, f64, Vec<String>); // (stop_id, name, lat, lon, line refs)
+pub type TbmLineMetadata = (String, String, String, Vec<(String, String)>); // (line_ref, name, code, destinations)
+
+/// Stops, lines and timetables of a plain GTFS feed of the configuration, a source of its own
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedData {
+    pub stops: Vec<Stop>,
+    pub lines: Vec<Line>,
+    pub gtfs_cache: GTFSCache,
+}
+
+impl FeedData {
+    pub fn empty(name: &str) -> Self {
+        FeedData { stops: Vec::new(), lines: Vec::new(), gtfs_cache: GTFSCache::empty(name) }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CachedNetworkData {
     // TBM Data
-    pub tbm_stops_metadata: Vec<(String, String, f64, f64, Vec<String>)>,
-    pub tbm_lines_metadata: Vec<(String, String, String, Vec<(String, String)>)>,
+    pub tbm_stops_metadata: Vec<TbmStopMetadata>,
+    pub tbm_lines_metadata: Vec<TbmLineMetadata>,
     pub tbm_gtfs_cache: GTFSCache,
 
     // New-Aquitaine Regional Networks Data (variable names kept as "transgironde" for backward compatibility)
@@ -300,14 +966,160 @@ pub struct CachedNetworkData {
     pub sncf_lines: Vec<Line>,
     pub sncf_gtfs_cache: GTFSCache,
 
+    // Plain GTFS feeds of the configuration
+    pub feeds: BTreeMap<String, FeedData>, // key: feed name
+
+    // Derived from the GTFS caches at each static load
+    pub trip_index: HashMap<String, Vec<(u32, String)>>, // key: trip_id, value: (stop_sequence, stop_id) in order
+    pub stop_communes: HashMap<String, Commune>, // key: stop_id
+    pub stop_index: StopIndex, // Stop positions of every source, for radius queries
+    pub search_index: SearchIndex, // Folded stop and line names of every source
+    pub headways: HeadwayIndex, // Headways of every route on representative days
+    pub stop_amenities: HashMap<String, StopAmenities>, // key: stop_id, filled in the background
+    pub alert_stops: HashMap<String, Vec<String>>, // key: alert id, value: affected stop ids, at each refresh
+
     pub last_static_update: u64,
     pub alerts: Vec<AlertInfo>,
     pub real_time: Vec<RealTimeInfo>,
+    pub vanished_vehicles: HashMap<String, u64>, // key: vehicle_id, value: refresh that dropped it, kept VANISHED_RETENTION_SECS
     pub trip_updates: Vec<gtfs_rt::TripUpdate>,
+    pub bike_share: BikeShare, // V³ stations, at each refresh
     pub last_dynamic_update: u64,
+    pub last_refresh: RefreshStatus, // Outcome of the last smart_refresh
+    pub source_freshness: BTreeMap<String, SourceFreshness>, // key: source (TBM, NewAquitaine, SNCF or a feed name)
+
+    pub upstreams: Upstreams, // Where refreshes fetch from
 }
 
 impl CachedNetworkData {
+    /// How long vehicles that left the feed are reported to ?since= polls
+    pub const VANISHED_RETENTION_SECS: u64 = 3600;
+
+    /// Cache holding no data, e.g. a sandbox before any simulation
+    pub fn empty(upstreams: Upstreams) -> Self {
+        CachedNetworkData {
+            tbm_stops_metadata: Vec::new(),
+            tbm_lines_metadata: Vec::new(),
+            tbm_gtfs_cache: GTFSCache::empty("TBM"),
+            transgironde_stops: Vec::new(),
+            transgironde_lines: Vec::new(),
+            transgironde_gtfs_cache: GTFSCache::empty("NewAquitaine"),
+            sncf_stops: Vec::new(),
+            sncf_lines: Vec::new(),
+            sncf_gtfs_cache: GTFSCache::empty("SNCF"),
+            feeds: BTreeMap::new(),
+            trip_index: HashMap::new(),
+            stop_communes: HashMap::new(),
+            stop_index: StopIndex::default(),
+            search_index: SearchIndex::default(),
+            headways: HeadwayIndex::default(),
+            stop_amenities: HashMap::new(),
+            alert_stops: HashMap::new(),
+            last_static_update: 0,
+            alerts: Vec::new(),
+            real_time: Vec::new(),
+            vanished_vehicles: HashMap::new(),
+            trip_updates: Vec::new(),
+            bike_share: BikeShare::default(),
+            last_dynamic_update: 0,
+            last_refresh: RefreshStatus::default(),
+            source_freshness: BTreeMap::new(),
+            upstreams,
+        }
+    }
+
+    /// Vehicles of real_time whose position comes from the `source` feed
+    pub fn vehicles_from<'a>(&'a self, source: &'a str) -> impl Iterator<Item = &'a RealTimeInfo> + 'a {
+        self.real_time.iter().filter(move |vehicle| vehicle.source.as_deref() == Some(source))
+    }
+
+    /// Note the vehicles of `previous` missing from real_time as vanished at `now`, forget those
+    /// back in the feed and those that left longer than VANISHED_RETENTION_SECS ago
+    pub fn track_vanished_vehicles(&mut self, previous: &[RealTimeInfo], now: u64) {
+        let current: HashSet<&str> = self.real_time.iter().map(|v| v.vehicle_id.as_str()).collect();
+        self.vanished_vehicles.retain(|id, vanished_at| {
+            !current.contains(id.as_str()) && now.saturating_sub(*vanished_at) <= Self::VANISHED_RETENTION_SECS
+        });
+        for vehicle in previous.iter().filter(|v| !current.contains(v.vehicle_id.as_str())) {
+            self.vanished_vehicles.insert(vehicle.vehicle_id.clone(), now);
+        }
+    }
+
+    /// Note a fetch at `now` of the `fetched` (source, kind) feeds; those without an entry in
+    /// `failures` answered
+    pub fn record_fetches(&mut self, now: u64, fetched: &[(&str, FeedKind)], failures: &[(&str, FeedKind, String)]) {
+        for &(source, kind) in fetched {
+            let errors: Vec<&str> = failures.iter()
+                .filter(|(s, k, _)| *s == source && *k == kind)
+                .map(|(_, _, error)| error.as_str())
+                .collect();
+            let freshness = self.source_freshness.entry(source.to_string()).or_default();
+            let feeds = match kind {
+                FeedKind::Static => &mut freshness.static_data,
+                FeedKind::RealTime => &mut freshness.real_time,
+            };
+            let feeds = feeds.get_or_insert_with(FeedFreshness::default);
+            if errors.is_empty() {
+                feeds.updated = now;
+                feeds.error = None;
+            } else {
+                feeds.error = Some(errors.join("; "));
+            }
+        }
+    }
+
+    /// Replace the static data and indexes with those of `refreshed`, a copy of this cache that
+    /// refresh_static_data ran on; the real-time data and amenities stay as they are
+    pub fn take_static_data(&mut self, refreshed: CachedNetworkData) {
+        self.take_static_freshness(&refreshed);
+        self.tbm_stops_metadata = refreshed.tbm_stops_metadata;
+        self.tbm_lines_metadata = refreshed.tbm_lines_metadata;
+        self.tbm_gtfs_cache = refreshed.tbm_gtfs_cache;
+        self.transgironde_stops = refreshed.transgironde_stops;
+        self.transgironde_lines = refreshed.transgironde_lines;
+        self.transgironde_gtfs_cache = refreshed.transgironde_gtfs_cache;
+        self.sncf_stops = refreshed.sncf_stops;
+        self.sncf_lines = refreshed.sncf_lines;
+        self.sncf_gtfs_cache = refreshed.sncf_gtfs_cache;
+        self.feeds = refreshed.feeds;
+        self.trip_index = refreshed.trip_index;
+        self.stop_communes = refreshed.stop_communes;
+        self.stop_index = refreshed.stop_index;
+        self.search_index = refreshed.search_index;
+        self.headways = refreshed.headways;
+        self.last_static_update = refreshed.last_static_update;
+        self.expand_alert_scope();
+    }
+
+    /// Static feed freshness of `refreshed`, after a static refresh on a copy of this cache
+    pub fn take_static_freshness(&mut self, refreshed: &CachedNetworkData) {
+        for (source, freshness) in &refreshed.source_freshness {
+            self.source_freshness.entry(source.clone()).or_default().static_data = freshness.static_data.clone();
+        }
+    }
+
+    /// (stop_id, latitude, longitude) of the stops of every source
+    pub fn stop_positions(&self) -> Vec<(String, f64, f64)> {
+        self.tbm_stops_metadata.iter()
+            .map(|(id, _, lat, lon, _)| (id.clone(), *lat, *lon))
+            .chain(self.gtfs_stops().map(|stop| (stop.stop_id.clone(), stop.latitude, stop.longitude)))
+            .collect()
+    }
+
+    /// Stops of the GTFS sources: New-Aquitaine, SNCF, then the plain feeds
+    pub fn gtfs_stops(&self) -> impl Iterator<Item = &Stop> {
+        self.transgironde_stops.iter()
+            .chain(&self.sncf_stops)
+            .chain(self.feeds.values().flat_map(|feed| &feed.stops))
+    }
+
+    /// Lines of the GTFS sources, in gtfs_stops order
+    pub fn gtfs_lines(&self) -> impl Iterator<Item = &Line> {
+        self.transgironde_lines.iter()
+            .chain(&self.sncf_lines)
+            .chain(self.feeds.values().flat_map(|feed| &feed.lines))
+    }
+
     pub fn needs_static_refresh(&self, max_age_seconds: u64) -> bool {
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -316,6 +1128,181 @@ impl CachedNetworkData {
         now.saturating_sub(self.last_static_update) > max_age_seconds
     }
 
+    /// Static GTFS data of every source (TBM, New-Aquitaine, SNCF, then the plain feeds)
+    pub fn gtfs_caches(&self) -> Vec<&GTFSCache> {
+        self.gtfs_sources().into_iter().map(|(_, gtfs_cache)| gtfs_cache).collect()
+    }
+
+    /// Sources whose last load returned data
+    pub fn loaded_sources(&self) -> Vec<&str> {
+        [
+            ("TBM", !self.tbm_stops_metadata.is_empty()),
+            ("New-Aquitaine", !self.transgironde_stops.is_empty()),
+            ("SNCF", !self.sncf_stops.is_empty()),
+        ]
+        .into_iter()
+        .chain(self.feeds.iter().map(|(name, feed)| (name.as_str(), !feed.stops.is_empty())))
+        .filter_map(|(source, loaded)| loaded.then_some(source))
+        .collect()
+    }
+
+    /// gtfs_caches with the name of their source, plain feeds by their own
+    pub fn gtfs_sources(&self) -> Vec<(&str, &GTFSCache)> {
+        [("TBM", &self.tbm_gtfs_cache), ("New-Aquitaine", &self.transgironde_gtfs_cache), ("SNCF", &self.sncf_gtfs_cache)]
+            .into_iter()
+            .chain(self.feeds.iter().map(|(name, feed)| (name.as_str(), &feed.gtfs_cache)))
+            .collect()
+    }
+
+    /// gtfs_caches with the operator their trips are given under: TBM, TransGironde, SNCF, then
+    /// each plain feed by its name
+    pub fn operator_caches(&self) -> Vec<(&GTFSCache, &str)> {
+        [(&self.tbm_gtfs_cache, "TBM"), (&self.transgironde_gtfs_cache, "TransGironde"), (&self.sncf_gtfs_cache, "SNCF")]
+            .into_iter()
+            .chain(self.feeds.iter().map(|(name, feed)| (&feed.gtfs_cache, name.as_str())))
+            .collect()
+    }
+
+    /// Affected stops of each alert: the stops it names and those its routes serve in the
+    /// timetables, so that route-level alerts reach stop pages too. Sets alert_stops and the
+    /// alerts' affected_stops_count.
+    pub fn expand_alert_scope(&mut self) {
+        let route_ids: HashSet<&str> = self.alerts.iter()
+            .flat_map(|alert| alert.route_ids.iter().map(String::as_str))
+            .collect();
+        let mut route_stops: HashMap<&str, HashSet<&str>> = HashMap::new();
+        for gtfs_cache in self.gtfs_caches() {
+            for trip in gtfs_cache.trips.values().filter(|trip| route_ids.contains(trip.route_id.as_str())) {
+                let stops = self.trip_index.get(&trip.trip_id).into_iter().flatten();
+                route_stops.entry(trip.route_id.as_str()).or_default()
+                    .extend(stops.map(|(_, stop_id)| stop_id.as_str()));
+            }
+        }
+
+        let alert_stops: HashMap<String, Vec<String>> = self.alerts.iter()
+            .map(|alert| {
+                let mut stops: Vec<String> = alert.stop_ids.iter().map(String::as_str)
+                    .chain(alert.route_ids.iter()
+                        .filter_map(|route_id| route_stops.get(route_id.as_str()))
+                        .flatten()
+                        .copied())
+                    .map(String::from)
+                    .collect();
+                stops.sort();
+                stops.dedup();
+                (alert.id.clone(), stops)
+            })
+            .collect();
+
+        for alert in &mut self.alerts {
+            alert.affected_stops_count = alert_stops.get(&alert.id).map_or(0, Vec::len);
+        }
+        self.alert_stops = alert_stops;
+    }
+
+    /// Replace the derived alerts with one per cancelled or late trip (see config::DerivedAlertsConfig)
+    /// on a route no published alert names. SNCF in particular often cancels trains in its
+    /// TripUpdates without publishing an alert.
+    pub fn derive_alerts(&mut self) {
+        let settings = &config::get().derived_alerts;
+        self.alerts.retain(|alert| !alert.derived);
+        if !settings.enabled {
+            return;
+        }
+
+        let covered: HashSet<&str> = self.alerts.iter()
+            .flat_map(|alert| alert.route_ids.iter().map(String::as_str))
+            .collect();
+        let mut seen = HashSet::new();
+        let derived: Vec<AlertInfo> = self.trip_updates.iter()
+            .filter_map(|update| self.derived_alert(update, settings.delay_threshold_secs))
+            .filter(|alert| !alert.route_ids.iter().any(|route_id| covered.contains(route_id.as_str())))
+            .filter(|alert| seen.insert(alert.id.clone()))
+            .collect();
+        self.alerts.extend(derived);
+    }
+
+    fn derived_alert(&self, update: &gtfs_rt::TripUpdate, delay_threshold_secs: i32) -> Option<AlertInfo> {
+        let trip_id = update.trip.trip_id.as_deref()?;
+        let cancelled = update.trip.schedule_relationship
+            == Some(gtfs_rt::trip_descriptor::ScheduleRelationship::Canceled as i32);
+        let delay = update.stop_time_update.iter()
+            .flat_map(|stop_time| [stop_time.arrival.as_ref(), stop_time.departure.as_ref()])
+            .flatten()
+            .filter_map(|event| event.delay)
+            .chain(update.delay)
+            .max();
+        if !cancelled && delay.is_none_or(|delay| delay < delay_threshold_secs) {
+            return None;
+        }
+
+        let scheduled = self.operator_caches().into_iter().find_map(|(gtfs_cache, operator)| Some((gtfs_cache, gtfs_cache.trips.get(trip_id)?, operator)));
+        let route_id = update.trip.route_id.clone()
+            .or_else(|| scheduled.map(|(_, trip, _)| trip.route_id.clone()))?;
+
+        // "<line> <first departure> to <headsign>", with what the feeds tell of the trip
+        let line_code = self.tbm_lines_metadata.iter()
+            .find(|(line_ref, _, _, _)| NVTModels::extract_line_id(line_ref) == Some(route_id.as_str()))
+            .map(|(_, _, code, _)| code.clone())
+            .or_else(|| self.gtfs_lines()
+                .find(|line| line.route_id == route_id)
+                .map(|line| line.line_code.clone()))
+            .unwrap_or_else(|| scheduled.map_or_else(
+                || route_id.clone(),
+                |(_, _, operator)| NVTModels::extract_line_code_from_route(&route_id, operator),
+            ));
+        let departure = scheduled.and_then(|(gtfs_cache, _, _)| {
+            let (sequence, stop_id) = self.trip_index.get(trip_id)?.first()?;
+            gtfs_cache.stop_times.get(stop_id)?.iter()
+                .find(|stop_time| stop_time.trip_id == trip_id && stop_time.stop_sequence == *sequence)
+                .and_then(|stop_time| NVTModels::normalize_gtfs_time(&stop_time.departure_time))
+                .map(|time| time[..5].to_string())
+        });
+        let headsign = scheduled.and_then(|(_, trip, _)| trip.trip_headsign.as_ref()).map(|headsign| format!("to {}", headsign));
+        let trip_name = [Some(line_code), departure, headsign].into_iter().flatten().collect::<Vec<_>>().join(" ");
+
+        let (text, description) = match delay {
+            _ if cancelled => (
+                format!("{} cancelled", trip_name),
+                "This trip is cancelled in the real-time feed.".to_string(),
+            ),
+            Some(delay) => (
+                format!("{} running {} min late", trip_name, delay / 60),
+                format!("This trip is {} minutes late in the real-time feed.", delay / 60),
+            ),
+            None => return None,
+        };
+        Some(AlertInfo {
+            id: format!("derived:{}", trip_id),
+            text,
+            description: format!("{} No alert has been published for it.", description),
+            url: None,
+            route_ids: vec![route_id],
+            stop_ids: Vec::new(),
+            active_period_start: None,
+            active_period_end: None,
+            severity: 2, // GTFS-RT INFO
+            active_periods: Vec::new(),
+            affected_stops_count: 0,
+            affected_stop_ids: None,
+            derived: true,
+            translations: Vec::new(),
+        })
+    }
+
+    /// GTFS route_text_color for a route, if its feed provides one
+    pub fn route_text_color(&self, route_id: &str) -> Option<&String> {
+        self.gtfs_caches().into_iter()
+            .find_map(|cache| cache.route_text_colors.get(route_id))
+    }
+
+    /// Transport mode of a route, from the route_type its feed gives
+    pub fn route_mode(&self, route_id: &str) -> Option<TransportMode> {
+        self.gtfs_caches().into_iter()
+            .find_map(|cache| cache.route_types.get(route_id))
+            .and_then(|route_type| TransportMode::from_route_type(*route_type))
+    }
+
     pub fn to_network_data(&self) -> NetworkData {
         let mut all_stops = NVTModels::build_stops(
             self.tbm_stops_metadata.clone(),
@@ -325,29 +1312,80 @@ impl CachedNetworkData {
             &self.tbm_lines_metadata,
         );
 
-        // Add New-Aquitaine stops
-        all_stops.extend(self.transgironde_stops.clone());
-
-        // Add SNCF stops
-        all_stops.extend(self.sncf_stops.clone());
+        // Add New-Aquitaine, SNCF and plain feed stops
+        all_stops.extend(self.gtfs_stops().cloned());
 
-        let mut all_lines = NVTModels::build_lines(
-            self.tbm_lines_metadata.clone(),
-            self.alerts.clone(),
-            self.real_time.clone(),
-            &self.tbm_gtfs_cache,
-        );
+        // Route-level alerts, on the stops their routes serve (see expand_alert_scope)
+        let mut stop_alerts: HashMap<&str, Vec<&AlertInfo>> = HashMap::new();
+        for alert in &self.alerts {
+            for stop_id in self.alert_stops.get(&alert.id).into_iter().flatten() {
+                stop_alerts.entry(stop_id.as_str()).or_default().push(alert);
+            }
+        }
 
-        // Add New-Aquitaine lines
-        all_lines.extend(self.transgironde_lines.clone());
+        // Pathways leaving each stop, both ways for the bidirectional ones
+        let mut stop_pathways: HashMap<&str, Vec<&Pathway>> = HashMap::new();
+        for pathway in self.gtfs_caches().into_iter().flat_map(|cache| &cache.accessibility.pathways) {
+            stop_pathways.entry(pathway.from_stop_id.as_str()).or_default().push(pathway);
+            if pathway.is_bidirectional {
+                stop_pathways.entry(pathway.to_stop_id.as_str()).or_default().push(pathway);
+            }
+        }
 
-        // Add SNCF lines
-        all_lines.extend(self.sncf_lines.clone());
+        for stop in &mut all_stops {
+            for alert in stop_alerts.get(stop.stop_id.as_str()).into_iter().flatten() {
+                if !stop.alerts.iter().any(|a| a.id == alert.id) {
+                    stop.alerts.push((*alert).clone());
+                }
+            }
+            if let Some(commune) = self.stop_communes.get(&stop.stop_id) {
+                stop.commune = Some(commune.name.clone());
+                stop.insee_code = Some(commune.insee_code.clone());
+            }
+            stop.amenities = self.stop_amenities.get(&stop.stop_id).cloned();
+            for gtfs_cache in self.gtfs_caches() {
+                let accessibility = &gtfs_cache.accessibility;
+                if let Some(&boarding) = accessibility.wheelchair_boarding.get(&stop.stop_id) {
+                    stop.wheelchair_boarding = Some(boarding);
+                }
+                if let Some(level) = accessibility.stop_levels.get(&stop.stop_id).and_then(|level_id| accessibility.levels.get(level_id)) {
+                    stop.level = Some(level.clone());
+                }
+            }
+            stop.pathways = stop_pathways.get(stop.stop_id.as_str()).into_iter().flatten().map(|pathway| (*pathway).clone()).collect();
+        }
+
+        let mut all_lines = NVTModels::build_lines(
+            self.tbm_lines_metadata.clone(),
+            self.alerts.clone(),
+            self.real_time.clone(),
+            &self.tbm_gtfs_cache,
+        );
+
+        let tbm_lines = all_lines.len();
+
+        // Add New-Aquitaine, SNCF and plain feed lines
+        all_lines.extend(self.gtfs_lines().cloned());
+
+        // Vehicles of the other feeds' GTFS-RT on their lines
+        let mut feed_vehicles: HashMap<&str, Vec<&RealTimeInfo>> = HashMap::new();
+        for vehicle in self.real_time.iter().filter(|vehicle| vehicle.source.as_deref() != Some("TBM")) {
+            if let Some(route_id) = &vehicle.route_id {
+                feed_vehicles.entry(route_id.as_str()).or_default().push(vehicle);
+            }
+        }
+        for line in &mut all_lines[tbm_lines..] {
+            if let Some(vehicles) = feed_vehicles.get(line.route_id.as_str()) {
+                line.real_time = vehicles.iter().map(|vehicle| (*vehicle).clone()).collect();
+                line.real_time.sort_by_key(|rt| rt.timestamp.unwrap_or(i64::MAX));
+            }
+        }
 
         // Combine shapes
-        let mut all_shapes = self.tbm_gtfs_cache.shapes.clone();
-        all_shapes.extend(self.transgironde_gtfs_cache.shapes.clone());
-        all_shapes.extend(self.sncf_gtfs_cache.shapes.clone());
+        let mut all_shapes = HashMap::new();
+        for gtfs_cache in self.gtfs_caches() {
+            all_shapes.extend(gtfs_cache.shapes.clone());
+        }
 
         NetworkData {
             stops: all_stops,
@@ -357,6 +1395,220 @@ impl CachedNetworkData {
     }
 }
 
+// ============================================================================
+// Cache Statistics and Health
+// ============================================================================
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RefreshStatus {
+    pub at: u64, // 0 before the first refresh
+    pub succeeded: bool,
+    pub error: Option<String>,
+}
+
+impl RefreshStatus {
+    pub fn of(result: &Result<()>) -> Self {
+        RefreshStatus {
+            at: NVTModels::get_current_timestamp() as u64,
+            succeeded: result.is_ok(),
+            error: result.as_ref().err().map(ToString::to_string),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeedKind {
+    Static, // GTFS feeds, TBM SIRI-Lite stops and lines
+    RealTime, // GTFS-RT feeds
+}
+
+/// Built-in sources of static data, as stats and health name them; plain feeds go by their name
+pub const SOURCES: [&str; 3] = ["TBM", "NewAquitaine", "SNCF"];
+
+/// One of SOURCES, from its name in any case or its short name (tbm, naq, sncf)
+pub fn source_name(name: &str) -> Option<&'static str> {
+    match name.to_lowercase().as_str() {
+        "tbm" => Some("TBM"),
+        "newaquitaine" | "naq" | "transgironde" => Some("NewAquitaine"),
+        "sncf" => Some("SNCF"),
+        _ => None,
+    }
+}
+
+/// What a forced refresh reloads (POST /api/tbm/refresh)
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RefreshScope {
+    Static,
+    Dynamic,
+    #[default]
+    All,
+}
+
+impl std::fmt::Display for RefreshScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RefreshScope::Static => "static",
+            RefreshScope::Dynamic => "dynamic",
+            RefreshScope::All => "all",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FeedFreshness {
+    pub updated: u64, // Last fetch every feed answered, 0 if none did
+    pub error: Option<String>, // Of the last fetch, None when it succeeded
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SourceFreshness {
+    pub static_data: Option<FeedFreshness>, // None until fetched
+    pub real_time: Option<FeedFreshness>, // None for sources without real-time feeds
+}
+
+/// Data ages behind /health, degraded when past the config::HealthConfig thresholds
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub degraded: bool,
+    pub ready: bool, // The initial load is done and at least one source is fresh
+    pub dynamic_age_secs: u64,
+    pub static_age_secs: u64,
+    pub freshness: BTreeMap<String, SourceHealth>, // key: source
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceHealth {
+    pub succeeded: bool, // The last fetch of each of its feeds answered
+    pub stale: bool,
+    pub static_age_secs: Option<u64>, // Since every feed last answered
+    pub real_time_age_secs: Option<u64>,
+    pub errors: Vec<String>,
+}
+
+/// What the cache holds and how old it is, behind /api/tbm/stats
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+    pub sources: Vec<SourceStats>,
+    pub vehicles: usize,
+    pub alerts: usize,
+    pub trip_updates: usize,
+    pub last_static_update: u64,
+    pub last_dynamic_update: u64,
+    pub static_age_secs: u64,
+    pub dynamic_age_secs: u64,
+    pub last_refresh: RefreshStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceStats {
+    pub source: String, // TBM, NewAquitaine, SNCF or the name of a plain feed
+    pub stops: usize,
+    pub lines: usize,
+    pub routes: usize, // Of the GTFS feed
+    pub shapes: usize,
+    pub trips: usize,
+    pub gtfs_age_secs: u64, // Since the feed was downloaded
+    pub cache_file_bytes: Option<u64>, // Saved feed, None when it could not be saved
+    pub feed_info: Option<FeedInfo>, // feed_info.txt of the feed, None when it has none
+}
+
+impl std::fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "📊 Cache Statistics:")?;
+        for source in &self.sources {
+            writeln!(f, " • {}: {} stops, {} lines | Colors: {} | Shapes: {} | Trips: {}",
+                     source.source, source.stops, source.lines, source.routes, source.shapes, source.trips)?;
+            writeln!(f, "   GTFS age: {}s | Cache file: {}", source.gtfs_age_secs,
+                     source.cache_file_bytes.map_or("none".to_string(), |bytes| format!("{} bytes", bytes)))?;
+        }
+        writeln!(f, " • Vehicles tracked: {} | Alerts: {} | Trip updates: {}", self.vehicles, self.alerts, self.trip_updates)?;
+        writeln!(f, " • Static data age: {}s | Dynamic data age: {}s", self.static_age_secs, self.dynamic_age_secs)?;
+        write!(f, " • Last update: {}", NVTModels::format_timestamp_full(self.last_dynamic_update as i64))?;
+        if self.last_refresh.at != 0 {
+            let outcome = match &self.last_refresh.error {
+                Some(error) => format!("failed ({})", error),
+                None => "succeeded".to_string(),
+            };
+            write!(f, "\n • Last refresh: {}, {}", NVTModels::format_timestamp_full(self.last_refresh.at as i64), outcome)?;
+        }
+        Ok(())
+    }
+}
+
+// ============================================================================
+// Upstream Sources
+// ============================================================================
+
+/// Where NVTModels fetches its data and caches the downloaded GTFS feeds. The default is the
+/// live services; tests point it at local mock servers.
+#[derive(Debug, Clone)]
+pub struct Upstreams {
+    pub tbm_api_url: String, // SIRI-Lite and GTFS-RT base URL, used when a TBM feed is configured
+    pub feeds: Vec<config::GtfsFeedConfig>, // GTFS sources, the built-in ones unless the config lists others
+    pub communes_url: String,
+    pub gbfs_url: String, // gbfs.json of the bike share
+    pub navitia: Option<Arc<Navitia>>, // Disruptions fallback, when configured
+    pub cache_dir: PathBuf,
+    pub cache_ages: config::CacheAgesConfig, // How long downloaded feeds are reused
+}
+
+impl Default for Upstreams {
+    fn default() -> Self {
+        let mut cache_dir = dirs::cache_dir().unwrap_or_else(|| PathBuf::from("."));
+        cache_dir.push("tbm_nvt");
+
+        let config = config::get();
+        let feeds = if config.feeds.is_empty() {
+            NVTModels::built_in_feeds(&config.cache_ages)
+        } else {
+            config.feeds.clone()
+        };
+
+        Upstreams {
+            tbm_api_url: NVTModels::BASE_URL.to_string(),
+            feeds,
+            communes_url: communes::COMMUNES_URL.to_string(),
+            gbfs_url: gbfs::GBFS_URL.to_string(),
+            navitia: config.navitia.clone().map(|navitia| Arc::new(Navitia::new(navitia))),
+            cache_dir,
+            cache_ages: config.cache_ages.clone(),
+        }
+    }
+}
+
+impl Upstreams {
+    pub fn feed(&self, name: &str) -> Option<&config::GtfsFeedConfig> {
+        self.feeds.iter().find(|feed| feed.name == name)
+    }
+
+    pub fn feed_mut(&mut self, name: &str) -> Option<&mut config::GtfsFeedConfig> {
+        self.feeds.iter_mut().find(|feed| feed.name == name)
+    }
+
+    /// Whether a configured feed provides `source`, one of SOURCES or a plain feed's name
+    pub fn has_source(&self, source: &str) -> bool {
+        self.feed(source).is_some()
+    }
+
+    /// SOURCES, then the plain GTFS feeds by their name, each a source of its own
+    pub fn sources(&self) -> Vec<String> {
+        SOURCES.iter()
+            .map(|source| source.to_string())
+            .chain(self.feeds.iter().filter(|feed| !feed.is_built_in()).map(|feed| feed.name.clone()))
+            .collect()
+    }
+
+    /// One of `sources` from its name in any case, or a short name of SOURCES (see source_name)
+    pub fn source_named(&self, name: &str) -> Option<String> {
+        source_name(name).map(str::to_string).or_else(|| {
+            self.feeds.iter()
+                .find(|feed| !feed.is_built_in() && feed.name.eq_ignore_ascii_case(name))
+                .map(|feed| feed.name.clone())
+        })
+    }
+}
+
 // ============================================================================
 // Error Handling
 // ============================================================================
@@ -391,77 +1643,58 @@ pub struct NVTModels;
 impl NVTModels {
     const API_KEY: &'static str = "opendata-bordeaux-metropole-flux-gtfs-rt";
     const BASE_URL: &'static str = "https://bdx.mecatran.com/utw/ws";
+    const TBM_GTFS_URL: &'static str = "https://transport.data.gouv.fr/resources/83024/download";
     const TRANSGIRONDE_GTFS_URL: &'static str = "https://www.pigma.org/public/opendata/nouvelle_aquitaine_mobilites/publication/naq-aggregated-gtfs.zip";
     const SNCF_GTFS_URL: &'static str = "https://eu.ftp.opendatasoft.com/sncf/plandata/Export_OpenData_SNCF_GTFS_NewTripId.zip";
     const SNCF_GTFS_RT_TRIP_UPDATES_URL: &'static str = "https://proxy.transport.data.gouv.fr/resource/sncf-gtfs-rt-trip-updates";
     const SNCF_GTFS_RT_SERVICE_ALERTS_URL: &'static str = "https://proxy.transport.data.gouv.fr/resource/sncf-gtfs-rt-service-alerts";
-    const STATIC_DATA_MAX_AGE: u64 = 3600;
     const REQUEST_TIMEOUT_SECS: u64 = 30;
+    const DEDUPE_RADIUS_METERS: f64 = 50.0;
 
     pub fn initialize_cache() -> Result<CachedNetworkData> {
+        Self::initialize_cache_with(Upstreams::default())
+    }
+
+    pub fn initialize_cache_with(upstreams: Upstreams) -> Result<CachedNetworkData> {
         println!("🔄 Initializing network data cache...");
         println!("   This may take a moment...");
 
         // Load TBM data
-        println!("\n📍 Loading TBM data...");
-        let tbm_stops = Self::fetch_stops().map_err(|e| {
-            NVTError::NetworkError(format!("Failed to fetch TBM stops: {}", e))
-        })?;
-        println!("   ✓ Loaded {} TBM stops", tbm_stops.len());
-
-        let tbm_lines = Self::fetch_lines().map_err(|e| {
-            NVTError::NetworkError(format!("Failed to fetch TBM lines: {}", e))
-        })?;
-        println!("   ✓ Loaded {} TBM lines", tbm_lines.len());
+        let tbm = upstreams.has_source("TBM");
+        let (tbm_stops, tbm_lines) = if tbm {
+            println!("\n📍 Loading TBM data...");
+            let tbm_stops = Self::fetch_stops(&upstreams).map_err(|e| {
+                NVTError::NetworkError(format!("Failed to fetch TBM stops: {}", e))
+            })?;
+            println!("   ✓ Loaded {} TBM stops", tbm_stops.len());
+
+            let tbm_lines = Self::fetch_lines(&upstreams).map_err(|e| {
+                NVTError::NetworkError(format!("Failed to fetch TBM lines: {}", e))
+            })?;
+            println!("   ✓ Loaded {} TBM lines", tbm_lines.len());
+            (tbm_stops, tbm_lines)
+        } else {
+            (Vec::new(), Vec::new())
+        };
 
-        let tbm_gtfs_cache = Self::load_gtfs_data("TBM", 15).unwrap_or_else(|e| {
+        let mut failures = Vec::new();
+        let tbm_gtfs_cache = Self::load_source_gtfs(&upstreams, "TBM").unwrap_or_else(|e| {
             println!("   ⚠️  Warning: Could not load TBM GTFS data ({})", e);
+            failures.push(("TBM", FeedKind::Static, format!("GTFS: {}", e)));
             println!("   Continuing with default colors...");
-            GTFSCache {
-                routes: HashMap::new(),
-                stops: Vec::new(),
-                shapes: HashMap::new(),
-                route_to_shapes: HashMap::new(),
-                stop_times: HashMap::new(),
-                trips: HashMap::new(),
-                calendar: HashMap::new(),
-                calendar_dates: HashMap::new(),
-                agencies: HashMap::new(),
-                route_agencies: HashMap::new(),
-                transfers: Vec::new(),
-                cached_at: SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs(),
-                source: "TBM".to_string(),
-            }
+            GTFSCache::empty("TBM")
         });
         println!("   ✓ Loaded {} TBM line colors", tbm_gtfs_cache.routes.len());
 
         // Load TransGironde data
         println!("\n🚌 Loading New-Aquitaine data...");
-        let (transgironde_stops, transgironde_lines, transgironde_gtfs_cache) =
-            Self::load_transgironde_data().unwrap_or_else(|e| {
+        let (transgironde_stops, transgironde_lines, transgironde_gtfs_cache) = Self::load_source_gtfs(&upstreams, "NewAquitaine")
+            .and_then(|gtfs_cache| Self::parse_transgironde_from_cache(gtfs_cache, &upstreams.cache_dir))
+            .unwrap_or_else(|e| {
                 println!("   ⚠️  Warning: Could not load New-Aquitaine data ({})", e);
+                failures.push(("NewAquitaine", FeedKind::Static, format!("GTFS: {}", e)));
                 println!("   Continuing without New-Aquitaine...");
-                (Vec::new(), Vec::new(), GTFSCache {
-                    routes: HashMap::new(),
-                    stops: Vec::new(),
-                    shapes: HashMap::new(),
-                    route_to_shapes: HashMap::new(),
-                    stop_times: HashMap::new(),
-                    trips: HashMap::new(),
-                    calendar: HashMap::new(),
-                    calendar_dates: HashMap::new(),
-                    agencies: HashMap::new(),
-                    route_agencies: HashMap::new(),
-                    transfers: Vec::new(),
-                    cached_at: SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_secs(),
-                    source: "NewAquitaine".to_string(),
-                })
+                (Vec::new(), Vec::new(), GTFSCache::empty("NewAquitaine"))
             });
         println!("   ✓ Loaded {} New-Aquitaine stops", transgironde_stops.len());
         println!("   ✓ Loaded {} New-Aquitaine lines", transgironde_lines.len());
@@ -469,52 +1702,69 @@ impl NVTModels {
 
         // Load SNCF data
         println!("\n🚄 Loading SNCF data...");
-        let (sncf_stops, sncf_lines, sncf_gtfs_cache) =
-            Self::load_sncf_data().unwrap_or_else(|e| {
+        let (sncf_stops, sncf_lines, sncf_gtfs_cache) = Self::load_source_gtfs(&upstreams, "SNCF")
+            .and_then(|gtfs_cache| Self::parse_sncf_from_cache(gtfs_cache, &upstreams.cache_dir))
+            .unwrap_or_else(|e| {
                 println!("   ⚠️  Warning: Could not load SNCF data ({})", e);
+                failures.push(("SNCF", FeedKind::Static, format!("GTFS: {}", e)));
                 println!("   Continuing without SNCF...");
-                (Vec::new(), Vec::new(), GTFSCache {
-                    routes: HashMap::new(),
-                    stops: Vec::new(),
-                    shapes: HashMap::new(),
-                    route_to_shapes: HashMap::new(),
-                    stop_times: HashMap::new(),
-                    trips: HashMap::new(),
-                    calendar: HashMap::new(),
-                    calendar_dates: HashMap::new(),
-                    agencies: HashMap::new(),
-                    route_agencies: HashMap::new(),
-                    transfers: Vec::new(),
-                    cached_at: SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_secs(),
-                    source: "SNCF".to_string(),
-                })
+                (Vec::new(), Vec::new(), GTFSCache::empty("SNCF"))
             });
         println!("   ✓ Loaded {} SNCF stops", sncf_stops.len());
         println!("   ✓ Loaded {} SNCF lines", sncf_lines.len());
         println!("   ✓ Loaded {} SNCF shapes", sncf_gtfs_cache.shapes.len());
 
+        // Load the plain GTFS feeds, each under its name
+        let plain_feeds: Vec<config::GtfsFeedConfig> = upstreams.feeds.iter().filter(|feed| !feed.is_built_in()).cloned().collect();
+        let mut feeds = BTreeMap::new();
+        for feed in &plain_feeds {
+            println!("\n🚌 Loading {} data...", feed.name);
+            let data = Self::load_plain_feed(feed, &upstreams.cache_dir).unwrap_or_else(|e| {
+                println!("   ⚠️  Warning: Could not load the {} GTFS feed ({})", feed.name, e);
+                failures.push((feed.name.as_str(), FeedKind::Static, format!("GTFS: {}", e)));
+                FeedData::empty(&feed.name)
+            });
+            println!("   ✓ Loaded {} {} stops", data.stops.len(), feed.name);
+            println!("   ✓ Loaded {} {} lines", data.lines.len(), feed.name);
+            feeds.insert(feed.name.clone(), data);
+        }
+
         // Load real-time data
         println!("\n📡 Loading real-time data...");
-        let alerts = Self::fetch_alerts().unwrap_or_else(|e| {
-            println!("   ⚠️  Warning: Could not fetch alerts ({})", e);
-            Vec::new()
-        });
-        println!("   ✓ Loaded {} alerts", alerts.len());
+        let (alerts, real_time, trip_updates) = if tbm {
+            let alerts = Self::fetch_alerts(&upstreams).unwrap_or_else(|e| {
+                println!("   ⚠️  Warning: Could not fetch alerts ({})", e);
+                failures.push(("TBM", FeedKind::RealTime, format!("alerts: {}", e)));
+                Vec::new()
+            });
+            println!("   ✓ Loaded {} alerts", alerts.len());
 
-        let real_time = Self::fetch_vehicle_positions().unwrap_or_else(|e| {
-            println!("   ⚠️  Warning: Could not fetch vehicle positions ({})", e);
-            Vec::new()
-        });
-        println!("   ✓ Loaded {} vehicle positions", real_time.len());
+            let real_time = Self::fetch_vehicle_positions(&upstreams).unwrap_or_else(|e| {
+                println!("   ⚠️  Warning: Could not fetch vehicle positions ({})", e);
+                failures.push(("TBM", FeedKind::RealTime, format!("vehicle positions: {}", e)));
+                Vec::new()
+            });
+            println!("   ✓ Loaded {} vehicle positions", real_time.len());
 
-        let trip_updates = Self::fetch_trip_updates().unwrap_or_else(|e| {
-            println!("   ⚠️  Warning: Could not fetch trip updates ({})", e);
-            Vec::new()
-        });
-        println!("   ✓ Loaded {} trip updates", trip_updates.len());
+            let trip_updates = Self::fetch_trip_updates(&upstreams).unwrap_or_else(|e| {
+                println!("   ⚠️  Warning: Could not fetch trip updates ({})", e);
+                failures.push(("TBM", FeedKind::RealTime, format!("trip updates: {}", e)));
+                Vec::new()
+            });
+            println!("   ✓ Loaded {} trip updates", trip_updates.len());
+            (alerts, real_time, trip_updates)
+        } else {
+            (Vec::new(), Vec::new(), Vec::new())
+        };
+
+        let mut bike_share = BikeShare::default();
+        match bike_share.refresh(&upstreams.gbfs_url, Self::get_current_timestamp() as u64) {
+            Ok(()) => println!("   ✓ Loaded {} bike share stations", bike_share.stations.len()),
+            Err(e) => println!("   ⚠️  Warning: Could not fetch bike share stations ({})", e),
+        }
+
+        println!("\n🏛️  Loading commune boundaries...");
+        let communes = CommuneIndex::load(&upstreams.communes_url, &upstreams.cache_dir);
 
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -525,9 +1775,12 @@ impl NVTModels {
         println!("  • TBM: {} stops, {} lines", tbm_stops.len(), tbm_lines.len());
         println!("  • New-Aquitaine: {} stops, {} lines", transgironde_stops.len(), transgironde_lines.len());
         println!("  • SNCF: {} stops, {} lines", sncf_stops.len(), sncf_lines.len());
+        for (name, feed) in &feeds {
+            println!("  • {}: {} stops, {} lines", name, feed.stops.len(), feed.lines.len());
+        }
         println!("  • {} vehicles tracked, {} alerts", real_time.len(), alerts.len());
 
-        Ok(CachedNetworkData {
+        let mut cache = CachedNetworkData {
             tbm_stops_metadata: tbm_stops,
             tbm_lines_metadata: tbm_lines,
             tbm_gtfs_cache,
@@ -537,203 +1790,706 @@ impl NVTModels {
             sncf_stops,
             sncf_lines,
             sncf_gtfs_cache,
+            feeds,
+            trip_index: HashMap::new(),
+            stop_communes: HashMap::new(),
+            stop_index: StopIndex::default(),
+            search_index: SearchIndex::default(),
+            headways: HeadwayIndex::default(),
+            stop_amenities: HashMap::new(),
+            alert_stops: HashMap::new(),
             last_static_update: now,
             alerts,
             real_time,
+            vanished_vehicles: HashMap::new(),
             trip_updates,
+            bike_share,
             last_dynamic_update: now,
-        })
+            last_refresh: RefreshStatus::default(),
+            source_freshness: BTreeMap::new(),
+            upstreams,
+        };
+        let fetched: Vec<(&str, FeedKind)> = SOURCES.iter()
+            .filter(|source| cache.upstreams.has_source(source))
+            .copied()
+            .chain(plain_feeds.iter().map(|feed| feed.name.as_str()))
+            .map(|source| (source, FeedKind::Static))
+            .chain(tbm.then_some(("TBM", FeedKind::RealTime)))
+            .collect();
+        cache.record_fetches(now, &fetched, &failures);
+        cache.trip_index = Self::build_trip_index(&cache.gtfs_caches());
+        cache.stop_communes = communes.assign_stops(&cache);
+        cache.stop_index = StopIndex::build(cache.stop_positions());
+        cache.search_index = SearchIndex::build(&cache);
+        cache.headways = HeadwayIndex::build(&cache, NVTModels::paris_now().date_naive());
+        cache.derive_alerts();
+        cache.expand_alert_scope();
+
+        Ok(cache)
     }
 
     pub fn refresh_dynamic_data(cache: &mut CachedNetworkData) -> Result<()> {
-        // Fetch TBM data
-        cache.alerts = Self::fetch_alerts().unwrap_or_else(|e| {
-            eprintln!("⚠️  Warning: Could not fetch TBM alerts ({})", e);
-            cache.alerts.clone()
-        });
+        let upstreams = cache.upstreams.clone();
+        let mut failures = Vec::new();
+        let (previous_disruptions, alerts): (Vec<AlertInfo>, Vec<AlertInfo>) = std::mem::take(&mut cache.alerts)
+            .into_iter()
+            .partition(|alert| alert.id.starts_with(navitia::ID_PREFIX));
+        cache.alerts = alerts;
 
-        cache.real_time = Self::fetch_vehicle_positions().unwrap_or_else(|e| {
-            eprintln!("⚠️  Warning: Could not fetch TBM vehicle positions ({})", e);
-            cache.real_time.clone()
-        });
+        // Fetch TBM data
+        let tbm = upstreams.has_source("TBM");
+        let real_time = if tbm {
+            cache.alerts = Self::fetch_alerts(&upstreams).unwrap_or_else(|e| {
+                eprintln!("⚠️  Warning: Could not fetch TBM alerts ({})", e);
+                failures.push(("TBM", FeedKind::RealTime, format!("alerts: {}", e)));
+                cache.alerts.clone()
+            });
 
-        cache.trip_updates = Self::fetch_trip_updates().unwrap_or_else(|e| {
-            eprintln!("⚠️  Warning: Could not fetch TBM trip updates ({})", e);
-            cache.trip_updates.clone()
-        });
+            let real_time = Self::fetch_vehicle_positions(&upstreams).unwrap_or_else(|e| {
+                eprintln!("⚠️  Warning: Could not fetch TBM vehicle positions ({})", e);
+                failures.push(("TBM", FeedKind::RealTime, format!("vehicle positions: {}", e)));
+                cache.vehicles_from("TBM").cloned().collect()
+            });
 
-        // Fetch SNCF real-time data
-        let sncf_alerts = Self::fetch_sncf_alerts().unwrap_or_else(|e| {
-            eprintln!("⚠️  Warning: Could not fetch SNCF alerts ({})", e);
+            cache.trip_updates = Self::fetch_trip_updates(&upstreams).unwrap_or_else(|e| {
+                eprintln!("⚠️  Warning: Could not fetch TBM trip updates ({})", e);
+                failures.push(("TBM", FeedKind::RealTime, format!("trip updates: {}", e)));
+                cache.trip_updates.clone()
+            });
+            real_time
+        } else {
+            (cache.alerts, cache.trip_updates) = (Vec::new(), Vec::new());
             Vec::new()
-        });
+        };
+        let previous_vehicles = std::mem::replace(&mut cache.real_time, real_time);
+
+        // Fetch the GTFS-RT feeds of the GTFS sources (SNCF trip updates and alerts by default)
+        let mut feed_alerts = Vec::new();
+        let mut feed_trip_updates = Vec::new();
+        let mut feed_vehicles = Vec::new();
+        let mut fetched = Vec::new();
+        if tbm {
+            fetched.push(("TBM", FeedKind::RealTime));
+        }
+        for feed in upstreams.feeds.iter().filter(|feed| !feed.rt_urls.is_empty()) {
+            let source = feed.name.as_str();
+            if !fetched.contains(&(source, FeedKind::RealTime)) {
+                fetched.push((source, FeedKind::RealTime));
+            }
+            let mut vehicles: Vec<RealTimeInfo> = Vec::new();
+            let mut failed = false;
+            for url in &feed.rt_urls {
+                match Self::fetch_gtfs_rt(feed, url) {
+                    Ok((alerts, trip_updates, positions)) => {
+                        feed_alerts.extend(alerts);
+                        feed_trip_updates.extend(trip_updates);
+                        vehicles.extend(positions);
+                    }
+                    Err(e) => {
+                        eprintln!("⚠️  Warning: Could not fetch {} GTFS-RT ({})", feed.name, e);
+                        failures.push((source, FeedKind::RealTime, format!("{}: {}", url, e)));
+                        failed = true;
+                    }
+                }
+            }
+            // A failed feed keeps the positions it gave last time
+            if failed {
+                let fetched_ids: HashSet<String> = vehicles.iter().map(|v| v.vehicle_id.clone()).collect();
+                let kept: Vec<RealTimeInfo> = previous_vehicles.iter()
+                    .filter(|v| v.source.as_deref() == Some(feed.name.as_str()) && !fetched_ids.contains(&v.vehicle_id))
+                    .cloned()
+                    .collect();
+                vehicles.extend(kept);
+            }
+            feed_vehicles.extend(vehicles);
+        }
 
-        let sncf_trip_updates = Self::fetch_sncf_trip_updates().unwrap_or_else(|e| {
-            eprintln!("⚠️  Warning: Could not fetch SNCF trip updates ({})", e);
-            Vec::new()
-        });
+        // Navitia disruptions whose text no feed published, on the local SNCF stops they name
+        if let Some(navitia) = &upstreams.navitia {
+            match navitia.disruptions(Self::get_current_timestamp() as u64) {
+                Ok(mut disruptions) => {
+                    let texts: HashSet<&str> = cache.alerts.iter().chain(&feed_alerts)
+                        .map(|alert| alert.text.as_str())
+                        .collect();
+                    disruptions.retain(|disruption| !texts.contains(disruption.text.as_str()));
+                    let sncf_stops: Vec<&str> = cache.sncf_stops.iter().map(|stop| stop.stop_id.as_str()).collect();
+                    navitia::localize_stops(&mut disruptions, &sncf_stops);
+                    feed_alerts.extend(disruptions);
+                }
+                Err(e) => {
+                    eprintln!("⚠️  Warning: Could not fetch Navitia disruptions ({})", e);
+                    feed_alerts.extend(previous_disruptions);
+                }
+            }
+        }
+
+        // Bike share stations keep their previous status on failure
+        if let Err(e) = cache.bike_share.refresh(&upstreams.gbfs_url, Self::get_current_timestamp() as u64) {
+            eprintln!("⚠️  Warning: Could not fetch bike share stations ({})", e);
+        }
 
-        // Merge SNCF data with TBM data
-        cache.alerts.extend(sncf_alerts);
-        cache.trip_updates.extend(sncf_trip_updates);
+        // Merge the feeds' data with TBM data
+        cache.alerts.extend(feed_alerts);
+        cache.trip_updates.extend(feed_trip_updates);
+        cache.real_time.extend(feed_vehicles);
+        cache.derive_alerts();
+        cache.expand_alert_scope();
 
         cache.last_dynamic_update = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
+        cache.track_vanished_vehicles(&previous_vehicles, cache.last_dynamic_update);
+        cache.record_fetches(cache.last_dynamic_update, &fetched, &failures);
 
         Ok(())
     }
 
     pub fn refresh_static_data(cache: &mut CachedNetworkData) -> Result<()> {
-        println!("🔄 Refreshing static network data...");
-
-        cache.tbm_stops_metadata = Self::fetch_stops()?;
-        cache.tbm_lines_metadata = Self::fetch_lines()?;
-        cache.tbm_gtfs_cache = Self::load_gtfs_data("TBM", 15)
-            .unwrap_or(cache.tbm_gtfs_cache.clone());
-
-        let (transgironde_stops, transgironde_lines, transgironde_gtfs_cache) =
-            Self::load_transgironde_data()
-                .unwrap_or((cache.transgironde_stops.clone(),
-                            cache.transgironde_lines.clone(),
-                            cache.transgironde_gtfs_cache.clone()));
-
-        cache.transgironde_stops = transgironde_stops;
-        cache.transgironde_lines = transgironde_lines;
-        cache.transgironde_gtfs_cache = transgironde_gtfs_cache;
-
-        let (sncf_stops, sncf_lines, sncf_gtfs_cache) =
-            Self::load_sncf_data()
-                .unwrap_or((cache.sncf_stops.clone(),
-                            cache.sncf_lines.clone(),
-                            cache.sncf_gtfs_cache.clone()));
-
-        cache.sncf_stops = sncf_stops;
-        cache.sncf_lines = sncf_lines;
-        cache.sncf_gtfs_cache = sncf_gtfs_cache;
-
-        cache.last_static_update = SystemTime::now()
+        let sources = cache.upstreams.sources();
+        Self::refresh_static_sources(cache, &sources.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+
+    /// refresh_static_data for some of the sources (see Upstreams::sources) only, the others keep
+    /// their data
+    pub fn refresh_static_sources(cache: &mut CachedNetworkData, sources: &[&str]) -> Result<()> {
+        println!("🔄 Refreshing static network data ({})...", sources.join(", "));
+        let upstreams = cache.upstreams.clone();
+        let sources: Vec<&str> = sources.iter().copied().filter(|source| upstreams.has_source(source)).collect();
+        let fetched: Vec<(&str, FeedKind)> = sources.iter().map(|&source| (source, FeedKind::Static)).collect();
+        let mut failures = Vec::new();
+        let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
 
+        if sources.contains(&"TBM") {
+            let tbm_metadata = Self::fetch_stops(&upstreams)
+                .and_then(|stops| Ok((stops, Self::fetch_lines(&upstreams)?)));
+            match tbm_metadata {
+                Ok((stops, lines)) => (cache.tbm_stops_metadata, cache.tbm_lines_metadata) = (stops, lines),
+                Err(e) => {
+                    cache.record_fetches(now, &[("TBM", FeedKind::Static)], &[("TBM", FeedKind::Static, format!("stops and lines: {}", e))]);
+                    return Err(e);
+                }
+            }
+            match Self::load_source_gtfs(&upstreams, "TBM") {
+                Ok(gtfs_cache) => cache.tbm_gtfs_cache = gtfs_cache,
+                Err(e) => failures.push(("TBM", FeedKind::Static, format!("GTFS: {}", e))),
+            }
+        }
+
+        if sources.contains(&"NewAquitaine") {
+            let regional = Self::load_source_gtfs(&upstreams, "NewAquitaine")
+                .and_then(|gtfs_cache| Self::parse_transgironde_from_cache(gtfs_cache, &upstreams.cache_dir));
+            match regional {
+                Ok((stops, lines, gtfs_cache)) => {
+                    (cache.transgironde_stops, cache.transgironde_lines, cache.transgironde_gtfs_cache) = (stops, lines, gtfs_cache);
+                }
+                Err(e) => failures.push(("NewAquitaine", FeedKind::Static, format!("GTFS: {}", e))),
+            }
+        }
+
+        if sources.contains(&"SNCF") {
+            let sncf = Self::load_source_gtfs(&upstreams, "SNCF")
+                .and_then(|gtfs_cache| Self::parse_sncf_from_cache(gtfs_cache, &upstreams.cache_dir));
+            match sncf {
+                Ok((stops, lines, gtfs_cache)) => {
+                    (cache.sncf_stops, cache.sncf_lines, cache.sncf_gtfs_cache) = (stops, lines, gtfs_cache);
+                }
+                Err(e) => failures.push(("SNCF", FeedKind::Static, format!("GTFS: {}", e))),
+            }
+        }
+
+        for feed in upstreams.feeds.iter().filter(|feed| !feed.is_built_in() && sources.contains(&feed.name.as_str())) {
+            match Self::load_plain_feed(feed, &upstreams.cache_dir) {
+                Ok(data) => {
+                    cache.feeds.insert(feed.name.clone(), data);
+                }
+                Err(e) => failures.push((feed.name.as_str(), FeedKind::Static, format!("GTFS: {}", e))),
+            }
+        }
+
+        cache.trip_index = Self::build_trip_index(&cache.gtfs_caches());
+        cache.stop_communes = CommuneIndex::load(&upstreams.communes_url, &upstreams.cache_dir).assign_stops(cache);
+        cache.stop_index = StopIndex::build(cache.stop_positions());
+        cache.search_index = SearchIndex::build(cache);
+        cache.headways = HeadwayIndex::build(cache, NVTModels::paris_now().date_naive());
+        cache.expand_alert_scope();
+
+        cache.last_static_update = now;
+        cache.record_fetches(now, &fetched, &failures);
+
         println!("✓ Static data refreshed!");
 
         Ok(())
     }
 
-    pub fn smart_refresh(cache: &mut CachedNetworkData) -> Result<()> {
-        Self::refresh_dynamic_data(cache)?;
+    /// Parse a partial GTFS zip in the layout of a source's feed ("tbm", "naq" or "sncf")
+    pub fn parse_gtfs_fragment(source: &str, zip_bytes: &[u8]) -> Result<GTFSCache> {
+        match source {
+            "tbm" => Self::parse_tbm_gtfs(zip_bytes),
+            "naq" => Self::parse_naq_gtfs(zip_bytes),
+            "sncf" => Self::parse_sncf_gtfs(zip_bytes),
+            other => Err(NVTError::ParseError(format!("Unknown source '{}' (expected tbm, naq or sncf)", other))),
+        }
+    }
 
-        if cache.needs_static_refresh(Self::STATIC_DATA_MAX_AGE) {
-            Self::refresh_static_data(cache)?;
+    /// Merge a GTFS fragment into a source of the cache (see GTFSCache::merge), then rebuild its
+    /// stops and lines and the indexes. TBM stops and lines come from SIRI, so the fragment's new
+    /// ones are added to the SIRI metadata.
+    pub fn merge_gtfs_fragment(cache: &mut CachedNetworkData, source: &str, fragment: GTFSCache) -> Result<()> {
+        let cache_dir = cache.upstreams.cache_dir.clone();
+        match source {
+            "tbm" => {
+                Self::add_tbm_metadata(cache, &fragment);
+                cache.tbm_gtfs_cache.merge(fragment);
+            }
+            "naq" => {
+                let mut gtfs_cache = std::mem::replace(&mut cache.transgironde_gtfs_cache, GTFSCache::empty("NewAquitaine"));
+                gtfs_cache.merge(fragment);
+                (cache.transgironde_stops, cache.transgironde_lines, cache.transgironde_gtfs_cache) =
+                    Self::parse_transgironde_from_cache(gtfs_cache, &cache_dir)?;
+            }
+            "sncf" => {
+                let mut gtfs_cache = std::mem::replace(&mut cache.sncf_gtfs_cache, GTFSCache::empty("SNCF"));
+                gtfs_cache.merge(fragment);
+                (cache.sncf_stops, cache.sncf_lines, cache.sncf_gtfs_cache) =
+                    Self::parse_sncf_from_cache(gtfs_cache, &cache_dir)?;
+            }
+            other => return Err(NVTError::ParseError(format!("Unknown source '{}' (expected tbm, naq or sncf)", other))),
         }
 
+        cache.trip_index = Self::build_trip_index(&cache.gtfs_caches());
+        cache.stop_index = StopIndex::build(cache.stop_positions());
+        cache.search_index = SearchIndex::build(cache);
+        cache.headways = HeadwayIndex::build(cache, NVTModels::paris_now().date_naive());
+        cache.expand_alert_scope();
         Ok(())
     }
 
-    // ============================================================================
-    // New-Aquitaine Regional Networks GTFS Loading
-    // (Function name kept as "load_transgironde_data" for backward compatibility)
-    // ============================================================================
+    /// SIRI metadata for the routes and stops of a TBM fragment that SIRI doesn't list
+    fn add_tbm_metadata(cache: &mut CachedNetworkData, fragment: &GTFSCache) {
+        let mut destinations: HashMap<&str, Vec<(String, String)>> = HashMap::new();
+        for trip in fragment.trips.values() {
+            let directions = destinations.entry(trip.route_id.as_str()).or_default();
+            let direction = trip.direction_id.unwrap_or(0).to_string();
+            if let Some(headsign) = &trip.trip_headsign
+                && !directions.iter().any(|(d, _)| *d == direction) {
+                directions.push((direction, headsign.clone()));
+            }
+        }
+        for (route_id, mut route_destinations) in destinations {
+            let known = cache.tbm_lines_metadata.iter()
+                .any(|(line_ref, _, _, _)| Self::extract_line_id(line_ref) == Some(route_id));
+            if !known {
+                route_destinations.sort();
+                let line_code = Self::extract_line_code_from_route(route_id, "TBM");
+                cache.tbm_lines_metadata.push((format!("bordeaux:Line:{}:LOC", route_id), line_code.clone(), line_code, route_destinations));
+            }
+        }
 
-    fn load_transgironde_data() -> Result<(Vec<Stop>, Vec<Line>, GTFSCache)> {
-        if let Some(cache) = GTFSCache::load("NewAquitaine", 30) {
-            return Self::parse_transgironde_from_cache(cache);
+        let mut stop_lines: HashMap<&str, HashSet<String>> = HashMap::new();
+        for stop_time in fragment.stop_times.values().flatten() {
+            if let Some(trip) = fragment.trips.get(&stop_time.trip_id).or_else(|| cache.tbm_gtfs_cache.trips.get(&stop_time.trip_id)) {
+                stop_lines.entry(stop_time.stop_id.as_str()).or_default().insert(format!("bordeaux:Line:{}:LOC", trip.route_id));
+            }
+        }
+        for (stop_id, name, lat, lon) in &fragment.stops {
+            match cache.tbm_stops_metadata.iter_mut().find(|(id, _, _, _, _)| id == stop_id) {
+                Some(existing) => (existing.1, existing.2, existing.3) = (name.clone(), *lat, *lon),
+                None => cache.tbm_stops_metadata.push((stop_id.clone(), name.clone(), *lat, *lon, Vec::new())),
+            }
         }
+        for (stop_id, _, _, _, lines) in &mut cache.tbm_stops_metadata {
+            if let Some(added) = stop_lines.get(stop_id.as_str()) {
+                lines.extend(added.iter().filter(|line| !lines.contains(line)).cloned().collect::<Vec<_>>());
+            }
+        }
+    }
 
-        println!("📥 Downloading New-Aquitaine GTFS data...");
+    /// Ordered stops of every trip, so trip progress doesn't need a scan of all stop times
+    pub fn build_trip_index(gtfs_caches: &[&GTFSCache]) -> HashMap<String, Vec<(u32, String)>> {
+        let mut index: HashMap<String, Vec<(u32, String)>> = HashMap::new();
 
-        let client = blocking::Client::builder()
-            .timeout(std::time::Duration::from_secs(Self::REQUEST_TIMEOUT_SECS))
-            .build()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to create HTTP client: {}", e)))?;
+        for gtfs_cache in gtfs_caches {
+            for stop_time in gtfs_cache.stop_times.values().flatten() {
+                index.entry(stop_time.trip_id.clone())
+                    .or_default()
+                    .push((stop_time.stop_sequence, stop_time.stop_id.clone()));
+            }
+        }
 
-        let response = client.get(Self::TRANSGIRONDE_GTFS_URL)
-            .send()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to download New-Aquitaine GTFS: {}", e)))?;
+        for stops in index.values_mut() {
+            stops.sort_by_key(|(sequence, _)| *sequence);
+        }
 
-        if !response.status().is_success() {
-            return Err(NVTError::NetworkError(format!("Download failed with status: {}", response.status())));
+        println!("   ✓ Indexed stops of {} trips", index.len());
+        index
+    }
+
+    /// (stop_sequence, stop_id) of a line in one direction, in travel order. Trips of a line
+    /// may skip stops or turn short, so the longest trip is taken as the line's stop pattern.
+    /// Feeds without direction_id count as direction 0.
+    pub fn line_stop_sequence<'a>(line: &Line, direction: u32, cache: &'a CachedNetworkData) -> &'a [(u32, String)] {
+        cache.gtfs_caches().into_iter()
+            .flat_map(|gtfs| gtfs.trips.values())
+            .filter(|trip| trip.route_id == line.route_id || trip.route_id == line.line_ref)
+            .filter(|trip| trip.direction_id.unwrap_or(0) == direction)
+            .filter_map(|trip| cache.trip_index.get(&trip.trip_id).map(|stops| (&trip.trip_id, stops)))
+            .max_by(|(id_a, a), (id_b, b)| a.len().cmp(&b.len()).then_with(|| id_b.cmp(id_a)))
+            .map_or(&[], |(_, stops)| stops.as_slice())
+    }
+
+    /// Position of a vehicle along its trip: (progress 0..1, stops remaining)
+    pub fn trip_progress(vehicle: &RealTimeInfo, cache: &CachedNetworkData) -> Option<(f32, u32)> {
+        let stops = cache.trip_index.get(&vehicle.trip_id)?;
+
+        let index = match (vehicle.current_stop_sequence, &vehicle.stop_id) {
+            (Some(sequence), _) => stops.iter().position(|(seq, _)| *seq == sequence),
+            (None, Some(stop_id)) => stops.iter().position(|(_, id)| id == stop_id),
+            (None, None) => None,
+        }?;
+
+        let last = stops.len().saturating_sub(1);
+        let progress = if last == 0 { 1.0 } else { index as f32 / last as f32 };
+        Some((progress, (last - index) as u32))
+    }
+
+    pub fn vehicles_with_progress(cache: &CachedNetworkData) -> Vec<VehicleWithProgress<'_>> {
+        cache.real_time.iter()
+            .map(|vehicle| {
+                let progress = Self::trip_progress(vehicle, cache);
+                VehicleWithProgress {
+                    vehicle: Cow::Borrowed(vehicle),
+                    progress: progress.map(|(p, _)| p),
+                    stops_remaining: progress.map(|(_, remaining)| remaining),
+                    cluster_size: None,
+                    interpolated: None,
+                }
+            })
+            .collect()
+    }
+
+    /// Vehicles reported after `since`, with the ids of those that left the feed since. Feed
+    /// timestamps lag behind the refreshes, so a removal may be listed by two consecutive polls.
+    pub fn vehicles_since(cache: &CachedNetworkData, since: i64) -> VehiclesDelta<'_> {
+        let vehicles: Vec<VehicleWithProgress> = Self::vehicles_with_progress(cache).into_iter()
+            .filter(|v| v.vehicle.timestamp.is_none_or(|timestamp| timestamp > since))
+            .collect();
+        let mut removed: Vec<String> = cache.vanished_vehicles.iter()
+            .filter(|(_, vanished_at)| **vanished_at as i64 > since)
+            .map(|(id, _)| id.clone())
+            .collect();
+        removed.sort();
+
+        VehiclesDelta {
+            since,
+            next_since: cache.real_time.iter().filter_map(|v| v.timestamp).max().unwrap_or(since).max(since),
+            vehicles,
+            removed,
         }
+    }
 
-        let zip_bytes = response.bytes()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to read GTFS zip: {}", e)))?;
+    /// Agencies of every source, in source order then by name
+    pub fn agencies(cache: &CachedNetworkData) -> Vec<AgencyInfo> {
+        let lines = cache.to_network_data().lines;
+        let mut agencies = Vec::new();
+        for (source, gtfs_cache) in cache.gtfs_sources() {
+            let mut source_agencies: Vec<AgencyInfo> = gtfs_cache.agencies.values()
+                .map(|agency| {
+                    let route_ids = Self::agency_route_ids(gtfs_cache, &agency.agency_id);
+                    AgencyInfo {
+                        agency: agency.clone(),
+                        source: source.to_string(),
+                        lines_count: lines.iter().filter(|line| route_ids.contains(line.route_id.as_str())).count(),
+                    }
+                })
+                .collect();
+            source_agencies.sort_by(|a, b| a.agency.agency_name.cmp(&b.agency.agency_name));
+            agencies.extend(source_agencies);
+        }
+        agencies
+    }
 
-        println!("✓ Downloaded {} KB, extracting...", zip_bytes.len() / 1024);
+    /// Lines run by the agency `agency_id` of any source, by line code; None when no feed has it
+    pub fn agency_lines(cache: &CachedNetworkData, agency_id: &str) -> Option<Vec<Line>> {
+        let sources: Vec<&GTFSCache> = cache.gtfs_caches().into_iter()
+            .filter(|gtfs_cache| gtfs_cache.agencies.contains_key(agency_id))
+            .collect();
+        if sources.is_empty() {
+            return None;
+        }
+        let route_ids: HashSet<&str> = sources.into_iter()
+            .flat_map(|gtfs_cache| Self::agency_route_ids(gtfs_cache, agency_id))
+            .collect();
 
-        let cursor = Cursor::new(zip_bytes);
-        let mut archive = ZipArchive::new(cursor)
-            .map_err(|e| NVTError::ParseError(format!("Failed to open GTFS zip: {}", e)))?;
+        let mut lines: Vec<Line> = cache.to_network_data().lines.into_iter()
+            .filter(|line| route_ids.contains(line.route_id.as_str()))
+            .collect();
+        lines.sort_by(|a, b| a.line_code.cmp(&b.line_code));
+        Some(lines)
+    }
 
-        // Parse agency.txt first to get operator information
-        let agencies = Self::parse_agencies(&mut archive)?;
-        println!("   ✓ Parsed {} agencies", agencies.len());
+    /// Routes of `agency_id` in a feed; routes.txt may leave agency_id out when there is one agency
+    fn agency_route_ids<'a>(gtfs_cache: &'a GTFSCache, agency_id: &str) -> HashSet<&'a str> {
+        let only_agency = gtfs_cache.agencies.len() == 1;
+        gtfs_cache.routes.keys()
+            .filter(|route_id| match gtfs_cache.route_agencies.get(*route_id) {
+                Some(route_agency) => route_agency == agency_id,
+                None => only_agency,
+            })
+            .map(String::as_str)
+            .collect()
+    }
 
-        // Parse routes.txt with agency_id
-        let (routes, route_agencies) = Self::parse_transgironde_routes(&mut archive)?;
-        println!("   ✓ Parsed {} New-Aquitaine routes", routes.len());
+    /// Great-circle distance in meters
+    pub fn distance_meters(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        let (dlat, dlon) = ((lat2 - lat1).to_radians(), (lon2 - lon1).to_radians());
+        let a = (dlat / 2.0).sin().powi(2)
+            + lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * 6_371_000.0 * a.sqrt().asin()
+    }
 
-        // Parse stops.txt
-        let stops_data = Self::parse_transgironde_stops(&mut archive)?;
-        println!("   ✓ Parsed {} New-Aquitaine stops", stops_data.len());
+    /// Collapse stops with the same name within DEDUPE_RADIUS_METERS of each other.
+    /// The first stop of each group (TBM before New-Aquitaine before SNCF) is kept as the
+    /// representative and gains the lines, alerts and real-time data of the others.
+    pub fn dedupe_stops(stops: Vec<Stop>) -> Vec<DedupedStop> {
+        let mut deduped: Vec<DedupedStop> = Vec::new();
+        let mut by_name: HashMap<String, Vec<usize>> = HashMap::new();
+
+        for stop in stops {
+            let name_key = stop.stop_name
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+                .to_lowercase();
+            let candidates = by_name.entry(name_key).or_default();
+
+            let existing = candidates.iter().copied().find(|&i| {
+                let representative = &deduped[i].stop;
+                Self::distance_meters(representative.latitude, representative.longitude, stop.latitude, stop.longitude)
+                    <= Self::DEDUPE_RADIUS_METERS
+            });
 
-        // Parse shapes.txt
-        let shapes = Self::parse_transgironde_shapes(&mut archive)?;
-        println!("   ✓ Parsed {} New-Aquitaine shapes", shapes.len());
+            match existing {
+                Some(i) => {
+                    let group = &mut deduped[i];
+                    group.merged_ids.push(stop.stop_id);
+                    for line in stop.lines {
+                        if !group.stop.lines.contains(&line) {
+                            group.stop.lines.push(line);
+                        }
+                    }
+                    for alert in stop.alerts {
+                        if !group.stop.alerts.iter().any(|a| a.id == alert.id) {
+                            group.stop.alerts.push(alert);
+                        }
+                    }
+                    group.stop.real_time.extend(stop.real_time);
+                }
+                None => {
+                    candidates.push(deduped.len());
+                    deduped.push(DedupedStop { merged_ids: vec![stop.stop_id.clone()], stop });
+                }
+            }
+        }
 
-        // Parse trips.txt to map routes to shapes
-        let route_to_shapes = Self::parse_transgironde_trips(&mut archive)?;
-        println!("   ✓ Mapped {} routes to shapes", route_to_shapes.len());
+        deduped
+    }
 
-        // Parse stop_times.txt for schedule predictions
-        let stop_times = Self::parse_stop_times(&mut archive)?;
-        println!("   ✓ Parsed {} stop time entries", stop_times.values().map(|v| v.len()).sum::<usize>());
+    pub fn smart_refresh(cache: &mut CachedNetworkData) -> Result<()> {
+        let result = Self::refresh_dynamic_data(cache).and_then(|()| {
+            if cache.needs_static_refresh(cache.upstreams.cache_ages.static_refresh_secs) {
+                Self::refresh_static_data(cache)?;
+            }
+            Ok(())
+        });
 
-        // Parse trips.txt for trip information
-        let trips = Self::parse_trips_info(&mut archive)?;
-        println!("   ✓ Parsed {} trips", trips.len());
+        cache.last_refresh = RefreshStatus::of(&result);
+        result
+    }
 
-        // Parse calendar.txt for service schedules
-        let calendar = Self::parse_calendar(&mut archive)?;
-        println!("   ✓ Parsed {} calendar services", calendar.len());
+    /// smart_refresh of the shared cache. The real-time refresh holds the lock; a static refresh,
+    /// when due or with `full`, runs on a copy swapped in once complete (refresh_static_snapshot)
+    pub fn smart_refresh_shared(state: &Mutex<CachedNetworkData>, full: bool) -> Result<()> {
+        let refresh = || {
+            let static_due = {
+                let mut cache = Self::lock_cache(state)?;
+                Self::refresh_dynamic_data(&mut cache)?;
+                full || cache.needs_static_refresh(cache.upstreams.cache_ages.static_refresh_secs)
+            };
+            if static_due {
+                let sources = Self::lock_cache(state)?.upstreams.sources();
+                Self::refresh_static_snapshot(state, &sources.iter().map(String::as_str).collect::<Vec<_>>())?;
+            }
+            Ok(())
+        };
+        let result = refresh();
 
-        // Parse calendar_dates.txt for exceptions
-        let calendar_dates = Self::parse_calendar_dates(&mut archive)?;
-        println!("   ✓ Parsed {} calendar date exceptions", calendar_dates.values().map(|v| v.len()).sum::<usize>());
+        Self::lock_cache(state)?.last_refresh = RefreshStatus::of(&result);
+        result
+    }
 
-        // Parse transfers.txt
-        let transfers = Self::parse_transfers(&mut archive)?;
-        println!("   ✓ Parsed {} transfers", transfers.len());
+    /// Forced refresh of the shared cache: the real-time data and/or the static data of `sources`
+    /// (all the real-time feeds are refreshed together, their data being merged)
+    pub fn scoped_refresh_shared(state: &Mutex<CachedNetworkData>, scope: RefreshScope, sources: &[&str]) -> Result<()> {
+        let refresh = || {
+            if scope != RefreshScope::Static {
+                Self::refresh_dynamic_data(&mut *Self::lock_cache(state)?)?;
+            }
+            if scope != RefreshScope::Dynamic {
+                Self::refresh_static_snapshot(state, sources)?;
+            }
+            Ok(())
+        };
+        let result = refresh();
 
-        let gtfs_cache = GTFSCache {
-            routes,
-            stops: stops_data.clone(),
-            shapes: shapes.clone(),
-            route_to_shapes: route_to_shapes.clone(),
-            stop_times,
-            trips,
-            calendar,
-            calendar_dates,
-            agencies,
-            route_agencies,
-            transfers,
-            cached_at: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs(),
-            source: "NewAquitaine".to_string(),
+        Self::lock_cache(state)?.last_refresh = RefreshStatus::of(&result);
+        result
+    }
+
+    /// refresh_static_sources on a copy of the shared cache, swapped in when complete: requests
+    /// meanwhile get the previous consistent data instead of waiting on the downloads, and the
+    /// real-time data refreshed in the meantime is kept
+    pub fn refresh_static_snapshot(state: &Mutex<CachedNetworkData>, sources: &[&str]) -> Result<()> {
+        let mut copy = Self::lock_cache(state)?.clone();
+        let result = Self::refresh_static_sources(&mut copy, sources);
+
+        let mut cache = Self::lock_cache(state)?;
+        if result.is_ok() {
+            cache.take_static_data(copy);
+        } else {
+            cache.take_static_freshness(&copy);
+        }
+        result
+    }
+
+    fn lock_cache(state: &Mutex<CachedNetworkData>) -> Result<MutexGuard<'_, CachedNetworkData>> {
+        state.lock().map_err(|e| NVTError::NetworkError(format!("Failed to lock cache: {}", e)))
+    }
+
+    // ============================================================================
+    // GTFS Feed Loading
+    // ============================================================================
+
+    /// The TBM, New-Aquitaine and SNCF feeds, the sources when the config lists no feeds
+    pub fn built_in_feeds(cache_ages: &config::CacheAgesConfig) -> Vec<config::GtfsFeedConfig> {
+        let feed = |name: &str, static_url: &str, rt_urls: &[&str], max_age_days: u64| config::GtfsFeedConfig {
+            name: name.to_string(),
+            static_url: static_url.to_string(),
+            rt_urls: rt_urls.iter().map(|url| url.to_string()).collect(),
+            max_age_days,
+            id_prefix: None,
+            mode: None,
         };
+        vec![
+            feed("TBM", Self::TBM_GTFS_URL, &[], cache_ages.tbm_days),
+            feed("NewAquitaine", Self::TRANSGIRONDE_GTFS_URL, &[], cache_ages.naq_days),
+            feed("SNCF", Self::SNCF_GTFS_URL, &[Self::SNCF_GTFS_RT_TRIP_UPDATES_URL, Self::SNCF_GTFS_RT_SERVICE_ALERTS_URL], cache_ages.sncf_days),
+        ]
+    }
 
-        if let Err(e) = gtfs_cache.save() {
-            eprintln!("⚠️  Warning: Could not save TransGironde cache: {}", e);
+    /// GTFS of one feed: its saved copy while younger than max_age_days, otherwise a download
+    /// read by the parser of its network (plain GTFS ones by the New-Aquitaine parser), its ids
+    /// prefixed, then saved
+    fn load_feed(feed: &config::GtfsFeedConfig, cache_dir: &Path) -> Result<GTFSCache> {
+        let with_mode = |gtfs_cache: GTFSCache| match feed.mode {
+            Some(mode) => gtfs_cache.with_mode(mode),
+            None => gtfs_cache,
+        };
+        if let Some(cache) = GTFSCache::load(cache_dir, &feed.name, feed.max_age_days) {
+            return Ok(with_mode(cache));
         }
+        // Kept for when the download turns out to hold the same data
+        let mut outdated = GTFSCache::load_outdated(cache_dir, &feed.name);
+
+        println!("📥 Downloading {} GTFS data...", feed.name);
+
+        let client = blocking::Client::builder()
+            .timeout(std::time::Duration::from_secs(Self::REQUEST_TIMEOUT_SECS * 3)) // Longer timeout for large files
+            .build()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to create HTTP client: {}", e)))?;
+
+        let mut request = client.get(&feed.static_url);
+        if let Some(outdated) = &outdated {
+            if let Some(etag) = &outdated.etag {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &outdated.last_modified {
+                request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let response = request.send()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to download {} GTFS: {}", feed.name, e)))?;
+
+        let header = |name: reqwest::header::HeaderName| response.headers().get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let (etag, last_modified) = (header(reqwest::header::ETAG), header(reqwest::header::LAST_MODIFIED));
+
+        if response.status() == reqwest::StatusCode::NOT_MODIFIED
+            && let Some(outdated) = outdated.take() {
+            println!("✓ {} GTFS not modified since the last download", feed.name);
+            return Ok(with_mode(outdated.renewed(etag, last_modified, cache_dir)));
+        }
+        if !response.status().is_success() {
+            return Err(NVTError::NetworkError(format!("Download failed with status: {}", response.status())));
+        }
+
+        let zip_bytes = response.bytes()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to read GTFS zip: {}", e)))?;
+
+        println!("✓ Downloaded {} KB, extracting...", zip_bytes.len() / 1024);
+
+        // A feed_version that didn't change is the same data, not worth parsing again
+        if let Some(outdated) = outdated.take_if(|outdated| {
+            let version = outdated.feed_info.as_ref().and_then(|info| info.version.as_deref());
+            version.is_some() && Self::zip_feed_version(&zip_bytes).as_deref() == version
+        }) {
+            println!("✓ {} GTFS still at version {}, keeping the parsed copy",
+                     feed.name, outdated.feed_info.as_ref().and_then(|info| info.version.as_deref()).unwrap_or_default());
+            return Ok(with_mode(outdated.renewed(etag, last_modified, cache_dir)));
+        }
+
+        let mut gtfs_cache = match feed.name.as_str() {
+            "TBM" => Self::parse_tbm_gtfs(&zip_bytes)?,
+            "SNCF" => Self::parse_sncf_gtfs(&zip_bytes)?,
+            _ => Self::parse_naq_gtfs(&zip_bytes)?,
+        };
+        if let Some(prefix) = &feed.id_prefix {
+            gtfs_cache = gtfs_cache.with_id_prefix(prefix);
+        }
+        gtfs_cache.source = feed.name.clone();
+        (gtfs_cache.etag, gtfs_cache.last_modified) = (etag, last_modified);
+
+        if let Err(e) = gtfs_cache.save(cache_dir) {
+            eprintln!("⚠️  Warning: Could not save {} GTFS cache: {}", feed.name, e);
+        }
+
+        Ok(with_mode(gtfs_cache))
+    }
+
+    /// GTFS of one of the SOURCES from its configured feed, empty without one
+    fn load_source_gtfs(upstreams: &Upstreams, source: &str) -> Result<GTFSCache> {
+        let cache_dir = &upstreams.cache_dir;
+        match upstreams.feed(source) {
+            Some(feed) => Ok(map_matching::apply(Self::load_feed(feed, cache_dir)?, cache_dir)),
+            None => Ok(GTFSCache::empty(source)),
+        }
+    }
 
-        Self::parse_transgironde_from_cache(gtfs_cache)
+    /// Stops, lines and timetables of a plain GTFS feed, built as New-Aquitaine's are
+    fn load_plain_feed(feed: &config::GtfsFeedConfig, cache_dir: &Path) -> Result<FeedData> {
+        let (stops, lines, gtfs_cache) = Self::parse_transgironde_from_cache(Self::load_feed(feed, cache_dir)?, cache_dir)?;
+        Ok(FeedData { stops, lines, gtfs_cache })
     }
 
-    fn parse_agencies(archive: &mut ZipArchive<Cursor<bytes::Bytes>>) -> Result<HashMap<String, Agency>> {
+    // ============================================================================
+    // New-Aquitaine Regional Networks GTFS Loading
+    // ============================================================================
+
+    fn parse_agencies(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<HashMap<String, Agency>> {
         let mut agencies_map = HashMap::new();
 
         if let Ok(mut agencies_file) = archive.by_name("agency.txt") {
@@ -763,7 +2519,7 @@ impl NVTModels {
         Ok(agencies_map)
     }
 
-    fn parse_transgironde_routes(archive: &mut ZipArchive<Cursor<bytes::Bytes>>) -> Result<(HashMap<String, String>, HashMap<String, String>)> {
+    fn parse_transgironde_routes(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<(HashMap<String, String>, HashMap<String, String>)> {
         let mut routes_file = archive.by_name("routes.txt")
             .map_err(|e| NVTError::FileError(format!("routes.txt not found: {}", e)))?;
 
@@ -801,7 +2557,7 @@ impl NVTModels {
         Ok((color_map, route_agencies))
     }
 
-    fn parse_transgironde_stops(archive: &mut ZipArchive<Cursor<bytes::Bytes>>) -> Result<Vec<(String, String, f64, f64)>> {
+    fn parse_transgironde_stops(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<Vec<(String, String, f64, f64)>> {
         // GTFS stops.txt field indices
         const STOP_ID_INDEX: usize = 0;
         const STOP_NAME_INDEX: usize = 1;
@@ -850,7 +2606,7 @@ impl NVTModels {
         Ok(stops_data)
     }
 
-    fn parse_transgironde_shapes(archive: &mut ZipArchive<Cursor<bytes::Bytes>>) -> Result<HashMap<String, Vec<ShapePoint>>> {
+    fn parse_transgironde_shapes(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<HashMap<String, Vec<ShapePoint>>> {
         let mut shapes_map: HashMap<String, Vec<ShapePoint>> = HashMap::new();
 
         if let Ok(mut shapes_file) = archive.by_name("shapes.txt") {
@@ -888,7 +2644,7 @@ impl NVTModels {
         Ok(shapes_map)
     }
 
-    fn parse_transgironde_trips(archive: &mut ZipArchive<Cursor<bytes::Bytes>>) -> Result<HashMap<String, Vec<String>>> {
+    fn parse_transgironde_trips(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<HashMap<String, Vec<String>>> {
         let mut route_to_shapes: HashMap<String, Vec<String>> = HashMap::new();
 
         if let Ok(mut trips_file) = archive.by_name("trips.txt") {
@@ -920,49 +2676,97 @@ impl NVTModels {
         Ok(route_to_shapes)
     }
 
-    fn parse_stop_times(archive: &mut ZipArchive<Cursor<bytes::Bytes>>) -> Result<HashMap<String, Vec<StopTime>>> {
-        let mut stop_times_map: HashMap<String, Vec<StopTime>> = HashMap::new();
-
+    fn parse_stop_times(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<(HashMap<String, Vec<StopTime>>, FeedQuality)> {
         if let Ok(mut stop_times_file) = archive.by_name("stop_times.txt") {
             let mut contents = String::new();
             stop_times_file.read_to_string(&mut contents).ok();
             drop(stop_times_file);
 
-            let mut rdr = csv::Reader::from_reader(contents.as_bytes());
+            return Ok(Self::parse_stop_times_csv(&contents));
+        }
 
-            for result in rdr.records() {
-                if let Ok(record) = result {
-                    // trip_id,arrival_time,departure_time,stop_id,stop_sequence,stop_headsign,pickup_type,drop_off_type,shape_dist_traveled
-                    if let (Some(trip_id), Some(arrival_time), Some(departure_time), Some(stop_id), Some(stop_sequence)) =
-                        (record.get(0), record.get(1), record.get(2), record.get(3), record.get(4)) {
-                        if let Ok(sequence) = stop_sequence.parse::<u32>() {
-                            let stop_time = StopTime {
-                                trip_id: trip_id.to_string(),
-                                arrival_time: arrival_time.to_string(),
-                                departure_time: departure_time.to_string(),
-                                stop_id: stop_id.to_string(),
-                                stop_sequence: sequence,
-                                stop_headsign: record.get(5).map(|s| s.to_string()).filter(|s| !s.is_empty()),
-                            };
+        Ok((HashMap::new(), FeedQuality::default()))
+    }
 
-                            stop_times_map.entry(stop_id.to_string())
-                                .or_insert_with(Vec::new)
-                                .push(stop_time);
+    /// Parse stop_times.txt contents, normalizing times and counting what had to be fixed or dropped
+    pub fn parse_stop_times_csv(contents: &str) -> (HashMap<String, Vec<StopTime>>, FeedQuality) {
+        let mut stop_times_map: HashMap<String, Vec<StopTime>> = HashMap::new();
+        let mut quality = FeedQuality::default();
+
+        let mut rdr = csv::Reader::from_reader(contents.as_bytes());
+
+        for result in rdr.records() {
+            quality.stop_times_total += 1;
+            let Ok(record) = result else {
+                quality.stop_times_rejected += 1;
+                continue;
+            };
+
+            // trip_id,arrival_time,departure_time,stop_id,stop_sequence,stop_headsign,pickup_type,drop_off_type,shape_dist_traveled
+            let (Some(trip_id), Some(arrival_time), Some(departure_time), Some(stop_id), Some(sequence)) =
+                (record.get(0), record.get(1), record.get(2), record.get(3), record.get(4).and_then(|s| s.trim().parse::<u32>().ok())) else {
+                quality.stop_times_rejected += 1;
+                continue;
+            };
+
+            let mut normalize = |raw: &str| -> String {
+                let raw = raw.trim();
+                if raw.is_empty() {
+                    return String::new();
+                }
+                match Self::normalize_gtfs_time(raw) {
+                    Some(normalized) => {
+                        if normalized != raw {
+                            quality.times_normalized += 1;
                         }
+                        normalized
+                    }
+                    None => {
+                        quality.times_rejected += 1;
+                        String::new()
                     }
                 }
+            };
+            let mut arrival_time = normalize(arrival_time);
+            let mut departure_time = normalize(departure_time);
+
+            // A timepoint must have both; feeds giving only one mean the same time for both
+            if arrival_time.is_empty() {
+                arrival_time = departure_time.clone();
+            } else if departure_time.is_empty() {
+                departure_time = arrival_time.clone();
             }
-
-            // Sort stop times by arrival time for each stop
-            for times in stop_times_map.values_mut() {
-                times.sort_by(|a, b| a.arrival_time.cmp(&b.arrival_time));
+            if arrival_time.is_empty() {
+                quality.untimed += 1;
             }
+
+            let stop_time = StopTime {
+                trip_id: trip_id.to_string(),
+                arrival_time,
+                departure_time,
+                stop_id: stop_id.to_string(),
+                stop_sequence: sequence,
+                stop_headsign: record.get(5).map(|s| s.to_string()).filter(|s| !s.is_empty()),
+            };
+
+            stop_times_map.entry(stop_id.to_string())
+                .or_insert_with(Vec::new)
+                .push(stop_time);
+        }
+
+        // Sort stop times by arrival time for each stop (zero-padded, so string order is time order)
+        for times in stop_times_map.values_mut() {
+            times.sort_by(|a, b| a.arrival_time.cmp(&b.arrival_time));
+        }
+
+        if quality.stop_times_rejected + quality.times_rejected > 0 {
+            println!("   ⚠️  stop_times.txt: {} rows dropped, {} times rejected", quality.stop_times_rejected, quality.times_rejected);
         }
 
-        Ok(stop_times_map)
+        (stop_times_map, quality)
     }
 
-    fn parse_trips_info(archive: &mut ZipArchive<Cursor<bytes::Bytes>>) -> Result<HashMap<String, Trip>> {
+    fn parse_trips_info(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<HashMap<String, Trip>> {
         let mut trips_map: HashMap<String, Trip> = HashMap::new();
 
         if let Ok(mut trips_file) = archive.by_name("trips.txt") {
@@ -972,6 +2776,11 @@ impl NVTModels {
 
             let mut rdr = csv::Reader::from_reader(contents.as_bytes());
 
+            // Optional columns, looked up by header
+            let headers = rdr.headers().cloned().unwrap_or_default();
+            let wheelchair_index = Self::header_index(&headers, "wheelchair_accessible");
+            let bikes_index = Self::header_index(&headers, "bikes_allowed");
+
             for result in rdr.records() {
                 if let Ok(record) = result {
                     // route_id,service_id,trip_id,trip_headsign,direction_id,block_id,shape_id,wheelchair_accessible,bikes_allowed
@@ -983,6 +2792,8 @@ impl NVTModels {
                             service_id: service_id.to_string(),
                             trip_headsign: record.get(3).map(|s| s.to_string()).filter(|s| !s.is_empty()),
                             direction_id: record.get(4).and_then(|s| s.parse::<u32>().ok()),
+                            wheelchair_accessible: Self::accessibility_flag(wheelchair_index.and_then(|index| record.get(index))),
+                            bikes_allowed: Self::accessibility_flag(bikes_index.and_then(|index| record.get(index))),
                         };
 
                         trips_map.insert(trip_id.to_string(), trip);
@@ -994,7 +2805,103 @@ impl NVTModels {
         Ok(trips_map)
     }
 
-    fn parse_calendar(archive: &mut ZipArchive<Cursor<bytes::Bytes>>) -> Result<HashMap<String, ServiceCalendar>> {
+    fn header_index(headers: &csv::StringRecord, name: &str) -> Option<usize> {
+        headers.iter().position(|header| header.trim_start_matches('\u{feff}') == name)
+    }
+
+    /// GTFS accessibility value: 1 yes, 2 no, 0 or empty unknown
+    fn accessibility_flag(value: Option<&str>) -> Option<bool> {
+        match value?.trim() {
+            "1" => Some(true),
+            "2" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Wheelchair boarding and levels of stops.txt, levels.txt and pathways.txt. A stop without
+    /// a wheelchair_boarding of its own takes that of its parent station.
+    fn parse_accessibility(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Accessibility {
+        let mut accessibility = Accessibility::default();
+        let mut read = |name: &str| -> Option<String> {
+            let mut file = archive.by_name(name).ok()?;
+            let mut contents = String::new();
+            file.read_to_string(&mut contents).ok()?;
+            Some(contents)
+        };
+
+        if let Some(contents) = read("stops.txt") {
+            let mut rdr = csv::Reader::from_reader(contents.as_bytes());
+            let headers = rdr.headers().cloned().unwrap_or_default();
+            let column = |name: &str| Self::header_index(&headers, name);
+            let (id_index, wheelchair_index, parent_index, level_index) =
+                (column("stop_id"), column("wheelchair_boarding"), column("parent_station"), column("level_id"));
+
+            let mut parents = Vec::new();
+            for record in rdr.records().flatten() {
+                let Some(stop_id) = id_index.and_then(|index| record.get(index)) else { continue };
+                let field = |index: Option<usize>| index.and_then(|index| record.get(index)).filter(|value| !value.is_empty());
+                if let Some(boarding) = Self::accessibility_flag(field(wheelchair_index)) {
+                    accessibility.wheelchair_boarding.insert(stop_id.to_string(), boarding);
+                }
+                if let Some(parent) = field(parent_index) {
+                    parents.push((stop_id.to_string(), parent.to_string()));
+                }
+                if let Some(level_id) = field(level_index) {
+                    accessibility.stop_levels.insert(stop_id.to_string(), level_id.to_string());
+                }
+            }
+            for (stop_id, parent) in parents {
+                if let Some(&boarding) = accessibility.wheelchair_boarding.get(&parent) {
+                    accessibility.wheelchair_boarding.entry(stop_id).or_insert(boarding);
+                }
+            }
+        }
+
+        if let Some(contents) = read("levels.txt") {
+            let mut rdr = csv::Reader::from_reader(contents.as_bytes());
+            let headers = rdr.headers().cloned().unwrap_or_default();
+            let column = |name: &str| Self::header_index(&headers, name);
+            let (id_index, index_index, name_index) = (column("level_id"), column("level_index"), column("level_name"));
+            for record in rdr.records().flatten() {
+                let field = |index: Option<usize>| index.and_then(|index| record.get(index)).filter(|value| !value.is_empty());
+                let (Some(level_id), Some(level_index)) = (field(id_index), field(index_index).and_then(|value| value.parse().ok())) else { continue };
+                accessibility.levels.insert(level_id.to_string(), Level {
+                    level_id: level_id.to_string(),
+                    level_index,
+                    level_name: field(name_index).map(String::from),
+                });
+            }
+        }
+
+        if let Some(contents) = read("pathways.txt") {
+            let mut rdr = csv::Reader::from_reader(contents.as_bytes());
+            let headers = rdr.headers().cloned().unwrap_or_default();
+            let column = |name: &str| Self::header_index(&headers, name);
+            let indexes = [
+                "pathway_id", "from_stop_id", "to_stop_id", "pathway_mode", "is_bidirectional", "length", "traversal_time", "stair_count",
+            ].map(column);
+            for record in rdr.records().flatten() {
+                let [id, from, to, mode, bidirectional, length, time, stairs] = indexes
+                    .map(|index| index.and_then(|index| record.get(index)).filter(|value| !value.is_empty()));
+                let (Some(pathway_id), Some(from_stop_id), Some(to_stop_id), Some(pathway_mode)) =
+                    (id, from, to, mode.and_then(|mode| mode.parse().ok())) else { continue };
+                accessibility.pathways.push(Pathway {
+                    pathway_id: pathway_id.to_string(),
+                    from_stop_id: from_stop_id.to_string(),
+                    to_stop_id: to_stop_id.to_string(),
+                    pathway_mode,
+                    is_bidirectional: bidirectional == Some("1"),
+                    length: length.and_then(|value| value.parse().ok()),
+                    traversal_time: time.and_then(|value| value.parse().ok()),
+                    stair_count: stairs.and_then(|value| value.parse().ok()),
+                });
+            }
+        }
+
+        accessibility
+    }
+
+    fn parse_calendar(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<HashMap<String, ServiceCalendar>> {
         let mut calendar_map: HashMap<String, ServiceCalendar> = HashMap::new();
 
         if let Ok(mut calendar_file) = archive.by_name("calendar.txt") {
@@ -1032,7 +2939,7 @@ impl NVTModels {
         Ok(calendar_map)
     }
 
-    fn parse_calendar_dates(archive: &mut ZipArchive<Cursor<bytes::Bytes>>) -> Result<HashMap<String, Vec<CalendarDate>>> {
+    fn parse_calendar_dates(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<HashMap<String, Vec<CalendarDate>>> {
         let mut calendar_dates_map: HashMap<String, Vec<CalendarDate>> = HashMap::new();
 
         if let Ok(mut calendar_dates_file) = archive.by_name("calendar_dates.txt") {
@@ -1066,7 +2973,7 @@ impl NVTModels {
         Ok(calendar_dates_map)
     }
 
-    fn parse_transfers(archive: &mut ZipArchive<Cursor<bytes::Bytes>>) -> Result<Vec<Transfer>> {
+    fn parse_transfers(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<Vec<Transfer>> {
         let mut transfers = Vec::new();
 
         if let Ok(mut transfers_file) = archive.by_name("transfers.txt") {
@@ -1085,22 +2992,156 @@ impl NVTModels {
                             let min_transfer_time = record.get(3)
                                 .and_then(|s| s.parse::<u32>().ok());
 
-                            transfers.push(Transfer {
-                                from_stop_id: from_stop_id.to_string(),
-                                to_stop_id: to_stop_id.to_string(),
-                                transfer_type: trans_type,
-                                min_transfer_time,
-                            });
-                        }
+                            transfers.push(Transfer {
+                                from_stop_id: from_stop_id.to_string(),
+                                to_stop_id: to_stop_id.to_string(),
+                                transfer_type: trans_type,
+                                min_transfer_time,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(transfers)
+    }
+
+    /// feed_info.txt, None when the feed has none
+    fn parse_feed_info(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Option<FeedInfo> {
+        let mut feed_info_file = archive.by_name("feed_info.txt").ok()?;
+        let mut contents = String::new();
+        feed_info_file.read_to_string(&mut contents).ok()?;
+        drop(feed_info_file);
+
+        let mut rdr = csv::Reader::from_reader(contents.as_bytes());
+        let headers = rdr.headers().cloned().ok()?;
+        let record = rdr.records().flatten().next()?;
+        let field = |name: &str| Self::header_index(&headers, name)
+            .and_then(|index| record.get(index))
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(String::from);
+        Some(FeedInfo {
+            publisher_name: field("feed_publisher_name").unwrap_or_default(),
+            publisher_url: field("feed_publisher_url").unwrap_or_default(),
+            lang: field("feed_lang"),
+            version: field("feed_version"),
+            start_date: field("feed_start_date"),
+            end_date: field("feed_end_date"),
+        })
+    }
+
+    /// feed_version of a GTFS zip, read without parsing the rest
+    fn zip_feed_version(zip_bytes: &[u8]) -> Option<String> {
+        let mut archive = ZipArchive::new(Cursor::new(zip_bytes)).ok()?;
+        Self::parse_feed_info(&mut archive)?.version
+    }
+
+    /// frequencies.txt windows by trip_id, each with the first departure of its trip's
+    /// stop_times, which the windows shift
+    fn parse_frequencies(archive: &mut ZipArchive<Cursor<&[u8]>>, stop_times: &HashMap<String, Vec<StopTime>>) -> HashMap<String, Vec<Frequency>> {
+        let mut windows: HashMap<String, Vec<(String, String, u32, bool)>> = HashMap::new();
+
+        if let Ok(mut frequencies_file) = archive.by_name("frequencies.txt") {
+            let mut contents = String::new();
+            frequencies_file.read_to_string(&mut contents).ok();
+            drop(frequencies_file);
+
+            let mut rdr = csv::Reader::from_reader(contents.as_bytes());
+
+            for record in rdr.records().flatten() {
+                // trip_id,start_time,end_time,headway_secs,exact_times
+                if let (Some(trip_id), Some(start_time), Some(end_time), Some(headway_secs)) =
+                    (record.get(0), record.get(1), record.get(2), record.get(3).and_then(|s| s.parse::<u32>().ok()))
+                    && headway_secs > 0 {
+                    windows.entry(trip_id.to_string()).or_default().push((
+                        start_time.trim().to_string(),
+                        end_time.trim().to_string(),
+                        headway_secs,
+                        record.get(4) == Some("1"),
+                    ));
+                }
+            }
+        }
+        if windows.is_empty() {
+            return HashMap::new();
+        }
+
+        let mut trip_starts: HashMap<&str, u32> = HashMap::new();
+        for stop_time in stop_times.values().flatten().filter(|stop_time| windows.contains_key(&stop_time.trip_id)) {
+            if let Some(seconds) = Self::parse_gtfs_time(&stop_time.departure_time) {
+                let start = trip_starts.entry(stop_time.trip_id.as_str()).or_insert(seconds);
+                *start = (*start).min(seconds);
+            }
+        }
+
+        windows.into_iter()
+            .filter_map(|(trip_id, windows)| {
+                let trip_start = *trip_starts.get(trip_id.as_str())?;
+                let frequencies = windows.into_iter()
+                    .map(|(start_time, end_time, headway_secs, exact_times)| Frequency { start_time, end_time, headway_secs, exact_times, trip_start })
+                    .collect();
+                Some((trip_id, frequencies))
+            })
+            .collect()
+    }
+
+    fn parse_route_text_colors(archive: &mut ZipArchive<Cursor<&[u8]>>) -> HashMap<String, String> {
+        let mut text_colors = HashMap::new();
+
+        if let Ok(mut routes_file) = archive.by_name("routes.txt") {
+            let mut contents = String::new();
+            routes_file.read_to_string(&mut contents).ok();
+            drop(routes_file);
+
+            let mut rdr = csv::Reader::from_reader(contents.as_bytes());
+
+            // route_text_color is optional and its position varies between feeds, so look it up by header
+            let text_color_index = rdr.headers().ok()
+                .and_then(|h| h.iter().position(|name| name.trim_start_matches('\u{feff}') == "route_text_color"));
+
+            if let Some(index) = text_color_index {
+                for record in rdr.records().flatten() {
+                    if let (Some(route_id), Some(text_color)) = (record.get(0), record.get(index).filter(|c| c.len() == 6)) {
+                        text_colors.insert(route_id.to_string(), text_color.to_string());
+                    }
+                }
+            }
+        }
+
+        text_colors
+    }
+
+    fn parse_route_types(archive: &mut ZipArchive<Cursor<&[u8]>>) -> HashMap<String, u16> {
+        let mut route_types = HashMap::new();
+
+        if let Ok(mut routes_file) = archive.by_name("routes.txt") {
+            let mut contents = String::new();
+            routes_file.read_to_string(&mut contents).ok();
+            drop(routes_file);
+
+            let mut rdr = csv::Reader::from_reader(contents.as_bytes());
+
+            // Looked up by header like route_text_color, some feeds reorder the columns
+            let route_type_index = rdr.headers().ok()
+                .and_then(|h| h.iter().position(|name| name.trim_start_matches('\u{feff}') == "route_type"));
+
+            if let Some(index) = route_type_index {
+                for record in rdr.records().flatten() {
+                    if let (Some(route_id), Some(route_type)) = (record.get(0), record.get(index).and_then(|t| t.trim().parse().ok())) {
+                        route_types.insert(route_id.to_string(), route_type);
                     }
                 }
             }
         }
 
-        Ok(transfers)
+        route_types
     }
 
-    fn parse_transgironde_from_cache(cache: GTFSCache) -> Result<(Vec<Stop>, Vec<Line>, GTFSCache)> {
+    fn parse_transgironde_from_cache(cache: GTFSCache, cache_dir: &Path) -> Result<(Vec<Stop>, Vec<Line>, GTFSCache)> {
+        let cache = map_matching::apply(cache, cache_dir);
+
         // Build a map of stop_id -> set of route_ids that serve this stop
         let mut stop_to_routes: HashMap<String, HashSet<String>> = HashMap::new();
         
@@ -1167,6 +3208,12 @@ impl NVTModels {
                 lines, // Now populated with actual route_ids (unique by nature of HashSet)
                 alerts: Vec::new(),
                 real_time: Vec::new(),
+                commune: None,
+                insee_code: None,
+                amenities: None,
+                wheelchair_boarding: None,
+                level: None,
+                pathways: Vec::new(),
             });
         }
 
@@ -1180,8 +3227,8 @@ impl NVTModels {
             let operator = if let Some(aid) = agency_id {
                 if let Some(agency) = cache.agencies.get(aid) {
                     // Extract short operator name from agency_name
-                    // Format: "Calibus (Libourne)" or "TBM (Bordeaux Métropole)"
-                    agency.agency_name.clone()
+                    // Format: "Calibus (Libourne)" or "TBM (Bordeaux Métropole)", or an alias of it
+                    operators::canonical_name(&agency.agency_name)
                 } else {
                     "New-Aquitaine".to_string()
                 }
@@ -1206,12 +3253,15 @@ impl NVTModels {
             }
             
             // Extract route short name from route_id
-            // Format: "CA_DU_LIBOURNAIS:Line:XXX" -> "XXX"
-            let line_code = route_id.split(':').last().unwrap_or(route_id);
+            // Format: "CA_DU_LIBOURNAIS:Line:XXX" -> "XXX", unless a configured rule says otherwise
+            let line_code = Self::configured_line_code("naq", route_id)
+                .unwrap_or_else(|| route_id.split(':').next_back().unwrap_or(route_id).to_string());
 
             let shape_ids = cache.route_to_shapes.get(route_id)
                 .cloned()
                 .unwrap_or_default();
+            let raw_text_color = cache.route_text_colors.get(route_id).cloned();
+            let (display_color, text_color) = colors::readable_colors(color, raw_text_color.as_deref());
 
             lines.push(Line {
                 line_ref: route_id.clone(),
@@ -1221,9 +3271,14 @@ impl NVTModels {
                 destinations: Vec::new(),
                 alerts: Vec::new(),
                 real_time: Vec::new(),
-                color: color.clone(),
+                color: display_color,
+                text_color,
+                raw_color: color.clone(),
+                raw_text_color,
+                synthetic: shape_ids.iter().any(|id| cache.synthetic_shape_ids.contains(id)),
                 shape_ids,
                 operator,
+                mode: cache.route_types.get(route_id).and_then(|t| TransportMode::from_route_type(*t)),
             });
         }
 
@@ -1234,94 +3289,7 @@ impl NVTModels {
     // SNCF GTFS Loading
     // ============================================================================
 
-    fn load_sncf_data() -> Result<(Vec<Stop>, Vec<Line>, GTFSCache)> {
-        if let Some(cache) = GTFSCache::load("SNCF", 30) {
-            return Self::parse_sncf_from_cache(cache);
-        }
-
-        println!("📥 Downloading SNCF GTFS data...");
-
-        let client = blocking::Client::builder()
-            .timeout(std::time::Duration::from_secs(Self::REQUEST_TIMEOUT_SECS * 3)) // Longer timeout for large file
-            .build()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to create HTTP client: {}", e)))?;
-
-        let response = client.get(Self::SNCF_GTFS_URL)
-            .send()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to download SNCF GTFS: {}", e)))?;
-
-        if !response.status().is_success() {
-            return Err(NVTError::NetworkError(format!("Download failed with status: {}", response.status())));
-        }
-
-        let zip_bytes = response.bytes()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to read GTFS zip: {}", e)))?;
-
-        println!("✓ Downloaded {} MB, extracting...", zip_bytes.len() / 1024 / 1024);
-
-        let cursor = Cursor::new(zip_bytes);
-        let mut archive = ZipArchive::new(cursor)
-            .map_err(|e| NVTError::ParseError(format!("Failed to open GTFS zip: {}", e)))?;
-
-        // Parse routes.txt
-        let routes = Self::parse_sncf_routes(&mut archive)?;
-        println!("   ✓ Parsed {} SNCF routes", routes.len());
-
-        // Parse stops.txt
-        let stops_data = Self::parse_sncf_stops(&mut archive)?;
-        println!("   ✓ Parsed {} SNCF stops", stops_data.len());
-
-        // Parse shapes.txt
-        let shapes = Self::parse_sncf_shapes(&mut archive)?;
-        println!("   ✓ Parsed {} SNCF shapes", shapes.len());
-
-        // Parse trips.txt to map routes to shapes
-        let route_to_shapes = Self::parse_sncf_trips(&mut archive)?;
-        println!("   ✓ Mapped {} routes to shapes", route_to_shapes.len());
-
-        // Parse stop_times.txt for schedule predictions
-        let stop_times = Self::parse_stop_times(&mut archive)?;
-        println!("   ✓ Parsed {} stop time entries", stop_times.values().map(|v| v.len()).sum::<usize>());
-
-        // Parse trips.txt for trip information
-        let trips = Self::parse_trips_info(&mut archive)?;
-        println!("   ✓ Parsed {} trips", trips.len());
-
-        // Parse calendar.txt for service schedules
-        let calendar = Self::parse_calendar(&mut archive)?;
-        println!("   ✓ Parsed {} calendar services", calendar.len());
-
-        // Parse calendar_dates.txt for exceptions
-        let calendar_dates = Self::parse_calendar_dates(&mut archive)?;
-        println!("   ✓ Parsed {} calendar date exceptions", calendar_dates.values().map(|v| v.len()).sum::<usize>());
-
-        let gtfs_cache = GTFSCache {
-            routes,
-            stops: stops_data.clone(),
-            shapes: shapes.clone(),
-            route_to_shapes: route_to_shapes.clone(),
-            stop_times,
-            trips,
-            calendar,
-            calendar_dates,
-            agencies: HashMap::new(),
-            route_agencies: HashMap::new(),
-            transfers: Vec::new(),
-            cached_at: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs(),
-            source: "SNCF".to_string(),
-        };
-
-        if let Err(e) = gtfs_cache.save() {
-            eprintln!("⚠️  Warning: Could not save SNCF cache: {}", e);
-        }
-
-        Self::parse_sncf_from_cache(gtfs_cache)
-    }
-
-    fn parse_sncf_routes(archive: &mut ZipArchive<Cursor<bytes::Bytes>>) -> Result<HashMap<String, String>> {
+    fn parse_sncf_routes(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<HashMap<String, String>> {
         let mut routes_file = archive.by_name("routes.txt")
             .map_err(|e| NVTError::FileError(format!("routes.txt not found: {}", e)))?;
 
@@ -1348,7 +3316,7 @@ impl NVTModels {
         Ok(color_map)
     }
 
-    fn extract_sncf_stop_id(full_id: &str) -> Option<String> {
+    pub fn extract_sncf_stop_id(full_id: &str) -> Option<String> {
         // SNCF stop_id format: "StopPoint:OCETGV INOUI-87192039" -> "87192039"
         // or "StopPoint:OCETrain TER-71793150" -> "71793150"
         if let Some(dash_pos) = full_id.rfind('-') {
@@ -1358,7 +3326,7 @@ impl NVTModels {
         }
     }
 
-    fn parse_sncf_stops(archive: &mut ZipArchive<Cursor<bytes::Bytes>>) -> Result<Vec<(String, String, f64, f64)>> {
+    fn parse_sncf_stops(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<Vec<(String, String, f64, f64)>> {
         let mut stops_file = archive.by_name("stops.txt")
             .map_err(|e| NVTError::FileError(format!("stops.txt not found: {}", e)))?;
 
@@ -1405,7 +3373,7 @@ impl NVTModels {
         Ok(stops_data)
     }
 
-    fn parse_sncf_shapes(archive: &mut ZipArchive<Cursor<bytes::Bytes>>) -> Result<HashMap<String, Vec<ShapePoint>>> {
+    fn parse_sncf_shapes(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<HashMap<String, Vec<ShapePoint>>> {
         let mut shapes_map: HashMap<String, Vec<ShapePoint>> = HashMap::new();
 
         if let Ok(mut shapes_file) = archive.by_name("shapes.txt") {
@@ -1442,7 +3410,7 @@ impl NVTModels {
         Ok(shapes_map)
     }
 
-    fn parse_sncf_trips(archive: &mut ZipArchive<Cursor<bytes::Bytes>>) -> Result<HashMap<String, Vec<String>>> {
+    fn parse_sncf_trips(archive: &mut ZipArchive<Cursor<&[u8]>>) -> Result<HashMap<String, Vec<String>>> {
         let mut route_to_shapes: HashMap<String, Vec<String>> = HashMap::new();
 
         if let Ok(mut trips_file) = archive.by_name("trips.txt") {
@@ -1474,7 +3442,9 @@ impl NVTModels {
         Ok(route_to_shapes)
     }
 
-    fn parse_sncf_from_cache(cache: GTFSCache) -> Result<(Vec<Stop>, Vec<Line>, GTFSCache)> {
+    fn parse_sncf_from_cache(cache: GTFSCache, cache_dir: &Path) -> Result<(Vec<Stop>, Vec<Line>, GTFSCache)> {
+        let cache = map_matching::apply(cache, cache_dir);
+
         // Build a map of stop_id -> set of route_ids that serve this stop
         let mut stop_to_routes: HashMap<String, HashSet<String>> = HashMap::new();
         
@@ -1505,6 +3475,12 @@ impl NVTModels {
                 lines, // Now populated with actual route_ids (unique by nature of HashSet)
                 alerts: Vec::new(),
                 real_time: Vec::new(),
+                commune: None,
+                insee_code: None,
+                amenities: None,
+                wheelchair_boarding: None,
+                level: None,
+                pathways: Vec::new(),
             });
         }
 
@@ -1512,11 +3488,14 @@ impl NVTModels {
         let mut lines = Vec::new();
         for (route_id, color) in &cache.routes {
             // Extract route short name from route_id for display
-            let line_code = route_id.split(':').last().unwrap_or(route_id);
+            let line_code = Self::configured_line_code("sncf", route_id)
+                .unwrap_or_else(|| route_id.split(':').next_back().unwrap_or(route_id).to_string());
 
             let shape_ids = cache.route_to_shapes.get(route_id)
                 .cloned()
                 .unwrap_or_default();
+            let raw_text_color = cache.route_text_colors.get(route_id).cloned();
+            let (display_color, text_color) = colors::readable_colors(color, raw_text_color.as_deref());
 
             lines.push(Line {
                 line_ref: route_id.clone(),
@@ -1526,9 +3505,14 @@ impl NVTModels {
                 destinations: Vec::new(),
                 alerts: Vec::new(),
                 real_time: Vec::new(),
-                color: color.clone(),
+                color: display_color,
+                text_color,
+                raw_color: color.clone(),
+                raw_text_color,
+                synthetic: shape_ids.iter().any(|id| cache.synthetic_shape_ids.contains(id)),
                 shape_ids,
                 operator: "SNCF".to_string(),
+                mode: cache.route_types.get(route_id).and_then(|t| TransportMode::from_route_type(*t)),
             });
         }
 
@@ -1539,10 +3523,10 @@ impl NVTModels {
     // TBM Data Fetching (existing methods)
     // ============================================================================
 
-    fn fetch_stops() -> Result<Vec<(String, String, f64, f64, Vec<String>)>> {
+    fn fetch_stops(upstreams: &Upstreams) -> Result<Vec<TbmStopMetadata>> {
         let url = format!(
             "{}/siri/2.0/bordeaux/stoppoints-discovery.json?AccountKey={}",
-            Self::BASE_URL,
+            upstreams.tbm_api_url,
             Self::API_KEY
         );
 
@@ -1559,10 +3543,153 @@ impl NVTModels {
             return Err(NVTError::NetworkError(format!("API returned error: {}", response.status())));
         }
 
-        let body = response.text()
+        let body = response.bytes()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to read response: {}", e)))?;
+
+        Self::parse_stop_points(&body)
+    }
+
+    fn fetch_lines(upstreams: &Upstreams) -> Result<Vec<TbmLineMetadata>> {
+        let url = format!(
+            "{}/siri/2.0/bordeaux/lines-discovery.json?AccountKey={}",
+            upstreams.tbm_api_url,
+            Self::API_KEY
+        );
+
+        let client = blocking::Client::builder()
+            .timeout(std::time::Duration::from_secs(Self::REQUEST_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to create HTTP client: {}", e)))?;
+
+        let response = client.get(&url)
+            .send()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to fetch lines: {}", e)))?;
+
+        if !response.status().is_success() {
+            return Err(NVTError::NetworkError(format!("API returned error: {}", response.status())));
+        }
+
+        let body = response.bytes()
             .map_err(|e| NVTError::NetworkError(format!("Failed to read response: {}", e)))?;
 
-        let json: serde_json::Value = serde_json::from_str(&body)
+        Self::parse_lines_discovery(&body)
+    }
+
+    fn create_http_client() -> Result<blocking::Client> {
+        blocking::Client::builder()
+            .timeout(std::time::Duration::from_secs(Self::REQUEST_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to create HTTP client: {}", e)))
+    }
+
+    fn fetch_alerts(upstreams: &Upstreams) -> Result<Vec<AlertInfo>> {
+        let url = format!(
+            "{}/gtfsfeed/alerts/bordeaux?apiKey={}",
+            upstreams.tbm_api_url,
+            Self::API_KEY
+        );
+
+        let client = Self::create_http_client()?;
+
+        let response = client.get(&url)
+            .send()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to fetch alerts: {}", e)))?;
+
+        let body = response.bytes()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to read alerts response: {}", e)))?;
+
+        Self::parse_alerts_feed(&body)
+    }
+
+    fn fetch_vehicle_positions(upstreams: &Upstreams) -> Result<Vec<RealTimeInfo>> {
+        let url = format!(
+            "{}/gtfsfeed/vehicles/bordeaux?apiKey={}",
+            upstreams.tbm_api_url,
+            Self::API_KEY
+        );
+
+        let client = Self::create_http_client()?;
+
+        let response = client.get(&url)
+            .send()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to fetch vehicle positions: {}", e)))?;
+
+        let body = response.bytes()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to read vehicles response: {}", e)))?;
+
+        let mut real_time = Self::parse_vehicle_positions(&body)?;
+        for vehicle in &mut real_time {
+            vehicle.source = Some("TBM".to_string());
+        }
+        Ok(real_time)
+    }
+
+    fn fetch_trip_updates(upstreams: &Upstreams) -> Result<Vec<gtfs_rt::TripUpdate>> {
+        let url = format!(
+            "{}/gtfsfeed/realtime/bordeaux?apiKey={}",
+            upstreams.tbm_api_url,
+            Self::API_KEY
+        );
+
+        let client = Self::create_http_client()?;
+
+        let response = client.get(&url)
+            .send()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to fetch trip updates: {}", e)))?;
+
+        let body = response.bytes()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to read trip updates response: {}", e)))?;
+
+        Self::parse_trip_updates(&body)
+    }
+
+    /// Alerts and trip updates of a GTFS-RT feed of `feed`, with its id prefix
+    fn fetch_gtfs_rt(feed: &config::GtfsFeedConfig, url: &str) -> Result<(Vec<AlertInfo>, Vec<gtfs_rt::TripUpdate>, Vec<RealTimeInfo>)> {
+        let client = Self::create_http_client()?;
+
+        let response = client.get(url)
+            .send()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to fetch {} GTFS-RT: {}", feed.name, e)))?;
+
+        if !response.status().is_success() {
+            return Err(NVTError::NetworkError(format!("{} GTFS-RT request failed with status: {}", feed.name, response.status())));
+        }
+
+        let body = response.bytes()
+            .map_err(|e| NVTError::NetworkError(format!("Failed to read {} GTFS-RT response: {}", feed.name, e)))?;
+
+        let mut alerts = Self::parse_alerts_feed(&body)?;
+        let mut trip_updates = Self::parse_trip_updates(&body)?;
+        let mut vehicles = Self::parse_vehicle_positions(&body)?;
+        for vehicle in &mut vehicles {
+            vehicle.source = Some(feed.name.clone());
+        }
+        if let Some(prefix) = &feed.id_prefix {
+            let prefixed = |id: &mut String| id.insert_str(0, prefix);
+            for alert in &mut alerts {
+                alert.route_ids.iter_mut().chain(&mut alert.stop_ids).for_each(prefixed);
+            }
+            for update in &mut trip_updates {
+                update.trip.trip_id.iter_mut().chain(&mut update.trip.route_id).for_each(prefixed);
+                update.stop_time_update.iter_mut().filter_map(|stop_time| stop_time.stop_id.as_mut()).for_each(prefixed);
+            }
+            for vehicle in &mut vehicles {
+                [&mut vehicle.vehicle_id, &mut vehicle.trip_id].into_iter()
+                    .chain(&mut vehicle.route_id)
+                    .chain(&mut vehicle.stop_id)
+                    .for_each(prefixed);
+            }
+        }
+        Ok((alerts, trip_updates, vehicles))
+    }
+
+    // ============================================================================
+    // Parsers (upstream bytes in, no network or disk; the fuzz targets call these)
+    // ============================================================================
+
+    /// SIRI-Lite stoppoints-discovery response
+    pub fn parse_stop_points(body: &[u8]) -> Result<Vec<TbmStopMetadata>> {
+        let json: serde_json::Value = serde_json::from_slice(body)
             .map_err(|e| NVTError::ParseError(format!("Invalid JSON response: {}", e)))?;
 
         let stop_points = json["Siri"]["StopPointsDelivery"]["AnnotatedStopPointRef"]
@@ -1597,30 +3724,9 @@ impl NVTModels {
         Ok(stops)
     }
 
-    fn fetch_lines() -> Result<Vec<(String, String, String, Vec<(String, String)>)>> {
-        let url = format!(
-            "{}/siri/2.0/bordeaux/lines-discovery.json?AccountKey={}",
-            Self::BASE_URL,
-            Self::API_KEY
-        );
-
-        let client = blocking::Client::builder()
-            .timeout(std::time::Duration::from_secs(Self::REQUEST_TIMEOUT_SECS))
-            .build()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to create HTTP client: {}", e)))?;
-
-        let response = client.get(&url)
-            .send()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to fetch lines: {}", e)))?;
-
-        if !response.status().is_success() {
-            return Err(NVTError::NetworkError(format!("API returned error: {}", response.status())));
-        }
-
-        let body = response.text()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to read response: {}", e)))?;
-
-        let json: serde_json::Value = serde_json::from_str(&body)
+    /// SIRI-Lite lines-discovery response
+    pub fn parse_lines_discovery(body: &[u8]) -> Result<Vec<TbmLineMetadata>> {
+        let json: serde_json::Value = serde_json::from_slice(body)
             .map_err(|e| NVTError::ParseError(format!("Invalid JSON response: {}", e)))?;
 
         let line_refs = json["Siri"]["LinesDelivery"]["AnnotatedLineRef"]
@@ -1657,30 +3763,9 @@ impl NVTModels {
         Ok(lines)
     }
 
-    fn create_http_client() -> Result<blocking::Client> {
-        blocking::Client::builder()
-            .timeout(std::time::Duration::from_secs(Self::REQUEST_TIMEOUT_SECS))
-            .build()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to create HTTP client: {}", e)))
-    }
-
-    fn fetch_alerts() -> Result<Vec<AlertInfo>> {
-        let url = format!(
-            "{}/gtfsfeed/alerts/bordeaux?apiKey={}",
-            Self::BASE_URL,
-            Self::API_KEY
-        );
-
-        let client = Self::create_http_client()?;
-
-        let response = client.get(&url)
-            .send()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to fetch alerts: {}", e)))?;
-
-        let body = response.bytes()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to read alerts response: {}", e)))?;
-
-        let feed = FeedMessage::decode(&*body)
+    /// Service alerts of a GTFS-RT feed (TBM or SNCF)
+    pub fn parse_alerts_feed(body: &[u8]) -> Result<Vec<AlertInfo>> {
+        let feed = FeedMessage::decode(body)
             .map_err(|e| NVTError::ParseError(format!("Failed to decode alerts feed: {}", e)))?;
 
         let alerts = feed
@@ -1688,6 +3773,33 @@ impl NVTModels {
             .into_iter()
             .filter_map(|entity| {
                 entity.alert.map(|alert| {
+                    // Texts by language, in the order the feed first gives each language
+                    let mut translations: Vec<AlertTranslation> = Vec::new();
+                    let texts = [&alert.header_text, &alert.description_text, &alert.url];
+                    for (field, text) in texts.into_iter().enumerate() {
+                        for translation in text.iter().flat_map(|t| &t.translation) {
+                            let Some(language) = translation.language.as_deref().filter(|l| !l.is_empty()) else { continue };
+                            let index = match translations.iter().position(|t| t.language.eq_ignore_ascii_case(language)) {
+                                Some(index) => index,
+                                None => {
+                                    translations.push(AlertTranslation {
+                                        language: language.to_string(),
+                                        text: None,
+                                        description: None,
+                                        url: None,
+                                    });
+                                    translations.len() - 1
+                                }
+                            };
+                            let slot = match field {
+                                0 => &mut translations[index].text,
+                                1 => &mut translations[index].description,
+                                _ => &mut translations[index].url,
+                            };
+                            slot.get_or_insert_with(|| translation.text.clone());
+                        }
+                    }
+
                     let header_text = alert
                         .header_text
                         .and_then(|h| h.translation.first().map(|t| t.text.clone()))
@@ -1714,15 +3826,16 @@ impl NVTModels {
                         }
                     }
 
-                    let (start, end) = alert.active_period
-                        .first()
+                    let active_periods: Vec<(Option<i64>, Option<i64>)> = alert.active_period
+                        .iter()
                         .map(|period| {
                             (
                                 period.start.map(|s| s as i64),
                                 period.end.map(|e| e as i64)
                             )
                         })
-                        .unwrap_or((None, None));
+                        .collect();
+                    let (start, end) = active_periods.first().copied().unwrap_or((None, None));
 
                     let severity = alert.severity_level.unwrap_or(0) as u32;
 
@@ -1732,35 +3845,26 @@ impl NVTModels {
                         description: description_text,
                         url,
                         route_ids,
-                        stop_ids,
-                        active_period_start: start,
-                        active_period_end: end,
-                        severity,
-                    }
-                })
-            })
-            .collect();
-
-        Ok(alerts)
-    }
-
-    fn fetch_vehicle_positions() -> Result<Vec<RealTimeInfo>> {
-        let url = format!(
-            "{}/gtfsfeed/vehicles/bordeaux?apiKey={}",
-            Self::BASE_URL,
-            Self::API_KEY
-        );
-
-        let client = Self::create_http_client()?;
-
-        let response = client.get(&url)
-            .send()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to fetch vehicle positions: {}", e)))?;
+                        stop_ids,
+                        active_period_start: start,
+                        active_period_end: end,
+                        severity,
+                        active_periods,
+                        affected_stops_count: 0,
+                        affected_stop_ids: None,
+                        derived: false,
+                        translations,
+                    }
+                })
+            })
+            .collect();
 
-        let body = response.bytes()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to read vehicles response: {}", e)))?;
+        Ok(alerts)
+    }
 
-        let feed = FeedMessage::decode(&*body)
+    /// Vehicle positions of a GTFS-RT feed
+    pub fn parse_vehicle_positions(body: &[u8]) -> Result<Vec<RealTimeInfo>> {
+        let feed = FeedMessage::decode(body)
             .map_err(|e| NVTError::ParseError(format!("Failed to decode vehicles feed: {}", e)))?;
 
         let real_time: Vec<RealTimeInfo> = feed
@@ -1790,6 +3894,11 @@ impl NVTModels {
                         .as_ref()
                         .and_then(|t| t.direction_id);
 
+                    let start_date = vehicle
+                        .trip
+                        .as_ref()
+                        .and_then(|t| t.start_date.clone());
+
                     let destination = vehicle
                         .vehicle
                         .as_ref()
@@ -1804,6 +3913,11 @@ impl NVTModels {
                     let stop_id = vehicle.stop_id.clone();
                     let current_stop_sequence = vehicle.current_stop_sequence;
                     let timestamp = vehicle.timestamp.map(|ts| ts as i64);
+                    let bearing = vehicle.position.as_ref().and_then(|p| p.bearing);
+                    let occupancy = vehicle.occupancy_status
+                        .and_then(OccupancyStatus::from_i32)
+                        .filter(|status| *status != OccupancyStatus::NoDataAvailable)
+                        .map(|status| status.as_str_name().to_string());
 
                     RealTimeInfo {
                         vehicle_id,
@@ -1817,6 +3931,10 @@ impl NVTModels {
                         current_stop_sequence,
                         timestamp,
                         delay: None,
+                        bearing,
+                        occupancy,
+                        source: None,
+                        start_date,
                     }
                 })
             })
@@ -1825,23 +3943,9 @@ impl NVTModels {
         Ok(real_time)
     }
 
-    fn fetch_trip_updates() -> Result<Vec<gtfs_rt::TripUpdate>> {
-        let url = format!(
-            "{}/gtfsfeed/realtime/bordeaux?apiKey={}",
-            Self::BASE_URL,
-            Self::API_KEY
-        );
-
-        let client = Self::create_http_client()?;
-
-        let response = client.get(&url)
-            .send()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to fetch trip updates: {}", e)))?;
-
-        let body = response.bytes()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to read trip updates response: {}", e)))?;
-
-        let feed = FeedMessage::decode(&*body)
+    /// Trip updates of a GTFS-RT feed (TBM or SNCF)
+    pub fn parse_trip_updates(body: &[u8]) -> Result<Vec<gtfs_rt::TripUpdate>> {
+        let feed = FeedMessage::decode(body)
             .map_err(|e| NVTError::ParseError(format!("Failed to decode trip updates feed: {}", e)))?;
 
         let updates = feed
@@ -1853,138 +3957,9 @@ impl NVTModels {
         Ok(updates)
     }
 
-    fn fetch_sncf_trip_updates() -> Result<Vec<gtfs_rt::TripUpdate>> {
-        let client = Self::create_http_client()?;
-
-        let response = client.get(Self::SNCF_GTFS_RT_TRIP_UPDATES_URL)
-            .send()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to fetch SNCF trip updates: {}", e)))?;
-
-        if !response.status().is_success() {
-            return Err(NVTError::NetworkError(format!("SNCF trip updates request failed with status: {}", response.status())));
-        }
-
-        let body = response.bytes()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to read SNCF trip updates response: {}", e)))?;
-
-        let feed = FeedMessage::decode(&*body)
-            .map_err(|e| NVTError::ParseError(format!("Failed to decode SNCF trip updates feed: {}", e)))?;
-
-        let updates = feed
-            .entity
-            .into_iter()
-            .filter_map(|entity| entity.trip_update)
-            .collect();
-
-        Ok(updates)
-    }
-
-    fn fetch_sncf_alerts() -> Result<Vec<AlertInfo>> {
-        let client = Self::create_http_client()?;
-
-        let response = client.get(Self::SNCF_GTFS_RT_SERVICE_ALERTS_URL)
-            .send()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to fetch SNCF alerts: {}", e)))?;
-
-        if !response.status().is_success() {
-            return Err(NVTError::NetworkError(format!("SNCF alerts request failed with status: {}", response.status())));
-        }
-
-        let body = response.bytes()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to read SNCF alerts response: {}", e)))?;
-
-        let feed = FeedMessage::decode(&*body)
-            .map_err(|e| NVTError::ParseError(format!("Failed to decode SNCF alerts feed: {}", e)))?;
-
-        let alerts = feed
-            .entity
-            .into_iter()
-            .filter_map(|entity| {
-                entity.alert.map(|alert| {
-                    let header_text = alert
-                        .header_text
-                        .and_then(|h| h.translation.first().map(|t| t.text.clone()))
-                        .unwrap_or_else(|| "No title".to_string());
-
-                    let description_text = alert
-                        .description_text
-                        .and_then(|d| d.translation.first().map(|t| t.text.clone()))
-                        .unwrap_or_else(|| "No description available".to_string());
-
-                    let url = alert
-                        .url
-                        .and_then(|u| u.translation.first().map(|t| t.text.clone()));
-
-                    let mut route_ids = Vec::new();
-                    let mut stop_ids = Vec::new();
-
-                    for informed_entity in alert.informed_entity {
-                        if let Some(route_id) = informed_entity.route_id {
-                            route_ids.push(route_id);
-                        }
-                        if let Some(stop_id) = informed_entity.stop_id {
-                            stop_ids.push(stop_id);
-                        }
-                    }
-
-                    let (start, end) = alert.active_period
-                        .first()
-                        .map(|period| {
-                            (
-                                period.start.map(|s| s as i64),
-                                period.end.map(|e| e as i64)
-                            )
-                        })
-                        .unwrap_or((None, None));
-
-                    let severity = alert.severity_level.unwrap_or(0) as u32;
-
-                    AlertInfo {
-                        id: entity.id,
-                        text: header_text,
-                        description: description_text,
-                        url,
-                        route_ids,
-                        stop_ids,
-                        active_period_start: start,
-                        active_period_end: end,
-                        severity,
-                    }
-                })
-            })
-            .collect();
-
-        Ok(alerts)
-    }
-
-    fn download_and_read_gtfs() -> Result<GTFSCache> {
-        if let Some(cache) = GTFSCache::load("TBM", 15) {
-            return Ok(cache);
-        }
-
-        println!("📥 Downloading fresh TBM GTFS data...");
-        let gtfs_url = "https://transport.data.gouv.fr/resources/83024/download";
-
-        let client = blocking::Client::builder()
-            .timeout(std::time::Duration::from_secs(60))
-            .build()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to create HTTP client: {}", e)))?;
-
-        let response = client.get(gtfs_url)
-            .send()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to download GTFS: {}", e)))?;
-
-        if !response.status().is_success() {
-            return Err(NVTError::NetworkError(format!("GTFS download failed with status: {}", response.status())));
-        }
-
-        let zip_bytes = response.bytes()
-            .map_err(|e| NVTError::NetworkError(format!("Failed to read GTFS zip: {}", e)))?;
-
-        println!("✓ Downloaded {} KB, extracting...", zip_bytes.len() / 1024);
-
-        let cursor = Cursor::new(zip_bytes);
-        let mut archive = ZipArchive::new(cursor)
+    /// TBM GTFS zip archive
+    pub fn parse_tbm_gtfs(zip_bytes: &[u8]) -> Result<GTFSCache> {
+        let mut archive = ZipArchive::new(Cursor::new(zip_bytes))
             .map_err(|e| NVTError::ParseError(format!("Failed to open GTFS zip archive: {}", e)))?;
 
         let mut routes_file = archive.by_name("routes.txt")
@@ -2078,52 +4053,199 @@ impl NVTModels {
             println!("✓ Mapped {} routes to shapes", route_to_shapes.len());
         }
 
-        let mut stops_data = Vec::new();
-        if let Ok(mut stops_file) = archive.by_name("stops.txt") {
-            let mut contents = String::new();
-            stops_file.read_to_string(&mut contents).ok();
-            drop(stops_file);
+        let mut stops_data = Vec::new();
+        if let Ok(mut stops_file) = archive.by_name("stops.txt") {
+            let mut contents = String::new();
+            stops_file.read_to_string(&mut contents).ok();
+            drop(stops_file);
+
+            let mut stops_rdr = csv::Reader::from_reader(contents.as_bytes());
+
+            for result in stops_rdr.records() {
+                if let Ok(record) = result {
+                    if let (Some(stop_id), Some(stop_name), Some(lat_str), Some(lon_str)) =
+                        (record.get(0), record.get(2), record.get(4), record.get(5)) {
+                        if let (Ok(lat), Ok(lon)) = (lat_str.parse::<f64>(), lon_str.parse::<f64>()) {
+                            stops_data.push((
+                                stop_id.to_string(),
+                                stop_name.to_string(),
+                                lat,
+                                lon,
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        // Parse stop_times.txt for schedule predictions
+        let (stop_times, quality) = Self::parse_stop_times(&mut archive)?;
+        let frequencies = Self::parse_frequencies(&mut archive, &stop_times);
+        println!("✓ Parsed {} stop time entries", stop_times.values().map(|v| v.len()).sum::<usize>());
+
+        // Parse trips.txt for trip information
+        let trips = Self::parse_trips_info(&mut archive)?;
+        println!("✓ Parsed {} trips", trips.len());
+
+        // Parse calendar.txt for service schedules
+        let calendar = Self::parse_calendar(&mut archive)?;
+        println!("✓ Parsed {} calendar services", calendar.len());
+
+        // Parse calendar_dates.txt for exceptions
+        let calendar_dates = Self::parse_calendar_dates(&mut archive)?;
+        println!("✓ Parsed {} calendar date exceptions", calendar_dates.values().map(|v| v.len()).sum::<usize>());
+
+        Ok(GTFSCache {
+            routes: color_map.clone(),
+            route_text_colors: Self::parse_route_text_colors(&mut archive),
+            route_types: Self::parse_route_types(&mut archive),
+            stops: stops_data,
+            shapes: shapes_map,
+            route_to_shapes,
+            stop_times,
+            trips,
+            calendar,
+            calendar_dates,
+            agencies: HashMap::new(),
+            route_agencies: HashMap::new(),
+            transfers: Vec::new(),
+            synthetic_shape_ids: HashSet::new(),
+            map_matched: false,
+            quality,
+            accessibility: Self::parse_accessibility(&mut archive),
+            frequencies,
+            feed_info: Self::parse_feed_info(&mut archive),
+            etag: None,
+            last_modified: None,
+            cached_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            source: "TBM".to_string(),
+        })
+    }
+
+    /// New-Aquitaine aggregated GTFS zip archive
+    pub fn parse_naq_gtfs(zip_bytes: &[u8]) -> Result<GTFSCache> {
+        let mut archive = ZipArchive::new(Cursor::new(zip_bytes))
+            .map_err(|e| NVTError::ParseError(format!("Failed to open GTFS zip: {}", e)))?;
+
+        // Parse agency.txt first to get operator information
+        let agencies = Self::parse_agencies(&mut archive)?;
+        println!("   ✓ Parsed {} agencies", agencies.len());
+
+        // Parse routes.txt with agency_id
+        let (routes, route_agencies) = Self::parse_transgironde_routes(&mut archive)?;
+        println!("   ✓ Parsed {} New-Aquitaine routes", routes.len());
+
+        // Parse stops.txt
+        let stops_data = Self::parse_transgironde_stops(&mut archive)?;
+        println!("   ✓ Parsed {} New-Aquitaine stops", stops_data.len());
+
+        // Parse shapes.txt
+        let shapes = Self::parse_transgironde_shapes(&mut archive)?;
+        println!("   ✓ Parsed {} New-Aquitaine shapes", shapes.len());
+
+        // Parse trips.txt to map routes to shapes
+        let route_to_shapes = Self::parse_transgironde_trips(&mut archive)?;
+        println!("   ✓ Mapped {} routes to shapes", route_to_shapes.len());
+
+        // Parse stop_times.txt for schedule predictions
+        let (stop_times, quality) = Self::parse_stop_times(&mut archive)?;
+        let frequencies = Self::parse_frequencies(&mut archive, &stop_times);
+        println!("   ✓ Parsed {} stop time entries", stop_times.values().map(|v| v.len()).sum::<usize>());
+
+        // Parse trips.txt for trip information
+        let trips = Self::parse_trips_info(&mut archive)?;
+        println!("   ✓ Parsed {} trips", trips.len());
+
+        // Parse calendar.txt for service schedules
+        let calendar = Self::parse_calendar(&mut archive)?;
+        println!("   ✓ Parsed {} calendar services", calendar.len());
+
+        // Parse calendar_dates.txt for exceptions
+        let calendar_dates = Self::parse_calendar_dates(&mut archive)?;
+        println!("   ✓ Parsed {} calendar date exceptions", calendar_dates.values().map(|v| v.len()).sum::<usize>());
+
+        // Parse transfers.txt
+        let transfers = Self::parse_transfers(&mut archive)?;
+        println!("   ✓ Parsed {} transfers", transfers.len());
+
+        Ok(GTFSCache {
+            routes,
+            route_text_colors: Self::parse_route_text_colors(&mut archive),
+            route_types: Self::parse_route_types(&mut archive),
+            stops: stops_data.clone(),
+            shapes: shapes.clone(),
+            route_to_shapes: route_to_shapes.clone(),
+            stop_times,
+            trips,
+            calendar,
+            calendar_dates,
+            agencies,
+            route_agencies,
+            transfers,
+            synthetic_shape_ids: HashSet::new(),
+            map_matched: false,
+            quality,
+            accessibility: Self::parse_accessibility(&mut archive),
+            frequencies,
+            feed_info: Self::parse_feed_info(&mut archive),
+            etag: None,
+            last_modified: None,
+            cached_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            source: "NewAquitaine".to_string(),
+        })
+    }
+
+    /// SNCF GTFS zip archive
+    pub fn parse_sncf_gtfs(zip_bytes: &[u8]) -> Result<GTFSCache> {
+        let mut archive = ZipArchive::new(Cursor::new(zip_bytes))
+            .map_err(|e| NVTError::ParseError(format!("Failed to open GTFS zip: {}", e)))?;
+
+        // Parse routes.txt
+        let routes = Self::parse_sncf_routes(&mut archive)?;
+        println!("   ✓ Parsed {} SNCF routes", routes.len());
+
+        // Parse stops.txt
+        let stops_data = Self::parse_sncf_stops(&mut archive)?;
+        println!("   ✓ Parsed {} SNCF stops", stops_data.len());
 
-            let mut stops_rdr = csv::Reader::from_reader(contents.as_bytes());
+        // Parse shapes.txt
+        let shapes = Self::parse_sncf_shapes(&mut archive)?;
+        println!("   ✓ Parsed {} SNCF shapes", shapes.len());
 
-            for result in stops_rdr.records() {
-                if let Ok(record) = result {
-                    if let (Some(stop_id), Some(stop_name), Some(lat_str), Some(lon_str)) =
-                        (record.get(0), record.get(2), record.get(4), record.get(5)) {
-                        if let (Ok(lat), Ok(lon)) = (lat_str.parse::<f64>(), lon_str.parse::<f64>()) {
-                            stops_data.push((
-                                stop_id.to_string(),
-                                stop_name.to_string(),
-                                lat,
-                                lon,
-                            ));
-                        }
-                    }
-                }
-            }
-        }
+        // Parse trips.txt to map routes to shapes
+        let route_to_shapes = Self::parse_sncf_trips(&mut archive)?;
+        println!("   ✓ Mapped {} routes to shapes", route_to_shapes.len());
 
         // Parse stop_times.txt for schedule predictions
-        let stop_times = Self::parse_stop_times(&mut archive)?;
-        println!("✓ Parsed {} stop time entries", stop_times.values().map(|v| v.len()).sum::<usize>());
+        let (stop_times, quality) = Self::parse_stop_times(&mut archive)?;
+        let frequencies = Self::parse_frequencies(&mut archive, &stop_times);
+        println!("   ✓ Parsed {} stop time entries", stop_times.values().map(|v| v.len()).sum::<usize>());
 
         // Parse trips.txt for trip information
         let trips = Self::parse_trips_info(&mut archive)?;
-        println!("✓ Parsed {} trips", trips.len());
+        println!("   ✓ Parsed {} trips", trips.len());
 
         // Parse calendar.txt for service schedules
         let calendar = Self::parse_calendar(&mut archive)?;
-        println!("✓ Parsed {} calendar services", calendar.len());
+        println!("   ✓ Parsed {} calendar services", calendar.len());
 
         // Parse calendar_dates.txt for exceptions
         let calendar_dates = Self::parse_calendar_dates(&mut archive)?;
-        println!("✓ Parsed {} calendar date exceptions", calendar_dates.values().map(|v| v.len()).sum::<usize>());
+        println!("   ✓ Parsed {} calendar date exceptions", calendar_dates.values().map(|v| v.len()).sum::<usize>());
 
-        let cache = GTFSCache {
-            routes: color_map.clone(),
-            stops: stops_data,
-            shapes: shapes_map,
-            route_to_shapes,
+        Ok(GTFSCache {
+            routes,
+            route_text_colors: Self::parse_route_text_colors(&mut archive),
+            route_types: Self::parse_route_types(&mut archive),
+            stops: stops_data.clone(),
+            shapes: shapes.clone(),
+            route_to_shapes: route_to_shapes.clone(),
             stop_times,
             trips,
             calendar,
@@ -2131,29 +4253,20 @@ impl NVTModels {
             agencies: HashMap::new(),
             route_agencies: HashMap::new(),
             transfers: Vec::new(),
+            synthetic_shape_ids: HashSet::new(),
+            map_matched: false,
+            quality,
+            accessibility: Self::parse_accessibility(&mut archive),
+            frequencies,
+            feed_info: Self::parse_feed_info(&mut archive),
+            etag: None,
+            last_modified: None,
             cached_at: SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap_or_default()
                 .as_secs(),
-            source: "TBM".to_string(),
-        };
-
-        if let Err(e) = cache.save() {
-            eprintln!("⚠️  Warning: Could not save TBM GTFS cache: {}", e);
-        }
-
-        println!("✓ Loaded {} route colors", cache.routes.len());
-        println!("✓ Cached {} stops for future use", cache.stops.len());
-
-        Ok(cache)
-    }
-
-    fn load_gtfs_data(source: &str, _max_age_days: u64) -> Result<GTFSCache> {
-        if source == "TBM" {
-            Self::download_and_read_gtfs()
-        } else {
-            Err(NVTError::ParseError(format!("Unknown GTFS source: {}", source)))
-        }
+            source: "SNCF".to_string(),
+        })
     }
 
     // Helper methods for building network data
@@ -2180,12 +4293,15 @@ impl NVTModels {
         let grace_period = 120;
         let cutoff_time = now - grace_period;
 
-        let mut trip_updates_by_stop: HashMap<String, Vec<(String, Option<String>, Option<u32>, Option<i32>, Option<i64>)>> = HashMap::new();
+        // (trip id, route id, direction, delay, predicted time, start date) of each stop's updates
+        type StopUpdate = (String, Option<String>, Option<u32>, Option<i32>, Option<i64>, Option<String>);
+        let mut trip_updates_by_stop: HashMap<String, Vec<StopUpdate>> = HashMap::new();
 
         for trip_update in &trip_updates {
             let trip_id = trip_update.trip.trip_id.clone().unwrap_or_else(|| "Unknown".to_string());
             let route_id = trip_update.trip.route_id.clone();
             let direction_id = trip_update.trip.direction_id;
+            let start_date = trip_update.trip.start_date.clone();
 
             for stu in &trip_update.stop_time_update {
                 if let Some(stop_id_raw) = &stu.stop_id {
@@ -2203,6 +4319,7 @@ impl NVTModels {
                                 direction_id,
                                 delay,
                                 time,
+                                start_date.clone(),
                             );
 
                             trip_updates_by_stop
@@ -2239,7 +4356,7 @@ impl NVTModels {
                     .collect();
 
                 if let Some(scheduled_arrivals) = trip_updates_by_stop.get(&id) {
-                    for (trip_id, route_id, direction_id, delay, time) in scheduled_arrivals {
+                    for (trip_id, route_id, direction_id, delay, time, start_date) in scheduled_arrivals {
                         let destination = route_id.as_ref().and_then(|rid| {
                             line_destinations_map.get(rid).and_then(|destinations| {
                                 direction_id.and_then(|dir_id| {
@@ -2262,6 +4379,10 @@ impl NVTModels {
                             current_stop_sequence: None,
                             timestamp: *time,
                             delay: *delay,
+                            bearing: None,
+                            occupancy: None,
+                            source: None,
+                            start_date: start_date.clone(),
                         });
                     }
                 }
@@ -2295,6 +4416,12 @@ impl NVTModels {
                     lines: line_refs,
                     alerts: stop_alerts,
                     real_time: stop_rt,
+                    commune: None,
+                    insee_code: None,
+                    amenities: None,
+                    wheelchair_boarding: None,
+                    level: None,
+                    pathways: Vec::new(),
                 }
             })
             .collect()
@@ -2329,6 +4456,9 @@ impl NVTModels {
                     .get(&line_id_str)
                     .cloned()
                     .unwrap_or_else(|| "808080".to_string());
+                let raw_text_color = gtfs_cache.route_text_colors.get(&line_id_str).cloned();
+                let (display_color, text_color) = colors::readable_colors(&color, raw_text_color.as_deref());
+                let mode = gtfs_cache.route_types.get(&line_id_str).and_then(|t| TransportMode::from_route_type(*t));
 
                 let shape_ids = gtfs_cache.route_to_shapes
                     .get(&line_id_str)
@@ -2372,9 +4502,14 @@ impl NVTModels {
                     destinations,
                     alerts: line_alerts,
                     real_time: line_rt,
-                    color,
+                    color: display_color,
+                    text_color,
+                    raw_color: color,
+                    raw_text_color,
                     shape_ids,
+                    synthetic: false,
                     operator: "TBM".to_string(),
+                    mode,
                 }
             })
             .collect();
@@ -2391,15 +4526,17 @@ impl NVTModels {
                 if !shape_ids.is_empty() {
                     // Extract line code from route_id with multiple fallback strategies
                     // Examples: "TBM:Line:A" -> "A", "A" -> "A", "12" -> "12"
-                    let line_code = if let Some(extracted) = Self::extract_line_id(route_id) {
+                    let line_code = if let Some(configured) = Self::configured_line_code("tbm", route_id) {
+                        configured
+                    } else if let Some(extracted) = Self::extract_line_id(route_id) {
                         // Format: "TBM:Line:CODE" -> extract CODE
-                        extracted
+                        extracted.to_string()
                     } else if let Some(last_part) = route_id.split(':').last() {
                         // Format: "XXX:YYY" -> use YYY, or "CODE" -> use CODE
-                        last_part
+                        last_part.to_string()
                     } else {
                         // Fallback: use full route_id (shouldn't happen as split always returns at least one element)
-                        route_id
+                        route_id.clone()
                     };
                     
                     // Use the actual route_id if it already contains "TBM:Line:", otherwise format it
@@ -2408,6 +4545,8 @@ impl NVTModels {
                     } else {
                         format!("TBM:Line:{}", line_code)
                     };
+                    let raw_text_color = gtfs_cache.route_text_colors.get(route_id).cloned();
+                    let (display_color, text_color) = colors::readable_colors(color, raw_text_color.as_deref());
                     
                     lines.push(Line {
                         line_ref,
@@ -2417,9 +4556,14 @@ impl NVTModels {
                         destinations: Vec::new(),
                         alerts: Vec::new(),
                         real_time: Vec::new(),
-                        color: color.clone(),
+                        color: display_color,
+                        text_color,
+                        raw_color: color.clone(),
+                        raw_text_color,
                         shape_ids: shape_ids.clone(),
+                        synthetic: false,
                         operator: "TBM".to_string(),
+                        mode: gtfs_cache.route_types.get(route_id).and_then(|t| TransportMode::from_route_type(*t)),
                     });
                 }
             }
@@ -2428,7 +4572,7 @@ impl NVTModels {
         lines
     }
 
-    fn extract_stop_id(full_id: &str) -> Option<String> {
+    pub fn extract_stop_id(full_id: &str) -> Option<String> {
         if full_id.contains("BP:") {
             full_id
                 .split("BP:")
@@ -2466,136 +4610,207 @@ impl NVTModels {
         Utc::now().timestamp()
     }
 
-    pub fn get_cache_stats(cache: &CachedNetworkData) -> String {
+    /// Current time in Europe/Paris, the zone of the timetables. Not chrono::Local, the server's
+    /// own zone (UTC in most containers)
+    pub fn paris_now() -> chrono::DateTime<chrono_tz::Tz> {
+        Utc::now().with_timezone(&chrono_tz::Europe::Paris)
+    }
+
+    pub fn get_cache_stats(cache: &CachedNetworkData) -> CacheStats {
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap_or_default()
             .as_secs();
+        let source_stats = |gtfs: &GTFSCache, stops: usize, lines: usize| SourceStats {
+            source: gtfs.source.clone(),
+            stops,
+            lines,
+            routes: gtfs.routes.len(),
+            shapes: gtfs.shapes.len(),
+            trips: gtfs.trips.len(),
+            gtfs_age_secs: now.saturating_sub(gtfs.cached_at),
+            cache_file_bytes: fs::metadata(GTFSCache::cache_path(&cache.upstreams.cache_dir, &gtfs.source))
+                .ok()
+                .map(|metadata| metadata.len()),
+            feed_info: gtfs.feed_info.clone(),
+        };
 
-        let static_age = now.saturating_sub(cache.last_static_update);
-        let dynamic_age = now.saturating_sub(cache.last_dynamic_update);
-
-        format!(
-            "📊 Cache Statistics:\n\
-             • TBM: {} stops, {} lines\n\
-             • New-Aquitaine: {} stops, {} lines\n\
-             • SNCF: {} stops, {} lines\n\
-             • TBM Colors: {} | TBM Shapes: {}\n\
-             • New-Aquitaine Colors: {} | New-Aquitaine Shapes: {}\n\
-             • SNCF Colors: {} | SNCF Shapes: {}\n\
-             • Vehicles tracked: {} | Alerts: {}\n\
-             • Static data age: {}s | Dynamic data age: {}s\n\
-             • Last update: {}",
-            cache.tbm_stops_metadata.len(),
-            cache.tbm_lines_metadata.len(),
-            cache.transgironde_stops.len(),
-            cache.transgironde_lines.len(),
-            cache.sncf_stops.len(),
-            cache.sncf_lines.len(),
-            cache.tbm_gtfs_cache.routes.len(),
-            cache.tbm_gtfs_cache.shapes.len(),
-            cache.transgironde_gtfs_cache.routes.len(),
-            cache.transgironde_gtfs_cache.shapes.len(),
-            cache.sncf_gtfs_cache.routes.len(),
-            cache.sncf_gtfs_cache.shapes.len(),
-            cache.real_time.len(),
-            cache.alerts.len(),
-            static_age,
-            dynamic_age,
-            Self::format_timestamp_full(cache.last_dynamic_update as i64)
-        )
+        CacheStats {
+            sources: [
+                source_stats(&cache.tbm_gtfs_cache, cache.tbm_stops_metadata.len(), cache.tbm_lines_metadata.len()),
+                source_stats(&cache.transgironde_gtfs_cache, cache.transgironde_stops.len(), cache.transgironde_lines.len()),
+                source_stats(&cache.sncf_gtfs_cache, cache.sncf_stops.len(), cache.sncf_lines.len()),
+            ]
+            .into_iter()
+            .chain(cache.feeds.values().map(|feed| source_stats(&feed.gtfs_cache, feed.stops.len(), feed.lines.len())))
+            .collect(),
+            vehicles: cache.real_time.len(),
+            alerts: cache.alerts.len(),
+            trip_updates: cache.trip_updates.len(),
+            last_static_update: cache.last_static_update,
+            last_dynamic_update: cache.last_dynamic_update,
+            static_age_secs: now.saturating_sub(cache.last_static_update),
+            dynamic_age_secs: now.saturating_sub(cache.last_dynamic_update),
+            last_refresh: cache.last_refresh.clone(),
+        }
+    }
+
+    /// Health of the cache at `now`: degraded when the data, or the feeds of a source, did not
+    /// update within the thresholds of `settings`. Upstream failures keep the last data and still
+    /// count as a refresh, so the per-source ages are what show a failing feed.
+    pub fn get_health(cache: &CachedNetworkData, settings: &config::HealthConfig, now: u64) -> HealthReport {
+        let freshness: BTreeMap<String, SourceHealth> = cache.source_freshness.iter()
+            .map(|(source, freshness)| {
+                let age = |feeds: &Option<FeedFreshness>| feeds.as_ref().map(|feeds| now.saturating_sub(feeds.updated));
+                let (static_age_secs, real_time_age_secs) = (age(&freshness.static_data), age(&freshness.real_time));
+                let errors: Vec<String> = [&freshness.static_data, &freshness.real_time].into_iter()
+                    .flatten()
+                    .filter_map(|feeds| feeds.error.clone())
+                    .collect();
+                let health = SourceHealth {
+                    succeeded: errors.is_empty(),
+                    stale: static_age_secs.is_some_and(|age| age > settings.max_static_age_secs)
+                        || real_time_age_secs.is_some_and(|age| age > settings.max_real_time_age_secs),
+                    static_age_secs,
+                    real_time_age_secs,
+                    errors,
+                };
+                (source.clone(), health)
+            })
+            .collect();
+
+        let dynamic_age_secs = now.saturating_sub(cache.last_dynamic_update);
+        let static_age_secs = now.saturating_sub(cache.last_static_update);
+        // A standby's data comes from its primary, without per-source freshness
+        let fresh = if freshness.is_empty() {
+            dynamic_age_secs <= settings.max_real_time_age_secs
+        } else {
+            freshness.values().any(|source| !source.stale)
+        };
+        HealthReport {
+            degraded: dynamic_age_secs > settings.max_real_time_age_secs
+                || static_age_secs > settings.max_static_age_secs
+                || freshness.values().any(|source| source.stale),
+            ready: cache.last_static_update != 0 && fresh,
+            dynamic_age_secs,
+            static_age_secs,
+            freshness,
+        }
     }
 
-    /// Get scheduled arrivals for a stop based on GTFS data
+    /// Timetable arrivals at a stop from `at` on, in time order across service days: the
+    /// previous day's trips running past midnight (times from 24:00:00), the day's, then the
+    /// next day's, so a late evening query still finds the first morning departures
     pub fn get_scheduled_arrivals(
         stop_id: &str,
         cache: &CachedNetworkData,
+        at: chrono::NaiveDateTime,
         max_results: usize,
     ) -> Vec<ScheduledArrival> {
-        use chrono::{Local, Datelike, Timelike};
-        
-        const SECONDS_PER_HOUR: u32 = 3600;
-        const SECONDS_PER_MINUTE: u32 = 60;
-        const SECONDS_IN_DAY: u32 = 86400;
-        const LATE_EVENING_THRESHOLD: u32 = 79200; // 22:00:00
-        
-        let now = Local::now();
-        let today_date = format!("{}{:02}{:02}", now.year(), now.month(), now.day());
-        let current_seconds = now.hour() * SECONDS_PER_HOUR + now.minute() * SECONDS_PER_MINUTE + now.second();
-        
-        let weekday_num = now.weekday().num_days_from_monday(); // 0 = Monday, 6 = Sunday
-        
-        let mut scheduled_arrivals = Vec::new();
-        
-        // Check all three GTFS caches
-        let gtfs_caches = vec![
-            (&cache.tbm_gtfs_cache, "TBM"),
-            (&cache.transgironde_gtfs_cache, "TransGironde"),
-            (&cache.sncf_gtfs_cache, "SNCF"),
-        ];
-        
-        for (gtfs_cache, operator) in gtfs_caches {
-            // Get stop times for this stop
-            if let Some(stop_times) = gtfs_cache.stop_times.get(stop_id) {
-                for stop_time in stop_times {
-                    // Get trip info
-                    if let Some(trip) = gtfs_cache.trips.get(&stop_time.trip_id) {
-                        // Check if service is active today
-                        if !Self::is_service_active(
-                            &trip.service_id,
-                            &today_date,
-                            weekday_num,
-                            &gtfs_cache.calendar,
-                            &gtfs_cache.calendar_dates,
-                        ) {
-                            continue;
-                        }
-                        
-                        // Parse arrival time
-                        if let Some(arrival_seconds) = Self::parse_gtfs_time(&stop_time.arrival_time) {
-                            // Handle next-day services (times >= 24:00:00)
-                            // Only include future arrivals within the next 2 hours window
-                            let is_future = if arrival_seconds >= SECONDS_IN_DAY {
-                                // Next-day service (e.g., 25:30:00)
-                                // Only show if current time is late enough (e.g., after 22:00)
-                                current_seconds >= LATE_EVENING_THRESHOLD
-                            } else {
-                                // Same-day service
-                                arrival_seconds >= current_seconds
-                            };
-                            
-                            if is_future {
-                                // Get line info
-                                let line_color = gtfs_cache.routes.get(&trip.route_id)
-                                    .cloned()
-                                    .unwrap_or_else(|| "808080".to_string());
-                                
-                                // Extract line code from route_id
-                                let line_code = Self::extract_line_code_from_route(&trip.route_id, operator);
-                                
-                                scheduled_arrivals.push(ScheduledArrival {
-                                    trip_id: stop_time.trip_id.clone(),
-                                    route_id: trip.route_id.clone(),
-                                    line_code,
-                                    line_color,
-                                    arrival_time: stop_time.arrival_time.clone(),
-                                    departure_time: stop_time.departure_time.clone(),
-                                    destination: trip.trip_headsign.clone(),
-                                    stop_headsign: stop_time.stop_headsign.clone(),
-                                    operator: operator.to_string(),
-                                });
-                            }
+        use chrono::Timelike;
+
+        const SECONDS_IN_DAY: i64 = 86400;
+
+        let current_seconds = i64::from(at.num_seconds_from_midnight());
+        let mut scheduled_arrivals: Vec<(i64, ScheduledArrival)> = [-1, 0, 1].into_iter()
+            .flat_map(|offset| {
+                let service_date = at.date() + chrono::Duration::days(offset);
+                Self::scheduled_arrivals_on(stop_id, service_date, cache).into_iter()
+                    .filter_map(move |arrival| {
+                        // Seconds since the midnight of `at`
+                        let seconds = i64::from(Self::parse_gtfs_time(&arrival.arrival_time)?) + offset * SECONDS_IN_DAY;
+                        (seconds >= current_seconds).then_some((seconds, arrival))
+                    })
+            })
+            .collect();
+        scheduled_arrivals.sort_by_key(|(seconds, _)| *seconds);
+        scheduled_arrivals.into_iter()
+            .take(max_results)
+            .map(|(_, arrival)| arrival)
+            .collect()
+    }
+
+    /// Every timetable arrival at a stop on a service date, sorted and deduplicated
+    pub fn scheduled_arrivals_on(stop_id: &str, date: chrono::NaiveDate, cache: &CachedNetworkData) -> Vec<ScheduledArrival> {
+        use chrono::Datelike;
+
+        let service_date = date.format("%Y%m%d").to_string();
+        let weekday_num = date.weekday().num_days_from_monday();
+        let gtfs_caches = cache.operator_caches();
+
+        let mut scheduled_arrivals: Vec<ScheduledArrival> = gtfs_caches.iter()
+            .flat_map(|(gtfs_cache, operator)| {
+                gtfs_cache.stop_times.get(stop_id).into_iter().flatten().filter_map(|stop_time| {
+                    let trip = gtfs_cache.trips.get(&stop_time.trip_id)?;
+                    Self::is_service_active(
+                        &trip.service_id,
+                        &service_date,
+                        weekday_num,
+                        &gtfs_cache.calendar,
+                        &gtfs_cache.calendar_dates,
+                    ).then(|| {
+                        let arrival = Self::scheduled_arrival(stop_time, trip, gtfs_cache, operator, date);
+                        match gtfs_cache.frequencies.get(&trip.trip_id) {
+                            Some(frequencies) => Self::expand_frequencies(&arrival, frequencies),
+                            None => vec![arrival],
                         }
-                    }
-                }
-            }
+                    })
+                })
+                .flatten()
+            })
+            .collect();
+        Self::sort_and_dedupe_arrivals(&mut scheduled_arrivals);
+        scheduled_arrivals
+    }
+
+    fn scheduled_arrival(stop_time: &StopTime, trip: &Trip, gtfs_cache: &GTFSCache, operator: &str, service_date: chrono::NaiveDate) -> ScheduledArrival {
+        ScheduledArrival {
+            trip_id: stop_time.trip_id.clone(),
+            route_id: trip.route_id.clone(),
+            line_code: Self::extract_line_code_from_route(&trip.route_id, operator),
+            line_color: gtfs_cache.routes.get(&trip.route_id)
+                .cloned()
+                .unwrap_or_else(|| "808080".to_string()),
+            arrival_time: stop_time.arrival_time.clone(),
+            departure_time: stop_time.departure_time.clone(),
+            destination: trip.trip_headsign.clone(),
+            stop_headsign: stop_time.stop_headsign.clone(),
+            operator: operator.to_string(),
+            service_date: service_date.format("%Y-%m-%d").to_string(),
+            wheelchair_accessible: trip.wheelchair_accessible,
+            bikes_allowed: trip.bikes_allowed,
         }
-        
-        // Sort by arrival time
+    }
+
+    /// The arrivals of a headway-based trip at a stop: its template arrival shifted to each
+    /// departure of the frequencies.txt windows, end_time excluded
+    fn expand_frequencies(template: &ScheduledArrival, frequencies: &[Frequency]) -> Vec<ScheduledArrival> {
+        let (Some(arrival), Some(departure)) = (Self::parse_gtfs_time(&template.arrival_time), Self::parse_gtfs_time(&template.departure_time)) else {
+            return Vec::new();
+        };
+        frequencies.iter()
+            .filter_map(|frequency| Some((frequency, Self::parse_gtfs_time(&frequency.start_time)?, Self::parse_gtfs_time(&frequency.end_time)?)))
+            .flat_map(|(frequency, start, end)| {
+                (start..end).step_by(frequency.headway_secs as usize).map(move |trip_start| ScheduledArrival {
+                    arrival_time: Self::format_gtfs_time(trip_start + arrival.saturating_sub(frequency.trip_start)),
+                    departure_time: Self::format_gtfs_time(trip_start + departure.saturating_sub(frequency.trip_start)),
+                    ..template.clone()
+                })
+            })
+            .collect()
+    }
+
+    /// Departures of `line` at a stop on a service date, for the whole day
+    pub fn line_timetable(line: &Line, stop_id: &str, date: chrono::NaiveDate, cache: &CachedNetworkData) -> Vec<ScheduledArrival> {
+        let mut departures = Self::scheduled_arrivals_on(stop_id, date, cache);
+        departures.retain(|arrival| arrival.route_id == line.route_id || arrival.route_id == line.line_ref);
+        departures
+    }
+
+    /// Sort by arrival time and keep the first arrival of each line, time and destination
+    fn sort_and_dedupe_arrivals(scheduled_arrivals: &mut Vec<ScheduledArrival>) {
         scheduled_arrivals.sort_by(|a, b| a.arrival_time.cmp(&b.arrival_time));
-        
-        // Deduplicate based on line_code, arrival_time, and destination
-        // Keep only the first occurrence of each unique combination
+
         let mut seen = std::collections::HashSet::new();
         scheduled_arrivals.retain(|arrival| {
             let key = (
@@ -2605,12 +4820,21 @@ impl NVTModels {
             );
             seen.insert(key)
         });
-        
-        // Take top results after deduplication
-        scheduled_arrivals.truncate(max_results);
-        scheduled_arrivals
     }
     
+    /// Whether a service of `gtfs_cache` runs on a date, from calendar.txt and calendar_dates.txt
+    pub fn service_runs(gtfs_cache: &GTFSCache, service_id: &str, date: chrono::NaiveDate) -> bool {
+        use chrono::Datelike;
+
+        Self::is_service_active(
+            service_id,
+            &date.format("%Y%m%d").to_string(),
+            date.weekday().num_days_from_monday(),
+            &gtfs_cache.calendar,
+            &gtfs_cache.calendar_dates,
+        )
+    }
+
     /// Check if a service is active on a given date
     fn is_service_active(
         service_id: &str,
@@ -2652,23 +4876,60 @@ impl NVTModels {
         false
     }
     
-    /// Parse GTFS time format (HH:MM:SS) to seconds since midnight
-    fn parse_gtfs_time(time_str: &str) -> Option<u32> {
-        let parts: Vec<&str> = time_str.split(':').collect();
-        if parts.len() != 3 {
+    /// Latest GTFS time accepted; later values are feed errors rather than long overnight trips
+    const MAX_GTFS_HOURS: u32 = 48;
+
+    /// Split a GTFS time into (hours, minutes, seconds), tolerating missing zero padding
+    /// ("7:5:00") and a missing seconds field ("07:05"), and rejecting out-of-range values
+    fn gtfs_time_components(time_str: &str) -> Option<(u32, u32, u32)> {
+        let parts: Vec<&str> = time_str.trim().split(':').collect();
+        if !(2..=3).contains(&parts.len()) || parts.iter().any(|p| p.is_empty() || p.len() > 2 || !p.bytes().all(|b| b.is_ascii_digit())) {
             return None;
         }
-        
+
         let hours: u32 = parts[0].parse().ok()?;
         let minutes: u32 = parts[1].parse().ok()?;
-        let seconds: u32 = parts[2].parse().ok()?;
-        
+        let seconds: u32 = parts.get(2).map_or(Some(0), |s| s.parse().ok())?;
+
+        if minutes >= 60 || seconds >= 60 || hours * 3600 + minutes * 60 + seconds > Self::MAX_GTFS_HOURS * 3600 {
+            return None;
+        }
+        Some((hours, minutes, seconds))
+    }
+
+    /// Parse GTFS time format (HH:MM:SS) to seconds since the start of the service day
+    pub fn parse_gtfs_time(time_str: &str) -> Option<u32> {
+        let (hours, minutes, seconds) = Self::gtfs_time_components(time_str)?;
         Some(hours * 3600 + minutes * 60 + seconds)
     }
+
+    /// Canonical zero-padded HH:MM:SS form of a GTFS time, or None if it can't be trusted
+    pub fn normalize_gtfs_time(time_str: &str) -> Option<String> {
+        let (hours, minutes, seconds) = Self::gtfs_time_components(time_str)?;
+        Some(format!("{:02}:{:02}:{:02}", hours, minutes, seconds))
+    }
+
+    /// HH:MM:SS GTFS time of seconds since the start of the service day, past 24:00:00 after midnight
+    pub fn format_gtfs_time(seconds: u32) -> String {
+        format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    }
     
+    /// Line code of a route id from the rules configured for `source` (see config::IdRule)
+    fn configured_line_code(source: &str, route_id: &str) -> Option<String> {
+        config::get().line_code_rules.get(source)
+            .and_then(|rules| config::apply_id_rules(rules, route_id))
+    }
+
     /// Extract line code from route ID for display
     fn extract_line_code_from_route(route_id: &str, operator: &str) -> String {
-        if operator == "TBM" {
+        let source = match operator {
+            "TransGironde" => "naq",
+            "SNCF" => "sncf",
+            _ => "tbm",
+        };
+        if let Some(configured) = Self::configured_line_code(source, route_id) {
+            configured
+        } else if operator == "TBM" {
             // TBM format: extract last part
             route_id.split(':').last().unwrap_or(route_id).to_string()
         } else if operator == "TransGironde" {
@@ -2680,6 +4941,69 @@ impl NVTModels {
         }
     }
 
+    /// A trip with its stop times in order, its service calendar and the delays of its GTFS-RT
+    /// TripUpdate. As GTFS-RT specifies, a delay holds for the following stops until the next
+    /// update.
+    pub fn get_trip_details(trip_id: &str, cache: &CachedNetworkData) -> Option<TripDetails> {
+        Self::trip_details_in(trip_id, cache, &cache.to_network_data().lines)
+    }
+
+    /// get_trip_details with the lines already built, for callers resolving many trips
+    pub fn trip_details_in(trip_id: &str, cache: &CachedNetworkData, lines: &[Line]) -> Option<TripDetails> {
+        let (gtfs_cache, operator) = cache.operator_caches().into_iter().find(|(gtfs_cache, _)| gtfs_cache.trips.contains_key(trip_id))?;
+        let trip = &gtfs_cache.trips[trip_id];
+        let trip_update = cache.trip_updates.iter()
+            .find(|update| update.trip.trip_id.as_deref() == Some(trip_id));
+
+        let stop_names: HashMap<&str, &str> = gtfs_cache.stops.iter()
+            .map(|(stop_id, stop_name, _, _)| (stop_id.as_str(), stop_name.as_str()))
+            .collect();
+        let mut delay = None;
+        let stop_times = cache.trip_index.get(trip_id).into_iter().flatten()
+            .filter_map(|(sequence, stop_id)| {
+                gtfs_cache.stop_times.get(stop_id)?.iter()
+                    .find(|stop_time| stop_time.trip_id == trip_id && stop_time.stop_sequence == *sequence)
+            })
+            .map(|stop_time| {
+                let update = trip_update.and_then(|trip_update| {
+                    trip_update.stop_time_update.iter().find(|update| match (update.stop_sequence, &update.stop_id) {
+                        (Some(sequence), _) => sequence == stop_time.stop_sequence,
+                        (None, Some(stop_id)) => *stop_id == stop_time.stop_id
+                            || Self::extract_stop_id(stop_id).is_some_and(|id| id == stop_time.stop_id),
+                        (None, None) => false,
+                    })
+                });
+                let event = update.and_then(|update| update.arrival.as_ref().or(update.departure.as_ref()));
+                if let Some(event_delay) = event.and_then(|event| event.delay) {
+                    delay = Some(event_delay);
+                }
+                TripStopTime {
+                    stop_time: stop_time.clone(),
+                    stop_name: stop_names.get(stop_time.stop_id.as_str()).map(|name| name.to_string()),
+                    delay,
+                    predicted_time: event.and_then(|event| event.time),
+                    skipped: update.and_then(|update| update.schedule_relationship)
+                        == Some(gtfs_rt::trip_update::stop_time_update::ScheduleRelationship::Skipped as i32),
+                }
+            })
+            .collect();
+
+        // Line the trip runs on, falling back to what the feed says of the route
+        let line = lines.iter().find(|line| line.route_id == trip.route_id);
+        Some(TripDetails {
+            trip: trip.clone(),
+            line_code: line.map_or_else(|| Self::extract_line_code_from_route(&trip.route_id, operator), |line| line.line_code.clone()),
+            line_color: line.map(|line| line.color.clone())
+                .or_else(|| gtfs_cache.routes.get(&trip.route_id).cloned())
+                .unwrap_or_else(|| "808080".to_string()),
+            operator: line.map_or_else(|| operator.to_string(), |line| line.operator.clone()),
+            calendar: gtfs_cache.calendar.get(&trip.service_id).cloned(),
+            calendar_dates: gtfs_cache.calendar_dates.get(&trip.service_id).cloned().unwrap_or_default(),
+            stop_times,
+            real_time: trip_update.is_some(),
+        })
+    }
+
     /// Get detailed information about a specific vehicle including stop sequence
     pub fn get_vehicle_details(vehicle_id: &str, cache: &CachedNetworkData) -> Option<VehicleDetails> {
         // Find the vehicle in real-time data
@@ -2691,66 +5015,44 @@ impl NVTModels {
             l.real_time.iter().any(|rt| rt.vehicle_id == vehicle_id)
         })?;
 
-        // Get the trip information to find stop sequence
-        let gtfs_caches = vec![
-            (&cache.tbm_gtfs_cache, "TBM"),
-            (&cache.transgironde_gtfs_cache, "TransGironde"),
-            (&cache.sncf_gtfs_cache, "SNCF"),
-        ];
-
         let mut current_stop = None;
         let mut next_stop = None;
         let mut previous_stop = None;
 
-        // Find stop sequence from trip information
-        for (gtfs_cache, _operator) in gtfs_caches {
-            if let Some(_trip) = gtfs_cache.trips.get(&vehicle.trip_id) {
-                // Get all stops for this trip in sequence
-                let mut trip_stops: Vec<_> = gtfs_cache.stop_times.values()
-                    .flatten()
-                    .filter(|st| st.trip_id == vehicle.trip_id)
-                    .collect();
-                
-                trip_stops.sort_by_key(|st| st.stop_sequence);
-
-                // Try to find current stop position using current_stop_sequence first (most accurate)
-                let current_idx = if let Some(seq) = vehicle.current_stop_sequence {
-                    // Use the sequence number from GTFS-RT to find exact position
-                    trip_stops.iter().position(|st| st.stop_sequence == seq)
-                } else if let Some(current_stop_id) = &vehicle.stop_id {
-                    // Fallback: find by stop_id (may not work correctly for duplicate stops)
-                    trip_stops.iter().position(|st| &st.stop_id == current_stop_id)
-                } else {
-                    None
-                };
+        // Stops of the trip in sequence, from the index built at static load
+        if let Some(trip_stops) = cache.trip_index.get(&vehicle.trip_id) {
+            // Try to find current stop position using current_stop_sequence first (most accurate)
+            let current_idx = if let Some(seq) = vehicle.current_stop_sequence {
+                // Use the sequence number from GTFS-RT to find exact position
+                trip_stops.iter().position(|(sequence, _)| *sequence == seq)
+            } else if let Some(current_stop_id) = &vehicle.stop_id {
+                // Fallback: find by stop_id (may not work correctly for duplicate stops)
+                trip_stops.iter().position(|(_, stop_id)| stop_id == current_stop_id)
+            } else {
+                None
+            };
 
-                if let Some(idx) = current_idx {
-                    // Get current stop
-                    if let Some(current_stop_id) = vehicle.stop_id.as_ref().or_else(|| {
-                        trip_stops.get(idx).map(|st| &st.stop_id)
-                    }) {
-                        current_stop = network_data.stops.iter()
-                            .find(|s| &s.stop_id == current_stop_id)
-                            .cloned();
-                    }
+            let find_stop = |stop_id: &String| network_data.stops.iter()
+                .find(|s| &s.stop_id == stop_id)
+                .cloned();
 
-                    // Get next stop
-                    if idx + 1 < trip_stops.len() {
-                        let next_stop_id = &trip_stops[idx + 1].stop_id;
-                        next_stop = network_data.stops.iter()
-                            .find(|s| &s.stop_id == next_stop_id)
-                            .cloned();
-                    }
+            if let Some(idx) = current_idx {
+                // Get current stop
+                if let Some(current_stop_id) = vehicle.stop_id.as_ref().or_else(|| {
+                    trip_stops.get(idx).map(|(_, stop_id)| stop_id)
+                }) {
+                    current_stop = find_stop(current_stop_id);
+                }
 
-                    // Get previous stop
-                    if idx > 0 {
-                        let prev_stop_id = &trip_stops[idx - 1].stop_id;
-                        previous_stop = network_data.stops.iter()
-                            .find(|s| &s.stop_id == prev_stop_id)
-                            .cloned();
-                    }
+                // Get next stop
+                if let Some((_, next_stop_id)) = trip_stops.get(idx + 1) {
+                    next_stop = find_stop(next_stop_id);
+                }
+
+                // Get previous stop
+                if idx > 0 {
+                    previous_stop = find_stop(&trip_stops[idx - 1].1);
                 }
-                break;
             }
         }
 
@@ -2772,4 +5074,431 @@ impl NVTModels {
             delay: vehicle.delay,
         })
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_canonical_gtfs_times() {
+        assert_eq!(NVTModels::parse_gtfs_time("00:00:00"), Some(0));
+        assert_eq!(NVTModels::parse_gtfs_time("08:30:15"), Some(8 * 3600 + 30 * 60 + 15));
+        assert_eq!(NVTModels::parse_gtfs_time("25:10:00"), Some(25 * 3600 + 600));
+        assert_eq!(NVTModels::parse_gtfs_time("48:00:00"), Some(48 * 3600));
+    }
+
+    #[test]
+    fn normalizes_sloppy_gtfs_times() {
+        // Single-digit fields and stray whitespace seen in New-Aquitaine operator exports
+        assert_eq!(NVTModels::normalize_gtfs_time("7:5:00").as_deref(), Some("07:05:00"));
+        assert_eq!(NVTModels::normalize_gtfs_time(" 6:45:00 ").as_deref(), Some("06:45:00"));
+        assert_eq!(NVTModels::normalize_gtfs_time("9:00:5").as_deref(), Some("09:00:05"));
+        // Seconds omitted
+        assert_eq!(NVTModels::normalize_gtfs_time("18:20").as_deref(), Some("18:20:00"));
+        // Already canonical times are left alone
+        assert_eq!(NVTModels::normalize_gtfs_time("24:05:00").as_deref(), Some("24:05:00"));
+    }
+
+    #[test]
+    fn rejects_malformed_gtfs_times() {
+        for bad in ["", "  ", "48:00:01", "72:15:00", "12:60:00", "12:00:60", "ab:cd:ef",
+                    "-1:00:00", "12:00:00.000", "12::00", "1:2:3:4", "123:00:00", "12h30"] {
+            assert_eq!(NVTModels::parse_gtfs_time(bad), None, "{:?} should be rejected", bad);
+            assert_eq!(NVTModels::normalize_gtfs_time(bad), None, "{:?} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn parses_bounding_boxes() {
+        let bbox = BoundingBox::parse("-0.60, 44.80,-0.55,44.86").expect("valid bbox");
+        assert!(bbox.contains(44.8421, -0.5746));
+        assert!(!bbox.contains(44.9153, -0.2437));
+
+        for bad in ["", "-0.6,44.8,-0.55", "-0.6,44.8,-0.55,44.86,1", "a,b,c,d",
+                    "-0.55,44.8,-0.6,44.86", "-0.6,44.86,-0.55,44.8", "0,95,1,96"] {
+            assert!(BoundingBox::parse(bad).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn keeps_shapes_crossing_a_bounding_box() {
+        let point = |latitude, longitude| ShapePoint { latitude, longitude, sequence: 0 };
+        let bbox = BoundingBox { min_lon: -0.58, min_lat: 44.83, max_lon: -0.56, max_lat: 44.85 };
+
+        // Both ends outside, the segment goes through
+        assert!(bbox.crosses_shape(&[point(44.82, -0.57), point(44.86, -0.57)]));
+        assert!(bbox.crosses_shape(&[point(44.9, -0.6), point(44.84, -0.57), point(44.9, -0.5)]));
+        assert!(!bbox.crosses_shape(&[point(44.86, -0.6), point(44.9, -0.5)]));
+        assert!(!bbox.crosses_shape(&[point(44.9, -0.57)]));
+        assert!(!bbox.crosses_shape(&[]));
+    }
+
+    #[test]
+    fn counts_stop_time_problems() {
+        let csv = "trip_id,arrival_time,departure_time,stop_id,stop_sequence,stop_headsign\n\
+                   T1,07:00:00,07:00:00,S1,1,\n\
+                   T1,7:5:00,7:5:30,S2,2,\n\
+                   T1,,,S3,3,\n\
+                   T1,75:00:00,75:00:00,S4,4,\n\
+                   T1,07:20:00,,S5,5,\n\
+                   T1,07:30:00,07:30:00,S6,x,\n\
+                   T1,07:40:00,07:40:00\n";
+
+        let (stop_times, quality) = NVTModels::parse_stop_times_csv(csv);
+
+        assert_eq!(quality.stop_times_total, 7);
+        assert_eq!(quality.stop_times_rejected, 2); // bad stop_sequence, truncated row
+        assert_eq!(quality.times_normalized, 2);
+        assert_eq!(quality.times_rejected, 2);
+        assert_eq!(quality.untimed, 2); // S3 has no times, S4's were rejected
+
+        assert_eq!(stop_times["S2"][0].arrival_time, "07:05:00");
+        assert_eq!(stop_times["S2"][0].departure_time, "07:05:30");
+        assert_eq!(stop_times["S4"][0].arrival_time, "");
+        assert_eq!(stop_times["S5"][0].departure_time, "07:20:00");
+        assert!(!stop_times.contains_key("S6"));
+    }
+
+    // Property tests: seeded generators of the malformed input seen upstream, so failures
+    // reproduce; raise CASES locally when changing a parser
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const CASES: u64 = 300;
+
+    fn pick<'a>(rng: &mut StdRng, options: &[&'a str]) -> &'a str {
+        options[rng.random_range(0..options.len())]
+    }
+
+    /// A GTFS time field: canonical, sloppy, out of range, or garbage
+    fn arbitrary_time(rng: &mut StdRng) -> String {
+        let (h, m, s) = (rng.random_range(0..80), rng.random_range(0..70), rng.random_range(0..70));
+        match rng.random_range(0..8) {
+            0 => format!("{:02}:{:02}:{:02}", h % 48, m % 60, s % 60),
+            1 => format!("{}:{}:{}", h, m, s),
+            2 => format!("{}:{:02}", h, m),
+            3 => format!(" {:02}:{:02}:{:02} ", h, m, s),
+            4 => String::new(),
+            5 => format!("{}:{}:{}:{}", h, m, s, h),
+            6 => pick(rng, &["12h30", "--:--:--", "∞:00:00", "\"\"", "08:00:00.000", "-1:00:00"]).to_string(),
+            _ => (0..rng.random_range(1..6)).map(|_| rng.random_range(' '..='~')).filter(|c| *c != ',' && *c != '"').collect(),
+        }
+    }
+
+    /// A stop_times.txt row, sometimes truncated or with an unusable stop_sequence
+    fn arbitrary_stop_time_row(rng: &mut StdRng) -> String {
+        let sequence = match rng.random_range(0..6) {
+            0 => pick(rng, &["x", "-1", "", "1.5", "99999999999"]).to_string(),
+            _ => rng.random_range(0..50).to_string(),
+        };
+        let fields = [
+            format!("T{}", rng.random_range(0..5)),
+            arbitrary_time(rng),
+            arbitrary_time(rng),
+            pick(rng, &["S1", "S2", "\"S,3\"", "StopPoint:OCETrain TER-87581009", " "]).to_string(),
+            sequence,
+            pick(rng, &["", "Gare", "\"Quai \"\"B\"\"\""]).to_string(),
+        ];
+        let width = if rng.random_bool(0.1) { rng.random_range(1..fields.len()) } else { fields.len() };
+        fields[..width].join(",")
+    }
+
+    #[test]
+    fn normalized_gtfs_times_are_canonical() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..CASES * 10 {
+            let raw = arbitrary_time(&mut rng);
+            if let Some(normalized) = NVTModels::normalize_gtfs_time(&raw) {
+                assert_eq!(NVTModels::normalize_gtfs_time(&normalized).as_deref(), Some(normalized.as_str()), "{:?}", raw);
+                assert_eq!(NVTModels::parse_gtfs_time(&normalized), NVTModels::parse_gtfs_time(&raw), "{:?}", raw);
+                assert_eq!(normalized.len(), 8, "{:?} -> {:?}", raw, normalized);
+            }
+        }
+    }
+
+    #[test]
+    fn configured_rules_derive_line_codes() {
+        let rules: Vec<config::IdRule> = serde_json::from_str(r#"[
+            { "pattern": "^CA_DU_LIBOURNAIS:Line:L?(\\d+)$", "template": "L$1" },
+            { "pattern": "^(?P<network>[A-Z]+)_[A-Z_]+:Line:(?P<code>.+)$", "template": "${network}-${code}" }
+        ]"#).expect("rules");
+        assert_eq!(config::apply_id_rules(&rules, "CA_DU_LIBOURNAIS:Line:L3").as_deref(), Some("L3"));
+        assert_eq!(config::apply_id_rules(&rules, "CA_DU_LIBOURNAIS:Line:B").as_deref(), Some("CA-B"));
+        assert_eq!(config::apply_id_rules(&rules, "OCESN:Line:TER-43"), None);
+        assert!(serde_json::from_str::<config::IdRule>(r#"{ "pattern": "(", "template": "$1" }"#).is_err());
+    }
+
+    #[test]
+    fn operator_aliases_give_canonical_names() {
+        let aliases = HashMap::from([
+            ("CALIBUS".to_string(), "Calibus (Libourne)".to_string()),
+            ("stclm".to_string(), "STCLM (Limoges Métropole)".to_string()),
+        ]);
+        assert_eq!(operators::canonical_name_with(&aliases, "CALIBUS"), "Calibus (Libourne)");
+        assert_eq!(operators::canonical_name_with(&aliases, "  Stclm "), "STCLM (Limoges Métropole)");
+        assert_eq!(operators::canonical_name_with(&aliases, "Yélo  (La Rochelle)"), "Yélo (La Rochelle)");
+        assert_eq!(operators::operator_slug("STCLM (Limoges Métropole)"), "stclm-limoges-metropole");
+        assert!(operators::matches("Calibus (Libourne)", "calibus-libourne"));
+        assert!(!operators::matches("Calibus (Libourne)", "calibus"));
+    }
+
+    #[test]
+    fn alert_filters_follow_active_periods() {
+        let alert = AlertInfo {
+            id: "1".to_string(),
+            text: "Travaux".to_string(),
+            description: String::new(),
+            url: None,
+            route_ids: vec!["59".to_string()],
+            stop_ids: vec!["bordeaux:StopPoint:BP:3672:LOC".to_string()],
+            active_period_start: Some(100),
+            active_period_end: Some(200),
+            severity: 3,
+            active_periods: vec![(Some(100), Some(200)), (Some(500), None)],
+            affected_stops_count: 1,
+            affected_stop_ids: None,
+            derived: false,
+            translations: Vec::new(),
+        };
+        assert!(alert.is_active_at(100) && alert.is_active_at(200) && alert.is_active_at(10_000));
+        assert!(!alert.is_active_at(99) && !alert.is_active_at(300));
+
+        let filter = |filter: AlertFilter| filter.matches(&alert, &[]);
+        assert!(filter(AlertFilter::default()));
+        assert!(filter(AlertFilter { stop_id: Some("3672".to_string()), active_at: Some(150), ..Default::default() }));
+        assert!(!filter(AlertFilter { route_ids: Some(vec!["A".to_string()]), ..Default::default() }));
+        assert!(!filter(AlertFilter { severity_min: Some(4), ..Default::default() }));
+
+        // Stops served by the alert's routes match too
+        let on_route = AlertFilter { stop_id: Some("3671".to_string()), ..Default::default() };
+        assert!(!on_route.matches(&alert, &[]));
+        assert!(on_route.matches(&alert, &["3671".to_string(), "3672".to_string()]));
+    }
+
+    #[test]
+    fn vehicles_are_thinned_by_zoom() {
+        let points = vec![(44.8430, -0.5741), (44.8447, -0.5735), (43.6045, 1.4440)];
+        let thinned = crate::spatial::thin_by_zoom(points.clone(), 6, |&point| point);
+        assert_eq!(thinned, [((44.8430, -0.5741), 2), ((43.6045, 1.4440), 1)]);
+
+        let full = crate::spatial::thin_by_zoom(points, crate::spatial::FULL_DETAIL_ZOOM, |&point| point);
+        assert!(full.len() == 3 && full.iter().all(|(_, count)| *count == 1));
+    }
+
+    #[test]
+    fn line_colors_are_corrected_for_display() {
+        // White on white, and a feed text color with too little contrast
+        assert_eq!(colors::readable_colors("FFFFFF", Some("FFFFFF")), ("FFFFFF".to_string(), "000000".to_string()));
+        assert_eq!(colors::readable_colors("FFD700", Some("FFFFFF")), ("FFD700".to_string(), "000000".to_string()));
+        // Readable feed colors are kept, in canonical form
+        assert_eq!(colors::readable_colors("#e3007a", Some("ffffff")), ("E3007A".to_string(), "FFFFFF".to_string()));
+        // Invalid or missing colors
+        assert_eq!(colors::readable_colors("ZZZZZZ", Some("blue")), ("808080".to_string(), "000000".to_string()));
+        assert_eq!(colors::readable_colors("0B2E59", None), ("0B2E59".to_string(), "FFFFFF".to_string()));
+        assert!((colors::contrast_ratio("000000", "FFFFFF") - 21.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn every_stop_time_row_is_accounted_for() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..CASES {
+            let rows: Vec<String> = (0..rng.random_range(0..40)).map(|_| arbitrary_stop_time_row(&mut rng)).collect();
+            let csv = format!("trip_id,arrival_time,departure_time,stop_id,stop_sequence,stop_headsign\n{}\n", rows.join("\n"));
+
+            let (stop_times, quality) = NVTModels::parse_stop_times_csv(&csv);
+            let kept: Vec<&StopTime> = stop_times.values().flatten().collect();
+
+            assert_eq!(quality.stop_times_total, rows.len(), "{}", csv);
+            assert_eq!(kept.len(), quality.stop_times_total - quality.stop_times_rejected, "{}", csv);
+            assert_eq!(kept.iter().filter(|st| st.arrival_time.is_empty()).count(), quality.untimed, "{}", csv);
+            for stop_time in kept {
+                assert_eq!(stop_time.arrival_time.is_empty(), stop_time.departure_time.is_empty(), "{:?}", stop_time);
+                for time in [&stop_time.arrival_time, &stop_time.departure_time].into_iter().filter(|t| !t.is_empty()) {
+                    assert_eq!(NVTModels::normalize_gtfs_time(time).as_ref(), Some(time), "{:?}", stop_time);
+                }
+            }
+            for times in stop_times.values() {
+                assert!(times.windows(2).all(|w| w[0].arrival_time <= w[1].arrival_time), "{}", csv);
+            }
+        }
+    }
+
+    /// Random byte flips, truncations and insertions of a valid input
+    fn mutate(rng: &mut StdRng, input: &[u8]) -> Vec<u8> {
+        let mut bytes = input.to_vec();
+        for _ in 0..rng.random_range(1..8) {
+            if bytes.is_empty() {
+                break;
+            }
+            let at = rng.random_range(0..bytes.len());
+            match rng.random_range(0..3) {
+                0 => bytes[at] = rng.random(),
+                1 => bytes.truncate(at),
+                _ => bytes.insert(at, rng.random()),
+            }
+        }
+        bytes
+    }
+
+    fn sample_feed() -> Vec<u8> {
+        use gtfs_rt::{Alert, EntitySelector, FeedEntity, FeedHeader, TripDescriptor, TripUpdate, VehiclePosition};
+
+        let trip = TripDescriptor { trip_id: Some("A-1".to_string()), route_id: Some("59".to_string()), ..Default::default() };
+        FeedMessage {
+            header: FeedHeader { gtfs_realtime_version: "2.0".to_string(), ..Default::default() },
+            entity: vec![
+                FeedEntity {
+                    id: "1".to_string(),
+                    vehicle: Some(VehiclePosition { trip: Some(trip.clone()), stop_id: Some("3672".to_string()), ..Default::default() }),
+                    trip_update: Some(TripUpdate { trip, ..Default::default() }),
+                    alert: Some(Alert {
+                        informed_entity: vec![EntitySelector { route_id: Some("59".to_string()), ..Default::default() }],
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            ],
+        }.encode_to_vec()
+    }
+
+    fn sample_gtfs_zip() -> Vec<u8> {
+        use std::io::Write;
+
+        let files = [
+            ("routes.txt", "route_id,agency_id,route_short_name,route_long_name,route_desc,route_type,route_url,route_color\n59,TBM,A,Tram A,,0,,814997\n"),
+            ("stops.txt", "stop_id,stop_code,stop_name,stop_desc,stop_lat,stop_lon\n3672,,Grand Théâtre,,44.8421,-0.5746\n"),
+            ("trips.txt", "route_id,service_id,trip_id,trip_headsign,direction_id,block_id,shape_id\n59,WEEK,A-1,Le Haillan,0,,A-0\n"),
+            ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\nA-1,08:00:00,08:00:00,3672,1\n"),
+        ];
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            zip.start_file(name, zip::write::FileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn parsers_survive_corrupted_input() {
+        let siri = include_bytes!("../tests/fixtures/tbm/stoppoints-discovery.json");
+        let lines = include_bytes!("../tests/fixtures/tbm/lines-discovery.json");
+        let (feed, gtfs) = (sample_feed(), sample_gtfs_zip());
+        assert!(NVTModels::parse_stop_points(siri).is_ok() && NVTModels::parse_lines_discovery(lines).is_ok());
+        assert!(NVTModels::parse_vehicle_positions(&feed).is_ok_and(|vehicles| vehicles.len() == 1));
+        assert!(NVTModels::parse_tbm_gtfs(&gtfs).is_ok_and(|cache| cache.stops.len() == 1));
+
+        // Whatever comes back, nothing may panic
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..CASES {
+            let _ = NVTModels::parse_stop_points(&mutate(&mut rng, siri));
+            let _ = NVTModels::parse_lines_discovery(&mutate(&mut rng, lines));
+            let corrupted = mutate(&mut rng, &feed);
+            let _ = NVTModels::parse_alerts_feed(&corrupted);
+            let _ = NVTModels::parse_vehicle_positions(&corrupted);
+            let _ = NVTModels::parse_trip_updates(&corrupted);
+            let corrupted = mutate(&mut rng, &gtfs);
+            let _ = NVTModels::parse_tbm_gtfs(&corrupted);
+            let _ = NVTModels::parse_naq_gtfs(&corrupted);
+            let _ = NVTModels::parse_sncf_gtfs(&corrupted);
+        }
+    }
+
+    /// S1: N1 at 25:10:00 on Sundays only, M1 at 06:00:00 and E1 at 23:40:00 every day.
+    /// S2: F1 at 07:05:00, every 10 minutes from its 07:00:00 start until 07:30:00
+    fn timetable() -> CachedNetworkData {
+        let mut cache = CachedNetworkData::empty(Upstreams::default());
+        let gtfs = &mut cache.tbm_gtfs_cache;
+        for (trip_id, service_id, stop_id, arrival, departure) in [
+            ("N1", "SUN", "S1", "25:10:00", "25:10:00"),
+            ("M1", "ALL", "S1", "06:00:00", "06:00:00"),
+            ("E1", "ALL", "S1", "23:40:00", "23:40:00"),
+            ("F1", "ALL", "S2", "07:05:00", "07:06:00"),
+        ] {
+            gtfs.trips.insert(trip_id.to_string(), Trip {
+                trip_id: trip_id.to_string(),
+                route_id: "R1".to_string(),
+                service_id: service_id.to_string(),
+                trip_headsign: Some(trip_id.to_string()),
+                direction_id: Some(0),
+                wheelchair_accessible: None,
+                bikes_allowed: None,
+            });
+            gtfs.stop_times.entry(stop_id.to_string()).or_default().push(StopTime {
+                trip_id: trip_id.to_string(),
+                arrival_time: arrival.to_string(),
+                departure_time: departure.to_string(),
+                stop_id: stop_id.to_string(),
+                stop_sequence: 2,
+                stop_headsign: None,
+            });
+        }
+        for (service_id, sunday_only) in [("ALL", false), ("SUN", true)] {
+            gtfs.calendar.insert(service_id.to_string(), ServiceCalendar {
+                service_id: service_id.to_string(),
+                monday: !sunday_only, tuesday: !sunday_only, wednesday: !sunday_only, thursday: !sunday_only,
+                friday: !sunday_only, saturday: !sunday_only, sunday: true,
+                start_date: "20260101".to_string(),
+                end_date: "20261231".to_string(),
+            });
+        }
+        gtfs.frequencies.insert("F1".to_string(), vec![Frequency {
+            start_time: "07:00:00".to_string(),
+            end_time: "07:30:00".to_string(),
+            headway_secs: 600,
+            exact_times: false,
+            trip_start: 7 * 3600,
+        }]);
+        cache
+    }
+
+    fn arrivals(cache: &CachedNetworkData, stop_id: &str, hour: u32, minute: u32, max_results: usize) -> Vec<(String, String, String)> {
+        // 4 May 2026 is a Monday
+        let at = chrono::NaiveDate::from_ymd_opt(2026, 5, 4).and_then(|date| date.and_hms_opt(hour, minute, 0)).expect("date");
+        NVTModels::get_scheduled_arrivals(stop_id, cache, at, max_results).into_iter()
+            .map(|arrival| (arrival.service_date, arrival.arrival_time, arrival.departure_time))
+            .collect()
+    }
+
+    fn rows(rows: &[(&str, &str, &str)]) -> Vec<(String, String, String)> {
+        rows.iter().map(|(date, arrival, departure)| (date.to_string(), arrival.to_string(), departure.to_string())).collect()
+    }
+
+    #[test]
+    fn scheduled_arrivals_span_the_previous_day_to_the_next() {
+        let cache = timetable();
+
+        // Past midnight, Sunday's night trip is still to come, ahead of Monday's trips
+        assert_eq!(arrivals(&cache, "S1", 0, 30, 4), rows(&[
+            ("2026-05-03", "25:10:00", "25:10:00"),
+            ("2026-05-04", "06:00:00", "06:00:00"),
+            ("2026-05-04", "23:40:00", "23:40:00"),
+            ("2026-05-05", "06:00:00", "06:00:00"),
+        ]));
+
+        // Late evening, only the next day's trips are left, and not those of the day after
+        assert_eq!(arrivals(&cache, "S1", 23, 50, 5), rows(&[
+            ("2026-05-05", "06:00:00", "06:00:00"),
+            ("2026-05-05", "23:40:00", "23:40:00"),
+        ]));
+
+        // An arrival at the very time asked for is kept
+        assert_eq!(arrivals(&cache, "S1", 6, 0, 1), rows(&[("2026-05-04", "06:00:00", "06:00:00")]));
+    }
+
+    #[test]
+    fn scheduled_arrivals_expand_frequency_based_trips() {
+        let cache = timetable();
+
+        // F1 leaves at 07:00, 07:10 and 07:20 (07:30 is the end of the window), stopping at S2
+        // 5 minutes later and leaving it after a minute
+        assert_eq!(arrivals(&cache, "S2", 7, 10, 5), rows(&[
+            ("2026-05-04", "07:15:00", "07:16:00"),
+            ("2026-05-04", "07:25:00", "07:26:00"),
+            ("2026-05-05", "07:05:00", "07:06:00"),
+            ("2026-05-05", "07:15:00", "07:16:00"),
+            ("2026-05-05", "07:25:00", "07:26:00"),
+        ]));
+        assert_eq!(arrivals(&cache, "S2", 7, 30, 1), rows(&[("2026-05-05", "07:05:00", "07:06:00")]));
+    }
+}
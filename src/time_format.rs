@@ -0,0 +1,113 @@
+// Time formatting for schedule endpoints
+// GTFS times are "HH:MM:SS" since the service day's start and may go past 24:00:00. With
+// ?time_format=rfc3339 or ?tz=<IANA zone>, schedule responses also carry absolute RFC3339
+// timestamps in the requested zone (Europe/Paris by default) next to the raw GTFS strings.
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone};
+use chrono_tz::Europe::Paris;
+use chrono_tz::Tz;
+
+use crate::tbm_api_models::NVTModels;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TimeOptions {
+    pub tz: Tz,
+    pub timestamps: bool, // Add RFC3339 timestamps next to the GTFS times
+}
+
+impl TimeOptions {
+    /// Validate `?tz=` and `?time_format=` (`gtfs`, the default, or `rfc3339`/`iso8601`)
+    pub fn from_query(tz: Option<&str>, time_format: Option<&str>) -> Result<Self, String> {
+        let zone = match tz {
+            Some(name) => name.parse::<Tz>().map_err(|_| format!("Unknown time zone '{}'", name))?,
+            None => Paris,
+        };
+        let rfc3339 = match time_format.map(str::to_lowercase).as_deref() {
+            None | Some("gtfs") => false,
+            Some("rfc3339") | Some("iso8601") => true,
+            Some(other) => return Err(format!("Unknown time_format '{}' (expected gtfs or rfc3339)", other)),
+        };
+
+        Ok(TimeOptions { tz: zone, timestamps: rfc3339 || tz.is_some() })
+    }
+
+    /// RFC3339 timestamp of a GTFS time on the given service date, if timestamps were requested
+    pub fn timestamp(&self, service_date: NaiveDate, gtfs_time: &str) -> Option<String> {
+        if !self.timestamps {
+            return None;
+        }
+        gtfs_datetime(service_date, gtfs_time).map(|dt| dt.with_timezone(&self.tz).to_rfc3339())
+    }
+}
+
+/// Absolute time of a GTFS time; the service day starts at noon minus 12h (Europe/Paris),
+/// which differs from midnight on DST change days
+pub fn gtfs_datetime(service_date: NaiveDate, gtfs_time: &str) -> Option<DateTime<Tz>> {
+    let seconds = NVTModels::parse_gtfs_time(gtfs_time)?;
+    let noon = Paris.from_local_datetime(&service_date.and_time(NaiveTime::from_hms_opt(12, 0, 0)?)).single()?;
+    Some(noon - Duration::hours(12) + Duration::seconds(seconds as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).expect("date")
+    }
+
+    fn rfc3339(service_date: NaiveDate, gtfs_time: &str) -> Option<String> {
+        gtfs_datetime(service_date, gtfs_time).map(|dt| dt.to_rfc3339())
+    }
+
+    #[test]
+    fn times_past_midnight_fall_on_the_next_day() {
+        assert_eq!(rfc3339(date(2026, 5, 4), "23:59:00").as_deref(), Some("2026-05-04T23:59:00+02:00"));
+        assert_eq!(rfc3339(date(2026, 5, 4), "24:00:00").as_deref(), Some("2026-05-05T00:00:00+02:00"));
+        assert_eq!(rfc3339(date(2026, 5, 4), "25:30:00").as_deref(), Some("2026-05-05T01:30:00+02:00"));
+        assert_eq!(rfc3339(date(2026, 5, 4), "8:05:00").as_deref(), Some("2026-05-04T08:05:00+02:00"));
+        assert_eq!(rfc3339(date(2026, 5, 4), "not a time"), None);
+    }
+
+    #[test]
+    fn service_days_start_at_noon_minus_twelve_hours_on_dst_changes() {
+        // Clocks go forward at 02:00 on 29 March 2026: the day starts at 23:00 the evening before
+        let spring = date(2026, 3, 29);
+        assert_eq!(rfc3339(spring, "00:30:00").as_deref(), Some("2026-03-28T23:30:00+01:00"));
+        assert_eq!(rfc3339(spring, "03:00:00").as_deref(), Some("2026-03-29T03:00:00+02:00"));
+        assert_eq!(rfc3339(spring, "08:00:00").as_deref(), Some("2026-03-29T08:00:00+02:00"));
+
+        // Clocks go back at 03:00 on 25 October 2026: the day starts at 01:00
+        let autumn = date(2026, 10, 25);
+        assert_eq!(rfc3339(autumn, "00:00:00").as_deref(), Some("2026-10-25T01:00:00+02:00"));
+        assert_eq!(rfc3339(autumn, "12:00:00").as_deref(), Some("2026-10-25T12:00:00+01:00"));
+        assert_eq!(rfc3339(autumn, "24:30:00").as_deref(), Some("2026-10-26T00:30:00+01:00"));
+    }
+
+    #[test]
+    fn timestamps_are_added_only_when_asked_for() {
+        let service_date = date(2026, 5, 4);
+
+        let gtfs = TimeOptions::from_query(None, None).expect("defaults");
+        assert_eq!((gtfs.tz, gtfs.timestamps), (Paris, false));
+        assert_eq!(gtfs.timestamp(service_date, "08:00:00"), None);
+        assert!(!TimeOptions::from_query(None, Some("GTFS")).expect("gtfs").timestamps);
+
+        for format in ["rfc3339", "ISO8601"] {
+            let absolute = TimeOptions::from_query(None, Some(format)).expect(format);
+            assert_eq!(absolute.timestamp(service_date, "08:00:00").as_deref(), Some("2026-05-04T08:00:00+02:00"));
+        }
+
+        // A zone alone asks for timestamps in it
+        let utc = TimeOptions::from_query(Some("UTC"), None).expect("utc");
+        assert_eq!(utc.timestamp(service_date, "25:30:00").as_deref(), Some("2026-05-04T23:30:00+00:00"));
+        let montreal = TimeOptions::from_query(Some("America/Montreal"), Some("gtfs")).expect("montreal");
+        assert_eq!(montreal.timestamp(service_date, "08:00:00").as_deref(), Some("2026-05-04T02:00:00-04:00"));
+
+        assert_eq!(TimeOptions::from_query(Some("Europe/Lyon"), None).err().as_deref(), Some("Unknown time zone 'Europe/Lyon'"));
+        assert_eq!(
+            TimeOptions::from_query(None, Some("unix")).err().as_deref(),
+            Some("Unknown time_format 'unix' (expected gtfs or rfc3339)"),
+        );
+    }
+}
@@ -0,0 +1,326 @@
+// Mapbox Vector Tiles (GET /tiles/{z}/{x}/{y}.mvt)
+// The stops and line shapes of the cache as vector tiles, which MapLibre and Mapbox GL load as
+// the map moves instead of every shape at once as JSON: a "stops" layer of points (stop_id,
+// name, lines, and the count of stops they stand for, thinned by zoom like ?zoom= on
+// /stops/clusters) and a "shapes" layer of lines (shape_id, line_ref, line_code, line_name,
+// color, operator). Shapes are clipped to the tile and a small buffer and simplified in tile
+// units, so that a tile stays light at any zoom. Tiles only change with the static data and
+// are kept in an LRU cache until it reloads.
+
+use nvtweb::spatial;
+use prost::Message;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::tbm_api_models::{Line, NetworkData, ShapePoint};
+
+pub const CONTENT_TYPE: &str = "application/vnd.mapbox-vector-tile";
+
+pub const MAX_ZOOM: u8 = 22;
+
+/// Tile width and height in tile units
+const EXTENT: i64 = 4096;
+
+/// Tile units drawn around each side of the tile, so that lines and markers are not cut at
+/// tile edges
+const BUFFER: i64 = 64;
+
+/// Shape points closer than this, in tile units, to the last one kept are dropped: half a
+/// pixel of a 256px tile
+const SIMPLIFY_UNITS: i64 = 8;
+
+/// Tiles kept in the cache
+const CACHED_TILES: usize = 2048;
+
+// Messages of vector_tile.proto (version 2.1 of the specification)
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Tile {
+    #[prost(message, repeated, tag = "3")]
+    pub layers: Vec<Layer>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Layer {
+    #[prost(uint32, required, tag = "15")]
+    pub version: u32,
+    #[prost(string, required, tag = "1")]
+    pub name: String,
+    #[prost(message, repeated, tag = "2")]
+    pub features: Vec<Feature>,
+    #[prost(string, repeated, tag = "3")]
+    pub keys: Vec<String>,
+    #[prost(message, repeated, tag = "4")]
+    pub values: Vec<TileValue>,
+    #[prost(uint32, optional, tag = "5")]
+    pub extent: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TileValue {
+    #[prost(string, optional, tag = "1")]
+    pub string_value: Option<String>,
+    #[prost(uint64, optional, tag = "5")]
+    pub uint_value: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Feature {
+    #[prost(uint32, repeated, packed = "true", tag = "2")]
+    pub tags: Vec<u32>,
+    #[prost(int32, optional, tag = "3")]
+    pub geom_type: Option<i32>,
+    #[prost(uint32, repeated, packed = "true", tag = "4")]
+    pub geometry: Vec<u32>,
+}
+
+pub const POINT: i32 = 1;
+pub const LINESTRING: i32 = 2;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Attribute {
+    Text(String),
+    Count(u64),
+}
+
+/// Layer under construction, sharing keys and values between its features
+struct LayerBuilder {
+    layer: Layer,
+    keys: HashMap<String, u32>,
+    values: HashMap<Attribute, u32>,
+}
+
+impl LayerBuilder {
+    fn new(name: &str) -> Self {
+        LayerBuilder {
+            layer: Layer { version: 2, name: name.to_string(), extent: Some(EXTENT as u32), ..Default::default() },
+            keys: HashMap::new(),
+            values: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, geom_type: i32, geometry: Vec<u32>, attributes: Vec<(&str, Attribute)>) {
+        let mut tags = Vec::with_capacity(attributes.len() * 2);
+        for (key, value) in attributes {
+            let layer = &mut self.layer;
+            let key_index = *self.keys.entry(key.to_string()).or_insert_with(|| {
+                layer.keys.push(key.to_string());
+                layer.keys.len() as u32 - 1
+            });
+            let value_index = *self.values.entry(value.clone()).or_insert_with(|| {
+                layer.values.push(match value {
+                    Attribute::Text(text) => TileValue { string_value: Some(text), ..Default::default() },
+                    Attribute::Count(count) => TileValue { uint_value: Some(count), ..Default::default() },
+                });
+                layer.values.len() as u32 - 1
+            });
+            tags.extend([key_index, value_index]);
+        }
+        self.layer.features.push(Feature { tags, geom_type: Some(geom_type), geometry });
+    }
+}
+
+/// Tile coordinates, checked to exist at their zoom
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileId {
+    pub z: u8,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileId {
+    pub fn parse(z: &str, x: &str, y: &str) -> Result<Self, String> {
+        let z: u8 = z.parse().ok().filter(|z| *z <= MAX_ZOOM)
+            .ok_or_else(|| format!("Invalid zoom '{}' (expected 0 to {})", z, MAX_ZOOM))?;
+        let size = 1u32 << z;
+        let coordinate = |value: &str| value.parse::<u32>().ok().filter(|v| *v < size)
+            .ok_or_else(|| format!("Invalid tile coordinate '{}' at zoom {} (expected 0 to {})", value, z, size - 1));
+        Ok(TileId { z, x: coordinate(x)?, y: coordinate(y)? })
+    }
+
+    /// Position of a point in the units of this tile, web mercator
+    fn project(&self, latitude: f64, longitude: f64) -> (i64, i64) {
+        let world = 2f64.powi(i32::from(self.z));
+        let lat = latitude.clamp(-85.0511, 85.0511).to_radians();
+        let x = (longitude + 180.0) / 360.0 * world;
+        let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / std::f64::consts::PI) / 2.0 * world;
+        (
+            ((x - f64::from(self.x)) * EXTENT as f64).round() as i64,
+            ((y - f64::from(self.y)) * EXTENT as f64).round() as i64,
+        )
+    }
+}
+
+fn in_buffer(value: i64) -> bool {
+    (-BUFFER..=EXTENT + BUFFER).contains(&value)
+}
+
+fn zigzag(value: i64) -> u32 {
+    ((value << 1) ^ (value >> 63)) as u32
+}
+
+fn command(id: u32, count: usize) -> u32 {
+    id | ((count as u32) << 3)
+}
+
+const MOVE_TO: u32 = 1;
+const LINE_TO: u32 = 2;
+
+fn point_geometry((x, y): (i64, i64)) -> Vec<u32> {
+    vec![command(MOVE_TO, 1), zigzag(x), zigzag(y)]
+}
+
+fn line_geometry(parts: &[Vec<(i64, i64)>]) -> Vec<u32> {
+    let mut geometry = Vec::new();
+    let mut cursor = (0, 0);
+    for part in parts {
+        for (index, &(x, y)) in part.iter().enumerate() {
+            match index {
+                0 => geometry.push(command(MOVE_TO, 1)),
+                1 => geometry.push(command(LINE_TO, part.len() - 1)),
+                _ => {}
+            }
+            geometry.extend([zigzag(x - cursor.0), zigzag(y - cursor.1)]);
+            cursor = (x, y);
+        }
+    }
+    geometry
+}
+
+fn crosses_buffer(from: (i64, i64), to: (i64, i64)) -> bool {
+    from.0.min(to.0) <= EXTENT + BUFFER && from.0.max(to.0) >= -BUFFER
+        && from.1.min(to.1) <= EXTENT + BUFFER && from.1.max(to.1) >= -BUFFER
+}
+
+/// The runs of a shape that cross the buffered tile, simplified
+fn clip_shape(tile: &TileId, points: &[ShapePoint]) -> Vec<Vec<(i64, i64)>> {
+    let projected: Vec<(i64, i64)> = points.iter()
+        .map(|point| tile.project(point.latitude, point.longitude))
+        .collect();
+
+    let mut parts = Vec::new();
+    let mut run: Vec<(i64, i64)> = Vec::new();
+    let mut skipped = None; // Last point dropped by the simplification, to end the run on
+    let mut close = |run: &mut Vec<(i64, i64)>, skipped: &mut Option<(i64, i64)>| {
+        if let Some(end) = skipped.take().filter(|end| run.last() != Some(end)) {
+            run.push(end);
+        }
+        if run.len() >= 2 {
+            parts.push(std::mem::take(run));
+        }
+        run.clear();
+    };
+    for segment in projected.windows(2) {
+        let (from, to) = (segment[0], segment[1]);
+        if !crosses_buffer(from, to) {
+            close(&mut run, &mut skipped);
+            continue;
+        }
+        if run.is_empty() {
+            run.push(from);
+        }
+        let last = run[run.len() - 1];
+        if (to.0 - last.0).abs().max((to.1 - last.1).abs()) >= SIMPLIFY_UNITS {
+            run.push(to);
+            skipped = None;
+        } else {
+            skipped = Some(to);
+        }
+    }
+    close(&mut run, &mut skipped);
+    parts
+}
+
+/// The tile, encoded
+pub fn render(tile: &TileId, network: &NetworkData) -> Vec<u8> {
+    let mut stops = LayerBuilder::new("stops");
+    let visible: Vec<_> = network.stops.iter()
+        .filter(|stop| {
+            let (x, y) = tile.project(stop.latitude, stop.longitude);
+            in_buffer(x) && in_buffer(y)
+        })
+        .collect();
+    for (stop, count) in spatial::thin_by_zoom(visible, tile.z, |stop| (stop.latitude, stop.longitude)) {
+        stops.add(POINT, point_geometry(tile.project(stop.latitude, stop.longitude)), vec![
+            ("stop_id", Attribute::Text(stop.stop_id.clone())),
+            ("name", Attribute::Text(stop.stop_name.clone())),
+            ("lines", Attribute::Text(stop.lines.join(","))),
+            ("count", Attribute::Count(count as u64)),
+        ]);
+    }
+
+    // Reversed so that the first line listing a shape wins, as in geojson::shapes
+    let lines: HashMap<&str, &Line> = network.lines.iter().rev()
+        .flat_map(|line| line.shape_ids.iter().map(move |id| (id.as_str(), line)))
+        .collect();
+    let mut shape_ids: Vec<&String> = network.shapes.keys().collect();
+    shape_ids.sort();
+    let mut shapes = LayerBuilder::new("shapes");
+    for shape_id in shape_ids {
+        let parts = clip_shape(tile, &network.shapes[shape_id]);
+        if parts.is_empty() {
+            continue;
+        }
+        let mut attributes = vec![("shape_id", Attribute::Text(shape_id.clone()))];
+        if let Some(line) = lines.get(shape_id.as_str()) {
+            attributes.extend([
+                ("line_ref", Attribute::Text(line.line_ref.clone())),
+                ("line_code", Attribute::Text(line.line_code.clone())),
+                ("line_name", Attribute::Text(line.line_name.clone())),
+                ("color", Attribute::Text(format!("#{}", line.color))),
+                ("operator", Attribute::Text(line.operator.clone())),
+            ]);
+        }
+        shapes.add(LINESTRING, line_geometry(&parts), attributes);
+    }
+
+    Tile { layers: vec![stops.layer, shapes.layer] }.encode_to_vec()
+}
+
+#[derive(Default)]
+struct Tiles {
+    etag: String, // Static data the tiles were rendered from
+    tiles: HashMap<TileId, (Vec<u8>, u64)>, // value: (tile, last use)
+    clock: u64,
+}
+
+/// Rendered tiles, least recently used first out
+#[derive(Clone, Default)]
+pub struct TileCache(Arc<Mutex<Tiles>>);
+
+impl TileCache {
+    fn tiles(&self, etag: &str) -> MutexGuard<'_, Tiles> {
+        let mut tiles = match self.0.lock() {
+            Ok(tiles) => tiles,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if tiles.etag != etag {
+            tiles.tiles.clear();
+            tiles.etag = etag.to_string();
+        }
+        tiles
+    }
+
+    pub fn get(&self, etag: &str, tile: &TileId) -> Option<Vec<u8>> {
+        let mut tiles = self.tiles(etag);
+        tiles.clock += 1;
+        let clock = tiles.clock;
+        tiles.tiles.get_mut(tile).map(|(body, last_use)| {
+            *last_use = clock;
+            body.clone()
+        })
+    }
+
+    pub fn insert(&self, etag: &str, tile: TileId, body: Vec<u8>) {
+        let mut tiles = self.tiles(etag);
+        tiles.clock += 1;
+        let clock = tiles.clock;
+        tiles.tiles.insert(tile, (body, clock));
+        if tiles.tiles.len() > CACHED_TILES {
+            let oldest = tiles.tiles.iter().min_by_key(|(_, (_, last_use))| *last_use).map(|(id, _)| *id);
+            if let Some(oldest) = oldest {
+                tiles.tiles.remove(&oldest);
+            }
+        }
+    }
+}
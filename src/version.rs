@@ -0,0 +1,66 @@
+// Build and runtime information (GET /api/tbm/version)
+// What is running, for deployments to check: crate version, git commit and build time (set by
+// build.rs), the cargo features compiled in, which sources loaded, and the process uptime.
+
+use serde::Serialize;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::tbm_api_models::{CachedNetworkData, NVTModels};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_COMMIT: &str = env!("NVT_GIT_COMMIT"); // "unknown" when built outside a git checkout
+const BUILD_TIMESTAMP: &str = env!("NVT_BUILD_TIMESTAMP");
+
+static STARTED: OnceLock<(Instant, i64)> = OnceLock::new();
+
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    pub build_timestamp: i64,
+    pub built_at: String, // Europe/Paris
+    pub features: Vec<&'static str>, // Optional cargo features compiled in
+    pub sources: Vec<String>, // Sources with loaded data
+    pub started_at: String,
+    pub uptime_secs: u64,
+}
+
+/// Note the process start; call first thing in main
+pub fn mark_started() {
+    started();
+}
+
+fn started() -> (Instant, i64) {
+    *STARTED.get_or_init(|| (Instant::now(), NVTModels::get_current_timestamp()))
+}
+
+pub fn uptime_secs() -> u64 {
+    started().0.elapsed().as_secs()
+}
+
+pub fn build_timestamp() -> i64 {
+    BUILD_TIMESTAMP.parse().unwrap_or_default()
+}
+
+pub fn features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "bot") {
+        features.push("bot");
+    }
+    features
+}
+
+pub fn info(cache: &CachedNetworkData) -> VersionInfo {
+    let (started, started_at) = started();
+    VersionInfo {
+        version: VERSION,
+        git_commit: GIT_COMMIT,
+        build_timestamp: build_timestamp(),
+        built_at: NVTModels::format_timestamp_full(build_timestamp()),
+        features: features(),
+        sources: cache.loaded_sources().into_iter().map(str::to_string).collect(),
+        started_at: NVTModels::format_timestamp_full(started_at),
+        uptime_secs: started.elapsed().as_secs(),
+    }
+}
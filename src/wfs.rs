@@ -0,0 +1,373 @@
+// WFS-lite output for GIS tools
+// A minimal OGC Web Feature Service (2.0) over the network layer, so QGIS, ArcGIS or a municipal
+// SIG can add the stops and lines as a WFS layer: GetCapabilities, DescribeFeatureType, and
+// GetFeature with bbox, count and startIndex, for the `nvt:stops` and `nvt:lines` feature types.
+// Features are GeoJSON (outputFormat=application/json; there is no GML) with flat attributes
+// that fit a shapefile-like table, and errors are OWS exception reports. Only WGS84 is served.
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::geojson;
+use crate::tbm_api_models::{BoundingBox, NetworkData};
+
+pub const XML_CONTENT_TYPE: &str = "application/xml";
+const OUTPUT_FORMAT: &str = "application/json";
+const DEFAULT_CRS: &str = "urn:ogc:def:crs:EPSG::4326";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeatureType {
+    Stops,
+    Lines,
+}
+
+impl FeatureType {
+    const ALL: [FeatureType; 2] = [FeatureType::Stops, FeatureType::Lines];
+
+    fn name(self) -> &'static str {
+        match self {
+            FeatureType::Stops => "stops",
+            FeatureType::Lines => "lines",
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            FeatureType::Stops => "Transit stops",
+            FeatureType::Lines => "Transit lines",
+        }
+    }
+
+    fn geometry_type(self) -> &'static str {
+        match self {
+            FeatureType::Stops => "gml:PointPropertyType",
+            FeatureType::Lines => "gml:MultiCurvePropertyType",
+        }
+    }
+
+    /// (name, XSD type) of the feature attributes
+    fn attributes(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            FeatureType::Stops => &[
+                ("stop_id", "xsd:string"),
+                ("stop_name", "xsd:string"),
+                ("commune", "xsd:string"),
+                ("insee_code", "xsd:string"),
+                ("lines", "xsd:string"), // Line refs joined by ','
+                ("alerts", "xsd:int"), // Number of active alerts
+            ],
+            FeatureType::Lines => &[
+                ("line_ref", "xsd:string"),
+                ("line_code", "xsd:string"),
+                ("line_name", "xsd:string"),
+                ("operator", "xsd:string"),
+                ("color", "xsd:string"),
+                ("alerts", "xsd:int"),
+            ],
+        }
+    }
+
+    /// Feature type from a type name, with or without the `nvt:` prefix
+    fn parse(name: &str) -> Result<Self, WfsError> {
+        let local = name.trim().rsplit(':').next().unwrap_or_default();
+        Self::ALL.into_iter()
+            .find(|feature_type| feature_type.name().eq_ignore_ascii_case(local))
+            .ok_or_else(|| WfsError::invalid("typeNames", format!("Unknown feature type '{}' (expected nvt:stops or nvt:lines)", name)))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WfsRequest {
+    GetCapabilities,
+    DescribeFeatureType(Vec<FeatureType>),
+    GetFeature {
+        feature_type: FeatureType,
+        bbox: Option<BoundingBox>,
+        count: Option<usize>,
+        start_index: usize,
+    },
+}
+
+/// OWS exception, served as an ExceptionReport
+#[derive(Debug)]
+pub struct WfsError {
+    pub code: &'static str,
+    pub locator: String,
+    pub text: String,
+}
+
+impl WfsError {
+    fn invalid(locator: &str, text: String) -> Self {
+        WfsError { code: "InvalidParameterValue", locator: locator.to_string(), text }
+    }
+
+    fn missing(locator: &str) -> Self {
+        WfsError { code: "MissingParameterValue", locator: locator.to_string(), text: format!("Missing parameter {}", locator) }
+    }
+
+    pub fn report(&self) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<ows:ExceptionReport xmlns:ows="http://www.opengis.net/ows/1.1" version="2.0.0">
+  <ows:Exception exceptionCode="{}" locator="{}">
+    <ows:ExceptionText>{}</ows:ExceptionText>
+  </ows:Exception>
+</ows:ExceptionReport>
+"#,
+            self.code, escape_xml(&self.locator), escape_xml(&self.text)
+        )
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// ============================================================================
+// Requests
+// ============================================================================
+
+/// WFS request from the query parameters, whose names are case-insensitive
+pub fn parse_request(params: &HashMap<String, String>) -> Result<WfsRequest, WfsError> {
+    let params: HashMap<String, &str> = params.iter().map(|(key, value)| (key.to_lowercase(), value.as_str())).collect();
+    let param = |name: &str| params.get(name).copied().filter(|value| !value.is_empty());
+
+    if let Some(service) = param("service").filter(|service| !service.eq_ignore_ascii_case("WFS")) {
+        return Err(WfsError::invalid("service", format!("Unknown service '{}' (expected WFS)", service)));
+    }
+    // typeName is the WFS 1.x spelling
+    let type_names = param("typenames").or_else(|| param("typename"));
+
+    let request = param("request").ok_or_else(|| WfsError::missing("request"))?;
+    match request.to_lowercase().as_str() {
+        "getcapabilities" => Ok(WfsRequest::GetCapabilities),
+        "describefeaturetype" => {
+            let feature_types = match type_names {
+                Some(names) => names.split(',').map(FeatureType::parse).collect::<Result<_, _>>()?,
+                None => FeatureType::ALL.to_vec(),
+            };
+            Ok(WfsRequest::DescribeFeatureType(feature_types))
+        }
+        "getfeature" => {
+            let names = type_names.ok_or_else(|| WfsError::missing("typeNames"))?;
+            if names.contains(',') {
+                return Err(WfsError::invalid("typeNames", "One feature type per GetFeature request".to_string()));
+            }
+            if let Some(format) = param("outputformat").filter(|format| !is_json_format(format)) {
+                return Err(WfsError::invalid("outputFormat", format!("Unsupported output format '{}' (expected {})", format, OUTPUT_FORMAT)));
+            }
+            // count is the WFS 2.0 spelling of maxFeatures
+            let count = param("count").or_else(|| param("maxfeatures"))
+                .map(|count| count.parse::<usize>().map_err(|_| WfsError::invalid("count", format!("Invalid count '{}'", count))))
+                .transpose()?;
+            let start_index = param("startindex")
+                .map(|start| start.parse::<usize>().map_err(|_| WfsError::invalid("startIndex", format!("Invalid startIndex '{}'", start))))
+                .transpose()?
+                .unwrap_or(0);
+            Ok(WfsRequest::GetFeature {
+                feature_type: FeatureType::parse(names)?,
+                bbox: param("bbox").map(parse_bbox).transpose()?,
+                count,
+                start_index,
+            })
+        }
+        _ => Err(WfsError {
+            code: "OperationNotSupported",
+            locator: request.to_string(),
+            text: format!("Unsupported request '{}' (expected GetCapabilities, DescribeFeatureType or GetFeature)", request),
+        }),
+    }
+}
+
+fn is_json_format(format: &str) -> bool {
+    let format = format.to_lowercase();
+    format == "json" || format == OUTPUT_FORMAT || format == geojson::CONTENT_TYPE || format.starts_with("application/json;")
+}
+
+/// WFS bbox: `minx,miny,maxx,maxy[,crs]`. The EPSG:4326 URNs have latitude first, as WFS 2.0
+/// clients send them; without a CRS, or with EPSG:4326 or CRS84, longitude comes first.
+fn parse_bbox(value: &str) -> Result<BoundingBox, WfsError> {
+    let parts: Vec<&str> = value.split(',').map(str::trim).collect();
+    let coordinates = match parts.as_slice() {
+        [coordinates @ .., crs] if parts.len() == 5 => {
+            let crs = crs.to_lowercase();
+            if crs == "urn:ogc:def:crs:epsg::4326" || crs == "http://www.opengis.net/def/crs/epsg/0/4326" {
+                vec![coordinates[1], coordinates[0], coordinates[3], coordinates[2]]
+            } else if crs == "epsg:4326" || crs.ends_with("crs84") {
+                coordinates.to_vec()
+            } else {
+                return Err(WfsError::invalid("bbox", format!("Unsupported CRS '{}' (only WGS84 is served)", crs)));
+            }
+        }
+        _ => parts,
+    };
+    BoundingBox::parse(&coordinates.join(",")).map_err(|message| WfsError::invalid("bbox", message))
+}
+
+// ============================================================================
+// Responses
+// ============================================================================
+
+/// Extent of the network as `lon lat` lower and upper corners
+fn extent(network: &NetworkData) -> (String, String) {
+    let points = network.stops.iter().map(|stop| (stop.longitude, stop.latitude))
+        .chain(network.shapes.values().flatten().map(|point| (point.longitude, point.latitude)));
+    let (min, max) = points.fold(None, |extent: Option<((f64, f64), (f64, f64))>, (lon, lat)| {
+        Some(match extent {
+            None => ((lon, lat), (lon, lat)),
+            Some((min, max)) => ((min.0.min(lon), min.1.min(lat)), (max.0.max(lon), max.1.max(lat))),
+        })
+    }).unwrap_or(((-180.0, -90.0), (180.0, 90.0)));
+    (format!("{} {}", min.0, min.1), format!("{} {}", max.0, max.1))
+}
+
+/// Capabilities document; `service_url` is the URL of this endpoint, which clients call back
+pub fn capabilities(service_url: &str, network: &NetworkData) -> String {
+    let url = escape_xml(service_url);
+    let operation = |name: &str, parameters: &str| format!(
+        r#"    <ows:Operation name="{}">
+      <ows:DCP><ows:HTTP><ows:Get xlink:href="{}"/></ows:HTTP></ows:DCP>{}
+    </ows:Operation>
+"#,
+        name, url, parameters
+    );
+    let output_formats = format!(
+        r#"
+      <ows:Parameter name="outputFormat"><ows:AllowedValues><ows:Value>{}</ows:Value></ows:AllowedValues></ows:Parameter>"#,
+        OUTPUT_FORMAT
+    );
+    let (lower, upper) = extent(network);
+    let feature_types: String = FeatureType::ALL.iter()
+        .map(|feature_type| format!(
+            r#"    <wfs:FeatureType>
+      <wfs:Name>nvt:{}</wfs:Name>
+      <wfs:Title>{}</wfs:Title>
+      <wfs:DefaultCRS>{}</wfs:DefaultCRS>
+      <wfs:OutputFormats><wfs:Format>{}</wfs:Format></wfs:OutputFormats>
+      <ows:WGS84BoundingBox><ows:LowerCorner>{}</ows:LowerCorner><ows:UpperCorner>{}</ows:UpperCorner></ows:WGS84BoundingBox>
+    </wfs:FeatureType>
+"#,
+            feature_type.name(), feature_type.title(), DEFAULT_CRS, OUTPUT_FORMAT, lower, upper
+        ))
+        .collect();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<wfs:WFS_Capabilities version="2.0.0" xmlns:wfs="http://www.opengis.net/wfs/2.0" xmlns:ows="http://www.opengis.net/ows/1.1" xmlns:xlink="http://www.w3.org/1999/xlink" xmlns:nvt="{url}">
+  <ows:ServiceIdentification>
+    <ows:Title>NVT Web Edition</ows:Title>
+    <ows:Abstract>Stops and lines of the Nouvelle-Aquitaine transit networks</ows:Abstract>
+    <ows:ServiceType>WFS</ows:ServiceType>
+    <ows:ServiceTypeVersion>2.0.0</ows:ServiceTypeVersion>
+  </ows:ServiceIdentification>
+  <ows:OperationsMetadata>
+{}{}{}  </ows:OperationsMetadata>
+  <wfs:FeatureTypeList>
+{}  </wfs:FeatureTypeList>
+</wfs:WFS_Capabilities>
+"#,
+        operation("GetCapabilities", ""),
+        operation("DescribeFeatureType", ""),
+        operation("GetFeature", &output_formats),
+        feature_types,
+    )
+}
+
+/// XML Schema of the feature types
+pub fn describe_feature_type(service_url: &str, feature_types: &[FeatureType]) -> String {
+    let types: String = feature_types.iter()
+        .map(|feature_type| {
+            let attributes: String = feature_type.attributes().iter()
+                .map(|(name, xsd_type)| format!("          <xsd:element name=\"{}\" type=\"{}\" minOccurs=\"0\"/>\n", name, xsd_type))
+                .collect();
+            format!(
+                r#"  <xsd:complexType name="{name}Type">
+    <xsd:complexContent>
+      <xsd:extension base="gml:AbstractFeatureType">
+        <xsd:sequence>
+          <xsd:element name="geometry" type="{}" minOccurs="0"/>
+{}        </xsd:sequence>
+      </xsd:extension>
+    </xsd:complexContent>
+  </xsd:complexType>
+  <xsd:element name="{name}" type="nvt:{name}Type" substitutionGroup="gml:AbstractFeature"/>
+"#,
+                feature_type.geometry_type(), attributes, name = feature_type.name()
+            )
+        })
+        .collect();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<xsd:schema xmlns:xsd="http://www.w3.org/2001/XMLSchema" xmlns:gml="http://www.opengis.net/gml/3.2" xmlns:nvt="{url}" targetNamespace="{url}" elementFormDefault="qualified">
+  <xsd:import namespace="http://www.opengis.net/gml/3.2" schemaLocation="http://schemas.opengis.net/gml/3.2.1/gml.xsd"/>
+{}</xsd:schema>
+"#,
+        types, url = escape_xml(service_url)
+    )
+}
+
+/// GetFeature response: the features of one type in the network (already cut to the bbox), in
+/// id order so that paging with startIndex is stable
+pub fn features(feature_type: FeatureType, network: &NetworkData, count: Option<usize>, start_index: usize) -> Value {
+    let mut features: Vec<(String, Value)> = match feature_type {
+        FeatureType::Stops => network.stops.iter()
+            .map(|stop| (stop.stop_id.clone(), json!({
+                "type": "Feature",
+                "id": format!("stops.{}", stop.stop_id),
+                "geometry": { "type": "Point", "coordinates": [stop.longitude, stop.latitude] },
+                "properties": {
+                    "stop_id": stop.stop_id,
+                    "stop_name": stop.stop_name,
+                    "commune": stop.commune,
+                    "insee_code": stop.insee_code,
+                    "lines": stop.lines.join(","),
+                    "alerts": stop.alerts.len(),
+                },
+            })))
+            .collect(),
+        FeatureType::Lines => network.lines.iter()
+            .map(|line| {
+                let curves: Vec<Vec<[f64; 2]>> = line.shape_ids.iter()
+                    .filter_map(|shape_id| network.shapes.get(shape_id))
+                    .map(|points| points.iter().map(|point| [point.longitude, point.latitude]).collect())
+                    .collect();
+                let geometry = if curves.is_empty() {
+                    Value::Null
+                } else {
+                    json!({ "type": "MultiLineString", "coordinates": curves })
+                };
+                (line.line_ref.clone(), json!({
+                    "type": "Feature",
+                    "id": format!("lines.{}", line.line_ref),
+                    "geometry": geometry,
+                    "properties": {
+                        "line_ref": line.line_ref,
+                        "line_code": line.line_code,
+                        "line_name": line.line_name,
+                        "operator": line.operator,
+                        "color": line.color,
+                        "alerts": line.alerts.len(),
+                    },
+                }))
+            })
+            .collect(),
+    };
+    features.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let matched = features.len();
+    let returned: Vec<Value> = features.into_iter()
+        .skip(start_index)
+        .take(count.unwrap_or(usize::MAX))
+        .map(|(_, feature)| feature)
+        .collect();
+    let number_returned = returned.len();
+    let mut collection = geojson::feature_collection(returned);
+    collection["numberMatched"] = json!(matched);
+    collection["numberReturned"] = json!(number_returned);
+    collection
+}
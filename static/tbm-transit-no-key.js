@@ -465,6 +465,7 @@ class TBMTransitMap {
             this.loadNetworkData();
             this.setupEventListeners();
             this.startAutoRefresh();
+            this.startAlertStream();
         });
     }
 
@@ -1103,7 +1104,7 @@ class TBMTransitMap {
                         <div class="route-step-icon">${lineType.icon}</div>
                         <div class="route-step-info">
                             <div class="route-step-line">
-                                <span class="line-badge" style="background-color: #${line.color}; color: white;">
+                                <span class="line-badge" style="background-color: #${line.color}; color: #${line.text_color || 'FFFFFF'};">
                                     ${line.line_code}
                                 </span>
                                 <span class="route-step-line-name">${line.line_name}</span>
@@ -1787,6 +1788,15 @@ class TBMTransitMap {
 
             // Apply initial spatial filtering based on current viewport
             this.updateVisibleNetworkData();
+
+            // Deep link from printed stop QR codes: /?stop=<stop_id>
+            if (!this.deepLinkHandled) {
+                this.deepLinkHandled = true;
+                const linkedStop = new URLSearchParams(window.location.search).get('stop');
+                if (linkedStop) {
+                    this.focusOnStop(linkedStop);
+                }
+            }
             
             // Show update indicator
             this.showUpdateIndicator();
@@ -2035,8 +2045,8 @@ class TBMTransitMap {
         const vehicleCount = (line.real_time || []).length;
         const alertCount = (line.alerts || []).length;
         const shapeCount = (line.shape_ids || []).length;
-        const rgb = this.hexToRgb(line.color || '808080');
-        const textColor = this.getContrastColor(rgb);
+        // The server corrects text colors for contrast; older servers don't send one
+        const textColor = line.text_color ? `#${line.text_color}` : this.getContrastColor(this.hexToRgb(line.color || '808080'));
         const isSelected = this.selectedLine === line.line_ref;
 
         const operatorBadge = this.getOperatorBadge(line.operator, true);
@@ -2211,7 +2221,7 @@ class TBMTransitMap {
                 <span class="popup-label">Active Vehicles:</span> ${(line.real_time || []).length}
             </div>
             <div class="popup-section">
-                <span class="popup-label">Shapes:</span> ${(line.shape_ids || []).length}
+                <span class="popup-label">Shapes:</span> ${(line.shape_ids || []).length}${line.synthetic ? ' (approximate, from stop sequence)' : ''}
             </div>
             <div class="popup-section">
                 <span class="popup-label">Stops on Line:</span> ${this.selectedLineStops.length}
@@ -2590,6 +2600,25 @@ class TBMTransitMap {
         }, 30000);
     }
 
+    /**
+     * Reload the network as soon as the server reports alert changes, instead of
+     * waiting for the next auto-refresh
+     */
+    startAlertStream() {
+        if (!window.EventSource) {
+            return;
+        }
+
+        const stream = new EventSource(`${this.apiEndpoint}/alerts/stream`);
+        const onChange = (event) => {
+            console.log(`📣 Alert ${event.type}: ${event.lastEventId}`);
+            // A refresh sends its changes together, reload once for all of them
+            clearTimeout(this.alertReloadDebounce);
+            this.alertReloadDebounce = setTimeout(() => this.loadNetworkData(), 1000);
+        };
+        ['new', 'updated', 'expired'].forEach(type => stream.addEventListener(type, onChange));
+    }
+
     showUpdateIndicator() {
         const indicator = document.getElementById('updateIndicator');
         indicator.classList.add('show');